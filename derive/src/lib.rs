@@ -0,0 +1,122 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! `#[derive(Poolable)]` for trade-data, re-exported by it with the `derive` feature.
+//!
+//! A struct whose fields are all poolable is pooled field by field, like a tuple: each field gets its own accumulator,
+//! its own high and low, and its own method under per-field pooling, falling back to the last method given.  The
+//! struct still needs `Clone`, `Copy`, `Default`, and `PartialOrd` of its own.  The accumulator is generated
+//! alongside it, named after it with "Accumulator" appended.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Index, Member};
+
+#[proc_macro_derive(Poolable)]
+pub fn derive_poolable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match poolable(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn poolable(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let fields = match input.data {
+        Data::Struct(ref data) if !data.fields.is_empty() => &data.fields,
+        _ => return Err(Error::new_spanned(input, "Poolable can only be derived for structs with fields")),
+    };
+
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "Poolable can't be derived for generic structs"));
+    }
+
+    let name = &input.ident;
+    let visibility = &input.vis;
+    let accumulator = format_ident!("{}Accumulator", name);
+
+    let members = fields.iter().enumerate()
+        .map(|(i, field)| match field.ident {
+            Some(ref ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        })
+        .collect::<Vec<Member>>();
+    let types = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+    let accumulators = (0..fields.len()).map(|i| format_ident!("field_{}", i)).collect::<Vec<_>>();
+    let indices = 0..fields.len();
+
+    Ok(quote! {
+        #[doc = "Accumulates each field of a record separately"]
+        #visibility struct #accumulator {
+            #(#accumulators: <#types as ::trade_data::Poolable>::Accumulator),*
+        }
+
+        impl ::trade_data::Accumulator<#name> for #accumulator {
+            fn new(pooling: ::trade_data::PoolingMethod) -> Self {
+                Self {
+                    #(#accumulators: <<#types as ::trade_data::Poolable>::Accumulator as ::trade_data::Accumulator<#types>>::new(pooling)),*
+                }
+            }
+
+            fn fold(&mut self, value: #name, weight: f64) {
+                #(::trade_data::Accumulator::<#types>::fold(&mut self.#accumulators, value.#members, weight);)*
+            }
+
+            fn finalize(&self) -> #name {
+                #name {
+                    #(#members: ::trade_data::Accumulator::<#types>::finalize(&self.#accumulators)),*
+                }
+            }
+
+            fn reset(&mut self) {
+                #(::trade_data::Accumulator::<#types>::reset(&mut self.#accumulators);)*
+            }
+
+            fn overflowed(&self) -> bool {
+                false #(|| ::trade_data::Accumulator::<#types>::overflowed(&self.#accumulators))*
+            }
+        }
+
+        impl ::trade_data::Poolable for #name {
+            type Accumulator = #accumulator;
+
+            fn high(values: &[Self]) -> Self {
+                #name {
+                    #(#members: <#types as ::trade_data::Poolable>::high(&values.iter().map(|value| value.#members).collect::<Vec<#types>>())),*
+                }
+            }
+
+            fn low(values: &[Self]) -> Self {
+                #name {
+                    #(#members: <#types as ::trade_data::Poolable>::low(&values.iter().map(|value| value.#members).collect::<Vec<#types>>())),*
+                }
+            }
+
+            fn pool_fields(values: &[Self], start_value: Self, methods: &[::trade_data::PoolingMethod]) -> Self {
+                #name {
+                    #(#members: ::trade_data::pool_values(
+                        &values.iter().map(|value| value.#members).collect::<Vec<#types>>(),
+                        start_value.#members,
+                        methods.get(#indices).or(methods.last()).cloned().unwrap_or(::trade_data::PoolingMethod::End),
+                    )),*
+                }
+            }
+        }
+    })
+}
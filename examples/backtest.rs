@@ -0,0 +1,187 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! A backtest over recorded data.
+//!
+//! Records a synthetic session, or opens a recording given with `--file`, and replays it unpaced through a moving
+//! average crossover strategy: long one unit while the fast average of one-minute closes is above the slow one,
+//! flat otherwise.  Reports the strategy's profit against holding for the whole session.
+//!
+//! Usage: cargo run --example backtest [-- --file PATH] [--fast N] [--slow N]
+
+extern crate trade_data;
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+use trade_data::{KeyValueStore, Query, Timestamp};
+use trade_data::replay;
+use trade_data::storage::FileStorage;
+
+use common::{Price, dollars};
+
+const MINUTE: Timestamp = 60 * 1000;
+
+/// The average of the last `period` values
+struct MovingAverage {
+    period: usize,
+    values: Vec<f64>,
+}
+
+impl MovingAverage {
+    fn new(period: usize) -> Self {
+        Self {
+            period: period,
+            values: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.values.push(value);
+        if self.values.len() > self.period {
+            self.values.remove(0);
+        }
+    }
+
+    /// `None` until a full period has been added
+    fn value(&self) -> Option<f64> {
+        if self.values.len() < self.period {
+            return None;
+        }
+
+        Some(self.values.iter().sum::<f64>() / self.period as f64)
+    }
+}
+
+#[derive(Default)]
+struct Results {
+    trades: usize,
+    profit: i64,
+    start: Option<Timestamp>,
+    entry: Option<Price>,
+    first: Option<Price>,
+    last: Price,
+}
+
+fn backtest(storage: &FileStorage<Timestamp, Price>, fast: usize, slow: usize) -> io::Result<Results> {
+    let end = match storage.last_key().and_then(|key| key.downcast::<Timestamp>().ok()) {
+        Some(last) => *last + 1,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "The recording is empty")),
+    };
+
+    let mut fast_average = MovingAverage::new(fast);
+    let mut slow_average = MovingAverage::new(slow);
+    let mut results = Results::default();
+
+    // The close of the minute being replayed, which is added to the averages once the minute is over
+    let mut minute = None;
+
+    for (timestamp, price) in replay::replay_unpaced::<Price>(storage, 0..end)? {
+        // Minutes start at the first trade, as pooled queries anchor their buckets
+        let start = *results.start.get_or_insert(timestamp);
+        results.first = results.first.or(Some(price));
+        results.last = price;
+
+        let bucket = timestamp - (timestamp - start) % MINUTE;
+        if let Some((previous, close)) = minute {
+            if previous != bucket {
+                fast_average.add(close as f64);
+                slow_average.add(close as f64);
+            }
+        }
+        minute = Some((bucket, price.0));
+
+        let signal = match (fast_average.value(), slow_average.value()) {
+            (Some(fast), Some(slow)) => fast > slow,
+            _ => continue,
+        };
+
+        match (signal, results.entry) {
+            (true, None) => {
+                results.entry = Some(price);
+                results.trades += 1;
+            },
+            (false, Some(entry)) => {
+                results.profit += price.0 as i64 - entry.0 as i64;
+                results.entry = None;
+            },
+            _ => (),
+        }
+    }
+
+    // Close out at the last price
+    if let Some(entry) = results.entry.take() {
+        results.profit += results.last.0 as i64 - entry.0 as i64;
+    }
+
+    Ok(results)
+}
+
+fn run(file: Option<String>, fast: usize, slow: usize) -> io::Result<()> {
+    let recorded = file.is_none();
+    let file = file.unwrap_or_else(|| "backtest_example".to_string());
+
+    let storage = if recorded {
+        common::record_session(&file, 7, 200_000)?
+    } else {
+        FileStorage::<Timestamp, Price>::new(&file)?
+    };
+    println!("Replaying {} trades from {}", storage.len(), file);
+
+    let results = backtest(&storage, fast, slow)?;
+    let hold = results.last.0 as i64 - results.first.map_or(0, |first| first.0 as i64);
+    println!("SMA {}/{} crossover: {} trades, profit {} (holding: {})", fast, slow, results.trades, dollars(results.profit), dollars(hold));
+
+    // The same closes can be had from storage with a query, without replaying anything
+    let closes = Query::new(&file).interval(MINUTE).evaluate_pooled::<Price>(&storage)?;
+    println!("{} one-minute candles, last close {}", closes.len(), dollars(closes.last().map_or(0, |close| close.1 .0 as i64)));
+
+    if recorded {
+        fs::remove_file(&file).ok();
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let mut file = None;
+    let mut fast = 10;
+    let mut slow = 30;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--file" => args.next().map(|value| file = Some(value)),
+            "--fast" => args.next().and_then(|value| value.parse().ok()).map(|value| fast = value),
+            "--slow" => args.next().and_then(|value| value.parse().ok()).map(|value| slow = value),
+            _ => None,
+        };
+
+        if parsed.is_none() || fast == 0 || fast >= slow {
+            eprintln!("Usage: backtest [--file PATH] [--fast N] [--slow N], with 0 < fast < slow");
+            process::exit(1);
+        }
+    }
+
+    if let Err(error) = run(file, fast, slow) {
+        eprintln!("Example failed: {}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,133 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! A chart export script.
+//!
+//! Pools a recording into OHLC candles, adds a moving average of their closes, and writes them as CSV for a charting
+//! tool.  Without `--file`, a synthetic session is recorded first.
+//!
+//! Usage: cargo run --example chart_export [-- --file PATH] [--interval 15m] [--sma N] [--output PATH]
+
+extern crate trade_data;
+
+mod common;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use trade_data::{Indicator, Interval, PoolingMethod, Query, Timestamp};
+use trade_data::indicator;
+use trade_data::storage::FileStorage;
+
+use common::{Price, dollars};
+
+struct Options {
+    file: Option<String>,
+    interval: Interval,
+    sma: usize,
+    output: Option<String>,
+}
+
+/// The open, high, low, and close of each bucket
+fn candles(storage: &FileStorage<Timestamp, Price>, source: &str, interval: Interval) -> io::Result<Vec<(Timestamp, [Price; 4])>> {
+    let query = Query::new(source).interval(interval.get());
+
+    let columns = [PoolingMethod::Start, PoolingMethod::High, PoolingMethod::Low, PoolingMethod::End].iter()
+        .map(|&pooling| query.clone().pooling(pooling).evaluate_pooled::<Price>(storage))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(columns[0].iter().enumerate().map(|(i, &(start, _))| {
+        (start, [columns[0][i].1, columns[1][i].1, columns[2][i].1, columns[3][i].1])
+    }).collect())
+}
+
+fn export<W>(output: W, candles: &[(Timestamp, [Price; 4])], sma: usize) -> io::Result<()> where W: Write {
+    let mut output = BufWriter::new(output);
+
+    // Indicators are computed over plain numbers, so the closes are converted first
+    let closes = candles.iter().map(|&(start, candle)| (start, candle[3].0 as f64)).collect::<Vec<_>>();
+    let averages = indicator::transform(&closes, Indicator::Sma(sma))?.into_iter().collect::<HashMap<_, _>>();
+
+    writeln!(output, "time,open,high,low,close,sma{}", sma)?;
+
+    for &(start, candle) in candles {
+        let prices = candle.iter().map(|price| dollars(price.0 as i64)).collect::<Vec<_>>();
+        let average = averages.get(&start).map_or(String::new(), |average| dollars(average.round() as i64));
+        writeln!(output, "{},{},{}", start, prices.join(","), average)?;
+    }
+
+    output.flush()
+}
+
+fn run(options: Options) -> io::Result<()> {
+    let recorded = options.file.is_none();
+    let file = options.file.unwrap_or_else(|| "chart_export_example".to_string());
+
+    let storage = if recorded {
+        common::record_session(&file, 11, 50_000)?
+    } else {
+        FileStorage::<Timestamp, Price>::new(&file)?
+    };
+
+    let candles = candles(&storage, &file, options.interval)?;
+
+    match options.output {
+        Some(ref output) => {
+            export(File::create(output)?, &candles, options.sma)?;
+            eprintln!("Wrote {} candles to {}", candles.len(), output);
+        },
+        None => export(io::stdout(), &candles, options.sma)?,
+    }
+
+    if recorded {
+        fs::remove_file(&file).ok();
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let mut options = Options {
+        file: None,
+        interval: Interval::minutes(60),
+        sma: 8,
+        output: None,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--file" => args.next().map(|value| options.file = Some(value)),
+            "--interval" => args.next().and_then(|value| value.parse().ok()).map(|value| options.interval = value),
+            "--sma" => args.next().and_then(|value| value.parse().ok()).map(|value| options.sma = value),
+            "--output" => args.next().map(|value| options.output = Some(value)),
+            _ => None,
+        };
+
+        if parsed.is_none() || options.sma == 0 {
+            eprintln!("Usage: chart_export [--file PATH] [--interval 15m] [--sma N] [--output PATH]");
+            process::exit(1);
+        }
+    }
+
+    if let Err(error) = run(options) {
+        eprintln!("Example failed: {}", error);
+        process::exit(1);
+    }
+}
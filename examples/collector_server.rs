@@ -0,0 +1,179 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! A minimal collector and server deployment.
+//!
+//! The ingestion supervisor runs a synthetic feed into a `FileStorage`, while a server answers queries over TCP
+//! from the same storage.  Each connection sends one line of query clauses, such as "pool 1h ohlc" or "limit 10",
+//! and is sent back one record per line.  Once the feed is done, the example queries itself and exits, unless it's
+//! asked to keep serving.
+//!
+//! Usage: cargo run --example collector_server [-- --file PATH] [--trades N] [--serve]
+
+extern crate trade_data;
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::sync::{Arc, RwLock, mpsc};
+use std::thread;
+use std::time::Duration;
+
+use trade_data::{KeyValueStore, PoolingMethod, Timestamp};
+use trade_data::ingest::{RestartPolicy, Supervisor};
+use trade_data::parse;
+use trade_data::storage::FileStorage;
+
+use common::{Price, SyntheticFeed};
+
+type Storage = Arc<RwLock<FileStorage<Timestamp, Price>>>;
+
+/// Collects the feed into storage until the feed ends
+fn collect(storage: Storage, trades: usize) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+
+    let mut supervisor = Supervisor::new(RestartPolicy { max_restarts: Some(3), backoff: Duration::from_millis(10) });
+    supervisor.spawn(SyntheticFeed::new(1, trades), sender);
+
+    for (timestamp, price) in receiver {
+        storage.write().unwrap().store(Box::new(timestamp), Box::new(price))?;
+    }
+
+    for (name, result) in supervisor.join() {
+        if let Err(error) = result {
+            return Err(io::Error::new(error.kind(), format!("Ingestor {} failed: {}", name, error)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Answers each connection with the records of its query
+fn serve(listener: TcpListener, storage: Storage) {
+    for connection in listener.incoming() {
+        let storage = storage.clone();
+
+        thread::spawn(move || {
+            if let Ok(mut connection) = connection {
+                if let Err(error) = answer(&mut connection, &storage) {
+                    let _ = writeln!(connection, "error: {}", error);
+                }
+            }
+        });
+    }
+}
+
+fn answer(connection: &mut TcpStream, storage: &Storage) -> io::Result<()> {
+    let mut line = String::new();
+    BufReader::new(connection.try_clone()?).read_line(&mut line)?;
+
+    let text = parse::parse_clauses("trades", &line, parse::now())?;
+    let storage = storage.read().unwrap();
+
+    let records = if text.ohlc {
+        // One column for each of the open, high, low, and close
+        let columns = [PoolingMethod::Start, PoolingMethod::High, PoolingMethod::Low, PoolingMethod::End].iter()
+            .map(|&pooling| text.query.clone().pooling(pooling).evaluate_pooled::<Price>(&*storage))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        columns[0].iter().enumerate().map(|(i, &(timestamp, _))| {
+            let values = columns.iter().map(|column| column[i].1 .0.to_string()).collect::<Vec<_>>();
+            format!("{} {}", timestamp, values.join(" "))
+        }).collect::<Vec<_>>()
+    } else if text.query.interval.is_some() {
+        text.query.evaluate_pooled::<Price>(&*storage)?.into_iter().map(|(timestamp, price)| format!("{} {}", timestamp, price.0)).collect()
+    } else {
+        text.query.evaluate::<Price>(&*storage)?.into_iter().map(|(timestamp, price)| format!("{} {}", timestamp, price.0)).collect()
+    };
+
+    for record in records {
+        writeln!(connection, "{}", record)?;
+    }
+
+    Ok(())
+}
+
+/// Sends a query to the server and returns its answer
+fn ask(address: &str, query: &str) -> io::Result<String> {
+    let mut connection = TcpStream::connect(address)?;
+    writeln!(connection, "{}", query)?;
+
+    let mut answer = String::new();
+    connection.read_to_string(&mut answer)?;
+    Ok(answer)
+}
+
+fn run(file: &str, trades: usize, keep_serving: bool) -> io::Result<()> {
+    fs::remove_file(file).ok();
+    let storage = Arc::new(RwLock::new(FileStorage::<Timestamp, Price>::new(file)?));
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?.to_string();
+    println!("Serving {} at {}", file, address);
+
+    let server_storage = storage.clone();
+    thread::spawn(move || serve(listener, server_storage));
+
+    collect(storage.clone(), trades)?;
+    println!("Collected {} trades", storage.read().unwrap().len());
+
+    for query in &["limit 3", "pool 6h ohlc", "pool 1d mean"] {
+        println!("\n> {}\n{}", query, ask(&address, query)?.trim_end());
+    }
+
+    if keep_serving {
+        println!("\nStill serving at {}; press Ctrl-C to stop", address);
+        loop {
+            thread::sleep(Duration::from_secs(60));
+        }
+    }
+
+    fs::remove_file(file).ok();
+    Ok(())
+}
+
+fn main() {
+    let mut file = "collector_server_example".to_string();
+    let mut trades = 100_000;
+    let mut keep_serving = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--file" => args.next().map(|value| file = value),
+            "--trades" => args.next().and_then(|value| value.parse().ok()).map(|value| trades = value),
+            "--serve" => {
+                keep_serving = true;
+                Some(())
+            },
+            _ => None,
+        };
+
+        if parsed.is_none() {
+            eprintln!("Usage: collector_server [--file PATH] [--trades N] [--serve]");
+            process::exit(1);
+        }
+    }
+
+    if let Err(error) = run(&file, trades, keep_serving) {
+        eprintln!("Example failed: {}", error);
+        process::exit(1);
+    }
+}
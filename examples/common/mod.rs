@@ -0,0 +1,190 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! What the examples share: a price type that can be stored and pooled, and a synthetic trade feed to record.
+
+#![allow(dead_code)]
+
+use std::io;
+use std::str::{self, FromStr};
+
+use trade_data::{Accumulator, KeyValueStore, Poolable, PoolingMethod, Statistics, Storable, Timestamp};
+use trade_data::ingest::Ingestor;
+use trade_data::storage::FileStorage;
+
+/// Where the recorded sessions start, in milliseconds since the epoch
+pub const SESSION_START: Timestamp = 1_500_000_000_000;
+
+/// The mean time between synthetic trades, in milliseconds
+const MEAN_TRADE_GAP: Timestamp = 2000;
+
+/// A trade price, in cents
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Price(pub u64);
+
+const PRICE_DIGITS: usize = 8;
+
+impl Storable<FileStorage<Timestamp, Price>> for Price {
+    fn size() -> usize {
+        PRICE_DIGITS
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        format!("{:size$}", self.0, size = PRICE_DIGITS).into_bytes()
+    }
+
+    fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
+        if let Ok(string) = str::from_utf8(buffer) {
+            if let Ok(value) = u64::from_str(string.trim()) {
+                return Ok(Price(value));
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid data"))
+    }
+}
+
+pub struct PriceAccumulator {
+    pooling: PoolingMethod,
+    statistics: Statistics,
+}
+
+impl Accumulator<Price> for PriceAccumulator {
+    fn new(pooling: PoolingMethod) -> Self {
+        Self {
+            pooling: pooling,
+            statistics: Statistics::default(),
+        }
+    }
+
+    fn fold(&mut self, value: Price, weight: f64) {
+        self.statistics.fold(value.0 as f64, weight);
+    }
+
+    fn finalize(&self) -> Price {
+        Price(self.statistics.value(self.pooling).round() as u64)
+    }
+
+    fn reset(&mut self) {
+        self.statistics = Statistics::default();
+    }
+}
+
+impl Poolable for Price {
+    type Accumulator = PriceAccumulator;
+}
+
+/// A small xorshift generator, so sessions can be reproduced from a seed
+#[derive(Clone, Copy)]
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in the range [low, high)
+    pub fn between(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next() % (high - low)
+    }
+}
+
+/// A feed of random-walk trade prices that ends after a number of trades.  It isn't paced, so a session of days
+/// is recorded in moments.
+pub struct SyntheticFeed {
+    random: Random,
+    next_time: Timestamp,
+    price: Price,
+    remaining: usize,
+}
+
+impl SyntheticFeed {
+    pub fn new(seed: u64, trades: usize) -> Self {
+        Self {
+            random: Random::new(seed),
+            next_time: SESSION_START,
+            price: Price(100_000),
+            remaining: trades,
+        }
+    }
+}
+
+impl Ingestor for SyntheticFeed {
+    type Message = (Timestamp, Price);
+    type Value = Price;
+
+    fn name(&self) -> &str {
+        "synthetic"
+    }
+
+    fn connect(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn subscribe(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Self::Message>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+
+        let message = (self.next_time, self.price);
+
+        self.next_time += self.random.between(1, MEAN_TRADE_GAP * 2);
+        self.price = match self.random.next() % 5 {
+            0 | 1 => Price(self.price.0.saturating_sub(self.random.between(1, 20)).max(1)),
+            2 | 3 => Price(self.price.0 + self.random.between(1, 20)),
+            _ => self.price,
+        };
+
+        Ok(Some(message))
+    }
+
+    fn normalize(&self, message: Self::Message) -> io::Result<Vec<(Timestamp, Price)>> {
+        Ok(vec![message])
+    }
+}
+
+/// Records a synthetic session into a new file, without going through the ingestion supervisor
+pub fn record_session(file: &str, seed: u64, trades: usize) -> io::Result<FileStorage<Timestamp, Price>> {
+    ::std::fs::remove_file(file).ok();
+
+    let mut storage = FileStorage::<Timestamp, Price>::new(file)?;
+    let mut feed = SyntheticFeed::new(seed, trades);
+
+    while let Some(message) = feed.receive()? {
+        for (timestamp, price) in feed.normalize(message)? {
+            storage.store(Box::new(timestamp), Box::new(price))?;
+        }
+    }
+
+    Ok(storage)
+}
+
+/// Formats cents as dollars
+pub fn dollars(cents: i64) -> String {
+    format!("{}{}.{:02}", if cents < 0 { "-" } else { "" }, cents.abs() / 100, cents.abs() % 100)
+}
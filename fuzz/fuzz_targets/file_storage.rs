@@ -0,0 +1,56 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Opens arbitrary bytes as a storage file and reads it every way a time series can be read.  A corrupt file may fail
+//! to open or give wrong answers, but must never panic.
+
+#![no_main]
+
+use std::env;
+use std::fs;
+use std::process;
+
+use libfuzzer_sys::fuzz_target;
+
+use trade_data::{TimeSeries, TimeUnit, Timestamp};
+use trade_data::storage::FileStorage;
+
+fn read_everything<S>(storage: &S) where S: TimeSeries {
+    storage.retrieve_all().ok();
+    storage.retrieve_from(5_000).ok();
+    storage.retrieve_to(5_000).ok();
+    storage.retrieve_range(1_000..9_000).ok();
+    storage.retrieve_nearest(5_000, None).ok();
+    storage.find_gaps(100, 0..10_000).ok();
+}
+
+fuzz_target!(|data: &[u8]| {
+    let path = env::temp_dir().join(format!("trade-data-fuzz-file-storage-{}", process::id()));
+    let filename = path.to_str().expect("Temporary directory isn't UTF-8");
+
+    if fs::write(filename, data).is_err() {
+        return;
+    }
+
+    if let Ok(storage) = FileStorage::<Timestamp, Timestamp>::new(filename) {
+        read_everything(&storage);
+    }
+
+    if let Ok(storage) = FileStorage::<Timestamp, (Timestamp, Timestamp)>::with_unit(filename, TimeUnit::Nanoseconds) {
+        read_everything(&storage);
+    }
+
+    fs::remove_file(filename).ok();
+});
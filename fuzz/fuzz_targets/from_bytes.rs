@@ -0,0 +1,30 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decodes arbitrary bytes as each of the library's storable values.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use trade_data::Storable;
+use trade_data::Timestamp;
+use trade_data::storage::FileStorage;
+
+fuzz_target!(|data: &[u8]| {
+    <Timestamp as Storable<FileStorage<Timestamp, Timestamp>>>::from_bytes(data).ok();
+    <(Timestamp, Timestamp) as Storable<FileStorage<Timestamp, (Timestamp, Timestamp)>>>::from_bytes(data).ok();
+    <(Timestamp, Timestamp, Timestamp) as Storable<FileStorage<Timestamp, (Timestamp, Timestamp, Timestamp)>>>::from_bytes(data).ok();
+});
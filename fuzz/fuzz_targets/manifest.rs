@@ -0,0 +1,28 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parses arbitrary text as a fingerprint manifest.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use trade_data::fingerprint::Manifest;
+
+fuzz_target!(|text: &str| {
+    if let Ok(manifest) = Manifest::parse(text) {
+        manifest.fingerprint();
+    }
+});
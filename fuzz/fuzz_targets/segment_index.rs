@@ -0,0 +1,49 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Opens a tiered storage whose segment index is arbitrary bytes.
+
+#![no_main]
+
+use std::env;
+use std::fs;
+use std::process;
+
+use libfuzzer_sys::fuzz_target;
+
+use trade_data::{TimeSeries, Timestamp};
+use trade_data::storage::{DirectoryStore, TieredStorage};
+
+fuzz_target!(|data: &[u8]| {
+    let directory = env::temp_dir().join(format!("trade-data-fuzz-segment-index-{}", process::id()));
+    let filename = directory.join("hot");
+    let filename = filename.to_str().expect("Temporary directory isn't UTF-8");
+
+    if fs::create_dir_all(directory.join("cold")).is_err() || fs::write(format!("{}.segments", filename), data).is_err() {
+        return;
+    }
+
+    let cold = match DirectoryStore::new(directory.join("cold").to_str().expect("Temporary directory isn't UTF-8")) {
+        Ok(cold) => cold,
+        Err(_) => return,
+    };
+
+    if let Ok(storage) = TieredStorage::<Timestamp>::new(filename, Box::new(cold)) {
+        storage.retrieve_all().ok();
+        storage.retrieve_range(1_000..9_000).ok();
+    }
+
+    fs::remove_dir_all(directory).ok();
+});
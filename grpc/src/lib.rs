@@ -0,0 +1,301 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The gRPC service of trade-data, generated from `proto/trade_data.proto` with tonic.
+//!
+//! The service only translates between gRPC and a `Backend`, which the server implements over its channels.  Backends
+//! are synchronous, like the rest of the server, so they're called on tokio's blocking threads.  This is a crate of
+//! its own because tonic's generated code needs the 2018 edition.
+
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::transport::Server;
+use tonic::{Request, Response};
+
+pub use prost::Message;
+pub use tonic::metadata::MetadataMap as Metadata;
+pub use tonic::{Code, Status};
+
+pub mod proto {
+    tonic::include_proto!("trade_data.v1");
+}
+
+use proto::trade_data_server::{TradeData, TradeDataServer};
+use proto::{PoolRangeRequest, PoolRangeResponse, RetrieveRequest, RetrieveResponse, StoreRequest, StoreResponse, SubscribeRequest, Update};
+
+/// How often subscriptions check for new records
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many updates may wait to be sent to a subscriber before its subscription stops polling
+const SUBSCRIPTION_BUFFER: usize = 64;
+
+/// What the service serves.  Each call is given the metadata of its request, for credentials.
+pub trait Backend: Send + Sync + 'static {
+    type Subscription: Subscription;
+
+    fn store(&self, metadata: &Metadata, request: StoreRequest) -> Result<StoreResponse, Status>;
+    fn retrieve(&self, metadata: &Metadata, request: RetrieveRequest) -> Result<RetrieveResponse, Status>;
+    fn pool_range(&self, metadata: &Metadata, request: PoolRangeRequest) -> Result<PoolRangeResponse, Status>;
+    fn subscribe(&self, metadata: &Metadata, request: SubscribeRequest) -> Result<Self::Subscription, Status>;
+}
+
+/// A subscription of a backend, polled for updates until it's finished or fails
+pub trait Subscription: Send + 'static {
+    /// The updates since the last poll.  The first poll returns everything so far.
+    fn poll(&mut self) -> Result<Vec<Update>, Status>;
+
+    /// Whether there will be no more updates
+    fn is_finished(&self) -> bool;
+}
+
+/// A backend with its address already bound, so that an address in use is found out before a thread is started to
+/// serve it
+pub struct Listener<B> {
+    listener: TcpListener,
+    backend: B,
+}
+
+/// Binds `address`, such as "127.0.0.1:8002", for a backend
+pub fn bind<B>(address: &str, backend: B) -> io::Result<Listener<B>> where B: Backend {
+    let address = address.parse::<SocketAddr>().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid gRPC address"))?;
+    let listener = TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+
+    Ok(Listener { listener, backend })
+}
+
+impl<B> Listener<B> where B: Backend {
+    /// Serves the backend.  Blocks until the server fails, so it's meant to be given a thread of its own.
+    pub fn serve(self) -> io::Result<()> {
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+
+        let server = Server::builder().add_service(TradeDataServer::new(Service(Arc::new(self.backend))));
+        let listener = self.listener;
+        runtime.block_on(async move {
+            let incoming = TcpListenerStream::new(tokio::net::TcpListener::from_std(listener)?);
+            server.serve_with_incoming(incoming).await.map_err(|error| io::Error::new(io::ErrorKind::Other, error.to_string()))
+        })
+    }
+}
+
+/// Serves a backend at `address`, such as "127.0.0.1:8002".  Blocks until the server fails, so it's meant to be
+/// given a thread of its own.
+pub fn serve<B>(address: &str, backend: B) -> io::Result<()> where B: Backend {
+    bind(address, backend)?.serve()
+}
+
+struct Service<B>(Arc<B>);
+
+#[tonic::async_trait]
+impl<B> TradeData for Service<B> where B: Backend {
+    async fn store(&self, request: Request<StoreRequest>) -> Result<Response<StoreResponse>, Status> {
+        let backend = self.0.clone();
+        blocking(move || backend.store(&request.metadata().clone(), request.into_inner())).await.map(Response::new)
+    }
+
+    async fn retrieve(&self, request: Request<RetrieveRequest>) -> Result<Response<RetrieveResponse>, Status> {
+        let backend = self.0.clone();
+        blocking(move || backend.retrieve(&request.metadata().clone(), request.into_inner())).await.map(Response::new)
+    }
+
+    async fn pool_range(&self, request: Request<PoolRangeRequest>) -> Result<Response<PoolRangeResponse>, Status> {
+        let backend = self.0.clone();
+        blocking(move || backend.pool_range(&request.metadata().clone(), request.into_inner())).await.map(Response::new)
+    }
+
+    type SubscribeStream = ReceiverStream<Result<Update, Status>>;
+
+    async fn subscribe(&self, request: Request<SubscribeRequest>) -> Result<Response<Self::SubscribeStream>, Status> {
+        let backend = self.0.clone();
+        let subscription = blocking(move || backend.subscribe(&request.metadata().clone(), request.into_inner())).await?;
+
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        tokio::spawn(follow(subscription, sender));
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+/// Sends a subscription's updates until it's finished, it fails, or the subscriber goes away
+async fn follow<S>(mut subscription: S, sender: mpsc::Sender<Result<Update, Status>>) where S: Subscription {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let (polled, updates) = match task::spawn_blocking(move || {
+            let updates = subscription.poll();
+            (subscription, updates)
+        }).await {
+            Ok(polled) => polled,
+            Err(_) => return,
+        };
+        subscription = polled;
+
+        let updates = match updates {
+            Ok(updates) => updates,
+            Err(status) => {
+                let _ = sender.send(Err(status)).await;
+                return;
+            },
+        };
+
+        for update in updates {
+            if sender.send(Ok(update)).await.is_err() {
+                return;
+            }
+        }
+
+        if subscription.is_finished() {
+            return;
+        }
+    }
+}
+
+async fn blocking<F, T>(call: F) -> Result<T, Status> where F: FnOnce() -> Result<T, Status> + Send + 'static, T: Send + 'static {
+    task::spawn_blocking(call).await.map_err(|_| Status::internal("Request could not be served"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use tokio_stream::StreamExt;
+
+    use proto::{Channel, Range, Record, Timestamp};
+    use proto::update::Kind;
+
+    /// A single channel in memory, readable by anyone and writable with a key
+    struct MemoryBackend(Arc<Mutex<Vec<Record>>>);
+
+    struct MemorySubscription {
+        records: Arc<Mutex<Vec<Record>>>,
+        sent: usize,
+        end: usize,
+    }
+
+    fn check_channel(channel: Option<Channel>) -> Result<(), Status> {
+        match channel {
+            Some(ref channel) if channel.name == "trades" => Ok(()),
+            Some(_) => Err(Status::not_found("No such channel")),
+            None => Err(Status::invalid_argument("Request names no channel")),
+        }
+    }
+
+    impl Backend for MemoryBackend {
+        type Subscription = MemorySubscription;
+
+        fn store(&self, metadata: &Metadata, request: StoreRequest) -> Result<StoreResponse, Status> {
+            check_channel(request.channel)?;
+            if metadata.get("x-api-key").is_none() {
+                return Err(Status::unauthenticated("Writing needs a key"));
+            }
+
+            let stored = request.records.len() as u64;
+            self.0.lock().unwrap().extend(request.records);
+            Ok(StoreResponse { stored: stored })
+        }
+
+        fn retrieve(&self, _metadata: &Metadata, request: RetrieveRequest) -> Result<RetrieveResponse, Status> {
+            check_channel(request.channel)?;
+            Ok(RetrieveResponse { unit: request.unit, records: self.0.lock().unwrap().clone(), next_cursor: String::new() })
+        }
+
+        fn pool_range(&self, _metadata: &Metadata, request: PoolRangeRequest) -> Result<PoolRangeResponse, Status> {
+            check_channel(request.channel)?;
+            Err(Status::unimplemented("Pooling isn't needed here"))
+        }
+
+        fn subscribe(&self, _metadata: &Metadata, request: SubscribeRequest) -> Result<MemorySubscription, Status> {
+            check_channel(request.channel)?;
+            Ok(MemorySubscription {
+                records: self.0.clone(),
+                sent: 0,
+                end: request.range.and_then(|range| range.end).map_or(usize::max_value(), |end| end.value as usize),
+            })
+        }
+    }
+
+    impl Subscription for MemorySubscription {
+        fn poll(&mut self) -> Result<Vec<Update>, Status> {
+            let records = self.records.lock().unwrap();
+            let updates = records[self.sent..].iter().cloned().enumerate().map(|(i, record)| Update {
+                seq: (self.sent + i + 1) as u64,
+                resume: String::new(),
+                unit: 0,
+                kind: Some(Kind::Record(record)),
+            }).collect();
+            self.sent = records.len();
+            Ok(updates)
+        }
+
+        fn is_finished(&self) -> bool {
+            self.sent >= self.end
+        }
+    }
+
+    fn channel(name: &str) -> Option<Channel> {
+        Some(Channel { market: "gemini".to_string(), symbol: "btcusd".to_string(), name: name.to_string() })
+    }
+
+    fn record(timestamp: u64, value: u64) -> Record {
+        Record { timestamp: Some(Timestamp { value: timestamp }), value: value }
+    }
+
+    #[test]
+    fn test_service() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let service = Service(Arc::new(MemoryBackend(Arc::new(Mutex::new(Vec::new())))));
+
+        runtime.block_on(async {
+            let store = StoreRequest { channel: channel("trades"), records: vec![record(10, 1), record(20, 2)] };
+            assert_eq!(service.store(Request::new(store.clone())).await.unwrap_err().code(), Code::Unauthenticated);
+
+            let mut request = Request::new(store);
+            request.metadata_mut().insert("x-api-key", "key".parse().unwrap());
+            assert_eq!(service.store(request).await.unwrap().into_inner().stored, 2);
+
+            let retrieve = RetrieveRequest {
+                channel: channel("trades"),
+                unit: proto::TimeUnit::Microseconds as i32,
+                ..RetrieveRequest::default()
+            };
+            let response = service.retrieve(Request::new(retrieve)).await.unwrap().into_inner();
+            assert_eq!(response.unit(), proto::TimeUnit::Microseconds);
+            assert_eq!(response.records, vec![record(10, 1), record(20, 2)]);
+
+            let retrieve = RetrieveRequest { channel: channel("quotes"), ..RetrieveRequest::default() };
+            assert_eq!(service.retrieve(Request::new(retrieve)).await.unwrap_err().code(), Code::NotFound);
+
+            // The subscription ends once it's sent the second record
+            let subscribe = SubscribeRequest {
+                channel: channel("trades"),
+                range: Some(Range { start: None, end: Some(Timestamp { value: 2 }) }),
+                ..SubscribeRequest::default()
+            };
+            let updates = service.subscribe(Request::new(subscribe)).await.unwrap().into_inner().collect::<Vec<_>>().await;
+            let sequences = updates.into_iter().map(|update| update.unwrap().seq).collect::<Vec<u64>>();
+            assert_eq!(sequences, vec![1, 2]);
+        });
+    }
+}
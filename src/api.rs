@@ -0,0 +1,111 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Versioning of the HTTP API.
+//!
+//! The server reports its API version at `/version` and in the `X-Trade-Data-Api-Version` header of every response,
+//! as "major.minor".  Additions that older clients can ignore, such as a new pooling method or a new response field,
+//! bump the minor version.  Changes to the shape of existing responses bump the major version.  A client checks the
+//! version it's given with `negotiate` before relying on any response, and refuses servers that can't serve it.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// The header every response carries the API version in
+pub const VERSION_HEADER: &str = "X-Trade-Data-Api-Version";
+
+/// The version of the API this build serves
+pub const API_VERSION: ApiVersion = ApiVersion { major: 1, minor: 0 };
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct ApiVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ApiVersion {
+    /// Whether a server of this version can serve a client that needs `required`
+    pub fn supports(&self, required: ApiVersion) -> bool {
+        self.major == required.major && self.minor >= required.minor
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid API version \"{}\"", text));
+
+        let mut parts = text.trim().splitn(2, '.');
+        let major = parts.next().and_then(|major| major.parse().ok()).ok_or_else(invalid)?;
+        let minor = parts.next().and_then(|minor| minor.parse().ok()).ok_or_else(invalid)?;
+
+        Ok(ApiVersion {
+            major: major,
+            minor: minor,
+        })
+    }
+}
+
+/// Checks the version a server advertised, the value of its version header if it sent one, against the version a
+/// client needs.  Returns the server's version if it can serve the client.
+pub fn negotiate(advertised: Option<&str>, required: ApiVersion) -> io::Result<ApiVersion> {
+    let advertised = advertised.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Server doesn't report an API version"))?;
+    let server = advertised.parse::<ApiVersion>()?;
+
+    if server.supports(required) {
+        Ok(server)
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, format!("Server API version {} can't serve a client of version {}", server, required)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_version() {
+        assert_eq!("1.2".parse::<ApiVersion>().unwrap(), ApiVersion { major: 1, minor: 2 });
+        assert_eq!(ApiVersion { major: 1, minor: 2 }.to_string(), "1.2");
+        assert!("1".parse::<ApiVersion>().is_err());
+        assert!("1.x".parse::<ApiVersion>().is_err());
+        assert!("".parse::<ApiVersion>().is_err());
+
+        assert!(API_VERSION.supports(API_VERSION));
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let client = ApiVersion { major: 1, minor: 1 };
+
+        // Newer minor versions only add to the API
+        assert_eq!(negotiate(Some("1.1"), client).unwrap(), ApiVersion { major: 1, minor: 1 });
+        assert_eq!(negotiate(Some("1.3"), client).unwrap(), ApiVersion { major: 1, minor: 3 });
+
+        assert!(negotiate(Some("1.0"), client).is_err());
+        assert!(negotiate(Some("2.1"), client).is_err());
+        assert!(negotiate(Some("0.9"), client).is_err());
+        assert!(negotiate(Some("one"), client).is_err());
+        assert!(negotiate(None, client).is_err());
+    }
+}
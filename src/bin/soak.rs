@@ -0,0 +1,392 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Soak test harness for storage configurations.
+//!
+//! Simulates weeks of capture on an accelerated clock.  A child collector process is fed synthetic trades
+//! through the ingestion supervisor and writes them to a `FileStorage`.  The parent repeatedly stops the
+//! collector, either gracefully or by killing it at a random point, and then verifies that the file
+//! reopens cleanly and that its records are intact before starting the collector again.
+//!
+//! Usage: soak [--file PATH] [--days N] [--speed X] [--seed N] [--max-lifetime MS]
+
+extern crate trade_data;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process::{self, Child, Command, Stdio};
+use std::str::{self, FromStr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use trade_data::{KeyValueStore, Storable, TimeSeries, Timestamp};
+use trade_data::ingest::{Ingestor, RestartPolicy, Supervisor};
+use trade_data::storage::FileStorage;
+
+/// Where the simulated clock starts, in milliseconds since the epoch
+const SIMULATION_START: Timestamp = 1_500_000_000_000;
+
+const MILLISECONDS_PER_DAY: Timestamp = 24 * 60 * 60 * 1000;
+
+/// The mean simulated time between synthetic trades, in milliseconds
+const MEAN_TRADE_GAP: Timestamp = 5000;
+
+/// Roughly one in this many synthetic messages will fail, forcing the supervisor to restart the feed
+const FEED_FAILURE_RATE: u64 = 5000;
+
+struct Options {
+    file: String,
+    days: u64,
+    speed: u64,
+    seed: u64,
+    max_lifetime: u64,
+}
+
+impl Options {
+    fn parse(mut args: Vec<String>) -> Result<Self, String> {
+        let mut options = Options {
+            file: "soak_test_storage".to_string(),
+            days: 14,
+            speed: 100_000,
+            seed: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(1),
+            max_lifetime: 2000,
+        };
+
+        args.reverse();
+        while let Some(arg) = args.pop() {
+            let value = args.pop().ok_or_else(|| format!("Missing value for {}", arg))?;
+
+            match arg.as_str() {
+                "--file" => options.file = value,
+                "--days" => options.days = parse_number(&arg, &value)?,
+                "--speed" => options.speed = parse_number(&arg, &value)?,
+                "--seed" => options.seed = parse_number(&arg, &value)?,
+                "--max-lifetime" => options.max_lifetime = parse_number(&arg, &value)?,
+                _ => return Err(format!("Unknown argument {}", arg)),
+            }
+        }
+
+        if options.speed == 0 || options.max_lifetime == 0 {
+            return Err("--speed and --max-lifetime must be greater than zero".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_number(arg: &str, value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", arg, value))
+}
+
+/// The synthetic trade price
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+struct Price(u64);
+
+const PRICE_DIGITS: usize = 8;
+
+impl Storable<FileStorage<Timestamp, Price>> for Price {
+    fn size() -> usize {
+        PRICE_DIGITS
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        format!("{:size$}", self.0, size = PRICE_DIGITS).into_bytes()
+    }
+
+    fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
+        if let Ok(string) = str::from_utf8(buffer) {
+            if let Ok(value) = u64::from_str(string.trim()) {
+                return Ok(Price(value));
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid data"))
+    }
+}
+
+/// A small xorshift generator, so runs can be reproduced from a seed
+#[derive(Clone, Copy)]
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Random(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in the range [low, high)
+    fn between(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next() % (high - low)
+    }
+}
+
+/// Maps real elapsed time onto simulated time
+struct SimulatedClock {
+    real_start: Instant,
+    simulated_start: Timestamp,
+    speed: u64,
+}
+
+impl SimulatedClock {
+    fn new(simulated_start: Timestamp, speed: u64) -> Self {
+        Self {
+            real_start: Instant::now(),
+            simulated_start: simulated_start,
+            speed: speed,
+        }
+    }
+
+    fn now(&self) -> Timestamp {
+        let elapsed = self.real_start.elapsed();
+        let elapsed_milliseconds = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+
+        self.simulated_start + elapsed_milliseconds * self.speed
+    }
+
+    /// Sleeps in real time until the simulated clock reaches the timestamp.
+    fn sleep_until(&self, timestamp: Timestamp) {
+        let now = self.now();
+        if timestamp > now {
+            let real_micros = (timestamp - now) * 1000 / self.speed;
+            thread::sleep(Duration::from_micros(real_micros));
+        }
+    }
+}
+
+/// A feed of random-walk trade prices that occasionally fails
+struct SyntheticFeed {
+    clock: SimulatedClock,
+    random: Random,
+    next_time: Timestamp,
+    end_time: Timestamp,
+    price: Price,
+}
+
+impl Ingestor for SyntheticFeed {
+    type Message = (Timestamp, Price);
+    type Value = Price;
+
+    fn name(&self) -> &str {
+        "synthetic"
+    }
+
+    fn connect(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn subscribe(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Self::Message>> {
+        if self.next_time >= self.end_time {
+            return Ok(None);
+        }
+
+        if self.random.next() % FEED_FAILURE_RATE == 0 {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "Synthetic feed dropped"));
+        }
+
+        self.clock.sleep_until(self.next_time);
+
+        let message = (self.next_time, self.price);
+
+        self.next_time += self.random.between(1, MEAN_TRADE_GAP * 2);
+        self.price = match self.random.next() % 3 {
+            0 => Price(self.price.0.saturating_sub(1).max(1)),
+            1 => Price(self.price.0 + 1),
+            _ => self.price,
+        };
+
+        Ok(Some(message))
+    }
+
+    fn normalize(&self, message: Self::Message) -> io::Result<Vec<(Timestamp, Price)>> {
+        Ok(vec![message])
+    }
+}
+
+/// Runs the collector side of the harness: feeds synthetic trades into storage until the end time is
+/// reached or stdin is closed by the parent.
+fn run_collector(file: &str, from: Timestamp, end: Timestamp, speed: u64, seed: u64) -> io::Result<()> {
+    let mut storage = FileStorage::<Timestamp, Price>::new(file)?;
+
+    let (sender, receiver) = mpsc::channel();
+
+    let mut supervisor = Supervisor::new(RestartPolicy { max_restarts: None, backoff: Duration::from_millis(1) });
+    supervisor.spawn(SyntheticFeed {
+        clock: SimulatedClock::new(from, speed),
+        random: Random::new(seed),
+        next_time: from,
+        end_time: end,
+        price: Price(1000),
+    }, sender);
+
+    // The parent asks for a graceful stop by closing our stdin
+    let handle = supervisor.handle();
+    thread::spawn(move || {
+        let mut buffer = [0u8; 1];
+        while let Ok(1) = io::stdin().read(&mut buffer) { }
+        handle.stop();
+    });
+
+    for (timestamp, price) in receiver {
+        storage.store(Box::new(timestamp), Box::new(price))?;
+    }
+
+    for (name, result) in supervisor.join() {
+        if let Err(error) = result {
+            return Err(io::Error::new(error.kind(), format!("Ingestor {} failed: {}", name, error)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Reopens the storage file and checks that every record is readable and in order.
+/// Returns the number of records and the last timestamp.
+fn verify(file: &str) -> Result<(usize, Option<Timestamp>), String> {
+    let storage = FileStorage::<Timestamp, Price>::new(file).map_err(|e| format!("File failed to reopen: {}", e))?;
+
+    let records = storage.retrieve_all().map_err(|e| format!("Records failed to read: {}", e))?.into_vec::<Timestamp, Price>();
+
+    if records.len() != storage.len() {
+        return Err(format!("Storage reports {} records but {} were read", storage.len(), records.len()));
+    }
+
+    if let Some(window) = records.windows(2).find(|w| w[0].0 >= w[1].0) {
+        return Err(format!("Records out of order: {} followed by {}", window[0].0, window[1].0));
+    }
+
+    Ok((records.len(), records.last().map(|r| r.0)))
+}
+
+fn spawn_collector(options: &Options, from: Timestamp, end: Timestamp, seed: u64) -> io::Result<Child> {
+    Command::new(env::current_exe()?)
+        .arg("--collector")
+        .args(&[options.file.clone(), from.to_string(), end.to_string(), options.speed.to_string(), seed.to_string()])
+        .stdin(Stdio::piped())
+        .spawn()
+}
+
+fn run_harness(options: Options) -> Result<(), String> {
+    println!("Soaking {} for {} simulated days at {}x, seed {}", options.file, options.days, options.speed, options.seed);
+
+    fs::remove_file(&options.file).ok();
+
+    let mut random = Random::new(options.seed);
+    let end = SIMULATION_START + options.days * MILLISECONDS_PER_DAY;
+
+    let mut from = SIMULATION_START;
+    let mut previous_count = 0;
+    let mut cycles = 0;
+    let mut kills = 0;
+
+    loop {
+        cycles += 1;
+
+        let mut child = spawn_collector(&options, from, end, random.next()).map_err(|e| format!("Failed to start collector: {}", e))?;
+
+        let lifetime = Duration::from_millis(random.between(1, options.max_lifetime));
+        let started = Instant::now();
+
+        let mut finished = false;
+        while started.elapsed() < lifetime {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => {
+                    finished = true;
+                    break;
+                },
+                Ok(Some(status)) => return Err(format!("Collector exited with {}", status)),
+                Ok(None) => thread::sleep(Duration::from_millis(5)),
+                Err(error) => return Err(format!("Failed to wait on collector: {}", error)),
+            }
+        }
+
+        if !finished {
+            if random.next() % 2 == 0 {
+                // Random kill-point
+                kills += 1;
+                child.kill().ok();
+                child.wait().ok();
+            } else {
+                // Periodic restart
+                child.stdin.take();
+                let status = child.wait().map_err(|e| format!("Failed to wait on collector: {}", e))?;
+                if !status.success() {
+                    return Err(format!("Collector exited with {} during graceful stop", status));
+                }
+            }
+        }
+
+        let (count, last) = verify(&options.file).map_err(|e| format!("Cycle {}: {}", cycles, e))?;
+
+        if count < previous_count {
+            return Err(format!("Cycle {}: record count fell from {} to {}", cycles, previous_count, count));
+        }
+        previous_count = count;
+
+        println!("Cycle {}: {} records, simulated day {:.2}", cycles, count, (last.unwrap_or(from) - SIMULATION_START) as f64 / MILLISECONDS_PER_DAY as f64);
+
+        if finished {
+            break;
+        }
+
+        if let Some(last) = last {
+            from = last + 1;
+        }
+    }
+
+    println!("Soak passed: {} cycles, {} kills, {} records", cycles, kills, previous_count);
+
+    fs::remove_file(&options.file).ok();
+
+    Ok(())
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+
+    if args.first().map(|a| a.as_str()) == Some("--collector") {
+        let numbers = args[2..].iter().map(|a| a.parse::<u64>()).collect::<Result<Vec<u64>, _>>();
+
+        let result = match numbers {
+            Ok(ref n) if n.len() == 4 => run_collector(&args[1], n[0], n[1], n[2], n[3]),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid collector arguments")),
+        };
+
+        if let Err(error) = result {
+            eprintln!("Collector failed: {}", error);
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    let result = Options::parse(args).and_then(run_harness);
+
+    if let Err(error) = result {
+        eprintln!("Soak failed: {}", error);
+        process::exit(1);
+    }
+}
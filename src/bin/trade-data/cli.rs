@@ -0,0 +1,916 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Command-line subcommands.  `query` runs a query in the query language of `trade_data::parse` against the
+//! configured channels, or just its clauses against a storage file given with `--file`, and prints the result as a
+//! table, CSV, or JSON.  A query of just `latest` prints the latest record.  `watch` re-evaluates a query, or reads a
+//! channel's latest record, on a timer and prints what changed.  `diff` compares two channels or storage files, and
+//! exits with a failure if they differ.  `import` loads an exchange history dump into
+//! a channel and prints the totals the same way.  `fingerprint` hashes every channel's storage file and can write a
+//! manifest of them, which `verify-fingerprint` checks a copy of the data against, exiting with a failure on any drift.
+//! `snapshot` copies every channel's storage file into a snapshot directory, and `restore` rebuilds the channels whose
+//! files are missing from one, replaying later records from copies of the files under `--directory`.
+//! `gc` removes the temporary files and orphaned segment indexes left by interrupted archives.  `compact` rewrites a
+//! storage file without its duplicate, out of order, and malformed records, moving the out of order ones into a
+//! quarantine if one is given.  It compacts the file as the value type and unit in its channel info unless `--type`
+//! and `--unit` say otherwise.  `export` writes the
+//! result of a query to a Parquet file, or to an Arrow IPC stream if the file name ends in ".arrow", when built with
+//! the columnar feature.  `completions` prints a completion script.
+//!
+//! Usage: trade-data query [--output json|csv|table] "gemini/btcusd/trades from now-6h pool 5m ohlc"
+//!        trade-data query [--output json|csv|table] --file gemini_btcusd_trades "from now-1h pool 5m ohlc"
+//!        trade-data watch [--output json|csv|table] [--interval 1s] "gemini/btcusd/trades from now-5m pool 1m"
+//!        trade-data diff [--output json|csv|table] [--range now-1d..now] gemini/btcusd/trades backup/gemini_btcusd_trades
+//!        trade-data import [--output json|csv|table] gemini/btcusd/trades trades.csv --exchange gemini
+//!        trade-data fingerprint [--output json|csv|table] [--manifest manifest.txt]
+//!        trade-data verify-fingerprint [--output json|csv|table] manifest.txt [--directory /mnt/backup]
+//!        trade-data snapshot [--output json|csv|table] snapshots/2024-01-01
+//!        trade-data restore [--output json|csv|table] snapshots/2024-01-01 [--directory /mnt/replica]
+//!        trade-data gc [--output json|csv|table] [--directory data] [--older-than 1h]
+//!        trade-data compact [--output json|csv|table] gemini_btcusd_trades [--type u64|quote|sourced|trade] [--unit us] [--quarantine gemini_btcusd_trades.quarantine]
+//!        trade-data export [--output json|csv|table] --out candles.parquet "gemini/btcusd/trades from now-1d pool 5m ohlc"
+//!        trade-data completions bash|zsh|fish
+
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{self, Map, Value};
+
+use trade_data::{Bands, ChannelInfo, Difference, Identified, PooledTimeSeries, PoolingMethod, Query, Quote, Sourced, TimeSeries, TimeUnit, Timestamp, diff_records};
+#[cfg(feature = "columnar")]
+use trade_data::export::{self, Column, RecordBatch};
+use trade_data::fingerprint::{self, Drift, Fingerprint, Manifest};
+use trade_data::parse::{self, parse_clauses, parse_interval, parse_timestamp};
+use trade_data::server::{self, Channel, ServedChannel};
+use trade_data::snapshot::Snapshot;
+use trade_data::storage::{FileStorage, Quarantine};
+
+use import;
+
+pub const SUBCOMMANDS: &[&str] = &["query", "watch", "diff", "import", "fingerprint", "verify-fingerprint", "snapshot", "restore", "gc", "compact", "export", "completions"];
+
+const OUTPUTS: &[&str] = &["json", "csv", "table"];
+
+const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+const CLAUSES: &[&str] = &[
+    "latest", "from", "to", "pool", "fill", "anchor", "tz", "week", "sma", "ema", "min", "max", "rsi", "change", "macd", "bollinger", "skip", "limit", "reverse",
+];
+
+const POOLING_METHODS: &[&str] = &["end", "start", "high", "low", "mean", "stddev", "sum", "vwap", "ohlc", "p50", "p95", "p99"];
+
+const EXCHANGES: &[&str] = &["gemini", "binance", "kraken"];
+
+const VALUE_TYPES: &[&str] = &["u64", "quote", "sourced", "trade"];
+
+/// How a subcommand prints its results
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    /// An array of objects, one per row
+    Json,
+    /// A header line followed by comma-separated rows
+    Csv,
+    /// Aligned columns under a header
+    Table,
+}
+
+impl FromStr for Output {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        match text {
+            "json" => Ok(Output::Json),
+            "csv" => Ok(Output::Csv),
+            "table" => Ok(Output::Table),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Output must be one of json, csv, or table")),
+        }
+    }
+}
+
+/// Named columns of values, printed in any `Output` format
+pub struct Rows {
+    columns: &'static [&'static str],
+    rows: Vec<Vec<Value>>,
+}
+
+impl Rows {
+    pub fn new(columns: &'static [&'static str], rows: Vec<Vec<Value>>) -> Self {
+        Self {
+            columns: columns,
+            rows: rows,
+        }
+    }
+
+    pub fn format(&self, output: Output) -> String {
+        match output {
+            Output::Json => serde_json::to_string(&self.objects()).unwrap_or_default() + "\n",
+            Output::Csv | Output::Table => self.format_lines(output, true),
+        }
+    }
+
+    /// Formats the rows one per line, for output that's printed a few rows at a time.  JSON rows are objects on
+    /// their own lines, and CSV and table rows are only preceded by a header if `header` is set.
+    pub fn format_lines(&self, output: Output, header: bool) -> String {
+        match output {
+            Output::Json => self.objects().iter().map(|object| object.to_string() + "\n").collect(),
+            Output::Csv => {
+                let mut text = if header { self.columns.join(",") + "\n" } else { String::new() };
+                for row in &self.rows {
+                    text += &row.iter().map(cell).collect::<Vec<String>>().join(",");
+                    text += "\n";
+                }
+                text
+            },
+            Output::Table => {
+                let cells = self.rows.iter().map(|row| row.iter().map(cell).collect::<Vec<String>>()).collect::<Vec<_>>();
+
+                let widths = self.columns.iter().enumerate().map(|(i, column)| {
+                    cells.iter().map(|row| row[i].len()).chain(Some(column.len())).max().unwrap_or(0)
+                }).collect::<Vec<usize>>();
+
+                let line = |row: Vec<&str>| {
+                    row.iter().zip(&widths).map(|(cell, &width)| format!("{:>1$}", cell, width)).collect::<Vec<String>>().join("  ") + "\n"
+                };
+
+                let mut text = if header { line(self.columns.to_vec()) } else { String::new() };
+                for row in &cells {
+                    text += &line(row.iter().map(|cell| cell.as_str()).collect());
+                }
+                text
+            },
+        }
+    }
+
+    /// The rows as an Arrow record batch.  The first column holds the timestamps, in `unit`.  Columns of whole
+    /// numbers stay whole, and the rest become floating point, with anything that isn't a number left null.
+    #[cfg(feature = "columnar")]
+    fn record_batch(&self, unit: TimeUnit) -> io::Result<RecordBatch> {
+        let timestamps = self.rows.iter()
+            .map(|row| row[0].as_u64().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Rows don't start with a timestamp")))
+            .collect::<io::Result<Vec<Timestamp>>>()?;
+
+        let columns = self.columns.iter().enumerate().skip(1).map(|(i, &name)| {
+            let whole = self.rows.iter().all(|row| row[i].is_u64() || row[i].is_null());
+
+            (name, if whole {
+                Column::UInt64(self.rows.iter().map(|row| row[i].as_u64()).collect())
+            } else {
+                Column::Float64(self.rows.iter().map(|row| row[i].as_f64()).collect())
+            })
+        }).collect();
+
+        export::record_batch(&timestamps, unit, columns)
+    }
+
+    fn objects(&self) -> Vec<Value> {
+        self.rows.iter().map(|row| {
+            Value::Object(self.columns.iter().map(|column| column.to_string()).zip(row.iter().cloned()).collect::<Map<String, Value>>())
+        }).collect()
+    }
+}
+
+/// A value as printed in a CSV or table.  Missing values are left blank.
+fn cell(value: &Value) -> String {
+    match *value {
+        Value::Null => String::new(),
+        Value::String(ref text) => text.clone(),
+        ref value => value.to_string(),
+    }
+}
+
+/// Runs a subcommand.  Options like `--output`, which defaults to a table, can be given anywhere after the
+/// subcommand.
+pub fn run(mut args: Vec<String>) -> io::Result<()> {
+    let output = match take_option(&mut args, "--output")? {
+        Some(output) => output.parse()?,
+        None => Output::Table,
+    };
+    let interval = take_option(&mut args, "--interval")?;
+    let file = take_option(&mut args, "--file")?;
+    let file = file.as_ref().map(|file| file.as_str());
+    let range = take_option(&mut args, "--range")?;
+    let manifest = take_option(&mut args, "--manifest")?;
+    let directory = take_option(&mut args, "--directory")?;
+    let directory = directory.as_ref().map(|directory| directory.as_str());
+    let older_than = take_option(&mut args, "--older-than")?;
+    let out = take_option(&mut args, "--out")?;
+    let unit = take_option(&mut args, "--unit")?;
+    let value_type = take_option(&mut args, "--type")?;
+    let quarantine = take_option(&mut args, "--quarantine")?;
+
+    match args.first().map(|arg| arg.as_str()) {
+        Some("query") => print!("{}", run_query(file, &args[1..].join(" "))?.format(output)),
+        Some("watch") => {
+            let interval = match interval {
+                Some(interval) => parse_interval(&interval)?,
+                None => 1000,
+            };
+
+            watch(file, &args[1..].join(" "), interval, output)?;
+        },
+        Some("diff") => {
+            if args.len() != 3 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Usage: diff <a> <b> [--range <from>..<to>]"));
+            }
+
+            let range = match range {
+                Some(range) => parse_range(&range)?,
+                None => 0..Timestamp::max_value(),
+            };
+
+            let differences = diff(&args[1], &args[2], range)?;
+            print!("{}", differences.format(output));
+
+            if !differences.rows.is_empty() {
+                process::exit(1);
+            }
+        },
+        Some("fingerprint") => print!("{}", fingerprint(manifest.as_ref().map(|manifest| manifest.as_str()))?.format(output)),
+        Some("verify-fingerprint") => {
+            let manifest = args.get(1).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Usage: verify-fingerprint <manifest> [--directory <directory>]"))?;
+            let (rows, drifted) = verify_fingerprint(manifest, directory)?;
+            print!("{}", rows.format(output));
+
+            if drifted {
+                process::exit(1);
+            }
+        },
+        Some("snapshot") => {
+            let snapshot_directory = args.get(1).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Usage: snapshot <directory>"))?;
+            print!("{}", snapshot(snapshot_directory)?.format(output));
+        },
+        Some("restore") => {
+            let snapshot_directory = args.get(1).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Usage: restore <snapshot> [--directory <tail>]"))?;
+            print!("{}", restore(snapshot_directory, directory)?.format(output));
+        },
+        Some("gc") => {
+            let older_than = match older_than {
+                Some(older_than) => parse_interval(&older_than)?,
+                None => server::GC_AGE,
+            };
+
+            print!("{}", gc(directory, older_than)?.format(output));
+        },
+        Some("compact") => {
+            let file = args.get(1).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Usage: compact <file> [--type <type>] [--unit <unit>] [--quarantine <file>]"))?;
+
+            let unit = match unit {
+                Some(unit) => Some(unit.parse()?),
+                None => None,
+            };
+
+            let value_type = value_type.as_ref().map(|value_type| value_type.as_str());
+            print!("{}", compact(file, value_type, unit, quarantine.as_ref().map(|quarantine| quarantine.as_str()))?.format(output));
+        },
+        Some("export") => {
+            let out = out.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Usage: export --out <file> <query>"))?;
+            print!("{}", export(file, &args[1..].join(" "), &out)?.format(output));
+        },
+        Some("import") => print!("{}", import::import(&import::Options::parse(&args[1..])?)?.format(output)),
+        Some("completions") => print!("{}", completions(args.get(1).map(|arg| arg.as_str()).unwrap_or(""))?),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Unknown subcommand")),
+    }
+
+    Ok(())
+}
+
+/// Removes an option and its value from the arguments
+fn take_option(args: &mut Vec<String>, name: &str) -> io::Result<Option<String>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(position) if position + 1 == args.len() => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Missing value for {}", name))),
+        Some(position) => {
+            let value = args.remove(position + 1);
+            args.remove(position);
+            Ok(Some(value))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Runs a query against a storage file if one is given, or the configured channels otherwise
+fn run_query(file: Option<&str>, text: &str) -> io::Result<Rows> {
+    match file {
+        Some(file) => query_file(file, text),
+        None => query(text),
+    }
+}
+
+/// Runs a query against the configured channels
+pub fn query(text: &str) -> io::Result<Rows> {
+    let text = text.trim_start();
+    let source = text.split_whitespace().next().unwrap_or("");
+
+    let served = find_channel(source)?;
+    let channel = served.channel.read().map_err(|_| io::Error::new(io::ErrorKind::Other, "Channel was poisoned"))?;
+
+    query_channel(time_series(&channel)?, channel.as_pooled_time_series(), source, &text[source.len()..])
+}
+
+/// Runs the clauses of a query against a storage file of timestamps, without loading the configuration
+pub fn query_file(file: &str, clauses: &str) -> io::Result<Rows> {
+    let storage = FileStorage::<Timestamp, Timestamp>::open_read_only(file)?;
+
+    query_channel(&*storage, None, file, clauses)
+}
+
+/// Runs the clauses of a query against a channel, pooling through the channel if it can.  A query of just
+/// `latest` gives the channel's latest record.
+fn query_channel(time_series: &dyn TimeSeries, pooled_time_series: Option<&dyn PooledTimeSeries>, source: &str, clauses: &str) -> io::Result<Rows> {
+    if clauses.trim() == "latest" {
+        return latest(time_series);
+    }
+
+    let mut parsed = parse_clauses(source, clauses, parse::now())?;
+    parsed.query = parsed.query.in_unit(time_series.time_unit());
+
+    if parsed.ohlc {
+        let methods = [PoolingMethod::Start, PoolingMethod::High, PoolingMethod::Low, PoolingMethod::End];
+        let mut columns = Vec::new();
+        for &method in &methods {
+            columns.push(evaluate(time_series, pooled_time_series, &parsed.query.clone().pooling(method))?);
+        }
+
+        let buckets = columns[0].iter().zip(&columns[1]).zip(&columns[2]).zip(&columns[3]);
+        Ok(Rows {
+            columns: &["timestamp", "open", "high", "low", "close"],
+            rows: buckets.map(|(((&(timestamp, open), high), low), close)| {
+                vec![timestamp.into(), open.into(), high.1.into(), low.1.into(), close.1.into()]
+            }).collect(),
+        })
+    } else if let Some(ref bands) = parsed.query.bands {
+        let columns: &'static [&'static str] = match *bands {
+            Bands::Macd { .. } => &["timestamp", "macd", "signal", "histogram"],
+            Bands::Bollinger { .. } => &["timestamp", "lower", "middle", "upper"],
+        };
+
+        let bands = match pooled_time_series {
+            Some(pooled_time_series) => parsed.query.evaluate_pooled_bands::<Timestamp>(pooled_time_series)?,
+            None => parsed.query.evaluate_bands::<Timestamp>(time_series)?,
+        };
+
+        Ok(Rows {
+            columns: columns,
+            rows: bands.into_iter().map(|(timestamp, (a, b, c))| vec![timestamp.into(), a.into(), b.into(), c.into()]).collect(),
+        })
+    } else if !parsed.query.indicators.is_empty() {
+        let values = match pooled_time_series {
+            Some(pooled_time_series) => parsed.query.evaluate_pooled_indicators::<Timestamp>(pooled_time_series)?,
+            None => parsed.query.evaluate_indicators::<Timestamp>(time_series)?,
+        };
+
+        Ok(Rows {
+            columns: &["timestamp", "value"],
+            rows: values.into_iter().map(|(timestamp, value)| vec![timestamp.into(), value.into()]).collect(),
+        })
+    } else {
+        Ok(Rows {
+            columns: &["timestamp", "value"],
+            rows: evaluate(time_series, pooled_time_series, &parsed.query)?.into_iter().map(|(timestamp, value)| vec![timestamp.into(), value.into()]).collect(),
+        })
+    }
+}
+
+/// The latest record of a channel
+fn latest(time_series: &dyn TimeSeries) -> io::Result<Rows> {
+    let mut rows = Vec::new();
+    if time_series.len() > 0 {
+        if let Some(&(timestamp, value)) = time_series.retrieve_nearest(Timestamp::max_value(), None)?.as_single::<Timestamp, Timestamp>() {
+            rows.push(vec![timestamp.into(), value.into()]);
+        }
+    }
+
+    Ok(Rows::new(&["timestamp", "value"], rows))
+}
+
+/// Evaluates an expression every `interval` milliseconds and prints the rows that are new or changed since the
+/// last evaluation, such as the open bucket of a pooled query.  An expression is either a query or a channel, for
+/// its latest record.  With a storage file, it's the clauses of a query, or nothing for the latest record.  Runs
+/// until it's interrupted.  Only a failure of the first evaluation ends it early.
+pub fn watch(file: Option<&str>, expression: &str, interval: Timestamp, output: Output) -> io::Result<()> {
+    let text = match file {
+        Some(_) if expression.trim().is_empty() => "latest".to_string(),
+        None if expression.split_whitespace().count() == 1 => format!("{} latest", expression.trim()),
+        _ => expression.to_string(),
+    };
+
+    let evaluate = || run_query(file, &text);
+
+    let mut rows = Some(evaluate()?);
+    let mut previous: Vec<Vec<Value>> = Vec::new();
+    let mut header = true;
+
+    loop {
+        if let Some(mut rows) = rows.take() {
+            let latest = rows.rows.clone();
+            rows.rows.retain(|row| !previous.contains(row));
+
+            if !rows.rows.is_empty() {
+                print!("{}", rows.format_lines(output, header));
+                header = false;
+            }
+
+            previous = latest;
+        }
+
+        thread::sleep(Duration::from_millis(interval));
+
+        rows = evaluate().map_err(|error| eprintln!("{}", error)).ok();
+    }
+}
+
+fn find_channel(source: &str) -> io::Result<&'static ServedChannel> {
+    let path = source.split('/').collect::<Vec<&str>>();
+    if path.len() != 3 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Channels are given as market/symbol/channel"));
+    }
+
+    server::find_channel(path[0], path[1], path[2]).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such channel"))
+}
+
+/// Aligns the records of two sources in a range, and lists the records missing from the second, the extra records
+/// in the second, and the values that don't match.  The counts are reported on stderr.
+pub fn diff(a: &str, b: &str, range: Range<Timestamp>) -> io::Result<Rows> {
+    let (a_records, b_records) = (read_source(a, range.clone())?, read_source(b, range)?);
+    let differences = diff_records(&a_records, &b_records);
+
+    let (mut missing, mut extra, mut mismatched) = (0, 0, 0);
+    for difference in &differences {
+        match *difference {
+            Difference::Missing(..) => missing += 1,
+            Difference::Extra(..) => extra += 1,
+            Difference::Mismatch(..) => mismatched += 1,
+        }
+    }
+
+    eprintln!(
+        "{} records in {}, {} in {}: {} missing, {} extra, {} mismatched",
+        a_records.len(), a, b_records.len(), b, missing, extra, mismatched,
+    );
+
+    Ok(Rows::new(&["timestamp", "difference", "a", "b"], differences.into_iter().map(|difference| match difference {
+        Difference::Missing(timestamp, value) => vec![timestamp.into(), "missing".into(), value.into(), Value::Null],
+        Difference::Extra(timestamp, value) => vec![timestamp.into(), "extra".into(), Value::Null, value.into()],
+        Difference::Mismatch(timestamp, a, b) => vec![timestamp.into(), "mismatch".into(), a.into(), b.into()],
+    }).collect()))
+}
+
+/// Reads the records of a source in a range.  A source is a storage file if one exists at that path, such as a
+/// backup, or a configured channel otherwise.
+fn read_source(source: &str, range: Range<Timestamp>) -> io::Result<Vec<(Timestamp, Timestamp)>> {
+    let retrieve = |time_series: &dyn TimeSeries| {
+        time_series.retrieve_range(range)?.as_vec::<Timestamp, Timestamp>().cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Source doesn't hold timestamps"))
+    };
+
+    if fs::metadata(source).is_ok() {
+        retrieve(&*FileStorage::<Timestamp, Timestamp>::open_read_only(source)?)
+    } else {
+        let served = find_channel(source)?;
+        let channel = served.channel.read().map_err(|_| io::Error::new(io::ErrorKind::Other, "Channel was poisoned"))?;
+        retrieve(time_series(&channel)?)
+    }
+}
+
+/// Parses a range of times, "<from>..<to>", where either end can be left out
+fn parse_range(text: &str) -> io::Result<Range<Timestamp>> {
+    let now = parse::now();
+    let mut ends = text.splitn(2, "..");
+
+    let (start, end) = match (ends.next(), ends.next()) {
+        (Some(start), Some(end)) => (start.trim(), end.trim()),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Ranges are given as <from>..<to>")),
+    };
+
+    Ok(Range {
+        start: if start.is_empty() { 0 } else { parse_timestamp(start, now)? },
+        end: if end.is_empty() { Timestamp::max_value() } else { parse_timestamp(end, now)? },
+    })
+}
+
+/// Fingerprints the storage file of every configured channel, and writes a manifest of them if a path is given.  The
+/// last row is for the whole data directory, with the fingerprint of the manifest.
+pub fn fingerprint(manifest_path: Option<&str>) -> io::Result<Rows> {
+    let config = server::read_config()?;
+
+    let mut manifest = Manifest::default();
+    let mut rows = Vec::new();
+    for (channel, file) in server::channel_files(&config) {
+        let fingerprint = Fingerprint::of_file(&file, None)?;
+        rows.push(vec![channel.into(), file.clone().into(), fingerprint.bytes.into(), fingerprint.hex().into()]);
+        manifest.entries.push((file, fingerprint));
+    }
+
+    let bytes = manifest.entries.iter().map(|&(_, ref fingerprint)| fingerprint.bytes).sum::<u64>();
+    rows.push(vec!["*".into(), manifest_path.map_or(Value::Null, Value::from), bytes.into(), manifest.fingerprint().hex().into()]);
+
+    if let Some(manifest_path) = manifest_path {
+        fs::write(manifest_path, manifest.to_string())?;
+    }
+
+    Ok(Rows::new(&["channel", "file", "bytes", "sha256"], rows))
+}
+
+/// Copies the storage file of every configured channel into a new snapshot directory
+pub fn snapshot(directory: &str) -> io::Result<Rows> {
+    let config = server::read_config()?;
+    let files = server::channel_files(&config).into_iter().map(|(_, file)| file).collect::<Vec<String>>();
+
+    let snapshot = Snapshot::take(directory, &files.iter().map(|file| file.as_str()).collect::<Vec<&str>>())?;
+
+    let rows = snapshot.manifest().entries.iter()
+        .map(|&(ref file, ref fingerprint)| vec![file.as_str().into(), fingerprint.bytes.into(), fingerprint.hex().into()])
+        .collect();
+
+    eprintln!("Snapshot {}: {}", directory, snapshot.manifest().fingerprint().hex());
+
+    Ok(Rows::new(&["file", "bytes", "sha256"], rows))
+}
+
+/// Restores every configured channel whose storage file is missing from a snapshot, then replays the records
+/// written since from the copies of the files under `tail`, e.g. a replica or a journal, if one is given.
+/// Channels whose files are still there are left alone.
+pub fn restore(snapshot: &str, tail: Option<&str>) -> io::Result<Rows> {
+    let config = server::read_config()?;
+    let snapshot = Snapshot::open(snapshot)?;
+
+    let mut rows = Vec::new();
+    for (file, unit) in server::channel_units(&config)? {
+        if Path::new(&file).exists() {
+            rows.push(vec![file.into(), "present".into(), Value::Null, Value::Null, Value::Null]);
+            continue;
+        }
+
+        let tail_storage = match tail.map(|tail| Path::new(tail).join(&file)) {
+            Some(ref path) if path.exists() => Some(FileStorage::<Timestamp, Timestamp>::read_only_with_unit(&path.to_string_lossy(), unit)?),
+            _ => None,
+        };
+
+        let restored = snapshot.restore::<Timestamp>(&file, &file, unit, tail_storage.as_ref().map(|tail| tail as &dyn TimeSeries))?;
+        rows.push(vec![file.into(), "restored".into(), restored.snapshot_records.into(), restored.replayed.into(), restored.skipped.into()]);
+    }
+
+    Ok(Rows::new(&["file", "status", "snapshot_records", "replayed", "skipped"], rows))
+}
+
+/// Checks every file in a manifest, relative to `directory` if one is given, and lists how each compares.  Files
+/// that have only been appended to since are consistent.  Also returns whether any file has drifted.
+pub fn verify_fingerprint(manifest_path: &str, directory: Option<&str>) -> io::Result<(Rows, bool)> {
+    let manifest = Manifest::parse(&fs::read_to_string(manifest_path)?)?;
+
+    let mut rows = Vec::new();
+    let mut drifted = 0;
+    for &(ref file, ref fingerprint) in &manifest.entries {
+        let path = directory.map_or_else(|| PathBuf::from(file), |directory| Path::new(directory).join(file));
+
+        let (status, bytes) = match fingerprint::verify(&path, fingerprint)? {
+            Ok(0) => ("ok", Value::Null),
+            Ok(grown) => ("grown", grown.into()),
+            Err(drift) => {
+                drifted += 1;
+                match drift {
+                    Drift::Missing => ("missing", Value::Null),
+                    Drift::Truncated(length) => ("truncated", length.into()),
+                    Drift::Changed => ("changed", Value::Null),
+                }
+            },
+        };
+
+        rows.push(vec![file.as_str().into(), status.into(), bytes]);
+    }
+
+    eprintln!("Manifest {}: {} files, {} drifted", manifest.fingerprint().hex(), manifest.entries.len(), drifted);
+
+    Ok((Rows::new(&["file", "status", "bytes"], rows), drifted > 0))
+}
+
+/// Removes the leftovers of interrupted archives older than `older_than` from `directory`, or from every directory
+/// holding a configured channel's storage file, and lists them
+pub fn gc(directory: Option<&str>, older_than: Timestamp) -> io::Result<Rows> {
+    let rows = server::collect_garbage(directory, older_than)?.into_iter()
+        .map(|path| vec![path.to_string_lossy().into_owned().into()])
+        .collect();
+
+    Ok(Rows::new(&["removed"], rows))
+}
+
+/// Compacts a storage file of `value_type` values with timestamps in `unit` and lists what was kept and dropped.
+/// Either is taken from the file's channel info if it isn't given, and the file must have one if the value type isn't.
+pub fn compact(file: &str, value_type: Option<&str>, unit: Option<TimeUnit>, quarantine: Option<&str>) -> io::Result<Rows> {
+    let info = ChannelInfo::load(file)?;
+    let value_type = value_type.or_else(|| info.as_ref().map(|info| info.value_type.as_str()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The file has no channel info, so its value type must be given with --type"))?;
+    let unit = unit.or_else(|| info.as_ref().map(|info| info.unit)).unwrap_or(TimeUnit::Milliseconds);
+
+    let quarantine = match quarantine {
+        Some(quarantine) => Some(Quarantine::open(quarantine)?),
+        None => None,
+    };
+    let quarantine = quarantine.as_ref();
+
+    let compaction = match value_type {
+        "u64" => FileStorage::<Timestamp, Timestamp>::compact(file, unit, quarantine)?,
+        "quote" => FileStorage::<Timestamp, Quote<Timestamp>>::compact(file, unit, quarantine)?,
+        "sourced" => FileStorage::<Timestamp, Sourced<Timestamp>>::compact(file, unit, quarantine)?,
+        "trade" => FileStorage::<Timestamp, Identified<Timestamp>>::compact(file, unit, quarantine)?,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Files of \"{}\" values can't be compacted", value_type))),
+    };
+
+    Ok(Rows::new(&["file", "kept", "duplicates", "out_of_order", "malformed"], vec![vec![
+        file.into(),
+        compaction.kept.into(),
+        compaction.duplicates.into(),
+        compaction.out_of_order.into(),
+        compaction.malformed.into(),
+    ]]))
+}
+
+/// Writes the result of a query to a Parquet file, or to an Arrow IPC stream if `out` ends in ".arrow", and lists
+/// the file with its number of rows
+#[cfg(feature = "columnar")]
+pub fn export(file: Option<&str>, text: &str, out: &str) -> io::Result<Rows> {
+    let rows = run_query(file, text)?;
+    let batch = rows.record_batch(query_unit(file, text)?)?;
+
+    if out.ends_with(".arrow") {
+        export::write_stream(fs::File::create(out)?, &[batch])?;
+    } else {
+        export::write_parquet(fs::File::create(out)?, &[batch])?;
+    }
+
+    Ok(Rows::new(&["file", "rows"], vec![vec![out.into(), rows.rows.len().into()]]))
+}
+
+#[cfg(not(feature = "columnar"))]
+pub fn export(_file: Option<&str>, _text: &str, _out: &str) -> io::Result<Rows> {
+    Err(io::Error::new(io::ErrorKind::Other, "Exporting needs a build with the columnar feature"))
+}
+
+/// The unit of the timestamps a query gives.  Storage files given with `--file` are in milliseconds.
+#[cfg(feature = "columnar")]
+fn query_unit(file: Option<&str>, text: &str) -> io::Result<TimeUnit> {
+    if file.is_some() {
+        return Ok(TimeUnit::Milliseconds);
+    }
+
+    let served = find_channel(text.trim_start().split_whitespace().next().unwrap_or(""))?;
+    let channel = served.channel.read().map_err(|_| io::Error::new(io::ErrorKind::Other, "Channel was poisoned"))?;
+
+    Ok(channel.as_time_series().map_or(TimeUnit::Milliseconds, |time_series| time_series.time_unit()))
+}
+
+fn evaluate(time_series: &dyn TimeSeries, pooled_time_series: Option<&dyn PooledTimeSeries>, query: &Query) -> io::Result<Vec<(Timestamp, Timestamp)>> {
+    match pooled_time_series {
+        Some(pooled_time_series) => query.evaluate_pooled::<Timestamp>(pooled_time_series),
+        None => query.evaluate::<Timestamp>(time_series),
+    }
+}
+
+fn time_series(channel: &Channel) -> io::Result<&dyn TimeSeries> {
+    channel.as_time_series().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Channel is not a time series"))
+}
+
+/// A completion script for a shell, covering the subcommands, their options, and the query clauses
+pub fn completions(shell: &str) -> io::Result<String> {
+    let subcommands = SUBCOMMANDS.join(" ");
+    let outputs = OUTPUTS.join(" ");
+    let shells = SHELLS.join(" ");
+    let clauses = CLAUSES.join(" ");
+    let methods = POOLING_METHODS.join(" ");
+    let exchanges = EXCHANGES.join(" ");
+    let value_types = VALUE_TYPES.join(" ");
+
+    match shell {
+        "bash" => Ok(format!(r#"_trade_data() {{
+    local current="${{COMP_WORDS[COMP_CWORD]}}"
+    local previous="${{COMP_WORDS[COMP_CWORD-1]}}"
+    local words
+
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        words="{subcommands}"
+    elif [ "${{COMP_WORDS[1]}}" = completions ]; then
+        words="{shells}"
+    elif [ "$previous" = --output ]; then
+        words="{outputs}"
+    elif [ "$previous" = --file ] || [ "$previous" = --manifest ] || [ "$previous" = --out ] || [ "$previous" = --quarantine ]; then
+        COMPREPLY=($(compgen -f -- "$current"))
+        return
+    elif [ "$previous" = --directory ]; then
+        COMPREPLY=($(compgen -d -- "$current"))
+        return
+    elif [ "$previous" = --exchange ]; then
+        words="{exchanges}"
+    elif [ "$previous" = --format ]; then
+        words="csv json"
+    elif [ "$previous" = --field ]; then
+        words="price amount"
+    elif [ "${{COMP_WORDS[1]}}" = fingerprint ]; then
+        words="--output --manifest"
+    elif [ "${{COMP_WORDS[1]}}" = snapshot ]; then
+        COMPREPLY=($(compgen -d -- "$current") $(compgen -W "--output" -- "$current"))
+        return
+    elif [ "${{COMP_WORDS[1]}}" = restore ]; then
+        COMPREPLY=($(compgen -d -- "$current") $(compgen -W "--output --directory" -- "$current"))
+        return
+    elif [ "$previous" = --unit ]; then
+        words="ms us ns"
+    elif [ "$previous" = --type ]; then
+        words="{value_types}"
+    elif [ "${{COMP_WORDS[1]}}" = gc ]; then
+        words="--output --directory --older-than"
+    elif [ "${{COMP_WORDS[1]}}" = compact ]; then
+        COMPREPLY=($(compgen -f -- "$current") $(compgen -W "--output --type --unit --quarantine" -- "$current"))
+        return
+    elif [ "${{COMP_WORDS[1]}}" = verify-fingerprint ]; then
+        COMPREPLY=($(compgen -f -- "$current") $(compgen -W "--output --directory" -- "$current"))
+        return
+    elif [ "${{COMP_WORDS[1]}}" = diff ]; then
+        COMPREPLY=($(compgen -f -- "$current") $(compgen -W "--output --range" -- "$current"))
+        return
+    elif [ "${{COMP_WORDS[1]}}" = import ]; then
+        COMPREPLY=($(compgen -f -- "$current") $(compgen -W "--output --exchange --format --field --digits" -- "$current"))
+        return
+    elif [ "$previous" = fill ]; then
+        words="default previous"
+    elif [ "$previous" = anchor ]; then
+        words="first start"
+    elif [ "$COMP_CWORD" -gt 2 ] && [ "${{COMP_WORDS[COMP_CWORD-2]}}" = pool ]; then
+        words="{methods} {clauses}"
+    else
+        words="--output --file {clauses}"
+        [ "${{COMP_WORDS[1]}}" = watch ] && words="--interval $words"
+        [ "${{COMP_WORDS[1]}}" = export ] && words="--out $words"
+    fi
+
+    COMPREPLY=($(compgen -W "$words" -- "$current"))
+}}
+
+complete -F _trade_data trade-data
+"#, subcommands = subcommands, shells = shells, outputs = outputs, exchanges = exchanges, methods = methods, clauses = clauses, value_types = value_types)),
+        "zsh" => Ok(format!(r#"#compdef trade-data
+
+_trade_data() {{
+    if (( CURRENT == 2 )); then
+        compadd {subcommands}
+    elif [[ $words[2] == completions ]]; then
+        compadd {shells}
+    elif [[ $words[CURRENT-1] == --output ]]; then
+        compadd {outputs}
+    elif [[ $words[CURRENT-1] == --file || $words[CURRENT-1] == --manifest || $words[CURRENT-1] == --out || $words[CURRENT-1] == --quarantine ]]; then
+        _files
+    elif [[ $words[CURRENT-1] == --directory ]]; then
+        _files -/
+    elif [[ $words[CURRENT-1] == --exchange ]]; then
+        compadd {exchanges}
+    elif [[ $words[CURRENT-1] == --format ]]; then
+        compadd csv json
+    elif [[ $words[CURRENT-1] == --field ]]; then
+        compadd price amount
+    elif [[ $words[2] == fingerprint ]]; then
+        compadd -- --output --manifest
+    elif [[ $words[2] == snapshot ]]; then
+        _files -/
+        compadd -- --output
+    elif [[ $words[2] == restore ]]; then
+        _files -/
+        compadd -- --output --directory
+    elif [[ $words[CURRENT-1] == --unit ]]; then
+        compadd ms us ns
+    elif [[ $words[CURRENT-1] == --type ]]; then
+        compadd {value_types}
+    elif [[ $words[2] == gc ]]; then
+        compadd -- --output --directory --older-than
+    elif [[ $words[2] == compact ]]; then
+        _files
+        compadd -- --output --type --unit --quarantine
+    elif [[ $words[2] == verify-fingerprint ]]; then
+        _files
+        compadd -- --output --directory
+    elif [[ $words[2] == diff ]]; then
+        _files
+        compadd -- --output --range
+    elif [[ $words[2] == import ]]; then
+        _files
+        compadd -- --output --exchange --format --field --digits
+    elif [[ $words[CURRENT-1] == fill ]]; then
+        compadd default previous
+    elif [[ $words[CURRENT-1] == anchor ]]; then
+        compadd first start
+    elif (( CURRENT > 3 )) && [[ $words[CURRENT-2] == pool ]]; then
+        compadd {methods} {clauses}
+    elif [[ $words[2] == watch ]]; then
+        compadd -- --output --file --interval {clauses}
+    elif [[ $words[2] == export ]]; then
+        compadd -- --output --file --out {clauses}
+    else
+        compadd -- --output --file {clauses}
+    fi
+}}
+
+compdef _trade_data trade-data
+"#, subcommands = subcommands, shells = shells, outputs = outputs, exchanges = exchanges, methods = methods, clauses = clauses, value_types = value_types)),
+        "fish" => Ok(format!(r#"complete -c trade-data -f
+complete -c trade-data -n __fish_use_subcommand -a '{subcommands}'
+complete -c trade-data -n '__fish_seen_subcommand_from completions' -a '{shells}'
+complete -c trade-data -n '__fish_seen_subcommand_from query watch diff import fingerprint verify-fingerprint snapshot restore gc compact export' -l output -x -a '{outputs}'
+complete -c trade-data -n '__fish_seen_subcommand_from query watch export' -a '{clauses}'
+complete -c trade-data -n '__fish_seen_subcommand_from query watch export' -l file -r -F
+complete -c trade-data -n '__fish_seen_subcommand_from export' -l out -r -F
+complete -c trade-data -n '__fish_seen_subcommand_from watch' -l interval -x
+complete -c trade-data -n '__fish_seen_subcommand_from diff' -F
+complete -c trade-data -n '__fish_seen_subcommand_from diff' -l range -x
+complete -c trade-data -n '__fish_seen_subcommand_from import' -F
+complete -c trade-data -n '__fish_seen_subcommand_from fingerprint' -l manifest -r -F
+complete -c trade-data -n '__fish_seen_subcommand_from verify-fingerprint' -F
+complete -c trade-data -n '__fish_seen_subcommand_from snapshot restore' -x -a '(__fish_complete_directories)'
+complete -c trade-data -n '__fish_seen_subcommand_from verify-fingerprint restore gc' -l directory -x -a '(__fish_complete_directories)'
+complete -c trade-data -n '__fish_seen_subcommand_from gc' -l older-than -x
+complete -c trade-data -n '__fish_seen_subcommand_from compact' -F
+complete -c trade-data -n '__fish_seen_subcommand_from compact' -l type -x -a '{value_types}'
+complete -c trade-data -n '__fish_seen_subcommand_from compact' -l unit -x -a 'ms us ns'
+complete -c trade-data -n '__fish_seen_subcommand_from compact' -l quarantine -r -F
+complete -c trade-data -n '__fish_seen_subcommand_from import' -l exchange -x -a '{exchanges}'
+complete -c trade-data -n '__fish_seen_subcommand_from import' -l format -x -a 'csv json'
+complete -c trade-data -n '__fish_seen_subcommand_from import' -l field -x -a 'price amount'
+complete -c trade-data -n '__fish_seen_subcommand_from import' -l digits -x
+"#, subcommands = subcommands, shells = shells, outputs = outputs, exchanges = exchanges, clauses = clauses, value_types = value_types)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Shell must be one of bash, zsh, or fish")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use trade_data::KeyValueStore;
+
+    #[test]
+    fn test_format() {
+        let rows = Rows {
+            columns: &["timestamp", "value"],
+            rows: vec![vec![Value::from(5i64), Value::from(100i64)], vec![Value::from(10i64), Value::Null]],
+        };
+
+        assert_eq!(rows.format(Output::Json), "[{\"timestamp\":5,\"value\":100},{\"timestamp\":10,\"value\":null}]\n");
+        assert_eq!(rows.format(Output::Csv), "timestamp,value\n5,100\n10,\n");
+        assert_eq!(rows.format(Output::Table), "timestamp  value\n        5    100\n       10       \n");
+
+        assert_eq!(rows.format_lines(Output::Json, true), "{\"timestamp\":5,\"value\":100}\n{\"timestamp\":10,\"value\":null}\n");
+        assert_eq!(rows.format_lines(Output::Csv, false), "5,100\n10,\n");
+        assert_eq!(rows.format_lines(Output::Table, false), "        5    100\n       10       \n");
+
+        assert!("xml".parse::<Output>().is_err());
+    }
+
+    #[test]
+    fn test_completions() {
+        // Every shell completes the output formats, though fish names the option without its dashes
+        for shell in SHELLS {
+            assert!(completions(shell).unwrap().contains(&OUTPUTS.join(" ")));
+        }
+        assert!(completions("powershell").is_err());
+    }
+
+    #[test]
+    fn test_compact() {
+        let file = "test_cli_compact";
+        fs::remove_file(file).ok();
+        fs::remove_file(ChannelInfo::path(file)).ok();
+
+        {
+            let mut storage = FileStorage::<Timestamp, Quote<Timestamp>>::new(file).unwrap();
+            storage.store(Box::new(1 as Timestamp), Box::new(Quote::new(10 as Timestamp, 11))).unwrap();
+            storage.store(Box::new(2 as Timestamp), Box::new(Quote::new(12 as Timestamp, 13))).unwrap();
+        }
+
+        // Without channel info, the value type has to be given
+        assert!(compact(file, None, None, None).is_err());
+
+        ChannelInfo { value_type: "quote".to_string(), ..ChannelInfo::of::<Quote<Timestamp>>(TimeUnit::Milliseconds) }.save(file).unwrap();
+        assert_eq!(compact(file, None, None, None).unwrap().rows[0][1], Value::from(2usize));
+
+        // Compacting as another type is refused rather than emptying the file
+        assert!(compact(file, Some("u64"), None, None).is_err());
+        assert_eq!(FileStorage::<Timestamp, Quote<Timestamp>>::new(file).unwrap().len(), 2);
+
+        fs::remove_file(file).ok();
+        fs::remove_file(ChannelInfo::path(file)).ok();
+    }
+}
@@ -0,0 +1,484 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bulk loading of exchange history dumps into a channel.
+//!
+//! Gemini, Binance, and Kraken dumps are read as CSV or JSON, normalized to one value per trade, and stored in time
+//! order.  Trades already in the channel are skipped, so an interrupted import can be run again.  The channel
+//! shouldn't be capturing while it's imported into.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
+
+use serde_json::{self, Value};
+
+use trade_data::Timestamp;
+use trade_data::server;
+
+use cli::Rows;
+
+/// How many trades are read between progress reports
+const PROGRESS_INTERVAL: usize = 100_000;
+
+/// Timestamps at least this large are in microseconds.  Binance's dumps switched from milliseconds to
+/// microseconds in 2025, and no timestamp in milliseconds reaches this until the year 33658.
+const MICROSECOND_TIMESTAMPS: Timestamp = 1_000_000_000_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exchange {
+    Gemini,
+    Binance,
+    Kraken,
+}
+
+impl FromStr for Exchange {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        match text {
+            "gemini" => Ok(Exchange::Gemini),
+            "binance" => Ok(Exchange::Binance),
+            "kraken" => Ok(Exchange::Kraken),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Exchange must be one of gemini, binance, or kraken")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DumpFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for DumpFormat {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        match text {
+            "csv" => Ok(DumpFormat::Csv),
+            "json" => Ok(DumpFormat::Json),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Format must be csv or json")),
+        }
+    }
+}
+
+/// Which part of each trade is stored as the channel's value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    /// Stored in cents by default
+    Price,
+    /// Stored in hundred-millionths by default
+    Amount,
+}
+
+impl Field {
+    fn default_digits(self) -> usize {
+        match self {
+            Field::Price => 2,
+            Field::Amount => 8,
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        match text {
+            "price" => Ok(Field::Price),
+            "amount" => Ok(Field::Amount),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Field must be price or amount")),
+        }
+    }
+}
+
+pub struct Options {
+    /// The channel to import into, as market/symbol/channel
+    source: String,
+    file: String,
+    exchange: Exchange,
+    format: DumpFormat,
+    field: Field,
+    /// The number of decimal places kept in the stored value
+    digits: usize,
+}
+
+impl Options {
+    /// Parses `<market/symbol/channel> <file> --exchange <exchange> [--format csv|json] [--field price|amount]
+    /// [--digits N]`.  The format defaults to JSON for ".json" files and CSV otherwise.
+    pub fn parse(args: &[String]) -> io::Result<Self> {
+        let mut positional = Vec::new();
+        let mut exchange = None;
+        let mut format = None;
+        let mut field = Field::Price;
+        let mut digits = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg.clone());
+                continue;
+            }
+
+            let value = args.next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Missing value for {}", arg)))?;
+
+            match arg.as_str() {
+                "--exchange" => exchange = Some(value.parse::<Exchange>()?),
+                "--format" => format = Some(value.parse::<DumpFormat>()?),
+                "--field" => field = value.parse::<Field>()?,
+                "--digits" => digits = Some(value.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid value for --digits"))?),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown argument {}", arg))),
+            }
+        }
+
+        if positional.len() != 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Usage: import <market/symbol/channel> <file> --exchange <exchange>"));
+        }
+
+        let file = positional.pop().unwrap();
+        let source = positional.pop().unwrap();
+
+        Ok(Options {
+            format: format.unwrap_or(if file.ends_with(".json") { DumpFormat::Json } else { DumpFormat::Csv }),
+            source: source,
+            file: file,
+            exchange: exchange.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Missing --exchange"))?,
+            field: field,
+            digits: digits.unwrap_or(field.default_digits()),
+        })
+    }
+}
+
+/// A trade read from a dump, with its price and amount still in the dump's decimal text
+#[derive(Debug, PartialEq)]
+struct Trade {
+    timestamp: Timestamp,
+    price: String,
+    amount: String,
+}
+
+/// Imports a dump.  Trades must be in time order, apart from JSON dumps that are entirely newest first, as
+/// exchange APIs often return them.  Only the last trade of each millisecond is kept, since a channel holds one
+/// record per timestamp.  Progress is reported on stderr, and the totals are returned.
+pub fn import(options: &Options) -> io::Result<Rows> {
+    let path = options.source.split('/').collect::<Vec<&str>>();
+    if path.len() != 3 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Channels are given as market/symbol/channel"));
+    }
+
+    let served = server::find_channel(path[0], path[1], path[2])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such channel"))?;
+    let mut channel = served.channel.write().map_err(|_| io::Error::new(io::ErrorKind::Other, "Channel was poisoned"))?;
+    let time_series = channel.as_mut_time_series()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Channel is not a time series"))?;
+
+    let last_key = if time_series.len() > 0 {
+        time_series.retrieve_nearest(Timestamp::max_value(), None)?.as_single::<Timestamp, Timestamp>().map(|&(key, _)| key)
+    } else {
+        None
+    };
+
+    let trades: Box<dyn Iterator<Item = io::Result<Trade>>> = match options.format {
+        DumpFormat::Csv => Box::new(csv_trades(options.exchange, File::open(&options.file)?)?),
+        DumpFormat::Json => Box::new(json_trades(options.exchange, &fs::read_to_string(&options.file)?)?.into_iter().map(Ok::<Trade, io::Error>)),
+    };
+
+    let (mut read, mut stored, mut skipped, mut merged) = (0usize, 0usize, 0usize, 0usize);
+    let mut pending: Option<(Timestamp, Timestamp)> = None;
+
+    {
+        let mut store = |(key, value): (Timestamp, Timestamp)| -> io::Result<()> {
+            if last_key.map_or(false, |last_key| key <= last_key) {
+                skipped += 1;
+                return Ok(());
+            }
+
+            time_series.as_mut_key_value_store().store(Box::new(key), Box::new(value))?;
+            stored += 1;
+            Ok(())
+        };
+
+        for trade in trades {
+            let trade = trade?;
+            read += 1;
+
+            let value = fixed_point(if options.field == Field::Price { &trade.price } else { &trade.amount }, options.digits)?;
+
+            if let Some(record) = pending {
+                if trade.timestamp < record.0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                        "Trade {} at {} is before the trade at {}.  Dumps must be in time order.", read, trade.timestamp, record.0,
+                    )));
+                }
+
+                if trade.timestamp == record.0 {
+                    merged += 1;
+                } else {
+                    store(record)?;
+                }
+            }
+            pending = Some((trade.timestamp, value));
+
+            if read % PROGRESS_INTERVAL == 0 {
+                eprintln!("Read {} trades, through {}", read, trade.timestamp);
+            }
+        }
+
+        if let Some(record) = pending {
+            store(record)?;
+        }
+    }
+
+    Ok(Rows::new(&["read", "stored", "skipped", "merged", "total"], vec![
+        vec![read.into(), stored.into(), skipped.into(), merged.into(), time_series.len().into()],
+    ]))
+}
+
+/// Reads the trades of a CSV dump as they're needed.  Gemini dumps need a header naming the "timestampms",
+/// "price", and "amount" columns.  Binance dumps are trade lists (id, price, quantity, quote quantity, time, ...)
+/// and Kraken dumps are time and sales (time in seconds, price, volume), either with or without a header.
+fn csv_trades(exchange: Exchange, file: File) -> io::Result<impl Iterator<Item = io::Result<Trade>>> {
+    let mut lines = BufReader::new(file).lines().enumerate().peekable();
+
+    let header = match lines.peek() {
+        Some(&(_, Ok(ref line))) => line.clone(),
+        _ => String::new(),
+    };
+    let header = csv_fields(&header);
+
+    // The timestamp, price, and amount columns
+    let layout = match exchange {
+        Exchange::Gemini => {
+            let column = |name: &str| header.iter().position(|field| *field == name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Gemini dumps need a \"{}\" column", name)));
+
+            [column("timestampms")?, column("price")?, column("amount")?]
+        },
+        Exchange::Binance => [4, 1, 2],
+        Exchange::Kraken => [0, 1, 2],
+    };
+
+    // A header is any first line that doesn't start with a number
+    if header.first().map_or(false, |field| field.parse::<f64>().is_err()) {
+        lines.next();
+    }
+
+    Ok(lines.filter(|&(_, ref line)| line.as_ref().map(|line| !line.trim().is_empty()).unwrap_or(true)).map(move |(index, line)| -> io::Result<Trade> {
+        let line = line?;
+        let fields = csv_fields(&line);
+        let field = |column: usize| fields.get(column).map(|field| field.to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Line {} is missing fields", index + 1)));
+
+        Ok(Trade {
+            timestamp: timestamp(exchange, &field(layout[0])?).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {}", index + 1, error)))?,
+            price: field(layout[1])?,
+            amount: field(layout[2])?,
+        })
+    }))
+}
+
+fn csv_fields(line: &str) -> Vec<&str> {
+    line.split(',').map(|field| field.trim().trim_matches('"')).collect()
+}
+
+/// Reads the trades of a JSON dump: an array of trade objects from Gemini's or Binance's trade history APIs, or
+/// the response of Kraken's.  A dump that's entirely newest first is put in time order.
+fn json_trades(exchange: Exchange, text: &str) -> io::Result<Vec<Trade>> {
+    let dump = serde_json::from_str::<Value>(text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+
+    let rows = match exchange {
+        Exchange::Kraken => dump.get("result")
+            .and_then(|result| result.as_object())
+            .and_then(|result| result.iter().find(|&(pair, _)| pair != "last"))
+            .and_then(|(_, trades)| trades.as_array()),
+        Exchange::Gemini | Exchange::Binance => dump.as_array(),
+    }.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Dump doesn't contain a list of trades"))?;
+
+    let mut trades = Vec::with_capacity(rows.len());
+    for (index, row) in rows.iter().enumerate() {
+        let fields = match exchange {
+            Exchange::Gemini => (row.get("timestampms"), row.get("price"), row.get("amount")),
+            Exchange::Binance => (
+                row.get("time").or_else(|| row.get("T")),
+                row.get("price").or_else(|| row.get("p")),
+                row.get("qty").or_else(|| row.get("q")),
+            ),
+            Exchange::Kraken => (row.get(2), row.get(0), row.get(1)),
+        };
+
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, format!("Trade {} is malformed", index + 1));
+
+        if let (Some(time), Some(price), Some(amount)) = (fields.0.and_then(json_text), fields.1.and_then(json_text), fields.2.and_then(json_text)) {
+            trades.push(Trade {
+                timestamp: timestamp(exchange, &time).map_err(|_| malformed())?,
+                price: price,
+                amount: amount,
+            });
+        } else {
+            return Err(malformed());
+        }
+    }
+
+    if trades.first().map(|trade| trade.timestamp) > trades.last().map(|trade| trade.timestamp) {
+        trades.reverse();
+    }
+
+    Ok(trades)
+}
+
+/// A number or string from a JSON dump, as text
+fn json_text(value: &Value) -> Option<String> {
+    match *value {
+        Value::Number(ref number) => Some(number.to_string()),
+        Value::String(ref text) => Some(text.clone()),
+        _ => None,
+    }
+}
+
+/// Converts an exchange's trade time to milliseconds since the epoch
+fn timestamp(exchange: Exchange, text: &str) -> io::Result<Timestamp> {
+    match exchange {
+        Exchange::Kraken => fixed_point(text, 3),
+        Exchange::Gemini => fixed_point(text, 0),
+        Exchange::Binance => fixed_point(text, 0).map(|time| if time >= MICROSECOND_TIMESTAMPS { time / 1000 } else { time }),
+    }
+}
+
+/// Parses a non-negative decimal number into an integer with `digits` decimal places.  Further decimal places are
+/// truncated.
+fn fixed_point(text: &str, digits: usize) -> io::Result<Timestamp> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid number \"{}\"", text));
+
+    let mut parts = text.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+
+    if whole.is_empty() || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    let fraction = fraction.chars().chain("0".repeat(digits).chars()).take(digits).collect::<String>();
+
+    (whole.to_string() + &fraction).parse::<Timestamp>().map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    /// Removes a test's file when the test is done with it, whether or not it passed
+    struct SetupFile {
+        filename: &'static str,
+    }
+
+    impl SetupFile {
+        fn new(filename: &'static str) -> Self {
+            fs::remove_file(filename).ok();
+            Self {
+                filename: filename,
+            }
+        }
+    }
+
+    impl Drop for SetupFile {
+        fn drop(&mut self) {
+            fs::remove_file(self.filename).ok();
+        }
+    }
+
+    #[test]
+    fn test_fixed_point() {
+        assert_eq!(fixed_point("3456.78", 2).unwrap(), 345678);
+        assert_eq!(fixed_point("3456.7", 2).unwrap(), 345670);
+        assert_eq!(fixed_point("3456", 2).unwrap(), 345600);
+        assert_eq!(fixed_point("0.123456789", 8).unwrap(), 12345678);
+        assert_eq!(fixed_point("1546300800.1234", 3).unwrap(), 1546300800123);
+        assert!(fixed_point("-1.5", 2).is_err());
+        assert!(fixed_point("1e-5", 8).is_err());
+        assert!(fixed_point("", 2).is_err());
+    }
+
+    #[test]
+    fn test_csv_trades() {
+        let _setup_file = SetupFile::new("test_csv_trades");
+
+        let read = |exchange: Exchange, contents: &str| {
+            File::create("test_csv_trades").unwrap().write_all(contents.as_bytes()).unwrap();
+            csv_trades(exchange, File::open("test_csv_trades").unwrap()).unwrap().collect::<io::Result<Vec<Trade>>>()
+        };
+
+        let trade = |timestamp: Timestamp, price: &str, amount: &str| Trade { timestamp: timestamp, price: price.to_string(), amount: amount.to_string() };
+
+        assert_eq!(
+            read(Exchange::Gemini, "tid,timestampms,price,amount\n1,1000,3500.01,0.5\n2,1001,3500.02,0.25\n").unwrap(),
+            vec![trade(1000, "3500.01", "0.5"), trade(1001, "3500.02", "0.25")],
+        );
+
+        assert_eq!(
+            read(Exchange::Binance, "1,3500.01,0.5,1750.005,1000,true,true\n2,3500.02,0.25,875.005,1001000,false,true\n").unwrap(),
+            vec![trade(1000, "3500.01", "0.5"), trade(1001000, "3500.02", "0.25")],
+        );
+        assert_eq!(
+            read(Exchange::Binance, "id,price,qty,quote_qty,time,is_buyer_maker,is_best_match\n1,3500.01,0.5,1750.005,1735689600000000,true,true\n").unwrap(),
+            vec![trade(1735689600000, "3500.01", "0.5")],
+        );
+
+        assert_eq!(
+            read(Exchange::Kraken, "1546300800,3500.1,0.5\n\n1546300801,3500.2,0.25\n").unwrap(),
+            vec![trade(1546300800000, "3500.1", "0.5"), trade(1546300801000, "3500.2", "0.25")],
+        );
+        assert!(read(Exchange::Kraken, "1546300800,3500.1\n").is_err());
+
+        File::create("test_csv_trades").unwrap().write_all(b"time,price,amount\n").unwrap();
+        assert!(csv_trades(Exchange::Gemini, File::open("test_csv_trades").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_json_trades() {
+        let trade = |timestamp: Timestamp, price: &str, amount: &str| Trade { timestamp: timestamp, price: price.to_string(), amount: amount.to_string() };
+
+        // Newest first, as Gemini's API returns them
+        assert_eq!(
+            json_trades(Exchange::Gemini, r#"[
+                {"timestamp": 1, "timestampms": 1001, "tid": 2, "price": "3500.02", "amount": "0.25", "type": "buy"},
+                {"timestamp": 1, "timestampms": 1000, "tid": 1, "price": "3500.01", "amount": "0.5", "type": "sell"}
+            ]"#).unwrap(),
+            vec![trade(1000, "3500.01", "0.5"), trade(1001, "3500.02", "0.25")],
+        );
+
+        assert_eq!(
+            json_trades(Exchange::Binance, r#"[
+                {"id": 1, "price": "3500.01", "qty": "0.5", "time": 1000, "isBuyerMaker": true},
+                {"a": 2, "p": "3500.02", "q": "0.25", "T": 1001, "m": false}
+            ]"#).unwrap(),
+            vec![trade(1000, "3500.01", "0.5"), trade(1001, "3500.02", "0.25")],
+        );
+
+        assert_eq!(
+            json_trades(Exchange::Kraken, r#"{"error": [], "result": {
+                "XXBTZUSD": [["3500.1", "0.5", 1546300800.1234, "b", "l", ""]],
+                "last": "1546300800123400000"
+            }}"#).unwrap(),
+            vec![trade(1546300800123, "3500.1", "0.5")],
+        );
+
+        assert!(json_trades(Exchange::Binance, r#"[{"price": "3500.01", "qty": "0.5"}]"#).is_err());
+        assert!(json_trades(Exchange::Kraken, "[]").is_err());
+    }
+}
@@ -0,0 +1,54 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The trade-data binary: a subcommand, if one is given, or else the server, configured by its file.
+//!
+//! Usage: trade-data [<subcommand> ...]
+
+extern crate serde_json;
+extern crate trade_data;
+
+use std::env;
+use std::process;
+#[cfg(unix)]
+use std::thread;
+
+use trade_data::server::{self, Server};
+
+mod cli;
+mod import;
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+
+    if args.first().map_or(false, |a| cli::SUBCOMMANDS.contains(&a.as_str())) {
+        if let Err(error) = cli::run(args) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    server::log_about();
+
+    let server = Server::builder().build().expect("Could not start server");
+    #[cfg(unix)]
+    thread::spawn(|| server::reload_on_hangup().expect("Could not handle SIGHUP"));
+    #[cfg(unix)]
+    thread::spawn(|| server::shutdown_on_signal().expect("Could not handle SIGINT or SIGTERM"));
+
+    server.launch();
+}
@@ -0,0 +1,836 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Calendar-aware bucketing.
+//!
+//! Days and weeks don't have a fixed length wherever daylight saving time is observed, so their boundaries are
+//! found from the local time of a time zone instead.  Time zones are read from the system's tz database, in the
+//! directory named by `TZDIR`, or `/usr/share/zoneinfo` by default.
+//!
+//! Markets that close for holidays can have their holiday buckets skipped or merged into the bucket before them.
+//! Holiday calendars are files of local dates, one "YYYY-MM-DD" per line, read from the directory named by
+//! `TRADE_DATA_HOLIDAYS`, or `holidays` by default.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use time_series::{TimeUnit, Timestamp};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+lazy_static! {
+    /// Every time zone loaded so far.  They're kept for the life of the process, so that intervals can refer to
+    /// them and still be copied freely.
+    static ref ZONES: Mutex<Vec<&'static TimeZone>> = Mutex::new(Vec::new());
+
+    /// Every holiday calendar loaded so far, kept for the same reason
+    static ref HOLIDAYS: Mutex<Vec<&'static HolidayCalendar>> = Mutex::new(Vec::new());
+}
+
+/// A day or week in the local time of a time zone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CalendarInterval {
+    /// From one local midnight to the next
+    Day { tz: &'static TimeZone },
+    /// From local midnight at the start of `anchor` to the same a week later
+    Week { tz: &'static TimeZone, anchor: Weekday },
+}
+
+impl CalendarInterval {
+    /// The start of the day or week that holds `timestamp`
+    pub fn floor(&self, timestamp: Timestamp, unit: TimeUnit) -> Timestamp {
+        let (tz, day) = self.first_day(timestamp, unit);
+        to_timestamp(tz.day_start(day), unit)
+    }
+
+    /// The start of the day or week after the one that holds `timestamp`
+    pub fn next(&self, timestamp: Timestamp, unit: TimeUnit) -> Timestamp {
+        let (tz, day) = self.first_day(timestamp, unit);
+        to_timestamp(tz.day_start(day + self.days()), unit)
+    }
+
+    /// Whether every local day of the interval that starts at `bucket_start` is a holiday
+    pub fn is_holiday(&self, bucket_start: Timestamp, unit: TimeUnit, holidays: &HolidayCalendar) -> bool {
+        let (_, day) = self.first_day(bucket_start, unit);
+        (day..day + self.days()).all(|day| holidays.contains(day))
+    }
+
+    /// The usual length of the interval, for when an estimate will do
+    pub fn nominal(&self, unit: TimeUnit) -> Timestamp {
+        self.days() as Timestamp * SECONDS_PER_DAY as Timestamp * unit.per_second()
+    }
+
+    fn days(&self) -> i64 {
+        match *self {
+            CalendarInterval::Day { .. } => 1,
+            CalendarInterval::Week { .. } => 7,
+        }
+    }
+
+    /// The time zone, and the local day, counted from the epoch, that the interval holding `timestamp` starts on
+    fn first_day(&self, timestamp: Timestamp, unit: TimeUnit) -> (&'static TimeZone, i64) {
+        let seconds = (timestamp / unit.per_second()) as i64;
+
+        match *self {
+            CalendarInterval::Day { tz } => (tz, tz.local_day(seconds)),
+            CalendarInterval::Week { tz, anchor } => {
+                let day = tz.local_day(seconds);
+                (tz, day - (Weekday::of_day(day) as i64 - anchor as i64 + 7) % 7)
+            },
+        }
+    }
+}
+
+fn to_timestamp(seconds: i64, unit: TimeUnit) -> Timestamp {
+    (seconds.max(0) as Timestamp).saturating_mul(unit.per_second())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Monday, Weekday::Tuesday, Weekday::Wednesday, Weekday::Thursday, Weekday::Friday, Weekday::Saturday, Weekday::Sunday,
+    ];
+
+    /// The weekday of a day counted from the epoch, which was a Thursday
+    fn of_day(day: i64) -> Weekday {
+        Weekday::ALL[((day + 3) % 7 + 7) as usize % 7]
+    }
+}
+
+impl FromStr for Weekday {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        match text {
+            "monday" => Ok(Weekday::Monday),
+            "tuesday" => Ok(Weekday::Tuesday),
+            "wednesday" => Ok(Weekday::Wednesday),
+            "thursday" => Ok(Weekday::Thursday),
+            "friday" => Ok(Weekday::Friday),
+            "saturday" => Ok(Weekday::Saturday),
+            "sunday" => Ok(Weekday::Sunday),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Weekday must be spelled out, e.g. \"monday\"")),
+        }
+    }
+}
+
+/// A time zone's offsets from UTC over time
+pub struct TimeZone {
+    name: String,
+    /// The UTC seconds at which the offset changes, along with the offset from then on
+    transitions: Vec<(i64, i32)>,
+    /// The offset before the first transition
+    initial_offset: i32,
+    /// How the offset changes after the last transition
+    rule: Option<Rule>,
+}
+
+impl TimeZone {
+    /// Loads a time zone from the tz database by name, e.g. "America/New_York".  "UTC" is always available.
+    pub fn load(name: &str) -> io::Result<&'static TimeZone> {
+        let mut zones = ZONES.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Time zone cache was poisoned"))?;
+
+        if let Some(zone) = zones.iter().find(|zone| zone.name == name) {
+            return Ok(*zone);
+        }
+
+        let zone = if name == "UTC" {
+            TimeZone::fixed(name, 0)
+        } else {
+            let data = fs::read(zone_path(name)?).map_err(|error| match error.kind() {
+                io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound, format!("Unknown time zone \"{}\"", name)),
+                _ => error,
+            })?;
+
+            TimeZone::parse(name, &data)?
+        };
+
+        let zone: &'static TimeZone = Box::leak(Box::new(zone));
+        zones.push(zone);
+        Ok(zone)
+    }
+
+    /// A time zone that's always the same offset from UTC, in seconds east
+    pub fn fixed(name: &str, offset: i32) -> TimeZone {
+        TimeZone {
+            name: name.to_string(),
+            transitions: Vec::new(),
+            initial_offset: offset,
+            rule: None,
+        }
+    }
+
+    /// Parses a TZif file, as found in the tz database
+    pub fn parse(name: &str, data: &[u8]) -> io::Result<TimeZone> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Time zone file is corrupt");
+
+        let header = |data: &[u8]| -> io::Result<[usize; 6]> {
+            if data.len() < 44 || &data[..4] != b"TZif" {
+                return Err(invalid());
+            }
+
+            let mut counts = [0; 6];
+            for (i, count) in counts.iter_mut().enumerate() {
+                *count = read_int(&data[20 + i * 4..24 + i * 4]) as usize;
+            }
+            Ok(counts)
+        };
+
+        // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+        let [utc, std, leap, times, types, chars] = header(data)?;
+        let v1_length = times * 5 + types * 6 + chars + leap * 8 + std + utc;
+
+        // Version 2 and later repeat the data with 64-bit times, followed by a rule for times after the last
+        let (time_size, counts, block, footer) = if data[4] >= b'2' {
+            let data = data.get(44 + v1_length..).ok_or_else(invalid)?;
+            let counts = header(data)?;
+            let [utc, std, leap, times, types, chars] = counts;
+            let length = times * 9 + types * 6 + chars + leap * 12 + std + utc;
+            let block = data.get(44..44 + length).ok_or_else(invalid)?;
+            (8, counts, block, &data[44 + length..])
+        } else {
+            (4, [utc, std, leap, times, types, chars], data.get(44..44 + v1_length).ok_or_else(invalid)?, &[][..])
+        };
+
+        let (times, types) = (counts[3], counts[4]);
+        if types == 0 {
+            return Err(invalid());
+        }
+
+        let indices = &block[times * time_size..times * (time_size + 1)];
+        let offsets = (0..types).map(|i| {
+            let start = times * (time_size + 1) + i * 6;
+            read_int(&block[start..start + 4]) as i32
+        }).collect::<Vec<i32>>();
+
+        let mut transitions = Vec::with_capacity(times);
+        for i in 0..times {
+            let offset = *offsets.get(indices[i] as usize).ok_or_else(invalid)?;
+            transitions.push((read_int(&block[i * time_size..(i + 1) * time_size]), offset));
+        }
+
+        let rule = match footer.split(|&b| b == b'\n').nth(1) {
+            Some(rule) if !rule.is_empty() => Some(Rule::parse(::std::str::from_utf8(rule).map_err(|_| invalid())?)?),
+            _ => None,
+        };
+
+        Ok(TimeZone {
+            name: name.to_string(),
+            transitions: transitions,
+            initial_offset: offsets[0],
+            rule: rule,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The offset from UTC, in seconds east, at a time in UTC seconds
+    pub fn offset_at(&self, seconds: i64) -> i32 {
+        let index = match self.transitions.binary_search_by(|&(time, _)| time.cmp(&seconds)) {
+            Ok(index) => index,
+            Err(0) if self.transitions.is_empty() => return self.rule.map_or(self.initial_offset, |rule| rule.offset_at(seconds)),
+            Err(0) => return self.initial_offset,
+            Err(index) => index - 1,
+        };
+
+        match self.rule {
+            Some(rule) if index + 1 == self.transitions.len() => rule.offset_at(seconds),
+            _ => self.transitions[index].1,
+        }
+    }
+
+    /// The local day, counted from the epoch, at a time in UTC seconds
+    fn local_day(&self, seconds: i64) -> i64 {
+        floor_div(seconds + self.offset_at(seconds) as i64, SECONDS_PER_DAY)
+    }
+
+    /// The first time, in UTC seconds, that's on or after local midnight of a day counted from the epoch.  When
+    /// midnight happens twice, that's the first of them, and when it's skipped, it's the end of the skip.
+    fn day_start(&self, day: i64) -> i64 {
+        let midnight = day * SECONDS_PER_DAY;
+
+        // Any change of offset around midnight is between these two
+        let candidates = [
+            midnight - self.offset_at(midnight - SECONDS_PER_DAY) as i64,
+            midnight - self.offset_at(midnight + SECONDS_PER_DAY) as i64,
+        ];
+
+        let valid = candidates.iter().cloned().filter(|&time| time + self.offset_at(time) as i64 == midnight).min();
+        if let Some(time) = valid {
+            return time;
+        }
+
+        // Midnight was skipped, so find where the clocks jumped past it
+        let (mut before, mut after) = (candidates[0].min(candidates[1]), candidates[0].max(candidates[1]));
+        while after - before > 1 {
+            let middle = before + (after - before) / 2;
+            if middle + self.offset_at(middle) as i64 >= midnight {
+                after = middle;
+            } else {
+                before = middle;
+            }
+        }
+        after
+    }
+}
+
+impl fmt::Debug for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TimeZone({})", self.name)
+    }
+}
+
+impl PartialEq for TimeZone {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+/// What to do with the buckets of a calendar interval that fall entirely on holidays
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HolidayPolicy {
+    /// Leave holiday buckets out, along with any records in them
+    Skip,
+    /// Pool holidays into the bucket before them
+    Merge,
+}
+
+impl FromStr for HolidayPolicy {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        match text {
+            "skip" => Ok(HolidayPolicy::Skip),
+            "merge" => Ok(HolidayPolicy::Merge),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Holiday policy must be \"skip\" or \"merge\"")),
+        }
+    }
+}
+
+/// The local dates a market is closed on
+pub struct HolidayCalendar {
+    name: String,
+    /// Days counted from the epoch, sorted
+    days: Vec<i64>,
+}
+
+impl HolidayCalendar {
+    /// Loads a holiday calendar by name, e.g. "nyse"
+    pub fn load(name: &str) -> io::Result<&'static HolidayCalendar> {
+        let mut calendars = HOLIDAYS.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Holiday calendar cache was poisoned"))?;
+
+        if let Some(calendar) = calendars.iter().find(|calendar| calendar.name == name) {
+            return Ok(*calendar);
+        }
+
+        let directory = env::var("TRADE_DATA_HOLIDAYS").unwrap_or_else(|_| "holidays".to_string());
+        let text = fs::read_to_string(database_path(&directory, name, "holiday calendar")?).map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound, format!("Unknown holiday calendar \"{}\"", name)),
+            _ => error,
+        })?;
+
+        let calendar: &'static HolidayCalendar = Box::leak(Box::new(HolidayCalendar::parse(name, &text)?));
+        calendars.push(calendar);
+        Ok(calendar)
+    }
+
+    /// Parses a holiday calendar of one "YYYY-MM-DD" date per line.  Blank lines and lines starting with '#' are
+    /// ignored.
+    pub fn parse(name: &str, text: &str) -> io::Result<HolidayCalendar> {
+        let mut days = Vec::new();
+
+        for line in text.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid holiday \"{}\" in calendar \"{}\"", line, name));
+
+            let fields = line.split('-').map(|field| field.parse::<i64>().map_err(|_| invalid())).collect::<io::Result<Vec<i64>>>()?;
+            let (year, month, day) = match fields[..] {
+                [year, month, day] if month >= 1 && month <= 12 && day >= 1 && day <= 31 => (year, month, day),
+                _ => return Err(invalid()),
+            };
+
+            let days_since_epoch = days_from_civil(year, month, day);
+            if civil_from_days(days_since_epoch) != (year, month, day) {
+                return Err(invalid());
+            }
+            days.push(days_since_epoch);
+        }
+
+        days.sort();
+        days.dedup();
+
+        Ok(HolidayCalendar {
+            name: name.to_string(),
+            days: days,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether a local day, counted from the epoch, is a holiday
+    pub fn contains(&self, day: i64) -> bool {
+        self.days.binary_search(&day).is_ok()
+    }
+}
+
+impl fmt::Debug for HolidayCalendar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HolidayCalendar({})", self.name)
+    }
+}
+
+impl PartialEq for HolidayCalendar {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+/// The path of a time zone in the tz database
+fn zone_path(name: &str) -> io::Result<PathBuf> {
+    let directory = env::var("TZDIR").unwrap_or_else(|_| "/usr/share/zoneinfo".to_string());
+    database_path(&directory, name, "time zone")
+}
+
+/// The path of a named file in a database directory.  Names can't leave the directory.
+fn database_path(directory: &str, name: &str, kind: &str) -> io::Result<PathBuf> {
+    let valid = !name.is_empty() &&
+        name.bytes().all(|b| b.is_ascii_alphanumeric() || b"/_-+".contains(&b)) &&
+        name.split('/').all(|part| !part.is_empty() && !part.starts_with('.'));
+
+    if !valid {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid {} \"{}\"", kind, name)));
+    }
+
+    Ok(PathBuf::from(directory).join(name))
+}
+
+/// Reads a big-endian signed integer of 4 or 8 bytes
+fn read_int(bytes: &[u8]) -> i64 {
+    let value = bytes.iter().fold(0u64, |value, &byte| value << 8 | byte as u64);
+    let shift = 64 - bytes.len() * 8;
+    (value << shift) as i64 >> shift
+}
+
+fn floor_div(a: i64, b: i64) -> i64 {
+    let quotient = a / b;
+    if a % b < 0 { quotient - 1 } else { quotient }
+}
+
+/// A POSIX TZ rule, e.g. "EST5EDT,M3.2.0,M11.1.0", which the tz database uses for times after its last transition
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rule {
+    /// The offset from UTC outside daylight saving time, in seconds east
+    standard: i32,
+    daylight: Option<Daylight>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Daylight {
+    offset: i32,
+    /// When daylight saving time starts, in local standard time
+    start: (DateRule, i32),
+    /// When it ends, in local daylight saving time
+    end: (DateRule, i32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DateRule {
+    /// `Jn`: the nth day of the year, from 1 to 365, never counting February 29th
+    Julian(i64),
+    /// `n`: the nth day of the year, from 0 to 365, counting February 29th
+    Day(i64),
+    /// `Mm.w.d`: weekday `d`, from Sunday as 0, of week `w` of month `m`, where week 5 is the last
+    Month { month: i64, week: i64, weekday: i64 },
+}
+
+impl Rule {
+    fn parse(text: &str) -> io::Result<Rule> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid time zone rule \"{}\"", text));
+        let mut parser = RuleParser { bytes: text.as_bytes(), position: 0 };
+
+        // POSIX offsets are west of UTC, so they're negated
+        parser.name().ok_or_else(invalid)?;
+        let standard = -parser.time().ok_or_else(invalid)?;
+
+        if parser.done() {
+            return Ok(Rule { standard: standard, daylight: None });
+        }
+
+        parser.name().ok_or_else(invalid)?;
+        let offset = match parser.peek() {
+            Some(b',') | None => standard + 3600,
+            _ => -parser.time().ok_or_else(invalid)?,
+        };
+
+        let mut transition = || -> Option<(DateRule, i32)> {
+            if !parser.eat(b',') {
+                return None;
+            }
+            let date = parser.date()?;
+            let time = if parser.eat(b'/') { parser.time()? } else { 7200 };
+            Some((date, time))
+        };
+
+        let (start, end) = match (transition(), transition()) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Err(invalid()),
+        };
+
+        if !parser.done() {
+            return Err(invalid());
+        }
+
+        Ok(Rule {
+            standard: standard,
+            daylight: Some(Daylight { offset: offset, start: start, end: end }),
+        })
+    }
+
+    fn offset_at(&self, seconds: i64) -> i32 {
+        let daylight = match self.daylight {
+            Some(daylight) => daylight,
+            None => return self.standard,
+        };
+
+        let year = civil_from_days(floor_div(seconds + self.standard as i64, SECONDS_PER_DAY)).0;
+        let start = daylight.start.0.day(year) * SECONDS_PER_DAY + daylight.start.1 as i64 - self.standard as i64;
+        let end = daylight.end.0.day(year) * SECONDS_PER_DAY + daylight.end.1 as i64 - daylight.offset as i64;
+
+        // In the southern hemisphere, daylight saving time spans the new year
+        let in_daylight = if start < end {
+            seconds >= start && seconds < end
+        } else {
+            !(seconds >= end && seconds < start)
+        };
+
+        if in_daylight { daylight.offset } else { self.standard }
+    }
+}
+
+impl DateRule {
+    /// The day, counted from the epoch, that the rule falls on in a year
+    fn day(&self, year: i64) -> i64 {
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let january_first = days_from_civil(year, 1, 1);
+
+        match *self {
+            DateRule::Julian(day) => january_first + day - 1 + if leap && day >= 60 { 1 } else { 0 },
+            DateRule::Day(day) => january_first + day,
+            DateRule::Month { month, week, weekday } => {
+                let first = days_from_civil(year, month, 1);
+                let days_in_month = days_from_civil(year + month / 12, month % 12 + 1, 1) - first;
+
+                // The epoch was a Thursday, which is 4 counting from Sunday
+                let first_weekday = ((first + 4) % 7 + 7) % 7;
+                let mut day = (weekday - first_weekday + 7) % 7 + (week - 1) * 7;
+                while day >= days_in_month {
+                    day -= 7;
+                }
+
+                first + day
+            },
+        }
+    }
+}
+
+struct RuleParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> RuleParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).cloned()
+    }
+
+    fn done(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// A zone abbreviation, either alphabetic or quoted in angle brackets
+    fn name(&mut self) -> Option<()> {
+        let start = self.position;
+
+        if self.eat(b'<') {
+            while self.peek()? != b'>' {
+                self.position += 1;
+            }
+            self.position += 1;
+        } else {
+            while self.peek().map_or(false, |b| b.is_ascii_alphabetic()) {
+                self.position += 1;
+            }
+        }
+
+        if self.position - start >= 3 { Some(()) } else { None }
+    }
+
+    fn number(&mut self) -> Option<i64> {
+        let start = self.position;
+        while self.peek().map_or(false, |b| b.is_ascii_digit()) {
+            self.position += 1;
+        }
+
+        ::std::str::from_utf8(&self.bytes[start..self.position]).ok()?.parse().ok()
+    }
+
+    /// `[+-]hh[:mm[:ss]]`, in seconds
+    fn time(&mut self) -> Option<i32> {
+        let sign = if self.eat(b'-') { -1 } else { self.eat(b'+'); 1 };
+
+        let mut seconds = self.number()? * 3600;
+        if self.eat(b':') {
+            seconds += self.number()? * 60;
+            if self.eat(b':') {
+                seconds += self.number()?;
+            }
+        }
+
+        Some(sign * seconds as i32)
+    }
+
+    fn date(&mut self) -> Option<DateRule> {
+        if self.eat(b'J') {
+            Some(DateRule::Julian(self.number()?))
+        } else if self.eat(b'M') {
+            let month = self.number()?;
+            if !self.eat(b'.') {
+                return None;
+            }
+            let week = self.number()?;
+            if !self.eat(b'.') {
+                return None;
+            }
+            let weekday = self.number()?;
+
+            if month < 1 || month > 12 || week < 1 || week > 5 || weekday > 6 {
+                return None;
+            }
+            Some(DateRule::Month { month: month, week: week, weekday: weekday })
+        } else {
+            Some(DateRule::Day(self.number()?))
+        }
+    }
+}
+
+/// The days since the epoch of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = floor_div(year, 400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The year, month, and day of a day counted from the epoch
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = floor_div(days, 146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2019-03-10, when New York moved its clocks forward an hour at 2 AM
+    const SPRING_FORWARD: i64 = 17_965;
+
+    fn new_york() -> TimeZone {
+        // Just the footer rule, as a slim TZif file would have it
+        TimeZone {
+            name: "America/New_York".to_string(),
+            transitions: Vec::new(),
+            initial_offset: -5 * 3600,
+            rule: Some(Rule::parse("EST5EDT,M3.2.0,M11.1.0").unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2019, 3, 10), SPRING_FORWARD);
+        assert_eq!(civil_from_days(SPRING_FORWARD), (2019, 3, 10));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(Weekday::of_day(0), Weekday::Thursday);
+        assert_eq!(Weekday::of_day(SPRING_FORWARD), Weekday::Sunday);
+    }
+
+    #[test]
+    fn test_rule() {
+        let rule = Rule::parse("EST5EDT,M3.2.0,M11.1.0").unwrap();
+
+        // 2019-03-10 06:59:59 and 07:00:00 UTC, either side of 2 AM standard time
+        let start = SPRING_FORWARD * SECONDS_PER_DAY + 7 * 3600;
+        assert_eq!(rule.offset_at(start - 1), -5 * 3600);
+        assert_eq!(rule.offset_at(start), -4 * 3600);
+
+        // 2019-11-03 05:59:59 and 06:00:00 UTC, either side of 2 AM daylight time
+        let end = days_from_civil(2019, 11, 3) * SECONDS_PER_DAY + 6 * 3600;
+        assert_eq!(rule.offset_at(end - 1), -4 * 3600);
+        assert_eq!(rule.offset_at(end), -5 * 3600);
+
+        // Southern hemisphere daylight saving time spans the new year
+        let sydney = Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset_at(days_from_civil(2019, 1, 15) * SECONDS_PER_DAY), 11 * 3600);
+        assert_eq!(sydney.offset_at(days_from_civil(2019, 7, 15) * SECONDS_PER_DAY), 10 * 3600);
+
+        assert_eq!(Rule::parse("<+03>-3").unwrap(), Rule { standard: 3 * 3600, daylight: None });
+        assert!(Rule::parse("EST5EDT,M13.2.0,M11.1.0").is_err());
+        assert!(Rule::parse("X5").is_err());
+    }
+
+    #[test]
+    fn test_parse_tzif() {
+        // A version 2 file with one transition, to +01:00 at 1000 seconds, and a fixed rule afterwards
+        let mut data = Vec::new();
+        let header = |data: &mut Vec<u8>, counts: [u32; 6]| {
+            data.extend_from_slice(b"TZif2");
+            data.extend_from_slice(&[0; 15]);
+            for count in &counts {
+                data.extend_from_slice(&count.to_be_bytes());
+            }
+        };
+
+        header(&mut data, [0, 0, 0, 1, 2, 8]);
+        data.extend_from_slice(&1000i32.to_be_bytes());
+        data.push(1);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 14, 16, 0, 4]);
+        data.extend_from_slice(b"UTC\0ONE\0");
+
+        header(&mut data, [0, 0, 0, 1, 2, 8]);
+        data.extend_from_slice(&1000i64.to_be_bytes());
+        data.push(1);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 14, 16, 0, 4]);
+        data.extend_from_slice(b"UTC\0ONE\0");
+        data.extend_from_slice(b"\n<+02>-2\n");
+
+        let zone = TimeZone::parse("Test/Zone", &data).unwrap();
+        assert_eq!(zone.offset_at(999), 0);
+        assert_eq!(zone.offset_at(1000), 2 * 3600);
+        assert_eq!(zone.transitions, vec![(1000, 3600)]);
+
+        assert!(TimeZone::parse("Test/Zone", &data[..50]).is_err());
+        assert!(TimeZone::parse("Test/Zone", b"not a zone file").is_err());
+    }
+
+    #[test]
+    fn test_calendar_interval() {
+        let tz: &'static TimeZone = Box::leak(Box::new(new_york()));
+        let day = CalendarInterval::Day { tz: tz };
+        let hour = 3600 * 1000;
+
+        // Midnight EST is 05:00 UTC
+        let march_9 = ((SPRING_FORWARD - 1) * SECONDS_PER_DAY) as Timestamp * 1000 + 5 * hour;
+        let march_10 = march_9 + 24 * hour;
+        let march_11 = march_10 + 23 * hour;
+
+        assert_eq!(day.floor(march_10 + 12 * hour, TimeUnit::Milliseconds), march_10);
+        assert_eq!(day.floor(march_10, TimeUnit::Milliseconds), march_10);
+        assert_eq!(day.floor(march_10 - 1, TimeUnit::Milliseconds), march_9);
+        assert_eq!(day.next(march_10, TimeUnit::Milliseconds), march_11);
+        assert_eq!(day.next(march_9, TimeUnit::Milliseconds), march_10);
+        assert_eq!(day.floor(march_10 * 1000 + 1, TimeUnit::Microseconds), march_10 * 1000);
+        assert_eq!(day.nominal(TimeUnit::Milliseconds), 24 * hour);
+
+        // Weeks starting on Monday, so the week of Sunday March 10th started on Monday March 4th
+        let week = CalendarInterval::Week { tz: tz, anchor: Weekday::Monday };
+        let march_4 = march_9 - 5 * 24 * hour;
+        assert_eq!(week.floor(march_10, TimeUnit::Milliseconds), march_4);
+        assert_eq!(week.next(march_10, TimeUnit::Milliseconds), march_11);
+
+        assert_eq!("sunday".parse::<Weekday>().unwrap(), Weekday::Sunday);
+        assert!("sun".parse::<Weekday>().is_err());
+    }
+
+    #[test]
+    fn test_skipped_midnight() {
+        // Clocks go forward at midnight, so the day starts at 1 AM
+        let rule = Rule::parse("<-03>3<-02>,M11.1.0/0,M2.3.0/0").unwrap();
+        let tz: &'static TimeZone = Box::leak(Box::new(TimeZone { name: "Test/Skip".to_string(), transitions: Vec::new(), initial_offset: -3 * 3600, rule: Some(rule) }));
+
+        let day = days_from_civil(2018, 11, 4);
+        assert_eq!(tz.day_start(day), day * SECONDS_PER_DAY + 3 * 3600);
+        assert_eq!(tz.day_start(day + 1), (day + 1) * SECONDS_PER_DAY + 2 * 3600);
+    }
+
+    #[test]
+    fn test_holidays() {
+        let holidays: &'static HolidayCalendar = Box::leak(Box::new(HolidayCalendar::parse("Test/Holidays", "# Closed\n2019-03-11\n\n2019-03-08\n").unwrap()));
+        assert!(holidays.contains(SPRING_FORWARD + 1));
+        assert!(!holidays.contains(SPRING_FORWARD));
+
+        let tz: &'static TimeZone = Box::leak(Box::new(new_york()));
+        let hour = 3600 * 1000;
+        let march_11 = (SPRING_FORWARD + 1) * SECONDS_PER_DAY * 1000 + 4 * hour;
+
+        let day = CalendarInterval::Day { tz: tz };
+        assert!(day.is_holiday(march_11 as Timestamp, TimeUnit::Milliseconds, holidays));
+        assert!(!day.is_holiday((march_11 + 24 * hour) as Timestamp, TimeUnit::Milliseconds, holidays));
+
+        // A week is only a holiday when every day of it is
+        let week = CalendarInterval::Week { tz: tz, anchor: Weekday::Monday };
+        assert!(!week.is_holiday(march_11 as Timestamp, TimeUnit::Milliseconds, holidays));
+
+        assert!(HolidayCalendar::parse("Test/Holidays", "2019-02-29").is_err());
+        assert!(HolidayCalendar::parse("Test/Holidays", "2019-03").is_err());
+        assert!(HolidayCalendar::parse("Test/Holidays", "March 11th").is_err());
+        assert_eq!("merge".parse::<HolidayPolicy>().unwrap(), HolidayPolicy::Merge);
+        assert!(HolidayCalendar::load("../etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_zone_path() {
+        assert!(zone_path("America/New_York").is_ok());
+        assert!(zone_path("Etc/GMT+5").is_ok());
+        assert!(zone_path("../etc/passwd").is_err());
+        assert!(zone_path("/etc/passwd").is_err());
+        assert!(zone_path("").is_err());
+    }
+}
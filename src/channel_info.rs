@@ -0,0 +1,259 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Metadata about a channel, kept beside its storage file.
+//!
+//! A channel's info is kept in a sidecar file named after its storage file with ".info" appended, one field per line
+//! as a name and a value separated by a space.  It's written when the channel is first opened, so its creation time
+//! survives restarts, and rewritten whenever the description or how its values are denominated changes.
+//!
+//! Value types that know how they're denominated describe it with `ValueMetadata`, so that clients can render a value
+//! like "12.34567890 BTC" without assuming what a channel holds.
+
+use std::any;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use parse;
+use time_series::{TimeUnit, Timestamp};
+
+/// How a value type is denominated.  Everything is unknown by default.
+pub trait ValueMetadata {
+    /// What the values count, e.g. "BTC" or "contracts"
+    fn unit() -> Option<&'static str> {
+        None
+    }
+
+    /// The code of the currency the values are amounts of, e.g. "USD", if they're money
+    fn currency() -> Option<&'static str> {
+        None
+    }
+
+    /// How many decimal places the values have been scaled by
+    fn precision() -> Option<u32> {
+        None
+    }
+}
+
+/// What a channel holds, for clients discovering channels rather than knowing them in advance
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelInfo {
+    /// The Rust type of the channel's values, e.g. "u64"
+    pub value_type: String,
+    /// The unit of the channel's timestamps
+    pub unit: TimeUnit,
+    /// How many decimal places the values have been scaled by, e.g. 2 for prices stored in cents
+    pub precision: Option<u32>,
+    /// What the values count, e.g. "BTC"
+    pub value_unit: Option<String>,
+    /// The code of the currency the values are amounts of, e.g. "USD"
+    pub currency: Option<String>,
+    pub description: Option<String>,
+    /// When the channel was created, in milliseconds, or `None` if it's derived from other channels and never stored
+    pub created_at: Option<Timestamp>,
+}
+
+impl ChannelInfo {
+    /// The info of a new channel of `V` values, created now
+    pub fn of<V: 'static>(unit: TimeUnit) -> Self {
+        Self {
+            value_type: any::type_name::<V>().to_string(),
+            unit: unit,
+            precision: None,
+            value_unit: None,
+            currency: None,
+            description: None,
+            created_at: Some(parse::now()),
+        }
+    }
+
+    /// Fills in what `V` knows of how it's denominated, keeping whatever the info already says
+    pub fn with_metadata<V>(self) -> Self where V: ValueMetadata {
+        Self {
+            precision: self.precision.or(V::precision()),
+            value_unit: self.value_unit.or_else(|| V::unit().map(str::to_string)),
+            currency: self.currency.or_else(|| V::currency().map(str::to_string)),
+            ..self
+        }
+    }
+
+    /// The sidecar file of a storage file
+    pub fn path(file: &str) -> String {
+        format!("{}.info", file)
+    }
+
+    /// Reads the info of the channel stored in `file`, or `None` if it has none
+    pub fn load(file: &str) -> io::Result<Option<Self>> {
+        match fs::read_to_string(Self::path(file)) {
+            Ok(contents) => contents.parse().map(Some),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Reads the info of the channel stored in `file`, writing `info` as its info first if it has none
+    pub fn load_or_create(file: &str, info: ChannelInfo) -> io::Result<Self> {
+        match Self::load(file)? {
+            Some(info) => Ok(info),
+            None => {
+                info.save(file)?;
+                Ok(info)
+            },
+        }
+    }
+
+    /// Writes the info of the channel stored in `file`, replacing what was there in one step
+    pub fn save(&self, file: &str) -> io::Result<()> {
+        let path = Self::path(file);
+        let temporary_path = format!("{}.tmp", path);
+
+        {
+            let mut temporary_file = File::create(&temporary_path)?;
+            temporary_file.write_all(self.to_string().as_bytes())?;
+            temporary_file.sync_all()?;
+        }
+
+        fs::rename(temporary_path, path)
+    }
+}
+
+impl fmt::Display for ChannelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "value_type {}", self.value_type)?;
+        writeln!(f, "unit {}", self.unit)?;
+        if let Some(precision) = self.precision {
+            writeln!(f, "precision {}", precision)?;
+        }
+        if let Some(ref value_unit) = self.value_unit {
+            writeln!(f, "value_unit {}", value_unit)?;
+        }
+        if let Some(ref currency) = self.currency {
+            writeln!(f, "currency {}", currency)?;
+        }
+        if let Some(ref description) = self.description {
+            // Descriptions are a single line
+            writeln!(f, "description {}", description.replace(|c| c == '\n' || c == '\r', " "))?;
+        }
+        if let Some(created_at) = self.created_at {
+            writeln!(f, "created_at {}", created_at)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ChannelInfo {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Channel info {}", message));
+
+        let mut value_type = None;
+        let mut unit = None;
+        let mut info = ChannelInfo {
+            value_type: String::new(),
+            unit: TimeUnit::Milliseconds,
+            precision: None,
+            value_unit: None,
+            currency: None,
+            description: None,
+            created_at: None,
+        };
+
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let mut fields = line.splitn(2, ' ');
+            let (name, value) = (fields.next().unwrap_or(""), fields.next().unwrap_or("").trim());
+
+            match name {
+                "value_type" => value_type = Some(value.to_string()),
+                "unit" => unit = Some(value.parse()?),
+                "precision" => info.precision = Some(value.parse().map_err(|_| invalid("has an invalid precision"))?),
+                "value_unit" => info.value_unit = Some(value.to_string()),
+                "currency" => info.currency = Some(value.to_string()),
+                "description" => info.description = Some(value.to_string()),
+                "created_at" => info.created_at = Some(value.parse().map_err(|_| invalid("has an invalid creation time"))?),
+                // Fields added by later versions are ignored
+                _ => (),
+            }
+        }
+
+        info.value_type = value_type.ok_or_else(|| invalid("is missing its value type"))?;
+        info.unit = unit.ok_or_else(|| invalid("is missing its unit"))?;
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use util::SetupFile;
+
+    #[test]
+    fn test_channel_info() {
+        let _setup_file = SetupFile::new("test_channel_info.info");
+
+        assert_eq!(ChannelInfo::load("test_channel_info").unwrap(), None);
+
+        let mut info = ChannelInfo::of::<u64>(TimeUnit::Microseconds);
+        assert_eq!(info.value_type, "u64");
+
+        let created = ChannelInfo::load_or_create("test_channel_info", info.clone()).unwrap();
+        assert_eq!(created, info);
+
+        // The first info is kept, along with its creation time
+        let later = ChannelInfo { created_at: Some(1), ..info.clone() };
+        assert_eq!(ChannelInfo::load_or_create("test_channel_info", later).unwrap(), info);
+
+        info.precision = Some(2);
+        info.currency = Some("USD".to_string());
+        info.description = Some("Trades\nin cents".to_string());
+        info.save("test_channel_info").unwrap();
+
+        let loaded = ChannelInfo::load("test_channel_info").unwrap().unwrap();
+        assert_eq!(loaded.precision, Some(2));
+        assert_eq!(loaded.currency, Some("USD".to_string()));
+        assert_eq!(loaded.description, Some("Trades in cents".to_string()));
+
+        assert!("unit ms\n".parse::<ChannelInfo>().is_err());
+        assert!("value_type u64\nunit ms\nprecision two\n".parse::<ChannelInfo>().is_err());
+        assert_eq!("value_type u64\nunit ns\nshape square\n".parse::<ChannelInfo>().unwrap().unit, TimeUnit::Nanoseconds);
+    }
+
+    #[test]
+    fn test_value_metadata() {
+        struct Cents;
+
+        impl ValueMetadata for Cents {
+            fn unit() -> Option<&'static str> {
+                Some("USD")
+            }
+
+            fn currency() -> Option<&'static str> {
+                Some("USD")
+            }
+
+            fn precision() -> Option<u32> {
+                Some(2)
+            }
+        }
+
+        // What the info already says wins
+        let info = ChannelInfo { precision: Some(4), ..ChannelInfo::of::<u64>(TimeUnit::Milliseconds) }.with_metadata::<Cents>();
+        assert_eq!((info.precision, info.value_unit.as_ref().map(String::as_str), info.currency.as_ref().map(String::as_str)), (Some(4), Some("USD"), Some("USD")));
+        assert_eq!(info.to_string().parse::<ChannelInfo>().unwrap(), info);
+    }
+}
@@ -0,0 +1,162 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Deprecated names from the old storage API, kept for one release while code migrates.
+//!
+//! Values used to implement a single `storage::Storable` trait for both encoding and pooling, and `FileStorage`
+//! was keyed by timestamp alone.  Encoding is now `Storable`, from `key_value_store`, and pooling is `Poolable`.
+//! Anything that still implements the old trait is adapted to both, so it can be stored and pooled as before.
+//! Using the old names warns, with a note on what replaces them.
+
+#![allow(deprecated)]
+
+use std::io;
+
+use key_value_store;
+use pooled_time_series::{Accumulator, Poolable, PoolingMethod};
+use storage;
+use time_series::Timestamp;
+
+/// A time series in a file
+#[deprecated(since = "0.2.0", note = "use `storage::FileStorage<Timestamp, V>`")]
+pub type FileStorage<V> = storage::FileStorage<Timestamp, V>;
+
+/// A value that can be stored in `S` and pooled
+#[deprecated(since = "0.2.0", note = "implement `Storable` for encoding and `Poolable` for pooling")]
+pub trait Storable<S>: Sized {
+    fn size() -> usize;
+    fn into_bytes(self) -> Vec<u8>;
+    fn from_bytes(buffer: &[u8]) -> io::Result<Self>;
+    fn mean(values: &[Self]) -> Self;
+    fn sum(values: &[Self]) -> Self;
+}
+
+impl<V> key_value_store::Storable<FileStorage<V>> for V where V: Storable<FileStorage<V>> + 'static + Copy + Default + Send + Sync {
+    fn size() -> usize {
+        <V as Storable<FileStorage<V>>>::size()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        <V as Storable<FileStorage<V>>>::into_bytes(self)
+    }
+
+    fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
+        <V as Storable<FileStorage<V>>>::from_bytes(buffer)
+    }
+}
+
+impl<V> Poolable for V where V: Storable<FileStorage<V>> + 'static + Copy + Default + Ord + Send + Sync {
+    type Accumulator = LegacyAccumulator<V>;
+
+    fn mean(values: &[Self]) -> Self {
+        <V as Storable<FileStorage<V>>>::mean(values)
+    }
+
+    fn sum(values: &[Self]) -> Self {
+        <V as Storable<FileStorage<V>>>::sum(values)
+    }
+}
+
+/// Pools values with the old trait's `mean` and `sum`, which need every value of the bucket.  The old trait had no
+/// standard deviation or VWAP, so those pool to the mean.
+pub struct LegacyAccumulator<V> {
+    pooling: PoolingMethod,
+    values: Vec<V>,
+}
+
+impl<V> Accumulator<V> for LegacyAccumulator<V> where V: Storable<FileStorage<V>> + Copy + Default {
+    fn new(pooling: PoolingMethod) -> Self {
+        Self {
+            pooling: pooling,
+            values: Vec::new(),
+        }
+    }
+
+    fn fold(&mut self, value: V, _weight: f64) {
+        self.values.push(value);
+    }
+
+    fn finalize(&self) -> V {
+        if self.values.is_empty() {
+            return V::default();
+        }
+
+        match self.pooling {
+            PoolingMethod::Sum => V::sum(&self.values),
+            _ => V::mean(&self.values),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.values.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use key_value_store::KeyValueStore;
+    use pooled_time_series::{Interval, PooledTimeSeries, PoolingOptions};
+    use time_series::TimeSeries;
+    use util::SetupFile;
+
+    /// A value written against the old API
+    #[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+    struct Cents(i64);
+
+    impl Storable<FileStorage<Cents>> for Cents {
+        fn size() -> usize {
+            8
+        }
+
+        fn into_bytes(self) -> Vec<u8> {
+            format!("{:>8}", self.0).into_bytes()
+        }
+
+        fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
+            String::from_utf8_lossy(buffer).trim().parse().map(Cents).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid data"))
+        }
+
+        fn mean(values: &[Self]) -> Self {
+            Cents(values.iter().map(|value| value.0).sum::<i64>() / values.len() as i64)
+        }
+
+        fn sum(values: &[Self]) -> Self {
+            Cents(values.iter().map(|value| value.0).sum())
+        }
+    }
+
+    #[test]
+    fn test_legacy_storable() {
+        let _setup_file = SetupFile::new("test_legacy_storable");
+
+        let mut fs = FileStorage::<Cents>::new("test_legacy_storable").unwrap();
+        fs.store(Box::new(10 as Timestamp), Box::new(Cents(100))).unwrap();
+        fs.store(Box::new(15 as Timestamp), Box::new(Cents(300))).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(Cents(50))).unwrap();
+
+        let retrieval = fs.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, Cents>(), Some(&vec![(10, Cents(100)), (15, Cents(300)), (20, Cents(50))]));
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::Sum, ..PoolingOptions::default() };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, Cents>(), Some(&vec![(10, Cents(400)), (20, Cents(50))]));
+
+        let pooling_options = PoolingOptions { pooling: PoolingMethod::Mean, ..pooling_options };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, Cents>(), Some(&vec![(10, Cents(200)), (20, Cents(50))]));
+    }
+}
@@ -0,0 +1,230 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! One cursor for paging through a query, whichever way it's served.
+//!
+//! A `Cursor` is where a client left off: how many results it has been given, the timestamp of the last one, and a
+//! fingerprint of the query they came from.  The library pages with it, the HTTP query endpoint returns it in a
+//! header, the gRPC retrieval returns it in its response, and subscriptions send one with every update, so a client
+//! can page over one transport and carry on over another.  A cursor given with a different query is refused.
+//!
+//! Cursors are opaque to clients, and are written as hexadecimal.  The range of a query isn't part of its
+//! fingerprint, so that a range relative to now can be paged through as time passes, and neither are the present and
+//! the heartbeat, which a server sets afresh for every request.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use fingerprint::Fingerprint;
+use query::Query;
+use stream::ResumeToken;
+use time_series::Timestamp;
+
+/// Where a client left off in the results of a query
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cursor {
+    /// The number of results given so far
+    pub sequence: u64,
+    /// The timestamp of the last result given
+    pub position: Option<Timestamp>,
+    /// The fingerprint of the query
+    pub query: u64,
+}
+
+impl Cursor {
+    /// A cursor of `query` at `token`
+    pub fn new(query: &Query, token: ResumeToken) -> Self {
+        Self {
+            sequence: token.sequence,
+            position: token.position,
+            query: fingerprint(query),
+        }
+    }
+
+    /// Where the cursor is, once it's been checked against the query it's given with
+    pub fn token(&self, query: &Query) -> io::Result<ResumeToken> {
+        if self.query != fingerprint(query) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cursor is for a different query"));
+        }
+
+        Ok(ResumeToken { sequence: self.sequence, position: self.position })
+    }
+
+    /// Parses where to resume `query` from, given either a cursor or a resume token from before cursors
+    pub fn resume(text: &str, query: &Query) -> io::Result<ResumeToken> {
+        match text.parse::<Cursor>() {
+            Ok(cursor) => cursor.token(query),
+            Err(_) => text.parse::<ResumeToken>(),
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}{:016x}", self.query, self.sequence)?;
+        match self.position {
+            Some(position) => write!(f, "{:016x}", position),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Invalid cursor");
+
+        if (text.len() != 32 && text.len() != 48) || !text.is_ascii() {
+            return Err(invalid());
+        }
+
+        let field = |i: usize| u64::from_str_radix(&text[i * 16..(i + 1) * 16], 16).map_err(|_| invalid());
+
+        Ok(Cursor {
+            query: field(0)?,
+            sequence: field(1)?,
+            position: if text.len() == 48 { Some(field(2)?) } else { None },
+        })
+    }
+}
+
+/// A page of the results of a query, with the cursor of the next page if there are more
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page<V> {
+    pub records: Vec<(Timestamp, V)>,
+    pub next: Option<Cursor>,
+}
+
+/// The fingerprint of the fields of a query that decide which results it gives.  New fields of `Query` have to be added
+/// here if they do.
+fn fingerprint(query: &Query) -> u64 {
+    let fields = format!(
+        "source {:?}\ninterval {:?}\npooling {:?}\ngap_fill {:?}\nfield_pooling {:?}\nanchor {:?}\nopen_bucket {:?}\ncalendar {:?}\nholidays {:?}\nunit {:?}\ntransform {:?}\nindicators {:?}\nbands {:?}\n",
+        query.source,
+        query.interval,
+        query.pooling,
+        query.gap_fill,
+        query.field_pooling,
+        query.anchor,
+        query.open_bucket,
+        query.calendar,
+        query.holidays,
+        query.unit,
+        query.transform,
+        query.indicators,
+        query.bands,
+    );
+
+    let hash = Fingerprint::of_reader(fields.as_bytes()).expect("Reading from memory can't fail").hash;
+    hash[..8].iter().fold(0, |fingerprint, &byte| fingerprint << 8 | byte as u64)
+}
+
+/// Takes the page of `records` that follows `token`.  `keyed` is whether the records were already retrieved from
+/// after the token's position.
+pub(crate) fn paginate<V>(query: &Query, mut records: Vec<(Timestamp, V)>, size: usize, token: ResumeToken, keyed: bool) -> Page<V> {
+    if !keyed {
+        // Transforms can reorder records, so their results are paged by count
+        let skip = if query.transform.is_empty() {
+            token.position.map_or(0, |position| records.iter().take_while(|record| record.0 <= position).count())
+        } else {
+            token.sequence as usize
+        };
+        records.drain(..skip.min(records.len()));
+    }
+
+    let more = records.len() > size;
+    records.truncate(size);
+
+    let next = match records.last() {
+        Some(last) if more => Some(Cursor::new(query, ResumeToken { sequence: token.sequence + records.len() as u64, position: Some(last.0) })),
+        _ => None,
+    };
+
+    Page {
+        records: records,
+        next: next,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use key_value_store::KeyValueStore;
+    use pooled_time_series::PoolingMethod;
+    use query::Transform;
+    use storage::FileStorage;
+    use util::SetupFile;
+
+    #[test]
+    fn test_cursor() {
+        let query = Query::new("m/s/c").from(10);
+        let cursor = Cursor::new(&query, ResumeToken { sequence: 3, position: Some(1546398245678) });
+
+        assert_eq!(cursor.to_string().parse::<Cursor>().unwrap(), cursor);
+        assert_eq!(Cursor::new(&query, ResumeToken::default()).to_string().len(), 32);
+
+        // The range can move, but the rest of the query can't change
+        assert_eq!(cursor.token(&query.clone().from(20)).unwrap(), ResumeToken { sequence: 3, position: Some(1546398245678) });
+        assert!(cursor.token(&query.clone().interval(10)).is_err());
+
+        // So can the present and the heartbeat, which are set for every request
+        assert!(cursor.token(&query.clone().now(1001).heartbeat(900)).is_ok());
+
+        assert_eq!(Cursor::resume(&cursor.to_string(), &query).unwrap().sequence, 3);
+        assert_eq!(Cursor::resume("12.20", &query).unwrap(), ResumeToken { sequence: 12, position: Some(20) });
+        assert!("12.20".parse::<Cursor>().is_err());
+    }
+
+    #[test]
+    fn test_page() {
+        let _setup_file = SetupFile::new("test_page");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_page").unwrap();
+        for i in 1..8 {
+            fs.store(Box::new(i * 10 as Timestamp), Box::new(i as i32)).unwrap();
+        }
+
+        let query = Query::new("m/s/c");
+        let first = query.page::<i32>(&fs, 3, None).unwrap();
+        assert_eq!(first.records, vec![(10, 1), (20, 2), (30, 3)]);
+
+        let second = query.page::<i32>(&fs, 3, first.next.as_ref()).unwrap();
+        assert_eq!(second.records, vec![(40, 4), (50, 5), (60, 6)]);
+
+        let last = query.page::<i32>(&fs, 3, second.next.as_ref()).unwrap();
+        assert_eq!(last.records, vec![(70, 7)]);
+        assert_eq!(last.next, None);
+
+        // A server sets the present for every request, and paging carries on across them
+        let first = query.clone().now(1000).page::<i32>(&fs, 3, None).unwrap();
+        assert_eq!(query.clone().now(1001).page::<i32>(&fs, 3, first.next.as_ref()).unwrap().records, vec![(40, 4), (50, 5), (60, 6)]);
+
+        // Reordered results are paged by count
+        let reversed = Query::new("m/s/c").transform(Transform::Reverse);
+        let first = reversed.page::<i32>(&fs, 4, None).unwrap();
+        assert_eq!(reversed.page::<i32>(&fs, 4, first.next.as_ref()).unwrap().records, vec![(30, 3), (20, 2), (10, 1)]);
+
+        let pooled = Query::new("m/s/c").interval(20).pooling(PoolingMethod::Sum);
+        let first = pooled.page_pooled::<i32>(&fs, 2, None).unwrap();
+        assert_eq!(first.records, vec![(10, 3), (30, 7)]);
+        assert_eq!(pooled.page_pooled::<i32>(&fs, 2, first.next.as_ref()).unwrap().records, vec![(50, 11), (70, 7)]);
+
+        assert!(query.page::<i32>(&fs, 3, first.next.as_ref()).is_err());
+        assert!(query.page::<i32>(&fs, 0, None).is_err());
+    }
+}
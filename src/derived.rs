@@ -0,0 +1,505 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::io;
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+
+use key_value_store::{Data, KeyValueStore, Retrieval};
+use pooled_time_series::{PooledTimeSeries, PoolingOptions};
+use query::Query;
+use schema::Quote;
+use storage::Bounded;
+use time_series::{RetrievalDirection, TimeSeries, Timestamp};
+
+/// Somewhere a derived channel can read its source records from
+pub trait DerivedSource: Send + Sync {
+    /// Performs the retrieval described by the query, ignoring its transforms.
+    fn query(&self, query: &Query) -> io::Result<Retrieval>;
+}
+
+impl<T> DerivedSource for Arc<RwLock<T>> where T: PooledTimeSeries {
+    fn query(&self, query: &Query) -> io::Result<Retrieval> {
+        let source = self.read().map_err(|_| io::Error::new(io::ErrorKind::Other, "Derived channel source was poisoned"))?;
+        query.retrieve_pooled(&*source)
+    }
+}
+
+/// How the latest values of several sources, usually the same symbol on different markets, are reduced to one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Consolidation {
+    /// The weighted median, which is always one of the values
+    Median,
+    /// The weighted mean
+    Mean,
+    /// The highest value, e.g. the best bid
+    High,
+    /// The lowest value, e.g. the best ask
+    Low,
+    /// The total, e.g. the depth of several levels of a book
+    Sum,
+}
+
+impl Consolidation {
+    /// Reduces a non-empty list of values with their weights.  High, low, and sum ignore the weights.
+    fn reduce<V>(&self, values: &mut [(V, f64)]) -> V where V: Bounded {
+        let by_value = |a: &(V, f64), b: &(V, f64)| a.0.to_f64().partial_cmp(&b.0.to_f64()).unwrap_or(Ordering::Equal);
+
+        match *self {
+            Consolidation::Median => {
+                values.sort_by(by_value);
+                let half = values.iter().map(|v| v.1).sum::<f64>() / 2.0;
+
+                let mut weight = 0.0;
+                for &(value, value_weight) in values.iter() {
+                    weight += value_weight;
+                    if weight >= half {
+                        return value;
+                    }
+                }
+                values[values.len() - 1].0
+            },
+            Consolidation::Mean => {
+                let total_weight = values.iter().map(|v| v.1).sum::<f64>();
+                if total_weight == 0.0 {
+                    V::from_f64(values.iter().map(|v| v.0.to_f64()).sum::<f64>() / values.len() as f64)
+                } else {
+                    V::from_f64(values.iter().map(|v| v.0.to_f64() * v.1).sum::<f64>() / total_weight)
+                }
+            },
+            Consolidation::High => values.iter().skip(1).fold(values[0].0, |high, v| if v.0.to_f64() > high.to_f64() { v.0 } else { high }),
+            Consolidation::Low => values.iter().skip(1).fold(values[0].0, |low, v| if v.0.to_f64() < low.to_f64() { v.0 } else { low }),
+            Consolidation::Sum => V::from_f64(values.iter().map(|v| v.0.to_f64()).sum()),
+        }
+    }
+}
+
+/// How the two legs of a cross rate are combined
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CrossRate {
+    /// The first leg times the second, e.g. BTC/EUR from BTC/USD and USD/EUR
+    Product,
+    /// The first leg over the second, e.g. BTC/EUR from BTC/USD and EUR/USD
+    Ratio,
+}
+
+type Derivation<V> = Box<dyn Fn(Vec<Retrieval>) -> io::Result<Vec<(Timestamp, V)>> + Send + Sync>;
+
+/// A read-only channel computed from other channels.
+///
+/// Nothing is stored.  Every retrieval or pooling is passed along to the sources, and their results are
+/// combined when the call is made.  Pooling a derived channel pools each source with the same options and
+/// then combines the buckets.
+pub struct DerivedChannel<V> {
+    sources: Vec<Box<dyn DerivedSource>>,
+    derivation: Derivation<V>,
+}
+
+impl<V> DerivedChannel<V> where V: 'static + Copy + Send + Sync {
+    /// Derives each record from a single source record.
+    pub fn map<A, F>(source: Box<dyn DerivedSource>, function: F) -> Self where A: 'static + Copy, F: 'static + Fn(A) -> V + Send + Sync {
+        Self {
+            sources: vec![source],
+            derivation: Box::new(move |mut retrievals: Vec<Retrieval>| {
+                let records = downcast::<A>(retrievals.remove(0))?;
+                Ok(records.into_iter().map(|(timestamp, value)| (timestamp, function(value))).collect())
+            }),
+        }
+    }
+
+    /// Derives records from two sources.  Every time either source has a record, the latest values of
+    /// both are combined, once both sources have produced a value.
+    pub fn combine<A, B, F>(a: Box<dyn DerivedSource>, b: Box<dyn DerivedSource>, function: F) -> Self where A: 'static + Copy, B: 'static + Copy, F: 'static + Fn(A, B) -> V + Send + Sync {
+        Self {
+            sources: vec![a, b],
+            derivation: Box::new(move |mut retrievals: Vec<Retrieval>| {
+                let b = downcast::<B>(retrievals.remove(1))?;
+                let a = downcast::<A>(retrievals.remove(0))?;
+                Ok(join_latest(&a, &b, None).into_iter().map(|(timestamp, a, b)| (timestamp, function(a, b))).collect())
+            }),
+        }
+    }
+
+    /// Consolidates any number of weighted sources.  Every time a source has a record, the latest values of every
+    /// source that has produced one so far are reduced to a single value.
+    pub fn consolidate(sources: Vec<(Box<dyn DerivedSource>, f64)>, consolidation: Consolidation) -> Self where V: Bounded {
+        let weights = sources.iter().map(|source| source.1).collect::<Vec<f64>>();
+
+        Self {
+            sources: sources.into_iter().map(|source| source.0).collect(),
+            derivation: Box::new(move |retrievals: Vec<Retrieval>| {
+                let lists = retrievals.into_iter().map(downcast::<V>).collect::<io::Result<Vec<_>>>()?;
+                Ok(consolidate_latest(&lists, &weights, consolidation))
+            }),
+        }
+    }
+
+    /// Prices an asset in a currency it doesn't trade in through a third asset, from the latest values of two legs.
+    /// The result is multiplied by `scale`, to bring fixed-point legs back to the precision of the first.  A leg
+    /// whose latest value is more than `max_age` old is stale, and nothing is derived from it until it has a newer
+    /// value; a ratio over a zero leg is left out too.
+    pub fn cross_rate(a: Box<dyn DerivedSource>, b: Box<dyn DerivedSource>, cross_rate: CrossRate, scale: f64, max_age: Option<Timestamp>) -> Self where V: Bounded {
+        Self {
+            sources: vec![a, b],
+            derivation: Box::new(move |mut retrievals: Vec<Retrieval>| {
+                let b = downcast::<V>(retrievals.remove(1))?;
+                let a = downcast::<V>(retrievals.remove(0))?;
+
+                Ok(join_latest(&a, &b, max_age).into_iter()
+                    .filter_map(|(timestamp, a, b)| {
+                        let rate = match cross_rate {
+                            CrossRate::Product => a.to_f64() * b.to_f64(),
+                            CrossRate::Ratio if b.to_f64() == 0.0 => return None,
+                            CrossRate::Ratio => a.to_f64() / b.to_f64(),
+                        };
+                        Some((timestamp, V::from_f64(rate * scale)))
+                    })
+                    .collect())
+            }),
+        }
+    }
+
+    /// The spread of a quote channel.  The source is pooled before the spread is taken, so a mean spread is exact,
+    /// but a high spread is the high ask less the high bid, not the widest the book got.
+    pub fn spread(source: Box<dyn DerivedSource>) -> Self where V: Bounded {
+        Self::map::<Quote<V>, _>(source, |quote| quote.spread())
+    }
+
+    /// The midprice of a quote channel, pooled the same way as `spread`
+    pub fn midprice(source: Box<dyn DerivedSource>) -> Self where V: Bounded {
+        Self::map::<Quote<V>, _>(source, |quote| quote.midprice())
+    }
+
+    fn derive(&self, query: &Query) -> io::Result<Vec<(Timestamp, V)>> {
+        let retrievals = self.sources.iter().map(|source| source.query(query)).collect::<io::Result<Vec<Retrieval>>>()?;
+        (self.derivation)(retrievals)
+    }
+}
+
+impl<A> DerivedChannel<(A, u32)> where A: 'static + Copy + Send + Sync {
+    /// Merges the records of several sources, such as the trades of one instrument on different venues, into one
+    /// time-ordered tape.  Each record is tagged with the index of its source.  Records at the same time are kept in
+    /// source order.
+    pub fn tape(sources: Vec<Box<dyn DerivedSource>>) -> Self {
+        Self {
+            sources: sources,
+            derivation: Box::new(|retrievals: Vec<Retrieval>| {
+                let lists = retrievals.into_iter().map(downcast::<A>).collect::<io::Result<Vec<_>>>()?;
+
+                let mut tape = lists.iter().enumerate()
+                    .flat_map(|(venue, list)| list.iter().map(move |&(timestamp, value)| (timestamp, (value, venue as u32))))
+                    .collect::<Vec<(Timestamp, (A, u32))>>();
+
+                // The sort is stable, so ties stay in source order
+                tape.sort_by_key(|record| record.0);
+                Ok(tape)
+            }),
+        }
+    }
+}
+
+fn downcast<A>(retrieval: Retrieval) -> io::Result<Vec<(Timestamp, A)>> where A: 'static {
+    retrieval.try_into_vec::<Timestamp, A>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Derived channel source has the wrong value type"))
+}
+
+/// Walks two sorted record lists together, pairing the latest value of each at every timestamp.  With a `max_age`, a
+/// value older than that isn't paired.
+fn join_latest<A, B>(a: &[(Timestamp, A)], b: &[(Timestamp, B)], max_age: Option<Timestamp>) -> Vec<(Timestamp, A, B)> where A: Copy, B: Copy {
+    let mut joined = Vec::with_capacity(a.len().max(b.len()));
+
+    let (mut i, mut j) = (0, 0);
+    let (mut latest_a, mut latest_b) = (None, None);
+
+    while i < a.len() || j < b.len() {
+        let next_a = a.get(i).map(|r| r.0);
+        let next_b = b.get(j).map(|r| r.0);
+
+        let timestamp = match (next_a, next_b) {
+            (Some(x), Some(y)) => x.min(y),
+            (Some(x), None) => x,
+            (None, Some(y)) => y,
+            (None, None) => break,
+        };
+
+        if next_a == Some(timestamp) {
+            latest_a = Some(a[i]);
+            i += 1;
+        }
+        if next_b == Some(timestamp) {
+            latest_b = Some(b[j]);
+            j += 1;
+        }
+
+        if let (Some((at_a, value_a)), Some((at_b, value_b))) = (latest_a, latest_b) {
+            if max_age.map_or(true, |max_age| timestamp - at_a.min(at_b) <= max_age) {
+                joined.push((timestamp, value_a, value_b));
+            }
+        }
+    }
+
+    joined
+}
+
+/// Walks any number of sorted record lists together, consolidating the latest value of each at every timestamp
+fn consolidate_latest<V>(lists: &[Vec<(Timestamp, V)>], weights: &[f64], consolidation: Consolidation) -> Vec<(Timestamp, V)> where V: Bounded {
+    let mut consolidated = Vec::with_capacity(lists.iter().map(|list| list.len()).max().unwrap_or(0));
+
+    let mut positions = vec![0; lists.len()];
+    let mut latest = vec![None; lists.len()];
+    let mut values = Vec::with_capacity(lists.len());
+
+    while let Some(timestamp) = lists.iter().zip(&positions).filter_map(|(list, &i)| list.get(i).map(|r| r.0)).min() {
+        for (i, list) in lists.iter().enumerate() {
+            if let Some(&(next, value)) = list.get(positions[i]) {
+                if next == timestamp {
+                    latest[i] = Some(value);
+                    positions[i] += 1;
+                }
+            }
+        }
+
+        values.clear();
+        values.extend(latest.iter().zip(weights).filter_map(|(value, &weight)| value.map(|value| (value, weight))));
+        consolidated.push((timestamp, consolidation.reduce(&mut values)));
+    }
+
+    consolidated
+}
+
+impl<V> KeyValueStore for DerivedChannel<V> where V: 'static + Copy + Send + Sync {
+    /// Derived channels have to be computed to be counted, so this is as expensive as `retrieve_all`.
+    fn len(&self) -> usize {
+        self.derive(&Query::new("")).map(|records| records.len()).unwrap_or(0)
+    }
+
+    /// Like `len`, this computes the whole channel.
+    fn last_key(&self) -> Option<Box<Data>> {
+        self.derive(&Query::new("")).ok()
+            .and_then(|records| records.last().map(|&(timestamp, _)| Box::new(timestamp) as Box<Data>))
+    }
+
+    fn store(&mut self, _key: Box<Data>, _value: Box<Data>) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "Derived channels are read-only"))
+    }
+}
+
+impl<V> TimeSeries for DerivedChannel<V> where V: 'static + Copy + Send + Sync {
+    fn retrieve_nearest(&self, timestamp: Timestamp, retrieval_direction: Option<RetrievalDirection>) -> io::Result<Retrieval> {
+        let record = match retrieval_direction {
+            Some(RetrievalDirection::Forward) => self.derive(&Query::new("").from(timestamp))?.first().cloned(),
+            Some(RetrievalDirection::Backward) => self.derive(&Query::new("").to(timestamp.saturating_add(1)))?.last().cloned(),
+            None => self.derive(&Query::new("").from(timestamp).to(timestamp.saturating_add(1)))?.first().cloned(),
+        };
+
+        match record {
+            Some(record) => Ok(Retrieval::new(Box::new(record))),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "Search key was not found")),
+        }
+    }
+
+    fn retrieve_all(&self) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.derive(&Query::new(""))?)))
+    }
+
+    fn retrieve_from(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.derive(&Query::new("").from(timestamp))?)))
+    }
+
+    fn retrieve_to(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.derive(&Query::new("").to(timestamp))?)))
+    }
+
+    fn retrieve_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.derive(&Query::new("").from(range.start).to(range.end))?)))
+    }
+
+    fn find_gaps(&self, min_gap: Timestamp, range: Range<Timestamp>) -> io::Result<Vec<Range<Timestamp>>> {
+        let records = self.derive(&Query::new("").from(range.start).to(range.end))?;
+
+        // Treat the ends of the range as records so that leading and trailing holes are found
+        let mut boundaries = vec![range.start];
+        boundaries.extend(records.iter().map(|r| r.0));
+        boundaries.push(range.end);
+
+        Ok(boundaries.windows(2)
+            .filter(|w| w[1] > w[0] && w[1] - w[0] >= min_gap)
+            .map(|w| w[0]..w[1])
+            .collect())
+    }
+}
+
+impl<V> PooledTimeSeries for DerivedChannel<V> where V: 'static + Copy + Send + Sync {
+    fn pool_all(&self, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.derive(&Query::new("").with_pooling_options(pooling_options))?)))
+    }
+
+    fn pool_from(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.derive(&Query::new("").from(timestamp).with_pooling_options(pooling_options))?)))
+    }
+
+    fn pool_to(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.derive(&Query::new("").to(timestamp).with_pooling_options(pooling_options))?)))
+    }
+
+    fn pool_range(&self, range: Range<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.derive(&Query::new("").from(range.start).to(range.end).with_pooling_options(pooling_options))?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pooled_time_series::{Interval, PoolingMethod};
+    use storage::FileStorage;
+    use util::SetupFile;
+
+    fn shared(filename: &str, records: &[(Timestamp, i32)]) -> Arc<RwLock<FileStorage<Timestamp, i32>>> {
+        let mut fs = FileStorage::<Timestamp, i32>::new(filename).unwrap();
+
+        for &(timestamp, value) in records {
+            fs.store(Box::new(timestamp), Box::new(value)).unwrap();
+        }
+
+        Arc::new(RwLock::new(fs))
+    }
+
+    #[test]
+    fn test_derived_channel_combine() {
+        let _bid_file = SetupFile::new("test_derived_channel_combine_bid");
+        let _ask_file = SetupFile::new("test_derived_channel_combine_ask");
+
+        let bid = shared("test_derived_channel_combine_bid", &[(10, 100), (20, 101), (30, 99)]);
+        let ask = shared("test_derived_channel_combine_ask", &[(10, 104), (20, 103), (35, 102)]);
+
+        let spread = DerivedChannel::combine::<i32, i32, _>(Box::new(bid), Box::new(ask), |bid, ask| ask - bid);
+
+        let retrieval = spread.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 4), (20, 2), (30, 4), (35, 3)]));
+
+        let retrieval = spread.retrieve_nearest(32, Some(RetrievalDirection::Backward)).unwrap();
+        assert_eq!(retrieval.as_single::<Timestamp, i32>(), Some(&(30, 4)));
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::End, ..PoolingOptions::default() };
+        let retrieval = spread.pool_range(10..40, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 4), (20, 2), (30, 3)]));
+
+        assert_eq!(spread.find_gaps(11, 0..50).unwrap(), vec![35..50]);
+    }
+
+    #[test]
+    fn test_derived_channel_cross_rate() {
+        let _a_file = SetupFile::new("test_derived_channel_cross_rate_a");
+        let _b_file = SetupFile::new("test_derived_channel_cross_rate_b");
+
+        // Prices in hundredths, so the product is scaled back down by a hundred
+        let btcusd = shared("test_derived_channel_cross_rate_a", &[(10, 5000), (20, 5100), (60, 5200)]);
+        let usdeur = shared("test_derived_channel_cross_rate_b", &[(10, 90), (25, 0), (30, 80)]);
+
+        let btceur = DerivedChannel::<i32>::cross_rate(Box::new(btcusd.clone()), Box::new(usdeur.clone()), CrossRate::Product, 0.01, None);
+        let retrieval = btceur.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 4500), (20, 4590), (25, 0), (30, 4080), (60, 4160)]));
+
+        // The second leg is stale by 60, and the ratio over its zero is left out
+        let ratio = DerivedChannel::<i32>::cross_rate(Box::new(btcusd), Box::new(usdeur), CrossRate::Ratio, 100.0, Some(20));
+        let retrieval = ratio.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 5556), (20, 5667), (30, 6375)]));
+    }
+
+    #[test]
+    fn test_derived_channel_quote() {
+        let _setup_file = SetupFile::new("test_derived_channel_quote");
+
+        let mut fs = FileStorage::<Timestamp, Quote<i32>>::new("test_derived_channel_quote").unwrap();
+        fs.store(Box::new(10 as Timestamp), Box::new(Quote::new(100, 104))).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(Quote::new(101, 103))).unwrap();
+        let quotes = Arc::new(RwLock::new(fs));
+
+        let spread = DerivedChannel::<i32>::spread(Box::new(quotes.clone()));
+        let retrieval = spread.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 4), (20, 2)]));
+
+        let midprice = DerivedChannel::<i32>::midprice(Box::new(quotes));
+        let retrieval = midprice.retrieve_from(15).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(20, 102)]));
+    }
+
+    #[test]
+    fn test_derived_channel_consolidate() {
+        let _a_file = SetupFile::new("test_derived_channel_consolidate_a");
+        let _b_file = SetupFile::new("test_derived_channel_consolidate_b");
+        let _c_file = SetupFile::new("test_derived_channel_consolidate_c");
+
+        drop(shared("test_derived_channel_consolidate_a", &[(10, 100), (30, 104)]));
+        drop(shared("test_derived_channel_consolidate_b", &[(20, 102)]));
+        drop(shared("test_derived_channel_consolidate_c", &[(20, 110)]));
+
+        // Each consolidation reads the files through its own handles, which can share them since they only read
+        let source = |filename: &str| Box::new(Arc::new(RwLock::new(FileStorage::<Timestamp, i32>::read_only(filename).unwrap()))) as Box<dyn DerivedSource>;
+        let sources = || vec![
+            (source("test_derived_channel_consolidate_a"), 1.0),
+            (source("test_derived_channel_consolidate_b"), 1.0),
+            (source("test_derived_channel_consolidate_c"), 3.0),
+        ];
+
+        let median = DerivedChannel::<i32>::consolidate(sources(), Consolidation::Median);
+        let retrieval = median.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 100), (20, 110), (30, 110)]));
+
+        let mean = DerivedChannel::<i32>::consolidate(sources(), Consolidation::Mean);
+        let retrieval = mean.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 100), (20, 106), (30, 107)]));
+
+        let low = DerivedChannel::<i32>::consolidate(sources(), Consolidation::Low);
+        let retrieval = low.retrieve_range(15..40).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(20, 102), (30, 102)]));
+
+        let sum = DerivedChannel::<i32>::consolidate(sources(), Consolidation::Sum);
+        let retrieval = sum.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 100), (20, 312), (30, 316)]));
+    }
+
+    #[test]
+    fn test_derived_channel_tape() {
+        let _a_file = SetupFile::new("test_derived_channel_tape_a");
+        let _b_file = SetupFile::new("test_derived_channel_tape_b");
+
+        let a = shared("test_derived_channel_tape_a", &[(10, 1), (20, 2), (30, 3)]);
+        let b = shared("test_derived_channel_tape_b", &[(15, 4), (20, 5)]);
+
+        let tape = DerivedChannel::<(i32, u32)>::tape(vec![Box::new(a), Box::new(b)]);
+
+        let retrieval = tape.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, (i32, u32)>(), Some(&vec![(10, (1, 0)), (15, (4, 1)), (20, (2, 0)), (20, (5, 1)), (30, (3, 0))]));
+
+        let retrieval = tape.retrieve_range(15..25).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, (i32, u32)>(), Some(&vec![(15, (4, 1)), (20, (2, 0)), (20, (5, 1))]));
+    }
+
+    #[test]
+    fn test_derived_channel_is_read_only() {
+        let _setup_file = SetupFile::new("test_derived_channel_is_read_only");
+
+        let source = shared("test_derived_channel_is_read_only", &[(10, 1), (20, 2)]);
+        let mut doubled = DerivedChannel::map::<i32, _>(Box::new(source), |value| value * 2);
+
+        let retrieval = doubled.retrieve_from(15).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(20, 4)]));
+
+        assert!(doubled.store(Box::new(30 as Timestamp), Box::new(3 as i32)).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+
+use time_series::Timestamp;
+
+/// A way in which one series of records differs from another
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Difference<V> {
+    /// A record of the first series that the second doesn't have
+    Missing(Timestamp, V),
+    /// A record of the second series that the first doesn't have
+    Extra(Timestamp, V),
+    /// A timestamp whose value differs, with the first series' value then the second's
+    Mismatch(Timestamp, V, V),
+}
+
+impl<V> Difference<V> {
+    pub fn timestamp(&self) -> Timestamp {
+        match *self {
+            Difference::Missing(timestamp, _) | Difference::Extra(timestamp, _) | Difference::Mismatch(timestamp, _, _) => timestamp,
+        }
+    }
+}
+
+/// Aligns two series of records by timestamp and lists their differences in time order.  Both series must be in
+/// time order, as retrieved.
+pub fn diff_records<V>(a: &[(Timestamp, V)], b: &[(Timestamp, V)]) -> Vec<Difference<V>> where V: Copy + PartialEq {
+    let mut differences = Vec::new();
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+
+    loop {
+        let ordering = match (a.peek(), b.peek()) {
+            (Some(&&(a_key, _)), Some(&&(b_key, _))) => a_key.cmp(&b_key),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+
+        match ordering {
+            Ordering::Less => {
+                let &(key, value) = a.next().unwrap();
+                differences.push(Difference::Missing(key, value));
+            },
+            Ordering::Greater => {
+                let &(key, value) = b.next().unwrap();
+                differences.push(Difference::Extra(key, value));
+            },
+            Ordering::Equal => {
+                let (&(key, a_value), &(_, b_value)) = (a.next().unwrap(), b.next().unwrap());
+                if a_value != b_value {
+                    differences.push(Difference::Mismatch(key, a_value, b_value));
+                }
+            },
+        }
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_records() {
+        let a = [(1, 10), (2, 20), (3, 30), (5, 50)];
+        let b = [(2, 20), (3, 31), (4, 40), (5, 50), (6, 60)];
+
+        assert_eq!(diff_records(&a, &b), vec![
+            Difference::Missing(1, 10),
+            Difference::Mismatch(3, 30, 31),
+            Difference::Extra(4, 40),
+            Difference::Extra(6, 60),
+        ]);
+
+        assert_eq!(diff_records(&a, &a), vec![]);
+        assert_eq!(diff_records(&a[..0], &a[..2]), vec![Difference::Extra(1, 10), Difference::Extra(2, 20)]);
+        assert_eq!(diff_records(&a, &b).iter().map(Difference::timestamp).collect::<Vec<_>>(), vec![1, 3, 4, 6]);
+    }
+}
@@ -0,0 +1,255 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Values whose type is only known at run time.
+//!
+//! Stores take and give their records as `Box<dyn Any>`, so naming the wrong value type only shows up as a failed
+//! downcast.  A `ValueType` names a store's value type as data, e.g. from a channel's `ChannelInfo`, and a `Value`
+//! carries a value along with its type, so records can be stored, retrieved, and written out without the concrete
+//! type being known where they're handled.  Values of the wrong type are refused with an error that names both
+//! types.
+
+use std::fmt;
+use std::io;
+
+use key_value_store::{Data, KeyValueStore, Retrieval};
+use sealed::Sealed;
+use time_series::Timestamp;
+
+/// The type of a store's values
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ValueType {
+    I32,
+    I64,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl ValueType {
+    pub fn of<V: Typed>() -> Self {
+        V::VALUE_TYPE
+    }
+
+    /// The name of the Rust type, as `any::type_name` gives it
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+            ValueType::U32 => "u32",
+            ValueType::U64 => "u64",
+            ValueType::F32 => "f32",
+            ValueType::F64 => "f64",
+        }
+    }
+
+    /// The value type named by `name`, e.g. the `value_type` of a `ChannelInfo`, or `None` if it isn't one
+    pub fn from_name(name: &str) -> Option<Self> {
+        [ValueType::I32, ValueType::I64, ValueType::U32, ValueType::U64, ValueType::F32, ValueType::F64].iter()
+            .cloned()
+            .find(|value_type| value_type.name() == name)
+    }
+
+    /// Parses a value of this type from text
+    pub fn parse(&self, text: &str) -> io::Result<Value> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not a {} value", text, self.name()));
+
+        match *self {
+            ValueType::I32 => text.parse().map(Value::I32).map_err(|_| invalid()),
+            ValueType::I64 => text.parse().map(Value::I64).map_err(|_| invalid()),
+            ValueType::U32 => text.parse().map(Value::U32).map_err(|_| invalid()),
+            ValueType::U64 => text.parse().map(Value::U64).map_err(|_| invalid()),
+            ValueType::F32 => text.parse().map(Value::F32).map_err(|_| invalid()),
+            ValueType::F64 => text.parse().map(Value::F64).map_err(|_| invalid()),
+        }
+    }
+
+    /// Stores a record in a store of values of this type.  Fails with `InvalidInput` if the value is of another
+    /// type.
+    pub fn store(&self, store: &mut dyn KeyValueStore, timestamp: Timestamp, value: Value) -> io::Result<()> {
+        if value.value_type() != *self {
+            return Err(mismatch(value.value_type(), *self));
+        }
+
+        store.store(Box::new(timestamp), value.into_data())
+    }
+
+    /// Takes the records of a retrieval of values of this type.  Fails with `InvalidData` if it holds anything else.
+    pub fn records(&self, retrieval: Retrieval) -> io::Result<Vec<(Timestamp, Value)>> {
+        match *self {
+            ValueType::I32 => records::<i32>(retrieval),
+            ValueType::I64 => records::<i64>(retrieval),
+            ValueType::U32 => records::<u32>(retrieval),
+            ValueType::U64 => records::<u64>(retrieval),
+            ValueType::F32 => records::<f32>(retrieval),
+            ValueType::F64 => records::<f64>(retrieval),
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// A value, along with its type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match *self {
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::U32(_) => ValueType::U32,
+            Value::U64(_) => ValueType::U64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+        }
+    }
+
+    /// The value as `V`, or `None` if it's of another type
+    pub fn get<V: Typed>(&self) -> Option<V> {
+        V::from_value(*self)
+    }
+
+    /// The value boxed as its own type, as stores take it
+    pub fn into_data(self) -> Box<Data> {
+        match self {
+            Value::I32(value) => Box::new(value),
+            Value::I64(value) => Box::new(value),
+            Value::U32(value) => Box::new(value),
+            Value::U64(value) => Box::new(value),
+            Value::F32(value) => Box::new(value),
+            Value::F64(value) => Box::new(value),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match *self {
+            Value::I32(value) => value as f64,
+            Value::I64(value) => value as f64,
+            Value::U32(value) => value as f64,
+            Value::U64(value) => value as f64,
+            Value::F32(value) => value as f64,
+            Value::F64(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::I32(value) => value.fmt(f),
+            Value::I64(value) => value.fmt(f),
+            Value::U32(value) => value.fmt(f),
+            Value::U64(value) => value.fmt(f),
+            Value::F32(value) => value.fmt(f),
+            Value::F64(value) => value.fmt(f),
+        }
+    }
+}
+
+/// A Rust type that values can be of.  Sealed, so that every `ValueType` has exactly one.
+pub trait Typed: 'static + Copy + Sealed {
+    const VALUE_TYPE: ValueType;
+
+    fn into_value(self) -> Value;
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+macro_rules! typed {
+    ($type:ty, $variant:ident) => {
+        impl Typed for $type {
+            const VALUE_TYPE: ValueType = ValueType::$variant;
+
+            fn into_value(self) -> Value {
+                Value::$variant(self)
+            }
+
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    Value::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+typed!(i32, I32);
+typed!(i64, I64);
+typed!(u32, U32);
+typed!(u64, U64);
+typed!(f32, F32);
+typed!(f64, F64);
+
+fn records<V: Typed>(retrieval: Retrieval) -> io::Result<Vec<(Timestamp, Value)>> {
+    match retrieval.try_into_vec::<Timestamp, V>() {
+        Ok(records) => Ok(records.into_iter().map(|(timestamp, value)| (timestamp, value.into_value())).collect()),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Retrieval doesn't hold {} values", V::VALUE_TYPE))),
+    }
+}
+
+fn mismatch(given: ValueType, expected: ValueType) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Got a {} value for a store of {} values", given, expected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::any;
+
+    use query::Query;
+    use storage::FileStorage;
+    use util::SetupFile;
+
+    #[test]
+    fn test_value_type() {
+        assert_eq!(ValueType::from_name(any::type_name::<u64>()), Some(ValueType::U64));
+        assert_eq!(ValueType::from_name("Trade"), None);
+        assert_eq!(ValueType::of::<f64>().to_string(), "f64");
+
+        assert_eq!(ValueType::I32.parse("-12").unwrap(), Value::I32(-12));
+        assert!(ValueType::U64.parse("-12").is_err());
+        assert_eq!(Value::U64(7).get::<u64>(), Some(7));
+        assert_eq!(Value::U64(7).get::<i64>(), None);
+    }
+
+    #[test]
+    fn test_store_values() {
+        let _setup_file = SetupFile::new("test_store_values");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_store_values").unwrap();
+        let value_type = ValueType::from_name(any::type_name::<i32>()).unwrap();
+
+        value_type.store(&mut fs, 10, Value::I32(5)).unwrap();
+        value_type.store(&mut fs, 20, Value::I32(-3)).unwrap();
+        assert_eq!(value_type.store(&mut fs, 30, Value::U64(1)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        assert_eq!(Query::new("m/s/c").evaluate_values(&fs, value_type).unwrap(), vec![(10, Value::I32(5)), (20, Value::I32(-3))]);
+        assert_eq!(Query::new("m/s/c").evaluate_values(&fs, ValueType::U64).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,344 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Apache Arrow record batches and Parquet files.
+//!
+//! Retrievals and pooled results become a batch with a `timestamp` column, in the unit of the series they came
+//! from, followed by a column for each part of the value.  Batches can be written as an Arrow IPC stream, for
+//! sending over HTTP, or as a Parquet file.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, BooleanArray, Float64Array, PrimitiveArray, PrimitiveArrayOps, TimestampMicrosecondArray, TimestampMillisecondArray, TimestampNanosecondArray, UInt64Array};
+use arrow::datatypes::{ArrowPrimitiveType, BooleanType, DataType, Field, Float64Type, Schema, TimeUnit as ArrowTimeUnit, TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType, UInt64Type};
+use arrow::ipc::writer::StreamWriter;
+use parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+use parquet::column::writer::ColumnWriter;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{FileWriter, SerializedFileWriter};
+use parquet::schema::types::{Type, TypePtr};
+
+use key_value_store::Retrieval;
+use sealed::Sealed;
+use time_series::{TimeUnit, Timestamp};
+
+pub use arrow::record_batch::RecordBatch;
+
+/// The values of one column.  Missing values become nulls.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Column {
+    UInt64(Vec<Option<u64>>),
+    Float64(Vec<Option<f64>>),
+    Boolean(Vec<bool>),
+}
+
+impl Column {
+    fn len(&self) -> usize {
+        match *self {
+            Column::UInt64(ref values) => values.len(),
+            Column::Float64(ref values) => values.len(),
+            Column::Boolean(ref values) => values.len(),
+        }
+    }
+
+    fn field(&self, name: &str) -> Field {
+        match *self {
+            Column::UInt64(ref values) => Field::new(name, DataType::UInt64, values.contains(&None)),
+            Column::Float64(ref values) => Field::new(name, DataType::Float64, values.contains(&None)),
+            Column::Boolean(_) => Field::new(name, DataType::Boolean, false),
+        }
+    }
+
+    fn into_array(self) -> ArrayRef {
+        match self {
+            Column::UInt64(values) => Arc::new(UInt64Array::from(values)),
+            Column::Float64(values) => Arc::new(Float64Array::from(values)),
+            Column::Boolean(values) => Arc::new(BooleanArray::from(values)),
+        }
+    }
+}
+
+/// A value that can be split into columns.  Sealed, so the layout of the columns can change with the format.
+pub trait Columnar: Sealed + Sized {
+    fn columns(values: &[Self]) -> Vec<Column>;
+}
+
+impl Columnar for u64 {
+    fn columns(values: &[Self]) -> Vec<Column> {
+        vec![Column::UInt64(values.iter().map(|&value| Some(value)).collect())]
+    }
+}
+
+impl Columnar for f64 {
+    fn columns(values: &[Self]) -> Vec<Column> {
+        vec![Column::Float64(values.iter().map(|&value| Some(value)).collect())]
+    }
+}
+
+impl Columnar for (f64, f64, f64) {
+    fn columns(values: &[Self]) -> Vec<Column> {
+        vec![
+            Column::Float64(values.iter().map(|value| Some(value.0)).collect()),
+            Column::Float64(values.iter().map(|value| Some(value.1)).collect()),
+            Column::Float64(values.iter().map(|value| Some(value.2)).collect()),
+        ]
+    }
+}
+
+/// Builds a batch from timestamps, in `unit`, and named columns of the same length
+pub fn record_batch(timestamps: &[Timestamp], unit: TimeUnit, columns: Vec<(&str, Column)>) -> io::Result<RecordBatch> {
+    if columns.iter().any(|&(_, ref column)| column.len() != timestamps.len()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Columns must have a value for every timestamp"));
+    }
+
+    let mut fields = vec![Field::new("timestamp", DataType::Timestamp(arrow_unit(unit), None), false)];
+    let mut arrays = vec![timestamp_array(timestamps, unit)?];
+
+    for (name, column) in columns {
+        fields.push(column.field(name));
+        arrays.push(column.into_array());
+    }
+
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).map_err(export_error)
+}
+
+/// Builds a batch from records, naming the columns of their values with `names`
+pub fn records_batch<V>(records: &[(Timestamp, V)], unit: TimeUnit, names: &[&str]) -> io::Result<RecordBatch> where V: Columnar + Copy {
+    let timestamps = records.iter().map(|record| record.0).collect::<Vec<Timestamp>>();
+    let values = records.iter().map(|record| record.1).collect::<Vec<V>>();
+
+    let columns = V::columns(&values);
+    if columns.len() != names.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Every value column needs a name"));
+    }
+
+    record_batch(&timestamps, unit, names.iter().cloned().zip(columns).collect())
+}
+
+/// Builds a batch from a retrieval of `(Timestamp, V)` records, such as one returned by a pooled time series
+pub fn retrieval_batch<V>(retrieval: &Retrieval, unit: TimeUnit, names: &[&str]) -> io::Result<RecordBatch> where V: 'static + Columnar + Copy {
+    match retrieval.as_vec::<Timestamp, V>() {
+        Some(records) => records_batch(records, unit, names),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "Retrieval has the wrong value type")),
+    }
+}
+
+/// Writes batches, which must share a schema, as an Arrow IPC stream
+pub fn write_stream<W>(writer: W, batches: &[RecordBatch]) -> io::Result<()> where W: Write {
+    let schema = match batches.first() {
+        Some(batch) => batch.schema(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Nothing to write")),
+    };
+
+    let mut writer = StreamWriter::try_new(writer, &schema).map_err(export_error)?;
+    for batch in batches {
+        writer.write(batch).map_err(export_error)?;
+    }
+    writer.finish().map_err(export_error)
+}
+
+/// Writes batches, which must share a schema, as a Parquet file
+pub fn write_parquet(file: File, batches: &[RecordBatch]) -> io::Result<()> {
+    let schema = match batches.first() {
+        Some(batch) => batch.schema(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Nothing to write")),
+    };
+
+    let properties = Rc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(file, parquet_schema(&schema)?, properties).map_err(export_error)?;
+
+    // Each batch becomes a row group, with its columns in the order of the schema
+    for batch in batches {
+        let mut row_group = writer.next_row_group().map_err(export_error)?;
+        let mut columns = schema.fields().iter().zip(batch.columns());
+
+        while let Some(mut column) = row_group.next_column().map_err(export_error)? {
+            match columns.next() {
+                Some((field, array)) => write_column(&mut column, field, array)?,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Batch doesn't match the schema")),
+            }
+            row_group.close_column(column).map_err(export_error)?;
+        }
+
+        writer.close_row_group(row_group).map_err(export_error)?;
+    }
+    writer.close().map_err(export_error)
+}
+
+fn parquet_schema(schema: &Schema) -> io::Result<TypePtr> {
+    let mut fields = schema.fields().iter().map(|field| parquet_type(field).map(Rc::new)).collect::<io::Result<Vec<TypePtr>>>()?;
+    let schema = Type::group_type_builder("schema").with_fields(&mut fields).build().map_err(export_error)?;
+
+    Ok(Rc::new(schema))
+}
+
+/// Parquet has no logical type for nanosecond timestamps, so those are written as plain integers
+fn parquet_type(field: &Field) -> io::Result<Type> {
+    let (physical_type, logical_type) = match *field.data_type() {
+        DataType::Timestamp(ArrowTimeUnit::Millisecond, _) => (PhysicalType::INT64, LogicalType::TIMESTAMP_MILLIS),
+        DataType::Timestamp(ArrowTimeUnit::Microsecond, _) => (PhysicalType::INT64, LogicalType::TIMESTAMP_MICROS),
+        DataType::Timestamp(ArrowTimeUnit::Nanosecond, _) => (PhysicalType::INT64, LogicalType::NONE),
+        DataType::UInt64 => (PhysicalType::INT64, LogicalType::UINT_64),
+        DataType::Float64 => (PhysicalType::DOUBLE, LogicalType::NONE),
+        DataType::Boolean => (PhysicalType::BOOLEAN, LogicalType::NONE),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Column type can't be written to Parquet")),
+    };
+    let repetition = if field.is_nullable() { Repetition::OPTIONAL } else { Repetition::REQUIRED };
+
+    Type::primitive_type_builder(field.name(), physical_type)
+        .with_logical_type(logical_type)
+        .with_repetition(repetition)
+        .build()
+        .map_err(export_error)
+}
+
+/// Writes the values of an array that aren't null, marking the nulls with definition levels if the field is nullable
+fn write_column(writer: &mut ColumnWriter, field: &Field, array: &ArrayRef) -> io::Result<()> {
+    let definitions = if field.is_nullable() {
+        Some((0..array.len()).map(|i| array.is_valid(i) as i16).collect::<Vec<i16>>())
+    } else {
+        None
+    };
+    let definitions = definitions.as_ref().map(|definitions| &definitions[..]);
+
+    let mismatch = || io::Error::new(io::ErrorKind::InvalidInput, "Column doesn't match its schema");
+
+    let written = match *writer {
+        ColumnWriter::Int64ColumnWriter(ref mut writer) => {
+            let values = match *field.data_type() {
+                DataType::Timestamp(ArrowTimeUnit::Millisecond, _) => valid_values::<TimestampMillisecondType>(array),
+                DataType::Timestamp(ArrowTimeUnit::Microsecond, _) => valid_values::<TimestampMicrosecondType>(array),
+                DataType::Timestamp(ArrowTimeUnit::Nanosecond, _) => valid_values::<TimestampNanosecondType>(array),
+                // UINT_64 is stored in the bits of an INT64
+                DataType::UInt64 => valid_values::<UInt64Type>(array).map(|values| values.into_iter().map(|value| value as i64).collect()),
+                _ => None,
+            };
+            writer.write_batch(&values.ok_or_else(mismatch)?, definitions, None)
+        },
+        ColumnWriter::DoubleColumnWriter(ref mut writer) => writer.write_batch(&valid_values::<Float64Type>(array).ok_or_else(mismatch)?, definitions, None),
+        ColumnWriter::BoolColumnWriter(ref mut writer) => writer.write_batch(&valid_values::<BooleanType>(array).ok_or_else(mismatch)?, definitions, None),
+        _ => return Err(mismatch()),
+    };
+    written.map_err(export_error)?;
+
+    Ok(())
+}
+
+/// The values of an array that aren't null, or `None` if it doesn't hold `T`
+fn valid_values<T>(array: &ArrayRef) -> Option<Vec<T::Native>> where T: ArrowPrimitiveType {
+    let array = array.as_any().downcast_ref::<PrimitiveArray<T>>()?;
+    Some((0..array.len()).filter(|&i| array.is_valid(i)).map(|i| array.value(i)).collect())
+}
+
+fn arrow_unit(unit: TimeUnit) -> ArrowTimeUnit {
+    match unit {
+        TimeUnit::Milliseconds => ArrowTimeUnit::Millisecond,
+        TimeUnit::Microseconds => ArrowTimeUnit::Microsecond,
+        TimeUnit::Nanoseconds => ArrowTimeUnit::Nanosecond,
+    }
+}
+
+/// Arrow timestamps are signed, so the far end of our range can't be represented
+fn timestamp_array(timestamps: &[Timestamp], unit: TimeUnit) -> io::Result<ArrayRef> {
+    let timestamps = timestamps.iter().map(|&timestamp| {
+        if timestamp > i64::max_value() as Timestamp {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "Timestamp is too large for Arrow"))
+        } else {
+            Ok(timestamp as i64)
+        }
+    }).collect::<io::Result<Vec<i64>>>()?;
+
+    let array: ArrayRef = match unit {
+        TimeUnit::Milliseconds => Arc::new(TimestampMillisecondArray::from_vec(timestamps, None)),
+        TimeUnit::Microseconds => Arc::new(TimestampMicrosecondArray::from_vec(timestamps, None)),
+        TimeUnit::Nanoseconds => Arc::new(TimestampNanosecondArray::from_vec(timestamps, None)),
+    };
+
+    Ok(array)
+}
+
+fn export_error<E>(error: E) -> io::Error where E: Display {
+    io::Error::new(io::ErrorKind::Other, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use arrow::ipc::reader::StreamReader;
+
+    use util::SetupFile;
+
+    #[test]
+    fn test_records_batch() {
+        let batch = records_batch(&[(10, 1u64), (20, 2), (30, 3)], TimeUnit::Microseconds, &["value"]).unwrap();
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(batch.num_columns(), 2);
+        assert_eq!(batch.schema().field(0).data_type(), &DataType::Timestamp(ArrowTimeUnit::Microsecond, None));
+        assert_eq!(batch.schema().field(1).name(), "value");
+
+        let bands = records_batch(&[(10, (1.0, 2.0, 3.0))], TimeUnit::Milliseconds, &["lower", "middle", "upper"]).unwrap();
+        assert_eq!(bands.num_columns(), 4);
+
+        assert!(records_batch(&[(10, 1u64)], TimeUnit::Milliseconds, &[]).is_err());
+        assert!(records_batch(&[(u64::max_value(), 1u64)], TimeUnit::Milliseconds, &["value"]).is_err());
+        assert!(record_batch(&[10, 20], TimeUnit::Milliseconds, vec![("value", Column::UInt64(vec![Some(1)]))]).is_err());
+
+        // Missing values make a column nullable
+        let batch = record_batch(&[10, 20], TimeUnit::Milliseconds, vec![("value", Column::Float64(vec![Some(1.0), None]))]).unwrap();
+        assert!(batch.schema().field(1).is_nullable());
+
+        let retrieval = Retrieval::new(Box::new(vec![(10 as Timestamp, 1.5f64)]));
+        assert_eq!(retrieval_batch::<f64>(&retrieval, TimeUnit::Milliseconds, &["value"]).unwrap().num_rows(), 1);
+        assert!(retrieval_batch::<u64>(&retrieval, TimeUnit::Milliseconds, &["value"]).is_err());
+    }
+
+    #[test]
+    fn test_write() {
+        let _setup_file = SetupFile::new("test_export_write");
+
+        let batch = records_batch(&[(10, 1u64), (20, 2)], TimeUnit::Milliseconds, &["value"]).unwrap();
+
+        let mut stream = Vec::new();
+        write_stream(&mut stream, &[batch.clone()]).unwrap();
+
+        let mut reader = StreamReader::try_new(&stream[..]).unwrap();
+        assert_eq!(reader.schema(), batch.schema());
+        assert_eq!(reader.next().unwrap().unwrap().num_rows(), 2);
+        assert!(reader.next().is_none());
+        assert!(write_stream(Vec::new(), &[]).is_err());
+
+        write_parquet(File::create("test_export_write").unwrap(), &[batch]).unwrap();
+
+        let mut contents = Vec::new();
+        File::open("test_export_write").unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(&contents[..4], b"PAR1");
+        assert_eq!(&contents[contents.len() - 4..], b"PAR1");
+
+        // Nulls are left out of the values and marked by definition levels
+        let batch = record_batch(&[10, 20], TimeUnit::Nanoseconds, vec![
+            ("value", Column::Float64(vec![Some(1.0), None])),
+            ("flag", Column::Boolean(vec![true, false])),
+        ]).unwrap();
+        write_parquet(File::create("test_export_write").unwrap(), &[batch.clone(), batch]).unwrap();
+        assert!(write_parquet(File::create("test_export_write").unwrap(), &[]).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Content fingerprints of storage files, and manifests of them for auditing copies such as off-site backups.
+//!
+//! A manifest lists a SHA-256 hash, size, and name for each file, one per line.  Storage files are only appended to,
+//! so a file is checked against its fingerprint by hashing as many bytes as were fingerprinted: a file that has
+//! grown since is still consistent, while one that's been truncated or changed has drifted.
+
+use std::fmt::{self, Write as FmtWrite};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The size and SHA-256 hash of a file's contents
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fingerprint {
+    pub bytes: u64,
+    pub hash: [u8; 32],
+}
+
+impl Fingerprint {
+    /// Fingerprints everything a reader produces
+    pub fn of_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut sha256 = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => sha256.update(&buffer[..read]),
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(sha256.finish())
+    }
+
+    /// Fingerprints a file, or only its first `bytes` bytes if given
+    pub fn of_file<P: AsRef<Path>>(path: P, bytes: Option<u64>) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        match bytes {
+            Some(bytes) => Self::of_reader(file.take(bytes)),
+            None => Self::of_reader(file),
+        }
+    }
+
+    /// The hash in lowercase hexadecimal
+    pub fn hex(&self) -> String {
+        let mut hex = String::with_capacity(64);
+        for byte in self.hash.iter() {
+            write!(hex, "{:02x}", byte).unwrap();
+        }
+        hex
+    }
+}
+
+/// How a file differs from its fingerprint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Drift {
+    /// The file is gone
+    Missing,
+    /// The file is shorter than it was, and is this many bytes long now
+    Truncated(u64),
+    /// The fingerprinted bytes have changed
+    Changed,
+}
+
+/// Checks a file against its fingerprint.  Returns the number of bytes the file has grown by since, or how it drifted.
+pub fn verify<P: AsRef<Path>>(path: P, fingerprint: &Fingerprint) -> io::Result<Result<u64, Drift>> {
+    let length = match path.as_ref().metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(Err(Drift::Missing)),
+        Err(error) => return Err(error),
+    };
+
+    if length < fingerprint.bytes {
+        return Ok(Err(Drift::Truncated(length)));
+    }
+
+    if Fingerprint::of_file(path, Some(fingerprint.bytes))? != *fingerprint {
+        return Ok(Err(Drift::Changed));
+    }
+
+    Ok(Ok(length - fingerprint.bytes))
+}
+
+/// Fingerprints of a set of files, by name
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub entries: Vec<(String, Fingerprint)>,
+}
+
+impl Manifest {
+    /// Parses a manifest written by `Display`
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |line: usize| io::Error::new(io::ErrorKind::InvalidData, format!("Manifest line {} is malformed", line + 1));
+
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()) {
+            let mut fields = line.splitn(3, ' ');
+            let (hex, bytes, name) = match (fields.next(), fields.next(), fields.next()) {
+                // The hash is sliced by byte below, so it has to be ASCII
+                (Some(hex), Some(bytes), Some(name)) if hex.len() == 64 && hex.is_ascii() && !name.is_empty() => (hex, bytes, name),
+                _ => return Err(invalid(index)),
+            };
+
+            let mut hash = [0u8; 32];
+            for (i, byte) in hash.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid(index))?;
+            }
+
+            entries.push((name.to_string(), Fingerprint {
+                bytes: bytes.parse().map_err(|_| invalid(index))?,
+                hash: hash,
+            }));
+        }
+
+        Ok(Manifest {
+            entries: entries,
+        })
+    }
+
+    /// The fingerprint of the manifest itself, which identifies the contents of every file in it at once
+    pub fn fingerprint(&self) -> Fingerprint {
+        let mut sha256 = Sha256::new();
+        sha256.update(self.to_string().as_bytes());
+        sha256.finish()
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(ref name, ref fingerprint) in &self.entries {
+            writeln!(f, "{} {} {}", fingerprint.hex(), fingerprint.bytes, name)?;
+        }
+        Ok(())
+    }
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256, as specified in FIPS 180-4
+struct Sha256 {
+    state: [u32; 8],
+    block: Vec<u8>,
+    bytes: u64,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Self {
+            state: [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19],
+            block: Vec::with_capacity(64),
+            bytes: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.bytes += data.len() as u64;
+
+        while !data.is_empty() {
+            let take = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+
+            if self.block.len() == 64 {
+                let block = self.block.clone();
+                self.compress(&block);
+                self.block.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> Fingerprint {
+        let bytes = self.bytes;
+
+        // Pad with a one bit, zeros up to the last eight bytes of a block, and the length in bits
+        let mut padding = vec![0x80u8];
+        padding.resize(1 + (119 - self.block.len()) % 64, 0);
+        padding.extend_from_slice(&(bytes * 8).to_be_bytes());
+        self.update(&padding);
+
+        let mut hash = [0u8; 32];
+        for (chunk, word) in hash.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+
+        Fingerprint {
+            bytes: bytes,
+            hash: hash,
+        }
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = u32::from(block[i * 4]) << 24 | u32::from(block[i * 4 + 1]) << 16 | u32::from(block[i * 4 + 2]) << 8 | u32::from(block[i * 4 + 3]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let mut v = self.state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let choice = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(choice).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let majority = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(majority);
+
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+
+        for (state, value) in self.state.iter_mut().zip(v.iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    use util::SetupFile;
+
+    #[test]
+    fn test_sha256() {
+        let hex = |data: &[u8]| Fingerprint::of_reader(data).unwrap().hex();
+
+        assert_eq!(hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        );
+        assert_eq!(hex(&vec![b'a'; 1000000]), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn test_verify() {
+        let _setup_file = SetupFile::new("test_verify");
+        fs::write("test_verify", b"0123456789").unwrap();
+
+        let fingerprint = Fingerprint::of_file("test_verify", None).unwrap();
+        assert_eq!(fingerprint.bytes, 10);
+        assert_eq!(verify("test_verify", &fingerprint).unwrap(), Ok(0));
+
+        OpenOptions::new().append(true).open("test_verify").unwrap().write_all(b"abc").unwrap();
+        assert_eq!(verify("test_verify", &fingerprint).unwrap(), Ok(3));
+
+        fs::write("test_verify", b"0123456780abc").unwrap();
+        assert_eq!(verify("test_verify", &fingerprint).unwrap(), Err(Drift::Changed));
+
+        fs::write("test_verify", b"01234").unwrap();
+        assert_eq!(verify("test_verify", &fingerprint).unwrap(), Err(Drift::Truncated(5)));
+
+        assert_eq!(verify("test_verify_missing", &fingerprint).unwrap(), Err(Drift::Missing));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = Manifest {
+            entries: vec![
+                ("gemini_btcusd_trades".to_string(), Fingerprint::of_reader(&b"abc"[..]).unwrap()),
+                ("data/with spaces".to_string(), Fingerprint::of_reader(&b""[..]).unwrap()),
+            ],
+        };
+
+        let text = manifest.to_string();
+        assert_eq!(text.lines().next().unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad 3 gemini_btcusd_trades");
+        assert_eq!(Manifest::parse(&text).unwrap(), manifest);
+        assert_eq!(Manifest::parse(&text).unwrap().fingerprint(), manifest.fingerprint());
+
+        assert!(Manifest::parse("abc 3 gemini_btcusd_trades").is_err());
+        assert!(Manifest::parse(&text.replace(" 3 ", " three ")).is_err());
+        assert!(Manifest::parse(&format!("{}é 3 gemini_btcusd_trades", "0".repeat(62))).is_err());
+    }
+}
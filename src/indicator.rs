@@ -0,0 +1,288 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use sealed::Sealed;
+use time_series::Timestamp;
+
+/// A windowed indicator computed from a series of records, usually pooled buckets.
+///
+/// Windowed indicators have no value until their window is full, so their output starts at the window's last
+/// record.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Indicator {
+    /// The simple moving average of this many records
+    Sma(usize),
+    /// The exponential moving average of this many records, seeded with the simple moving average of the first
+    Ema(usize),
+    /// The lowest value of this many records
+    RollingMin(usize),
+    /// The highest value of this many records
+    RollingMax(usize),
+    /// The percentage change from the previous record.  Records following a zero have no value.
+    PercentChange,
+    /// The relative strength index of the changes over this many records, with Wilder's smoothing
+    Rsi(usize),
+    /// The realized volatility of this many returns: the square root of the sum of their squared log returns.
+    /// Returns from or to a value that isn't positive count as no change.
+    RealizedVolatility(usize),
+}
+
+/// An indicator made of three lines, computed from a series of records.  Like other records, bands can be wrapped in
+/// a `Retrieval` as a `Vec<(Timestamp, (f64, f64, f64))>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bands {
+    /// The difference between the `fast` and `slow` EMAs, the `signal` EMA of that difference, and the histogram
+    /// of the difference from the signal line, in that order
+    Macd { fast: usize, slow: usize, signal: usize },
+    /// The lower band, the `period` SMA, and the upper band, where the bands are `width` standard deviations from
+    /// the SMA
+    Bollinger { period: usize, width: f64 },
+}
+
+/// A value that indicators can be computed from.  Sealed, since how values convert is up to this crate.
+pub trait Numeric: Sealed + Copy {
+    fn to_f64(self) -> f64;
+}
+
+impl Numeric for i32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Numeric for i64 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Numeric for u32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Numeric for u64 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Numeric for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Numeric for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+/// Computes an indicator over a series of records.  Fails if a window is empty.
+pub fn transform<V>(series: &[(Timestamp, V)], indicator: Indicator) -> io::Result<Vec<(Timestamp, f64)>> where V: Numeric {
+    let values = series.iter().map(|&(timestamp, value)| (timestamp, value.to_f64())).collect::<Vec<_>>();
+
+    match indicator {
+        Indicator::Sma(window) => windowed(&values, window, |window| window.iter().sum::<f64>() / window.len() as f64),
+        Indicator::Ema(window) => ema(&values, window),
+        Indicator::RollingMin(window) => windowed(&values, window, |window| window.iter().cloned().fold(::std::f64::INFINITY, f64::min)),
+        Indicator::RollingMax(window) => windowed(&values, window, |window| window.iter().cloned().fold(::std::f64::NEG_INFINITY, f64::max)),
+        Indicator::PercentChange => Ok(values.windows(2).filter(|pair| pair[0].1 != 0.0).map(|pair| {
+            (pair[1].0, (pair[1].1 - pair[0].1) / pair[0].1.abs() * 100.0)
+        }).collect()),
+        Indicator::Rsi(period) => rsi(&values, period),
+        Indicator::RealizedVolatility(window) => {
+            let returns = values.windows(2)
+                .map(|pair| (pair[1].0, if pair[0].1 > 0.0 && pair[1].1 > 0.0 { (pair[1].1 / pair[0].1).ln() } else { 0.0 }))
+                .collect::<Vec<_>>();
+            windowed(&returns, window, |window| window.iter().map(|r| r * r).sum::<f64>().sqrt())
+        },
+    }
+}
+
+/// Computes bands over a series of records.  Fails if a window is empty, if a MACD's fast EMA isn't shorter than its
+/// slow EMA, or if a Bollinger band's width is negative.
+pub fn bands<V>(series: &[(Timestamp, V)], bands: Bands) -> io::Result<Vec<(Timestamp, (f64, f64, f64))>> where V: Numeric {
+    let values = series.iter().map(|&(timestamp, value)| (timestamp, value.to_f64())).collect::<Vec<_>>();
+
+    match bands {
+        Bands::Macd { fast, slow, signal } => {
+            if fast >= slow {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "MACD fast period must be shorter than its slow period"));
+            }
+
+            // The fast EMA starts earlier, so it's lined up with the slow one from the end
+            let fast = ema(&values, fast)?;
+            let slow = ema(&values, slow)?;
+            let difference = fast[fast.len() - slow.len()..].iter().zip(&slow).map(|(fast, slow)| (slow.0, fast.1 - slow.1)).collect::<Vec<_>>();
+
+            let signal = ema(&difference, signal)?;
+            Ok(difference[difference.len() - signal.len()..].iter().zip(&signal).map(|(difference, signal)| {
+                (signal.0, (difference.1, signal.1, difference.1 - signal.1))
+            }).collect())
+        },
+        Bands::Bollinger { period, width } => {
+            if !(width >= 0.0) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Bollinger band width must not be negative"));
+            }
+
+            let middle = windowed(&values, period, |window| window.iter().sum::<f64>() / window.len() as f64)?;
+            let deviation = windowed(&values, period, |window| {
+                let mean = window.iter().sum::<f64>() / window.len() as f64;
+                (window.iter().map(|value| (value - mean) * (value - mean)).sum::<f64>() / window.len() as f64).sqrt()
+            })?;
+
+            Ok(middle.into_iter().zip(deviation).map(|((timestamp, middle), (_, deviation))| {
+                (timestamp, (middle - width * deviation, middle, middle + width * deviation))
+            }).collect())
+        },
+    }
+}
+
+fn windowed<F>(values: &[(Timestamp, f64)], window: usize, f: F) -> io::Result<Vec<(Timestamp, f64)>> where F: Fn(&[f64]) -> f64 {
+    if window == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Indicator window must not be empty"));
+    }
+
+    if values.len() < window {
+        return Ok(Vec::new());
+    }
+
+    let window_values = values.iter().map(|record| record.1).collect::<Vec<f64>>();
+
+    Ok(window_values.windows(window).zip(&values[window - 1..]).map(|(window, record)| (record.0, f(window))).collect())
+}
+
+fn ema(values: &[(Timestamp, f64)], window: usize) -> io::Result<Vec<(Timestamp, f64)>> {
+    let mut result = windowed(values, window, |window| window.iter().sum::<f64>() / window.len() as f64)?;
+    result.truncate(1);
+
+    let alpha = 2.0 / (window as f64 + 1.0);
+    for &(timestamp, value) in values.iter().skip(window) {
+        let previous = result[result.len() - 1].1;
+        result.push((timestamp, previous + alpha * (value - previous)));
+    }
+
+    Ok(result)
+}
+
+fn rsi(values: &[(Timestamp, f64)], period: usize) -> io::Result<Vec<(Timestamp, f64)>> {
+    if period == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Indicator window must not be empty"));
+    }
+
+    let changes = values.windows(2).map(|pair| (pair[1].0, pair[1].1 - pair[0].1)).collect::<Vec<_>>();
+    if changes.len() < period {
+        return Ok(Vec::new());
+    }
+
+    let index = |gain: f64, loss: f64| if loss == 0.0 {
+        if gain == 0.0 { 50.0 } else { 100.0 }
+    } else {
+        100.0 - 100.0 / (1.0 + gain / loss)
+    };
+
+    let mut gain = changes[..period].iter().map(|change| change.1.max(0.0)).sum::<f64>() / period as f64;
+    let mut loss = changes[..period].iter().map(|change| (-change.1).max(0.0)).sum::<f64>() / period as f64;
+    let mut result = vec![(changes[period - 1].0, index(gain, loss))];
+
+    for &(timestamp, change) in &changes[period..] {
+        gain = (gain * (period - 1) as f64 + change.max(0.0)) / period as f64;
+        loss = (loss * (period - 1) as f64 + (-change).max(0.0)) / period as f64;
+        result.push((timestamp, index(gain, loss)));
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform() {
+        let series: Vec<(Timestamp, i32)> = vec![(0, 2), (10, 4), (20, 6), (30, 3), (40, 0), (50, 5)];
+
+        assert_eq!(transform(&series, Indicator::Sma(3)).unwrap(), vec![(20, 4.0), (30, 13.0 / 3.0), (40, 3.0), (50, 8.0 / 3.0)]);
+        assert_eq!(transform(&series, Indicator::RollingMin(2)).unwrap(), vec![(10, 2.0), (20, 4.0), (30, 3.0), (40, 0.0), (50, 0.0)]);
+        assert_eq!(transform(&series, Indicator::RollingMax(4)).unwrap(), vec![(30, 6.0), (40, 6.0), (50, 6.0)]);
+
+        // Seeded with the SMA of 4, then each value moves the average half of the way to it
+        assert_eq!(transform(&series, Indicator::Ema(3)).unwrap(), vec![(20, 4.0), (30, 3.5), (40, 1.75), (50, 3.375)]);
+
+        // The change after a zero has no value
+        assert_eq!(transform(&series, Indicator::PercentChange).unwrap(), vec![(10, 100.0), (20, 50.0), (30, -50.0), (40, -100.0)]);
+
+        assert_eq!(transform(&series, Indicator::Sma(10)).unwrap(), vec![]);
+        assert!(transform(&series, Indicator::Sma(0)).is_err());
+        assert!(transform(&[] as &[(Timestamp, i32)], Indicator::Ema(3)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rsi() {
+        let series: Vec<(Timestamp, i32)> = vec![(0, 10), (10, 12), (20, 11), (30, 13), (40, 13), (50, 9)];
+
+        // Gains average 4 / 3 and losses 1 / 3 over the first three changes, then the smoothing takes in a flat
+        // change and a loss of 4
+        let rsi = transform(&series, Indicator::Rsi(3)).unwrap();
+        assert_eq!(rsi.iter().map(|record| record.0).collect::<Vec<_>>(), vec![30, 40, 50]);
+        assert_eq!(rsi[0].1, 80.0);
+        assert_eq!(rsi[1].1, 80.0);
+        assert!((rsi[2].1 - 100.0 * (16.0 / 27.0) / (16.0 / 27.0 + 40.0 / 27.0)).abs() < 1e-9);
+
+        let rising: Vec<(Timestamp, i32)> = vec![(0, 1), (10, 2), (20, 3)];
+        assert_eq!(transform(&rising, Indicator::Rsi(2)).unwrap(), vec![(20, 100.0)]);
+        assert_eq!(transform(&rising, Indicator::Rsi(3)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_realized_volatility() {
+        // Doubling and halving are log returns of ln 2 and -ln 2
+        let series: Vec<(Timestamp, i32)> = vec![(0, 10), (10, 20), (20, 10), (30, 10), (40, 0)];
+
+        let volatility = transform(&series, Indicator::RealizedVolatility(2)).unwrap();
+        assert_eq!(volatility.iter().map(|record| record.0).collect::<Vec<_>>(), vec![20, 30, 40]);
+        assert!((volatility[0].1 - 2.0f64.ln() * 2.0f64.sqrt()).abs() < 1e-9);
+        assert!((volatility[1].1 - 2.0f64.ln()).abs() < 1e-9);
+        assert_eq!(volatility[2].1, 0.0);
+    }
+
+    #[test]
+    fn test_bands() {
+        let series: Vec<(Timestamp, i32)> = vec![(0, 2), (10, 4), (20, 4), (30, 4), (40, 5), (50, 5), (60, 7), (70, 9)];
+
+        let bollinger = bands(&series, Bands::Bollinger { period: 8, width: 2.0 }).unwrap();
+        assert_eq!(bollinger, vec![(70, (1.0, 5.0, 9.0))]);
+
+        // A flat series has no momentum
+        let flat: Vec<(Timestamp, i32)> = (0..10).map(|i| (i * 10, 7)).collect();
+        let macd = bands(&flat, Bands::Macd { fast: 2, slow: 4, signal: 3 }).unwrap();
+        assert_eq!(macd.iter().map(|record| record.0).collect::<Vec<_>>(), vec![50, 60, 70, 80, 90]);
+        assert!(macd.iter().all(|record| record.1 == (0.0, 0.0, 0.0)));
+
+        // A steady rise keeps the fast EMA above the slow one
+        let rising: Vec<(Timestamp, i32)> = (0..10).map(|i| (i * 10, i as i32)).collect();
+        let macd = bands(&rising, Bands::Macd { fast: 2, slow: 4, signal: 3 }).unwrap();
+        assert!(macd.iter().all(|record| (record.1).0 > 0.0));
+
+        assert!(bands(&series, Bands::Macd { fast: 4, slow: 4, signal: 3 }).is_err());
+        assert!(bands(&series, Bands::Bollinger { period: 3, width: -1.0 }).is_err());
+        assert_eq!(bands(&series, Bands::Macd { fast: 2, slow: 20, signal: 3 }).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,272 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Trades from Kraken's WebSocket API.
+//!
+//! Kraken names pairs by their WebSocket names, like "XBT/USD", and calls a few assets by names of its own, e.g. XBT
+//! for bitcoin.  Prices, volumes, and times all come as decimal text, which is kept exactly as fixed-point integers
+//! rather than going through floating point.
+
+use std::io;
+use std::net::TcpStream;
+
+use serde_json::{self, Value};
+use tungstenite::{self, Message, WebSocket};
+use tungstenite::stream::MaybeTlsStream;
+
+use ingest::Ingestor;
+use symbols::{SymbolFormat, SymbolMap};
+use time_series::{TimeUnit, Timestamp};
+
+/// Kraken's public WebSocket feed
+pub const ENDPOINT: &str = "wss://ws.kraken.com";
+
+/// Assets Kraken names differently from everyone else, as Kraken's name and the usual one
+const ASSET_ALIASES: &[(&str, &str)] = &[("XBT", "BTC"), ("XDG", "DOGE")];
+
+/// The assets Kraken quotes pairs in
+const QUOTES: &[&str] = &["USD", "USDT", "USDC", "EUR", "GBP", "CAD", "JPY", "CHF", "AUD", "XBT", "ETH"];
+
+/// Which side took liquidity in a trade
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// A trade, with its price and volume as fixed-point integers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Trade {
+    pub price: Timestamp,
+    pub volume: Timestamp,
+    pub side: Side,
+}
+
+/// Kraken's WebSocket name for a pair, given its assets by their usual names, e.g. "XBT/USD" for BTC and USD
+pub fn pair_name(base: &str, quote: &str) -> String {
+    format!("{}/{}", kraken_asset(base), kraken_asset(quote))
+}
+
+/// The assets of a pair Kraken names, by their usual names, e.g. ("BTC", "USD") for "XBT/USD"
+pub fn parse_pair(pair: &str) -> io::Result<(String, String)> {
+    let mut assets = pair.splitn(2, '/');
+
+    match (assets.next(), assets.next()) {
+        (Some(base), Some(quote)) if !base.is_empty() && !quote.is_empty() => Ok((usual_asset(base), usual_asset(quote))),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid Kraken pair \"{}\"", pair))),
+    }
+}
+
+/// Translates Kraken's pair names, like "XBT/USD", to canonical symbols, like "btcusd", and back
+pub fn symbols() -> SymbolMap {
+    let map = ASSET_ALIASES.iter().fold(SymbolMap::new(SymbolFormat { separator: Some('/'), uppercase: true }), |map, &(kraken, usual)| map.alias(kraken, usual));
+    QUOTES.iter().fold(map, |map, quote| map.quote(&usual_asset(quote)))
+}
+
+fn kraken_asset(asset: &str) -> String {
+    let asset = asset.to_uppercase();
+    ASSET_ALIASES.iter().find(|&&(_, usual)| usual == asset).map_or(asset.clone(), |&(kraken, _)| kraken.to_string())
+}
+
+fn usual_asset(asset: &str) -> String {
+    ASSET_ALIASES.iter().find(|&&(kraken, _)| kraken == asset).map_or(asset.to_string(), |&(_, usual)| usual.to_string())
+}
+
+/// Ingests the trades of one pair.  Kraken can report several trades in the same instant, and they're all emitted,
+/// so a channel that holds one record per timestamp keeps the last of them.
+pub struct KrakenTrades {
+    pair: String,
+    /// The decimal places kept in prices
+    price_digits: usize,
+    /// The decimal places kept in volumes
+    volume_digits: usize,
+    unit: TimeUnit,
+    socket: Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+}
+
+impl KrakenTrades {
+    /// Ingests the trades of a pair given by its assets' usual names, with prices and volumes kept to the given decimal
+    /// places, and timestamps in milliseconds
+    pub fn new(base: &str, quote: &str, price_digits: usize, volume_digits: usize) -> Self {
+        Self {
+            pair: pair_name(base, quote),
+            price_digits: price_digits,
+            volume_digits: volume_digits,
+            unit: TimeUnit::Milliseconds,
+            socket: None,
+        }
+    }
+
+    /// Ingests the trades of a pair given by its canonical symbol, e.g. "btcusd", as translated by `map`
+    pub fn for_symbol(map: &SymbolMap, symbol: &str, price_digits: usize, volume_digits: usize) -> io::Result<Self> {
+        let (base, quote) = parse_pair(&map.external(symbol)?)?;
+        Ok(Self::new(&base, &quote, price_digits, volume_digits))
+    }
+
+    /// Emits timestamps in another unit.  Kraken reports trades to the microsecond.
+    pub fn unit(mut self, unit: TimeUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    fn socket(&mut self) -> io::Result<&mut WebSocket<MaybeTlsStream<TcpStream>>> {
+        self.socket.as_mut().ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Not connected to Kraken"))
+    }
+
+    /// The trades in a trade message, which looks like `[channel, [[price, volume, time, side, type, misc], ...],
+    /// "trade", pair]`
+    fn trades(&self, message: &[Value]) -> io::Result<Vec<(Timestamp, Trade)>> {
+        let trades = message.get(1).and_then(Value::as_array).ok_or_else(|| invalid("Trade message has no trades"))?;
+
+        trades.iter().map(|trade| {
+            let field = |i: usize| trade.get(i).and_then(Value::as_str).ok_or_else(|| invalid("Trade is missing a field"));
+
+            let time = fixed_point(field(2)?, fraction_digits(self.unit))?;
+            let side = match field(3)? {
+                "b" => Side::Buy,
+                "s" => Side::Sell,
+                _ => return Err(invalid("Trade has an unknown side")),
+            };
+
+            Ok((time, Trade {
+                price: fixed_point(field(0)?, self.price_digits)?,
+                volume: fixed_point(field(1)?, self.volume_digits)?,
+                side: side,
+            }))
+        }).collect()
+    }
+}
+
+impl Ingestor for KrakenTrades {
+    type Message = String;
+    type Value = Trade;
+
+    fn name(&self) -> &str {
+        &self.pair
+    }
+
+    fn connect(&mut self) -> io::Result<()> {
+        let (socket, _) = tungstenite::connect(ENDPOINT).map_err(websocket_error)?;
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    fn subscribe(&mut self) -> io::Result<()> {
+        let request = json!({
+            "event": "subscribe",
+            "pair": [self.pair],
+            "subscription": { "name": "trade" },
+        });
+
+        self.socket()?.write_message(Message::Text(request.to_string())).map_err(websocket_error)
+    }
+
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Pings are answered by the socket itself
+            match self.socket()?.read_message().map_err(websocket_error)? {
+                Message::Text(text) => return Ok(Some(text)),
+                Message::Close(_) => return Ok(None),
+                _ => (),
+            }
+        }
+    }
+
+    /// Heartbeats and status messages produce no trades, but a failed subscription is an error
+    fn normalize(&self, message: String) -> io::Result<Vec<(Timestamp, Trade)>> {
+        let message: Value = serde_json::from_str(&message).map_err(|error| invalid(&error.to_string()))?;
+
+        if let Some(array) = message.as_array() {
+            if array.get(array.len().saturating_sub(2)).and_then(Value::as_str) == Some("trade") {
+                return self.trades(array);
+            }
+        } else if message["event"] == "subscriptionStatus" && message["status"] == "error" {
+            let reason = message["errorMessage"].as_str().unwrap_or("Subscription failed");
+            return Err(io::Error::new(io::ErrorKind::Other, format!("Kraken refused the subscription: {}", reason)));
+        }
+
+        Ok(Vec::new())
+    }
+}
+
+/// The decimal places of seconds in a timestamp of a unit
+fn fraction_digits(unit: TimeUnit) -> usize {
+    match unit {
+        TimeUnit::Milliseconds => 3,
+        TimeUnit::Microseconds => 6,
+        TimeUnit::Nanoseconds => 9,
+    }
+}
+
+/// Reads decimal text as an integer scaled by `digits` decimal places.  Places beyond those are dropped.
+fn fixed_point(text: &str, digits: usize) -> io::Result<Timestamp> {
+    let mut parts = text.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+
+    if whole.is_empty() || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(invalid(&format!("Invalid number \"{}\"", text)));
+    }
+
+    let fraction = fraction.chars().chain("0".repeat(digits).chars()).take(digits).collect::<String>();
+
+    (whole.to_string() + &fraction).parse::<Timestamp>().map_err(|_| invalid(&format!("Invalid number \"{}\"", text)))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn websocket_error(error: tungstenite::Error) -> io::Error {
+    match error {
+        tungstenite::Error::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::Other, error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairs() {
+        assert_eq!(pair_name("btc", "usd"), "XBT/USD");
+        assert_eq!(pair_name("ETH", "XBT"), "ETH/XBT");
+        assert_eq!(parse_pair("XDG/EUR").unwrap(), ("DOGE".to_string(), "EUR".to_string()));
+        assert!(parse_pair("XBTUSD").is_err());
+
+        assert_eq!(symbols().external("btcusd").unwrap(), "XBT/USD");
+        assert_eq!(symbols().canonical("XDG/XBT").unwrap(), "dogebtc");
+        assert_eq!(KrakenTrades::for_symbol(&symbols(), "ethbtc", 5, 8).unwrap().name(), "ETH/XBT");
+    }
+
+    #[test]
+    fn test_normalize() {
+        let trades = KrakenTrades::new("BTC", "USD", 2, 8);
+
+        let message = r#"[0,[["5541.20000","0.15850568","1534614057.321597","s","l",""],["5542.5","1","1534614057.324998","b","m",""]],"trade","XBT/USD"]"#;
+        assert_eq!(trades.normalize(message.to_string()).unwrap(), vec![
+            (1534614057321, Trade { price: 554120, volume: 15850568, side: Side::Sell }),
+            (1534614057324, Trade { price: 554250, volume: 100000000, side: Side::Buy }),
+        ]);
+
+        let trades = trades.unit(TimeUnit::Microseconds);
+        assert_eq!(trades.normalize(message.to_string()).unwrap()[0].0, 1534614057321597);
+
+        assert!(trades.normalize(r#"{"event":"heartbeat"}"#.to_string()).unwrap().is_empty());
+        assert!(trades.normalize(r#"{"event":"subscriptionStatus","status":"error","errorMessage":"Currency pair not supported"}"#.to_string()).is_err());
+        assert!(trades.normalize(r#"[0,[["5541.2","x","1534614057.3","s","l",""]],"trade","XBT/USD"]"#.to_string()).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::ops::Range;
+
+use time_series::Timestamp;
+
+pub use self::sequence::{SequenceGap, SequenceTracker};
+pub use self::supervisor::{RestartPolicy, Supervisor, SupervisorHandle};
+
+/// A source of records from a single exchange feed.
+///
+/// The supervisor drives an ingestor through `connect`, `subscribe`, and then repeated calls to `receive`,
+/// passing each raw message through `normalize` and emitting the resulting records.  Any error returned
+/// along the way causes the ingestor to be restarted from `connect`.
+pub trait Ingestor: Send {
+    /// The raw message type produced by the feed
+    type Message;
+    /// The type of value this ingestor emits
+    type Value: Send;
+
+    /// A short name identifying this ingestor, used in error reporting
+    fn name(&self) -> &str;
+
+    /// Establishes a connection to the feed.
+    fn connect(&mut self) -> io::Result<()>;
+
+    /// Subscribes to whatever the ingestor is interested in on the connected feed.
+    fn subscribe(&mut self) -> io::Result<()>;
+
+    /// Blocks until the next raw message arrives.  Returns `None` when the feed has ended cleanly.
+    fn receive(&mut self) -> io::Result<Option<Self::Message>>;
+
+    /// Converts a raw message into zero or more records.
+    fn normalize(&self, message: Self::Message) -> io::Result<Vec<(Timestamp, Self::Value)>>;
+
+    /// The feed's sequence number for a message, if the feed numbers its messages, so that dropped messages can be
+    /// noticed.
+    fn sequence(&self, _message: &Self::Message) -> Option<u64> {
+        None
+    }
+
+    /// Whether the feed numbers each connection's messages from the start, like Gemini's `socket_sequence`, rather
+    /// than carrying on across connections, like Binance's trade IDs.
+    fn sequence_per_connection(&self) -> bool {
+        false
+    }
+
+    /// Fetches the records of skipped messages some other way, usually the exchange's REST API.  Fails for feeds that
+    /// can't.
+    fn backfill(&mut self, _missing: Range<u64>) -> io::Result<Vec<(Timestamp, Self::Value)>> {
+        Err(io::Error::new(io::ErrorKind::Other, "Ingestor doesn't support backfill"))
+    }
+}
+
+#[cfg(feature = "kraken")]
+pub mod kraken;
+
+mod sequence;
+mod supervisor;
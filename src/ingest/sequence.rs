@@ -0,0 +1,108 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sequence numbers of feed messages, and the gaps in them.
+//!
+//! Feeds that number their messages, like Gemini's `socket_sequence` or Binance's trade IDs, show when messages were
+//! dropped.  The supervisor follows each ingestor's numbers with a `SequenceTracker` and reports each run it skipped as
+//! a `SequenceGap`, which can be kept in a diagnostics channel of its own with `SequenceGap::record`.
+
+use std::ops::Range;
+
+use time_series::Timestamp;
+
+/// A run of messages a feed never delivered, going by its sequence numbers
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequenceGap {
+    /// The name of the ingestor whose feed skipped them
+    pub feed: String,
+    /// The sequence numbers that were skipped
+    pub missing: Range<u64>,
+    /// When the gap was noticed, in milliseconds
+    pub detected_at: Timestamp,
+    /// Whether the skipped messages were backfilled, and their records emitted ahead of the message after them
+    pub backfilled: bool,
+}
+
+impl SequenceGap {
+    /// The gap as a record of a diagnostics channel: when it was noticed, with the first skipped sequence number and
+    /// how many were skipped
+    pub fn record(&self) -> (Timestamp, (u64, u64)) {
+        (self.detected_at, (self.missing.start, self.missing.end - self.missing.start))
+    }
+}
+
+/// Follows a feed's sequence numbers, noticing when some are skipped
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SequenceTracker {
+    next: Option<u64>,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The sequence number expected next, once one has been seen
+    pub fn next(&self) -> Option<u64> {
+        self.next
+    }
+
+    /// Notes a message's sequence number, returning the numbers skipped since the last one, if any were.  Numbers
+    /// before the expected one, from redelivered messages, are passed over.
+    pub fn observe(&mut self, sequence: u64) -> Option<Range<u64>> {
+        let skipped = match self.next {
+            Some(next) if sequence < next => return None,
+            Some(next) if sequence > next => Some(next..sequence),
+            _ => None,
+        };
+
+        self.next = Some(sequence.saturating_add(1));
+        skipped
+    }
+
+    /// Forgets the numbers seen so far, for feeds that number each connection's messages from the start
+    pub fn reset(&mut self) {
+        self.next = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_tracker() {
+        let mut tracker = SequenceTracker::new();
+
+        assert_eq!(tracker.observe(5), None);
+        assert_eq!(tracker.observe(6), None);
+        assert_eq!(tracker.observe(9), Some(7..9));
+
+        // Redelivered messages don't move the tracker back
+        assert_eq!(tracker.observe(8), None);
+        assert_eq!(tracker.next(), Some(10));
+
+        tracker.reset();
+        assert_eq!(tracker.observe(0), None);
+        assert_eq!(tracker.observe(2), Some(1..2));
+    }
+
+    #[test]
+    fn test_sequence_gap_record() {
+        let gap = SequenceGap { feed: "gemini".to_string(), missing: 7..10, detected_at: 1000, backfilled: false };
+        assert_eq!(gap.record(), (1000, (7, 3)));
+    }
+}
@@ -0,0 +1,357 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use ingest::{Ingestor, SequenceGap, SequenceTracker};
+use parse;
+use time_series::Timestamp;
+
+/// How the supervisor should react when an ingestor fails
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RestartPolicy {
+    /// The maximum number of restarts before giving up on the ingestor, or `None` to restart forever
+    pub max_restarts: Option<usize>,
+    /// How long to wait before restarting a failed ingestor
+    pub backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: None,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Where to report the messages an ingestor's feed skipped, and whether to backfill them
+#[derive(Clone)]
+struct GapAlarms {
+    sender: Sender<SequenceGap>,
+    backfill: bool,
+}
+
+/// Runs multiple ingestors concurrently, each on its own thread, routing their records to channels.
+pub struct Supervisor {
+    restart_policy: RestartPolicy,
+    gap_alarms: Option<GapAlarms>,
+    running: Arc<AtomicBool>,
+    threads: Vec<(String, JoinHandle<io::Result<()>>)>,
+}
+
+impl Supervisor {
+    pub fn new(restart_policy: RestartPolicy) -> Self {
+        Self {
+            restart_policy: restart_policy,
+            gap_alarms: None,
+            running: Arc::new(AtomicBool::new(true)),
+            threads: Vec::new(),
+        }
+    }
+
+    /// Follows the sequence numbers of the ingestors spawned from here on, sending a `SequenceGap` to `alarms` each
+    /// time one's feed skips messages.  With `backfill`, the ingestor is asked for the skipped records first.  A
+    /// failed backfill is only reported, and the feed carries on.
+    pub fn alarm_gaps(&mut self, alarms: Sender<SequenceGap>, backfill: bool) {
+        self.gap_alarms = Some(GapAlarms {
+            sender: alarms,
+            backfill: backfill,
+        });
+    }
+
+    /// Starts running the ingestor on a new thread, sending each record it produces to `sender`.
+    pub fn spawn<I>(&mut self, ingestor: I, sender: Sender<(Timestamp, I::Value)>) where I: Ingestor + 'static {
+        let name = ingestor.name().to_string();
+        let restart_policy = self.restart_policy;
+        let gap_alarms = self.gap_alarms.clone();
+        let running = self.running.clone();
+
+        let thread = thread::spawn(move || run_ingestor(ingestor, sender, restart_policy, gap_alarms, running));
+
+        self.threads.push((name, thread));
+    }
+
+    /// Returns a handle that can be used to stop the supervised ingestors from another thread.
+    pub fn handle(&self) -> SupervisorHandle {
+        SupervisorHandle {
+            running: self.running.clone(),
+        }
+    }
+
+    /// Waits for all ingestors to finish, returning the name and final result of each.
+    pub fn join(self) -> Vec<(String, io::Result<()>)> {
+        self.threads.into_iter().map(|(name, thread)| {
+            let result = thread.join().unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "Ingestor thread panicked")));
+            (name, result)
+        }).collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct SupervisorHandle {
+    running: Arc<AtomicBool>,
+}
+
+impl SupervisorHandle {
+    /// Signals all ingestors to stop after their current message.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+}
+
+fn run_ingestor<I>(
+    mut ingestor: I,
+    sender: Sender<(Timestamp, I::Value)>,
+    restart_policy: RestartPolicy,
+    gap_alarms: Option<GapAlarms>,
+    running: Arc<AtomicBool>,
+) -> io::Result<()> where I: Ingestor {
+    let mut restarts = 0;
+
+    // Kept across restarts, since a restart is just when messages go missing
+    let mut tracker = SequenceTracker::new();
+
+    while running.load(Ordering::SeqCst) {
+        match run_once(&mut ingestor, &sender, gap_alarms.as_ref().map(|alarms| (alarms, &mut tracker)), &running) {
+            Ok(()) => return Ok(()),
+            // Nobody is listening anymore, so there's no point in restarting
+            Err(ref error) if error.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(error) => {
+                if restart_policy.max_restarts.map_or(false, |max| restarts >= max) {
+                    return Err(error);
+                }
+
+                restarts += 1;
+                thread::sleep(restart_policy.backoff);
+            },
+        }
+    }
+
+    Ok(())
+}
+
+fn run_once<I>(
+    ingestor: &mut I,
+    sender: &Sender<(Timestamp, I::Value)>,
+    mut gap_alarms: Option<(&GapAlarms, &mut SequenceTracker)>,
+    running: &AtomicBool,
+) -> io::Result<()> where I: Ingestor {
+    ingestor.connect()?;
+    ingestor.subscribe()?;
+
+    if let Some((_, ref mut tracker)) = gap_alarms {
+        if ingestor.sequence_per_connection() {
+            tracker.reset();
+        }
+    }
+
+    while running.load(Ordering::SeqCst) {
+        let message = match ingestor.receive()? {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+
+        if let Some((alarms, ref mut tracker)) = gap_alarms {
+            if let Some(missing) = ingestor.sequence(&message).and_then(|sequence| tracker.observe(sequence)) {
+                alarm_gap(ingestor, sender, alarms, missing)?;
+            }
+        }
+
+        send_records(sender, ingestor.normalize(message)?)?;
+    }
+
+    Ok(())
+}
+
+/// Backfills skipped messages if the alarms ask for it, and reports them.  Fails only if nobody is listening to the
+/// records anymore.
+fn alarm_gap<I>(ingestor: &mut I, sender: &Sender<(Timestamp, I::Value)>, alarms: &GapAlarms, missing: Range<u64>) -> io::Result<()> where I: Ingestor {
+    let backfilled = if alarms.backfill {
+        match ingestor.backfill(missing.clone()) {
+            Ok(records) => {
+                send_records(sender, records)?;
+                true
+            },
+            Err(_) => false,
+        }
+    } else {
+        false
+    };
+
+    // The alarms are diagnostics, so the feed goes on even if nobody is listening to them
+    let _ = alarms.sender.send(SequenceGap {
+        feed: ingestor.name().to_string(),
+        missing: missing,
+        detected_at: parse::now(),
+        backfilled: backfilled,
+    });
+
+    Ok(())
+}
+
+fn send_records<V>(sender: &Sender<(Timestamp, V)>, records: Vec<(Timestamp, V)>) -> io::Result<()> {
+    for record in records {
+        if sender.send(record).is_err() {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Ingestor output channel was closed"));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    struct MockIngestor {
+        connects: usize,
+        messages: Vec<u64>,
+    }
+
+    impl Ingestor for MockIngestor {
+        type Message = u64;
+        type Value = i32;
+
+        fn name(&self) -> &str {
+            "mock"
+        }
+
+        fn connect(&mut self) -> io::Result<()> {
+            self.connects += 1;
+
+            // Fail the first connection attempt to exercise the restart path
+            if self.connects == 1 {
+                Err(io::Error::new(io::ErrorKind::ConnectionRefused, "Mock connection refused"))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn subscribe(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn receive(&mut self) -> io::Result<Option<u64>> {
+            Ok(if self.messages.is_empty() {
+                None
+            } else {
+                Some(self.messages.remove(0))
+            })
+        }
+
+        fn normalize(&self, message: u64) -> io::Result<Vec<(Timestamp, i32)>> {
+            Ok(vec![(message, message as i32 * 2)])
+        }
+    }
+
+    #[test]
+    fn test_supervisor_restarts_ingestor() {
+        let (sender, receiver) = mpsc::channel();
+
+        let mut supervisor = Supervisor::new(RestartPolicy { max_restarts: Some(1), backoff: Duration::from_millis(1) });
+        supervisor.spawn(MockIngestor { connects: 0, messages: vec![1, 2, 3] }, sender);
+
+        let results = supervisor.join();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.is_ok());
+
+        assert_eq!(receiver.iter().collect::<Vec<_>>(), vec![(1, 2), (2, 4), (3, 6)]);
+    }
+
+    /// Numbers its messages by their values, and backfills any it skipped by repeating them
+    struct SequencedIngestor {
+        messages: Vec<u64>,
+    }
+
+    impl Ingestor for SequencedIngestor {
+        type Message = u64;
+        type Value = i32;
+
+        fn name(&self) -> &str {
+            "sequenced"
+        }
+
+        fn connect(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn subscribe(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn receive(&mut self) -> io::Result<Option<u64>> {
+            Ok(if self.messages.is_empty() { None } else { Some(self.messages.remove(0)) })
+        }
+
+        fn normalize(&self, message: u64) -> io::Result<Vec<(Timestamp, i32)>> {
+            Ok(vec![(message, message as i32)])
+        }
+
+        fn sequence(&self, message: &u64) -> Option<u64> {
+            Some(*message)
+        }
+
+        fn backfill(&mut self, missing: Range<u64>) -> io::Result<Vec<(Timestamp, i32)>> {
+            Ok(missing.map(|sequence| (sequence, sequence as i32)).collect())
+        }
+    }
+
+    #[test]
+    fn test_supervisor_alarms_gaps() {
+        for &backfill in &[false, true] {
+            let (sender, receiver) = mpsc::channel();
+            let (alarms, gaps) = mpsc::channel();
+
+            let mut supervisor = Supervisor::new(RestartPolicy::default());
+            supervisor.alarm_gaps(alarms, backfill);
+            supervisor.spawn(SequencedIngestor { messages: vec![1, 2, 5, 6, 6, 8] }, sender);
+            supervisor.join();
+
+            let gaps = gaps.iter().map(|gap| (gap.feed, gap.missing, gap.backfilled)).collect::<Vec<_>>();
+            assert_eq!(gaps, vec![("sequenced".to_string(), 3..5, backfill), ("sequenced".to_string(), 7..8, backfill)]);
+
+            let records = receiver.iter().map(|record| record.0).collect::<Vec<_>>();
+            if backfill {
+                assert_eq!(records, vec![1, 2, 3, 4, 5, 6, 6, 7, 8]);
+            } else {
+                assert_eq!(records, vec![1, 2, 5, 6, 6, 8]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_supervisor_gives_up() {
+        let (sender, _receiver) = mpsc::channel();
+
+        let mut supervisor = Supervisor::new(RestartPolicy { max_restarts: Some(0), backoff: Duration::from_millis(1) });
+        supervisor.spawn(MockIngestor { connects: 0, messages: vec![1] }, sender);
+
+        let results = supervisor.join();
+        assert!(results[0].1.is_err());
+    }
+}
@@ -15,6 +15,13 @@
 
 use std::any::Any;
 use std::io;
+use std::ops::{Add, Sub};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use pooled_time_series::Summary;
+use sealed::Sealed;
+use time_series::Timestamp;
 
 pub type Data = dyn Any;
 
@@ -45,6 +52,14 @@ impl Retrieval {
         }
     }
 
+    /// Like `into_vec`, but hands the retrieval back instead of panicking if it's the wrong type.
+    pub fn try_into_vec<K: 'static, V: 'static>(self) -> Result<Vec<(K, V)>, Self> {
+        match self.data.downcast::<Vec<(K, V)>>() {
+            Ok(cast) => Ok(*cast),
+            Err(data) => Err(Self::new(data)),
+        }
+    }
+
     pub fn into_vec<K: 'static, V: 'static>(self) -> Vec<(K, V)> {
         if let Ok(cast) = self.data.downcast::<Vec<(K, V)>>() {
             *cast
@@ -52,16 +67,191 @@ impl Retrieval {
             panic!("into_vec called on a Retrieval of the wrong type");
         }
     }
+
+    /// Takes apart the retrieval of `PooledTimeSeries::summarize_range`
+    pub fn into_summary<V: 'static>(self) -> Option<Summary<V>> {
+        if let Ok(cast) = self.data.downcast::<Option<Summary<V>>>() {
+            *cast
+        } else {
+            panic!("into_summary called on a Retrieval of the wrong type");
+        }
+    }
+}
+
+/// A record that was just stored, as sent to a store's subscribers.  Like a `Retrieval`, it's taken apart by
+/// naming its key and value types.
+pub struct Notification {
+    data: Box<dyn Any + Send>,
+}
+
+impl Notification {
+    pub fn new<K, V>(key: K, value: V) -> Self where K: 'static + Send, V: 'static + Send {
+        Self {
+            data: Box::new((key, value)),
+        }
+    }
+
+    pub fn as_single<K: 'static, V: 'static>(&self) -> Option<&(K, V)> {
+        self.data.downcast_ref::<(K, V)>()
+    }
+
+    pub fn into_single<K: 'static, V: 'static>(self) -> (K, V) {
+        if let Ok(cast) = self.data.downcast::<(K, V)>() {
+            *cast
+        } else {
+            panic!("into_single called on a Notification of the wrong type");
+        }
+    }
+}
+
+/// The subscribers of a store.  Subscribers that have hung up are forgotten the next time a record is stored.
+#[derive(Default)]
+pub struct Subscribers {
+    senders: Mutex<Vec<Sender<Notification>>>,
+}
+
+impl Subscribers {
+    pub fn subscribe(&self) -> Receiver<Notification> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut senders) = self.senders.lock() {
+            senders.push(sender);
+        }
+
+        receiver
+    }
+
+    pub fn notify<K, V>(&self, key: K, value: V) where K: 'static + Copy + Send, V: 'static + Copy + Send {
+        if let Ok(mut senders) = self.senders.lock() {
+            senders.retain(|sender| sender.send(Notification::new(key, value)).is_ok());
+        }
+    }
+}
+
+/// Cumulative disk activity of a store.  Take the difference of two snapshots to attribute activity to a query.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IoStats {
+    pub bytes_read: u64,
+    pub seeks: u64,
+}
+
+impl Add for IoStats {
+    type Output = IoStats;
+
+    fn add(self, other: IoStats) -> IoStats {
+        IoStats {
+            bytes_read: self.bytes_read + other.bytes_read,
+            seeks: self.seeks + other.seeks,
+        }
+    }
+}
+
+impl Sub for IoStats {
+    type Output = IoStats;
+
+    fn sub(self, other: IoStats) -> IoStats {
+        IoStats {
+            bytes_read: self.bytes_read - other.bytes_read,
+            seeks: self.seeks - other.seeks,
+        }
+    }
+}
+
+/// The windows, in milliseconds, that `StoreStats` gives the rate of records over: a minute, an hour, and a day
+pub const RATE_WINDOWS: [Timestamp; 3] = [60 * 1000, 60 * 60 * 1000, 24 * 60 * 60 * 1000];
+
+/// What a store holds and how fast it's been filling, for discovery, downsampling, and monitoring
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StoreStats {
+    pub records: usize,
+    /// The keys of the first and last records, in the store's unit, if it's keyed by timestamps and isn't empty
+    pub first_timestamp: Option<Timestamp>,
+    pub last_timestamp: Option<Timestamp>,
+    pub bytes_on_disk: u64,
+    /// The rate of records over each of `RATE_WINDOWS` back from now, shortest first, so a store that's stopped filling
+    /// shows it.  Windows longer than the store's history are left out.
+    pub rates: Vec<RecordRate>,
+}
+
+/// The average number of records per second over a window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordRate {
+    /// In milliseconds
+    pub window: Timestamp,
+    pub per_second: f64,
+}
+
+impl RecordRate {
+    pub fn new(window: Timestamp, records: usize) -> Self {
+        Self {
+            window: window,
+            per_second: records as f64 * 1000.0 / window as f64,
+        }
+    }
 }
 
-pub trait KeyValueStore: Send {
+/// Stores are `Sync` so that they can be read from many threads at once, e.g. behind an `RwLock`.
+pub trait KeyValueStore: Send + Sync {
     fn len(&self) -> usize;
 
+    /// Returns the disk activity performed by this store since it was opened.
+    fn io_stats(&self) -> IoStats {
+        IoStats::default()
+    }
+
+    /// Returns the key of the last record, or `None` if the store is empty.
+    fn last_key(&self) -> Option<Box<Data>>;
+
+    /// Returns the number of bytes the store occupies on local disk.  Stores that keep nothing locally report zero.
+    fn size_on_disk(&self) -> io::Result<u64> {
+        Ok(0)
+    }
+
+    /// Returns what the store holds and how fast it's been filling.  Stores only give their first timestamp and their
+    /// rates if they can find them without reading their records, which isn't the case by default.
+    fn stats(&self) -> io::Result<StoreStats> {
+        Ok(StoreStats {
+            records: self.len(),
+            first_timestamp: None,
+            last_timestamp: self.last_key().and_then(|key| key.downcast_ref::<Timestamp>().cloned()),
+            bytes_on_disk: self.size_on_disk()?,
+            rates: Vec::new(),
+        })
+    }
+
     fn store(&mut self, key: Box<Data>, value: Box<Data>) -> io::Result<()>;
+
+    /// Writes out anything the store has buffered and waits for its records to reach the disk, e.g. before the
+    /// process exits.  Stores that keep nothing locally have nothing to do.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns a receiver of every record stored from now on, so that new records can be handled without polling.
+    /// Fails if the store doesn't support subscriptions.
+    fn subscribe(&self) -> io::Result<Receiver<Notification>> {
+        Err(io::Error::new(io::ErrorKind::Other, "Store doesn't support subscriptions"))
+    }
+
     //fn retrieve(&self, key: Box<Data>) -> io::Result<Retrieval>;
 }
 
-pub trait Storable<T: KeyValueStore>: 'static + Copy + Default + Sized + Send {
+/// Views a store as a `dyn KeyValueStore`.  Every store has it, so it's sealed rather than implemented by backends.
+pub trait AsKeyValueStore: Sealed {
+    fn as_key_value_store(&self) -> &dyn KeyValueStore;
+    fn as_mut_key_value_store(&mut self) -> &mut dyn KeyValueStore;
+}
+
+impl<T> AsKeyValueStore for T where T: KeyValueStore {
+    fn as_key_value_store(&self) -> &dyn KeyValueStore {
+        self
+    }
+
+    fn as_mut_key_value_store(&mut self) -> &mut dyn KeyValueStore {
+        self
+    }
+}
+
+pub trait Storable<T>: 'static + Copy + Default + Sized + Send + Sync {
     fn size() -> usize;
     fn into_bytes(self) -> Vec<u8>;
     fn from_bytes(buffer: &[u8]) -> io::Result<Self>;
@@ -13,14 +13,123 @@
 // You should have received a copy of the GNU General Public License
 // along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
 
-pub use key_value_store::{KeyValueStore, Retrieval};
-pub use pooled_time_series::{Interval, GapFillMethod, Poolable, PooledTimeSeries, PoolingOptions};
-pub use time_series::{TimeSeries, Timestamp};
+//! Storage, pooling, and querying of market data time series.
+//!
+//! # Stability
+//!
+//! Traits come in two tiers:
+//!
+//! - Backend and value traits are meant to be implemented outside the crate, and only change with a major version:
+//!   `KeyValueStore`, `TimeSeries`, and `PooledTimeSeries` for storage backends, `ObjectStore` for cold storage,
+//!   `DerivedSource`, `Ingestor`, and `Storable`, `Poolable`, `Accumulator`, and `ValueMetadata` for values.
+//! - Sealed traits can be used anywhere but only implemented here, so they may gain methods at any time: the codec
+//!   traits `Numeric`, `Bounded`, `Columnar`, and `SqlValue`, `Typed` for the types a `Value` can hold, and the
+//!   upcasts `AsKeyValueStore` and `AsTimeSeries`, which every store gets for free.
+//!
+//! # Server
+//!
+//! With the `server` feature, which is off by default and needs a nightly compiler, the `server` module has the HTTP
+//! API that the trade-data binary serves, for embedding in another application with `Server::builder`.  The binary
+//! itself needs it too: `cargo build --features server`, on the nightly that `rust-toolchain.toml` pins.
+//!
+//! The `columnar` feature, for the Arrow and Parquet writers of the `export` module, needs a nightly compiler as
+//! well: arrow 2.0 enables `specialization`, which stable rejects with E0554.
 
+#![cfg_attr(feature = "server", feature(proc_macro_hygiene, decl_macro))]
+
+#[cfg(feature = "columnar")]
+extern crate arrow;
+extern crate fs2;
+#[cfg(feature = "server")]
+extern crate jsonwebtoken;
+#[macro_use]
+extern crate lazy_static;
+#[cfg(feature = "mmap")]
+extern crate memmap;
+#[cfg(feature = "columnar")]
+extern crate parquet;
+#[cfg(feature = "postgresql")]
+extern crate postgres;
+#[cfg(test)]
+extern crate proptest;
+#[cfg(feature = "postgresql")]
+extern crate r2d2;
+#[cfg(feature = "postgresql")]
+extern crate r2d2_postgres;
+#[cfg(feature = "server")]
+#[macro_use]
+extern crate rocket;
+#[cfg(feature = "server")]
+extern crate rocket_contrib;
+#[cfg(feature = "s3")]
+extern crate rusoto_core;
+#[cfg(feature = "s3")]
+extern crate rusoto_s3;
+#[cfg(feature = "server")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(any(feature = "kraken", feature = "server"))]
+#[cfg_attr(feature = "kraken", macro_use)]
+extern crate serde_json;
+#[cfg(all(unix, feature = "server"))]
+extern crate signal_hook;
+#[cfg(feature = "server")]
+extern crate toml;
+#[cfg(feature = "derive")]
+extern crate trade_data_derive;
+#[cfg(all(feature = "grpc", feature = "server"))]
+extern crate trade_data_grpc;
+#[cfg(feature = "kraken")]
+extern crate tungstenite;
+#[cfg(feature = "server")]
+extern crate ws;
+
+pub use calendar::{CalendarInterval, HolidayCalendar, HolidayPolicy, TimeZone, Weekday};
+pub use channel_info::{ChannelInfo, ValueMetadata};
+pub use cursor::{Cursor, Page};
+pub use derived::{Consolidation, CrossRate, DerivedChannel, DerivedSource};
+pub use diff::{Difference, diff_records};
+pub use dynamic::{Typed, Value, ValueType};
+pub use indicator::{Bands, Indicator, Numeric};
+pub use key_value_store::{AsKeyValueStore, IoStats, KeyValueStore, Notification, RATE_WINDOWS, RecordRate, Retrieval, Storable, StoreStats};
+pub use pooled_time_series::{Accumulator, BucketAnchor, FieldPooling, Interval, GapFillMethod, OpenBucket, Poolable, PooledTimeSeries, PoolingMethod, PoolingOptions, Sampling, Statistics, Summary, pool_values, pool_weighted, sample_records, split_open_bucket, summarize_records};
+pub use primitive::PrimitiveAccumulator;
+pub use query::{Query, Transform};
+pub use schema::{Identified, MIXED_SOURCES, Quote, Source, SourceFilter, Sourced, TradeId};
+pub use stream::{CandleStream, CandleUpdate, RecordStream, ResumeToken, Sequenced};
+pub use time_series::{AsTimeSeries, TimeSeries, TimeUnit, Timestamp, UnitTimestamp, retrieve_nearest_within};
+#[cfg(feature = "derive")]
+pub use trade_data_derive::Poolable;
+
+pub mod api;
+pub mod compat;
+#[cfg(feature = "columnar")]
+pub mod export;
+pub mod fingerprint;
+pub mod indicator;
+pub mod ingest;
+pub mod microstructure;
+pub mod parse;
+pub mod replay;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod snapshot;
 pub mod storage;
+pub mod symbols;
 //pub mod value;
 
+mod calendar;
+mod channel_info;
+mod cursor;
+mod derived;
+mod diff;
+mod dynamic;
 mod key_value_store;
 mod pooled_time_series;
+mod primitive;
+mod query;
+mod schema;
+mod sealed;
+mod stream;
 mod time_series;
 mod util;
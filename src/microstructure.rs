@@ -0,0 +1,148 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Measures of how a market trades, computed from its stored records.
+//!
+//! Realized volatility is an `Indicator`, and the depth of a book is a derived channel that sums the sizes of its
+//! levels with `Consolidation::Sum`.  What's here needs more than one value per record or per bucket.
+
+use std::io;
+use std::ops::Range;
+
+use indicator::Numeric;
+use time_series::Timestamp;
+
+/// How the sizes of a set of trades are distributed
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeDistribution {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p90: f64,
+    pub p99: f64,
+    /// Equal-width bins from `min` to `max`, as the lower edge of each bin and the number of sizes in it.  The last
+    /// bin includes `max`.
+    pub histogram: Vec<(f64, usize)>,
+}
+
+/// The distribution of trade sizes, with a histogram of `bins` bins.  Every statistic is zero when there are no
+/// trades.  Fails if there are no bins.
+pub fn size_distribution<V>(sizes: &[(Timestamp, V)], bins: usize) -> io::Result<SizeDistribution> where V: Numeric {
+    if bins == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Size distribution needs at least one bin"));
+    }
+
+    let mut values = sizes.iter().map(|record| record.1.to_f64()).filter(|size| !size.is_nan()).collect::<Vec<f64>>();
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    if values.is_empty() {
+        return Ok(SizeDistribution { count: 0, min: 0.0, max: 0.0, mean: 0.0, median: 0.0, p90: 0.0, p99: 0.0, histogram: Vec::new() });
+    }
+
+    // The nearest rank
+    let percentile = |p: f64| values[((p * values.len() as f64).ceil() as usize).max(1) - 1];
+
+    let (min, max) = (values[0], values[values.len() - 1]);
+    let width = (max - min) / bins as f64;
+
+    let mut histogram = (0..bins).map(|bin| (min + width * bin as f64, 0)).collect::<Vec<(f64, usize)>>();
+    for &value in &values {
+        let bin = if width > 0.0 { ((value - min) / width) as usize } else { 0 };
+        histogram[bin.min(bins - 1)].1 += 1;
+    }
+
+    Ok(SizeDistribution {
+        count: values.len(),
+        min: min,
+        max: max,
+        mean: values.iter().sum::<f64>() / values.len() as f64,
+        median: percentile(0.5),
+        p90: percentile(0.9),
+        p99: percentile(0.99),
+        histogram: histogram,
+    })
+}
+
+/// The spread between a bid and an ask over a range, averaged by how long each spread lasted.  Records before the
+/// range set the spread it starts with.  Returns `None` if the bid and ask never both have a value in the range.
+pub fn average_spread<V>(bid: &[(Timestamp, V)], ask: &[(Timestamp, V)], range: Range<Timestamp>) -> Option<f64> where V: Numeric {
+    let mut changes = bid.iter().map(|&(timestamp, value)| (timestamp, Some(value.to_f64()), None))
+        .chain(ask.iter().map(|&(timestamp, value)| (timestamp, None, Some(value.to_f64()))))
+        .collect::<Vec<(Timestamp, Option<f64>, Option<f64>)>>();
+    changes.sort_by_key(|change| change.0);
+
+    let (mut latest_bid, mut latest_ask) = (None, None);
+    let (mut total, mut duration) = (0.0, 0);
+
+    for (i, &(timestamp, bid, ask)) in changes.iter().enumerate() {
+        latest_bid = bid.or(latest_bid);
+        latest_ask = ask.or(latest_ask);
+
+        let end = changes.get(i + 1).map_or(range.end, |next| next.0).min(range.end);
+        let start = timestamp.max(range.start);
+
+        if let (Some(bid), Some(ask)) = (latest_bid, latest_ask) {
+            if end > start {
+                total += (ask - bid) * (end - start) as f64;
+                duration += end - start;
+            }
+        }
+    }
+
+    if duration > 0 {
+        Some(total / duration as f64)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_distribution() {
+        let sizes = (1..=10).map(|size| (size as Timestamp, size)).collect::<Vec<(Timestamp, i32)>>();
+
+        let distribution = size_distribution(&sizes, 3).unwrap();
+        assert_eq!(distribution.count, 10);
+        assert_eq!((distribution.min, distribution.max, distribution.mean), (1.0, 10.0, 5.5));
+        assert_eq!((distribution.median, distribution.p90, distribution.p99), (5.0, 9.0, 10.0));
+        assert_eq!(distribution.histogram, vec![(1.0, 3), (4.0, 3), (7.0, 4)]);
+
+        let same_sizes: &[(Timestamp, i32)] = &[(0, 2), (1, 2)];
+        let same = size_distribution(same_sizes, 4).unwrap();
+        assert_eq!(same.histogram[0], (2.0, 2));
+
+        assert_eq!(size_distribution(&[] as &[(Timestamp, i32)], 4).unwrap().count, 0);
+        assert!(size_distribution(&sizes, 0).is_err());
+    }
+
+    #[test]
+    fn test_average_spread() {
+        let bid: Vec<(Timestamp, i32)> = vec![(0, 100), (20, 101)];
+        let ask: Vec<(Timestamp, i32)> = vec![(10, 104), (30, 102)];
+
+        // A spread of 4 for 10, then 3 for 10, then 1 for 10
+        assert_eq!(average_spread(&bid, &ask, 0..40), Some(8.0 / 3.0));
+
+        // The spread before the range carries into it
+        assert_eq!(average_spread(&bid, &ask, 25..35), Some(2.0));
+
+        assert_eq!(average_spread(&bid, &[], 0..40), None);
+    }
+}
@@ -0,0 +1,444 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Human-readable times and intervals.
+//!
+//! Timestamps are milliseconds since the Unix epoch.  A time can be given as a plain number of milliseconds,
+//! an RFC 3339 date and time ("2019-01-02T03:04:05.678Z"), or relative to the present ("now", "now-1h",
+//! "now+30s").  An interval is a plain number of milliseconds, or a sequence of amounts with units
+//! ("500ms", "5m", "1h30m", "1d"), where the units are ms, s, m, h, d, and w.
+//!
+//! A query is a source followed by clauses, e.g. "gemini/btcusd/trades from now-6h pool 5m ohlc":
+//!
+//! - `from <time>` and `to <time>` set the range
+//! - `pool <interval> [<method>]` pools into buckets, with `end` (the default), `start`, `high`, `low`, `mean`,
+//!   `stddev`, `sum`, `vwap`, or `ohlc` for all of start, high, low, and end
+//! - `fill default|previous` and `anchor first|start` set the gap filling and bucket anchor
+//! - `tz <zone>` makes `pool 1d` or `pool 1w` buckets start at local midnight in a time zone, such as
+//!   "America/New_York", and `week <weekday>` sets the day weekly buckets start on, Monday by default
+//! - `holidays <calendar> skip|merge` skips the time zone's holiday buckets, or merges them into the bucket before
+//! - `sma <n>`, `ema <n>`, `min <n>`, `max <n>`, `rsi <n>`, `vol <n>`, and `change` compute indicators, in order
+//! - `macd <fast> <slow> <signal>` or `bollinger <period> <width>` compute bands after the indicators
+//! - `skip <n>`, `limit <n>`, and `reverse` transform the result, in order
+
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use calendar::{CalendarInterval, HolidayCalendar, TimeZone, Weekday};
+use indicator::{Bands, Indicator};
+use pooled_time_series::{BucketAnchor, GapFillMethod, PoolingMethod};
+use query::{Query, Transform};
+use time_series::Timestamp;
+
+const SECOND: Timestamp = 1000;
+const MINUTE: Timestamp = 60 * SECOND;
+const HOUR: Timestamp = 60 * MINUTE;
+const DAY: Timestamp = 24 * HOUR;
+const WEEK: Timestamp = 7 * DAY;
+
+/// The current time
+pub fn now() -> Timestamp {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_secs() * SECOND + since_epoch.subsec_millis() as Timestamp
+}
+
+/// Parses a time, relative to `now` if it's a relative expression.
+pub fn parse_timestamp(text: &str, now: Timestamp) -> io::Result<Timestamp> {
+    let text = text.trim();
+
+    if text.starts_with("now") {
+        let offset = text["now".len()..].trim_start();
+
+        if offset.is_empty() {
+            Ok(now)
+        } else if offset.starts_with('-') {
+            now.checked_sub(parse_interval(&offset[1..])?).ok_or_else(|| invalid("Relative time is before the epoch"))
+        } else if offset.starts_with('+') {
+            now.checked_add(parse_interval(&offset[1..])?).ok_or_else(|| invalid("Relative time is too far in the future"))
+        } else {
+            Err(invalid("Relative time must be \"now\", optionally followed by + or - and an interval"))
+        }
+    } else if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) {
+        text.parse().map_err(|_| invalid("Timestamp is too large"))
+    } else {
+        parse_rfc3339(text)
+    }
+}
+
+/// Parses an interval.
+pub fn parse_interval(text: &str) -> io::Result<Timestamp> {
+    let text = text.trim();
+
+    if text.is_empty() {
+        return Err(invalid("Interval is empty"));
+    }
+
+    if text.bytes().all(|b| b.is_ascii_digit()) {
+        return text.parse().map_err(|_| invalid("Interval is too large"));
+    }
+
+    let mut interval: Timestamp = 0;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return Err(invalid("Interval amounts must be whole numbers"));
+        }
+
+        let amount: Timestamp = rest[..digits].parse().map_err(|_| invalid("Interval is too large"))?;
+        rest = &rest[digits..];
+
+        let unit_length = rest.bytes().take_while(|b| b.is_ascii_alphabetic()).count();
+        let unit = match &rest[..unit_length] {
+            "ms" => 1,
+            "s" => SECOND,
+            "m" => MINUTE,
+            "h" => HOUR,
+            "d" => DAY,
+            "w" => WEEK,
+            _ => return Err(invalid("Interval units must be one of ms, s, m, h, d, or w")),
+        };
+        rest = &rest[unit_length..];
+
+        interval = amount.checked_mul(unit)
+            .and_then(|amount| interval.checked_add(amount))
+            .ok_or_else(|| invalid("Interval is too large"))?;
+    }
+
+    Ok(interval)
+}
+
+/// A parsed query
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryText {
+    pub query: Query,
+    /// Whether the query asked for OHLC buckets, in which case it should be evaluated once with each of
+    /// `PoolingMethod::Start`, `High`, `Low`, and `End`
+    pub ohlc: bool,
+}
+
+/// Parses a query.  Relative times are relative to `now`.
+pub fn parse_query(text: &str, now: Timestamp) -> io::Result<QueryText> {
+    let text = text.trim_start();
+
+    let source = text.split_whitespace().next().ok_or_else(|| invalid("Query is empty"))?;
+    if source.split('/').count() != 3 {
+        return Err(invalid("Query source must be \"market/symbol/channel\""));
+    }
+
+    parse_clauses(source, &text[source.len()..], now)
+}
+
+/// Parses the clauses of a query, without its source.  The source can be anything, such as a file name.
+pub fn parse_clauses(source: &str, text: &str, now: Timestamp) -> io::Result<QueryText> {
+    let mut words = text.split_whitespace();
+
+    let mut query = Query::new(source);
+    let mut ohlc = false;
+    let mut tz = None;
+    let mut week = None;
+    let mut holidays = None;
+
+    while let Some(word) = words.next() {
+        let mut argument = || words.next().ok_or_else(|| invalid("Query clause is missing its argument"));
+        let count = |text: &str| text.parse::<usize>().map_err(|_| invalid("Query clause needs a whole number"));
+
+        match word {
+            "from" => query.start = Some(parse_timestamp(argument()?, now)?),
+            "to" => query.end = Some(parse_timestamp(argument()?, now)?),
+            "pool" => {
+                query.interval = Some(parse_interval(argument()?)?);
+
+                // The pooling method is optional
+                let method = match words.clone().next() {
+                    Some("end") => Some(PoolingMethod::End),
+                    Some("start") => Some(PoolingMethod::Start),
+                    Some("high") => Some(PoolingMethod::High),
+                    Some("low") => Some(PoolingMethod::Low),
+                    Some("mean") => Some(PoolingMethod::Mean),
+                    Some("stddev") => Some(PoolingMethod::StdDev),
+                    Some("sum") => Some(PoolingMethod::Sum),
+                    Some("vwap") => Some(PoolingMethod::Vwap),
+                    Some("ohlc") => {
+                        ohlc = true;
+                        Some(PoolingMethod::End)
+                    },
+                    Some(word) => parse_percentile(word),
+                    None => None,
+                };
+
+                if let Some(method) = method {
+                    query.pooling = method;
+                    words.next();
+                }
+            },
+            "fill" => query.gap_fill = Some(match argument()? {
+                "default" => GapFillMethod::Default,
+                "previous" => GapFillMethod::Previous,
+                _ => return Err(invalid("Gap fill must be \"default\" or \"previous\"")),
+            }),
+            "anchor" => query.anchor = match argument()? {
+                "first" => BucketAnchor::FirstRecord,
+                "start" => BucketAnchor::RequestedStart,
+                _ => return Err(invalid("Anchor must be \"first\" or \"start\"")),
+            },
+            "tz" => tz = Some(argument()?),
+            "week" => week = Some(argument()?),
+            "holidays" => holidays = Some((argument()?, argument()?)),
+            "sma" => query.indicators.push(Indicator::Sma(count(argument()?)?)),
+            "ema" => query.indicators.push(Indicator::Ema(count(argument()?)?)),
+            "min" => query.indicators.push(Indicator::RollingMin(count(argument()?)?)),
+            "max" => query.indicators.push(Indicator::RollingMax(count(argument()?)?)),
+            "rsi" => query.indicators.push(Indicator::Rsi(count(argument()?)?)),
+            "change" => query.indicators.push(Indicator::PercentChange),
+            "vol" => query.indicators.push(Indicator::RealizedVolatility(count(argument()?)?)),
+            "macd" => query.bands = Some(Bands::Macd { fast: count(argument()?)?, slow: count(argument()?)?, signal: count(argument()?)? }),
+            "bollinger" => query.bands = Some(Bands::Bollinger {
+                period: count(argument()?)?,
+                width: argument()?.parse().map_err(|_| invalid("Bollinger band width must be a number"))?,
+            }),
+            "skip" => query.transform.push(Transform::Skip(count(argument()?)?)),
+            "limit" => query.transform.push(Transform::Limit(count(argument()?)?)),
+            "reverse" => query.transform.push(Transform::Reverse),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown query clause \"{}\"", word))),
+        }
+    }
+
+    if ohlc && (!query.indicators.is_empty() || query.bands.is_some()) {
+        return Err(invalid("OHLC queries can't have indicators"));
+    }
+
+    if let Some(tz) = tz {
+        let calendar = calendar_interval(query.interval, tz, week)?;
+        query = query.calendar(calendar);
+    } else if week.is_some() {
+        return Err(invalid("Week start needs a time zone"));
+    } else if holidays.is_some() {
+        return Err(invalid("Holidays need a time zone"));
+    }
+
+    if let Some((calendar, policy)) = holidays {
+        query = query.holidays(HolidayCalendar::load(calendar)?, policy.parse()?);
+    }
+
+    Ok(QueryText {
+        query: query,
+        ohlc: ohlc,
+    })
+}
+
+/// The calendar interval for pooling by `interval` in a time zone, with weeks starting on `week`, Monday by
+/// default.  Fails unless the interval is exactly one day or one week.
+pub fn calendar_interval(interval: Option<Timestamp>, tz: &str, week: Option<&str>) -> io::Result<CalendarInterval> {
+    let tz = TimeZone::load(tz)?;
+
+    match (interval, week) {
+        (Some(DAY), None) => Ok(CalendarInterval::Day { tz: tz }),
+        (Some(WEEK), None) => Ok(CalendarInterval::Week { tz: tz, anchor: Weekday::Monday }),
+        (Some(WEEK), Some(week)) => Ok(CalendarInterval::Week { tz: tz, anchor: week.parse()? }),
+        _ => Err(invalid("Time zone queries must pool by 1d or 1w")),
+    }
+}
+
+/// A percentile pooling method, like "p95" or "p99.9", or `None` if the word isn't one
+fn parse_percentile(word: &str) -> Option<PoolingMethod> {
+    if !word.starts_with('p') {
+        return None;
+    }
+
+    match word[1..].parse::<f64>() {
+        Ok(percent) if percent >= 0.0 && percent <= 100.0 => Some(PoolingMethod::Quantile(percent / 100.0)),
+        _ => None,
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Parses an RFC 3339 date and time, e.g. "2019-01-02T03:04:05.678+01:00"
+fn parse_rfc3339(text: &str) -> io::Result<Timestamp> {
+    let error = || invalid("Time is not a number of milliseconds, a relative time, or an RFC 3339 time");
+
+    let bytes = text.as_bytes();
+    if !text.is_ascii() || bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || !(bytes[10] == b'T' || bytes[10] == b't' || bytes[10] == b' ') || bytes[13] != b':' || bytes[16] != b':' {
+        return Err(error());
+    }
+
+    let number = |range: ::std::ops::Range<usize>| -> io::Result<i64> {
+        let field = &text[range];
+        if field.bytes().all(|b| b.is_ascii_digit()) {
+            field.parse().map_err(|_| error())
+        } else {
+            Err(error())
+        }
+    };
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 60 {
+        return Err(error());
+    }
+
+    // Fractional seconds are kept to the millisecond
+    let mut position = 19;
+    let mut milliseconds = 0;
+    if bytes[position] == b'.' {
+        let digits = bytes[position + 1..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return Err(error());
+        }
+
+        for (i, &digit) in bytes[position + 1..position + 1 + digits.min(3)].iter().enumerate() {
+            milliseconds += (digit - b'0') as i64 * 10i64.pow(2 - i as u32);
+        }
+        position += 1 + digits;
+    }
+
+    let offset_minutes = match &text[position..] {
+        "Z" | "z" => 0,
+        offset if offset.len() == 6 && (offset.starts_with('+') || offset.starts_with('-')) && offset.as_bytes()[3] == b':' => {
+            let minutes = number(position + 1..position + 3)? * 60 + number(position + 4..position + 6)?;
+            if offset.starts_with('-') { -minutes } else { minutes }
+        },
+        _ => return Err(error()),
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset_minutes * 60;
+    let milliseconds = seconds * 1000 + milliseconds;
+
+    if milliseconds < 0 {
+        Err(invalid("Time is before the epoch"))
+    } else {
+        Ok(milliseconds as Timestamp)
+    }
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The number of days from the epoch to a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Count years from March, so that the leap day is at the end of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("250").unwrap(), 250);
+        assert_eq!(parse_interval("500ms").unwrap(), 500);
+        assert_eq!(parse_interval("5m").unwrap(), 300000);
+        assert_eq!(parse_interval("1h30m").unwrap(), 5400000);
+        assert_eq!(parse_interval("1d").unwrap(), 86400000);
+        assert!(parse_interval("").is_err());
+        assert!(parse_interval("5y").is_err());
+        assert!(parse_interval("h").is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let now = 1546398245678;
+
+        assert_eq!(parse_timestamp("now", now).unwrap(), now);
+        assert_eq!(parse_timestamp("now-1h", now).unwrap(), now - 3600000);
+        assert_eq!(parse_timestamp("now + 30s", now).unwrap(), now + 30000);
+        assert_eq!(parse_timestamp("12345", now).unwrap(), 12345);
+        assert!(parse_timestamp("now*2", now).is_err());
+        assert!(parse_timestamp("yesterday", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z", 0).unwrap(), 0);
+        assert_eq!(parse_timestamp("2019-01-02T03:04:05.678Z", 0).unwrap(), 1546398245678);
+        assert_eq!(parse_timestamp("2019-01-02T04:04:05.678+01:00", 0).unwrap(), 1546398245678);
+        assert_eq!(parse_timestamp("2020-02-29T00:00:00.5Z", 0).unwrap(), 1582934400500);
+        assert!(parse_timestamp("2019-02-29T00:00:00Z", 0).is_err());
+        assert!(parse_timestamp("1969-12-31T23:59:59Z", 0).is_err());
+        assert!(parse_timestamp("2019-01-02 03:04:05", 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let now = 1546398245678;
+
+        let parsed = parse_query("gemini/btcusd/trades from now-6h pool 5m ohlc", now).unwrap();
+        assert_eq!(parsed.query, Query::new("gemini/btcusd/trades").from(now - 21600000).interval(300000));
+        assert!(parsed.ohlc);
+
+        let parsed = parse_query("gemini/btcusd/trades from 1000 to 5000 pool 1m mean fill previous sma 3 limit 10", now).unwrap();
+        let query = Query::new("gemini/btcusd/trades").from(1000).to(5000).interval(60000).pooling(PoolingMethod::Mean)
+            .gap_fill(GapFillMethod::Previous).indicator(Indicator::Sma(3)).transform(Transform::Limit(10));
+        assert_eq!(parsed.query, query);
+        assert!(!parsed.ohlc);
+
+        assert_eq!(parse_query("a/b/c pool 1m p95", now).unwrap().query, Query::new("a/b/c").interval(60000).pooling(PoolingMethod::Quantile(0.95)));
+        assert!(parse_query("a/b/c pool 1m p101", now).is_err());
+
+        // The pooling method is optional
+        assert_eq!(parse_query("a/b/c pool 1s reverse", now).unwrap().query, Query::new("a/b/c").interval(1000).transform(Transform::Reverse));
+
+        assert!(parse_query("", now).is_err());
+        assert!(parse_query("gemini/btcusd", now).is_err());
+        assert!(parse_query("a/b/c from", now).is_err());
+        assert!(parse_query("a/b/c limit ten", now).is_err());
+        assert_eq!(parse_query("a/b/c pool 1m rsi 14 bollinger 20 2.5", now).unwrap().query,
+            Query::new("a/b/c").interval(60000).indicator(Indicator::Rsi(14)).bands(Bands::Bollinger { period: 20, width: 2.5 }));
+
+        assert!(parse_query("a/b/c pool 1m ohlc sma 3", now).is_err());
+        assert!(parse_query("a/b/c macd 12 26", now).is_err());
+        assert!(parse_query("a/b/c frm now", now).is_err());
+
+        // Clauses can be parsed for any source
+        assert_eq!(parse_clauses("gemini_btcusd_trades", " from 1000 limit 5", now).unwrap().query,
+            Query::new("gemini_btcusd_trades").from(1000).transform(Transform::Limit(5)));
+        assert_eq!(parse_clauses("trades", "", now).unwrap().query, Query::new("trades"));
+    }
+
+    #[test]
+    fn test_parse_calendar() {
+        let utc = TimeZone::load("UTC").unwrap();
+
+        assert_eq!(parse_query("a/b/c pool 1d tz UTC", 0).unwrap().query, Query::new("a/b/c").calendar(CalendarInterval::Day { tz: utc }));
+        assert_eq!(parse_query("a/b/c tz UTC pool 1w week sunday", 0).unwrap().query,
+            Query::new("a/b/c").calendar(CalendarInterval::Week { tz: utc, anchor: Weekday::Sunday }));
+        assert_eq!(parse_query("a/b/c pool 1w tz UTC", 0).unwrap().query.calendar, Some(CalendarInterval::Week { tz: utc, anchor: Weekday::Monday }));
+
+        assert!(parse_query("a/b/c pool 1h tz UTC", 0).is_err());
+        assert!(parse_query("a/b/c pool 1d tz UTC week monday", 0).is_err());
+        assert!(parse_query("a/b/c pool 1w week monday", 0).is_err());
+        assert!(parse_query("a/b/c pool 1d tz ../etc/passwd", 0).is_err());
+        assert!(parse_query("a/b/c pool 1d holidays nyse skip", 0).is_err());
+        assert!(parse_query("a/b/c pool 1d tz UTC holidays ../etc/passwd skip", 0).is_err());
+    }
+}
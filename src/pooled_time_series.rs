@@ -13,13 +13,62 @@
 // You should have received a copy of the GNU General Public License
 // along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::io;
+use std::mem;
 use std::ops::Range;
+use std::str::FromStr;
 
+use calendar::{CalendarInterval, HolidayCalendar, HolidayPolicy};
 use key_value_store::Retrieval;
-use time_series::{TimeSeries, Timestamp};
+use parse::parse_interval;
+use time_series::{AsTimeSeries, RetrievalDirection, TimeSeries, TimeUnit, Timestamp};
 
-pub type Interval = Timestamp;
+/// The length of each bucket, in the unit of the pooled timestamps.  It's never zero, since buckets of no length
+/// would never reach the end of a range.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Interval(Timestamp);
+
+impl Interval {
+    /// Fails if `length` is zero
+    pub fn new(length: Timestamp) -> io::Result<Self> {
+        if length == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Interval must be positive"));
+        }
+
+        Ok(Interval(length))
+    }
+
+    /// Panics if `millis` is zero.  The rest of the constructors are in milliseconds too, as the query language
+    /// gives intervals.
+    pub fn millis(millis: Timestamp) -> Self {
+        Self::new(millis).expect("Interval was given zero milliseconds")
+    }
+
+    /// Panics if `seconds` is zero or too long
+    pub fn seconds(seconds: Timestamp) -> Self {
+        Self::millis(seconds.checked_mul(1000).expect("Interval was given too many seconds"))
+    }
+
+    /// Panics if `minutes` is zero or too long
+    pub fn minutes(minutes: Timestamp) -> Self {
+        Self::seconds(minutes.checked_mul(60).expect("Interval was given too many minutes"))
+    }
+
+    pub fn get(&self) -> Timestamp {
+        self.0
+    }
+}
+
+/// Parses shorthand like "5m" or "1h30m", or a bare number of milliseconds, as `parse_interval` does
+impl FromStr for Interval {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        Self::new(parse_interval(text)?)
+    }
+}
 
 /// The value to return during gaps in the record
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -40,7 +89,67 @@ pub enum PoolingMethod {
     /// When gap_fill is Some(Default), the bucket value is the first record in the bucket.
     /// Otherwise, the bucket value is the most recent record upon bucket start.
     Start,
+    /// The population standard deviation of the records in the bucket
+    StdDev,
     Sum,
+    /// The mean of the records, weighted by volume.  Only multi-value records have a volume, in their second
+    /// field; for single-value records this is the same as `Mean`.
+    Vwap,
+    /// The record at this fraction of the way through the bucket's sorted values, from 0 to 1, e.g. 0.95 for the
+    /// 95th percentile.  Takes the nearest rank rather than interpolating, so the value is always one that was stored.
+    Quantile(f64),
+}
+
+/// Where the first bucket starts when the requested range starts before the first record.  Which of them placed a
+/// query's first bucket, if either did, is given by `Query::applied_anchor`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BucketAnchor {
+    /// Buckets start at the first record, and nothing is returned for the time before it
+    FirstRecord,
+    /// Buckets start at the requested start.  The buckets before the first record are empty, and are only
+    /// returned when gap filling.  Since there's no earlier record to carry forward, they always receive
+    /// the data type's default value.
+    RequestedStart,
+}
+
+/// What to do with the final bucket when it's still open, i.e. when it runs past the end of the pooled range,
+/// or the range has no end, so more records could still arrive in it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpenBucket {
+    /// Return the open bucket along with the rest
+    Include,
+    /// Leave the open bucket out, returning only complete buckets
+    Exclude,
+    /// Return the open bucket along with the rest, for the caller to split off with `split_open_bucket`
+    Label,
+}
+
+/// The most fields a multi-value record can have
+pub const MAX_FIELDS: usize = 3;
+
+/// A separate pooling method for each field of a multi-value record
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldPooling {
+    methods: [PoolingMethod; MAX_FIELDS],
+    len: usize,
+}
+
+impl FieldPooling {
+    /// Panics if given more than MAX_FIELDS methods.
+    pub fn new(methods: &[PoolingMethod]) -> Self {
+        assert!(methods.len() <= MAX_FIELDS, "FieldPooling was given more methods than a record can have fields");
+
+        let mut field_pooling = Self {
+            methods: [PoolingMethod::End; MAX_FIELDS],
+            len: methods.len(),
+        };
+        field_pooling.methods[..methods.len()].copy_from_slice(methods);
+        field_pooling
+    }
+
+    pub fn methods(&self) -> &[PoolingMethod] {
+        &self.methods[..self.len]
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -51,44 +160,857 @@ pub struct PoolingOptions {
     pub pooling: PoolingMethod,
     /// Whether and how to fill gaps
     pub gap_fill: Option<GapFillMethod>,
+    /// Per-field pooling methods for multi-value records.  Overrides `pooling` when present.
+    pub field_pooling: Option<FieldPooling>,
+    /// Where buckets start when the range starts before the first record.  Ranges that start after the
+    /// first record always start their buckets at the requested start.
+    pub anchor: BucketAnchor,
+    /// What to do with the final bucket if it's still open
+    pub open_bucket: OpenBucket,
+    /// Days or weeks in a time zone.  Overrides `interval` when present.
+    pub calendar: Option<CalendarInterval>,
+    /// The holidays of calendar buckets and what to do with them.  Ignored without `calendar`.
+    pub holidays: Option<(&'static HolidayCalendar, HolidayPolicy)>,
+    /// The unit of the pooled timestamps, for finding calendar boundaries
+    pub unit: TimeUnit,
+    /// When the feed was last known to be up, from its heartbeats.  With one, empty buckets that start after it are
+    /// unknown rather than empty, and are left out even when gap filling.  Gap filling also carries on past the last
+    /// record to the heartbeat, since the feed was up through those buckets and they had no records.
+    pub heartbeat: Option<Timestamp>,
+    /// The present, when pooling up to it.  A bucket that ends after it is still open even if the range runs on past
+    /// it, as a range ending tomorrow would, so today's bucket isn't mistaken for a finished one.
+    pub now: Option<Timestamp>,
 }
 
 impl Default for PoolingOptions {
     fn default() -> Self {
         Self {
-            interval: 0,
+            interval: Interval::minutes(1),
             pooling: PoolingMethod::End,
             gap_fill: None,
+            field_pooling: None,
+            anchor: BucketAnchor::FirstRecord,
+            open_bucket: OpenBucket::Include,
+            calendar: None,
+            holidays: None,
+            unit: TimeUnit::Milliseconds,
+            heartbeat: None,
+            now: None,
+        }
+    }
+}
+
+impl PoolingOptions {
+    /// The start of the bucket that holds `timestamp`.  Fixed intervals start their buckets anywhere, but
+    /// calendar buckets start on a boundary.  A merged holiday belongs to the bucket before it, and a skipped one
+    /// to the bucket after it.
+    pub fn bucket_start(&self, timestamp: Timestamp) -> Timestamp {
+        let calendar = match self.calendar {
+            Some(calendar) => calendar,
+            None => return timestamp,
+        };
+
+        let mut start = calendar.floor(timestamp, self.unit);
+        match self.holidays {
+            Some((holidays, HolidayPolicy::Merge)) => {
+                while start > 0 && calendar.is_holiday(start, self.unit, holidays) {
+                    start = calendar.floor(start - 1, self.unit);
+                }
+                start
+            },
+            Some((_, HolidayPolicy::Skip)) => self.next_bucket_start(start),
+            None => start,
+        }
+    }
+
+    /// The end of the bucket that starts at `bucket_start`.  Calendar buckets run on through any merged holidays
+    /// after them.
+    pub fn bucket_end(&self, bucket_start: Timestamp) -> Timestamp {
+        match self.calendar {
+            Some(calendar) => {
+                let mut end = calendar.next(bucket_start, self.unit);
+                if let Some((holidays, HolidayPolicy::Merge)) = self.holidays {
+                    while calendar.is_holiday(end, self.unit, holidays) {
+                        end = calendar.next(end, self.unit);
+                    }
+                }
+                end
+            },
+            None => bucket_start.saturating_add(self.interval.get()),
+        }
+    }
+
+    /// Whether the bucket that starts at `bucket_start` is after the last heartbeat, so that nothing is known of it
+    /// unless it has records
+    pub fn is_unknown_bucket(&self, bucket_start: Timestamp) -> bool {
+        self.heartbeat.map_or(false, |heartbeat| bucket_start > heartbeat)
+    }
+
+    /// The start of the first bucket at or after `bucket_end`.  That's `bucket_end` itself unless holidays are
+    /// skipped, which leaves a gap between buckets.
+    pub fn next_bucket_start(&self, bucket_end: Timestamp) -> Timestamp {
+        match (self.calendar, self.holidays) {
+            (Some(calendar), Some((holidays, HolidayPolicy::Skip))) => {
+                let mut start = bucket_end;
+                while calendar.is_holiday(start, self.unit, holidays) {
+                    start = calendar.next(start, self.unit);
+                }
+                start
+            },
+            _ => bucket_end,
         }
     }
 }
 
-pub trait PooledTimeSeries: TimeSeries {
+pub trait PooledTimeSeries: TimeSeries + AsTimeSeries {
     fn pool_all(&self, pooling_options: PoolingOptions) -> io::Result<Retrieval>;
     fn pool_from(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval>;
     fn pool_to(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval>;
     fn pool_range(&self, range: Range<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Retrieval>;
 
-    fn as_time_series(&self) -> &dyn TimeSeries;
-    fn as_mut_time_series(&mut self) -> &mut dyn TimeSeries;
+    /// Pools just the bucket that starts at `bucket_start`.  This is a cheap way to refresh an open bucket as
+    /// new records arrive.  The bucket is returned even if it's open.
+    fn pool_bucket(&self, bucket_start: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        let pooling_options = PoolingOptions {
+            anchor: BucketAnchor::RequestedStart,
+            open_bucket: OpenBucket::Include,
+            ..pooling_options
+        };
+
+        self.pool_range(bucket_start..pooling_options.bucket_end(bucket_start), pooling_options)
+    }
+
+    /// Pools a range at several intervals at once, e.g. the 1m, 5m, and 1h candles of a chart, into one retrieval per
+    /// interval, in the same order.  Everything but the interval comes from `pooling_options`.  Stores that can read
+    /// the range once for every interval do; by default it's pooled once per interval.
+    fn pool_multi(&self, range: Range<Timestamp>, intervals: &[Interval], pooling_options: PoolingOptions) -> io::Result<Vec<Retrieval>> {
+        intervals.iter()
+            .map(|&interval| self.pool_range(range.clone(), PoolingOptions { interval: interval, ..pooling_options }))
+            .collect()
+    }
+
+    /// Summarizes the records in a range in a single pass, without pooling them into buckets first.  The retrieval
+    /// holds an `Option<Summary<V>>`, which is `None` if the range has no records; see `Retrieval::into_summary`.
+    /// Fails if the series can't summarize.
+    fn summarize_range(&self, _range: Range<Timestamp>) -> io::Result<Retrieval> {
+        Err(io::Error::new(io::ErrorKind::Other, "Pooled time series doesn't support summaries"))
+    }
 }
 
-pub trait Poolable: 'static + Copy + Default + Ord + Sized {
-    fn mean(values: &[Self]) -> Self;
-    fn sum(values: &[Self]) -> Self;
+/// Reduces the values of a bucket to a single value in one pass, without keeping the values around.
+pub trait Accumulator<V> {
+    /// Starts an empty accumulation for the pooling method
+    fn new(pooling: PoolingMethod) -> Self where Self: Sized;
+
+    /// Folds the next value of the bucket in.  `weight` is only used by `PoolingMethod::Vwap`.
+    fn fold(&mut self, value: V, weight: f64);
+
+    /// Returns the pooled value of everything folded in since the last reset
+    fn finalize(&self) -> V;
+
+    /// Empties the accumulation so that it can be reused for the next bucket
+    fn reset(&mut self);
+
+    /// Whether the pooled value is past what its type can hold, in which case `finalize` gives the nearest value it
+    /// can.  Only integer sums and means overflow.
+    fn overflowed(&self) -> bool {
+        false
+    }
+}
+
+/// Running totals for the statistical pooling methods, for accumulators of numeric types to build on.
+/// The variance is kept with Welford's method to avoid the cancellation of a naive sum of squares.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Statistics {
+    pub count: usize,
+    pub sum: f64,
+    pub mean: f64,
+    squared_deviation: f64,
+    weighted_sum: f64,
+    total_weight: f64,
 }
 
+impl Statistics {
+    pub fn fold(&mut self, value: f64, weight: f64) {
+        self.count += 1;
+        self.sum += value;
+
+        let deviation = value - self.mean;
+        self.mean += deviation / self.count as f64;
+        self.squared_deviation += deviation * (value - self.mean);
+
+        self.weighted_sum += value * weight;
+        self.total_weight += weight;
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            (self.squared_deviation / self.count as f64).sqrt()
+        }
+    }
+
+    /// The weighted mean, or the plain mean if nothing had any weight
+    pub fn weighted_mean(&self) -> f64 {
+        if self.total_weight == 0.0 {
+            self.mean
+        } else {
+            self.weighted_sum / self.total_weight
+        }
+    }
+
+    /// The statistic for the pooling method.  Methods that aren't statistical pool to the sum.
+    pub fn value(&self, pooling: PoolingMethod) -> f64 {
+        match pooling {
+            PoolingMethod::Mean => self.mean,
+            PoolingMethod::StdDev => self.std_dev(),
+            PoolingMethod::Vwap => self.weighted_mean(),
+            _ => self.sum,
+        }
+    }
+}
+
+/// A value that can be pooled.  Integers and floats are poolable already, and `#[derive(Poolable)]`, with the `derive`
+/// feature, pools structs field by field.
+pub trait Poolable: 'static + Copy + Default + PartialOrd + Sized {
+    /// Pools the statistical methods (mean, standard deviation, sum and VWAP) in a single pass
+    type Accumulator: Accumulator<Self>;
+
+    fn mean(values: &[Self]) -> Self {
+        accumulate(values, PoolingMethod::Mean)
+    }
+
+    fn sum(values: &[Self]) -> Self {
+        accumulate(values, PoolingMethod::Sum)
+    }
+
+    /// The highest value.  Values that don't compare with the others are passed over.
+    fn high(values: &[Self]) -> Self {
+        extreme(values, Ordering::Greater)
+    }
+
+    /// The lowest value.  Values that don't compare with the others are passed over.
+    fn low(values: &[Self]) -> Self {
+        extreme(values, Ordering::Less)
+    }
+
+    /// How much a record weighs when this value is its volume field
+    fn weight(self) -> f64 {
+        1.0
+    }
+
+    /// Pools a bucket using a separate method for each field.  Single-value types use only the first method.
+    fn pool_fields(values: &[Self], start_value: Self, methods: &[PoolingMethod]) -> Self {
+        pool_values(values, start_value, methods.first().cloned().unwrap_or(PoolingMethod::End))
+    }
+}
+
+/// The value that compares as `ordering` to every other, or the default if there are none
+fn extreme<V>(values: &[V], ordering: Ordering) -> V where V: Copy + Default + PartialOrd {
+    let mut comparable = values.iter().cloned().filter(|value| value.partial_cmp(value).is_some());
+    let first = comparable.next().unwrap_or_default();
+    comparable.fold(first, |extreme, value| if value.partial_cmp(&extreme) == Some(ordering) { value } else { extreme })
+}
+
+/// Whether a pooling method is computed by the value type's accumulator
+pub fn is_statistical(pooling: PoolingMethod) -> bool {
+    match pooling {
+        PoolingMethod::Mean | PoolingMethod::StdDev | PoolingMethod::Sum | PoolingMethod::Vwap => true,
+        _ => false,
+    }
+}
+
+/// The value at `fraction` of the way through the values by nearest rank, or the default if there are none.  Values
+/// that don't compare with the others are passed over.  Selects in place in linear time, which reorders the values.
+pub fn quantile<V>(values: &mut Vec<V>, fraction: f64) -> V where V: Copy + Default + PartialOrd {
+    values.retain(|value| value.partial_cmp(value).is_some());
+    if values.is_empty() {
+        return V::default();
+    }
+
+    let rank = (fraction.max(0.0).min(1.0) * values.len() as f64).ceil() as usize;
+    let index = rank.max(1) - 1;
+
+    *values.select_nth_unstable_by(index, |a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal)).1
+}
+
+/// Runs a slice of values through a fresh accumulator
+pub fn accumulate<V>(values: &[V], pooling: PoolingMethod) -> V where V: Poolable {
+    let mut accumulator = V::Accumulator::new(pooling);
+
+    for &value in values {
+        accumulator.fold(value, 1.0);
+    }
+
+    accumulator.finalize()
+}
+
+/// Reduces the values of a non-empty bucket to a single value.
+/// `start_value` is the value to use for `PoolingMethod::Start`.
+pub fn pool_values<V>(values: &[V], start_value: V, pooling: PoolingMethod) -> V where V: Poolable {
+    match pooling {
+        PoolingMethod::End => values.last().cloned().unwrap_or_default(),
+        PoolingMethod::High => V::high(values),
+        PoolingMethod::Low => V::low(values),
+        PoolingMethod::Start => start_value,
+        PoolingMethod::Mean | PoolingMethod::StdDev | PoolingMethod::Sum | PoolingMethod::Vwap => accumulate(values, pooling),
+        PoolingMethod::Quantile(fraction) => quantile(&mut values.to_vec(), fraction),
+    }
+}
+
+/// Accumulates the records of one bucket as they stream past, without keeping the records themselves.
+/// The statistical pooling methods are folded into the value type's accumulator.  Only per-field pooling and
+/// quantiles need every value at once, so they keep the values in a scratch vector that is reused from bucket to
+/// bucket.
+struct Bucket<V> where V: Poolable {
+    start: Timestamp,
+    end: Timestamp,
+    count: usize,
+    first: (Timestamp, V),
+    last: (Timestamp, V),
+    high: V,
+    low: V,
+    accumulator: Option<V::Accumulator>,
+    values: Vec<V>,
+    keep_values: bool,
+}
+
+impl<V> Bucket<V> where V: Poolable {
+    fn new(start: Timestamp, pooling_options: PoolingOptions) -> Self {
+        Self {
+            start: start,
+            end: pooling_options.bucket_end(start),
+            count: 0,
+            first: (0, V::default()),
+            last: (0, V::default()),
+            high: V::default(),
+            low: V::default(),
+            accumulator: if pooling_options.field_pooling.is_none() && is_statistical(pooling_options.pooling) {
+                Some(V::Accumulator::new(pooling_options.pooling))
+            } else {
+                None
+            },
+            values: Vec::new(),
+            keep_values: match (pooling_options.field_pooling, pooling_options.pooling) {
+                (Some(_), _) | (None, PoolingMethod::Quantile(_)) => true,
+                _ => false,
+            },
+        }
+    }
+
+    fn add(&mut self, record: (Timestamp, V), weight: f64) {
+        if self.count == 0 {
+            self.first = record;
+            self.high = record.1;
+            self.low = record.1;
+        } else {
+            self.high = V::high(&[self.high, record.1]);
+            self.low = V::low(&[self.low, record.1]);
+        }
+
+        if let Some(ref mut accumulator) = self.accumulator {
+            accumulator.fold(record.1, weight);
+        }
+
+        if self.keep_values {
+            self.values.push(record.1);
+        }
+
+        self.last = record;
+        self.count += 1;
+    }
+
+    /// Moves on to the next bucket, keeping the scratch space.  Fine units leave less headroom before the end of
+    /// time, so the end stops there instead of overflowing.
+    fn advance(&mut self, pooling_options: PoolingOptions) {
+        self.start = pooling_options.next_bucket_start(self.end);
+        self.end = pooling_options.bucket_end(self.start);
+        self.count = 0;
+        self.values.clear();
+
+        if let Some(ref mut accumulator) = self.accumulator {
+            accumulator.reset();
+        }
+    }
+
+    /// Adds the final bucket value onto the list, depending on the type of pooling.  Fails if the value overflowed.
+    fn conclude(&mut self, values: &mut Vec<(Timestamp, V)>, last_record: (Timestamp, V), pooling_options: PoolingOptions) -> io::Result<()> {
+        if self.count > 0 {
+            let start_value = if self.first.0 == self.start || pooling_options.gap_fill == Some(GapFillMethod::Default) {
+                self.first.1
+            } else {
+                last_record.1
+            };
+
+            values.push((self.start, match pooling_options.field_pooling {
+                Some(field_pooling) => V::pool_fields(&self.values, start_value, field_pooling.methods()),
+                None => match pooling_options.pooling {
+                    PoolingMethod::End => self.last.1,
+                    PoolingMethod::High => self.high,
+                    PoolingMethod::Low => self.low,
+                    PoolingMethod::Start => start_value,
+                    PoolingMethod::Mean | PoolingMethod::StdDev | PoolingMethod::Sum | PoolingMethod::Vwap => match self.accumulator {
+                        Some(ref accumulator) if accumulator.overflowed() => return Err(overflow_error()),
+                        Some(ref accumulator) => accumulator.finalize(),
+                        None => V::default(),
+                    },
+                    PoolingMethod::Quantile(fraction) => quantile(&mut self.values, fraction),
+                },
+            }));
+        } else if let (Some(gap_fill_method), false) = (pooling_options.gap_fill, pooling_options.is_unknown_bucket(self.start)) {
+            let value = match gap_fill_method {
+                GapFillMethod::Default => V::default(),
+                GapFillMethod::Previous => last_record.1,
+            };
+
+            values.push((self.start, value));
+        }
+
+        Ok(())
+    }
+}
+
+fn overflow_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Pooled value is too large for its type")
+}
+
+/// Whether a bucket can still receive records, given the end of the pooled range, if it has one, and the present, if
+/// the options have it
+pub fn is_open_bucket(bucket_start: Timestamp, pooling_options: PoolingOptions, range_end: Option<Timestamp>) -> bool {
+    let bucket_end = pooling_options.bucket_end(bucket_start);
+    range_end.map_or(true, |end| bucket_end > end) || pooling_options.now.map_or(false, |now| bucket_end > now)
+}
+
+/// Splits the final bucket off of pooled values if it's still open
+pub fn split_open_bucket<V>(mut values: Vec<(Timestamp, V)>, pooling_options: PoolingOptions, range_end: Option<Timestamp>) -> (Vec<(Timestamp, V)>, Option<(Timestamp, V)>) {
+    let open = match values.last() {
+        Some(bucket) => is_open_bucket(bucket.0, pooling_options, range_end),
+        None => false,
+    };
+
+    let open_bucket = if open { values.pop() } else { None };
+    (values, open_bucket)
+}
+
+/// Which raw records of each bucket to keep when sampling
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+    /// Up to this many records from the start of each bucket
+    First(usize),
+    /// Up to this many records from the end of each bucket
+    Last(usize),
+}
+
+/// The aggregates of a range of records
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary<V> {
+    pub count: usize,
+    pub min: V,
+    pub max: V,
+    /// Pooled like `PoolingMethod::Mean`, so integers round toward zero
+    pub mean: V,
+    pub sum: V,
+    pub first: (Timestamp, V),
+    pub last: (Timestamp, V),
+}
+
+/// Summarizes a stream of records as they go past, without keeping them.  Returns `None` if there are none.
+pub fn summarize_records<V, I>(records: I) -> io::Result<Option<Summary<V>>> where V: Poolable, I: Iterator<Item = io::Result<(Timestamp, V)>> {
+    let mut mean = V::Accumulator::new(PoolingMethod::Mean);
+    let mut sum = V::Accumulator::new(PoolingMethod::Sum);
+    let mut summary: Option<Summary<V>> = None;
+
+    for record in records {
+        let record = record?;
+        mean.fold(record.1, 1.0);
+        sum.fold(record.1, 1.0);
+
+        summary = Some(match summary {
+            Some(summary) => Summary {
+                count: summary.count + 1,
+                min: V::low(&[summary.min, record.1]),
+                max: V::high(&[summary.max, record.1]),
+                last: record,
+                ..summary
+            },
+            None => Summary {
+                count: 1,
+                min: record.1,
+                max: record.1,
+                mean: record.1,
+                sum: record.1,
+                first: record,
+                last: record,
+            },
+        });
+    }
+
+    if mean.overflowed() || sum.overflowed() {
+        return Err(overflow_error());
+    }
+
+    Ok(summary.map(|summary| Summary {
+        mean: mean.finalize(),
+        sum: sum.finalize(),
+        ..summary
+    }))
+}
+
+/// Splits sorted records into the buckets of the pooling options, starting at `start_time` like `pool_records`, and
+/// keeps a sample of each bucket's raw records rather than pooling them.  Records before the first bucket and buckets
+/// with no records are left out.  The pooling method, field pooling, and gap filling of the options are ignored.
+pub fn sample_records<V>(records: &[(Timestamp, V)], start_time: Timestamp, range_end: Option<Timestamp>, pooling_options: PoolingOptions, sampling: Sampling) -> Vec<(Timestamp, Vec<(Timestamp, V)>)> where V: Copy {
+    let mut buckets: Vec<(Timestamp, Vec<(Timestamp, V)>)> = Vec::new();
+
+    let mut start = pooling_options.bucket_start(start_time);
+    let mut end = pooling_options.bucket_end(start);
+    let mut sample = VecDeque::new();
+
+    let first_start = start;
+    for &record in records.iter().skip_while(move |record| record.0 < first_start) {
+        if record.0 >= end {
+            if !sample.is_empty() {
+                buckets.push((start, mem::replace(&mut sample, VecDeque::new()).into()));
+            }
+
+            while record.0 >= end {
+                start = pooling_options.next_bucket_start(end);
+                end = pooling_options.bucket_end(start);
+            }
+        }
+
+        // Records on skipped holidays fall between buckets
+        if record.0 < start {
+            continue;
+        }
+
+        match sampling {
+            Sampling::First(count) => if sample.len() < count {
+                sample.push_back(record);
+            },
+            Sampling::Last(count) => {
+                sample.push_back(record);
+                if sample.len() > count {
+                    sample.pop_front();
+                }
+            },
+        }
+    }
+
+    if !sample.is_empty() && !(pooling_options.open_bucket == OpenBucket::Exclude && is_open_bucket(start, pooling_options, range_end)) {
+        buckets.push((start, sample.into()));
+    }
+
+    buckets
+}
+
+/// Pools a sorted stream of records into buckets starting at `start_time`, or at the start of its day or week
+/// for calendar buckets.
+///
+/// If the first record is before `start_time`, it isn't pooled, but is carried forward into the first bucket
+/// for `PoolingMethod::Start` and gap filling.  `range_end` is the end of the pooled range, if it has one,
+/// for deciding whether the final bucket is open.
+pub fn pool_records<V, I>(records: I, start_time: Timestamp, range_end: Option<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Vec<(Timestamp, V)>> where V: Poolable, I: Iterator<Item = io::Result<(Timestamp, V)>> {
+    pool_weighted_records(records.map(|record| record.map(|record| (record, 1.0))), start_time, range_end, pooling_options)
+}
+
+/// Pools the records a store read out of `range` for it, for stores that can't pool straight from a file.  With a
+/// requested start, the last record of `time_series` before it is carried into the first bucket, and if there's none,
+/// the buckets start where the options' anchor puts them.  Without one, buckets start at the first record.  An
+/// unbounded range is pooled up to the largest timestamp.
+pub fn pool_with_carry<V>(time_series: &dyn TimeSeries, mut records: Vec<(Timestamp, V)>, range: Range<Timestamp>, requested_start: Option<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Vec<(Timestamp, V)>> where V: Poolable {
+    let start_time = match requested_start {
+        Some(start) if records.first().map_or(true, |r| r.0 != start) => match record_before(time_series, start)? {
+            Some(record) => {
+                records.insert(0, record);
+                start
+            },
+            None => match pooling_options.anchor {
+                BucketAnchor::FirstRecord => records.first().map_or(start, |r| r.0),
+                BucketAnchor::RequestedStart => start,
+            },
+        },
+        Some(start) => start,
+        None => records.first().map_or(0, |r| r.0),
+    };
+
+    let range_end = if range.end == Timestamp::max_value() { None } else { Some(range.end) };
+
+    pool_records(records.into_iter().map(Ok), start_time, range_end, pooling_options)
+}
+
+/// The last record before the timestamp, if there is one
+fn record_before<V>(time_series: &dyn TimeSeries, timestamp: Timestamp) -> io::Result<Option<(Timestamp, V)>> where V: 'static + Copy {
+    if timestamp == 0 {
+        return Ok(None);
+    }
+
+    match time_series.retrieve_nearest(timestamp - 1, Some(RetrievalDirection::Backward)) {
+        Ok(retrieval) => Ok(Some(retrieval.into_single::<Timestamp, V>())),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Pools sorted prices into buckets like `pool_records`, to the mean of each bucket weighted by volume.  Each price is
+/// weighted by the volume recorded at the same time, matched in order when several share a timestamp, and prices
+/// without a volume weigh nothing.  Buckets whose prices all weigh nothing get their plain mean.  The pooling method
+/// and field pooling of the options are ignored.
+pub fn pool_weighted<V, W>(prices: &[(Timestamp, V)], volumes: &[(Timestamp, W)], start_time: Timestamp, range_end: Option<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Vec<(Timestamp, V)>> where V: Poolable, W: Poolable {
+    let pooling_options = PoolingOptions {
+        pooling: PoolingMethod::Vwap,
+        field_pooling: None,
+        ..pooling_options
+    };
+
+    let mut volumes = volumes.iter().peekable();
+
+    let records = prices.iter().map(|&price| {
+        while volumes.peek().map_or(false, |volume| volume.0 < price.0) {
+            volumes.next();
+        }
+
+        let weight = match volumes.peek() {
+            Some(&&(timestamp, volume)) if timestamp == price.0 => {
+                volumes.next();
+                volume.weight()
+            },
+            _ => 0.0,
+        };
+
+        Ok((price, weight))
+    });
+
+    pool_weighted_records(records, start_time, range_end, pooling_options)
+}
+
+/// Pools a sorted stream of records, each with the weight it carries into `PoolingMethod::Vwap`
+fn pool_weighted_records<V, I>(mut records: I, start_time: Timestamp, range_end: Option<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Vec<(Timestamp, V)>> where V: Poolable, I: Iterator<Item = io::Result<((Timestamp, V), f64)>> {
+    let mut values: Vec<(Timestamp, V)> = Vec::new();
+
+    let (first_record, first_weight) = match records.next() {
+        Some(record) => record?,
+        None => return Ok(values),
+    };
+
+    let start_time = pooling_options.bucket_start(start_time);
+    let mut bucket = Bucket::new(start_time, pooling_options);
+
+    // If the buckets start before the first record, the first record belongs in them
+    if first_record.0 >= start_time {
+        // Leading buckets have nothing before them to carry forward
+        while bucket.end <= first_record.0 {
+            if pooling_options.gap_fill.is_some() && !pooling_options.is_unknown_bucket(bucket.start) {
+                values.push((bucket.start, V::default()));
+            }
+            bucket.advance(pooling_options);
+        }
+
+        // Records on skipped holidays fall between buckets
+        if first_record.0 >= bucket.start {
+            bucket.add(first_record, first_weight);
+        }
+    }
+
+    let mut last_record = first_record;
+
+    // For the rest of the records
+    for record in records {
+        let (record, weight) = record?;
+
+        // If the record we just read doesn't fit in this bucket,
+        if record.0 >= bucket.end {
+            // end the current bucket and start new ones until the record fits.
+            bucket.conclude(&mut values, last_record, pooling_options)?;
+
+            if bucket.count > 0 {
+                last_record = bucket.last;
+            }
+
+            bucket.advance(pooling_options);
+
+            while bucket.end <= record.0 {
+                bucket.conclude(&mut values, last_record, pooling_options)?;
+                bucket.advance(pooling_options);
+            }
+        }
+
+        if record.0 >= bucket.start {
+            bucket.add(record, weight);
+        }
+    }
+
+    bucket.conclude(&mut values, last_record, pooling_options)?;
+
+    // The feed was up through the buckets between the last record and the heartbeat, so they had no records
+    if let (Some(_), Some(heartbeat)) = (pooling_options.gap_fill, pooling_options.heartbeat) {
+        if bucket.count > 0 {
+            last_record = bucket.last;
+        }
+        bucket.advance(pooling_options);
+
+        while bucket.start <= heartbeat && range_end.map_or(true, |end| bucket.start < end) && bucket.end > bucket.start {
+            bucket.conclude(&mut values, last_record, pooling_options)?;
+            bucket.advance(pooling_options);
+        }
+    }
+
+    if pooling_options.open_bucket == OpenBucket::Exclude {
+        values = split_open_bucket(values, pooling_options, range_end).0;
+    }
+
+    Ok(values)
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    impl Poolable for i32 {
-        fn mean(values: &[Self]) -> Self {
-            (values.iter().sum::<Self>() as f32 / values.len() as f32) as Self
-        }
+    #[test]
+    fn test_interval() {
+        assert_eq!(Interval::millis(500).get(), 500);
+        assert_eq!(Interval::seconds(2).get(), 2_000);
+        assert_eq!(Interval::minutes(5).get(), 300_000);
+        assert_eq!("1h30m".parse::<Interval>().unwrap(), Interval::minutes(90));
+        assert_eq!("250".parse::<Interval>().unwrap(), Interval::millis(250));
 
-        fn sum(values: &[Self]) -> Self {
-            values.iter().sum()
+        assert!(Interval::new(0).is_err());
+        assert!("0s".parse::<Interval>().is_err());
+        assert!("5 fortnights".parse::<Interval>().is_err());
+    }
+
+    #[test]
+    fn test_statistics() {
+        let mut statistics = Statistics::default();
+
+        for &(value, weight) in &[(2.0, 1.0), (4.0, 1.0), (4.0, 1.0), (4.0, 1.0), (5.0, 1.0), (5.0, 1.0), (7.0, 1.0), (9.0, 1.0)] {
+            statistics.fold(value, weight);
         }
+
+        assert_eq!(statistics.value(PoolingMethod::Sum), 40.0);
+        assert_eq!(statistics.value(PoolingMethod::Mean), 5.0);
+        assert_eq!(statistics.value(PoolingMethod::StdDev), 2.0);
+
+        let mut statistics = Statistics::default();
+        statistics.fold(10.0, 3.0);
+        statistics.fold(20.0, 1.0);
+        assert_eq!(statistics.value(PoolingMethod::Vwap), 12.5);
+    }
+
+    #[test]
+    fn test_accumulate() {
+        assert_eq!(accumulate(&[1, 2, 3, 6], PoolingMethod::Mean), 3);
+        assert_eq!(accumulate(&[1, 2, 3, 6], PoolingMethod::Sum), 12);
+        assert_eq!(pool_values(&[1, 2, 3, 6], 0, PoolingMethod::StdDev), 1);
+    }
+
+    #[test]
+    fn test_quantile() {
+        let values = [7, 1, 9, 3, 5, 2, 8, 4, 6, 10];
+        assert_eq!(pool_values(&values, 0, PoolingMethod::Quantile(0.5)), 5);
+        assert_eq!(pool_values(&values, 0, PoolingMethod::Quantile(0.95)), 10);
+        assert_eq!(pool_values(&values, 0, PoolingMethod::Quantile(0.0)), 1);
+        assert_eq!(pool_values(&values, 0, PoolingMethod::Quantile(1.0)), 10);
+
+        // NaN is passed over
+        assert_eq!(quantile(&mut vec![2.0, ::std::f64::NAN, 1.0], 1.0), 2.0);
+        assert_eq!(quantile(&mut Vec::<i32>::new(), 0.5), 0);
+
+        let records: Vec<(Timestamp, i32)> = vec![(0, 4), (1, 1), (2, 3), (3, 2), (10, 8), (11, 6)];
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::Quantile(0.75), ..PoolingOptions::default() };
+        assert_eq!(pool_records(records.iter().map(|&record| Ok(record)), 0, None, pooling_options).unwrap(), vec![(0, 3), (10, 8)]);
+    }
+
+    #[test]
+    fn test_sample_records() {
+        let records: Vec<(Timestamp, i32)> = vec![(0, 1), (1, 2), (2, 3), (15, 4), (30, 5), (31, 6)];
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), ..PoolingOptions::default() };
+
+        assert_eq!(sample_records(&records, 0, None, pooling_options, Sampling::First(2)), vec![
+            (0, vec![(0, 1), (1, 2)]),
+            (10, vec![(15, 4)]),
+            (30, vec![(30, 5), (31, 6)]),
+        ]);
+
+        assert_eq!(sample_records(&records, 0, None, pooling_options, Sampling::Last(1)), vec![
+            (0, vec![(2, 3)]),
+            (10, vec![(15, 4)]),
+            (30, vec![(31, 6)]),
+        ]);
+
+        // The open bucket can be left out, and records before the first bucket always are
+        let pooling_options = PoolingOptions { open_bucket: OpenBucket::Exclude, ..pooling_options };
+        assert_eq!(sample_records(&records, 5, Some(32), pooling_options, Sampling::First(1)), vec![(15, vec![(15, 4)])]);
+
+        assert!(sample_records(&records, 0, None, pooling_options, Sampling::First(0)).is_empty());
+    }
+
+    #[test]
+    fn test_pool_weighted() {
+        let prices: Vec<(Timestamp, f64)> = vec![(0, 10.0), (1, 20.0), (10, 30.0), (10, 40.0), (15, 50.0)];
+        let volumes: Vec<(Timestamp, i32)> = vec![(0, 3), (1, 1), (10, 1), (10, 3)];
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), ..PoolingOptions::default() };
+
+        // The last price has no volume, so it weighs nothing
+        assert_eq!(pool_weighted(&prices, &volumes, 0, None, pooling_options).unwrap(), vec![(0, 12.5), (10, 37.5)]);
+
+        // Without any volume, each bucket is a plain mean
+        assert_eq!(pool_weighted(&prices, &[] as &[(Timestamp, i32)], 0, None, pooling_options).unwrap(), vec![(0, 15.0), (10, 40.0)]);
+    }
+
+    #[test]
+    fn test_summarize_records() {
+        let records: Vec<(Timestamp, i32)> = vec![(10, 4), (12, -2), (15, 9), (20, 3)];
+
+        assert_eq!(summarize_records(records.iter().map(|&record| Ok(record))).unwrap(), Some(Summary {
+            count: 4,
+            min: -2,
+            max: 9,
+            mean: 3,
+            sum: 14,
+            first: (10, 4),
+            last: (20, 3),
+        }));
+        assert_eq!(summarize_records(Vec::<io::Result<(Timestamp, i32)>>::new().into_iter()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_split_open_bucket_at_now() {
+        let buckets: Vec<(Timestamp, i32)> = vec![(0, 1), (10, 2), (20, 3)];
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), ..PoolingOptions::default() };
+
+        // The range runs past the last bucket, so it looks finished
+        assert_eq!(split_open_bucket(buckets.clone(), pooling_options, Some(100)), (buckets.clone(), None));
+
+        // Until the present is known to be inside it
+        let pooling_options = PoolingOptions { now: Some(25), ..pooling_options };
+        assert_eq!(split_open_bucket(buckets.clone(), pooling_options, Some(100)), (vec![(0, 1), (10, 2)], Some((20, 3))));
+
+        // A bucket that ended at the present is complete
+        let pooling_options = PoolingOptions { now: Some(30), ..pooling_options };
+        assert_eq!(split_open_bucket(buckets.clone(), pooling_options, Some(100)), (buckets, None));
+    }
+
+    #[test]
+    fn test_pool_heartbeat() {
+        let records: Vec<(Timestamp, i32)> = vec![(0, 1), (12, 2), (35, 3)];
+        let pool = |pooling_options| pool_records(records.iter().map(|&record| Ok(record)), 0, Some(80), pooling_options).unwrap();
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), gap_fill: Some(GapFillMethod::Previous), ..PoolingOptions::default() };
+        assert_eq!(pool(pooling_options), vec![(0, 1), (10, 2), (20, 2), (30, 3)]);
+
+        // The feed was up until 52, so the buckets through 50 had no trades
+        let pooling_options = PoolingOptions { heartbeat: Some(52), ..pooling_options };
+        assert_eq!(pool(pooling_options), vec![(0, 1), (10, 2), (20, 2), (30, 3), (40, 3), (50, 3)]);
+
+        // Nothing is known of the empty buckets after the heartbeat
+        let pooling_options = PoolingOptions { heartbeat: Some(15), ..pooling_options };
+        assert_eq!(pool(pooling_options), vec![(0, 1), (10, 2), (30, 3)]);
+
+        // Nor is anything filled in without gap filling
+        let pooling_options = PoolingOptions { gap_fill: None, heartbeat: Some(52), ..pooling_options };
+        assert_eq!(pool(pooling_options), vec![(0, 1), (10, 2), (30, 3)]);
     }
 }
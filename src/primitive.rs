@@ -0,0 +1,223 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pooling of primitive numbers.
+//!
+//! Every integer and float type is poolable.  Integers are summed exactly, and their means are taken from the exact
+//! sum, rounding toward zero.  A sum that doesn't fit in the integer's type is reported as an overflow.  Standard
+//! deviations and VWAPs, and everything of floats, go through `Statistics`.  Floats have no total order of their own,
+//! so their highs and lows use IEEE 754's, where NaN sorts past infinity.  Used as the volume field of a multi-value
+//! record, a primitive weighs its own value.
+
+use std::marker::PhantomData;
+
+use pooled_time_series::{Accumulator, Poolable, PoolingMethod, Statistics};
+
+/// Pools a primitive number in one pass
+pub struct PrimitiveAccumulator<V> {
+    pooling: PoolingMethod,
+    statistics: Statistics,
+    /// The exact sum of the integers folded in, or `None` once it's past even an `i128`.  Unused for floats.
+    total: Option<i128>,
+    value: PhantomData<V>,
+}
+
+impl<V> PrimitiveAccumulator<V> {
+    /// The exact sum or mean of the integers folded in, or `None` for the other methods or once the sum is lost
+    fn exact(&self) -> Option<i128> {
+        match self.pooling {
+            PoolingMethod::Sum => self.total,
+            PoolingMethod::Mean if self.statistics.count == 0 => Some(0),
+            PoolingMethod::Mean => self.total.map(|total| total / self.statistics.count as i128),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! integer_accumulator {
+    ($t:ty) => {
+        impl Accumulator<$t> for PrimitiveAccumulator<$t> {
+            fn new(pooling: PoolingMethod) -> Self {
+                Self {
+                    pooling: pooling,
+                    statistics: Statistics::default(),
+                    total: Some(0),
+                    value: PhantomData,
+                }
+            }
+
+            fn fold(&mut self, value: $t, weight: f64) {
+                self.statistics.fold(value as f64, weight);
+                self.total = self.total.and_then(|total| total.checked_add(value as i128));
+            }
+
+            fn finalize(&self) -> $t {
+                match self.exact() {
+                    Some(exact) if exact > <$t>::max_value() as i128 => <$t>::max_value(),
+                    Some(exact) if exact < <$t>::min_value() as i128 => <$t>::min_value(),
+                    Some(exact) => exact as $t,
+                    None => self.statistics.value(self.pooling) as $t,
+                }
+            }
+
+            fn reset(&mut self) {
+                self.statistics = Statistics::default();
+                self.total = Some(0);
+            }
+
+            fn overflowed(&self) -> bool {
+                match (self.pooling, self.exact()) {
+                    (PoolingMethod::Sum, None) | (PoolingMethod::Mean, None) => true,
+                    (_, Some(exact)) => exact > <$t>::max_value() as i128 || exact < <$t>::min_value() as i128,
+                    _ => false,
+                }
+            }
+        }
+    };
+}
+
+macro_rules! float_accumulator {
+    ($t:ty) => {
+        impl Accumulator<$t> for PrimitiveAccumulator<$t> {
+            fn new(pooling: PoolingMethod) -> Self {
+                Self {
+                    pooling: pooling,
+                    statistics: Statistics::default(),
+                    total: None,
+                    value: PhantomData,
+                }
+            }
+
+            fn fold(&mut self, value: $t, weight: f64) {
+                self.statistics.fold(value as f64, weight);
+            }
+
+            fn finalize(&self) -> $t {
+                self.statistics.value(self.pooling) as $t
+            }
+
+            fn reset(&mut self) {
+                self.statistics = Statistics::default();
+            }
+        }
+    };
+}
+
+macro_rules! poolable_integers {
+    ($($t:ty),*) => {
+        $(
+            integer_accumulator!($t);
+
+            impl Poolable for $t {
+                type Accumulator = PrimitiveAccumulator<$t>;
+
+                fn weight(self) -> f64 {
+                    self as f64
+                }
+            }
+        )*
+    };
+}
+
+/// `$bits` is the signed integer as wide as the float
+macro_rules! poolable_floats {
+    ($($t:ty: $bits:ty),*) => {
+        $(
+            float_accumulator!($t);
+
+            impl Poolable for $t {
+                type Accumulator = PrimitiveAccumulator<$t>;
+
+                fn high(values: &[Self]) -> Self {
+                    values.iter().cloned().max_by_key(|&value| total_order!(value, $bits)).unwrap_or_default()
+                }
+
+                fn low(values: &[Self]) -> Self {
+                    values.iter().cloned().min_by_key(|&value| total_order!(value, $bits)).unwrap_or_default()
+                }
+
+                fn weight(self) -> f64 {
+                    self as f64
+                }
+            }
+        )*
+    };
+}
+
+/// Maps a float onto a signed integer that sorts in IEEE 754 total order.  Negative floats have their magnitude
+/// bits flipped, so that they sort in reverse of their bits.
+macro_rules! total_order {
+    ($value:expr, $bits:ty) => {{
+        let bits = $value.to_bits() as $bits;
+        bits ^ ((bits >> (8 * ::std::mem::size_of::<$bits>() - 1)) as $bits & <$bits>::max_value())
+    }};
+}
+
+poolable_integers!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+poolable_floats!(f32: i32, f64: i64);
+
+#[cfg(test)]
+mod tests {
+    use pooled_time_series::{Accumulator, Interval, Poolable, PoolingMethod, PoolingOptions, accumulate, pool_records, pool_values, summarize_records};
+
+    #[test]
+    fn test_integers() {
+        assert_eq!(accumulate(&[1u64, 2, 4], PoolingMethod::Mean), 2);
+        assert_eq!(accumulate(&[1u64, 2, 4], PoolingMethod::Sum), 7);
+        assert_eq!(accumulate(&[-7i32, 2], PoolingMethod::Mean), -2);
+        assert_eq!(pool_values(&[-3i8, 5, 1], 0, PoolingMethod::High), 5);
+        assert_eq!(pool_values(&[-3i8, 5, 1], 0, PoolingMethod::Low), -3);
+        assert_eq!(10u32.weight(), 10.0);
+
+        // Sums and means are exact past the precision of a float
+        assert_eq!(u64::sum(&[9_007_199_254_740_993]), 9_007_199_254_740_993);
+        assert_eq!(u64::sum(&[9_007_199_254_740_992, 1]), 9_007_199_254_740_993);
+        assert_eq!(u64::mean(&[u64::max_value(), u64::max_value() - 2]), u64::max_value() - 1);
+        assert_eq!(i64::mean(&[i64::max_value(), i64::max_value()]), i64::max_value());
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let mut accumulator = <i64 as Poolable>::Accumulator::new(PoolingMethod::Sum);
+        accumulator.fold(i64::max_value(), 1.0);
+        assert!(!accumulator.overflowed());
+
+        accumulator.fold(1, 1.0);
+        assert!(accumulator.overflowed());
+        assert_eq!(accumulator.finalize(), i64::max_value());
+
+        accumulator.reset();
+        accumulator.fold(1, 1.0);
+        assert!(!accumulator.overflowed());
+
+        // Pooling reports the overflow rather than passing on a wrong value
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::Sum, ..PoolingOptions::default() };
+        assert!(pool_records(vec![Ok((0, u8::max_value())), Ok((1, 1))].into_iter(), 0, None, pooling_options).is_err());
+        assert_eq!(pool_records(vec![Ok((0, u8::max_value())), Ok((10, 1))].into_iter(), 0, None, pooling_options).unwrap(), vec![(0, 255), (10, 1)]);
+        assert!(summarize_records(vec![Ok((0, u8::max_value())), Ok((1, 1))].into_iter()).is_err());
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(accumulate(&[1.0f64, 2.0, 4.5], PoolingMethod::Sum), 7.5);
+        assert_eq!(accumulate(&[1.0f32, 2.0], PoolingMethod::Mean), 1.5);
+
+        assert_eq!(f64::high(&[-1.0, -0.0, 0.0, -5.0]).to_bits(), 0.0f64.to_bits());
+        assert_eq!(f64::low(&[-1.0, -0.0, 0.0, -5.0]), -5.0);
+        assert_eq!(f64::low(&[2.0, ::std::f64::NEG_INFINITY]), ::std::f64::NEG_INFINITY);
+        assert!(f64::high(&[1.0, ::std::f64::NAN, ::std::f64::INFINITY]).is_nan());
+        assert_eq!(f32::high(&[]), 0.0);
+    }
+}
@@ -0,0 +1,768 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp;
+use std::io;
+
+use calendar::{CalendarInterval, HolidayCalendar, HolidayPolicy};
+use cursor::{self, Cursor, Page};
+use dynamic::{Value, ValueType};
+use key_value_store::Retrieval;
+use indicator::{self, Bands, Indicator, Numeric};
+use pooled_time_series::{self, BucketAnchor, FieldPooling, GapFillMethod, Interval, OpenBucket, Poolable, PooledTimeSeries, PoolingMethod, PoolingOptions, Sampling, split_open_bucket};
+use schema::{SourceFilter, Sourced};
+use stream::ResumeToken;
+use time_series::{RetrievalDirection, TimeSeries, TimeUnit, Timestamp};
+
+/// A post-processing step applied to the records of a query, in order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transform {
+    /// Keep at most this many records
+    Limit(usize),
+    /// Drop this many records from the front
+    Skip(usize),
+    /// Reverse the order of the records
+    Reverse,
+}
+
+impl Transform {
+    pub fn apply<V>(&self, mut records: Vec<(Timestamp, V)>) -> Vec<(Timestamp, V)> {
+        match *self {
+            Transform::Limit(count) => records.truncate(count),
+            Transform::Skip(count) => {
+                let count = count.min(records.len());
+                records.drain(..count);
+            },
+            Transform::Reverse => records.reverse(),
+        }
+
+        records
+    }
+}
+
+/// A composable description of a retrieval.
+///
+/// Without an interval, a query retrieves raw records.  With one, it pools them into buckets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    /// The channel to query, as "market/symbol/channel"
+    pub source: String,
+    /// The inclusive start of the range, or `None` to start at the first record
+    pub start: Option<Timestamp>,
+    /// The exclusive end of the range, or `None` to end after the last record
+    pub end: Option<Timestamp>,
+    /// The size of each bucket, or `None` to retrieve raw records.  Pooling fails if it's zero.
+    pub interval: Option<Timestamp>,
+    pub pooling: PoolingMethod,
+    pub gap_fill: Option<GapFillMethod>,
+    pub field_pooling: Option<FieldPooling>,
+    pub anchor: BucketAnchor,
+    pub open_bucket: OpenBucket,
+    /// Days or weeks in a time zone, instead of buckets of a fixed interval
+    pub calendar: Option<CalendarInterval>,
+    /// The holidays of the calendar and what to do with their buckets
+    pub holidays: Option<(&'static HolidayCalendar, HolidayPolicy)>,
+    /// The unit of the range and interval
+    pub unit: TimeUnit,
+    /// When the feed was last known to be up.  See `PoolingOptions::heartbeat`.
+    pub heartbeat: Option<Timestamp>,
+    /// The present, for telling when the last bucket is still open.  See `PoolingOptions::now`.
+    pub now: Option<Timestamp>,
+    pub transform: Vec<Transform>,
+    /// Indicators computed from the records, in order, before the transforms are applied.  Only used by
+    /// `evaluate_indicators`, `evaluate_pooled_indicators`, and the band evaluations.
+    pub indicators: Vec<Indicator>,
+    /// Bands computed from the records after the indicators.  Only used by `evaluate_bands` and
+    /// `evaluate_pooled_bands`.
+    pub bands: Option<Bands>,
+}
+
+impl Query {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            start: None,
+            end: None,
+            interval: None,
+            pooling: PoolingMethod::End,
+            gap_fill: None,
+            field_pooling: None,
+            anchor: BucketAnchor::FirstRecord,
+            open_bucket: OpenBucket::Include,
+            calendar: None,
+            holidays: None,
+            unit: TimeUnit::Milliseconds,
+            heartbeat: None,
+            now: None,
+            transform: Vec::new(),
+            indicators: Vec::new(),
+            bands: None,
+        }
+    }
+
+    pub fn from(mut self, start: Timestamp) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    pub fn to(mut self, end: Timestamp) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    pub fn interval(mut self, interval: Timestamp) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    pub fn pooling(mut self, pooling: PoolingMethod) -> Self {
+        self.pooling = pooling;
+        self
+    }
+
+    pub fn gap_fill(mut self, gap_fill: GapFillMethod) -> Self {
+        self.gap_fill = Some(gap_fill);
+        self
+    }
+
+    pub fn field_pooling(mut self, field_pooling: FieldPooling) -> Self {
+        self.field_pooling = Some(field_pooling);
+        self
+    }
+
+    pub fn anchor(mut self, anchor: BucketAnchor) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    pub fn open_bucket(mut self, open_bucket: OpenBucket) -> Self {
+        self.open_bucket = open_bucket;
+        self
+    }
+
+    /// Pools into days or weeks.  The interval becomes their usual length.
+    pub fn calendar(mut self, calendar: CalendarInterval) -> Self {
+        self.interval = Some(calendar.nominal(self.unit));
+        self.calendar = Some(calendar);
+        self
+    }
+
+    /// Skips or merges the calendar buckets that fall on holidays
+    pub fn holidays(mut self, holidays: &'static HolidayCalendar, policy: HolidayPolicy) -> Self {
+        self.holidays = Some((holidays, policy));
+        self
+    }
+
+    /// Leaves the empty buckets after a heartbeat out as unknown, and gap fills up to it
+    pub fn heartbeat(mut self, heartbeat: Timestamp) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// Treats buckets that end after `now` as open, even if the range runs past them
+    pub fn now(mut self, now: Timestamp) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// Uses the range, interval, and pooling of existing pooling options
+    pub fn with_pooling_options(mut self, pooling_options: PoolingOptions) -> Self {
+        self.interval = Some(pooling_options.interval.get());
+        self.pooling = pooling_options.pooling;
+        self.gap_fill = pooling_options.gap_fill;
+        self.field_pooling = pooling_options.field_pooling;
+        self.anchor = pooling_options.anchor;
+        self.open_bucket = pooling_options.open_bucket;
+        self.calendar = pooling_options.calendar;
+        self.holidays = pooling_options.holidays;
+        self.unit = pooling_options.unit;
+        self.heartbeat = pooling_options.heartbeat;
+        self.now = pooling_options.now;
+        self
+    }
+
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.transform.push(transform);
+        self
+    }
+
+    pub fn indicator(mut self, indicator: Indicator) -> Self {
+        self.indicators.push(indicator);
+        self
+    }
+
+    pub fn bands(mut self, bands: Bands) -> Self {
+        self.bands = Some(bands);
+        self
+    }
+
+    /// Converts the range and interval, which are in milliseconds as the query language gives them, to the unit of
+    /// the series the query will run against
+    pub fn in_unit(mut self, unit: TimeUnit) -> Self {
+        let from = self.unit;
+        let convert = |value| from.convert(value, unit);
+
+        self.start = self.start.map(&convert);
+        self.end = self.end.map(&convert);
+        self.interval = self.interval.map(&convert);
+        self.heartbeat = self.heartbeat.map(&convert);
+        self.now = self.now.map(&convert);
+        self.unit = unit;
+        self
+    }
+
+    /// The pooling options this query will use, if it pools at all.  Fails if the interval is zero.
+    pub fn pooling_options(&self) -> io::Result<Option<PoolingOptions>> {
+        let interval = match self.interval {
+            Some(interval) => Interval::new(interval)?,
+            None => return Ok(None),
+        };
+
+        Ok(Some(PoolingOptions {
+            interval: interval,
+            pooling: self.pooling,
+            gap_fill: self.gap_fill,
+            field_pooling: self.field_pooling,
+            anchor: self.anchor,
+            open_bucket: self.open_bucket,
+            calendar: self.calendar,
+            holidays: self.holidays,
+            unit: self.unit,
+            heartbeat: self.heartbeat,
+            now: self.now,
+        }))
+    }
+
+    /// Widens the interval just enough that the query gives at most `max_points` results, pooling a raw query if it
+    /// would retrieve more records than that.  How many records a raw query would retrieve is estimated from the
+    /// average density of the whole series, so a burst inside the range can push it over.  Calendar queries are left
+    /// as they are.  Fails if `max_points` is zero.
+    pub fn downsample<V>(mut self, time_series: &dyn TimeSeries, max_points: usize) -> io::Result<Self> where V: 'static {
+        if max_points == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Max points must be positive"));
+        }
+
+        if self.calendar.is_some() || time_series.len() == 0 {
+            return Ok(self);
+        }
+
+        let first = time_series.retrieve_nearest(0, Some(RetrievalDirection::Forward))?.as_single::<Timestamp, V>().map(|record| record.0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Query downsampled with the wrong value type"))?;
+        let last = time_series.last_key().and_then(|key| key.downcast_ref::<Timestamp>().cloned()).unwrap_or(first);
+
+        // Only the part of the range that has records counts
+        let start = self.start.map_or(first, |start| cmp::max(start, first));
+        let end = self.end.map_or(last + 1, |end| cmp::min(end, last + 1));
+        if end <= start {
+            return Ok(self);
+        }
+
+        let span = end - start;
+        if self.interval.is_none() {
+            let records = time_series.len() as f64 * span as f64 / (last - first + 1) as f64;
+            if records <= max_points as f64 {
+                return Ok(self);
+            }
+        }
+
+        // Buckets start at the range start or the first record after it, so no more than this many fit in the range
+        let interval = (span + max_points as Timestamp - 1) / max_points as Timestamp;
+        self.interval = Some(self.interval.map_or(interval, |current| cmp::max(current, interval)));
+        Ok(self)
+    }
+
+    /// Evaluates a raw query against a time series.  Fails if the query asks for pooling.
+    pub fn evaluate<V>(&self, time_series: &dyn TimeSeries) -> io::Result<Vec<(Timestamp, V)>> where V: 'static {
+        self.finish(self.retrieve(time_series)?)
+    }
+
+    /// Evaluates the query against a pooled time series, pooling only if the query has an interval.
+    pub fn evaluate_pooled<V>(&self, pooled_time_series: &dyn PooledTimeSeries) -> io::Result<Vec<(Timestamp, V)>> where V: 'static {
+        self.finish(self.retrieve_pooled(pooled_time_series)?)
+    }
+
+    /// Evaluates a raw query against a time series of `value_type` values, whatever their Rust type
+    pub fn evaluate_values(&self, time_series: &dyn TimeSeries, value_type: ValueType) -> io::Result<Vec<(Timestamp, Value)>> {
+        self.finish_values(self.retrieve(time_series)?, value_type)
+    }
+
+    /// Evaluates the query against a pooled time series of `value_type` values, pooling only if the query has an
+    /// interval.
+    pub fn evaluate_pooled_values(&self, pooled_time_series: &dyn PooledTimeSeries, value_type: ValueType) -> io::Result<Vec<(Timestamp, Value)>> {
+        self.finish_values(self.retrieve_pooled(pooled_time_series)?, value_type)
+    }
+
+    /// Evaluates a raw query against a time series and computes its indicators
+    pub fn evaluate_indicators<V>(&self, time_series: &dyn TimeSeries) -> io::Result<Vec<(Timestamp, f64)>> where V: 'static + Numeric {
+        self.finish_indicators::<V>(self.retrieve(time_series)?)
+    }
+
+    /// Evaluates the query against a pooled time series and computes its indicators, pooling only if the query has
+    /// an interval.
+    pub fn evaluate_pooled_indicators<V>(&self, pooled_time_series: &dyn PooledTimeSeries) -> io::Result<Vec<(Timestamp, f64)>> where V: 'static + Numeric {
+        self.finish_indicators::<V>(self.retrieve_pooled(pooled_time_series)?)
+    }
+
+    /// Evaluates a raw query against a time series and computes its bands.  Fails if the query has no bands.
+    pub fn evaluate_bands<V>(&self, time_series: &dyn TimeSeries) -> io::Result<Vec<(Timestamp, (f64, f64, f64))>> where V: 'static + Numeric {
+        self.finish_bands::<V>(self.retrieve(time_series)?)
+    }
+
+    /// Evaluates the query against a pooled time series and computes its bands, pooling only if the query has an
+    /// interval.  Fails if the query has no bands.
+    pub fn evaluate_pooled_bands<V>(&self, pooled_time_series: &dyn PooledTimeSeries) -> io::Result<Vec<(Timestamp, (f64, f64, f64))>> where V: 'static + Numeric {
+        self.finish_bands::<V>(self.retrieve_pooled(pooled_time_series)?)
+    }
+
+    /// Evaluates a pooled query of prices to the mean of each bucket weighted by the volumes recorded with them, as
+    /// with `pool_weighted`.  The query's pooling method is ignored.  Fails if the query has no interval.
+    pub fn evaluate_weighted<V, W>(&self, prices: &dyn TimeSeries, volumes: &dyn TimeSeries) -> io::Result<Vec<(Timestamp, V)>> where V: Poolable, W: Poolable {
+        let pooling_options = match self.pooling_options()? {
+            Some(pooling_options) => pooling_options,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Weighted query has no interval")),
+        };
+
+        let raw = self.raw();
+        let prices = raw.evaluate::<V>(prices)?;
+        let volumes = raw.evaluate::<W>(volumes)?;
+
+        let start_time = match self.first_bucket(&prices) {
+            Some(start_time) => start_time,
+            None => return Ok(Vec::new()),
+        };
+
+        let records = pooled_time_series::pool_weighted(&prices, &volumes, start_time, self.end, pooling_options)?;
+        Ok(self.transform.iter().fold(records, |records, transform| transform.apply(records)))
+    }
+
+    /// Evaluates a pooled query to a sample of the raw records of each bucket instead of a pooled value, as with
+    /// `sample_records`.  Fails if the query has no interval.
+    pub fn evaluate_samples<V>(&self, time_series: &dyn TimeSeries, sampling: Sampling) -> io::Result<Vec<(Timestamp, Vec<(Timestamp, V)>)>> where V: 'static + Copy {
+        let pooling_options = match self.pooling_options()? {
+            Some(pooling_options) => pooling_options,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Sampled query has no interval")),
+        };
+
+        let records = self.raw().evaluate::<V>(time_series)?;
+
+        let start_time = match self.first_bucket(&records) {
+            Some(start_time) => start_time,
+            None => return Ok(Vec::new()),
+        };
+
+        let samples = pooled_time_series::sample_records(&records, start_time, self.end, pooling_options, sampling);
+        Ok(self.transform.iter().fold(samples, |samples, transform| transform.apply(samples)))
+    }
+
+    /// Evaluates the query against a time series of sourced values, leaving out the records of the sources that
+    /// `filter` excludes before pooling them, if the query has an interval.
+    pub fn evaluate_sourced<V>(&self, time_series: &dyn TimeSeries, filter: &SourceFilter) -> io::Result<Vec<(Timestamp, Sourced<V>)>> where V: Poolable {
+        let records = self.raw().evaluate::<Sourced<V>>(time_series)?
+            .into_iter()
+            .filter(|record| filter.includes(record.1.source))
+            .collect::<Vec<_>>();
+
+        let records = match self.pooling_options()? {
+            Some(pooling_options) => match self.first_bucket(&records) {
+                Some(start_time) => pooled_time_series::pool_records(records.into_iter().map(Ok), start_time, self.end, pooling_options)?,
+                None => return Ok(Vec::new()),
+            },
+            None => records,
+        };
+
+        Ok(self.transform.iter().fold(records, |records, transform| transform.apply(records)))
+    }
+
+    /// The query without its interval or transforms, for pooling raw records outside of a store
+    fn raw(&self) -> Self {
+        Self {
+            interval: None,
+            transform: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// The anchor that placed this query's first bucket, which is its own when its range starts before the first
+    /// record of the series.  `None` if it has no start, if there's a record at or before its start, so the buckets
+    /// start there whatever the anchor, or if there are no records in its range at all.
+    pub fn applied_anchor<V>(&self, time_series: &dyn TimeSeries) -> io::Result<Option<BucketAnchor>> where V: 'static + Copy {
+        let start = match self.start {
+            Some(start) if time_series.len() > 0 => start,
+            _ => return Ok(None),
+        };
+
+        match time_series.retrieve_nearest(start, Some(RetrievalDirection::Backward)) {
+            Ok(_) => return Ok(None),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error),
+        }
+
+        let first = match time_series.retrieve_nearest(start, Some(RetrievalDirection::Forward)) {
+            Ok(retrieval) => retrieval.into_single::<Timestamp, V>().0,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        Ok(if self.end.map_or(true, |end| first < end) { Some(self.anchor) } else { None })
+    }
+
+    /// Where buckets of the raw records of this query start, given its anchor, or `None` if there are no records
+    fn first_bucket<V>(&self, records: &[(Timestamp, V)]) -> Option<Timestamp> {
+        match (self.start, records.first()) {
+            (Some(start), Some(_)) if self.anchor == BucketAnchor::RequestedStart => Some(start),
+            (_, first) => first.map(|first| first.0),
+        }
+    }
+
+    /// Evaluates a pooled query, returning the final bucket separately if it's still open.
+    /// Transforms are applied to the complete buckets only.
+    pub fn evaluate_live<V>(&self, pooled_time_series: &dyn PooledTimeSeries) -> io::Result<(Vec<(Timestamp, V)>, Option<(Timestamp, V)>)> where V: 'static {
+        let pooling_options = match self.pooling_options()? {
+            Some(pooling_options) => pooling_options,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Live query has no interval")),
+        };
+
+        let records = self.retrieve_pooled(pooled_time_series)?.try_into_vec::<Timestamp, V>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Query evaluated with the wrong value type"))?;
+        let (closed, open) = split_open_bucket(records, pooling_options, self.end);
+
+        let open = if self.open_bucket == OpenBucket::Exclude { None } else { open };
+
+        Ok((self.transform.iter().fold(closed, |records, transform| transform.apply(records)), open))
+    }
+
+    /// Re-pools just the bucket of a pooled query that starts at `bucket_start`, ignoring the query's range
+    /// and transforms.
+    pub fn evaluate_bucket<V>(&self, pooled_time_series: &dyn PooledTimeSeries, bucket_start: Timestamp) -> io::Result<Option<(Timestamp, V)>> where V: 'static {
+        let pooling_options = match self.pooling_options()? {
+            Some(pooling_options) => pooling_options,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Bucket query has no interval")),
+        };
+
+        let records = pooled_time_series.pool_bucket(bucket_start, pooling_options)?.try_into_vec::<Timestamp, V>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Query evaluated with the wrong value type"))?;
+
+        Ok(records.into_iter().find(|record| record.0 == bucket_start))
+    }
+
+    /// Evaluates a page of at most `size` results of a raw query, starting after `cursor` or at the first result.
+    /// Fails if the cursor is for a different query.
+    pub fn page<V>(&self, time_series: &dyn TimeSeries, size: usize, cursor: Option<&Cursor>) -> io::Result<Page<V>> where V: 'static {
+        let token = self.page_token(size, cursor)?;
+
+        // Without transforms, only the records after the cursor need to be read
+        match token.position {
+            Some(position) if self.transform.is_empty() => {
+                let start = self.start.map_or(position.saturating_add(1), |start| cmp::max(start, position.saturating_add(1)));
+                Ok(cursor::paginate(self, self.clone().from(start).evaluate(time_series)?, size, token, true))
+            },
+            _ => Ok(cursor::paginate(self, self.evaluate(time_series)?, size, token, false)),
+        }
+    }
+
+    /// Evaluates a page of at most `size` results of the query against a pooled time series, pooling only if the
+    /// query has an interval.  Fails if the cursor is for a different query.
+    pub fn page_pooled<V>(&self, pooled_time_series: &dyn PooledTimeSeries, size: usize, cursor: Option<&Cursor>) -> io::Result<Page<V>> where V: 'static {
+        if self.interval.is_none() {
+            return self.page(pooled_time_series.as_time_series(), size, cursor);
+        }
+
+        // Buckets depend on where pooling starts, so the whole range is pooled
+        let token = self.page_token(size, cursor)?;
+        Ok(cursor::paginate(self, self.evaluate_pooled(pooled_time_series)?, size, token, false))
+    }
+
+    fn page_token(&self, size: usize, cursor: Option<&Cursor>) -> io::Result<ResumeToken> {
+        if size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Page size must be positive"));
+        }
+
+        cursor.map_or(Ok(ResumeToken::default()), |cursor| cursor.token(self))
+    }
+
+    /// Performs the retrieval described by a raw query without applying its transforms.
+    pub fn retrieve(&self, time_series: &dyn TimeSeries) -> io::Result<Retrieval> {
+        if self.interval.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Pooled query evaluated against a time series that can't pool"));
+        }
+
+        match (self.start, self.end) {
+            (None, None) => time_series.retrieve_all(),
+            (Some(start), None) => time_series.retrieve_from(start),
+            (None, Some(end)) => time_series.retrieve_to(end),
+            (Some(start), Some(end)) => time_series.retrieve_range(start..end),
+        }
+    }
+
+    /// Performs the retrieval described by the query without applying its transforms,
+    /// pooling only if the query has an interval.
+    pub fn retrieve_pooled(&self, pooled_time_series: &dyn PooledTimeSeries) -> io::Result<Retrieval> {
+        let pooling_options = match self.pooling_options()? {
+            Some(pooling_options) => pooling_options,
+            None => return self.retrieve(pooled_time_series.as_time_series()),
+        };
+
+        match (self.start, self.end) {
+            (None, None) => pooled_time_series.pool_all(pooling_options),
+            (Some(start), None) => pooled_time_series.pool_from(start, pooling_options),
+            (None, Some(end)) => pooled_time_series.pool_to(end, pooling_options),
+            (Some(start), Some(end)) => pooled_time_series.pool_range(start..end, pooling_options),
+        }
+    }
+
+    fn finish_values(&self, retrieval: Retrieval, value_type: ValueType) -> io::Result<Vec<(Timestamp, Value)>> {
+        let records = value_type.records(retrieval)?;
+        Ok(self.transform.iter().fold(records, |records, transform| transform.apply(records)))
+    }
+
+    fn finish<V>(&self, retrieval: Retrieval) -> io::Result<Vec<(Timestamp, V)>> where V: 'static {
+        let records = retrieval.try_into_vec::<Timestamp, V>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Query evaluated with the wrong value type"))?;
+
+        Ok(self.transform.iter().fold(records, |records, transform| transform.apply(records)))
+    }
+
+    fn finish_indicators<V>(&self, retrieval: Retrieval) -> io::Result<Vec<(Timestamp, f64)>> where V: 'static + Numeric {
+        let values = self.indicator_values::<V>(retrieval)?;
+
+        Ok(self.transform.iter().fold(values, |values, transform| transform.apply(values)))
+    }
+
+    fn finish_bands<V>(&self, retrieval: Retrieval) -> io::Result<Vec<(Timestamp, (f64, f64, f64))>> where V: 'static + Numeric {
+        let bands = self.bands.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Query has no bands"))?;
+        let bands = indicator::bands(&self.indicator_values::<V>(retrieval)?, bands)?;
+
+        Ok(self.transform.iter().fold(bands, |bands, transform| transform.apply(bands)))
+    }
+
+    /// The records of a retrieval as floats, with the indicators computed
+    fn indicator_values<V>(&self, retrieval: Retrieval) -> io::Result<Vec<(Timestamp, f64)>> where V: 'static + Numeric {
+        let records = retrieval.try_into_vec::<Timestamp, V>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Query evaluated with the wrong value type"))?;
+
+        let mut values = records.into_iter().map(|(timestamp, value)| (timestamp, value.to_f64())).collect::<Vec<_>>();
+        for &indicator in &self.indicators {
+            values = indicator::transform(&values, indicator)?;
+        }
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use key_value_store::KeyValueStore;
+    use schema::MIXED_SOURCES;
+    use storage::FileStorage;
+    use util::SetupFile;
+
+    #[test]
+    fn test_query_in_unit() {
+        let query = Query::new("gemini/btcusd/trades").from(1_546_300_800_000).interval(300_000).in_unit(TimeUnit::Microseconds);
+
+        assert_eq!(query.start, Some(1_546_300_800_000_000));
+        assert_eq!(query.end, None);
+        assert_eq!(query.interval, Some(300_000_000));
+        assert_eq!(query.clone().in_unit(TimeUnit::Microseconds), query);
+    }
+
+    #[test]
+    fn test_query_evaluate() {
+        let _setup_file = SetupFile::new("test_query_evaluate");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_query_evaluate").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
+        fs.store(Box::new(40 as Timestamp), Box::new(4 as i32)).unwrap();
+
+        let query = Query::new("m/s/c").from(15).to(41);
+        assert_eq!(query.evaluate::<i32>(&fs).unwrap(), vec![(20, 2), (30, 3), (40, 4)]);
+
+        let query = query.transform(Transform::Reverse).transform(Transform::Limit(2));
+        assert_eq!(query.evaluate::<i32>(&fs).unwrap(), vec![(40, 4), (30, 3)]);
+
+        assert!(Query::new("m/s/c").evaluate::<u64>(&fs).is_err());
+
+        // Indicators are computed before the transforms
+        let query = Query::new("m/s/c").indicator(Indicator::Sma(2)).transform(Transform::Limit(2));
+        assert_eq!(query.evaluate_indicators::<i32>(&fs).unwrap(), vec![(20, 1.5), (30, 2.5)]);
+    }
+
+    #[test]
+    fn test_query_downsample() {
+        let _setup_file = SetupFile::new("test_query_downsample");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_query_downsample").unwrap();
+
+        for timestamp in 0..100 {
+            fs.store(Box::new(timestamp * 10 as Timestamp), Box::new(timestamp as i32)).unwrap();
+        }
+
+        // Few enough records are left raw
+        let query = Query::new("m/s/c").from(100).to(190).downsample::<i32>(&fs, 10).unwrap();
+        assert_eq!(query.interval, None);
+
+        // Too many are pooled
+        let query = Query::new("m/s/c").from(100).to(500).downsample::<i32>(&fs, 10).unwrap();
+        assert_eq!(query.interval, Some(40));
+        assert_eq!(query.evaluate_pooled::<i32>(&fs).unwrap().len(), 10);
+
+        // The range is clipped to the records, and an interval that's already wide enough is kept
+        let query = Query::new("m/s/c").to(10_000).interval(500).downsample::<i32>(&fs, 10).unwrap();
+        assert_eq!(query.interval, Some(500));
+        let query = Query::new("m/s/c").interval(50).downsample::<i32>(&fs, 7).unwrap();
+        assert_eq!(query.interval, Some(142));
+        assert!(query.evaluate_pooled::<i32>(&fs).unwrap().len() <= 7);
+
+        assert!(Query::new("m/s/c").downsample::<i32>(&fs, 0).is_err());
+        assert!(Query::new("m/s/c").downsample::<u64>(&fs, 10).is_err());
+    }
+
+    #[test]
+    fn test_query_evaluate_weighted() {
+        let _prices_file = SetupFile::new("test_query_evaluate_weighted_prices");
+        let _volumes_file = SetupFile::new("test_query_evaluate_weighted_volumes");
+
+        let mut prices = FileStorage::<Timestamp, i32>::new("test_query_evaluate_weighted_prices").unwrap();
+        let mut volumes = FileStorage::<Timestamp, i32>::new("test_query_evaluate_weighted_volumes").unwrap();
+
+        for &(timestamp, price, volume) in &[(10, 10, 3), (12, 20, 1), (20, 30, 5), (25, 60, 5)] {
+            prices.store(Box::new(timestamp as Timestamp), Box::new(price as i32)).unwrap();
+            volumes.store(Box::new(timestamp as Timestamp), Box::new(volume as i32)).unwrap();
+        }
+
+        let query = Query::new("m/s/c").interval(10).transform(Transform::Reverse);
+        assert_eq!(query.evaluate_weighted::<i32, i32>(&prices, &volumes).unwrap(), vec![(20, 45), (10, 12)]);
+
+        let query = Query::new("m/s/c").from(0).interval(10).anchor(BucketAnchor::RequestedStart);
+        assert_eq!(query.evaluate_weighted::<i32, i32>(&prices, &volumes).unwrap(), vec![(10, 12), (20, 45)]);
+
+        assert!(Query::new("m/s/c").evaluate_weighted::<i32, i32>(&prices, &volumes).is_err());
+    }
+
+    #[test]
+    fn test_query_evaluate_samples() {
+        let _setup_file = SetupFile::new("test_query_evaluate_samples");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_query_evaluate_samples").unwrap();
+
+        for &(timestamp, value) in &[(10, 1), (12, 2), (14, 3), (25, 4)] {
+            fs.store(Box::new(timestamp as Timestamp), Box::new(value as i32)).unwrap();
+        }
+
+        let query = Query::new("m/s/c").interval(10);
+        assert_eq!(query.evaluate_samples::<i32>(&fs, Sampling::Last(2)).unwrap(), vec![(10, vec![(12, 2), (14, 3)]), (20, vec![(25, 4)])]);
+
+        let query = query.from(11).transform(Transform::Limit(1));
+        assert_eq!(query.evaluate_samples::<i32>(&fs, Sampling::First(1)).unwrap(), vec![(12, vec![(12, 2)])]);
+
+        assert!(Query::new("m/s/c").evaluate_samples::<i32>(&fs, Sampling::First(1)).is_err());
+    }
+
+    #[test]
+    fn test_query_evaluate_sourced() {
+        let _setup_file = SetupFile::new("test_query_evaluate_sourced");
+
+        let mut fs = FileStorage::<Timestamp, Sourced<i32>>::new("test_query_evaluate_sourced").unwrap();
+
+        for &(timestamp, value, source) in &[(10, 5, 1), (12, 7, 2), (15, 100, 3), (21, 6, 1), (25, 8, 1)] {
+            fs.store(Box::new(timestamp as Timestamp), Box::new(Sourced::new(value as i32, source))).unwrap();
+        }
+
+        // Source 3 reported a bad print
+        let filter = SourceFilter::new().exclude(3);
+
+        let query = Query::new("m/s/c").from(12);
+        assert_eq!(query.evaluate_sourced::<i32>(&fs, &filter).unwrap(), vec![(12, Sourced::new(7, 2)), (21, Sourced::new(6, 1)), (25, Sourced::new(8, 1))]);
+
+        let query = Query::new("m/s/c").interval(10).pooling(PoolingMethod::Mean);
+        assert_eq!(query.evaluate_sourced::<i32>(&fs, &filter).unwrap(), vec![(10, Sourced::new(6, MIXED_SOURCES)), (20, Sourced::new(7, 1))]);
+
+        let query = Query::new("m/s/c").interval(10).pooling(PoolingMethod::High).transform(Transform::Limit(1));
+        assert_eq!(query.evaluate_sourced::<i32>(&fs, &SourceFilter::new()).unwrap(), vec![(10, Sourced::new(100, 3))]);
+    }
+
+    #[test]
+    fn test_query_evaluate_pooled() {
+        let _setup_file = SetupFile::new("test_query_evaluate_pooled");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_query_evaluate_pooled").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(14 as Timestamp), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(3 as i32)).unwrap();
+        fs.store(Box::new(26 as Timestamp), Box::new(4 as i32)).unwrap();
+
+        let query = Query::new("m/s/c").interval(10).pooling(PoolingMethod::Sum).transform(Transform::Skip(1));
+        assert_eq!(query.evaluate_pooled::<i32>(&fs).unwrap(), vec![(20, 7)]);
+
+        let query = Query::new("m/s/c").from(14);
+        assert_eq!(query.evaluate_pooled::<i32>(&fs).unwrap(), vec![(14, 2), (20, 3), (26, 4)]);
+
+        assert!(Query::new("m/s/c").interval(0).evaluate_pooled::<i32>(&fs).is_err());
+    }
+
+    #[test]
+    fn test_query_applied_anchor() {
+        let _setup_file = SetupFile::new("test_query_applied_anchor");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_query_applied_anchor").unwrap();
+        assert_eq!(Query::new("m/s/c").from(5).applied_anchor::<i32>(&fs).unwrap(), None);
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+
+        // Only a range starting before the first record is anchored
+        let query = Query::new("m/s/c").interval(10).anchor(BucketAnchor::RequestedStart);
+        assert_eq!(query.clone().from(5).applied_anchor::<i32>(&fs).unwrap(), Some(BucketAnchor::RequestedStart));
+        assert_eq!(query.clone().from(5).anchor(BucketAnchor::FirstRecord).applied_anchor::<i32>(&fs).unwrap(), Some(BucketAnchor::FirstRecord));
+        assert_eq!(query.clone().from(10).applied_anchor::<i32>(&fs).unwrap(), None);
+        assert_eq!(query.clone().from(15).applied_anchor::<i32>(&fs).unwrap(), None);
+        assert_eq!(query.clone().applied_anchor::<i32>(&fs).unwrap(), None);
+
+        // Nor is one that ends before it
+        assert_eq!(query.from(0).to(10).applied_anchor::<i32>(&fs).unwrap(), None);
+    }
+
+    #[test]
+    fn test_query_evaluate_live() {
+        let _setup_file = SetupFile::new("test_query_evaluate_live");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_query_evaluate_live").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(14 as Timestamp), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(3 as i32)).unwrap();
+
+        let query = Query::new("m/s/c").from(10).interval(10).pooling(PoolingMethod::Sum).open_bucket(OpenBucket::Label);
+        assert_eq!(query.evaluate_live::<i32>(&fs).unwrap(), (vec![(10, 3)], Some((20, 3))));
+
+        // A new record arrives in the open bucket
+        fs.store(Box::new(25 as Timestamp), Box::new(4 as i32)).unwrap();
+        assert_eq!(query.evaluate_bucket::<i32>(&fs, 20).unwrap(), Some((20, 7)));
+
+        let query = query.open_bucket(OpenBucket::Exclude);
+        assert_eq!(query.evaluate_pooled::<i32>(&fs).unwrap(), vec![(10, 3)]);
+
+        // A range into the future leaves the bucket holding the present open
+        let query = query.to(40).open_bucket(OpenBucket::Label).now(26);
+        assert_eq!(query.evaluate_live::<i32>(&fs).unwrap(), (vec![(10, 3)], Some((20, 7))));
+        assert_eq!(query.open_bucket(OpenBucket::Exclude).evaluate_pooled::<i32>(&fs).unwrap(), vec![(10, 3)]);
+    }
+}
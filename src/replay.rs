@@ -0,0 +1,156 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Replaying stored records for backtesting.
+//!
+//! A paced replay yields each record when it's due, keeping the records' original spacing divided by the speed, so
+//! a speed of 2.0 replays an hour of records in half an hour.  An unpaced replay yields them as fast as they're
+//! asked for.
+
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use time_series::{TimeSeries, Timestamp};
+
+/// Replays the records in a range, paced at `speed` times their original rate.  Fails if the speed isn't positive.
+pub fn replay<V>(time_series: &dyn TimeSeries, range: Range<Timestamp>, speed: f64) -> io::Result<Replay<V>> where V: 'static {
+    if !(speed > 0.0) || speed.is_infinite() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Replay speed must be positive"));
+    }
+
+    Ok(Replay::new(retrieve(time_series, range)?, Some(speed)))
+}
+
+/// Replays the records in a range as fast as they're asked for
+pub fn replay_unpaced<V>(time_series: &dyn TimeSeries, range: Range<Timestamp>) -> io::Result<Replay<V>> where V: 'static {
+    Ok(Replay::new(retrieve(time_series, range)?, None))
+}
+
+fn retrieve<V>(time_series: &dyn TimeSeries, range: Range<Timestamp>) -> io::Result<Vec<(Timestamp, V)>> where V: 'static {
+    time_series.retrieve_range(range)?.try_into_vec::<Timestamp, V>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Replay retrieved the wrong value type"))
+}
+
+/// An iterator over replayed records.  `next` sleeps until the next record is due; `wait_time` and `take_due` are
+/// for callers that do their own waiting.
+pub struct Replay<V> {
+    records: VecDeque<(Timestamp, V)>,
+    speed: Option<f64>,
+    /// When the replay started, and the timestamp of its first record
+    origin: Option<(Instant, Timestamp)>,
+}
+
+impl<V> Replay<V> {
+    fn new(records: Vec<(Timestamp, V)>, speed: Option<f64>) -> Self {
+        Self {
+            records: records.into(),
+            speed: speed,
+            origin: None,
+        }
+    }
+
+    /// How long after `now` the next record is due, or `None` if the replay is over.  The replay starts the
+    /// first time this or `take_due` is called.
+    pub fn wait_time(&mut self, now: Instant) -> Option<Duration> {
+        let timestamp = self.records.front()?.0;
+        let due = self.due(timestamp, now);
+
+        Some(if due > now { due - now } else { Duration::from_millis(0) })
+    }
+
+    /// Takes every record that's due by `now`, without waiting
+    pub fn take_due(&mut self, now: Instant) -> Vec<(Timestamp, V)> {
+        let mut due = Vec::new();
+
+        while let Some(timestamp) = self.records.front().map(|record| record.0) {
+            if self.due(timestamp, now) > now {
+                break;
+            }
+
+            due.extend(self.records.pop_front());
+        }
+
+        due
+    }
+
+    /// The number of records left to replay
+    pub fn remaining(&self) -> usize {
+        self.records.len()
+    }
+
+    fn due(&mut self, timestamp: Timestamp, now: Instant) -> Instant {
+        let speed = match self.speed {
+            Some(speed) => speed,
+            None => return now,
+        };
+
+        let (started, first) = *self.origin.get_or_insert((now, timestamp));
+        let elapsed = timestamp.saturating_sub(first) as f64 / speed;
+
+        started + Duration::from_micros((elapsed * 1000.0) as u64)
+    }
+}
+
+impl<V> Iterator for Replay<V> {
+    type Item = (Timestamp, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let wait_time = self.wait_time(Instant::now())?;
+        if wait_time > Duration::from_millis(0) {
+            thread::sleep(wait_time);
+        }
+
+        self.records.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use key_value_store::KeyValueStore;
+    use storage::FileStorage;
+    use util::SetupFile;
+
+    #[test]
+    fn test_replay() {
+        let _setup_file = SetupFile::new("test_replay");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_replay").unwrap();
+
+        fs.store(Box::new(100 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(120 as Timestamp), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(140 as Timestamp), Box::new(3 as i32)).unwrap();
+        fs.store(Box::new(200 as Timestamp), Box::new(4 as i32)).unwrap();
+
+        // At double speed, records 20ms apart are replayed 10ms apart
+        let mut paced = replay::<i32>(&fs, 100..150, 2.0).unwrap();
+        let start = Instant::now();
+
+        assert_eq!(paced.take_due(start), vec![(100, 1)]);
+        assert_eq!(paced.wait_time(start), Some(Duration::from_millis(10)));
+        assert_eq!(paced.take_due(start + Duration::from_millis(9)), vec![]);
+        assert_eq!(paced.take_due(start + Duration::from_millis(25)), vec![(120, 2), (140, 3)]);
+        assert_eq!(paced.wait_time(start), None);
+
+        let unpaced = replay_unpaced::<i32>(&fs, 0..1000).unwrap();
+        assert_eq!(unpaced.collect::<Vec<_>>(), vec![(100, 1), (120, 2), (140, 3), (200, 4)]);
+
+        assert!(replay::<i32>(&fs, 0..1000, 0.0).is_err());
+    }
+}
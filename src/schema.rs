@@ -0,0 +1,684 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Multi-value records.
+//!
+//! Tuples of storable values are storable, with each field kept at its own fixed width and separated by a
+//! space.  Fields must not contain whitespace of their own.  A field type only needs to be storable on its own, e.g. `(Usd, Btc)` is storable if `Usd` and
+//! `Btc` are.  Tuples of poolable values are poolable field by field.
+//!
+//! A `Quote` is the top of a book, its best bid and ask, kept in one record so that they can't drift apart the way two
+//! channels can.  It's stored like a pair and pooled field by field, but neither field weights the other.
+//!
+//! A `Sourced` value carries the market it came from, for channels that consolidate several.  It's stored like a pair
+//! of the value and its source, and pooled by its value.  A pooled bucket keeps the source of the record it picked,
+//! or of all its records if it combined them and they share one, and `MIXED_SOURCES` otherwise.  A `SourceFilter`
+//! leaves sources out of a query after the fact, e.g. one that's found to have misbehaved.
+//!
+//! An `Identified` value carries the exchange's ID for the trade it records, so that a trade seen twice, say by both
+//! a backfill and the live feed, can be told apart from two trades.  It's stored like a pair of the value and its ID,
+//! and pooled by its value.  A pooled bucket keeps the ID of the record it picked, or of the last record it combined.
+
+use std::io;
+
+use key_value_store::Storable;
+use pooled_time_series::{Accumulator, Poolable, PoolingMethod, pool_values};
+use storage::{Bounded, FileStorage};
+use time_series::Timestamp;
+
+/// Splits a multi-value record into exactly as many fields as `fields` holds.  Records are split on every read, so
+/// the fields go into the caller's array rather than a new vector.
+fn split_fields<'a>(buffer: &'a [u8], fields: &mut [&'a [u8]]) -> io::Result<()> {
+    let mut split = buffer.split(|b| b.is_ascii_whitespace()).filter(|f| !f.is_empty());
+
+    for field in fields.iter_mut() {
+        *field = split.next().ok_or_else(wrong_field_count)?;
+    }
+
+    match split.next() {
+        Some(_) => Err(wrong_field_count()),
+        None => Ok(()),
+    }
+}
+
+fn wrong_field_count() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Multi-value record has the wrong number of fields")
+}
+
+fn join_fields(fields: Vec<Vec<u8>>) -> Vec<u8> {
+    fields.join(&b' ')
+}
+
+impl<A, B> Storable<FileStorage<Timestamp, (A, B)>> for (A, B) where
+    A: Storable<FileStorage<Timestamp, A>>,
+    B: Storable<FileStorage<Timestamp, B>>,
+{
+    fn size() -> usize {
+        <A as Storable<FileStorage<Timestamp, A>>>::size() + 1 +
+        <B as Storable<FileStorage<Timestamp, B>>>::size()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        join_fields(vec![
+            <A as Storable<FileStorage<Timestamp, A>>>::into_bytes(self.0),
+            <B as Storable<FileStorage<Timestamp, B>>>::into_bytes(self.1),
+        ])
+    }
+
+    fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
+        let mut fields: [&[u8]; 2] = [&[]; 2];
+        split_fields(buffer, &mut fields)?;
+
+        Ok((
+            <A as Storable<FileStorage<Timestamp, A>>>::from_bytes(fields[0])?,
+            <B as Storable<FileStorage<Timestamp, B>>>::from_bytes(fields[1])?,
+        ))
+    }
+}
+
+impl<A, B, C> Storable<FileStorage<Timestamp, (A, B, C)>> for (A, B, C) where
+    A: Storable<FileStorage<Timestamp, A>>,
+    B: Storable<FileStorage<Timestamp, B>>,
+    C: Storable<FileStorage<Timestamp, C>>,
+{
+    fn size() -> usize {
+        <A as Storable<FileStorage<Timestamp, A>>>::size() + 1 +
+        <B as Storable<FileStorage<Timestamp, B>>>::size() + 1 +
+        <C as Storable<FileStorage<Timestamp, C>>>::size()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        join_fields(vec![
+            <A as Storable<FileStorage<Timestamp, A>>>::into_bytes(self.0),
+            <B as Storable<FileStorage<Timestamp, B>>>::into_bytes(self.1),
+            <C as Storable<FileStorage<Timestamp, C>>>::into_bytes(self.2),
+        ])
+    }
+
+    fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
+        let mut fields: [&[u8]; 3] = [&[]; 3];
+        split_fields(buffer, &mut fields)?;
+
+        Ok((
+            <A as Storable<FileStorage<Timestamp, A>>>::from_bytes(fields[0])?,
+            <B as Storable<FileStorage<Timestamp, B>>>::from_bytes(fields[1])?,
+            <C as Storable<FileStorage<Timestamp, C>>>::from_bytes(fields[2])?,
+        ))
+    }
+}
+
+/// Returns the pooling method for a field, falling back to the last method given
+fn field_method(methods: &[PoolingMethod], field: usize) -> PoolingMethod {
+    methods.get(field).or(methods.last()).cloned().unwrap_or(PoolingMethod::End)
+}
+
+/// The method to pool the volume field with.  Volume-weighted pooling totals the volume.
+fn volume_method(pooling: PoolingMethod) -> PoolingMethod {
+    if pooling == PoolingMethod::Vwap {
+        PoolingMethod::Sum
+    } else {
+        pooling
+    }
+}
+
+/// Accumulates two-value records field by field.  For volume-weighted pooling, the second field is the
+/// volume that the first is weighted by.
+pub struct PairAccumulator<A, B> where A: Poolable, B: Poolable {
+    a: A::Accumulator,
+    b: B::Accumulator,
+}
+
+impl<A, B> Accumulator<(A, B)> for PairAccumulator<A, B> where A: Poolable, B: Poolable {
+    fn new(pooling: PoolingMethod) -> Self {
+        Self {
+            a: A::Accumulator::new(pooling),
+            b: B::Accumulator::new(volume_method(pooling)),
+        }
+    }
+
+    fn fold(&mut self, value: (A, B), weight: f64) {
+        self.a.fold(value.0, weight * value.1.weight());
+        self.b.fold(value.1, weight);
+    }
+
+    fn finalize(&self) -> (A, B) {
+        (self.a.finalize(), self.b.finalize())
+    }
+
+    fn reset(&mut self) {
+        self.a.reset();
+        self.b.reset();
+    }
+
+    fn overflowed(&self) -> bool {
+        self.a.overflowed() || self.b.overflowed()
+    }
+}
+
+/// Accumulates three-value records field by field.  For volume-weighted pooling, the second field is the
+/// volume that the others are weighted by.
+pub struct TripleAccumulator<A, B, C> where A: Poolable, B: Poolable, C: Poolable {
+    a: A::Accumulator,
+    b: B::Accumulator,
+    c: C::Accumulator,
+}
+
+impl<A, B, C> Accumulator<(A, B, C)> for TripleAccumulator<A, B, C> where A: Poolable, B: Poolable, C: Poolable {
+    fn new(pooling: PoolingMethod) -> Self {
+        Self {
+            a: A::Accumulator::new(pooling),
+            b: B::Accumulator::new(volume_method(pooling)),
+            c: C::Accumulator::new(pooling),
+        }
+    }
+
+    fn fold(&mut self, value: (A, B, C), weight: f64) {
+        self.a.fold(value.0, weight * value.1.weight());
+        self.b.fold(value.1, weight);
+        self.c.fold(value.2, weight * value.1.weight());
+    }
+
+    fn finalize(&self) -> (A, B, C) {
+        (self.a.finalize(), self.b.finalize(), self.c.finalize())
+    }
+
+    fn reset(&mut self) {
+        self.a.reset();
+        self.b.reset();
+        self.c.reset();
+    }
+
+    fn overflowed(&self) -> bool {
+        self.a.overflowed() || self.b.overflowed() || self.c.overflowed()
+    }
+}
+
+impl<A, B> Poolable for (A, B) where A: Poolable, B: Poolable {
+    type Accumulator = PairAccumulator<A, B>;
+
+    fn high(values: &[Self]) -> Self {
+        (
+            A::high(&values.iter().map(|v| v.0).collect::<Vec<A>>()),
+            B::high(&values.iter().map(|v| v.1).collect::<Vec<B>>()),
+        )
+    }
+
+    fn low(values: &[Self]) -> Self {
+        (
+            A::low(&values.iter().map(|v| v.0).collect::<Vec<A>>()),
+            B::low(&values.iter().map(|v| v.1).collect::<Vec<B>>()),
+        )
+    }
+
+    fn pool_fields(values: &[Self], start_value: Self, methods: &[PoolingMethod]) -> Self {
+        (
+            pool_values(&values.iter().map(|v| v.0).collect::<Vec<A>>(), start_value.0, field_method(methods, 0)),
+            pool_values(&values.iter().map(|v| v.1).collect::<Vec<B>>(), start_value.1, field_method(methods, 1)),
+        )
+    }
+}
+
+impl<A, B, C> Poolable for (A, B, C) where A: Poolable, B: Poolable, C: Poolable {
+    type Accumulator = TripleAccumulator<A, B, C>;
+
+    fn high(values: &[Self]) -> Self {
+        (
+            A::high(&values.iter().map(|v| v.0).collect::<Vec<A>>()),
+            B::high(&values.iter().map(|v| v.1).collect::<Vec<B>>()),
+            C::high(&values.iter().map(|v| v.2).collect::<Vec<C>>()),
+        )
+    }
+
+    fn low(values: &[Self]) -> Self {
+        (
+            A::low(&values.iter().map(|v| v.0).collect::<Vec<A>>()),
+            B::low(&values.iter().map(|v| v.1).collect::<Vec<B>>()),
+            C::low(&values.iter().map(|v| v.2).collect::<Vec<C>>()),
+        )
+    }
+
+    fn pool_fields(values: &[Self], start_value: Self, methods: &[PoolingMethod]) -> Self {
+        (
+            pool_values(&values.iter().map(|v| v.0).collect::<Vec<A>>(), start_value.0, field_method(methods, 0)),
+            pool_values(&values.iter().map(|v| v.1).collect::<Vec<B>>(), start_value.1, field_method(methods, 1)),
+            pool_values(&values.iter().map(|v| v.2).collect::<Vec<C>>(), start_value.2, field_method(methods, 2)),
+        )
+    }
+}
+
+/// The best bid and ask of a book
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Quote<V> {
+    pub bid: V,
+    pub ask: V,
+}
+
+impl<V> Quote<V> {
+    pub fn new(bid: V, ask: V) -> Self {
+        Self {
+            bid: bid,
+            ask: ask,
+        }
+    }
+}
+
+impl<V> Quote<V> where V: Bounded {
+    /// The ask less the bid, which is negative for a crossed book if the value type is signed
+    pub fn spread(&self) -> V {
+        V::from_f64(self.ask.to_f64() - self.bid.to_f64())
+    }
+
+    /// Halfway between the bid and the ask
+    pub fn midprice(&self) -> V {
+        V::from_f64((self.bid.to_f64() + self.ask.to_f64()) / 2.0)
+    }
+
+    /// Whether the bid is above the ask, which only a stale or broken feed shows
+    pub fn is_crossed(&self) -> bool {
+        self.bid.to_f64() > self.ask.to_f64()
+    }
+}
+
+impl<V> Storable<FileStorage<Timestamp, Quote<V>>> for Quote<V> where V: Storable<FileStorage<Timestamp, V>> {
+    fn size() -> usize {
+        <(V, V) as Storable<FileStorage<Timestamp, (V, V)>>>::size()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        <(V, V) as Storable<FileStorage<Timestamp, (V, V)>>>::into_bytes((self.bid, self.ask))
+    }
+
+    fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
+        <(V, V) as Storable<FileStorage<Timestamp, (V, V)>>>::from_bytes(buffer).map(|(bid, ask)| Quote::new(bid, ask))
+    }
+}
+
+/// Accumulates quotes field by field.  Unlike a pair's, the bid's accumulator isn't weighted by the ask.
+pub struct QuoteAccumulator<V> where V: Poolable {
+    bid: V::Accumulator,
+    ask: V::Accumulator,
+}
+
+impl<V> Accumulator<Quote<V>> for QuoteAccumulator<V> where V: Poolable {
+    fn new(pooling: PoolingMethod) -> Self {
+        Self {
+            bid: V::Accumulator::new(pooling),
+            ask: V::Accumulator::new(pooling),
+        }
+    }
+
+    fn fold(&mut self, value: Quote<V>, weight: f64) {
+        self.bid.fold(value.bid, weight);
+        self.ask.fold(value.ask, weight);
+    }
+
+    fn finalize(&self) -> Quote<V> {
+        Quote::new(self.bid.finalize(), self.ask.finalize())
+    }
+
+    fn reset(&mut self) {
+        self.bid.reset();
+        self.ask.reset();
+    }
+
+    fn overflowed(&self) -> bool {
+        self.bid.overflowed() || self.ask.overflowed()
+    }
+}
+
+impl<V> Poolable for Quote<V> where V: Poolable {
+    type Accumulator = QuoteAccumulator<V>;
+
+    fn high(values: &[Self]) -> Self {
+        Quote::new(
+            V::high(&values.iter().map(|v| v.bid).collect::<Vec<V>>()),
+            V::high(&values.iter().map(|v| v.ask).collect::<Vec<V>>()),
+        )
+    }
+
+    fn low(values: &[Self]) -> Self {
+        Quote::new(
+            V::low(&values.iter().map(|v| v.bid).collect::<Vec<V>>()),
+            V::low(&values.iter().map(|v| v.ask).collect::<Vec<V>>()),
+        )
+    }
+
+    fn pool_fields(values: &[Self], start_value: Self, methods: &[PoolingMethod]) -> Self {
+        Quote::new(
+            pool_values(&values.iter().map(|v| v.bid).collect::<Vec<V>>(), start_value.bid, field_method(methods, 0)),
+            pool_values(&values.iter().map(|v| v.ask).collect::<Vec<V>>(), start_value.ask, field_method(methods, 1)),
+        )
+    }
+}
+
+/// Identifies the market a record came from
+pub type Source = u64;
+
+/// The source of a bucket whose records came from more than one market.  Real sources start at 1.
+pub const MIXED_SOURCES: Source = 0;
+
+/// A value tagged with the market it came from.  Ordered by value first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Sourced<V> {
+    pub value: V,
+    pub source: Source,
+}
+
+impl<V> Sourced<V> {
+    pub fn new(value: V, source: Source) -> Self {
+        Self {
+            value: value,
+            source: source,
+        }
+    }
+}
+
+impl<V> Storable<FileStorage<Timestamp, Sourced<V>>> for Sourced<V> where V: Storable<FileStorage<Timestamp, V>> {
+    fn size() -> usize {
+        <V as Storable<FileStorage<Timestamp, V>>>::size() + 1 +
+        <Source as Storable<FileStorage<Timestamp, Self>>>::size()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        join_fields(vec![
+            <V as Storable<FileStorage<Timestamp, V>>>::into_bytes(self.value),
+            <Source as Storable<FileStorage<Timestamp, Self>>>::into_bytes(self.source),
+        ])
+    }
+
+    fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
+        let mut fields: [&[u8]; 2] = [&[]; 2];
+        split_fields(buffer, &mut fields)?;
+
+        Ok(Sourced::new(
+            <V as Storable<FileStorage<Timestamp, V>>>::from_bytes(fields[0])?,
+            <Source as Storable<FileStorage<Timestamp, Self>>>::from_bytes(fields[1])?,
+        ))
+    }
+}
+
+/// Accumulates sourced values by their value, keeping their source only while they all share it
+pub struct SourcedAccumulator<V> where V: Poolable {
+    value: V::Accumulator,
+    source: Option<Source>,
+}
+
+impl<V> Accumulator<Sourced<V>> for SourcedAccumulator<V> where V: Poolable {
+    fn new(pooling: PoolingMethod) -> Self {
+        Self {
+            value: V::Accumulator::new(pooling),
+            source: None,
+        }
+    }
+
+    fn fold(&mut self, value: Sourced<V>, weight: f64) {
+        self.value.fold(value.value, weight);
+        self.source = match self.source {
+            Some(source) if source != value.source => Some(MIXED_SOURCES),
+            Some(source) => Some(source),
+            None => Some(value.source),
+        };
+    }
+
+    fn finalize(&self) -> Sourced<V> {
+        Sourced::new(self.value.finalize(), self.source.unwrap_or(MIXED_SOURCES))
+    }
+
+    fn reset(&mut self) {
+        self.value.reset();
+        self.source = None;
+    }
+
+    fn overflowed(&self) -> bool {
+        self.value.overflowed()
+    }
+}
+
+/// Methods that pick a record, like `High` and `End`, pick it whole, source and all
+impl<V> Poolable for Sourced<V> where V: Poolable {
+    type Accumulator = SourcedAccumulator<V>;
+
+    fn weight(self) -> f64 {
+        self.value.weight()
+    }
+}
+
+/// The sources to leave out of a query of sourced values.  Nothing is left out by default.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SourceFilter {
+    excluded: Vec<Source>,
+}
+
+impl SourceFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn exclude(mut self, source: Source) -> Self {
+        if !self.excluded.contains(&source) {
+            self.excluded.push(source);
+        }
+        self
+    }
+
+    pub fn includes(&self, source: Source) -> bool {
+        !self.excluded.contains(&source)
+    }
+}
+
+/// An exchange's ID for a trade
+pub type TradeId = u64;
+
+/// A value of a trade with the exchange's ID for it.  Ordered by value first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Identified<V> {
+    pub value: V,
+    pub id: TradeId,
+}
+
+impl<V> Identified<V> {
+    pub fn new(value: V, id: TradeId) -> Self {
+        Self {
+            value: value,
+            id: id,
+        }
+    }
+}
+
+impl<V> Storable<FileStorage<Timestamp, Identified<V>>> for Identified<V> where V: Storable<FileStorage<Timestamp, V>> {
+    fn size() -> usize {
+        <V as Storable<FileStorage<Timestamp, V>>>::size() + 1 +
+        <TradeId as Storable<FileStorage<Timestamp, Self>>>::size()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        join_fields(vec![
+            <V as Storable<FileStorage<Timestamp, V>>>::into_bytes(self.value),
+            <TradeId as Storable<FileStorage<Timestamp, Self>>>::into_bytes(self.id),
+        ])
+    }
+
+    fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
+        let mut fields: [&[u8]; 2] = [&[]; 2];
+        split_fields(buffer, &mut fields)?;
+
+        Ok(Identified::new(
+            <V as Storable<FileStorage<Timestamp, V>>>::from_bytes(fields[0])?,
+            <TradeId as Storable<FileStorage<Timestamp, Self>>>::from_bytes(fields[1])?,
+        ))
+    }
+}
+
+/// Accumulates identified values by their value, keeping the ID of the last
+pub struct IdentifiedAccumulator<V> where V: Poolable {
+    value: V::Accumulator,
+    id: TradeId,
+}
+
+impl<V> Accumulator<Identified<V>> for IdentifiedAccumulator<V> where V: Poolable {
+    fn new(pooling: PoolingMethod) -> Self {
+        Self {
+            value: V::Accumulator::new(pooling),
+            id: 0,
+        }
+    }
+
+    fn fold(&mut self, value: Identified<V>, weight: f64) {
+        self.value.fold(value.value, weight);
+        self.id = value.id;
+    }
+
+    fn finalize(&self) -> Identified<V> {
+        Identified::new(self.value.finalize(), self.id)
+    }
+
+    fn reset(&mut self) {
+        self.value.reset();
+        self.id = 0;
+    }
+
+    fn overflowed(&self) -> bool {
+        self.value.overflowed()
+    }
+}
+
+impl<V> Poolable for Identified<V> where V: Poolable {
+    type Accumulator = IdentifiedAccumulator<V>;
+
+    fn weight(self) -> f64 {
+        self.value.weight()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use key_value_store::KeyValueStore;
+    use pooled_time_series::{FieldPooling, Interval, PooledTimeSeries, PoolingOptions};
+    use time_series::TimeSeries;
+    use util::SetupFile;
+
+    #[test]
+    fn test_tuple_storable() {
+        assert_eq!(<(i32, i32) as Storable<FileStorage<Timestamp, (i32, i32)>>>::size(), 9);
+        assert_eq!((1, -22).into_bytes(), b"   1  -22".to_vec());
+        assert_eq!(<(i32, i32, i32)>::from_bytes(b"1  2    3").unwrap(), (1, 2, 3));
+        assert!(<(i32, i32)>::from_bytes(b"1  2    3").is_err());
+    }
+
+    #[test]
+    fn test_tuple_file_storage() {
+        let _setup_file = SetupFile::new("test_tuple_file_storage");
+
+        let mut fs = FileStorage::<Timestamp, (i32, i32)>::new("test_tuple_file_storage").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new((1, 10))).unwrap();
+        fs.store(Box::new(12 as Timestamp), Box::new((3, 20))).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new((2, 30))).unwrap();
+
+        let retrieval = fs.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, (i32, i32)>(), Some(&vec![(10, (1, 10)), (12, (3, 20)), (20, (2, 30))]));
+
+        // Pool prices by their high and volumes by their sum
+        let pooling_options = PoolingOptions {
+            interval: Interval::millis(10),
+            field_pooling: Some(FieldPooling::new(&[PoolingMethod::High, PoolingMethod::Sum])),
+            ..PoolingOptions::default()
+        };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, (i32, i32)>(), Some(&vec![(10, (3, 30)), (20, (2, 30))]));
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::Low, ..PoolingOptions::default() };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, (i32, i32)>(), Some(&vec![(10, (1, 10)), (20, (2, 30))]));
+
+        // Weight prices by their volumes, and total the volumes
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::Vwap, ..PoolingOptions::default() };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, (i32, i32)>(), Some(&vec![(10, (2, 30)), (20, (2, 30))]));
+    }
+
+    #[test]
+    fn test_quote() {
+        let quote = Quote::new(100, 104);
+        assert_eq!((quote.spread(), quote.midprice()), (4, 102));
+        assert!(!quote.is_crossed());
+        assert!(Quote::new(105, 104).is_crossed());
+
+        assert_eq!(quote.into_bytes(), b" 100  104".to_vec());
+        assert_eq!(<Quote<i32>>::from_bytes(b" 100  104").unwrap(), quote);
+    }
+
+    #[test]
+    fn test_quote_file_storage() {
+        let _setup_file = SetupFile::new("test_quote_file_storage");
+
+        let mut fs = FileStorage::<Timestamp, Quote<i32>>::new("test_quote_file_storage").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(Quote::new(100, 104))).unwrap();
+        fs.store(Box::new(12 as Timestamp), Box::new(Quote::new(102, 102))).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(Quote::new(101, 105))).unwrap();
+
+        // The best bid and the worst ask of each bucket
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::High, ..PoolingOptions::default() };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, Quote<i32>>(), Some(&vec![(10, Quote::new(102, 104)), (20, Quote::new(101, 105))]));
+
+        // Neither side weights the other
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::Vwap, ..PoolingOptions::default() };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, Quote<i32>>(), Some(&vec![(10, Quote::new(101, 103)), (20, Quote::new(101, 105))]));
+    }
+
+    #[test]
+    fn test_sourced() {
+        let _setup_file = SetupFile::new("test_sourced");
+
+        assert_eq!(Sourced::new(-22, 3).into_bytes(), b" -22 0000000000003".to_vec());
+        assert_eq!(<Sourced<i32>>::from_bytes(b" -22 0000000000003").unwrap(), Sourced::new(-22, 3));
+
+        let mut fs = FileStorage::<Timestamp, Sourced<i32>>::new("test_sourced").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(Sourced::new(5, 1))).unwrap();
+        fs.store(Box::new(12 as Timestamp), Box::new(Sourced::new(100, 2))).unwrap();
+        fs.store(Box::new(21 as Timestamp), Box::new(Sourced::new(6, 1))).unwrap();
+        fs.store(Box::new(25 as Timestamp), Box::new(Sourced::new(8, 1))).unwrap();
+
+        // A picked record keeps its source
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::High, ..PoolingOptions::default() };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, Sourced<i32>>(), Some(&vec![(10, Sourced::new(100, 2)), (20, Sourced::new(8, 1))]));
+
+        // Combined records keep theirs only if they share it
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::Sum, ..PoolingOptions::default() };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, Sourced<i32>>(), Some(&vec![(10, Sourced::new(105, MIXED_SOURCES)), (20, Sourced::new(14, 1))]));
+
+        let filter = SourceFilter::new().exclude(2).exclude(2);
+        assert!(filter.includes(1));
+        assert!(!filter.includes(2));
+    }
+
+    #[test]
+    fn test_identified() {
+        assert_eq!(Identified::new(7, 1234).into_bytes(), b"   7 0000000001234".to_vec());
+        assert_eq!(<Identified<i32>>::from_bytes(b"   7 0000000001234").unwrap(), Identified::new(7, 1234));
+
+        let values = [Identified::new(5, 1), Identified::new(9, 2), Identified::new(6, 3)];
+        assert_eq!(pool_values(&values, values[0], PoolingMethod::High), Identified::new(9, 2));
+        assert_eq!(pool_values(&values, values[0], PoolingMethod::Sum), Identified::new(20, 3));
+    }
+}
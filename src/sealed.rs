@@ -0,0 +1,35 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The marker that seals traits to this crate.
+//!
+//! A public trait with `Sealed` as a supertrait can be named and used anywhere, but only implemented here, since
+//! `Sealed` can't be named outside the crate.  That leaves the crate free to add methods to it.
+
+use key_value_store::KeyValueStore;
+
+pub trait Sealed {}
+
+// Values the codec traits cover
+impl Sealed for i32 {}
+impl Sealed for i64 {}
+impl Sealed for u32 {}
+impl Sealed for u64 {}
+impl Sealed for f32 {}
+impl Sealed for f64 {}
+impl Sealed for (f64, f64, f64) {}
+
+// Stores get the upcasts of `AsKeyValueStore` and `AsTimeSeries` for free
+impl<T> Sealed for T where T: KeyValueStore {}
@@ -0,0 +1,167 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A self-report of the server's runtime configuration: where its data lives and how much room is left there, the
+//! channels it found and how their files are laid out, the optional features it was built with, and how durably it
+//! writes.  It's logged at startup and served at `/about`, so that support requests can include it.
+
+use std::path::Path;
+use std::process::Command;
+
+use serde_json;
+
+use server::market;
+
+#[derive(Serialize)]
+pub struct Report {
+    version: &'static str,
+    config_file: String,
+    data_directories: Vec<DataDirectory>,
+    channels: Vec<ChannelReport>,
+    derived_channels: usize,
+    features: Vec<&'static str>,
+    durability: Durability,
+    stream_address: String,
+    /// Only served if the server was built with gRPC
+    grpc_address: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DataDirectory {
+    path: String,
+    /// Bytes available to the server, if `df` could say
+    free_bytes: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ChannelReport {
+    channel: String,
+    file: String,
+    /// Whether the channel's storage could be read
+    found: bool,
+    records: Option<usize>,
+    /// The unit of the timestamps: ms, us, or ns
+    unit: Option<String>,
+    /// The width of each timestamp in the file, which depends on the unit
+    key_digits: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Durability {
+    /// Whether each stored record is synced to disk before the store returns.  Records are handed to the
+    /// operating system as they're stored, but not synced.
+    sync_on_store: bool,
+    /// Whether archive segment indexes are synced before they replace the old ones
+    sync_segment_index: bool,
+    /// Whether every channel is synced before the server exits on SIGINT or SIGTERM
+    sync_on_shutdown: bool,
+}
+
+pub fn report() -> Report {
+    let channels = market::stored_channels().into_iter().map(|(market, symbol, name, file)| {
+        let channel = market::find_channel(market, symbol, name).and_then(|served| served.channel.read().ok());
+        let time_series = channel.as_ref().and_then(|channel| channel.as_time_series());
+        let unit = time_series.map(|time_series| time_series.time_unit());
+
+        ChannelReport {
+            channel: format!("{}/{}/{}", market, symbol, name),
+            file: file.to_string(),
+            found: channel.is_some(),
+            records: channel.as_ref().and_then(|channel| channel.as_key_value_store()).map(|store| store.len()),
+            unit: unit.map(|unit| unit.to_string()),
+            key_digits: unit.map(|unit| unit.significant_digits()),
+        }
+    }).collect();
+
+    Report {
+        version: env!("CARGO_PKG_VERSION"),
+        config_file: market::config_path(),
+        data_directories: market::channel_directories(&market::CONFIG).into_iter().map(|directory| DataDirectory {
+            free_bytes: free_bytes(&directory),
+            path: directory.to_string_lossy().into_owned(),
+        }).collect(),
+        channels: channels,
+        derived_channels: market::derived_channel_count(),
+        features: features(),
+        durability: Durability {
+            sync_on_store: false,
+            sync_segment_index: true,
+            sync_on_shutdown: cfg!(unix),
+        },
+        stream_address: market::CONFIG.stream_address.clone(),
+        grpc_address: if cfg!(feature = "grpc") { Some(market::CONFIG.grpc_address.clone()) } else { None },
+    }
+}
+
+/// Logs the report as a single line of JSON
+pub fn log() {
+    match serde_json::to_string(&report()) {
+        Ok(report) => eprintln!("Starting trade-data {}: {}", env!("CARGO_PKG_VERSION"), report),
+        Err(error) => eprintln!("Could not report the runtime configuration: {}", error),
+    }
+}
+
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+
+    if cfg!(feature = "columnar") {
+        features.push("columnar");
+    }
+    if cfg!(feature = "grpc") {
+        features.push("grpc");
+    }
+    if cfg!(feature = "kraken") {
+        features.push("kraken");
+    }
+    if cfg!(feature = "mmap") {
+        features.push("mmap");
+    }
+    if cfg!(feature = "postgresql") {
+        features.push("postgresql");
+    }
+    if cfg!(feature = "s3") {
+        features.push("s3");
+    }
+
+    features
+}
+
+/// The space available on the filesystem holding `directory`
+fn free_bytes(directory: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(directory).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    parse_df(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Reads the available space out of POSIX `df -Pk` output, which is in kilobytes
+fn parse_df(output: &str) -> Option<u64> {
+    let available = output.lines().nth(1)?.split_whitespace().nth(3)?;
+    available.parse::<u64>().ok().map(|kilobytes| kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_df() {
+        let output = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n/dev/sda1        102400000  51200000  51200000      50% /\n";
+        assert_eq!(parse_df(output), Some(51200000 * 1024));
+        assert_eq!(parse_df("Filesystem 1024-blocks Used Available Capacity Mounted on\n"), None);
+    }
+}
@@ -0,0 +1,520 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use jsonwebtoken::{self, Algorithm, Validation};
+use rocket::Outcome;
+use rocket::http::{HeaderMap, Status};
+use rocket::request::{self, FromRequest, Request};
+
+use server::market::{self, Channel, Config};
+use server::usage::{self, Quota, Usage};
+
+/// What a role lets a caller do.  Each role includes the ones before it.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Reading records
+    ReadOnly,
+    /// Writing records
+    Ingest,
+    /// Administering the server, e.g. reloading its configuration.  Only an admin role over every channel
+    /// grants this; a narrower one is the same as ingest.
+    Admin,
+}
+
+/// A role over the channels matching a "market", "market/symbol", or "market/symbol/channel" scope, where any
+/// part may be "*"
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RoleGrant {
+    role: Role,
+    #[serde(default = "RoleGrant::everything")]
+    scope: String,
+}
+
+impl RoleGrant {
+    fn everything() -> String {
+        "*".to_string()
+    }
+}
+
+/// What a caller may do
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Grants {
+    #[serde(default)]
+    roles: Vec<RoleGrant>,
+    /// Limits on the caller's usage
+    #[serde(default)]
+    quota: Option<Quota>,
+    /// Who the caller's usage is accounted to.  Set by the provider.
+    #[serde(skip)]
+    account: String,
+}
+
+impl Grants {
+    /// Whether any of the roles allows `role` on a channel
+    fn allows(&self, role: Role, path: &[&str; 3]) -> bool {
+        self.roles.iter().any(|grant| grant.role >= role && in_scope(&grant.scope, path))
+    }
+
+    /// Whether the caller may administer the server
+    fn is_admin(&self) -> bool {
+        self.roles.iter().any(|grant| grant.role == Role::Admin && in_scope(&grant.scope, &["*", "*", "*"]))
+    }
+}
+
+/// An API key accepted by the server, as declared in the configuration file
+#[derive(Clone, Deserialize)]
+pub struct KeyConfig {
+    key: String,
+    /// Who the key's usage is accounted to.  "key <n>" for the nth key if omitted.
+    name: Option<String>,
+    #[serde(flatten)]
+    grants: Grants,
+}
+
+/// Accepts JSON Web Tokens from an identity provider, given as "Authorization: Bearer <token>".  The "roles"
+/// claim lists the token's roles in the same form as the configuration file, e.g.
+/// `[{"role": "read-only", "scope": "gemini"}]`.
+#[derive(Clone, Deserialize)]
+pub struct JwtConfig {
+    /// The expected "iss" claim
+    issuer: String,
+    /// The expected "aud" claim, if any
+    audience: Option<String>,
+    /// "HS256" with `secret`, or "RS256" with `public_key_file`, a DER-encoded RSA public key
+    algorithm: String,
+    secret: Option<String>,
+    public_key_file: Option<String>,
+    /// Limits on the usage of each token subject
+    quota: Option<Quota>,
+}
+
+/// Accepts the client certificate subject passed on by a TLS-terminating proxy that verifies client
+/// certificates.  The proxy must drop the header from the requests it receives.
+#[derive(Clone, Deserialize)]
+pub struct MtlsConfig {
+    /// The header the proxy puts the verified subject in, e.g. "X-Client-Subject"
+    header: String,
+    #[serde(default, rename = "identity")]
+    identities: Vec<MtlsIdentityConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MtlsIdentityConfig {
+    subject: String,
+    #[serde(flatten)]
+    grants: Grants,
+}
+
+/// Where the credentials of a request come from
+pub trait Credentials {
+    fn header(&self, name: &str) -> Option<&str>;
+
+    /// The token of an "Authorization: Bearer <token>" header
+    fn bearer_token(&self) -> Option<&str> {
+        self.header("Authorization").and_then(|value| {
+            if value.starts_with("Bearer ") { Some(value["Bearer ".len()..].trim()) } else { None }
+        })
+    }
+}
+
+impl<'h> Credentials for HeaderMap<'h> {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.get_one(name)
+    }
+}
+
+/// A way of establishing what a caller may do
+pub trait AuthProvider: Send + Sync {
+    /// Returns `Ok(None)` if the request has no credentials this provider understands, or fails if it has
+    /// credentials that this provider rejects.
+    fn authenticate(&self, credentials: &dyn Credentials) -> Result<Option<Arc<Grants>>, Status>;
+}
+
+/// API keys listed in the configuration file, given as "X-Api-Key: <key>" or "Authorization: Bearer <key>"
+pub struct StaticKeys(HashMap<String, Arc<Grants>>);
+
+impl StaticKeys {
+    pub fn new(keys: &[KeyConfig]) -> Self {
+        StaticKeys(keys.iter().enumerate().map(|(i, key)| {
+            let account = key.name.clone().unwrap_or_else(|| format!("key {}", i + 1));
+            (key.key.clone(), Arc::new(Grants { account: account, ..key.grants.clone() }))
+        }).collect())
+    }
+}
+
+impl AuthProvider for StaticKeys {
+    fn authenticate(&self, credentials: &dyn Credentials) -> Result<Option<Arc<Grants>>, Status> {
+        if let Some(key) = credentials.header("X-Api-Key") {
+            return self.0.get(key).cloned().map(Some).ok_or(Status::Unauthorized);
+        }
+
+        // A bearer token that isn't one of these keys may be for another provider
+        Ok(credentials.bearer_token().and_then(|token| self.0.get(token).cloned()))
+    }
+}
+
+pub struct Jwt {
+    key: Vec<u8>,
+    validation: Validation,
+    issuer: String,
+    quota: Option<Quota>,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: Option<String>,
+    #[serde(default)]
+    roles: Vec<RoleGrant>,
+}
+
+impl Jwt {
+    pub fn new(config: &JwtConfig) -> io::Result<Self> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+        let (algorithm, key) = match (config.algorithm.as_str(), &config.secret, &config.public_key_file) {
+            ("HS256", &Some(ref secret), _) => (Algorithm::HS256, secret.clone().into_bytes()),
+            ("RS256", _, &Some(ref public_key_file)) => (Algorithm::RS256, fs::read(public_key_file)?),
+            ("HS256", _, _) => return Err(invalid("HS256 tokens need a secret")),
+            ("RS256", _, _) => return Err(invalid("RS256 tokens need a public key file")),
+            _ => return Err(invalid("JWT algorithm must be HS256 or RS256")),
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.iss = Some(config.issuer.clone());
+        if let Some(ref audience) = config.audience {
+            validation.set_audience(audience);
+        }
+
+        Ok(Jwt {
+            key: key,
+            validation: validation,
+            issuer: config.issuer.clone(),
+            quota: config.quota.clone(),
+        })
+    }
+}
+
+impl AuthProvider for Jwt {
+    fn authenticate(&self, credentials: &dyn Credentials) -> Result<Option<Arc<Grants>>, Status> {
+        let token = match credentials.bearer_token() {
+            Some(token) if token.split('.').count() == 3 => token,
+            _ => return Ok(None),
+        };
+
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation).map_err(|_| Status::Unauthorized)?.claims;
+
+        // Tokens without a subject are accounted to their issuer
+        Ok(Some(Arc::new(Grants {
+            roles: claims.roles,
+            quota: self.quota.clone(),
+            account: claims.sub.unwrap_or_else(|| self.issuer.clone()),
+        })))
+    }
+}
+
+pub struct Mtls {
+    header: String,
+    identities: HashMap<String, Arc<Grants>>,
+}
+
+impl Mtls {
+    pub fn new(config: &MtlsConfig) -> Self {
+        Mtls {
+            header: config.header.clone(),
+            identities: config.identities.iter().map(|identity| {
+                (identity.subject.clone(), Arc::new(Grants { account: identity.subject.clone(), ..identity.grants.clone() }))
+            }).collect(),
+        }
+    }
+}
+
+impl AuthProvider for Mtls {
+    fn authenticate(&self, credentials: &dyn Credentials) -> Result<Option<Arc<Grants>>, Status> {
+        match credentials.header(&self.header) {
+            Some(subject) => self.identities.get(subject).cloned().map(Some).ok_or(Status::Unauthorized),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The providers a configuration asks for, in the order they're tried
+fn providers(config: &Config) -> io::Result<Vec<Box<dyn AuthProvider>>> {
+    let key_quotas = config.keys.iter().map(|key| &key.grants.quota);
+    let jwt_quotas = config.jwt.iter().map(|jwt| &jwt.quota);
+    let mtls_quotas = config.mtls.iter().flat_map(|mtls| mtls.identities.iter().map(|identity| &identity.grants.quota));
+
+    for quota in key_quotas.chain(jwt_quotas).chain(mtls_quotas).filter_map(Option::as_ref) {
+        quota.validate()?;
+    }
+
+    let mut providers: Vec<Box<dyn AuthProvider>> = vec![Box::new(StaticKeys::new(&config.keys))];
+
+    if let Some(ref jwt) = config.jwt {
+        providers.push(Box::new(Jwt::new(jwt)?));
+    }
+
+    if let Some(ref mtls) = config.mtls {
+        providers.push(Box::new(Mtls::new(mtls)));
+    }
+
+    Ok(providers)
+}
+
+lazy_static! {
+    static ref PROVIDERS: RwLock<Vec<Box<dyn AuthProvider>>> = RwLock::new(providers(&market::CONFIG).expect("Could not set up authentication"));
+}
+
+/// Replaces the auth providers with those of a reloaded configuration.  Requests already in progress keep
+/// what they were granted.
+pub fn reload(config: &Config) -> io::Result<()> {
+    let providers = providers(config)?;
+    match PROVIDERS.write() {
+        Ok(mut current) => *current = providers,
+        Err(poisoned) => *poisoned.into_inner() = providers,
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// What the maker of a request may do, if it gave any credentials.  Requests with credentials that aren't
+/// accepted, or whose quota is used up, are refused outright.
+#[derive(Clone)]
+pub struct Caller(Option<Arc<Grants>>);
+
+/// The outcome of authenticating a request, kept so the request is only accounted for once
+struct Authenticated(Option<Result<Caller, Status>>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Caller {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        match request.local_cache(|| Authenticated(Some(Caller::authenticate(request.headers())))).0 {
+            Some(Ok(ref caller)) => Outcome::Success(caller.clone()),
+            Some(Err(status)) => Outcome::Failure((status, ())),
+            None => Outcome::Failure((Status::InternalServerError, ())),
+        }
+    }
+}
+
+impl Caller {
+    /// Identifies a caller with the first provider that understands its credentials
+    pub fn authenticate(credentials: &dyn Credentials) -> Result<Caller, Status> {
+        let providers = PROVIDERS.read().map_err(|_| Status::InternalServerError)?;
+
+        for provider in providers.iter() {
+            if let Some(grants) = provider.authenticate(credentials)? {
+                usage::charge(&grants.account, grants.quota.as_ref(), Usage { requests: 1, ..Usage::default() })?;
+                return Ok(Caller(Some(grants)));
+            }
+        }
+
+        // Credentials that no provider understood
+        if credentials.header("Authorization").is_some() {
+            Err(Status::Unauthorized)
+        } else {
+            Ok(Caller(None))
+        }
+    }
+
+    /// The caller of a request that's already been authenticated
+    pub fn of_request(request: &Request) -> Option<Caller> {
+        match request.local_cache(|| Authenticated(None)).0 {
+            Some(Ok(ref caller)) => Some(caller.clone()),
+            _ => None,
+        }
+    }
+
+    /// Accounts for a pooled query.  Fails with `TooManyRequests` if the caller's quota of them is used up.
+    pub fn charge_pooled_query(&self) -> Result<(), Status> {
+        match self.0 {
+            Some(ref grants) => usage::charge(&grants.account, grants.quota.as_ref(), Usage { pooled_queries: 1, ..Usage::default() }),
+            None => Ok(()),
+        }
+    }
+
+    /// Accounts for bytes sent to the caller
+    pub fn charge_bytes(&self, bytes: u64) {
+        if let Some(ref grants) = self.0 {
+            // Bytes are accounted for after they're sent, so they're never refused
+            let _ = usage::charge(&grants.account, grants.quota.as_ref(), Usage { bytes: bytes, ..Usage::default() });
+        }
+    }
+
+    /// Fails with `Unauthorized` if no credentials were given, or `Forbidden` if they don't grant admin
+    pub fn require_admin(&self) -> Result<(), Status> {
+        match self.0 {
+            None => Err(Status::Unauthorized),
+            Some(ref grants) if grants.is_admin() => Ok(()),
+            Some(_) => Err(Status::Forbidden),
+        }
+    }
+
+    /// Looks up a channel, checking that the caller may access it.  Fails with `NotFound` if there's no such
+    /// channel, `Unauthorized` if credentials are needed but none were given, or `Forbidden` if they don't
+    /// allow it.
+    pub fn channel(&self, market: &str, symbol: &str, channel: &str, access: Access) -> Result<&'static RwLock<Channel>, Status> {
+        let served = market::find_channel(market, symbol, channel).ok_or(Status::NotFound)?;
+
+        let path = [market, symbol, channel];
+        let allowed = match access {
+            Access::Read => served.is_public() || self.allows(Role::ReadOnly, &path),
+            Access::Write => self.allows(Role::Ingest, &path),
+        };
+
+        if allowed {
+            Ok(&served.channel)
+        } else if self.0.is_none() {
+            Err(Status::Unauthorized)
+        } else {
+            Err(Status::Forbidden)
+        }
+    }
+
+    /// Checks that the caller may access any of a symbol's channels.  Fails like `channel`.
+    pub fn symbol(&self, market: &str, symbol: &str, access: Access) -> Result<(), Status> {
+        let mut status = Status::NotFound;
+
+        for (m, s, name, _) in market::served_channels() {
+            if m == market && s == symbol {
+                match self.channel(market, symbol, name, access) {
+                    Ok(_) => return Ok(()),
+                    Err(error) => status = error,
+                }
+            }
+        }
+
+        Err(status)
+    }
+
+    /// Who the caller's usage is accounted to, if it gave credentials
+    pub fn account(&self) -> Option<&str> {
+        self.0.as_ref().map(|grants| grants.account.as_str())
+    }
+
+    fn allows(&self, role: Role, path: &[&str; 3]) -> bool {
+        self.0.as_ref().map_or(false, |grants| grants.allows(role, path))
+    }
+}
+
+/// A caller with admin rights.  Requests from anyone else are refused.
+pub struct Admin;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let caller = request.guard::<Caller>()?;
+        match caller.require_admin() {
+            Ok(()) => Outcome::Success(Admin),
+            Err(status) => Outcome::Failure((status, ())),
+        }
+    }
+}
+
+/// Whether a channel is within a scope.  A scope with fewer than three parts covers everything under them.
+fn in_scope(scope: &str, path: &[&str; 3]) -> bool {
+    let parts = scope.split('/').collect::<Vec<&str>>();
+    if parts == ["*"] {
+        return true;
+    }
+
+    parts.len() <= 3 && parts.iter().zip(path.iter()).all(|(part, name)| *part == "*" || part == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Credentials for HashMap<&'static str, &'static str> {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.get(name).cloned()
+        }
+    }
+
+    fn grants(roles: &[(Role, &str)]) -> Grants {
+        Grants {
+            roles: roles.iter().map(|&(role, scope)| RoleGrant { role: role, scope: scope.to_string() }).collect(),
+            ..Grants::default()
+        }
+    }
+
+    #[test]
+    fn test_in_scope() {
+        let path = ["gemini", "btcusd", "trades"];
+
+        assert!(in_scope("gemini/btcusd/trades", &path));
+        assert!(in_scope("gemini/*/trades", &path));
+        assert!(in_scope("*/*/*", &path));
+        assert!(in_scope("*", &path));
+        assert!(in_scope("gemini", &path));
+        assert!(in_scope("gemini/btcusd", &path));
+        assert!(!in_scope("gemini/ethusd/trades", &path));
+        assert!(!in_scope("gemini/ethusd", &path));
+        assert!(!in_scope("kraken", &path));
+        assert!(!in_scope("gemini/btcusd/trades/x", &path));
+    }
+
+    #[test]
+    fn test_roles() {
+        let consumer = grants(&[(Role::ReadOnly, "gemini")]);
+        assert!(consumer.allows(Role::ReadOnly, &["gemini", "btcusd", "trades"]));
+        assert!(!consumer.allows(Role::Ingest, &["gemini", "btcusd", "trades"]));
+        assert!(!consumer.allows(Role::ReadOnly, &["execution", "btcusd", "fills"]));
+        assert!(!consumer.is_admin());
+
+        let feeder = grants(&[(Role::Ingest, "gemini/*/trades"), (Role::ReadOnly, "*")]);
+        assert!(feeder.allows(Role::Ingest, &["gemini", "btcusd", "trades"]));
+        assert!(feeder.allows(Role::ReadOnly, &["gemini", "btcusd", "trades"]));
+        assert!(!feeder.allows(Role::Ingest, &["gemini", "btcusd", "book"]));
+        assert!(feeder.allows(Role::ReadOnly, &["execution", "btcusd", "fills"]));
+
+        // An admin role over part of the channels doesn't make an admin of the server
+        let market_admin = grants(&[(Role::Admin, "gemini")]);
+        assert!(market_admin.allows(Role::Ingest, &["gemini", "btcusd", "trades"]));
+        assert!(!market_admin.is_admin());
+        assert!(grants(&[(Role::Admin, "*")]).is_admin());
+    }
+
+    #[test]
+    fn test_static_keys() {
+        let writer = Grants { account: "key 1".to_string(), ..grants(&[(Role::Ingest, "*")]) };
+        let keys = StaticKeys::new(&[KeyConfig { key: "secret".to_string(), name: None, grants: writer.clone() }]);
+
+        let credentials = vec![("X-Api-Key", "secret")].into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(keys.authenticate(&credentials).unwrap().map(|grants| (*grants).clone()), Some(writer.clone()));
+
+        let credentials = vec![("Authorization", "Bearer secret")].into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(keys.authenticate(&credentials).unwrap().map(|grants| (*grants).clone()), Some(writer));
+
+        // An unknown bearer token is left for other providers, but an unknown API key is refused
+        let credentials = vec![("Authorization", "Bearer a.b.c")].into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(keys.authenticate(&credentials), Ok(None));
+
+        let credentials = vec![("X-Api-Key", "guess")].into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(keys.authenticate(&credentials), Err(Status::Unauthorized));
+    }
+}
@@ -0,0 +1,63 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Collection of the temporary files and orphaned segment indexes that interrupted archives leave in the channel
+//! directories, on demand and periodically while serving.
+
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use Timestamp;
+use storage;
+
+use server::market;
+
+/// How long to wait between garbage collections while serving
+const GC_PERIOD: Timestamp = 60 * 60 * 1000;
+
+/// How old a leftover file has to be before it's collected, unless asked otherwise
+pub const GC_AGE: Timestamp = 60 * 60 * 1000;
+
+/// Removes the leftovers of interrupted archives older than `older_than` milliseconds from `directory`, or from every
+/// directory holding a configured channel's storage file, and returns them
+pub fn collect_garbage(directory: Option<&str>, older_than: Timestamp) -> io::Result<Vec<PathBuf>> {
+    let directories = match directory {
+        Some(directory) => vec![PathBuf::from(directory)],
+        None => market::channel_directories(&market::read_config()?),
+    };
+
+    let mut removed = Vec::new();
+    for directory in directories {
+        removed.extend(storage::collect_garbage(&directory, Duration::from_millis(older_than))?);
+    }
+
+    Ok(removed)
+}
+
+/// Collects garbage in the channel directories every `GC_PERIOD`, for as long as the server runs
+pub fn collect_periodically() {
+    loop {
+        thread::sleep(Duration::from_millis(GC_PERIOD));
+
+        match collect_garbage(None, GC_AGE) {
+            Ok(removed) => for path in removed {
+                eprintln!("Collected garbage: {}", path.display());
+            },
+            Err(error) => eprintln!("Could not collect garbage: {}", error),
+        }
+    }
+}
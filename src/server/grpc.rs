@@ -0,0 +1,356 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The gRPC service, for clients that want typed messages and streaming without JSON.
+//!
+//! Credentials are given in metadata rather than headers, and grant the same access as they do over HTTP.  Ranges and
+//! intervals are in the unit a request names, milliseconds by default, and timestamps come back in the channel's
+//! unit.  Stored records are given in the channel's unit, as they are when posted.
+
+use std::io;
+use std::sync::RwLock;
+use std::thread;
+
+use rocket::http::Status;
+use trade_data_grpc::{self, Code, Message, Metadata, Status as GrpcStatus};
+use trade_data_grpc::proto::{self, Candle, PoolRangeRequest, PoolRangeResponse, Record, RetrieveRequest, RetrieveResponse, StoreRequest, StoreResponse, SubscribeRequest, Update};
+use trade_data_grpc::proto::update::Kind;
+
+use {CandleStream, CandleUpdate, Cursor, Page, PoolingMethod, Query, RecordStream, ResumeToken, Sequenced, TimeSeries, TimeUnit, Timestamp};
+
+use server::auth::{Access, Caller, Credentials};
+use server::market::Channel;
+use server::slow_queries;
+use server::workers;
+use server::{find_query_channel, is_timed_out, query_error_status};
+
+/// Binds `address` and serves the service from it on a thread of its own.  Fails if the address can't be bound, rather
+/// than once serving has started.
+pub fn serve(address: &str) -> io::Result<()> {
+    let listener = trade_data_grpc::bind(address, Backend)?;
+
+    thread::spawn(move || if let Err(error) = listener.serve() {
+        eprintln!("Could not serve gRPC: {}", error);
+    });
+
+    Ok(())
+}
+
+struct Backend;
+
+impl trade_data_grpc::Backend for Backend {
+    type Subscription = Subscription;
+
+    fn store(&self, metadata: &Metadata, request: StoreRequest) -> Result<StoreResponse, GrpcStatus> {
+        let caller = Caller::authenticate(metadata).map_err(status)?;
+        let (channel, _) = find_channel(&caller, request.channel, Access::Write)?;
+        let records = request.records;
+
+        workers::INGEST.run(move || {
+            let mut channel = channel.write().map_err(|_| GrpcStatus::internal("Channel is unavailable"))?;
+            let key_value_store = channel.as_mut_key_value_store().ok_or_else(|| GrpcStatus::invalid_argument("Channel can't be stored in"))?;
+
+            for record in &records {
+                let timestamp = record.timestamp.as_ref().ok_or_else(|| GrpcStatus::invalid_argument("Record has no timestamp"))?;
+                key_value_store.store(Box::new(timestamp.value), Box::new(record.value)).map_err(|error| match error.kind() {
+                    io::ErrorKind::PermissionDenied => GrpcStatus::permission_denied("Channel can't be stored in"),
+                    io::ErrorKind::InvalidData => GrpcStatus::invalid_argument(error.to_string()),
+                    _ => status(query_error_status(&error)),
+                })?;
+            }
+
+            Ok(StoreResponse { stored: records.len() as u64 })
+        }).map_err(status)?
+    }
+
+    fn retrieve(&self, metadata: &Metadata, request: RetrieveRequest) -> Result<RetrieveResponse, GrpcStatus> {
+        let caller = Caller::authenticate(metadata).map_err(status)?;
+        let unit = time_unit(request.unit());
+        let cursor = match request.cursor.as_str() {
+            "" => None,
+            cursor => Some(cursor.parse::<Cursor>().map_err(|_| GrpcStatus::invalid_argument("Invalid cursor"))?),
+        };
+        let page_size = match (request.page_size, cursor) {
+            (0, Some(_)) => return Err(GrpcStatus::invalid_argument("Cursor given without a page size")),
+            (0, None) => None,
+            (page_size, _) => Some(page_size as usize),
+        };
+
+        let (channel, source) = find_channel(&caller, request.channel, Access::Read)?;
+        let range = request.range;
+
+        let response = workers::QUERY.run(move || -> Result<RetrieveResponse, GrpcStatus> {
+            let channel = channel.read().map_err(|_| GrpcStatus::internal("Channel is unavailable"))?;
+            let time_series = time_series(&channel)?;
+
+            let query = range_query(&source, range, unit).in_unit(time_series.time_unit());
+            let page = evaluate_page(&channel, &query, page_size.map(|page_size| (page_size, cursor.as_ref())))?;
+
+            Ok(RetrieveResponse {
+                unit: proto_unit(time_series.time_unit()) as i32,
+                records: page.records.into_iter().map(|(timestamp, value)| record(timestamp, value)).collect(),
+                next_cursor: page.next.map_or(String::new(), |next| next.to_string()),
+            })
+        }).map_err(status)??;
+        caller.charge_bytes(response.encoded_len() as u64);
+
+        Ok(response)
+    }
+
+    fn pool_range(&self, metadata: &Metadata, request: PoolRangeRequest) -> Result<PoolRangeResponse, GrpcStatus> {
+        let caller = Caller::authenticate(metadata).map_err(status)?;
+        if request.interval == 0 {
+            return Err(GrpcStatus::invalid_argument("Interval must be positive"));
+        }
+
+        let unit = time_unit(request.unit());
+        let (channel, source) = find_channel(&caller, request.channel, Access::Read)?;
+        caller.charge_pooled_query().map_err(status)?;
+
+        let (range, interval) = (request.range, request.interval);
+
+        let response = workers::QUERY.run(move || -> Result<PoolRangeResponse, GrpcStatus> {
+            let channel = channel.read().map_err(|_| GrpcStatus::internal("Channel is unavailable"))?;
+            let time_series = time_series(&channel)?;
+            let query = range_query(&source, range, unit).interval(interval).in_unit(time_series.time_unit());
+
+            let methods = [PoolingMethod::Start, PoolingMethod::High, PoolingMethod::Low, PoolingMethod::End];
+            let mut columns = Vec::new();
+            for &method in &methods {
+                columns.push(evaluate(&channel, &query.clone().pooling(method))?);
+            }
+
+            let buckets = columns[0].iter().zip(&columns[1]).zip(&columns[2]).zip(&columns[3]);
+            Ok(PoolRangeResponse {
+                unit: proto_unit(time_series.time_unit()) as i32,
+                candles: buckets.map(|(((&(start, open), high), low), close)| Candle {
+                    start: Some(proto::Timestamp { value: start }),
+                    open: open,
+                    high: high.1,
+                    low: low.1,
+                    close: close.1,
+                }).collect(),
+            })
+        }).map_err(status)??;
+        caller.charge_bytes(response.encoded_len() as u64);
+
+        Ok(response)
+    }
+
+    fn subscribe(&self, metadata: &Metadata, request: SubscribeRequest) -> Result<Subscription, GrpcStatus> {
+        let caller = Caller::authenticate(metadata).map_err(status)?;
+        let unit = time_unit(request.unit());
+        let pooling = pooling_method(request.pooling());
+        let (channel, source) = find_channel(&caller, request.channel, Access::Read)?;
+        let channel = channel.read().map_err(|_| GrpcStatus::internal("Channel is unavailable"))?;
+        let channel_unit = time_series(&channel)?.time_unit();
+
+        let query = match request.interval {
+            0 => range_query(&source, request.range, unit).in_unit(channel_unit),
+            interval => range_query(&source, request.range, unit).interval(interval).pooling(pooling).in_unit(channel_unit),
+        };
+        let token = match request.resume.as_str() {
+            "" => ResumeToken::default(),
+            resume => Cursor::resume(resume, &query).map_err(|_| GrpcStatus::invalid_argument("Invalid cursor"))?,
+        };
+
+        let stream = if query.interval.is_some() {
+            caller.charge_pooled_query().map_err(status)?;
+            CandleStream::resume(query.clone(), token).map(Stream::Candles)
+        } else {
+            RecordStream::resume(query.clone(), token).map(Stream::Records)
+        };
+
+        Ok(Subscription {
+            caller: caller,
+            source: source,
+            query: query,
+            unit: channel_unit,
+            stream: stream.map_err(|_| GrpcStatus::invalid_argument("Invalid subscription"))?,
+        })
+    }
+}
+
+enum Stream {
+    Records(RecordStream<Timestamp>),
+    Candles(CandleStream<Timestamp>),
+}
+
+struct Subscription {
+    caller: Caller,
+    source: String,
+    /// The query as it was subscribed, which the cursors sent are for
+    query: Query,
+    /// The unit of the channel
+    unit: TimeUnit,
+    stream: Stream,
+}
+
+impl trade_data_grpc::Subscription for Subscription {
+    fn poll(&mut self) -> Result<Vec<Update>, GrpcStatus> {
+        let channel = find_query_channel(&self.caller, &self.source).map_err(|_| GrpcStatus::not_found("Channel is no longer available"))?;
+        let channel = channel.read().map_err(|_| GrpcStatus::internal("Channel is unavailable"))?;
+        let unit = proto_unit(self.unit);
+
+        let updates = match self.stream {
+            Stream::Records(ref mut stream) => {
+                stream.poll(time_series(&channel)?).map(|updates| updates.into_iter().map(|sequenced| record_update(&self.query, sequenced, unit)).collect::<Vec<_>>())
+            },
+            Stream::Candles(ref mut stream) => match channel.as_pooled_time_series() {
+                Some(pooled_time_series) => stream.poll(pooled_time_series).map(|updates| updates.into_iter().map(|sequenced| candle_update(&self.query, sequenced, unit)).collect::<Vec<_>>()),
+                None => return Err(GrpcStatus::invalid_argument("Channel can't be pooled")),
+            },
+        }.map_err(|error| status(query_error_status(&error)))?;
+
+        self.caller.charge_bytes(updates.iter().map(|update| update.encoded_len() as u64).sum());
+
+        Ok(updates)
+    }
+
+    fn is_finished(&self) -> bool {
+        match self.stream {
+            Stream::Candles(ref stream) => stream.is_finished(),
+            Stream::Records(_) => false,
+        }
+    }
+}
+
+impl Credentials for Metadata {
+    fn header(&self, name: &str) -> Option<&str> {
+        // Metadata keys are lowercase
+        self.get(name.to_lowercase().as_str()).and_then(|value| value.to_str().ok())
+    }
+}
+
+/// Looks up a channel, checking that the caller may access it.  Returns its source, "market/symbol/channel", too.
+fn find_channel(caller: &Caller, channel: Option<proto::Channel>, access: Access) -> Result<(&'static RwLock<Channel>, String), GrpcStatus> {
+    let channel = channel.ok_or_else(|| GrpcStatus::invalid_argument("Request names no channel"))?;
+    let found = caller.channel(&channel.market, &channel.symbol, &channel.name, access).map_err(status)?;
+
+    Ok((found, format!("{}/{}/{}", channel.market, channel.symbol, channel.name)))
+}
+
+fn time_series(channel: &Channel) -> Result<&dyn TimeSeries, GrpcStatus> {
+    channel.as_time_series().ok_or_else(|| GrpcStatus::invalid_argument("Channel is not a time series"))
+}
+
+/// Evaluates a query under the slow-query limit, and logs it if it's slow
+fn evaluate(channel: &Channel, query: &Query) -> Result<Vec<(Timestamp, Timestamp)>, GrpcStatus> {
+    evaluate_page(channel, query, None).map(|page| page.records)
+}
+
+/// Evaluates a query, or only a page of it if given a page size and the cursor to page from
+fn evaluate_page(channel: &Channel, query: &Query, page: Option<(usize, Option<&Cursor>)>) -> Result<Page<Timestamp>, GrpcStatus> {
+    let time_series = time_series(channel)?;
+    let timer = slow_queries::Timer::start(query);
+    let io_stats_before = time_series.io_stats();
+
+    let unpaged = |records| Page { records: records, next: None };
+    let page = timer.limit(|| match (page, channel.as_pooled_time_series()) {
+        (Some((size, cursor)), Some(pooled_time_series)) => query.page_pooled::<Timestamp>(pooled_time_series, size, cursor),
+        (Some((size, cursor)), None) => query.page::<Timestamp>(time_series, size, cursor),
+        (None, Some(pooled_time_series)) => query.evaluate_pooled::<Timestamp>(pooled_time_series).map(unpaged),
+        (None, None) => query.evaluate::<Timestamp>(time_series).map(unpaged),
+    });
+    timer.finish(time_series.io_stats() - io_stats_before, is_timed_out(&page));
+
+    page.map_err(|error| status(query_error_status(&error)))
+}
+
+/// A query of a source over a range given in `unit`
+fn range_query(source: &str, range: Option<proto::Range>, unit: TimeUnit) -> Query {
+    let mut query = Query::new(source);
+    query.unit = unit;
+
+    if let Some(range) = range {
+        query.start = range.start.map(|start| start.value);
+        query.end = range.end.map(|end| end.value);
+    }
+
+    query
+}
+
+fn record(timestamp: Timestamp, value: Timestamp) -> Record {
+    Record {
+        timestamp: Some(proto::Timestamp { value: timestamp }),
+        value: value,
+    }
+}
+
+fn record_update(query: &Query, sequenced: Sequenced<(Timestamp, Timestamp)>, unit: proto::TimeUnit) -> Update {
+    let (timestamp, value) = sequenced.update;
+    Update {
+        seq: sequenced.resume.sequence,
+        resume: Cursor::new(query, sequenced.resume).to_string(),
+        unit: unit as i32,
+        kind: Some(Kind::Record(record(timestamp, value))),
+    }
+}
+
+fn candle_update(query: &Query, sequenced: Sequenced<CandleUpdate<Timestamp>>, unit: proto::TimeUnit) -> Update {
+    Update {
+        seq: sequenced.resume.sequence,
+        resume: Cursor::new(query, sequenced.resume).to_string(),
+        unit: unit as i32,
+        kind: Some(match sequenced.update {
+            CandleUpdate::Partial(start, value) => Kind::Partial(record(start, value)),
+            CandleUpdate::Closed(start, value) => Kind::Closed(record(start, value)),
+        }),
+    }
+}
+
+fn time_unit(unit: proto::TimeUnit) -> TimeUnit {
+    match unit {
+        proto::TimeUnit::Milliseconds => TimeUnit::Milliseconds,
+        proto::TimeUnit::Microseconds => TimeUnit::Microseconds,
+        proto::TimeUnit::Nanoseconds => TimeUnit::Nanoseconds,
+    }
+}
+
+fn proto_unit(unit: TimeUnit) -> proto::TimeUnit {
+    match unit {
+        TimeUnit::Milliseconds => proto::TimeUnit::Milliseconds,
+        TimeUnit::Microseconds => proto::TimeUnit::Microseconds,
+        TimeUnit::Nanoseconds => proto::TimeUnit::Nanoseconds,
+    }
+}
+
+fn pooling_method(pooling: proto::Pooling) -> PoolingMethod {
+    match pooling {
+        proto::Pooling::End => PoolingMethod::End,
+        proto::Pooling::Start => PoolingMethod::Start,
+        proto::Pooling::High => PoolingMethod::High,
+        proto::Pooling::Low => PoolingMethod::Low,
+        proto::Pooling::Mean => PoolingMethod::Mean,
+        proto::Pooling::Sum => PoolingMethod::Sum,
+        proto::Pooling::StdDev => PoolingMethod::StdDev,
+        proto::Pooling::Vwap => PoolingMethod::Vwap,
+    }
+}
+
+/// The gRPC status closest to an HTTP status
+fn status(status: Status) -> GrpcStatus {
+    let code = match status {
+        Status::BadRequest | Status::UnprocessableEntity => Code::InvalidArgument,
+        Status::Unauthorized => Code::Unauthenticated,
+        Status::Forbidden | Status::MethodNotAllowed => Code::PermissionDenied,
+        Status::NotFound => Code::NotFound,
+        Status::TooManyRequests => Code::ResourceExhausted,
+        Status::ServiceUnavailable => Code::Unavailable,
+        _ => Code::Internal,
+    };
+
+    GrpcStatus::new(code, status.reason)
+}
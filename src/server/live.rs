@@ -0,0 +1,383 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! WebSocket streams of records and of live pooled buckets.
+//!
+//! A client subscribes by sending a query, in the same JSON form as `POST /query`.  Without an interval, the server
+//! sends a "record" message for each record so far and then for each new record.  With one, it sends a "closed"
+//! message for each complete bucket and a "partial" message for the open one, then keeps sending a "partial"
+//! message whenever the open bucket changes and a "closed" message as each bucket closes.
+//!
+//! Every update has a sequence number, `seq`, and a `resume` token.  A client that disconnects can resubscribe
+//! with the same query and the `resume` token of the last update it received to carry on without gaps or
+//! duplicates.  A resumed candle stream sends its open bucket again.
+//!
+//! A subscription with a `replay` object replays the records of the query's range instead, as "replay" messages
+//! paced at `speed` times their original rate, or as fast as possible without a speed.  The stream closes once
+//! they've all been sent.
+
+use std::cmp;
+use std::io;
+use std::str;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use rocket::http::Status;
+use ws::{self, CloseCode, Handler, Handshake, Message, Sender};
+use ws::deflate::DeflateHandler;
+use ws::util::Token;
+
+use serde_json;
+
+use {CandleStream, CandleUpdate, Cursor, Query, RecordStream, ResumeToken, Sequenced, Timestamp};
+use parse;
+use replay::{self, Replay};
+
+use server::auth::{Caller, Credentials};
+use server::{QueryRequest, find_query_channel};
+
+/// How often streams check for new records, in milliseconds
+const POLL_INTERVAL: u64 = 250;
+
+/// The most backlog records sent per poll while a record stream catches up, unless the subscription says otherwise
+const CATCH_UP_CHUNK: usize = 1000;
+
+const POLL: Token = Token(1);
+
+/// Binds `address` and serves streams from it on a thread of its own.  Fails if the address can't be bound, rather than
+/// once serving has started.  Streams are compressed with permessage-deflate for clients that offer it.
+pub fn serve(address: &str) -> io::Result<()> {
+    let address = address.to_string();
+    let (bound, bind_result) = mpsc::channel();
+
+    // The socket is made on the thread that runs it, and only the result of binding it is sent back
+    thread::spawn(move || {
+        let socket = ws::Builder::new().build(|out| DeflateHandler::new(Connection {
+            out: out,
+            caller: None,
+            subscription: None,
+        })).and_then(|socket| socket.bind(&*address));
+
+        let socket = match socket {
+            Ok(socket) => {
+                bound.send(Ok(())).ok();
+                socket
+            },
+            Err(error) => {
+                bound.send(Err(io_error(error))).ok();
+                return;
+            },
+        };
+
+        if let Err(error) = socket.run() {
+            eprintln!("Could not serve streams: {}", error);
+        }
+    });
+
+    bind_result.recv().unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "Stream server stopped before binding")))
+}
+
+fn io_error(error: ws::Error) -> io::Error {
+    match error.kind {
+        ws::ErrorKind::Io(error) => error,
+        _ => io::Error::new(io::ErrorKind::Other, error.details.into_owned()),
+    }
+}
+
+/// A subscription can pick up where a client left off with either the `resume` cursor of the last message it got
+/// or, for raw records, `since`, the timestamp of the last record it saw.  The backlog of records is sent in chunks
+/// of `chunk` records, and a `caught_up` message follows once the stream is live.
+#[derive(Deserialize)]
+struct SubscribeRequest {
+    #[serde(flatten)]
+    query: QueryRequest,
+    resume: Option<String>,
+    since: Option<Timestamp>,
+    chunk: Option<usize>,
+    replay: Option<ReplayRequest>,
+}
+
+#[derive(Deserialize)]
+struct ReplayRequest {
+    speed: Option<f64>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamMessage {
+    Record { seq: u64, resume: String, timestamp: Timestamp, value: Timestamp },
+    Partial { seq: u64, resume: String, start: Timestamp, value: Timestamp },
+    Closed { seq: u64, resume: String, start: Timestamp, value: Timestamp },
+    Replay { seq: u64, timestamp: Timestamp, value: Timestamp },
+    /// The backlog has been sent, and updates from here on are live
+    CaughtUp,
+    Error { message: String },
+}
+
+enum Stream {
+    Records(RecordStream<Timestamp>),
+    Candles(CandleStream<Timestamp>),
+    /// A replay, and the sequence number of the last record it sent
+    Replay(Replay<Timestamp>, u64),
+}
+
+struct Subscription {
+    source: String,
+    /// The query as it was subscribed, which the cursors sent are for
+    query: Query,
+    stream: Stream,
+    /// Whether the `caught_up` message has been sent
+    caught_up: bool,
+}
+
+struct Connection {
+    out: Sender,
+    caller: Option<Caller>,
+    subscription: Option<Subscription>,
+}
+
+impl Connection {
+    fn send(&self, message: &StreamMessage) -> ws::Result<()> {
+        let text = serde_json::to_string(message).map_err(|error| ws::Error::new(ws::ErrorKind::Internal, error.to_string()))?;
+
+        if let Some(ref caller) = self.caller {
+            caller.charge_bytes(text.len() as u64);
+        }
+
+        self.out.send(text)
+    }
+
+    fn fail(&self, message: &str) -> ws::Result<()> {
+        self.send(&StreamMessage::Error { message: message.to_string() })?;
+        self.out.close(CloseCode::Policy)
+    }
+
+    /// Sends whatever has changed since the last poll
+    fn poll(&mut self) -> ws::Result<()> {
+        let messages = {
+            let subscription = match self.subscription {
+                Some(Subscription { stream: Stream::Replay(..), .. }) => return self.poll_replay(),
+                Some(ref mut subscription) => subscription,
+                None => return Ok(()),
+            };
+
+            let channel = match self.caller.as_ref().map(|caller| find_query_channel(caller, &subscription.source)) {
+                Some(Ok(channel)) => channel,
+                _ => return self.fail("Channel is no longer available"),
+            };
+            let channel = match channel.read() {
+                Ok(channel) => channel,
+                Err(_) => return self.fail("Channel is unavailable"),
+            };
+
+            match subscription.stream {
+                Stream::Records(ref mut stream) => match channel.as_time_series() {
+                    Some(time_series) => stream.poll(time_series).map(|updates| updates.into_iter().map(|update| record_message(&subscription.query, update)).collect::<Vec<_>>()),
+                    None => return self.fail("Channel has no records"),
+                },
+                Stream::Candles(ref mut stream) => match channel.as_pooled_time_series() {
+                    Some(pooled_time_series) => stream.poll(pooled_time_series).map(|updates| updates.into_iter().map(|update| candle_message(&subscription.query, update)).collect::<Vec<_>>()),
+                    None => return self.fail("Channel can't be pooled"),
+                },
+                Stream::Replay(..) => unreachable!(),
+            }
+        };
+
+        match messages {
+            Ok(messages) => {
+                for message in &messages {
+                    self.send(message)?;
+                }
+
+                let (finished, catching_up) = match self.subscription {
+                    Some(Subscription { stream: Stream::Candles(ref stream), .. }) => (stream.is_finished(), false),
+                    Some(Subscription { stream: Stream::Records(ref stream), .. }) => (false, !stream.is_caught_up()),
+                    _ => (false, false),
+                };
+
+                // Announce the switch to live records once, after the last chunk of the backlog
+                let caught_up = match self.subscription {
+                    Some(ref mut subscription) if !catching_up && !subscription.caught_up => {
+                        subscription.caught_up = true;
+                        true
+                    },
+                    _ => false,
+                };
+                if caught_up {
+                    self.send(&StreamMessage::CaughtUp)?;
+                }
+
+                if finished {
+                    self.out.close(CloseCode::Normal)
+                } else if catching_up {
+                    // Send the next chunk as soon as this one is out
+                    self.out.timeout(1, POLL)
+                } else {
+                    self.out.timeout(POLL_INTERVAL, POLL)
+                }
+            },
+            Err(_) => self.fail("Could not evaluate query"),
+        }
+    }
+
+    /// Sends the replayed records that are due, and waits for the next
+    fn poll_replay(&mut self) -> ws::Result<()> {
+        let now = Instant::now();
+
+        let (messages, wait_time) = match self.subscription {
+            Some(Subscription { stream: Stream::Replay(ref mut replay, ref mut sequence), .. }) => {
+                let messages = replay.take_due(now).into_iter().map(|(timestamp, value)| {
+                    *sequence += 1;
+                    StreamMessage::Replay { seq: *sequence, timestamp: timestamp, value: value }
+                }).collect::<Vec<_>>();
+
+                (messages, replay.wait_time(now))
+            },
+            _ => return Ok(()),
+        };
+
+        for message in &messages {
+            self.send(message)?;
+        }
+
+        match wait_time {
+            Some(wait_time) => self.out.timeout(cmp::max(wait_time.as_secs() * 1000 + wait_time.subsec_millis() as u64, 1), POLL),
+            None => self.out.close(CloseCode::Normal),
+        }
+    }
+
+    /// Loads the records of a replay from the channel
+    fn start_replay(&self, query: &Query, speed: Option<f64>) -> Result<Replay<Timestamp>, &'static str> {
+        let start = query.start.ok_or("Replays need a start")?;
+        let end = query.end.unwrap_or_else(parse::now);
+
+        let caller = self.caller.as_ref().ok_or("Credentials were not accepted")?;
+        let channel = find_query_channel(caller, &query.source).map_err(|_| "Channel is unavailable")?;
+        let channel = channel.read().map_err(|_| "Channel is unavailable")?;
+        let time_series = channel.as_time_series().ok_or("Channel has no records")?;
+
+        match speed {
+            Some(speed) => replay::replay(time_series, start..end, speed).map_err(|_| "Replay speed must be positive"),
+            None => replay::replay_unpaced(time_series, start..end).map_err(|_| "Could not read records to replay"),
+        }
+    }
+}
+
+fn record_message(query: &Query, sequenced: Sequenced<(Timestamp, Timestamp)>) -> StreamMessage {
+    let (timestamp, value) = sequenced.update;
+    StreamMessage::Record { seq: sequenced.resume.sequence, resume: Cursor::new(query, sequenced.resume).to_string(), timestamp: timestamp, value: value }
+}
+
+fn candle_message(query: &Query, sequenced: Sequenced<CandleUpdate<Timestamp>>) -> StreamMessage {
+    let (seq, resume) = (sequenced.resume.sequence, Cursor::new(query, sequenced.resume).to_string());
+    match sequenced.update {
+        CandleUpdate::Partial(start, value) => StreamMessage::Partial { seq: seq, resume: resume, start: start, value: value },
+        CandleUpdate::Closed(start, value) => StreamMessage::Closed { seq: seq, resume: resume, start: start, value: value },
+    }
+}
+
+impl Credentials for ws::Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        ws::Request::header(self, name).and_then(|value| str::from_utf8(value).ok())
+    }
+}
+
+impl Handler for Connection {
+    fn on_open(&mut self, handshake: Handshake) -> ws::Result<()> {
+        self.caller = Caller::authenticate(&handshake.request).ok();
+
+        if self.caller.is_none() {
+            self.fail("Credentials were not accepted")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn on_message(&mut self, message: Message) -> ws::Result<()> {
+        if self.subscription.is_some() {
+            return self.fail("Already subscribed");
+        }
+
+        let request = match message.as_text().ok().and_then(|text| serde_json::from_str::<SubscribeRequest>(text).ok()) {
+            Some(request) => request,
+            None => return self.fail("Subscription is not a query"),
+        };
+        let query = match request.query.into_query(parse::now()) {
+            // The subscription outlives the moment it was made, which would otherwise hold every later bucket open
+            Ok(query) => Query { now: None, ..query },
+            Err(_) => return self.fail("Subscription has an invalid time or interval"),
+        };
+        let token = match request.resume.as_ref().map(|resume| Cursor::resume(resume, &query)) {
+            Some(Ok(token)) => token,
+            Some(Err(_)) => return self.fail("Invalid cursor"),
+            None => ResumeToken::default(),
+        };
+
+        let status = self.caller.as_ref().map_or(Err(Status::Unauthorized), |caller| find_query_channel(caller, &query.source).map(|_| ()));
+        match status {
+            Ok(()) => {},
+            Err(Status::NotFound) => return self.fail("No such channel"),
+            Err(Status::BadRequest) => return self.fail("Source must be \"market/symbol/channel\""),
+            Err(_) => return self.fail("Not allowed to read this channel"),
+        }
+
+        if !query.indicators.is_empty() || query.bands.is_some() {
+            return self.fail("Indicators can't be streamed");
+        }
+
+        let source = query.source.clone();
+
+        if let Some(replay_request) = request.replay {
+            return match self.start_replay(&query, replay_request.speed) {
+                Ok(replay) => {
+                    self.subscription = Some(Subscription { source: source, query: query, stream: Stream::Replay(replay, 0), caught_up: false });
+                    self.poll_replay()
+                },
+                Err(message) => self.fail(message),
+            };
+        }
+
+        let subscribed = query.clone();
+        let stream = if query.interval.is_some() {
+            if self.caller.as_ref().map_or(Ok(()), Caller::charge_pooled_query).is_err() {
+                return self.fail("Pooled query quota is used up");
+            }
+
+            CandleStream::resume(query, token).map(Stream::Candles)
+        } else {
+            let chunk = request.chunk.unwrap_or(CATCH_UP_CHUNK);
+            match request.since {
+                Some(since) if request.resume.is_none() => RecordStream::since(query, since),
+                _ => RecordStream::resume(query, token),
+            }.map(|stream| Stream::Records(stream.chunked(chunk)))
+        };
+
+        match stream {
+            Ok(stream) => self.subscription = Some(Subscription { source: source, query: subscribed, stream: stream, caught_up: false }),
+            Err(_) => return self.fail("Invalid subscription"),
+        }
+
+        self.poll()
+    }
+
+    fn on_timeout(&mut self, event: Token) -> ws::Result<()> {
+        if event == POLL {
+            self.poll()
+        } else {
+            Ok(())
+        }
+    }
+}
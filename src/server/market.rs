@@ -0,0 +1,1152 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use lazy_static;
+use toml;
+
+use {ChannelInfo, Consolidation, CrossRate, DerivedChannel, DerivedSource, Identified, Interval, KeyValueStore, PooledTimeSeries, PoolingMethod, Query, Retrieval, TimeSeries, TimeUnit, Timestamp};
+use parse::{self, parse_interval, parse_timestamp};
+use storage::{Annotations, Constraints, DedupStore, Events, FileStorage, Quarantine, RollupPolicy, RollupStorage, StorageRegistry, ValidatedStore, ValidationPolicy};
+use storage::layout;
+use symbols::{SymbolFormat, SymbolMap, SymbolRegistry};
+
+use server::auth::{JwtConfig, KeyConfig, MtlsConfig};
+use server::slow_queries::SlowQueryConfig;
+
+lazy_static! {
+    /// The configuration the server started with.  The stream address and worker pools are only read from this;
+    /// channels follow reloads.
+    pub static ref CONFIG: Config = take_provided_config().map_or_else(read_config, Ok).expect("Could not load configuration file");
+
+    /// A configuration given in code, to be taken as `CONFIG` in place of the configuration file
+    static ref PROVIDED_CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+
+    /// The served channels, which change when the configuration is reloaded
+    static ref MARKETS: RwLock<HashMap<String, Market>> = RwLock::new(load_markets(&CONFIG).expect("Could not load configured channels"));
+
+    /// Held while the channels are reloaded, so that two reloads don't interleave
+    static ref RELOADING: Mutex<()> = Mutex::new(());
+
+    /// How each market names its symbols, which changes when the configuration is reloaded
+    static ref SYMBOLS: RwLock<SymbolRegistry> = RwLock::new(symbol_registry(&CONFIG));
+
+    /// The annotations of each symbol that's been annotated or asked about, by "market/symbol"
+    static ref ANNOTATIONS: Mutex<HashMap<String, Annotations>> = Mutex::new(HashMap::new());
+
+    static ref EVENTS: io::Result<Events> = Events::open(&CONFIG.events_file);
+
+    /// How to open a channel of each value type that can be configured
+    static ref STORAGE: StorageRegistry = StorageRegistry::default();
+}
+
+/// Whether `CONFIG` was given in code rather than read from the configuration file
+static CONFIG_WAS_PROVIDED: AtomicBool = AtomicBool::new(false);
+
+pub struct Market(HashMap<String, Symbol>);
+
+pub struct Symbol(HashMap<String, &'static ServedChannel>);
+
+/// A channel as it's served.  These are never freed, so that requests can hold on to one without holding the
+/// registry's lock; a channel removed by a reload is closed, and its `ServedChannel` is left behind.
+pub struct ServedChannel {
+    pub market: String,
+    pub symbol: String,
+    pub name: String,
+    pub channel: Arc<RwLock<Channel>>,
+    /// Whether the channel can be read without an API key.  Updated when the configuration is reloaded.
+    public: AtomicBool,
+    /// Where records the channel refused are kept, if its validation policy is to quarantine them
+    pub quarantine: Option<Quarantine>,
+    pub info: ChannelInfo,
+    /// How the channel was configured, to tell whether a reloaded configuration changes it
+    origin: Origin,
+}
+
+enum Origin {
+    Stored(ChannelConfig),
+    Derived(DerivedChannelConfig),
+    /// Registered in code, and left alone by reloads
+    Registered,
+}
+
+impl ServedChannel {
+    pub fn is_public(&self) -> bool {
+        self.public.load(Ordering::Relaxed)
+    }
+
+    /// The channel's storage file, if it's stored rather than derived
+    pub fn file(&self) -> Option<&str> {
+        match self.origin {
+            Origin::Stored(ref stored) => Some(&stored.file),
+            Origin::Derived(_) | Origin::Registered => None,
+        }
+    }
+
+    fn path(&self) -> String {
+        format!("{}/{}/{}", self.market, self.symbol, self.name)
+    }
+
+    /// Whether a reloaded configuration still has the channel as it is, given the channels it's retiring.  A
+    /// derived channel is retired along with any of its sources.
+    fn kept_by(&self, config: &Config, retired: &[&ServedChannel]) -> bool {
+        match self.origin {
+            Origin::Stored(ref stored) => config.channels.iter().any(|c| c.same_storage(stored)),
+            Origin::Derived(ref derived) => {
+                config.derived_channels.iter().any(|d| d.same_derivation(derived)) &&
+                    !retired.iter().any(|r| r.market == self.market && derived.sources.iter().any(|s| derived.source(s) == (r.symbol.as_str(), r.name.as_str())))
+            },
+            Origin::Registered => true,
+        }
+    }
+
+    /// Closes the channel's storage once the requests using it have finished, syncing it first.  It's closed
+    /// even if it can't be synced.
+    fn drain(&self) -> io::Result<()> {
+        let mut channel = self.channel.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let synced = channel.as_key_value_store().map_or(Ok(()), |key_value_store| key_value_store.sync());
+        *channel = Channel::Closed;
+        synced
+    }
+}
+
+/// The configuration file named by `TRADE_DATA_CONFIG`, or "trade-data.toml" by default
+pub fn config_path() -> String {
+    env::var("TRADE_DATA_CONFIG").unwrap_or_else(|_| "trade-data.toml".to_string())
+}
+
+/// Reads the configuration file at `config_path`, and works out where each channel's file is
+pub fn read_config() -> io::Result<Config> {
+    match fs::read_to_string(config_path()) {
+        Ok(contents) => contents.parse(),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Config::default().resolve_files(),
+        Err(error) => Err(error),
+    }
+}
+
+/// Has `CONFIG` take `config` in place of the configuration file.  Fails if `CONFIG` was already loaded.
+pub fn provide_config(config: Config) -> io::Result<()> {
+    *PROVIDED_CONFIG.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(config);
+    lazy_static::initialize(&CONFIG);
+
+    match PROVIDED_CONFIG.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+        Some(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "Configuration was already loaded")),
+        None => Ok(()),
+    }
+}
+
+fn take_provided_config() -> Option<Config> {
+    let provided = PROVIDED_CONFIG.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+    CONFIG_WAS_PROVIDED.store(provided.is_some(), Ordering::Relaxed);
+    provided
+}
+
+/// Whether `CONFIG` was given in code, in which case there's no configuration file to reload
+pub fn config_was_provided() -> bool {
+    CONFIG_WAS_PROVIDED.load(Ordering::Relaxed)
+}
+
+fn path_string(path: PathBuf) -> io::Result<String> {
+    path.into_os_string().into_string().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Storage path is not UTF-8"))
+}
+
+/// Applies the channel visibility of a reloaded configuration
+pub fn apply_visibility(config: &Config) {
+    let channels = config.channels.iter().map(|c| (&c.market, &c.symbol, &c.name, c.public));
+    let derived_channels = config.derived_channels.iter().map(|c| (&c.market, &c.symbol, &c.name, c.public));
+
+    for (market, symbol, name, public) in channels.chain(derived_channels) {
+        if let Some(served) = find_channel(market, symbol, name) {
+            served.public.store(public, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The market, symbol, name, and storage file of every channel that's stored rather than derived
+pub fn stored_channels() -> Vec<(&'static str, &'static str, &'static str, &'static str)> {
+    served_channels().into_iter().filter_map(|(market, symbol, name, served)| served.file().map(|file| (market, symbol, name, file))).collect()
+}
+
+/// The number of channels derived from others
+pub fn derived_channel_count() -> usize {
+    served_channels().into_iter().filter(|&(_, _, _, served)| served.file().is_none()).count()
+}
+
+/// The storage file of each configured channel, along with its market/symbol/channel
+pub fn channel_files(config: &Config) -> Vec<(String, String)> {
+    config.channels.iter().map(|c| (format!("{}/{}/{}", c.market, c.symbol, c.name), c.file.clone())).collect()
+}
+
+/// The storage file of every configured channel, with the unit of its timestamps
+pub fn channel_units(config: &Config) -> io::Result<Vec<(String, TimeUnit)>> {
+    config.channels.iter()
+        .map(|c| Ok((c.file.clone(), c.unit.as_ref().map_or(Ok(TimeUnit::Milliseconds), |unit| unit.parse())?)))
+        .collect()
+}
+
+/// Every directory that holds a configured channel's storage file
+pub fn channel_directories(config: &Config) -> Vec<PathBuf> {
+    let mut directories = config.channels.iter()
+        .map(|c| match Path::new(&c.file).parent() {
+            Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        })
+        .collect::<Vec<PathBuf>>();
+
+    directories.sort();
+    directories.dedup();
+    directories
+}
+
+/// Reads the records of a channel in a range, in milliseconds, ahead of time
+pub fn preload(market: &str, symbol: &str, channel: &str, range: Range<Timestamp>) -> io::Result<()> {
+    let served = find_channel(market, symbol, channel).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Channel not found"))?;
+    let channel = served.channel.read().map_err(|_| io::Error::new(io::ErrorKind::Other, "Channel was poisoned"))?;
+    let time_series = channel.as_time_series().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Channel is not a time series"))?;
+
+    let unit = time_series.time_unit();
+    let convert = |timestamp| TimeUnit::Milliseconds.convert(timestamp, unit);
+
+    time_series.preload(convert(range.start)..convert(range.end))
+}
+
+/// Preloads every channel the configuration asks to, from its preload time up to now.  Failures are reported but
+/// don't stop the others.
+pub fn preload_configured() {
+    let now = parse::now();
+
+    for channel in &CONFIG.channels {
+        let start = match channel.preload {
+            Some(ref preload) => parse_timestamp(preload, now),
+            None => continue,
+        };
+
+        let result = start.and_then(|start| preload(&channel.market, &channel.symbol, &channel.name, start..Timestamp::max_value()));
+        if let Err(error) = result {
+            eprintln!("Could not preload {}/{}/{}: {}", channel.market, channel.symbol, channel.name, error);
+        }
+    }
+}
+
+/// The annotations of a symbol, kept in the annotations directory as "<market>/<symbol>.annotations".  Returns
+/// `None` if no channel is served for the symbol.
+pub fn annotations(market: &str, symbol: &str) -> io::Result<Option<Annotations>> {
+    if markets().get(market).and_then(|m| m.0.get(symbol)).is_none() {
+        return Ok(None);
+    }
+
+    let mut opened = ANNOTATIONS.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Annotations were poisoned"))?;
+    let key = format!("{}/{}", market, symbol);
+
+    if let Some(annotations) = opened.get(&key) {
+        return Ok(Some(annotations.clone()));
+    }
+
+    let directory = Path::new(&CONFIG.annotations_directory).join(layout::check_name(market)?);
+    fs::create_dir_all(&directory)?;
+
+    let filename = directory.join(format!("{}.annotations", layout::check_name(symbol)?));
+    let filename = filename.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Annotations path is not UTF-8"))?;
+    let annotations = Annotations::open(filename)?;
+    opened.insert(key, annotations.clone());
+
+    Ok(Some(annotations))
+}
+
+/// The scheduled events, kept in the configured events file
+pub fn events() -> io::Result<Events> {
+    EVENTS.as_ref().map(Events::clone).map_err(|error| io::Error::new(error.kind(), error.to_string()))
+}
+
+/// Every served channel, by market, symbol, and name, in that order
+pub fn served_channels() -> Vec<(&'static str, &'static str, &'static str, &'static ServedChannel)> {
+    let mut channels = markets().values()
+        .flat_map(|m| m.0.values())
+        .flat_map(|s| s.0.values())
+        .map(|&served| (served.market.as_str(), served.symbol.as_str(), served.name.as_str(), served))
+        .collect::<Vec<_>>();
+
+    channels.sort_by(|a, b| (a.0, a.1, a.2).cmp(&(b.0, b.1, b.2)));
+    channels
+}
+
+/// Finds a served channel, by its symbol's canonical name or the market's own name for it
+pub fn find_channel(market: &str, symbol: &str, channel: &str) -> Option<&'static ServedChannel> {
+    let find = |symbol: &str| markets().get(market)
+        .and_then(|m| m.0.get(symbol))
+        .and_then(|s| s.0.get(channel))
+        .cloned();
+
+    find(symbol).or_else(|| canonical_symbol(market, symbol).ok().and_then(|canonical| find(&canonical)))
+}
+
+/// The registry of served channels.  It's only ever changed whole, so it can still be read if a reload panicked.
+fn markets() -> RwLockReadGuard<'static, HashMap<String, Market>> {
+    MARKETS.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// What reloading the configuration changed about the served channels, as "market/symbol/channel" paths.  A
+/// channel whose storage was reconfigured is both removed and added.
+pub struct Reload {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Brings the served channels in line with a reloaded configuration.  Channels that were dropped from it, or whose
+/// storage file, unit, or validation changed, are taken out of the registry and closed once the requests already
+/// using them finish, and channels new to it are opened.  Channels it leaves alone keep serving throughout.  If a
+/// channel can't be opened, the reload stops there, and the channels already removed stay removed.
+pub fn reload_channels(config: &Config) -> io::Result<Reload> {
+    let _reloading = RELOADING.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Reload was poisoned"))?;
+
+    config.slow_queries.validate()?;
+    check_derived(config)?;
+
+    let mut retired: Vec<&'static ServedChannel> = Vec::new();
+    loop {
+        let retiring = served_channels().into_iter()
+            .map(|(_, _, _, served)| served)
+            .filter(|&served| !retired.iter().any(|&r| ptr::eq(r, served)) && !served.kept_by(config, &retired))
+            .collect::<Vec<_>>();
+
+        if retiring.is_empty() {
+            break;
+        }
+        retired.extend(retiring);
+    }
+
+    {
+        let mut markets = MARKETS.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for served in &retired {
+            unregister(&mut markets, served);
+        }
+    }
+
+    // Draining happens outside the registry's lock, so that a long request on a retired channel doesn't hold up
+    // requests on the others
+    for served in &retired {
+        if let Err(error) = served.drain() {
+            eprintln!("Could not sync {}: {}", served.path(), error);
+        }
+    }
+
+    let mut markets = MARKETS.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut added = Vec::new();
+
+    for channel in &config.channels {
+        let channels = symbol_channels(&mut markets, &channel.market, &channel.symbol);
+        if !channels.contains_key(&channel.name) {
+            let served = serve(open_channel(config, channel)?);
+            channels.insert(channel.name.clone(), served);
+            added.push(served.path());
+        }
+    }
+
+    for derived in &config.derived_channels {
+        if !symbol_channels(&mut markets, &derived.market, &derived.symbol).contains_key(&derived.name) {
+            let served = serve(derive_channel(&markets, derived)?);
+            symbol_channels(&mut markets, &derived.market, &derived.symbol).insert(derived.name.clone(), served);
+            added.push(served.path());
+        }
+    }
+
+    Ok(Reload {
+        added: added,
+        removed: retired.iter().map(|served| served.path()).collect(),
+    })
+}
+
+/// Serves a channel that isn't in the configuration.  Reloads leave it alone, and it's public until a reload
+/// says otherwise.
+pub fn register(market: &str, symbol: &str, name: &str, channel: Channel, info: ChannelInfo) -> io::Result<()> {
+    let _reloading = RELOADING.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Reload was poisoned"))?;
+    let mut markets = MARKETS.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let channels = symbol_channels(&mut markets, market, symbol);
+    if channels.contains_key(name) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Channel is already served"));
+    }
+
+    channels.insert(name.to_string(), serve(ServedChannel {
+        market: market.to_string(),
+        symbol: symbol.to_string(),
+        name: name.to_string(),
+        channel: Arc::new(RwLock::new(channel)),
+        public: AtomicBool::new(true),
+        quarantine: None,
+        info: info,
+        origin: Origin::Registered,
+    }));
+
+    Ok(())
+}
+
+/// Drains every served channel, for exiting without losing the records stored just before.  Stores that arrive
+/// afterward fail, and reloads wait for it.  Gives the channels that couldn't be synced, as
+/// "market/symbol/channel" paths.
+pub fn shutdown() -> Vec<(String, io::Error)> {
+    let _reloading = RELOADING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    served_channels().into_iter()
+        .filter_map(|(_, _, _, served)| served.drain().err().map(|error| (served.path(), error)))
+        .collect()
+}
+
+/// Takes a channel out of the registry, along with its symbol and market if they're left empty
+fn unregister(markets: &mut HashMap<String, Market>, served: &ServedChannel) {
+    let market_empty = match markets.get_mut(&served.market) {
+        Some(market) => {
+            let symbol_empty = match market.0.get_mut(&served.symbol) {
+                Some(symbol) => {
+                    symbol.0.remove(&served.name);
+                    symbol.0.is_empty()
+                },
+                None => false,
+            };
+            if symbol_empty {
+                market.0.remove(&served.symbol);
+            }
+            market.0.is_empty()
+        },
+        None => false,
+    };
+
+    if market_empty {
+        markets.remove(&served.market);
+    }
+}
+
+/// The channels to serve and how to authenticate callers, as declared in the configuration file.  Parsed from
+/// the file's TOML with `str::parse`.
+#[derive(Deserialize)]
+pub struct Config {
+    #[serde(default, rename = "channel")]
+    channels: Vec<ChannelConfig>,
+    #[serde(default, rename = "derived")]
+    derived_channels: Vec<DerivedChannelConfig>,
+    #[serde(default, rename = "key")]
+    pub keys: Vec<KeyConfig>,
+    pub jwt: Option<JwtConfig>,
+    pub mtls: Option<MtlsConfig>,
+    /// The address to serve WebSocket streams on
+    #[serde(default = "default_stream_address")]
+    pub stream_address: String,
+    /// The address to serve gRPC on, if the server was built with it
+    #[serde(default = "default_grpc_address")]
+    pub grpc_address: String,
+    /// The sizes of the worker pools that ingestion and queries run on
+    #[serde(default)]
+    pub pools: PoolConfig,
+    /// Which queries are logged as slow, and when they're stopped
+    #[serde(default)]
+    pub slow_queries: SlowQueryConfig,
+    /// Where the annotations of each symbol are kept
+    #[serde(default = "default_annotations_directory")]
+    pub annotations_directory: String,
+    /// Where scheduled events are kept
+    #[serde(default = "default_events_file")]
+    pub events_file: String,
+    /// The storage root.  With one, channels are kept at "<root>/<market>/<symbol>/<channel>" unless they name a
+    /// file, which is then relative to the root and can't be outside of it.  Without one, channels name their
+    /// files as paths of their own, and default to "<market>_<symbol>_<channel>".
+    pub data_directory: Option<String>,
+    /// How markets name their symbols, for markets that don't name them canonically
+    #[serde(default, rename = "market")]
+    pub markets: Vec<MarketConfig>,
+}
+
+/// How a market names its symbols, e.g. "XBT/USD" for the canonical "btcusd"
+#[derive(Deserialize)]
+pub struct MarketConfig {
+    name: String,
+    /// What comes between a pair's base and quote assets, if anything
+    separator: Option<char>,
+    #[serde(default)]
+    uppercase: bool,
+    /// Canonical assets by the market's names for them, e.g. XBT = "btc"
+    #[serde(default)]
+    aliases: HashMap<String, String>,
+    /// The assets the market quotes pairs in, so that canonical symbols can be split into their assets
+    #[serde(default)]
+    quotes: Vec<String>,
+    /// The market's names for pairs that follow no rule, by canonical symbol
+    #[serde(default)]
+    symbols: HashMap<String, String>,
+}
+
+impl MarketConfig {
+    fn symbol_map(&self) -> SymbolMap {
+        let format = SymbolFormat { separator: self.separator, uppercase: self.uppercase };
+
+        let map = self.aliases.iter().fold(SymbolMap::new(format), |map, (external, canonical)| map.alias(external, canonical));
+        let map = self.quotes.iter().fold(map, |map, quote| map.quote(quote));
+        self.symbols.iter().fold(map, |map, (canonical, external)| map.symbol(canonical, external))
+    }
+}
+
+fn symbol_registry(config: &Config) -> SymbolRegistry {
+    let mut registry = SymbolRegistry::new();
+    for market in &config.markets {
+        registry.insert(&market.name, market.symbol_map());
+    }
+    registry
+}
+
+/// Applies the symbol names of a reloaded configuration
+pub fn reload_symbols(config: &Config) {
+    *SYMBOLS.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = symbol_registry(config);
+}
+
+/// The canonical name of a symbol as a market names it
+pub fn canonical_symbol(market: &str, external: &str) -> io::Result<String> {
+    SYMBOLS.read().unwrap_or_else(|poisoned| poisoned.into_inner()).canonical(market, external)
+}
+
+/// A market's name for a canonical symbol
+pub fn external_symbol(market: &str, canonical: &str) -> io::Result<String> {
+    SYMBOLS.read().unwrap_or_else(|poisoned| poisoned.into_inner()).external(market, canonical)
+}
+
+/// Ingestion and queries run on separate pools of threads, so that queries can't delay ingestion.  Each pool
+/// takes up to its queue's worth of work beyond what its threads are running, and turns more away.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    pub ingest_threads: usize,
+    pub ingest_queue: usize,
+    pub query_threads: usize,
+    pub query_queue: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            ingest_threads: 2,
+            ingest_queue: 256,
+            query_threads: 4,
+            query_queue: 64,
+        }
+    }
+}
+
+fn default_stream_address() -> String {
+    "127.0.0.1:8001".to_string()
+}
+
+fn default_grpc_address() -> String {
+    "127.0.0.1:8002".to_string()
+}
+
+fn default_annotations_directory() -> String {
+    "annotations".to_string()
+}
+
+fn default_events_file() -> String {
+    "events".to_string()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            channels: vec![ChannelConfig {
+                market: "gemini".to_string(),
+                symbol: "btcusd".to_string(),
+                name: "trades".to_string(),
+                configured_file: None,
+                file: layout::flat_name("gemini", "btcusd", "trades"),
+                unit: None,
+                value_type: None,
+                public: true,
+                validate: None,
+                rollup: None,
+                dedup_window: None,
+                description: None,
+                precision: None,
+                value_unit: None,
+                currency: None,
+                preload: None,
+            }],
+            derived_channels: Vec::new(),
+            keys: Vec::new(),
+            jwt: None,
+            mtls: None,
+            stream_address: default_stream_address(),
+            grpc_address: default_grpc_address(),
+            pools: PoolConfig::default(),
+            slow_queries: SlowQueryConfig::default(),
+            annotations_directory: default_annotations_directory(),
+            events_file: default_events_file(),
+            data_directory: None,
+            markets: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Works out where each channel's file is
+    fn resolve_files(mut self) -> io::Result<Self> {
+        let data_directory = self.data_directory.clone();
+        for channel in &mut self.channels {
+            channel.file = channel.path(data_directory.as_ref().map(Path::new))?;
+
+            if let Some(ref mut validate) = channel.validate {
+                validate.quarantine_file = match (data_directory.as_ref(), validate.quarantine_file.take()) {
+                    (Some(root), Some(file)) => Some(path_string(layout::resolve(Path::new(root), &file)?)?),
+                    (_, file) => file,
+                };
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+impl FromStr for Config {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        let config: Config = toml::from_str(text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+        config.resolve_files()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct ChannelConfig {
+    market: String,
+    symbol: String,
+    name: String,
+    /// The storage file as configured, if it was
+    #[serde(rename = "file")]
+    configured_file: Option<String>,
+    /// Where the storage file is, once the configuration has been read
+    #[serde(skip)]
+    file: String,
+    /// The unit of the channel's timestamps: "ms", the default, "us", or "ns"
+    unit: Option<String>,
+    /// The type of the channel's values, by its name in the storage registry: "u64", the default, "quote",
+    /// "sourced", or "trade".  Only "u64" channels can be validated, and the query routes read them alone.
+    value_type: Option<String>,
+    #[serde(default = "default_public")]
+    public: bool,
+    /// Constraints that records must satisfy to be stored
+    validate: Option<ValidationConfig>,
+    /// Rolls records up into coarser buckets once they're older than a window, dropping the raw records.  Only
+    /// unvalidated "u64" channels can be rolled up.
+    rollup: Option<RollupConfig>,
+    /// Skips records whose trade IDs were already stored within this interval of them, such as "1m", so that a
+    /// backfill overlapping the live feed doesn't store its trades twice.  Only "trade" channels have trade IDs.
+    dedup_window: Option<String>,
+    /// What the channel holds, for clients discovering channels
+    description: Option<String>,
+    /// How many decimal places the values have been scaled by, e.g. 2 for prices in cents
+    precision: Option<u32>,
+    /// What the values count, e.g. "BTC", for clients rendering them
+    value_unit: Option<String>,
+    /// The code of the currency the values are amounts of, e.g. "USD"
+    currency: Option<String>,
+    /// Where to start reading the channel ahead of time after startup, as a time such as "now-1d", so that the
+    /// first queries after a deploy don't have to bring its recent records in from disk
+    preload: Option<String>,
+}
+
+impl ChannelConfig {
+    /// Where the channel's storage file is, given the storage root if there is one
+    fn path(&self, root: Option<&Path>) -> io::Result<String> {
+        match (root, self.configured_file.as_ref()) {
+            (Some(root), Some(file)) => path_string(layout::resolve(root, file)?),
+            (Some(root), None) => path_string(layout::channel_path(root, &self.market, &self.symbol, &self.name)?),
+            (None, Some(file)) => Ok(file.clone()),
+            (None, None) => {
+                for name in &[&self.market, &self.symbol, &self.name] {
+                    layout::check_name(name)?;
+                }
+                Ok(layout::flat_name(&self.market, &self.symbol, &self.name))
+            },
+        }
+    }
+
+    /// Whether two configurations are of the same channel, stored the same way
+    fn same_storage(&self, other: &ChannelConfig) -> bool {
+        (&self.market, &self.symbol, &self.name, &self.file, &self.unit, &self.value_type, &self.validate, &self.rollup, &self.dedup_window) ==
+            (&other.market, &other.symbol, &other.name, &other.file, &other.unit, &other.value_type, &other.validate, &other.rollup, &other.dedup_window)
+    }
+
+    /// Moves the channel's file into the storage root if it's still kept flat in the working directory, as it was
+    /// before there was a root
+    fn migrate(&self, root: &Path) -> io::Result<()> {
+        if self.configured_file.is_none() {
+            let flat = layout::flat_name(&self.market, &self.symbol, &self.name);
+            if layout::migrate(root, &self.market, &self.symbol, &self.name, Path::new(&flat))? {
+                eprintln!("Moved {} to {}", flat, self.file);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+struct ValidationConfig {
+    min: Option<f64>,
+    max: Option<f64>,
+    /// The furthest a record's timestamp may be from the wall clock, as an interval such as "5m"
+    max_skew: Option<String>,
+    /// The largest change from the previous record, as a fraction of its value
+    max_jump: Option<f64>,
+    #[serde(default)]
+    policy: PolicyConfig,
+    /// Where quarantined records are stored.  Defaults to the channel's file with ".quarantine" appended.
+    quarantine_file: Option<String>,
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum PolicyConfig {
+    Reject,
+    Clamp,
+    Quarantine,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        PolicyConfig::Reject
+    }
+}
+
+impl ValidationConfig {
+    /// Wraps a channel's storage so that records are validated before they're stored
+    fn wrap(&self, storage: FileStorage<Timestamp, Timestamp>, file: &str, unit: TimeUnit) -> io::Result<ValidatedStore<FileStorage<Timestamp, Timestamp>>> {
+        let constraints = Constraints {
+            min_value: self.min,
+            max_value: self.max,
+            max_skew: match self.max_skew {
+                Some(ref max_skew) => Some(parse_interval(max_skew)?),
+                None => None,
+            },
+            max_jump: self.max_jump,
+        };
+
+        let policy = match self.policy {
+            PolicyConfig::Reject => ValidationPolicy::Reject,
+            PolicyConfig::Clamp => ValidationPolicy::Clamp,
+            PolicyConfig::Quarantine => {
+                let quarantine_file = self.quarantine_file.clone().unwrap_or_else(|| format!("{}.quarantine", file));
+                ValidationPolicy::Quarantine(Quarantine::open(&quarantine_file)?)
+            },
+        };
+
+        Ok(ValidatedStore::new::<Timestamp>(storage, constraints, policy).unit(unit))
+    }
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+struct RollupConfig {
+    /// How long records are kept at full resolution, as an interval such as "30d"
+    window: String,
+    /// The length of the buckets older records are pooled into.  Defaults to "1m".
+    interval: Option<String>,
+    #[serde(default)]
+    pooling: RollupPoolingConfig,
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum RollupPoolingConfig {
+    End,
+    High,
+    Low,
+    Mean,
+    Sum,
+}
+
+impl Default for RollupPoolingConfig {
+    fn default() -> Self {
+        RollupPoolingConfig::End
+    }
+}
+
+impl RollupConfig {
+    /// The roll-up policy of a channel of timestamps in `unit`
+    fn policy(&self, unit: TimeUnit) -> io::Result<RollupPolicy> {
+        let interval = self.interval.as_ref().map_or(Ok(60 * 1000), |interval| parse_interval(interval))?;
+
+        Ok(RollupPolicy {
+            window: TimeUnit::Milliseconds.convert(parse_interval(&self.window)?, unit),
+            interval: Interval::new(TimeUnit::Milliseconds.convert(interval, unit))?,
+            pooling: match self.pooling {
+                RollupPoolingConfig::End => PoolingMethod::End,
+                RollupPoolingConfig::High => PoolingMethod::High,
+                RollupPoolingConfig::Low => PoolingMethod::Low,
+                RollupPoolingConfig::Mean => PoolingMethod::Mean,
+                RollupPoolingConfig::Sum => PoolingMethod::Sum,
+            },
+            // The default is in milliseconds, like the configured intervals
+            batch: TimeUnit::Milliseconds.convert(RollupPolicy::default().batch, unit),
+        })
+    }
+}
+
+/// A channel computed from other channels of the same market.  Sources are channels of the same symbol, or
+/// "symbol/channel" for another symbol's, as the second leg of a cross rate would be.
+#[derive(Clone, Deserialize)]
+struct DerivedChannelConfig {
+    market: String,
+    symbol: String,
+    name: String,
+    kind: DerivedKind,
+    sources: Vec<String>,
+    /// For cross rates, how old either leg's latest value can be before nothing is derived from it, e.g. "5m"
+    max_age: Option<String>,
+    #[serde(default = "default_public")]
+    public: bool,
+    description: Option<String>,
+}
+
+impl DerivedChannelConfig {
+    /// Whether two configurations are of the same channel, derived the same way
+    fn same_derivation(&self, other: &DerivedChannelConfig) -> bool {
+        (&self.market, &self.symbol, &self.name, self.kind, &self.sources, &self.max_age) ==
+            (&other.market, &other.symbol, &other.name, other.kind, &other.sources, &other.max_age)
+    }
+
+    /// The symbol and name of a source
+    fn source<'a>(&'a self, source: &'a str) -> (&'a str, &'a str) {
+        let mut parts = source.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(symbol), Some(name)) => (symbol, name),
+            _ => (self.symbol.as_str(), source),
+        }
+    }
+}
+
+/// Channels are public unless the configuration says otherwise
+fn default_public() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum DerivedKind {
+    /// The first source minus the second, e.g. ask minus bid
+    Spread,
+    /// The average of the two sources
+    Midpoint,
+    /// The sum of the two sources
+    Sum,
+    /// The total size of any number of book levels, given as their size channels from the top of the book down
+    Depth,
+    /// A cross rate of the first source times the second, e.g. btceur from btcusd and usdeur/trades, at the
+    /// first source's precision
+    CrossProduct,
+    /// A cross rate of the first source over the second, e.g. btceur from btcusd and eurusd/trades, at the first
+    /// source's precision
+    CrossRatio,
+}
+
+impl DerivedKind {
+    fn name(&self) -> &'static str {
+        match *self {
+            DerivedKind::Spread => "Spread",
+            DerivedKind::Midpoint => "Midpoint",
+            DerivedKind::Sum => "Sum",
+            DerivedKind::Depth => "Depth",
+            DerivedKind::CrossProduct => "Product",
+            DerivedKind::CrossRatio => "Ratio",
+        }
+    }
+}
+
+fn load_markets(config: &Config) -> io::Result<HashMap<String, Market>> {
+    config.slow_queries.validate()?;
+    check_derived(config)?;
+
+    let mut markets = HashMap::new();
+
+    for channel in &config.channels {
+        let served = serve(open_channel(config, channel)?);
+        symbol_channels(&mut markets, &channel.market, &channel.symbol).insert(channel.name.clone(), served);
+    }
+
+    // Derived channels are loaded in order, so they may be built on top of earlier derived channels
+    for derived in &config.derived_channels {
+        let served = serve(derive_channel(&markets, derived)?);
+        symbol_channels(&mut markets, &derived.market, &derived.symbol).insert(derived.name.clone(), served);
+    }
+
+    Ok(markets)
+}
+
+/// Leaks a served channel into the registry's keeping
+fn serve(served: ServedChannel) -> &'static ServedChannel {
+    Box::leak(Box::new(served))
+}
+
+/// Checks that each derived channel has the right number of sources, and that they're configured ahead of it
+fn check_derived(config: &Config) -> io::Result<()> {
+    for (i, derived) in config.derived_channels.iter().enumerate() {
+        match derived.kind {
+            DerivedKind::Depth if derived.sources.is_empty() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Depth channels need at least one source"));
+            },
+            DerivedKind::Depth => (),
+            _ if derived.sources.len() != 2 => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Derived channels need exactly two sources"));
+            },
+            _ => (),
+        }
+
+        if let Some(ref max_age) = derived.max_age {
+            parse_interval(max_age)?;
+        }
+
+        let channels = config.channels.iter().map(|c| (&c.market, &c.symbol, &c.name));
+        let earlier = config.derived_channels[..i].iter().map(|d| (&d.market, &d.symbol, &d.name));
+        let available = channels.chain(earlier).filter(|&(m, _, _)| *m == derived.market);
+
+        if derived.sources.iter().any(|source| !available.clone().any(|(_, s, name)| derived.source(source) == (s.as_str(), name.as_str()))) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Derived channel source was not found"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Opens a stored channel's file, moving it into the storage root first if need be
+fn open_channel(config: &Config, channel: &ChannelConfig) -> io::Result<ServedChannel> {
+    if let Some(ref root) = config.data_directory {
+        channel.migrate(Path::new(root))?;
+
+        if let Some(parent) = Path::new(&channel.file).parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let unit = channel.unit.as_ref().map_or(Ok(TimeUnit::Milliseconds), |unit| unit.parse())?;
+    let value_type = channel.value_type.as_ref().map_or("u64", |value_type| value_type.as_str());
+    if !STORAGE.contains(value_type) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Channel values can't be of type \"{}\"", value_type)));
+    }
+    if channel.dedup_window.is_some() && value_type != "trade" {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only trade channels can be deduplicated"));
+    }
+
+    // The configuration can describe a channel after it was created, but not change what it holds
+    let stored_info = ChannelInfo::load_or_create(&channel.file, ChannelInfo { value_type: value_type.to_string(), ..ChannelInfo::of::<Timestamp>(unit) })?;
+    if stored_info.value_type != value_type {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Channel holds {} values, not {}", stored_info.value_type, value_type)));
+    }
+    let info = ChannelInfo {
+        precision: channel.precision.or(stored_info.precision),
+        value_unit: channel.value_unit.clone().or_else(|| stored_info.value_unit.clone()),
+        currency: channel.currency.clone().or_else(|| stored_info.currency.clone()),
+        description: channel.description.clone().or_else(|| stored_info.description.clone()),
+        ..stored_info.clone()
+    };
+    if info != stored_info {
+        info.save(&channel.file)?;
+    }
+    let (storage, quarantine) = match (channel.validate.as_ref(), value_type) {
+        (Some(_), _) if channel.rollup.is_some() => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Rolled up channels can't be validated")),
+        (Some(validate), "u64") => {
+            let validated = validate.wrap(FileStorage::<Timestamp, Timestamp>::with_unit(&channel.file, unit)?, &channel.file, unit)?;
+            let quarantine = validated.quarantine().cloned();
+            (Channel::PooledTimeSeries(Box::new(validated)), quarantine)
+        },
+        (Some(_), _) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only u64 channels can be validated")),
+        (None, "u64") => match channel.rollup {
+            Some(ref rollup) => (Channel::PooledTimeSeries(Box::new(RollupStorage::<Timestamp>::with_unit(&channel.file, unit, rollup.policy(unit)?)?)), None),
+            None => (Channel::PooledTimeSeries(STORAGE.open(value_type, &channel.file, unit)?), None),
+        },
+        (None, _) if channel.rollup.is_some() => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only u64 channels can be rolled up")),
+        (None, "trade") => match channel.dedup_window {
+            Some(ref window) => {
+                let storage = FileStorage::<Timestamp, Identified<Timestamp>>::with_unit(&channel.file, unit)?;
+                let window = TimeUnit::Milliseconds.convert(parse_interval(window)?, unit);
+                (Channel::PooledTimeSeries(Box::new(DedupStore::new::<Timestamp>(storage, window)?)), None)
+            },
+            None => (Channel::PooledTimeSeries(STORAGE.open(value_type, &channel.file, unit)?), None),
+        },
+        (None, _) => (Channel::PooledTimeSeries(STORAGE.open(value_type, &channel.file, unit)?), None),
+    };
+
+    Ok(ServedChannel {
+        market: channel.market.clone(),
+        symbol: channel.symbol.clone(),
+        name: channel.name.clone(),
+        channel: Arc::new(RwLock::new(storage)),
+        public: AtomicBool::new(channel.public),
+        quarantine: quarantine,
+        info: info,
+        origin: Origin::Stored(channel.clone()),
+    })
+}
+
+/// Builds a derived channel on top of its market's other channels
+fn derive_channel(markets: &HashMap<String, Market>, derived: &DerivedChannelConfig) -> io::Result<ServedChannel> {
+    let mut sources = Vec::new();
+    let mut precisions = Vec::new();
+    for source in &derived.sources {
+        let (symbol, name) = derived.source(source);
+        let channel = markets.get(&derived.market)
+            .and_then(|m| m.0.get(symbol))
+            .and_then(|s| s.0.get(name))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Derived channel source was not found"))?;
+        sources.push(Box::new(ChannelSource(channel.channel.clone())) as Box<dyn DerivedSource>);
+        precisions.push(channel.info.precision.unwrap_or(0));
+    }
+
+    let max_age = derived.max_age.as_ref().map(|max_age| parse_interval(max_age)).transpose()?;
+
+    let channel = if let DerivedKind::Depth = derived.kind {
+        DerivedChannel::<Timestamp>::consolidate(sources.into_iter().map(|source| (source, 1.0)).collect(), Consolidation::Sum)
+    } else {
+        let b = sources.pop().unwrap();
+        let a = sources.pop().unwrap();
+
+        match derived.kind {
+            DerivedKind::Spread => DerivedChannel::combine::<Timestamp, Timestamp, _>(a, b, |a, b| a.saturating_sub(b)),
+            DerivedKind::Midpoint => DerivedChannel::combine::<Timestamp, Timestamp, _>(a, b, |a, b| a / 2 + b / 2 + (a % 2 + b % 2) / 2),
+            DerivedKind::Sum | DerivedKind::Depth => DerivedChannel::combine::<Timestamp, Timestamp, _>(a, b, |a, b| a.saturating_add(b)),
+            // Scaled so that the second leg's decimal places cancel out
+            DerivedKind::CrossProduct => DerivedChannel::cross_rate(a, b, CrossRate::Product, 10f64.powi(-(precisions[1] as i32)), max_age),
+            DerivedKind::CrossRatio => DerivedChannel::cross_rate(a, b, CrossRate::Ratio, 10f64.powi(precisions[1] as i32), max_age),
+        }
+    };
+
+    let precision = match derived.kind {
+        DerivedKind::CrossProduct | DerivedKind::CrossRatio => Some(precisions[0]),
+        _ => None,
+    };
+
+    let info = ChannelInfo {
+        precision: precision,
+        description: Some(derived.description.clone().unwrap_or_else(|| format!("{} of {}", derived.kind.name(), derived.sources.join(" and ")))),
+        created_at: None,
+        ..ChannelInfo::of::<Timestamp>(channel.time_unit())
+    };
+
+    Ok(ServedChannel {
+        market: derived.market.clone(),
+        symbol: derived.symbol.clone(),
+        name: derived.name.clone(),
+        channel: Arc::new(RwLock::new(Channel::PooledTimeSeries(Box::new(channel)))),
+        public: AtomicBool::new(derived.public),
+        quarantine: None,
+        info: info,
+        origin: Origin::Derived(derived.clone()),
+    })
+}
+
+fn symbol_channels<'a>(markets: &'a mut HashMap<String, Market>, market: &str, symbol: &str) -> &'a mut HashMap<String, &'static ServedChannel> {
+    let market = markets.entry(market.to_string()).or_insert_with(|| Market(HashMap::new()));
+    let symbol = market.0.entry(symbol.to_string()).or_insert_with(|| Symbol(HashMap::new()));
+    &mut symbol.0
+}
+
+/// A served channel as the source of a channel derived on request
+pub fn channel_source(market: &str, symbol: &str, channel: &str) -> Option<Box<dyn DerivedSource>> {
+    find_channel(market, symbol, channel).map(|served| Box::new(ChannelSource(served.channel.clone())) as Box<dyn DerivedSource>)
+}
+
+/// Lets a served channel be used as the source of a derived channel
+struct ChannelSource(Arc<RwLock<Channel>>);
+
+impl DerivedSource for ChannelSource {
+    fn query(&self, query: &Query) -> io::Result<Retrieval> {
+        let channel = self.0.read().map_err(|_| io::Error::new(io::ErrorKind::Other, "Derived channel source was poisoned"))?;
+
+        if let Some(pooled_time_series) = channel.as_pooled_time_series() {
+            query.retrieve_pooled(pooled_time_series)
+        } else if let Some(time_series) = channel.as_time_series() {
+            query.retrieve(time_series)
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "Derived channel source is not a time series"))
+        }
+    }
+}
+
+pub enum Channel {
+    KeyValueStore(Box<dyn KeyValueStore>),
+    TimeSeries(Box<dyn TimeSeries>),
+    PooledTimeSeries(Box<dyn PooledTimeSeries>),
+    /// A channel a reload took out of service, whose storage has been closed
+    Closed,
+}
+
+impl Channel {
+    pub fn as_key_value_store(&self) -> Option<&dyn KeyValueStore> {
+        match self {
+            Channel::KeyValueStore(x) => Some(&**x),
+            Channel::TimeSeries(x) => Some(x.as_key_value_store()),
+            Channel::PooledTimeSeries(x) => Some(x.as_key_value_store()),
+            Channel::Closed => None,
+        }
+    }
+
+    pub fn as_time_series(&self) -> Option<&dyn TimeSeries> {
+        match self {
+            Channel::KeyValueStore(_) => None,
+            Channel::TimeSeries(x) => Some(&**x),
+            Channel::PooledTimeSeries(x) => Some(x.as_time_series()),
+            Channel::Closed => None,
+        }
+    }
+
+    pub fn as_pooled_time_series(&self) -> Option<&dyn PooledTimeSeries> {
+        match self {
+            Channel::KeyValueStore(_) => None,
+            Channel::TimeSeries(_) => None,
+            Channel::PooledTimeSeries(x) => Some(&**x),
+            Channel::Closed => None,
+        }
+    }
+
+    pub fn as_mut_key_value_store(&mut self) -> Option<&mut dyn KeyValueStore> {
+        match self {
+            Channel::KeyValueStore(x) => Some(&mut **x),
+            Channel::TimeSeries(x) => Some(x.as_mut_key_value_store()),
+            Channel::PooledTimeSeries(x) => Some(x.as_mut_key_value_store()),
+            Channel::Closed => None,
+        }
+    }
+
+    pub fn as_mut_time_series(&mut self) -> Option<&mut dyn TimeSeries> {
+        match self {
+            Channel::KeyValueStore(_) => None,
+            Channel::TimeSeries(x) => Some(&mut **x),
+            Channel::PooledTimeSeries(x) => Some(x.as_mut_time_series()),
+            Channel::Closed => None,
+        }
+    }
+
+    pub fn as_mut_pooled_time_series(&mut self) -> Option<&mut dyn PooledTimeSeries> {
+        match self {
+            Channel::KeyValueStore(_) => None,
+            Channel::TimeSeries(_) => None,
+            Channel::PooledTimeSeries(x) => Some(&mut **x),
+            Channel::Closed => None,
+        }
+    }
+}
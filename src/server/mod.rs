@@ -0,0 +1,1913 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The HTTP server, and its live stream and gRPC endpoints.
+//!
+//! A `Server` serves the channels of a configuration, along with any channels registered on its builder, so that an
+//! application can host the trade-data API in its own process:
+//!
+//! ```ignore
+//! let server = Server::builder()
+//!     .config(config)
+//!     .channel("gemini", "btcusd", "trades", Channel::TimeSeries(Box::new(storage)), info)
+//!     .build()?;
+//!
+//! server.launch();
+//! ```
+//!
+//! The served channels are global to the process, so only one server can be built in it.
+
+use std::io::Cursor;
+#[cfg(unix)]
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+
+use rocket::{Request, Rocket};
+use rocket::error::LaunchError;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Accept, ContentType, RawStr, Status};
+use rocket::request::FromFormValue;
+use rocket::response::{self, status, Responder, Response};
+use rocket_contrib::json::Json;
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+
+use {Bands, BucketAnchor, ChannelInfo, Consolidation, DerivedChannel, GapFillMethod, HolidayCalendar, HolidayPolicy, Indicator, IoStats, OpenBucket, PoolingMethod, Query, RecordRate, Sampling, StoreStats, TimeUnit, Timestamp, Transform, Value, ValueType};
+use api::{API_VERSION, VERSION_HEADER};
+use microstructure::{average_spread, size_distribution};
+use parse::{self, parse_interval, parse_timestamp};
+use storage::{Annotation, Event, Quarantine};
+
+use self::auth::{Access, Admin, Caller};
+
+/// Logs a report of the runtime configuration, as the binary does at startup
+pub use self::about::log as log_about;
+pub use self::gc::{GC_AGE, collect_garbage};
+pub use self::market::{Channel, Config, ServedChannel, channel_files, channel_units, find_channel, read_config};
+
+mod about;
+mod auth;
+mod gc;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod live;
+mod market;
+mod slow_queries;
+mod usage;
+mod workers;
+
+#[get("/")]
+fn index() -> &'static str {
+    "Hello world!"
+}
+
+#[derive(Serialize)]
+struct DataThing {
+    value: String,
+}
+
+// Every other route of three segments could match its path, so it's ranked after them all
+#[get("/<market>/<symbol>/<channel>", rank = 3)]
+fn get_data(market: String, symbol: String, channel: String) -> Json<DataThing> {
+    Json(DataThing { value: format!("You asked for the {} market, and the {} symbol, and the {} channel.", market, symbol, channel) })
+}
+
+/// Attaches the disk activity of a query to the response as debug headers
+struct WithIoStats<R> {
+    inner: R,
+    io_stats: IoStats,
+}
+
+impl<'r, R> Responder<'r> for WithIoStats<R> where R: Responder<'r> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        Response::build_from(self.inner.respond_to(request)?)
+            .raw_header("X-Debug-Io-Bytes-Read", self.io_stats.bytes_read.to_string())
+            .raw_header("X-Debug-Io-Seeks", self.io_stats.seeks.to_string())
+            .ok()
+    }
+}
+
+/// Attaches the cursor of the next page of a paged query to the response
+struct Paged<R> {
+    inner: R,
+    next_cursor: Option<::Cursor>,
+}
+
+impl<'r, R> Responder<'r> for Paged<R> where R: Responder<'r> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = self.inner.respond_to(request)?;
+        if let Some(next_cursor) = self.next_cursor {
+            response.set_raw_header("X-Trade-Data-Next-Cursor", next_cursor.to_string());
+        }
+        Ok(response)
+    }
+}
+
+/// Attaches the interval a downsampled query was pooled by, in milliseconds, to the response.  Nothing is attached if
+/// it wasn't pooled.
+struct Downsampled<R> {
+    inner: R,
+    interval: Option<Timestamp>,
+}
+
+impl<'r, R> Responder<'r> for Downsampled<R> where R: Responder<'r> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = self.inner.respond_to(request)?;
+        if let Some(interval) = self.interval {
+            response.set_raw_header("X-Trade-Data-Interval", interval.to_string());
+        }
+        Ok(response)
+    }
+}
+
+/// Attaches the anchor that placed the first bucket of a pooled query, as "first_record" or "requested_start", to the
+/// response.  Nothing is attached if the range didn't start before the first record, since then neither did.
+struct Anchored<R> {
+    inner: R,
+    anchor: Option<BucketAnchor>,
+}
+
+impl<'r, R> Responder<'r> for Anchored<R> where R: Responder<'r> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = self.inner.respond_to(request)?;
+        if let Some(anchor) = self.anchor {
+            response.set_raw_header("X-Trade-Data-Anchor", anchor_name(anchor));
+        }
+        Ok(response)
+    }
+}
+
+fn anchor_name(anchor: BucketAnchor) -> &'static str {
+    match anchor {
+        BucketAnchor::FirstRecord => "first_record",
+        BucketAnchor::RequestedStart => "requested_start",
+    }
+}
+
+/// A time in a query string, in any form accepted by `trade_data::parse`
+struct TimeParam(Timestamp);
+
+impl<'v> FromFormValue<'v> for TimeParam {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        let decoded = value.url_decode().map_err(|_| value)?;
+        parse_timestamp(&decoded, parse::now()).map(TimeParam).map_err(|_| value)
+    }
+}
+
+/// An interval in a query string, in any form accepted by `trade_data::parse`
+struct IntervalParam(Timestamp);
+
+impl<'v> FromFormValue<'v> for IntervalParam {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        let decoded = value.url_decode().map_err(|_| value)?;
+        parse_interval(&decoded).map(IntervalParam).map_err(|_| value)
+    }
+}
+
+#[derive(Serialize)]
+struct Gap {
+    start: Timestamp,
+    end: Timestamp,
+}
+
+#[get("/<market>/<symbol>/<channel>/gaps?<min_gap>&<start>&<end>")]
+fn get_gaps(caller: Caller, market: String, symbol: String, channel: String, min_gap: IntervalParam, start: TimeParam, end: TimeParam) -> Result<WithIoStats<Json<Vec<Gap>>>, Status> {
+    let (min_gap, start, end) = (min_gap.0, start.0, end.0);
+
+    let channel = caller.channel(&market, &symbol, &channel, Access::Read)?;
+
+    workers::QUERY.run(move || {
+        let channel = channel.read().map_err(|_| Status::InternalServerError)?;
+        let time_series = channel.as_time_series().ok_or(Status::BadRequest)?;
+
+        let io_stats_before = time_series.io_stats();
+        let gaps = time_series.find_gaps(min_gap, start..end).map_err(|_| Status::InternalServerError)?;
+
+        Ok(WithIoStats {
+            inner: Json(gaps.into_iter().map(|gap| Gap { start: gap.start, end: gap.end }).collect()),
+            io_stats: time_series.io_stats() - io_stats_before,
+        })
+    })?
+}
+
+#[derive(Serialize)]
+struct ChannelStats {
+    records: usize,
+    first_timestamp: Option<Timestamp>,
+    last_timestamp: Option<Timestamp>,
+    bytes_on_disk: u64,
+    rates: Vec<RateResponse>,
+    bytes_read: u64,
+    seeks: u64,
+}
+
+#[derive(Serialize)]
+struct RateResponse {
+    /// In milliseconds
+    window: Timestamp,
+    per_second: f64,
+}
+
+impl From<RecordRate> for RateResponse {
+    fn from(rate: RecordRate) -> Self {
+        Self {
+            window: rate.window,
+            per_second: rate.per_second,
+        }
+    }
+}
+
+/// What a channel holds, how fast it's been filling, and how much it's read from disk since the server started
+#[get("/<market>/<symbol>/<channel>/stats")]
+fn get_stats(caller: Caller, market: String, symbol: String, channel: String) -> Result<Json<ChannelStats>, Status> {
+    let channel = caller.channel(&market, &symbol, &channel, Access::Read)?;
+    let channel = channel.read().map_err(|_| Status::InternalServerError)?;
+    let key_value_store = channel.as_key_value_store().ok_or(Status::BadRequest)?;
+
+    let stats = key_value_store.stats().map_err(|_| Status::InternalServerError)?;
+    let io_stats = key_value_store.io_stats();
+
+    Ok(Json(ChannelStats {
+        records: stats.records,
+        first_timestamp: stats.first_timestamp,
+        last_timestamp: stats.last_timestamp,
+        bytes_on_disk: stats.bytes_on_disk,
+        rates: stats.rates.into_iter().map(RateResponse::from).collect(),
+        bytes_read: io_stats.bytes_read,
+        seeks: io_stats.seeks,
+    }))
+}
+
+#[derive(Serialize)]
+struct SummaryResponse {
+    count: usize,
+    min: Timestamp,
+    max: Timestamp,
+    mean: Timestamp,
+    sum: Timestamp,
+    first: (Timestamp, Timestamp),
+    last: (Timestamp, Timestamp),
+}
+
+/// Header stats for a channel from `start` to `end`, read in one pass, or null if there are no records
+#[get("/<market>/<symbol>/<channel>/summary?<start>&<end>")]
+fn get_summary(caller: Caller, market: String, symbol: String, channel: String, start: TimeParam, end: TimeParam) -> Result<WithIoStats<Json<Option<SummaryResponse>>>, Status> {
+    let (start, end) = (start.0, end.0);
+
+    let channel = caller.channel(&market, &symbol, &channel, Access::Read)?;
+
+    workers::QUERY.run(move || {
+        let channel = channel.read().map_err(|_| Status::InternalServerError)?;
+        let pooled_time_series = channel.as_pooled_time_series().ok_or(Status::BadRequest)?;
+
+        let io_stats_before = pooled_time_series.io_stats();
+        let summary = pooled_time_series.summarize_range(start..end).map_err(|error| match error.kind() {
+            std::io::ErrorKind::Other => Status::BadRequest,
+            _ => Status::InternalServerError,
+        })?;
+
+        Ok(WithIoStats {
+            inner: Json(summary.into_summary::<Timestamp>().map(|summary| SummaryResponse {
+                count: summary.count,
+                min: summary.min,
+                max: summary.max,
+                mean: summary.mean,
+                sum: summary.sum,
+                first: summary.first,
+                last: summary.last,
+            })),
+            io_stats: pooled_time_series.io_stats() - io_stats_before,
+        })
+    })?
+}
+
+/// The markets to read a symbol's channel from, with their weights, given as in `get_consolidated`.  Defaults to every
+/// market the caller can read the channel on.  Fails if the caller can't read a market that was asked for, or if
+/// there are none.
+fn symbol_markets(caller: &Caller, symbol: &str, channel: &str, markets: Option<String>) -> Result<Vec<(String, f64)>, Status> {
+    let markets = match markets {
+        Some(markets) => markets.split(',')
+            .map(|market| {
+                let mut parts = market.splitn(2, ':');
+                let name = parts.next().unwrap_or("").to_string();
+                let weight = parts.next().map_or(Ok(1.0), |weight| weight.parse::<f64>()).map_err(|_| Status::BadRequest)?;
+                caller.channel(&name, symbol, channel, Access::Read)?;
+                Ok((name, weight))
+            })
+            .collect::<Result<Vec<(String, f64)>, Status>>()?,
+        None => market::served_channels().into_iter()
+            .filter(|&(m, s, name, _)| s == symbol && name == channel && caller.channel(m, s, name, Access::Read).is_ok())
+            .map(|(m, _, _, _)| (m.to_string(), 1.0))
+            .collect(),
+    };
+
+    if markets.is_empty() {
+        Err(Status::NotFound)
+    } else {
+        Ok(markets)
+    }
+}
+
+/// The consolidated price of a symbol's channel across markets.  `markets` lists the markets to consolidate, each with
+/// an optional weight, e.g. "gemini:2,kraken", and defaults to every market the caller can read the channel on, all
+/// weighted 1.  `method` is "median", the default, "mean", "high", or "low".  With an interval, each market is pooled
+/// to the end of each bucket before the buckets are consolidated.
+#[get("/consolidated/<symbol>/<channel>?<start>&<end>&<interval>&<method>&<markets>", rank = 1)]
+fn get_consolidated(caller: Caller, symbol: String, channel: String, start: Option<TimeParam>, end: Option<TimeParam>, interval: Option<IntervalParam>, method: Option<String>, markets: Option<String>) -> Result<Json<Vec<(Timestamp, Timestamp)>>, Status> {
+    let consolidation = match method.as_ref().map_or("median", |method| method.as_str()) {
+        "median" => Consolidation::Median,
+        "mean" => Consolidation::Mean,
+        "high" => Consolidation::High,
+        "low" => Consolidation::Low,
+        _ => return Err(Status::BadRequest),
+    };
+
+    let markets = symbol_markets(&caller, &symbol, &channel, markets)?;
+
+    let sources = markets.iter()
+        .map(|&(ref market, weight)| market::channel_source(market, &symbol, &channel).map(|source| (source, weight)).ok_or(Status::NotFound))
+        .collect::<Result<Vec<_>, Status>>()?;
+    let consolidated = DerivedChannel::<Timestamp>::consolidate(sources, consolidation);
+
+    let mut query = Query::new("");
+    query.start = start.map(|start| start.0);
+    query.end = end.map(|end| end.0);
+    query.interval = interval.map(|interval| interval.0);
+
+    workers::QUERY.run(move || {
+        let records = query.evaluate_pooled::<Timestamp>(&consolidated).map_err(|error| query_error_status(&error))?;
+        Ok(Json(records))
+    })?
+}
+
+#[derive(Serialize)]
+struct TapeRecord {
+    timestamp: Timestamp,
+    value: Timestamp,
+    /// The market the record came from
+    venue: String,
+}
+
+/// The records of a symbol's channel on several markets, merged into one time-ordered tape with the market of each
+/// record.  `markets` is given as in `get_consolidated`, without weights.
+#[get("/tape/<symbol>/<channel>?<start>&<end>&<markets>", rank = 1)]
+fn get_tape(caller: Caller, symbol: String, channel: String, start: Option<TimeParam>, end: Option<TimeParam>, markets: Option<String>) -> Result<Json<Vec<TapeRecord>>, Status> {
+    let markets = symbol_markets(&caller, &symbol, &channel, markets)?.into_iter().map(|market| market.0).collect::<Vec<String>>();
+
+    let sources = markets.iter()
+        .map(|market| market::channel_source(market, &symbol, &channel).ok_or(Status::NotFound))
+        .collect::<Result<Vec<_>, Status>>()?;
+    let tape = DerivedChannel::<(Timestamp, u32)>::tape(sources);
+
+    let mut query = Query::new("");
+    query.start = start.map(|start| start.0);
+    query.end = end.map(|end| end.0);
+
+    workers::QUERY.run(move || {
+        let records = query.evaluate::<(Timestamp, u32)>(&tape).map_err(|error| query_error_status(&error))?;
+
+        Ok(Json(records.into_iter().map(|(timestamp, (value, venue))| TapeRecord {
+            timestamp: timestamp,
+            value: value,
+            venue: markets[venue as usize].clone(),
+        }).collect()))
+    })?
+}
+
+#[derive(Serialize)]
+struct Sizes {
+    count: usize,
+    min: f64,
+    max: f64,
+    mean: f64,
+    median: f64,
+    p90: f64,
+    p99: f64,
+    /// The lower edge of each bin, with the number of sizes in it
+    histogram: Vec<(f64, usize)>,
+}
+
+/// The distribution of the sizes in a channel of trade sizes, with a histogram of `bins` bins, 10 by default
+#[get("/<market>/<symbol>/<channel>/sizes?<start>&<end>&<bins>")]
+fn get_sizes(caller: Caller, market: String, symbol: String, channel: String, start: Option<TimeParam>, end: Option<TimeParam>, bins: Option<usize>) -> Result<Json<Sizes>, Status> {
+    let channel = caller.channel(&market, &symbol, &channel, Access::Read)?;
+
+    let mut query = Query::new("");
+    query.start = start.map(|start| start.0);
+    query.end = end.map(|end| end.0);
+
+    workers::QUERY.run(move || {
+        let channel = channel.read().map_err(|_| Status::InternalServerError)?;
+        let time_series = channel.as_time_series().ok_or(Status::BadRequest)?;
+
+        let sizes = query.evaluate::<Timestamp>(time_series).map_err(|error| query_error_status(&error))?;
+        let distribution = size_distribution(&sizes, bins.unwrap_or(10)).map_err(|_| Status::BadRequest)?;
+
+        Ok(Json(Sizes {
+            count: distribution.count,
+            min: distribution.min,
+            max: distribution.max,
+            mean: distribution.mean,
+            median: distribution.median,
+            p90: distribution.p90,
+            p99: distribution.p99,
+            histogram: distribution.histogram,
+        }))
+    })?
+}
+
+#[derive(Serialize)]
+struct Spread {
+    /// The spread averaged over how long each spread lasted, or null if the book was never two-sided
+    average: Option<f64>,
+}
+
+/// The average spread between a symbol's `bid` and `ask` channels from `start` to `end`.  It's ranked after the routes
+/// that start with a fixed segment, such as `/tape/<symbol>/<channel>`, whose paths its own could match.
+#[get("/<market>/<symbol>/spread?<bid>&<ask>&<start>&<end>", rank = 2)]
+fn get_spread(caller: Caller, market: String, symbol: String, bid: String, ask: String, start: TimeParam, end: TimeParam) -> Result<Json<Spread>, Status> {
+    let (start, end) = (start.0, end.0);
+
+    let bid = caller.channel(&market, &symbol, &bid, Access::Read)?;
+    let ask = caller.channel(&market, &symbol, &ask, Access::Read)?;
+
+    // Reach back for the spread the range starts with
+    let mut query = Query::new("");
+    query.end = Some(end);
+
+    workers::QUERY.run(move || {
+        let records = |channel: &std::sync::RwLock<market::Channel>| {
+            let channel = channel.read().map_err(|_| Status::InternalServerError)?;
+            let time_series = channel.as_time_series().ok_or(Status::BadRequest)?;
+            query.evaluate::<Timestamp>(time_series).map_err(|error| query_error_status(&error))
+        };
+
+        Ok(Json(Spread { average: average_spread(&records(&bid)?, &records(&ask)?, start..end) }))
+    })?
+}
+
+/// A symbol's `price` channel pooled to the mean of each bucket weighted by its `volume` channel.  It's ranked like
+/// `get_spread`.
+#[get("/<market>/<symbol>/vwap?<price>&<volume>&<start>&<end>&<interval>", rank = 2)]
+fn get_vwap(caller: Caller, market: String, symbol: String, price: String, volume: String, start: Option<TimeParam>, end: Option<TimeParam>, interval: IntervalParam) -> Result<Json<Vec<(Timestamp, Timestamp)>>, Status> {
+    let price = caller.channel(&market, &symbol, &price, Access::Read)?;
+    let volume = caller.channel(&market, &symbol, &volume, Access::Read)?;
+
+    let mut query = Query::new("");
+    query.start = start.map(|start| start.0);
+    query.end = end.map(|end| end.0);
+    query.interval = Some(interval.0);
+
+    workers::QUERY.run(move || {
+        let price = price.read().map_err(|_| Status::InternalServerError)?;
+        let volume = volume.read().map_err(|_| Status::InternalServerError)?;
+
+        let prices = price.as_time_series().ok_or(Status::BadRequest)?;
+        let volumes = volume.as_time_series().ok_or(Status::BadRequest)?;
+
+        let records = query.evaluate_weighted::<Timestamp, Timestamp>(prices, volumes).map_err(|error| query_error_status(&error))?;
+        Ok(Json(records))
+    })?
+}
+
+/// Up to `first` records from the start of each bucket, or `last` from the end, instead of a pooled value.  One of
+/// them has to be given.
+#[get("/<market>/<symbol>/<channel>/samples?<start>&<end>&<interval>&<first>&<last>")]
+fn get_samples(caller: Caller, market: String, symbol: String, channel: String, start: Option<TimeParam>, end: Option<TimeParam>, interval: IntervalParam, first: Option<usize>, last: Option<usize>) -> Result<Json<Vec<(Timestamp, Vec<(Timestamp, Timestamp)>)>>, Status> {
+    let sampling = match (first, last) {
+        (Some(first), None) => Sampling::First(first),
+        (None, Some(last)) => Sampling::Last(last),
+        _ => return Err(Status::BadRequest),
+    };
+
+    let channel = caller.channel(&market, &symbol, &channel, Access::Read)?;
+
+    let mut query = Query::new("");
+    query.start = start.map(|start| start.0);
+    query.end = end.map(|end| end.0);
+    query.interval = Some(interval.0);
+
+    workers::QUERY.run(move || {
+        let channel = channel.read().map_err(|_| Status::InternalServerError)?;
+        let time_series = channel.as_time_series().ok_or(Status::BadRequest)?;
+
+        let samples = query.evaluate_samples::<Timestamp>(time_series, sampling).map_err(|error| query_error_status(&error))?;
+        Ok(Json(samples))
+    })?
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+/// Liveness.  Answers as long as the server is handling requests.
+#[derive(Serialize)]
+struct ChannelListing {
+    market: &'static str,
+    symbol: &'static str,
+    channel: &'static str,
+    /// Whether the channel is computed from other channels rather than stored
+    derived: bool,
+    value_type: &'static str,
+    unit: String,
+    precision: Option<u32>,
+    value_unit: Option<&'static str>,
+    currency: Option<&'static str>,
+    description: Option<&'static str>,
+    created_at: Option<Timestamp>,
+    /// How many records the channel holds and when they start and end, if it's stored and could be read
+    records: Option<usize>,
+    first_timestamp: Option<Timestamp>,
+    last_timestamp: Option<Timestamp>,
+}
+
+/// Lists the channels the caller can read, with what each holds
+#[get("/channels")]
+fn get_channels(caller: Caller) -> Json<Vec<ChannelListing>> {
+    Json(market::served_channels().into_iter()
+        .filter(|&(market, symbol, channel, _)| caller.channel(market, symbol, channel, Access::Read).is_ok())
+        .map(|(market, symbol, channel, served)| {
+            let derived = served.info.created_at.is_none();
+            let stats = if derived { None } else { stored_stats(served) };
+
+            ChannelListing {
+                market: market,
+                symbol: symbol,
+                channel: channel,
+                derived: derived,
+                value_type: &served.info.value_type,
+                unit: served.info.unit.to_string(),
+                precision: served.info.precision,
+                value_unit: served.info.value_unit.as_ref().map(|value_unit| value_unit.as_str()),
+                currency: served.info.currency.as_ref().map(|currency| currency.as_str()),
+                description: served.info.description.as_ref().map(|description| description.as_str()),
+                created_at: served.info.created_at,
+                records: stats.as_ref().map(|stats| stats.records),
+                first_timestamp: stats.as_ref().and_then(|stats| stats.first_timestamp),
+                last_timestamp: stats.as_ref().and_then(|stats| stats.last_timestamp),
+            }
+        })
+        .collect())
+}
+
+/// The stats of a stored channel, or `None` if it can't be read.  Derived channels would have to be computed.
+fn stored_stats(served: &market::ServedChannel) -> Option<StoreStats> {
+    let channel = served.channel.read().ok()?;
+    channel.as_key_value_store()?.stats().ok()
+}
+
+#[get("/health")]
+fn get_health() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+#[derive(Serialize)]
+struct ChannelStatus {
+    channel: String,
+    records: usize,
+    last_timestamp: Option<Timestamp>,
+    /// Milliseconds since the last record, which shows how far behind ingestion is
+    lag: Option<Timestamp>,
+    size_on_disk: u64,
+    /// The rate of records over recent windows back from now
+    rates: Vec<RateResponse>,
+}
+
+#[derive(Serialize)]
+struct ServiceStatus {
+    ready: bool,
+    channels: Vec<ChannelStatus>,
+    /// Channels that couldn't be read
+    unavailable: Vec<String>,
+}
+
+/// Readiness, along with the state of every stored channel the caller can read.  Derived channels are left out, since
+/// they'd have to be computed.  Answers with 503 Service Unavailable if any channel can't be read.
+#[get("/status")]
+fn get_status(caller: Caller) -> status::Custom<Json<ServiceStatus>> {
+    let now = parse::now();
+    let mut channels = Vec::new();
+    let mut unavailable = Vec::new();
+
+    for (market, symbol, name, _) in market::stored_channels() {
+        let channel = match caller.channel(market, symbol, name, Access::Read) {
+            Ok(channel) => channel,
+            Err(_) => continue,
+        };
+
+        let path = format!("{}/{}/{}", market, symbol, name);
+
+        let channel = match channel.read() {
+            Ok(channel) => channel,
+            Err(_) => {
+                unavailable.push(path);
+                continue;
+            },
+        };
+
+        let key_value_store = match channel.as_key_value_store() {
+            Some(key_value_store) => key_value_store,
+            None => continue,
+        };
+
+        let stats = match key_value_store.stats() {
+            Ok(stats) => stats,
+            Err(_) => {
+                unavailable.push(path);
+                continue;
+            },
+        };
+
+        let unit = channel.as_time_series().map_or(TimeUnit::Milliseconds, |time_series| time_series.time_unit());
+
+        channels.push(ChannelStatus {
+            channel: path,
+            records: stats.records,
+            last_timestamp: stats.last_timestamp,
+            lag: stats.last_timestamp.map(|last_timestamp| now.saturating_sub(unit.convert(last_timestamp, TimeUnit::Milliseconds))),
+            size_on_disk: stats.bytes_on_disk,
+            rates: stats.rates.into_iter().map(RateResponse::from).collect(),
+        });
+    }
+
+    let ready = unavailable.is_empty();
+    status::Custom(if ready { Status::Ok } else { Status::ServiceUnavailable }, Json(ServiceStatus {
+        ready: ready,
+        channels: channels,
+        unavailable: unavailable,
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PoolingRequest {
+    End,
+    High,
+    Low,
+    Mean,
+    Start,
+    StdDev,
+    Sum,
+    Vwap,
+    /// A fraction from 0 to 1, given as `{"quantile": 0.95}`
+    Quantile(f64),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum GapFillRequest {
+    Default,
+    Previous,
+}
+
+/// What to do with a final bucket that's still open.  Defaults to including it.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OpenBucketRequest {
+    Include,
+    Exclude,
+    Label,
+}
+
+/// Where buckets start when the range starts before the first record.  Defaults to the first record.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AnchorRequest {
+    FirstRecord,
+    RequestedStart,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TransformRequest {
+    Limit(usize),
+    Skip(usize),
+    Reverse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum IndicatorRequest {
+    Sma(usize),
+    Ema(usize),
+    RollingMin(usize),
+    RollingMax(usize),
+    PercentChange,
+    Rsi(usize),
+    RealizedVolatility(usize),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum BandsRequest {
+    Macd { fast: usize, slow: usize, signal: usize },
+    Bollinger { period: usize, width: f64 },
+}
+
+/// A time in a JSON body, as milliseconds or as a string in any form accepted by `trade_data::parse`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TimeRequest {
+    Milliseconds(Timestamp),
+    Text(String),
+}
+
+impl TimeRequest {
+    fn resolve(self, now: Timestamp) -> std::io::Result<Timestamp> {
+        match self {
+            TimeRequest::Milliseconds(timestamp) => Ok(timestamp),
+            TimeRequest::Text(text) => parse_timestamp(&text, now),
+        }
+    }
+}
+
+/// An interval in a JSON body, as milliseconds or as a string in any form accepted by `trade_data::parse`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IntervalRequest {
+    Milliseconds(Timestamp),
+    Text(String),
+}
+
+impl IntervalRequest {
+    fn resolve(self) -> std::io::Result<Timestamp> {
+        match self {
+            IntervalRequest::Milliseconds(interval) => Ok(interval),
+            IntervalRequest::Text(text) => parse_interval(&text),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RangeRequest {
+    start: Option<TimeRequest>,
+    end: Option<TimeRequest>,
+}
+
+/// The JSON form of a `Query`
+#[derive(Deserialize)]
+struct QueryRequest {
+    source: String,
+    range: Option<RangeRequest>,
+    interval: Option<IntervalRequest>,
+    pooling: Option<PoolingRequest>,
+    gap_fill: Option<GapFillRequest>,
+    /// Where buckets start if the range starts before the first record.  When it does, the anchor is given in the
+    /// `X-Trade-Data-Anchor` header.
+    anchor: Option<AnchorRequest>,
+    open_bucket: Option<OpenBucketRequest>,
+    /// Pools daily or weekly buckets from local midnight in this time zone, e.g. "America/New_York"
+    time_zone: Option<String>,
+    /// The weekday weekly buckets start on, e.g. "sunday"
+    week_start: Option<String>,
+    /// The holiday calendar of the time zone's market, e.g. "nyse"
+    holidays: Option<String>,
+    /// Whether holiday buckets are skipped, the default, or merged into the bucket before them
+    holiday_policy: Option<String>,
+    #[serde(default)]
+    transform: Vec<TransformRequest>,
+    #[serde(default)]
+    indicator: Vec<IndicatorRequest>,
+    bands: Option<BandsRequest>,
+    /// Pages the records, at most this many at a time.  The cursor of the next page is given in the
+    /// `X-Trade-Data-Next-Cursor` header.
+    page_size: Option<usize>,
+    /// Picks up paging after the page that gave this cursor
+    cursor: Option<String>,
+    /// Responds with the scheduled events that overlap the query's range alongside its results
+    #[serde(default)]
+    include_events: bool,
+    /// Responds with how the channel's values are denominated alongside its results, so that they can be rendered
+    /// without knowing the channel
+    #[serde(default)]
+    include_metadata: bool,
+    /// Leaves the empty buckets after the channel's last heartbeat out, since nothing is known of them, and gap fills
+    /// the buckets before it
+    #[serde(default)]
+    heartbeat: bool,
+    /// Widens the interval, pooling a raw query if need be, so that there are at most this many results.  The
+    /// interval used is given in the `X-Trade-Data-Interval` header.
+    max_points: Option<usize>,
+}
+
+impl QueryRequest {
+    /// The size of each page and the cursor to page from, if the records are paged.  Fails if the cursor is invalid
+    /// or given without a page size.
+    fn page(&self) -> Result<Option<(usize, Option<::Cursor>)>, Status> {
+        let cursor = self.cursor.as_ref().map(|cursor| cursor.parse::<::Cursor>()).transpose().map_err(|_| Status::BadRequest)?;
+
+        match (self.page_size, cursor) {
+            (Some(size), cursor) => Ok(Some((size, cursor))),
+            (None, Some(_)) => Err(Status::BadRequest),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Fails if a time or interval can't be parsed.  Relative times are relative to `now`.
+    fn into_query(self, now: Timestamp) -> std::io::Result<Query> {
+        let range = self.range.unwrap_or(RangeRequest { start: None, end: None });
+        let interval = self.interval.map(|interval| interval.resolve()).transpose()?;
+
+        let calendar = match (self.time_zone, self.week_start) {
+            (Some(time_zone), week_start) => Some(parse::calendar_interval(interval, &time_zone, week_start.as_ref().map(|week_start| week_start.as_str()))?),
+            (None, Some(_)) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Week start needs a time zone")),
+            (None, None) => None,
+        };
+
+        let holidays = match (self.holidays, calendar) {
+            (Some(holidays), Some(_)) => Some((
+                HolidayCalendar::load(&holidays)?,
+                self.holiday_policy.as_ref().map_or(Ok(HolidayPolicy::Skip), |policy| policy.parse())?,
+            )),
+            (Some(_), None) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Holidays need a time zone")),
+            (None, _) => None,
+        };
+
+        Ok(Query {
+            source: self.source,
+            start: range.start.map(|start| start.resolve(now)).transpose()?,
+            end: range.end.map(|end| end.resolve(now)).transpose()?,
+            interval: interval,
+            pooling: match self.pooling.unwrap_or(PoolingRequest::End) {
+                PoolingRequest::End => PoolingMethod::End,
+                PoolingRequest::High => PoolingMethod::High,
+                PoolingRequest::Low => PoolingMethod::Low,
+                PoolingRequest::Mean => PoolingMethod::Mean,
+                PoolingRequest::Start => PoolingMethod::Start,
+                PoolingRequest::StdDev => PoolingMethod::StdDev,
+                PoolingRequest::Sum => PoolingMethod::Sum,
+                PoolingRequest::Vwap => PoolingMethod::Vwap,
+                PoolingRequest::Quantile(fraction) if fraction >= 0.0 && fraction <= 1.0 => PoolingMethod::Quantile(fraction),
+                PoolingRequest::Quantile(_) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Quantile must be from 0 to 1")),
+            },
+            gap_fill: self.gap_fill.map(|g| match g {
+                GapFillRequest::Default => GapFillMethod::Default,
+                GapFillRequest::Previous => GapFillMethod::Previous,
+            }),
+            field_pooling: None,
+            anchor: match self.anchor.unwrap_or(AnchorRequest::FirstRecord) {
+                AnchorRequest::FirstRecord => BucketAnchor::FirstRecord,
+                AnchorRequest::RequestedStart => BucketAnchor::RequestedStart,
+            },
+            open_bucket: match self.open_bucket.unwrap_or(OpenBucketRequest::Include) {
+                OpenBucketRequest::Include => OpenBucket::Include,
+                OpenBucketRequest::Exclude => OpenBucket::Exclude,
+                OpenBucketRequest::Label => OpenBucket::Label,
+            },
+            calendar: calendar,
+            holidays: holidays,
+            unit: TimeUnit::Milliseconds,
+            heartbeat: None,
+            now: Some(now),
+            transform: self.transform.into_iter().map(|t| match t {
+                TransformRequest::Limit(count) => Transform::Limit(count),
+                TransformRequest::Skip(count) => Transform::Skip(count),
+                TransformRequest::Reverse => Transform::Reverse,
+            }).collect(),
+            indicators: self.indicator.into_iter().map(|i| match i {
+                IndicatorRequest::Sma(window) => Indicator::Sma(window),
+                IndicatorRequest::Ema(window) => Indicator::Ema(window),
+                IndicatorRequest::RollingMin(window) => Indicator::RollingMin(window),
+                IndicatorRequest::RollingMax(window) => Indicator::RollingMax(window),
+                IndicatorRequest::PercentChange => Indicator::PercentChange,
+                IndicatorRequest::Rsi(period) => Indicator::Rsi(period),
+                IndicatorRequest::RealizedVolatility(window) => Indicator::RealizedVolatility(window),
+            }).collect(),
+            bands: self.bands.map(|b| match b {
+                BandsRequest::Macd { fast, slow, signal } => Bands::Macd { fast: fast, slow: slow, signal: signal },
+                BandsRequest::Bollinger { period, width } => Bands::Bollinger { period: period, width: width },
+            }),
+        })
+    }
+}
+
+/// Looks up the channel named by a query's source, "market/symbol/channel", for reading
+fn find_query_channel(caller: &Caller, source: &str) -> Result<&'static std::sync::RwLock<market::Channel>, Status> {
+    let path = source.split('/').collect::<Vec<&str>>();
+    if path.len() != 3 {
+        return Err(Status::BadRequest);
+    }
+
+    caller.channel(path[0], path[1], path[2], Access::Read)
+}
+
+fn query_error_status(error: &std::io::Error) -> Status {
+    match error.kind() {
+        std::io::ErrorKind::InvalidInput => Status::BadRequest,
+        // Queries stopped for running too long
+        std::io::ErrorKind::TimedOut => Status::ServiceUnavailable,
+        _ => Status::InternalServerError,
+    }
+}
+
+/// Whether a query was stopped for running too long
+fn is_timed_out<T>(result: &std::io::Result<T>) -> bool {
+    result.as_ref().err().map_or(false, |error| error.kind() == std::io::ErrorKind::TimedOut)
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum QueryResponse {
+    Records(Vec<(Timestamp, Timestamp)>),
+    /// The response to a query that labels its open bucket
+    Live {
+        buckets: Vec<(Timestamp, Timestamp)>,
+        open: Option<(Timestamp, Timestamp)>,
+    },
+    /// The response to a query with indicators
+    Indicators(Vec<(Timestamp, f64)>),
+    /// The response to a query with bands
+    Bands(Vec<(Timestamp, (f64, f64, f64))>),
+    /// Any of the above, along with the scheduled events that overlap the query's range
+    WithEvents {
+        results: Box<QueryResponse>,
+        events: Vec<EventResponse>,
+    },
+    /// Any of the above, along with how the channel's values are denominated
+    WithMetadata {
+        results: Box<QueryResponse>,
+        metadata: MetadataResponse,
+    },
+}
+
+/// How a channel's values are denominated, e.g. a unit of "BTC" and a precision of 8 for values to render as
+/// "12.34567890 BTC".  Unknown fields are null.
+#[derive(Serialize)]
+struct MetadataResponse {
+    unit: Option<String>,
+    currency: Option<String>,
+    precision: Option<u32>,
+}
+
+impl<'a> From<&'a ChannelInfo> for MetadataResponse {
+    fn from(info: &'a ChannelInfo) -> Self {
+        MetadataResponse {
+            unit: info.value_unit.clone(),
+            currency: info.currency.clone(),
+            precision: info.precision,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EventResponse {
+    start: Timestamp,
+    end: Timestamp,
+    kind: String,
+    scope: String,
+    title: String,
+}
+
+impl From<Event> for EventResponse {
+    fn from(event: Event) -> Self {
+        EventResponse {
+            start: event.start,
+            end: event.end,
+            kind: event.kind,
+            scope: event.scope,
+            title: event.title,
+        }
+    }
+}
+
+/// How the values of a query's source are denominated
+fn query_metadata(query: &Query) -> Result<MetadataResponse, Status> {
+    let parts = query.source.split('/').collect::<Vec<&str>>();
+    if parts.len() != 3 {
+        return Err(Status::BadRequest);
+    }
+
+    market::find_channel(parts[0], parts[1], parts[2]).map(|served| MetadataResponse::from(&served.info)).ok_or(Status::NotFound)
+}
+
+/// The scheduled events that overlap a query's range and apply to its source
+fn query_events(query: &Query) -> Result<Vec<EventResponse>, Status> {
+    let parts = query.source.split('/').collect::<Vec<&str>>();
+    if parts.len() != 3 {
+        return Err(Status::BadRequest);
+    }
+
+    let range = query.start.unwrap_or(0)..query.end.unwrap_or(Timestamp::max_value());
+    let events = market::events().and_then(|events| events.overlapping(range, parts[0], parts[1])).map_err(|_| Status::InternalServerError)?;
+
+    Ok(events.into_iter().map(EventResponse::from).collect())
+}
+
+/// A query response in the format the caller accepts
+enum QueryBody {
+    Json(Json<QueryResponse>),
+    /// An Arrow IPC stream, for callers that accept `application/vnd.apache.arrow.stream`
+    Arrow(Vec<u8>),
+}
+
+impl<'r> Responder<'r> for QueryBody {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            QueryBody::Json(json) => json.respond_to(request),
+            QueryBody::Arrow(stream) => Response::build()
+                .header(ContentType::new("application", "vnd.apache.arrow.stream"))
+                .sized_body(Cursor::new(stream))
+                .ok(),
+        }
+    }
+}
+
+/// Whether the caller asked for an Arrow IPC stream
+fn accepts_arrow(accept: &Accept) -> bool {
+    accept.media_types().any(|media_type| media_type.top() == "application" && media_type.sub() == "vnd.apache.arrow.stream")
+}
+
+/// Encodes a query response as an Arrow IPC stream of one record batch.  Live responses have their open bucket last,
+/// marked by an "open" column.
+#[cfg(feature = "columnar")]
+fn arrow_stream(response: QueryResponse, unit: TimeUnit, bands: Option<Bands>) -> std::io::Result<Vec<u8>> {
+    use export::{self, Column};
+
+    let batch = match response {
+        QueryResponse::Records(records) => export::records_batch(&records, unit, &["value"])?,
+        QueryResponse::Live { buckets, open } => {
+            let closed = buckets.len();
+            let records = buckets.into_iter().chain(open).collect::<Vec<(Timestamp, Timestamp)>>();
+
+            export::record_batch(&records.iter().map(|record| record.0).collect::<Vec<Timestamp>>(), unit, vec![
+                ("value", Column::UInt64(records.iter().map(|record| Some(record.1)).collect())),
+                ("open", Column::Boolean((0..records.len()).map(|i| i >= closed).collect())),
+            ])?
+        },
+        QueryResponse::Indicators(values) => export::records_batch(&values, unit, &["value"])?,
+        QueryResponse::Bands(values) => export::records_batch(&values, unit, match bands {
+            Some(Bands::Macd { .. }) => &["macd", "signal", "histogram"],
+            _ => &["lower", "middle", "upper"],
+        })?,
+        QueryResponse::WithEvents { .. } => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Events can't be sent as Arrow")),
+        QueryResponse::WithMetadata { .. } => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Metadata can't be sent as Arrow")),
+    };
+
+    let mut stream = Vec::new();
+    export::write_stream(&mut stream, &[batch])?;
+    Ok(stream)
+}
+
+#[cfg(not(feature = "columnar"))]
+fn arrow_stream(_response: QueryResponse, _unit: TimeUnit, _bands: Option<Bands>) -> std::io::Result<Vec<u8>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Other, "Built without the columnar feature"))
+}
+
+/// Evaluates a query.  Responds with an Arrow IPC stream instead of JSON if the caller accepts one and the server was
+/// built with the columnar feature.  Paged queries can only retrieve records, not indicators, bands, or a labeled open
+/// bucket.  Events and metadata can only be included in JSON responses.
+#[post("/query", format = "json", data = "<query>")]
+fn post_query(caller: Caller, accept: Option<&Accept>, query: Json<QueryRequest>) -> Result<WithIoStats<Downsampled<Anchored<Paged<QueryBody>>>>, Status> {
+    let arrow = accept.map_or(false, accepts_arrow);
+    if arrow && !cfg!(feature = "columnar") {
+        return Err(Status::NotAcceptable);
+    }
+
+    let prepared = prepare_query(&caller, query.into_inner(), arrow)?;
+    workers::QUERY.run(move || prepared.evaluate(arrow))?
+}
+
+/// A query that's been checked and charged for, ready to be evaluated on a query worker
+struct PreparedQuery {
+    channel: &'static std::sync::RwLock<market::Channel>,
+    query: Query,
+    page: Option<(usize, Option<::Cursor>)>,
+    events: Option<Vec<EventResponse>>,
+    metadata: Option<MetadataResponse>,
+    max_points: Option<usize>,
+}
+
+impl PreparedQuery {
+    fn evaluate(self, arrow: bool) -> Result<WithIoStats<Downsampled<Anchored<Paged<QueryBody>>>>, Status> {
+        evaluate_query(self.channel, self.query, arrow, self.page, self.events, self.metadata, self.max_points)
+    }
+}
+
+/// Checks that a query can be evaluated as asked and that the caller can read its channel, and charges for it
+fn prepare_query(caller: &Caller, query: QueryRequest, arrow: bool) -> Result<PreparedQuery, Status> {
+    let page = query.page()?;
+    let include_events = query.include_events;
+    let include_metadata = query.include_metadata;
+    let heartbeat = query.heartbeat;
+    let max_points = query.max_points;
+    let mut query = query.into_query(parse::now()).map_err(|_| Status::BadRequest)?;
+
+    if page.is_some() && (!query.indicators.is_empty() || query.bands.is_some() || query.open_bucket == OpenBucket::Label) {
+        return Err(Status::BadRequest);
+    }
+    if (include_events || include_metadata) && arrow {
+        return Err(Status::NotAcceptable);
+    }
+    let events = if include_events { Some(query_events(&query)?) } else { None };
+
+    let channel = find_query_channel(caller, &query.source)?;
+    let metadata = if include_metadata { Some(query_metadata(&query)?) } else { None };
+    // Whether a downsampled query pools isn't known until it runs
+    if query.interval.is_some() || max_points.is_some() {
+        caller.charge_pooled_query()?;
+    }
+    if heartbeat {
+        query.heartbeat = last_update(channel)?;
+    }
+
+    Ok(PreparedQuery {
+        channel: channel,
+        query: query,
+        page: page,
+        events: events,
+        metadata: metadata,
+        max_points: max_points,
+    })
+}
+
+/// The outcome of one query of a batch: its results and what a query on its own would have had in its headers, or
+/// the status it would have failed with
+#[derive(Serialize)]
+struct BatchResult {
+    status: u16,
+    results: Option<QueryResponse>,
+    /// The interval a downsampled query was pooled by, in milliseconds
+    interval: Option<Timestamp>,
+    anchor: Option<&'static str>,
+    next_cursor: Option<String>,
+}
+
+impl From<Result<WithIoStats<Downsampled<Anchored<Paged<QueryBody>>>>, Status>> for BatchResult {
+    fn from(result: Result<WithIoStats<Downsampled<Anchored<Paged<QueryBody>>>>, Status>) -> Self {
+        match result {
+            Ok(evaluated) => {
+                let downsampled = evaluated.inner;
+                let anchored = downsampled.inner;
+                let paged = anchored.inner;
+
+                BatchResult {
+                    status: Status::Ok.code,
+                    results: match paged.inner {
+                        QueryBody::Json(json) => Some(json.into_inner()),
+                        QueryBody::Arrow(_) => None,
+                    },
+                    interval: downsampled.interval,
+                    anchor: anchored.anchor.map(anchor_name),
+                    next_cursor: paged.next_cursor.map(|next_cursor| next_cursor.to_string()),
+                }
+            },
+            Err(status) => BatchResult {
+                status: status.code,
+                results: None,
+                interval: None,
+                anchor: None,
+                next_cursor: None,
+            },
+        }
+    }
+}
+
+/// Evaluates several queries at once, each on its own query worker, so that a dashboard can load all of its panels
+/// in one request.  Each query succeeds or fails on its own, with the status it would have had alone, so a batch
+/// bigger than the query pool can take has its last queries turned away as unavailable.  The results are in the order
+/// of the queries, and are always JSON.
+#[post("/query/batch", format = "json", data = "<queries>")]
+fn post_query_batch(caller: Caller, queries: Json<Vec<QueryRequest>>) -> Result<WithIoStats<Json<Vec<BatchResult>>>, Status> {
+    let jobs = queries.into_inner().into_iter()
+        .map(|query| prepare_query(&caller, query, false))
+        .map(|prepared| move || prepared.and_then(|prepared| prepared.evaluate(false)))
+        .collect::<Vec<_>>();
+
+    let results = workers::QUERY.run_all(jobs).into_iter().map(|result| result.and_then(|result| result)).collect::<Vec<_>>();
+    let io_stats = results.iter().filter_map(|result| result.as_ref().ok()).fold(IoStats::default(), |io_stats, result| io_stats + result.io_stats);
+
+    Ok(WithIoStats {
+        inner: Json(results.into_iter().map(BatchResult::from).collect()),
+        io_stats: io_stats,
+    })
+}
+
+/// When a channel's feed was last known to be up, in milliseconds
+fn last_update(channel: &std::sync::RwLock<market::Channel>) -> Result<Option<Timestamp>, Status> {
+    let channel = channel.read().map_err(|_| Status::InternalServerError)?;
+    let time_series = channel.as_time_series().ok_or(Status::BadRequest)?;
+
+    Ok(time_series.last_update().map(|last_update| time_series.time_unit().convert(last_update, TimeUnit::Milliseconds)))
+}
+
+/// Evaluates a query against a channel, on a query worker.  Only the page is evaluated if one is given, events and
+/// metadata are sent alongside the results if given, and the query is downsampled to `max_points` results if given.
+fn evaluate_query(channel: &std::sync::RwLock<market::Channel>, query: Query, arrow: bool, page: Option<(usize, Option<::Cursor>)>, events: Option<Vec<EventResponse>>, metadata: Option<MetadataResponse>, max_points: Option<usize>) -> Result<WithIoStats<Downsampled<Anchored<Paged<QueryBody>>>>, Status> {
+    let channel = channel.read().map_err(|_| Status::InternalServerError)?;
+    let time_series = channel.as_time_series().ok_or(Status::BadRequest)?;
+    let query = query.in_unit(time_series.time_unit());
+    let query = match max_points {
+        Some(max_points) => query.downsample::<Timestamp>(time_series, max_points).map_err(|error| query_error_status(&error))?,
+        None => query,
+    };
+    let downsampled_interval = match max_points {
+        Some(_) => query.interval.map(|interval| time_series.time_unit().convert(interval, TimeUnit::Milliseconds)),
+        None => None,
+    };
+
+    let timer = slow_queries::Timer::start(&query);
+    let io_stats_before = time_series.io_stats();
+
+    let mut next_cursor = None;
+    let response = timer.limit(|| match (page, channel.as_pooled_time_series()) {
+        (Some((size, cursor)), Some(pooled_time_series)) => query.page_pooled::<Timestamp>(pooled_time_series, size, cursor.as_ref()).map(|page| {
+            next_cursor = page.next;
+            QueryResponse::Records(page.records)
+        }),
+        (Some((size, cursor)), None) => query.page::<Timestamp>(time_series, size, cursor.as_ref()).map(|page| {
+            next_cursor = page.next;
+            QueryResponse::Records(page.records)
+        }),
+        (None, Some(pooled_time_series)) if query.bands.is_some() => query.evaluate_pooled_bands::<Timestamp>(pooled_time_series).map(QueryResponse::Bands),
+        (None, None) if query.bands.is_some() => query.evaluate_bands::<Timestamp>(time_series).map(QueryResponse::Bands),
+        (None, Some(pooled_time_series)) if !query.indicators.is_empty() => {
+            query.evaluate_pooled_indicators::<Timestamp>(pooled_time_series).map(QueryResponse::Indicators)
+        },
+        (None, None) if !query.indicators.is_empty() => query.evaluate_indicators::<Timestamp>(time_series).map(QueryResponse::Indicators),
+        (None, Some(pooled_time_series)) if query.open_bucket == OpenBucket::Label && query.interval.is_some() => {
+            query.evaluate_live::<Timestamp>(pooled_time_series).map(|(buckets, open)| QueryResponse::Live { buckets: buckets, open: open })
+        },
+        (None, Some(pooled_time_series)) => query.evaluate_pooled::<Timestamp>(pooled_time_series).map(QueryResponse::Records),
+        (None, None) => query.evaluate::<Timestamp>(time_series).map(QueryResponse::Records),
+    });
+
+    // Only pooled queries have buckets to anchor
+    let anchor = match (&response, query.interval) {
+        (&Ok(_), Some(_)) => query.applied_anchor::<Timestamp>(time_series),
+        _ => Ok(None),
+    };
+
+    let io_stats = time_series.io_stats() - io_stats_before;
+    timer.finish(io_stats, is_timed_out(&response));
+    let anchor = anchor.map_err(|error| query_error_status(&error))?;
+
+    let body = match response {
+        Ok(response) if arrow => QueryBody::Arrow(arrow_stream(response, time_series.time_unit(), query.bands).map_err(|_| Status::InternalServerError)?),
+        Ok(response) => {
+            let response = match events {
+                Some(events) => QueryResponse::WithEvents { results: Box::new(response), events: events },
+                None => response,
+            };
+
+            QueryBody::Json(Json(match metadata {
+                Some(metadata) => QueryResponse::WithMetadata { results: Box::new(response), metadata: metadata },
+                None => response,
+            }))
+        },
+        Err(ref error) => return Err(query_error_status(error)),
+    };
+
+    Ok(WithIoStats {
+        inner: Downsampled {
+            inner: Anchored {
+                inner: Paged {
+                    inner: body,
+                    next_cursor: next_cursor,
+                },
+                anchor: anchor,
+            },
+            interval: downsampled_interval,
+        },
+        io_stats: io_stats,
+    })
+}
+
+/// Re-pools just the bucket of a pooled query that starts at `start`, for refreshing a live chart's open bucket
+#[post("/query/bucket?<start>", format = "json", data = "<query>")]
+fn post_query_bucket(caller: Caller, start: TimeParam, query: Json<QueryRequest>) -> Result<Json<Option<(Timestamp, Timestamp)>>, Status> {
+    let query = query.into_inner().into_query(parse::now()).map_err(|_| Status::BadRequest)?;
+    let start = start.0;
+
+    let channel = find_query_channel(&caller, &query.source)?;
+    caller.charge_pooled_query()?;
+
+    workers::QUERY.run(move || {
+        let channel = channel.read().map_err(|_| Status::InternalServerError)?;
+        let pooled_time_series = channel.as_pooled_time_series().ok_or(Status::BadRequest)?;
+        let query = query.in_unit(pooled_time_series.time_unit());
+
+        let timer = slow_queries::Timer::start(&query);
+        let io_stats_before = pooled_time_series.io_stats();
+
+        let bucket = timer.limit(|| query.evaluate_bucket::<Timestamp>(pooled_time_series, start));
+        timer.finish(pooled_time_series.io_stats() - io_stats_before, is_timed_out(&bucket));
+
+        bucket.map(Json).map_err(|error| query_error_status(&error))
+    })?
+}
+
+#[derive(Serialize)]
+struct StoreResponse {
+    stored: usize,
+}
+
+/// Stores records in a channel.  Needs a key with write access to the channel.  Values are checked against the
+/// channel's value type before anything is stored.  Records are stored in order, and a record the channel's
+/// constraints refuse fails the request with the records before it stored.  Its path also matches annotations', so
+/// it's ranked after them.
+#[post("/<market>/<symbol>/<channel>", format = "json", data = "<records>", rank = 2)]
+fn post_records(caller: Caller, market: String, symbol: String, channel: String, records: Json<Vec<(Timestamp, serde_json::Value)>>) -> Result<Json<StoreResponse>, Status> {
+    let channel_lock = caller.channel(&market, &symbol, &channel, Access::Write)?;
+    let value_type = market::find_channel(&market, &symbol, &channel)
+        .and_then(|served| ValueType::from_name(&served.info.value_type))
+        .ok_or(Status::InternalServerError)?;
+
+    let records = records.into_inner().into_iter()
+        .map(|(timestamp, value)| Ok((timestamp, value_type.parse(&value.to_string())?)))
+        .collect::<std::io::Result<Vec<(Timestamp, Value)>>>()
+        .map_err(|_| Status::BadRequest)?;
+
+    workers::INGEST.run(move || {
+        let mut channel = channel_lock.write().map_err(|_| Status::InternalServerError)?;
+        let key_value_store = channel.as_mut_key_value_store().ok_or(Status::BadRequest)?;
+
+        for &(timestamp, value) in &records {
+            value_type.store(key_value_store, timestamp, value).map_err(|error| match error.kind() {
+                std::io::ErrorKind::PermissionDenied => Status::MethodNotAllowed,
+                // Records the channel's constraints refused
+                std::io::ErrorKind::InvalidData => Status::UnprocessableEntity,
+                _ => query_error_status(&error),
+            })?;
+        }
+
+        Ok(Json(StoreResponse { stored: records.len() }))
+    })?
+}
+
+#[derive(Serialize)]
+struct HeartbeatResponse {
+    /// The later of the channel's last heartbeat and its last record, in milliseconds
+    last_update: Option<Timestamp>,
+}
+
+/// Records that a channel's feed is up, even though it has nothing to store, so that readers can tell a quiet market
+/// from a feed that's down.  The time defaults to now.  Needs a key with write access to the channel.
+#[post("/<market>/<symbol>/<channel>/heartbeat?<timestamp>")]
+fn post_heartbeat(caller: Caller, market: String, symbol: String, channel: String, timestamp: Option<TimeParam>) -> Result<Json<HeartbeatResponse>, Status> {
+    let channel_lock = caller.channel(&market, &symbol, &channel, Access::Write)?;
+    let timestamp = timestamp.map_or_else(parse::now, |timestamp| timestamp.0);
+
+    workers::INGEST.run(move || {
+        {
+            let mut channel = channel_lock.write().map_err(|_| Status::InternalServerError)?;
+            let time_series = channel.as_mut_time_series().ok_or(Status::BadRequest)?;
+            let unit = time_series.time_unit();
+
+            time_series.heartbeat(TimeUnit::Milliseconds.convert(timestamp, unit)).map_err(|error| match error.kind() {
+                std::io::ErrorKind::PermissionDenied => Status::MethodNotAllowed,
+                std::io::ErrorKind::Other => Status::NotImplemented,
+                _ => Status::InternalServerError,
+            })?;
+        }
+
+        Ok(Json(HeartbeatResponse { last_update: last_update(channel_lock)? }))
+    })?
+}
+
+/// When a channel's feed was last known to be up
+#[get("/<market>/<symbol>/<channel>/heartbeat")]
+fn get_heartbeat(caller: Caller, market: String, symbol: String, channel: String) -> Result<Json<HeartbeatResponse>, Status> {
+    let channel_lock = caller.channel(&market, &symbol, &channel, Access::Read)?;
+    Ok(Json(HeartbeatResponse { last_update: last_update(channel_lock)? }))
+}
+
+/// Looks up a channel that quarantines the records it refuses, for writing
+fn find_quarantine(caller: &Caller, market: &str, symbol: &str, channel: &str) -> Result<(&'static std::sync::RwLock<market::Channel>, &'static Quarantine), Status> {
+    let channel_lock = caller.channel(market, symbol, channel, Access::Write)?;
+    let quarantine = market::find_channel(market, symbol, channel).and_then(|served| served.quarantine.as_ref()).ok_or(Status::NotFound)?;
+
+    Ok((channel_lock, quarantine))
+}
+
+#[derive(Serialize)]
+struct QuarantinedResponse {
+    timestamp: Timestamp,
+    value: Timestamp,
+    /// Why the record was refused, e.g. "above_max" or "out_of_order"
+    reason: &'static str,
+}
+
+/// Lists the records a channel refused, in the order they were refused.  Needs a key with write access to the
+/// channel, and answers 404 Not Found for channels that don't quarantine.
+#[get("/<market>/<symbol>/<channel>/quarantine")]
+fn get_quarantine(caller: Caller, market: String, symbol: String, channel: String) -> Result<Json<Vec<QuarantinedResponse>>, Status> {
+    let (_, quarantine) = find_quarantine(&caller, &market, &symbol, &channel)?;
+    let records = quarantine.records::<Timestamp>().map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(records.into_iter().map(|record| QuarantinedResponse {
+        timestamp: record.timestamp,
+        value: record.value,
+        reason: record.reason.code(),
+    }).collect()))
+}
+
+#[derive(Serialize)]
+struct ReprocessResponse {
+    stored: usize,
+    /// Records refused again, which stay in quarantine
+    quarantined: usize,
+}
+
+/// Stores a channel's quarantined records again, in timestamp order, under the channel's current constraints
+#[post("/<market>/<symbol>/<channel>/quarantine/reprocess")]
+fn post_quarantine_reprocess(caller: Caller, market: String, symbol: String, channel: String) -> Result<Json<ReprocessResponse>, Status> {
+    let (channel, quarantine) = find_quarantine(&caller, &market, &symbol, &channel)?;
+
+    workers::INGEST.run(move || {
+        let mut channel = channel.write().map_err(|_| Status::InternalServerError)?;
+        let key_value_store = channel.as_mut_key_value_store().ok_or(Status::BadRequest)?;
+
+        let reprocessed = quarantine.reprocess::<Timestamp>(key_value_store).map_err(|_| Status::InternalServerError)?;
+
+        Ok(Json(ReprocessResponse {
+            stored: reprocessed.stored,
+            quarantined: reprocessed.quarantined,
+        }))
+    })?
+}
+
+#[derive(Serialize)]
+struct ReloadResponse {
+    keys: usize,
+    /// The channels opened, as "market/symbol/channel"
+    added: Vec<String>,
+    /// The channels drained and closed
+    removed: Vec<String>,
+}
+
+/// Reloads the auth providers and channels from the configuration file, for the admin endpoint and SIGHUP alike.
+/// The running configuration is kept if the file can't be read, or if it was given in code instead.
+fn reload_configuration() -> std::io::Result<ReloadResponse> {
+    if market::config_was_provided() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Configuration was given in code, not read from a file"));
+    }
+
+    let config = market::read_config()?;
+
+    auth::reload(&config).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string()))?;
+    let channels = market::reload_channels(&config)?;
+    market::apply_visibility(&config);
+    market::reload_symbols(&config);
+
+    Ok(ReloadResponse {
+        keys: config.keys.len(),
+        added: channels.added,
+        removed: channels.removed,
+    })
+}
+
+/// Reloads the configuration each time the process is sent SIGHUP.  Failures are reported, and the server carries on
+/// as it was.
+#[cfg(unix)]
+pub fn reload_on_hangup() -> std::io::Result<()> {
+    let signals = Signals::new(&[signal_hook::SIGHUP])?;
+
+    for _ in signals.forever() {
+        match reload_configuration() {
+            Ok(reloaded) => eprintln!("Reloaded configuration: added {:?}, removed {:?}", reloaded.added, reloaded.removed),
+            Err(error) => eprintln!("Could not reload configuration: {}", error),
+        }
+    }
+
+    Ok(())
+}
+
+/// Syncs and closes every channel when the process is sent SIGINT or SIGTERM, then exits, so that stopping the server
+/// mid-ingestion can't lose the records it has just stored.
+#[cfg(unix)]
+pub fn shutdown_on_signal() -> std::io::Result<()> {
+    let signals = Signals::new(&[signal_hook::SIGINT, signal_hook::SIGTERM])?;
+
+    if signals.forever().next().is_some() {
+        eprintln!("Shutting down");
+
+        let failures = market::shutdown();
+        for &(ref path, ref error) in &failures {
+            eprintln!("Could not sync {}: {}", path, error);
+        }
+
+        process::exit(if failures.is_empty() { 0 } else { 1 });
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct AnnotationRequest {
+    /// When the annotated event happened, in any form accepted by `trade_data::parse`
+    timestamp: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct AnnotationResponse {
+    timestamp: Timestamp,
+    author: String,
+    text: String,
+}
+
+/// Lists the annotations of a symbol in a range, in timestamp order.  Needs read access to any of the symbol's
+/// channels.
+#[get("/annotations/<market>/<symbol>?<start>&<end>", rank = 1)]
+fn get_annotations(caller: Caller, market: String, symbol: String, start: Option<TimeParam>, end: Option<TimeParam>) -> Result<Json<Vec<AnnotationResponse>>, Status> {
+    caller.symbol(&market, &symbol, Access::Read)?;
+    let annotations = market::annotations(&market, &symbol).map_err(|_| Status::InternalServerError)?.ok_or(Status::NotFound)?;
+
+    let range = start.map_or(0, |start| start.0)..end.map_or(Timestamp::max_value(), |end| end.0);
+    let annotations = annotations.range(range).map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(annotations.into_iter().map(|annotation| AnnotationResponse {
+        timestamp: annotation.timestamp,
+        author: annotation.author,
+        text: annotation.text,
+    }).collect()))
+}
+
+/// Annotates a symbol, such as with "exchange outage" or "fat finger".  Needs credentials with read access to any of
+/// the symbol's channels, and the annotation's author is who the credentials belong to.
+#[post("/annotations/<market>/<symbol>", format = "json", data = "<annotation>", rank = 1)]
+fn post_annotation(caller: Caller, market: String, symbol: String, annotation: Json<AnnotationRequest>) -> Result<status::Created<Json<AnnotationResponse>>, Status> {
+    let author = caller.account().ok_or(Status::Unauthorized)?.to_string();
+    caller.symbol(&market, &symbol, Access::Read)?;
+    let annotations = market::annotations(&market, &symbol).map_err(|_| Status::InternalServerError)?.ok_or(Status::NotFound)?;
+
+    let annotation = annotation.into_inner();
+    let annotation = Annotation {
+        timestamp: parse_timestamp(&annotation.timestamp, parse::now()).map_err(|_| Status::BadRequest)?,
+        author: author,
+        text: annotation.text,
+    };
+    annotations.add(&annotation).map_err(|error| match error.kind() {
+        std::io::ErrorKind::InvalidInput => Status::BadRequest,
+        _ => Status::InternalServerError,
+    })?;
+
+    let location = format!("/annotations/{}/{}", market, symbol);
+    Ok(status::Created(location, Some(Json(AnnotationResponse {
+        timestamp: annotation.timestamp,
+        author: annotation.author,
+        text: annotation.text,
+    }))))
+}
+
+#[derive(Deserialize)]
+struct EventRequest {
+    /// When the event starts and ends, in any form accepted by `trade_data::parse`
+    start: String,
+    end: String,
+    kind: String,
+    /// "*" for everything, "market", or "market/symbol"
+    #[serde(default = "EventRequest::everything")]
+    scope: String,
+    title: String,
+}
+
+impl EventRequest {
+    fn everything() -> String {
+        "*".to_string()
+    }
+}
+
+#[derive(Serialize)]
+struct SymbolResponse {
+    canonical: String,
+    /// The market's own name for the symbol
+    external: String,
+}
+
+/// Translates a symbol between its canonical name, which channels are served under, and a market's own name for it.
+/// Either name can be given.
+#[get("/symbols/<market>/<symbol>", rank = 1)]
+fn get_symbol(market: String, symbol: String) -> Result<Json<SymbolResponse>, Status> {
+    let status = |error: std::io::Error| match error.kind() {
+        std::io::ErrorKind::NotFound => Status::NotFound,
+        _ => Status::BadRequest,
+    };
+
+    let canonical = market::canonical_symbol(&market, &symbol)
+        .or_else(|error| market::external_symbol(&market, &symbol).map(|_| symbol.to_lowercase()).map_err(|_| error))
+        .map_err(status)?;
+    let external = market::external_symbol(&market, &canonical).map_err(status)?;
+
+    Ok(Json(SymbolResponse {
+        canonical: canonical,
+        external: external,
+    }))
+}
+
+/// Lists the scheduled events that overlap a range, in order of their start.  Given a market and symbol, only lists
+/// the events that apply to them.
+#[get("/events?<start>&<end>&<market>&<symbol>")]
+fn get_events(_caller: Caller, start: Option<TimeParam>, end: Option<TimeParam>, market: Option<String>, symbol: Option<String>) -> Result<Json<Vec<EventResponse>>, Status> {
+    let range = start.map_or(0, |start| start.0)..end.map_or(Timestamp::max_value(), |end| end.0);
+    let events = market::events().map_err(|_| Status::InternalServerError)?;
+
+    let events = match (market, symbol) {
+        (Some(market), Some(symbol)) => events.overlapping(range, &market, &symbol),
+        (None, None) => events.all().map(|events| events.into_iter().filter(|event| event.overlaps(&range)).collect()),
+        _ => return Err(Status::BadRequest),
+    }.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(events.into_iter().map(EventResponse::from).collect()))
+}
+
+/// Schedules an event, such as a maintenance window or an economic release
+#[post("/admin/events", format = "json", data = "<event>")]
+fn post_admin_event(_admin: Admin, event: Json<EventRequest>) -> Result<status::Created<Json<EventResponse>>, Status> {
+    let event = event.into_inner();
+    let now = parse::now();
+
+    let event = Event {
+        start: parse_timestamp(&event.start, now).map_err(|_| Status::BadRequest)?,
+        end: parse_timestamp(&event.end, now).map_err(|_| Status::BadRequest)?,
+        kind: event.kind,
+        scope: event.scope,
+        title: event.title,
+    };
+    market::events().and_then(|events| events.add(&event)).map_err(|error| match error.kind() {
+        std::io::ErrorKind::InvalidInput => Status::BadRequest,
+        _ => Status::InternalServerError,
+    })?;
+
+    Ok(status::Created("/events".to_string(), Some(Json(EventResponse::from(event)))))
+}
+
+/// Reloads the auth providers and channels from the configuration file, without interrupting capture or streams on
+/// the channels it leaves alone.  New channels are opened, and removed ones are drained and closed.  Needs the admin
+/// role over every channel.
+#[post("/admin/reload")]
+fn post_admin_reload(_admin: Admin) -> Result<Json<ReloadResponse>, Status> {
+    reload_configuration().map(Json).map_err(|error| match error.kind() {
+        std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput | std::io::ErrorKind::NotFound => Status::UnprocessableEntity,
+        _ => Status::InternalServerError,
+    })
+}
+
+/// Reports the usage of every account that has made a request since the server started.  Needs the admin role over
+/// every channel.
+#[get("/admin/usage")]
+fn get_admin_usage(_admin: Admin) -> Json<Vec<usage::AccountUsage>> {
+    Json(usage::report())
+}
+
+/// Reads a channel's records in a range ahead of time, e.g. before a burst of queries is expected.  Needs the admin
+/// role over every channel.
+#[post("/admin/preload/<market>/<symbol>/<channel>?<start>&<end>")]
+fn post_admin_preload(_admin: Admin, market: String, symbol: String, channel: String, start: TimeParam, end: Option<TimeParam>) -> Result<(), Status> {
+    let range = start.0..end.map_or(Timestamp::max_value(), |end| end.0);
+
+    market::preload(&market, &symbol, &channel, range).map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => Status::NotFound,
+        _ => query_error_status(&error),
+    })
+}
+
+/// The most recent entries of the slow-query log, oldest first, 100 by default.  Needs the admin role over every
+/// channel.
+#[get("/admin/slow-queries?<count>")]
+fn get_admin_slow_queries(_admin: Admin, count: Option<usize>) -> Result<Json<Vec<slow_queries::Entry>>, Status> {
+    slow_queries::recent(count.unwrap_or(100)).map(Json).map_err(|_| Status::InternalServerError)
+}
+
+#[derive(Serialize)]
+struct VersionResponse {
+    /// The API version, as "major.minor"
+    api: String,
+    /// The version of this build of the server
+    server: &'static str,
+}
+
+/// The API version, for clients to check that they can use this server before relying on its responses
+#[get("/version")]
+fn get_version() -> Json<VersionResponse> {
+    Json(VersionResponse {
+        api: API_VERSION.to_string(),
+        server: env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// Reports the API version in a header of every response
+struct ApiVersionHeader;
+
+impl Fairing for ApiVersionHeader {
+    fn info(&self) -> Info {
+        Info {
+            name: "API version header",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, _request: &Request, response: &mut Response) {
+        response.set_raw_header(VERSION_HEADER, API_VERSION.to_string());
+    }
+}
+
+/// The runtime configuration the server started with.  Needs an admin key, since it names files on the server.
+#[get("/about")]
+fn get_about(_admin: Admin) -> Json<about::Report> {
+    Json(about::report())
+}
+
+fn create_http_server() -> Rocket {
+    rocket::ignite()
+        .mount("/", routes![index])
+        .mount("/", routes![get_data])
+        .mount("/", routes![get_gaps])
+        .mount("/", routes![get_stats])
+        .mount("/", routes![get_summary])
+        .mount("/", routes![get_consolidated])
+        .mount("/", routes![get_tape])
+        .mount("/", routes![get_sizes])
+        .mount("/", routes![get_spread])
+        .mount("/", routes![get_vwap])
+        .mount("/", routes![get_samples])
+        .mount("/", routes![get_channels])
+        .mount("/", routes![get_health])
+        .mount("/", routes![get_status])
+        .mount("/", routes![post_query])
+        .mount("/", routes![post_query_batch])
+        .mount("/", routes![post_query_bucket])
+        .mount("/", routes![post_records])
+        .mount("/", routes![post_heartbeat])
+        .mount("/", routes![get_heartbeat])
+        .mount("/", routes![get_quarantine])
+        .mount("/", routes![post_quarantine_reprocess])
+        .mount("/", routes![get_annotations])
+        .mount("/", routes![post_annotation])
+        .mount("/", routes![get_events])
+        .mount("/", routes![get_symbol])
+        .mount("/", routes![post_admin_event])
+        .mount("/", routes![post_admin_reload])
+        .mount("/", routes![get_admin_usage])
+        .mount("/", routes![get_admin_slow_queries])
+        .mount("/", routes![post_admin_preload])
+        .mount("/", routes![get_about])
+        .mount("/", routes![get_version])
+        .attach(usage::Accounting)
+        .attach(ApiVersionHeader)
+}
+
+lazy_static! {
+    /// Whether a `Server` was built in this process, since the served channels are global to it.  Builds hold it
+    /// throughout, so that only one can succeed.
+    static ref SERVER_BUILT: Mutex<bool> = Mutex::new(false);
+}
+
+/// The trade-data API, with its live stream and gRPC endpoints already serving
+pub struct Server {
+    rocket: Rocket,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Serves HTTP on this thread until the process exits.  Only returns if the server couldn't start.
+    pub fn launch(self) -> LaunchError {
+        self.rocket.launch()
+    }
+
+    /// The HTTP server, for an application to mount its own routes or attach its own fairings to before launching it
+    pub fn into_rocket(self) -> Rocket {
+        self.rocket
+    }
+}
+
+/// Sets up a `Server`: the configuration to serve, read from the configuration file if none is given, and any channels
+/// to serve besides the configured ones
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: Option<Config>,
+    channels: Vec<(String, String, String, Channel, ChannelInfo)>,
+}
+
+impl ServerBuilder {
+    /// Serves `config` rather than the configuration file.  Reloading is refused, since there's no file to reload.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Serves a channel as "market/symbol/name".  It's public, and reloads leave it alone.  Configured derived channels
+    /// can't be built on it, since they're checked against the configuration alone.
+    pub fn channel(mut self, market: &str, symbol: &str, name: &str, channel: Channel, info: ChannelInfo) -> Self {
+        self.channels.push((market.to_string(), symbol.to_string(), name.to_string(), channel, info));
+        self
+    }
+
+    /// Opens the configured channels, registers the others, and starts serving streams, gRPC if the crate was built
+    /// with it, and the background garbage collection and preloading.  Fails if a server was already built in this
+    /// process, if a registered channel has the path of another, or if the stream or gRPC address can't be bound.  A
+    /// server isn't counted as built until it all succeeds.
+    ///
+    /// # Panics
+    ///
+    /// If the configured channels can't be opened, as at the binary's startup.
+    pub fn build(self) -> std::io::Result<Server> {
+        let mut built = SERVER_BUILT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if *built {
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "A server was already built in this process"));
+        }
+
+        if let Some(config) = self.config {
+            market::provide_config(config)?;
+        }
+
+        for (market_name, symbol, name, channel, info) in self.channels {
+            market::register(&market_name, &symbol, &name, channel, info)?;
+        }
+
+        live::serve(&market::CONFIG.stream_address)?;
+        #[cfg(feature = "grpc")]
+        grpc::serve(&market::CONFIG.grpc_address)?;
+
+        *built = true;
+
+        thread::spawn(gc::collect_periodically);
+        thread::spawn(market::preload_configured);
+
+        Ok(Server {
+            rocket: create_http_server(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rocket::local::Client;
+    use rocket::http::Status;
+
+    use storage::FileStorage;
+    use util::SetupFile;
+
+    #[test]
+    fn test_client_hello_world() {
+        let client = Client::new(create_http_server()).expect("create server");
+        let request = client.get("/");
+        let mut response = request.dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string(), Some("Hello world!".into()));
+    }
+
+    #[test]
+    fn test_client_version() {
+        let client = Client::new(create_http_server()).expect("create server");
+        let mut response = client.get("/version").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one(VERSION_HEADER), Some(API_VERSION.to_string().as_str()));
+
+        let body = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["api"], API_VERSION.to_string());
+
+        // Every response carries the version, so clients can check it on whatever they ask for first
+        let response = client.get("/").dispatch();
+        assert_eq!(::api::negotiate(response.headers().get_one(VERSION_HEADER), API_VERSION).unwrap(), API_VERSION);
+    }
+    #[test]
+    fn test_client_symbol() {
+        let client = Client::new(create_http_server()).expect("create server");
+        let mut response = client.get("/symbols/gemini/btcusd").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+
+        let body = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["canonical"], "btcusd");
+        assert_eq!(body["external"], "btcusd");
+    }
+
+    #[test]
+    fn test_register_channel() {
+        let _setup_file = SetupFile::new("test_register_channel");
+
+        let info = ChannelInfo::of::<Timestamp>(TimeUnit::Milliseconds);
+        let storage = FileStorage::<Timestamp, Timestamp>::new("test_register_channel").unwrap();
+        market::register("test", "registered", "trades", Channel::TimeSeries(Box::new(storage)), info.clone()).unwrap();
+        assert!(market::find_channel("test", "registered", "trades").map_or(false, |served| served.is_public()));
+
+        let error = market::register("test", "registered", "trades", Channel::Closed, info).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+}
@@ -0,0 +1,191 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The slow-query log.  Queries that take longer than the configured threshold are appended to a log file as a line of
+//! JSON each, with their parameters, how long they took, and their disk activity.  Queries that run past the hard
+//! limit are stopped at their next read and fail, and are logged as killed.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json;
+
+use {IoStats, Query, Timestamp};
+use parse::{self, parse_interval};
+use storage::with_deadline;
+
+use server::market::CONFIG;
+
+#[derive(Deserialize)]
+pub struct SlowQueryConfig {
+    /// How long a query can take before it's logged, as an interval such as "500ms".  Nothing is logged without one.
+    threshold: Option<String>,
+    /// How long a query can take before it's stopped
+    limit: Option<String>,
+    #[serde(default = "default_log_file")]
+    log_file: String,
+}
+
+fn default_log_file() -> String {
+    "slow_queries.log".to_string()
+}
+
+impl Default for SlowQueryConfig {
+    fn default() -> Self {
+        Self {
+            threshold: None,
+            limit: None,
+            log_file: default_log_file(),
+        }
+    }
+}
+
+impl SlowQueryConfig {
+    /// Fails if the threshold or limit isn't a valid interval
+    pub fn validate(&self) -> io::Result<()> {
+        self.threshold()?;
+        self.limit()?;
+        Ok(())
+    }
+
+    fn threshold(&self) -> io::Result<Option<Timestamp>> {
+        self.threshold.as_ref().map(|threshold| parse_interval(threshold)).transpose()
+    }
+
+    fn limit(&self) -> io::Result<Option<Timestamp>> {
+        self.limit.as_ref().map(|limit| parse_interval(limit)).transpose()
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Entry {
+    /// When the query finished
+    pub timestamp: Timestamp,
+    /// The query, with its range and interval in the channel's unit
+    pub query: String,
+    pub milliseconds: u64,
+    pub bytes_read: u64,
+    pub seeks: u64,
+    /// Whether the query was stopped for running past the limit
+    pub killed: bool,
+}
+
+lazy_static! {
+    /// Serializes appends to the log
+    static ref LOG: Mutex<()> = Mutex::new(());
+}
+
+/// Times a query against the configuration's threshold and limit
+pub struct Timer {
+    query: String,
+    start: Instant,
+}
+
+impl Timer {
+    pub fn start(query: &Query) -> Self {
+        Self {
+            query: format!("{:?}", query),
+            start: Instant::now(),
+        }
+    }
+
+    /// Evaluates the query, stopping its reads once it's run past the limit
+    pub fn limit<T, F>(&self, evaluate: F) -> T where F: FnOnce() -> T {
+        match CONFIG.slow_queries.limit().ok().and_then(|limit| limit) {
+            Some(limit) => with_deadline(Duration::from_millis(limit).checked_sub(self.start.elapsed()).unwrap_or_default(), evaluate),
+            None => evaluate(),
+        }
+    }
+
+    /// Logs the query if it was slow.  A failure to log is reported but doesn't fail the query.
+    pub fn finish(self, io_stats: IoStats, killed: bool) {
+        let elapsed = self.start.elapsed();
+        let milliseconds = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+
+        let threshold = CONFIG.slow_queries.threshold().ok().and_then(|threshold| threshold);
+        if !killed && threshold.map_or(true, |threshold| milliseconds < threshold) {
+            return;
+        }
+
+        let entry = Entry {
+            timestamp: parse::now(),
+            query: self.query,
+            milliseconds: milliseconds,
+            bytes_read: io_stats.bytes_read,
+            seeks: io_stats.seeks,
+            killed: killed,
+        };
+
+        if let Err(error) = append(&CONFIG.slow_queries.log_file, &entry) {
+            eprintln!("Could not log slow query: {}", error);
+        }
+    }
+}
+
+fn append(log_file: &str, entry: &Entry) -> io::Result<()> {
+    let mut line = serde_json::to_string(entry).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+    line.push('\n');
+
+    let _log = LOG.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Slow query log was poisoned"))?;
+    OpenOptions::new().append(true).create(true).open(log_file)?.write_all(line.as_bytes())
+}
+
+/// The most recent entries in the log, oldest first
+pub fn recent(count: usize) -> io::Result<Vec<Entry>> {
+    let contents = match fs::read_to_string(&CONFIG.slow_queries.log_file) {
+        Ok(contents) => contents,
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+
+    let lines = contents.lines().filter(|line| !line.is_empty()).collect::<Vec<&str>>();
+    lines[lines.len().saturating_sub(count)..].iter()
+        .map(|line| serde_json::from_str(line).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append() {
+        let log_file = "test_slow_queries.log";
+        fs::remove_file(log_file).ok();
+
+        for &killed in &[false, true] {
+            append(log_file, &Entry { timestamp: 5, query: "Query".to_string(), milliseconds: 1500, bytes_read: 10, seeks: 2, killed: killed }).unwrap();
+        }
+
+        let contents = fs::read_to_string(log_file).unwrap();
+        let entries = contents.lines().map(|line| serde_json::from_str::<Entry>(line).unwrap()).collect::<Vec<Entry>>();
+        assert_eq!(entries.iter().map(|entry| entry.killed).collect::<Vec<bool>>(), vec![false, true]);
+        assert_eq!(entries[0].milliseconds, 1500);
+
+        fs::remove_file(log_file).ok();
+    }
+
+    #[test]
+    fn test_config() {
+        let config = SlowQueryConfig { threshold: Some("500ms".to_string()), limit: None, log_file: default_log_file() };
+        assert_eq!(config.threshold().unwrap(), Some(500));
+        assert!(config.validate().is_ok());
+
+        let config = SlowQueryConfig { threshold: None, limit: Some("soon".to_string()), log_file: default_log_file() };
+        assert!(config.validate().is_err());
+    }
+}
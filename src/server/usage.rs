@@ -0,0 +1,210 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Usage accounting and quotas.
+//!
+//! Usage is accounted to the account of the credentials it was made with: a key's `name`, a token's subject, or a
+//! client certificate's subject.  Requests without credentials aren't accounted for.  A quota limits the usage of
+//! each period, with periods starting at multiples of its length since the epoch.  Once a quota of requests or
+//! bytes is used up, the account's requests are refused until the next period; once a quota of pooled queries is
+//! used up, just its pooled queries are.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use rocket::{Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::response::Body;
+
+use Timestamp;
+use parse::{self, parse_interval};
+
+use server::auth::Caller;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub requests: u64,
+    pub bytes: u64,
+    /// Queries with an interval, which are pooled on the server
+    pub pooled_queries: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: Usage) {
+        self.requests += other.requests;
+        self.bytes += other.bytes;
+        self.pooled_queries += other.pooled_queries;
+    }
+}
+
+/// Limits on an account's usage in each period
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Quota {
+    /// How long each period is, e.g. "1d"
+    period: String,
+    requests: Option<u64>,
+    bytes: Option<u64>,
+    pooled_queries: Option<u64>,
+}
+
+impl Quota {
+    /// Fails if the period isn't a valid, nonzero interval
+    pub fn validate(&self) -> io::Result<()> {
+        match parse_interval(&self.period)? {
+            0 => Err(io::Error::new(io::ErrorKind::InvalidInput, "Quota period must not be zero")),
+            _ => Ok(()),
+        }
+    }
+
+    fn period(&self) -> Timestamp {
+        parse_interval(&self.period).unwrap_or(0).max(1)
+    }
+
+    /// Whether `charge` should be refused after `used`
+    fn refuses(&self, used: &Usage, charge: &Usage) -> bool {
+        let used_up = |limit: Option<u64>, count: u64| limit.map_or(false, |limit| count >= limit);
+
+        (charge.requests > 0 && (used_up(self.requests, used.requests) || used_up(self.bytes, used.bytes)))
+            || (charge.pooled_queries > 0 && used_up(self.pooled_queries, used.pooled_queries))
+    }
+}
+
+#[derive(Default)]
+struct Account {
+    total: Usage,
+    /// The usage of the current period, and when it started
+    period: Usage,
+    period_start: Option<Timestamp>,
+    quota: Option<Quota>,
+}
+
+lazy_static! {
+    static ref ACCOUNTS: Mutex<HashMap<String, Account>> = Mutex::new(HashMap::new());
+}
+
+/// Adds to an account's usage.  Fails with `TooManyRequests`, adding nothing, if its quota refuses it.
+pub fn charge(account: &str, quota: Option<&Quota>, charge: Usage) -> Result<(), Status> {
+    charge_at(account, quota, charge, parse::now())
+}
+
+fn charge_at(account: &str, quota: Option<&Quota>, charge: Usage, now: Timestamp) -> Result<(), Status> {
+    let mut accounts = ACCOUNTS.lock().map_err(|_| Status::InternalServerError)?;
+    let account = accounts.entry(account.to_string()).or_insert_with(Account::default);
+
+    // The quota may have changed since the last charge if the configuration was reloaded
+    account.quota = quota.cloned();
+
+    if let Some(quota) = quota {
+        let period_start = now - now % quota.period();
+        if account.period_start != Some(period_start) {
+            account.period = Usage::default();
+            account.period_start = Some(period_start);
+        }
+
+        if quota.refuses(&account.period, &charge) {
+            return Err(Status::TooManyRequests);
+        }
+    } else {
+        account.period_start = None;
+    }
+
+    account.total.add(charge);
+    account.period.add(charge);
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AccountUsage {
+    account: String,
+    total: Usage,
+    /// The usage of the current period, if the account has a quota
+    period: Option<PeriodUsage>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PeriodUsage {
+    start: Timestamp,
+    usage: Usage,
+    quota: Quota,
+}
+
+/// The usage of every account, by name
+pub fn report() -> Vec<AccountUsage> {
+    let accounts = match ACCOUNTS.lock() {
+        Ok(accounts) => accounts,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    let mut report = accounts.iter().map(|(name, account)| AccountUsage {
+        account: name.clone(),
+        total: account.total,
+        period: match (account.period_start, &account.quota) {
+            (Some(start), &Some(ref quota)) => Some(PeriodUsage { start: start, usage: account.period, quota: quota.clone() }),
+            _ => None,
+        },
+    }).collect::<Vec<_>>();
+
+    report.sort_by(|a, b| a.account.cmp(&b.account));
+    report
+}
+
+/// Accounts for the bytes of each response to an authenticated caller
+pub struct Accounting;
+
+impl Fairing for Accounting {
+    fn info(&self) -> Info {
+        Info {
+            name: "Usage accounting",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if let (Some(caller), Some(Body::Sized(_, size))) = (Caller::of_request(request), response.body()) {
+            caller.charge_bytes(size);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charge() {
+        let quota = Quota { period: "1m".to_string(), requests: Some(2), bytes: Some(100), pooled_queries: Some(1) };
+        let request = Usage { requests: 1, ..Usage::default() };
+        let pooled_query = Usage { pooled_queries: 1, ..Usage::default() };
+        let bytes = |bytes| Usage { bytes: bytes, ..Usage::default() };
+
+        assert_eq!(charge_at("test_charge", Some(&quota), request, 60_000), Ok(()));
+        assert_eq!(charge_at("test_charge", Some(&quota), pooled_query, 60_000), Ok(()));
+        assert_eq!(charge_at("test_charge", Some(&quota), pooled_query, 60_000), Err(Status::TooManyRequests));
+        assert_eq!(charge_at("test_charge", Some(&quota), request, 60_000), Ok(()));
+        assert_eq!(charge_at("test_charge", Some(&quota), request, 60_000), Err(Status::TooManyRequests));
+
+        // The next period starts afresh, but sending more than the quota of bytes refuses later requests
+        assert_eq!(charge_at("test_charge", Some(&quota), request, 120_000), Ok(()));
+        assert_eq!(charge_at("test_charge", Some(&quota), bytes(150), 120_000), Ok(()));
+        assert_eq!(charge_at("test_charge", Some(&quota), request, 120_000), Err(Status::TooManyRequests));
+
+        let report = report().into_iter().find(|account| account.account == "test_charge").unwrap();
+        assert_eq!(report.total, Usage { requests: 3, bytes: 150, pooled_queries: 1 });
+        assert_eq!(report.period.map(|period| (period.start, period.usage)), Some((120_000, Usage { requests: 1, bytes: 150, pooled_queries: 0 })));
+    }
+}
@@ -0,0 +1,179 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Separate pools of threads for storing records and for evaluating queries, so that a burst of heavy queries waits
+//! behind other queries rather than holding up ingestion.  Requests hand their work to a pool and wait for it.  A pool
+//! with too much work outstanding turns more away at once, so waiting requests can't tie up every server thread.
+
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use rocket::http::Status;
+
+use server::market::CONFIG;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    /// Runs stores and reprocessing
+    pub static ref INGEST: Pool = Pool::new("ingest", CONFIG.pools.ingest_threads, CONFIG.pools.ingest_queue);
+
+    /// Runs queries and other reads that scan a channel
+    pub static ref QUERY: Pool = Pool::new("query", CONFIG.pools.query_threads, CONFIG.pools.query_queue);
+}
+
+pub struct Pool {
+    sender: Mutex<Sender<Job>>,
+    /// Jobs queued or running
+    outstanding: Arc<AtomicUsize>,
+    max_outstanding: usize,
+}
+
+impl Pool {
+    /// Starts `threads` workers, at least one, that take up to `queue` jobs beyond the ones they're running
+    pub fn new(name: &str, threads: usize, queue: usize) -> Self {
+        let threads = cmp::max(threads, 1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let outstanding = Arc::new(AtomicUsize::new(0));
+
+        for i in 0..threads {
+            let receiver = receiver.clone();
+
+            thread::Builder::new().name(format!("{}-{}", name, i)).spawn(move || loop {
+                // The receiver is only locked while waiting for a job, not while running it
+                let job = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => return,
+                };
+
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => return,
+                }
+            }).expect("Could not start worker thread");
+        }
+
+        Self {
+            sender: Mutex::new(sender),
+            outstanding: outstanding,
+            max_outstanding: threads + queue,
+        }
+    }
+
+    /// Runs a job on the pool and waits for its result.  Fails with `ServiceUnavailable` if the pool has too much
+    /// work outstanding, and `InternalServerError` if the job panics.
+    pub fn run<T, F>(&self, job: F) -> Result<T, Status> where T: 'static + Send, F: 'static + FnOnce() -> T + Send {
+        self.submit(job)?.recv().map_err(|_| Status::InternalServerError)
+    }
+
+    /// Queues every job on the pool before waiting for any, so they run side by side on as many workers as are
+    /// free, and gives their results in order.  Each job fails on its own as it would with `run`.
+    pub fn run_all<T, F>(&self, jobs: Vec<F>) -> Vec<Result<T, Status>> where T: 'static + Send, F: 'static + FnOnce() -> T + Send {
+        let receivers = jobs.into_iter().map(|job| self.submit(job)).collect::<Vec<_>>();
+
+        receivers.into_iter()
+            .map(|receiver| receiver.and_then(|receiver| receiver.recv().map_err(|_| Status::InternalServerError)))
+            .collect()
+    }
+
+    /// Queues a job on the pool, giving a receiver of its result
+    fn submit<T, F>(&self, job: F) -> Result<Receiver<T>, Status> where T: 'static + Send, F: 'static + FnOnce() -> T + Send {
+        if self.outstanding.fetch_add(1, Ordering::SeqCst) >= self.max_outstanding {
+            self.outstanding.fetch_sub(1, Ordering::SeqCst);
+            return Err(Status::ServiceUnavailable);
+        }
+
+        let (result_sender, result_receiver) = mpsc::channel();
+        let outstanding = self.outstanding.clone();
+        let job: Job = Box::new(move || {
+            // A job that panics fails its own request, and not the worker.  Either way it stops being outstanding
+            // before its request hears back.
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            outstanding.fetch_sub(1, Ordering::SeqCst);
+
+            if let Ok(result) = result {
+                result_sender.send(result).ok();
+            }
+        });
+
+        let sent = self.sender.lock().map_err(|_| Status::InternalServerError)?.send(job);
+        if sent.is_err() {
+            self.outstanding.fetch_sub(1, Ordering::SeqCst);
+            return Err(Status::InternalServerError);
+        }
+
+        Ok(result_receiver)
+    }
+
+    /// The number of jobs queued or running
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Barrier;
+
+    #[test]
+    fn test_pool() {
+        let pool = Pool::new("test", 1, 1);
+        assert_eq!(pool.run(|| 1 + 1), Ok(2));
+        assert_eq!(pool.run(|| -> () { panic!("job failed") }), Err(Status::InternalServerError));
+        assert_eq!(pool.outstanding(), 0);
+
+        // Hold the only worker, then fill the queue
+        let pool = Arc::new(pool);
+        let barrier = Arc::new(Barrier::new(2));
+        let held = {
+            let (pool, barrier) = (pool.clone(), barrier.clone());
+            thread::spawn(move || pool.run(move || { barrier.wait(); barrier.wait(); }))
+        };
+        barrier.wait();
+
+        let queued = {
+            let pool = pool.clone();
+            thread::spawn(move || pool.run(|| 3))
+        };
+        while pool.outstanding() < 2 {
+            thread::yield_now();
+        }
+
+        assert_eq!(pool.run(|| 4), Err(Status::ServiceUnavailable));
+
+        barrier.wait();
+        assert_eq!(held.join().unwrap(), Ok(()));
+        assert_eq!(queued.join().unwrap(), Ok(3));
+    }
+
+    #[test]
+    fn test_pool_run_all() {
+        // Both jobs have to be running at once to get past the barrier
+        let pool = Pool::new("test", 2, 1);
+        let barrier = Arc::new(Barrier::new(2));
+        let jobs = (0..2).map(|i| {
+            let barrier = barrier.clone();
+            move || { barrier.wait(); i }
+        }).collect::<Vec<_>>();
+        assert_eq!(pool.run_all(jobs), vec![Ok(0), Ok(1)]);
+    }
+}
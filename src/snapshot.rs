@@ -0,0 +1,263 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Snapshots of storage files, and point-in-time restore from them.
+//!
+//! A snapshot is a directory holding copies of storage files along with a fingerprint manifest of the copies, in a
+//! file named `manifest`.  Restoring a file from a snapshot checks its copy against the manifest, rebuilds the file
+//! from the copy, and then replays the tail of records written after the snapshot was taken, e.g. from a journal or
+//! a replica, so that an operator can recover from the loss of a disk.  Records have to stay in order throughout,
+//! and the file is only moved into place once all of it has been checked.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use fingerprint::{self, Fingerprint, Manifest};
+use key_value_store::{KeyValueStore, Storable};
+use storage::FileStorage;
+use time_series::{TimeSeries, TimeUnit, Timestamp};
+
+/// The name of a snapshot's manifest within its directory
+pub const MANIFEST: &str = "manifest";
+
+/// What restoring a file did
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Restored {
+    /// Records restored from the snapshot
+    pub snapshot_records: usize,
+    /// Records replayed from the tail
+    pub replayed: usize,
+    /// Records of the tail that were already in the snapshot
+    pub skipped: usize,
+}
+
+/// A directory of copied storage files and their manifest
+pub struct Snapshot {
+    directory: PathBuf,
+    manifest: Manifest,
+}
+
+impl Snapshot {
+    /// Copies storage files into a new snapshot in `directory`, which must not exist yet.  Files are named in the
+    /// snapshot as they're given, so they must be relative paths that stay within it.
+    pub fn take<P: AsRef<Path>>(directory: P, files: &[&str]) -> io::Result<Self> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory.parent().unwrap_or(Path::new(".")))?;
+        fs::create_dir(directory)?;
+
+        let mut manifest = Manifest::default();
+        for &file in files {
+            let copy = snapshot_path(directory, file)?;
+            if let Some(parent) = copy.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            // Only the bytes there when the copy starts, since the file may still be appended to
+            let mut source = File::open(file)?;
+            let length = source.metadata()?.len();
+            io::copy(&mut Read::by_ref(&mut source).take(length), &mut File::create(&copy)?)?;
+
+            manifest.entries.push((file.to_string(), Fingerprint::of_file(&copy, None)?));
+        }
+
+        fs::write(directory.join(MANIFEST), manifest.to_string())?;
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            manifest: manifest,
+        })
+    }
+
+    /// Opens the snapshot in `directory`
+    pub fn open<P: AsRef<Path>>(directory: P) -> io::Result<Self> {
+        let directory = directory.as_ref();
+        let manifest = Manifest::parse(&fs::read_to_string(directory.join(MANIFEST))?)?;
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            manifest: manifest,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Restores the file named `name` in the snapshot to `target`, which must not exist, and replays the records
+    /// of `tail` that come after the snapshot's last record.  Fails without touching `target` if the copy doesn't
+    /// match its fingerprint or any record is out of order.  `V` is the type of the file's values.
+    pub fn restore<V>(&self, name: &str, target: &str, unit: TimeUnit, tail: Option<&dyn TimeSeries>) -> io::Result<Restored> where V: Storable<FileStorage<Timestamp, V>> {
+        let fingerprint = self.manifest.entries.iter()
+            .find(|&&(ref entry, _)| entry == name)
+            .map(|&(_, ref fingerprint)| *fingerprint)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} isn't in the snapshot", name)))?;
+
+        if Path::new(target).exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", target)));
+        }
+
+        let copy = snapshot_path(&self.directory, name)?;
+        if fingerprint::verify(&copy, &fingerprint)?.is_err() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("The snapshot of {} doesn't match its manifest", name)));
+        }
+
+        // Rebuild the file beside the target, so that a failed restore leaves nothing in its place
+        let temporary_filename = format!("{}.restore", target);
+        if let Err(error) = fs::remove_file(&temporary_filename) {
+            if error.kind() != io::ErrorKind::NotFound {
+                return Err(error);
+            }
+        }
+
+        let result = rebuild::<V>(&copy, fingerprint, &temporary_filename, unit, tail);
+        match result {
+            Ok(restored) => {
+                File::open(&temporary_filename)?.sync_all()?;
+                fs::rename(&temporary_filename, target)?;
+                Ok(restored)
+            },
+            Err(error) => {
+                fs::remove_file(&temporary_filename).ok();
+                Err(error)
+            },
+        }
+    }
+}
+
+/// Where a file named in a manifest is kept in a snapshot.  Names that would leave the snapshot are refused.
+fn snapshot_path(directory: &Path, name: &str) -> io::Result<PathBuf> {
+    let path = Path::new(name);
+    if path.components().any(|component| match component {
+        Component::Normal(_) | Component::CurDir => false,
+        _ => true,
+    }) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't a relative path within the snapshot", name)));
+    }
+
+    Ok(directory.join(path))
+}
+
+/// Copies the fingerprinted bytes of a snapshot copy into a new storage file, checks their order, and stores the tail
+fn rebuild<V>(copy: &Path, fingerprint: Fingerprint, filename: &str, unit: TimeUnit, tail: Option<&dyn TimeSeries>) -> io::Result<Restored> where V: Storable<FileStorage<Timestamp, V>> {
+    io::copy(&mut File::open(copy)?.take(fingerprint.bytes), &mut File::create(filename)?)?;
+
+    let mut storage = FileStorage::<Timestamp, V>::with_unit(filename, unit)?;
+    let records = storage.retrieve_all()?.into_vec::<Timestamp, V>();
+
+    if records.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "The snapshot's records are out of order"));
+    }
+
+    let mut restored = Restored {
+        snapshot_records: records.len(),
+        ..Restored::default()
+    };
+
+    if let Some(tail) = tail {
+        let last_key = records.last().map(|record| record.0);
+        let tail_records = tail.retrieve_all()?
+            .try_into_vec::<Timestamp, V>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "The tail holds a different type of value"))?;
+
+        for (timestamp, value) in tail_records {
+            if last_key.map_or(false, |last_key| timestamp <= last_key) {
+                restored.skipped += 1;
+                continue;
+            }
+
+            storage.store(Box::new(timestamp), Box::new(value)).map_err(|error| match error.kind() {
+                io::ErrorKind::InvalidInput => io::Error::new(io::ErrorKind::InvalidData, "The tail's records are out of order"),
+                _ => error,
+            })?;
+            restored.replayed += 1;
+        }
+    }
+
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    use util::SetupFile;
+
+    fn store_all(storage: &mut FileStorage<Timestamp, i32>, records: &[(Timestamp, i32)]) {
+        for &(timestamp, value) in records {
+            storage.store(Box::new(timestamp), Box::new(value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_restore() {
+        let _setup_file = SetupFile::new("test_snapshot_restore");
+        let _setup_tail = SetupFile::new("test_snapshot_restore.tail");
+        let _setup_restored = SetupFile::new("test_snapshot_restore.restored");
+        fs::remove_dir_all("test_snapshot_restore_snapshot").ok();
+
+        let mut storage = FileStorage::<Timestamp, i32>::new("test_snapshot_restore").unwrap();
+        store_all(&mut storage, &[(1, 1), (2, 2), (3, 3)]);
+
+        let snapshot = Snapshot::take("test_snapshot_restore_snapshot", &["test_snapshot_restore"]).unwrap();
+        assert!(Snapshot::take("test_snapshot_restore_snapshot", &["test_snapshot_restore"]).is_err());
+
+        // The tail overlaps the snapshot, as a journal kept from before it was taken would
+        let mut tail = FileStorage::<Timestamp, i32>::new("test_snapshot_restore.tail").unwrap();
+        store_all(&mut tail, &[(2, 2), (3, 3), (4, 4), (5, 5)]);
+
+        let snapshot = Snapshot::open("test_snapshot_restore_snapshot").map(|_| snapshot).unwrap();
+        let restored = snapshot.restore::<i32>("test_snapshot_restore", "test_snapshot_restore.restored", TimeUnit::Milliseconds, Some(&tail)).unwrap();
+        assert_eq!(restored, Restored { snapshot_records: 3, replayed: 2, skipped: 2 });
+
+        let restored = FileStorage::<Timestamp, i32>::new("test_snapshot_restore.restored").unwrap();
+        assert_eq!(restored.retrieve_all().unwrap().into_vec::<Timestamp, i32>(), vec![(1, 1), (2, 2), (3, 3), (4, 4), (5, 5)]);
+
+        // An existing file is never overwritten
+        let error = snapshot.restore::<i32>("test_snapshot_restore", "test_snapshot_restore.restored", TimeUnit::Milliseconds, None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+
+        fs::remove_dir_all("test_snapshot_restore_snapshot").ok();
+    }
+
+    #[test]
+    fn test_restore_damaged() {
+        let _setup_file = SetupFile::new("test_snapshot_damaged");
+        let _setup_restored = SetupFile::new("test_snapshot_damaged.restored");
+        fs::remove_dir_all("test_snapshot_damaged_snapshot").ok();
+
+        let mut storage = FileStorage::<Timestamp, i32>::new("test_snapshot_damaged").unwrap();
+        store_all(&mut storage, &[(1, 1), (2, 2)]);
+
+        let snapshot = Snapshot::take("test_snapshot_damaged_snapshot", &["test_snapshot_damaged"]).unwrap();
+        assert!(Snapshot::take("test_snapshot_damaged_other", &["../test_snapshot_damaged"]).is_err());
+        fs::remove_dir_all("test_snapshot_damaged_other").ok();
+
+        let copy = Path::new("test_snapshot_damaged_snapshot").join("test_snapshot_damaged");
+        OpenOptions::new().write(true).open(&copy).unwrap().write_all(b"9").unwrap();
+
+        let error = snapshot.restore::<i32>("test_snapshot_damaged", "test_snapshot_damaged.restored", TimeUnit::Milliseconds, None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(!Path::new("test_snapshot_damaged.restored").exists());
+        assert!(!Path::new("test_snapshot_damaged.restored.restore").exists());
+
+        assert_eq!(snapshot.restore::<i32>("missing", "test_snapshot_damaged.restored", TimeUnit::Milliseconds, None).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        fs::remove_dir_all("test_snapshot_damaged_snapshot").ok();
+    }
+}
@@ -0,0 +1,138 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Notes about moments in a market, such as "exchange outage" or "fat finger".
+//!
+//! Annotations are written by people, after the fact, so they arrive in any order.  Like a `Quarantine`, they're kept
+//! in a plain log, one per line, and sorted when they're read.  Each line is the timestamp, the author, and the text,
+//! separated by tabs, with tabs, newlines, and backslashes in the author and text escaped.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use time_series::Timestamp;
+use util::{escape_field, unescape_field};
+
+/// A note about a moment
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotation {
+    pub timestamp: Timestamp,
+    /// Who wrote the note
+    pub author: String,
+    pub text: String,
+}
+
+/// A log of annotations.  Clones share the file.
+#[derive(Clone)]
+pub struct Annotations {
+    filename: Arc<String>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Annotations {
+    /// Opens the annotations in `filename`, creating it if it doesn't exist
+    pub fn open(filename: &str) -> io::Result<Self> {
+        OpenOptions::new().append(true).create(true).open(filename)?;
+
+        Ok(Self {
+            filename: Arc::new(filename.to_string()),
+            lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, ()>> {
+        self.lock.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Annotations were poisoned"))
+    }
+
+    /// Adds an annotation.  Fails if its author or text is empty.
+    pub fn add(&self, annotation: &Annotation) -> io::Result<()> {
+        if annotation.author.is_empty() || annotation.text.trim().is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Annotations need an author and text"));
+        }
+
+        let _lock = self.lock()?;
+        let mut file = OpenOptions::new().append(true).open(&*self.filename)?;
+
+        // One write per annotation, so that an annotation is never split by a crash
+        file.write_all(format!("{}\t{}\t{}\n", annotation.timestamp, escape_field(&annotation.author), escape_field(&annotation.text)).as_bytes())
+    }
+
+    /// Returns the annotations in a range, in timestamp order.  Annotations of the same moment are in the order they
+    /// were added.
+    pub fn range(&self, range: Range<Timestamp>) -> io::Result<Vec<Annotation>> {
+        let mut annotations = self.all()?;
+        annotations.retain(|annotation| annotation.timestamp >= range.start && annotation.timestamp < range.end);
+        Ok(annotations)
+    }
+
+    /// Returns every annotation, in timestamp order
+    pub fn all(&self) -> io::Result<Vec<Annotation>> {
+        let mut annotations = self.read()?;
+        annotations.sort_by_key(|annotation| annotation.timestamp);
+        Ok(annotations)
+    }
+
+    fn read(&self) -> io::Result<Vec<Annotation>> {
+        let _lock = self.lock()?;
+        let contents = fs::read_to_string(&*self.filename)?;
+
+        contents.lines().filter(|line| !line.is_empty()).map(|line| {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid annotation");
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            if fields.len() != 3 {
+                return Err(invalid());
+            }
+
+            Ok(Annotation {
+                timestamp: fields[0].parse().map_err(|_| invalid())?,
+                author: unescape_field(fields[1]).ok_or_else(invalid)?,
+                text: unescape_field(fields[2]).ok_or_else(invalid)?,
+            })
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use util::SetupFile;
+
+    #[test]
+    fn test_annotations() {
+        let _setup_file = SetupFile::new("test_annotations");
+
+        let annotation = |timestamp, text: &str| Annotation { timestamp: timestamp, author: "key 1".to_string(), text: text.to_string() };
+
+        let annotations = Annotations::open("test_annotations").unwrap();
+        annotations.add(&annotation(20, "fat finger")).unwrap();
+        annotations.add(&annotation(10, "exchange outage\n\tbackslash \\ and all")).unwrap();
+        annotations.add(&annotation(30, "resumed")).unwrap();
+        assert!(annotations.add(&annotation(40, " ")).is_err());
+
+        assert_eq!(annotations.clone().all().unwrap(), vec![
+            annotation(10, "exchange outage\n\tbackslash \\ and all"),
+            annotation(20, "fat finger"),
+            annotation(30, "resumed"),
+        ]);
+        assert_eq!(annotations.range(15..30).unwrap(), vec![annotation(20, "fat finger")]);
+    }
+}
@@ -0,0 +1,79 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Time limits on reads.
+//!
+//! A deadline applies to the thread that sets it.  Once it passes, reads of storage files on that thread fail with a
+//! `TimedOut` error, so a query that runs too long stops at its next read rather than running to the end.
+
+use std::cell::Cell;
+use std::io;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = Cell::new(None);
+}
+
+/// Puts back the deadline from before `with_deadline`, even if it unwinds
+struct Restore(Option<Instant>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        DEADLINE.with(|deadline| deadline.set(self.0));
+    }
+}
+
+/// Runs `f` with storage reads on this thread failing once `limit` has passed.  An earlier deadline that's already
+/// set is kept.
+pub fn with_deadline<T, F>(limit: Duration, f: F) -> T where F: FnOnce() -> T {
+    let previous = DEADLINE.with(|deadline| deadline.get());
+    let _restore = Restore(previous);
+
+    let deadline = Instant::now() + limit;
+    DEADLINE.with(|current| current.set(Some(previous.map_or(deadline, |previous| previous.min(deadline)))));
+
+    f()
+}
+
+/// Fails if this thread's deadline has passed
+pub fn check() -> io::Result<()> {
+    match DEADLINE.with(|deadline| deadline.get()) {
+        Some(deadline) if Instant::now() >= deadline => Err(io::Error::new(io::ErrorKind::TimedOut, "Read passed its deadline")),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_with_deadline() {
+        assert!(check().is_ok());
+
+        with_deadline(Duration::from_millis(10), || {
+            assert!(check().is_ok());
+            thread::sleep(Duration::from_millis(20));
+            assert_eq!(check().unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+            // A later deadline doesn't extend an earlier one
+            with_deadline(Duration::from_secs(60), || assert!(check().is_err()));
+        });
+
+        assert!(check().is_ok());
+    }
+}
@@ -0,0 +1,314 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Deduplication of trades by their exchange IDs.
+//!
+//! A `DedupStore` wraps a store of `Identified` values and skips any record whose trade ID was already stored within a
+//! window of its timestamp, so that a backfill overlapping the live feed doesn't count its trades twice.  The IDs of
+//! the last window of records are kept in memory for the live feed, and records older than the newest are checked
+//! against the store itself.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::ops::Range;
+use std::sync::mpsc::Receiver;
+
+use key_value_store::{Data, IoStats, KeyValueStore, Notification, Retrieval, StoreStats};
+use pooled_time_series::{Interval, PooledTimeSeries, PoolingOptions};
+use schema::{Identified, TradeId};
+use time_series::{RetrievalDirection, TimeSeries, TimeUnit, Timestamp};
+
+/// A store that skips trades it already holds
+pub struct DedupStore<S> {
+    store: S,
+    /// How far apart, in the store's unit, two records of the same trade may be
+    window: Timestamp,
+    /// The IDs of the records within the window of the newest, oldest first
+    recent: VecDeque<(Timestamp, TradeId)>,
+    /// The latest time each of the recent IDs was stored at
+    recent_ids: HashMap<TradeId, Timestamp>,
+    /// The records skipped since opening
+    duplicates: usize,
+    id_of: fn(&Data) -> Option<TradeId>,
+    ids_of: fn(Retrieval) -> io::Result<Vec<(Timestamp, TradeId)>>,
+}
+
+impl<S> DedupStore<S> where S: TimeSeries {
+    /// Deduplicates records whose values are `Identified<V>`, reading the IDs of the last window of records from the
+    /// store
+    pub fn new<V>(store: S, window: Timestamp) -> io::Result<Self> where V: 'static + Copy {
+        let mut dedup = Self {
+            store: store,
+            window: window,
+            recent: VecDeque::new(),
+            recent_ids: HashMap::new(),
+            duplicates: 0,
+            id_of: id_of::<V>,
+            ids_of: ids_of::<V>,
+        };
+
+        let last_key = dedup.store.last_key().and_then(|key| key.downcast_ref::<Timestamp>().cloned());
+        if let Some(last_key) = last_key {
+            for (timestamp, id) in (dedup.ids_of)(dedup.store.retrieve_from(last_key.saturating_sub(window))?)? {
+                dedup.remember(timestamp, id);
+            }
+        }
+
+        Ok(dedup)
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    pub fn window(&self) -> Timestamp {
+        self.window
+    }
+
+    /// The number of records skipped as duplicates since the store was opened
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    /// Whether a record of trade `id` at `timestamp` is already stored
+    pub fn is_duplicate(&self, timestamp: Timestamp, id: TradeId) -> io::Result<bool> {
+        match self.recent.back() {
+            // Everything within the window before the newest record is in memory
+            Some(&(newest, _)) if timestamp < newest => {
+                let range = timestamp.saturating_sub(self.window)..timestamp.saturating_add(self.window).saturating_add(1);
+                Ok((self.ids_of)(self.store.retrieve_range(range)?)?.iter().any(|&(_, stored)| stored == id))
+            },
+            _ => Ok(self.recent_ids.get(&id).map_or(false, |&seen| seen.saturating_add(self.window) >= timestamp)),
+        }
+    }
+
+    fn remember(&mut self, timestamp: Timestamp, id: TradeId) {
+        self.recent.push_back((timestamp, id));
+        self.recent_ids.insert(id, timestamp);
+
+        while self.recent.front().map_or(false, |&(oldest, _)| oldest.saturating_add(self.window) < timestamp) {
+            if let Some((oldest, id)) = self.recent.pop_front() {
+                // The ID may have been stored again since, in which case it's still recent
+                if self.recent_ids.get(&id) == Some(&oldest) {
+                    self.recent_ids.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+fn id_of<V>(value: &Data) -> Option<TradeId> where V: 'static + Copy {
+    value.downcast_ref::<Identified<V>>().map(|value| value.id)
+}
+
+fn ids_of<V>(retrieval: Retrieval) -> io::Result<Vec<(Timestamp, TradeId)>> where V: 'static + Copy {
+    let records = retrieval.try_into_vec::<Timestamp, Identified<V>>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Store holds values of another type"))?;
+
+    Ok(records.into_iter().map(|(timestamp, value)| (timestamp, value.id)).collect())
+}
+
+impl<S> KeyValueStore for DedupStore<S> where S: TimeSeries {
+    fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    fn io_stats(&self) -> IoStats {
+        self.store.io_stats()
+    }
+
+    fn last_key(&self) -> Option<Box<Data>> {
+        self.store.last_key()
+    }
+
+    fn size_on_disk(&self) -> io::Result<u64> {
+        self.store.size_on_disk()
+    }
+
+    fn stats(&self) -> io::Result<StoreStats> {
+        self.store.stats()
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.store.sync()
+    }
+
+    /// Skips the record without an error if its trade is already stored
+    fn store(&mut self, key: Box<Data>, value: Box<Data>) -> io::Result<()> {
+        let timestamp = key.downcast_ref::<Timestamp>().cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Only timestamped records can be deduplicated"))?;
+        let id = (self.id_of)(&*value).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Value has the wrong type"))?;
+
+        if self.is_duplicate(timestamp, id)? {
+            self.duplicates += 1;
+            return Ok(());
+        }
+
+        self.store.store(key, value)?;
+        self.remember(timestamp, id);
+        Ok(())
+    }
+
+    fn subscribe(&self) -> io::Result<Receiver<Notification>> {
+        self.store.subscribe()
+    }
+}
+
+impl<S> TimeSeries for DedupStore<S> where S: TimeSeries {
+    fn retrieve_nearest(&self, timestamp: Timestamp, retrieval_direction: Option<RetrievalDirection>) -> io::Result<Retrieval> {
+        self.store.retrieve_nearest(timestamp, retrieval_direction)
+    }
+
+    fn retrieve_all(&self) -> io::Result<Retrieval> {
+        self.store.retrieve_all()
+    }
+
+    fn retrieve_from(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        self.store.retrieve_from(timestamp)
+    }
+
+    fn retrieve_to(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        self.store.retrieve_to(timestamp)
+    }
+
+    fn retrieve_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval> {
+        self.store.retrieve_range(range)
+    }
+
+    fn find_gaps(&self, min_gap: Timestamp, range: Range<Timestamp>) -> io::Result<Vec<Range<Timestamp>>> {
+        self.store.find_gaps(min_gap, range)
+    }
+
+    fn time_unit(&self) -> TimeUnit {
+        self.store.time_unit()
+    }
+
+    fn preload(&self, range: Range<Timestamp>) -> io::Result<()> {
+        self.store.preload(range)
+    }
+
+    fn heartbeat(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.store.heartbeat(timestamp)
+    }
+
+    fn last_update(&self) -> Option<Timestamp> {
+        self.store.last_update()
+    }
+}
+
+/// Pooling reads the records that were stored, so it goes straight to the store
+impl<S> PooledTimeSeries for DedupStore<S> where S: PooledTimeSeries {
+    fn pool_all(&self, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_all(pooling_options)
+    }
+
+    fn pool_from(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_from(timestamp, pooling_options)
+    }
+
+    fn pool_to(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_to(timestamp, pooling_options)
+    }
+
+    fn pool_range(&self, range: Range<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_range(range, pooling_options)
+    }
+
+    fn pool_bucket(&self, bucket_start: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_bucket(bucket_start, pooling_options)
+    }
+
+    fn pool_multi(&self, range: Range<Timestamp>, intervals: &[Interval], pooling_options: PoolingOptions) -> io::Result<Vec<Retrieval>> {
+        self.store.pool_multi(range, intervals, pooling_options)
+    }
+
+    fn summarize_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval> {
+        self.store.summarize_range(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pooled_time_series::PoolingMethod;
+    use storage::FileStorage;
+    use util::SetupFile;
+
+    fn store(dedup: &mut DedupStore<FileStorage<Timestamp, Identified<i32>>>, timestamp: Timestamp, value: i32, id: TradeId) -> io::Result<()> {
+        dedup.store(Box::new(timestamp), Box::new(Identified::new(value, id)))
+    }
+
+    #[test]
+    fn test_dedup_store() {
+        let _setup_file = SetupFile::new("test_dedup_store");
+
+        {
+            let storage = FileStorage::<Timestamp, Identified<i32>>::new("test_dedup_store").unwrap();
+            let mut dedup = DedupStore::new::<i32>(storage, 10).unwrap();
+
+            store(&mut dedup, 100, 5, 1).unwrap();
+            store(&mut dedup, 105, 6, 2).unwrap();
+            store(&mut dedup, 120, 7, 3).unwrap();
+
+            // The live feed repeats a trade, and a backfill overlaps it
+            store(&mut dedup, 121, 7, 3).unwrap();
+            store(&mut dedup, 104, 6, 2).unwrap();
+            assert_eq!(dedup.duplicates(), 2);
+
+            // A new trade out of order still fails in the wrapped store
+            assert!(store(&mut dedup, 110, 8, 4).is_err());
+
+            // An ID outside the window is another trade
+            store(&mut dedup, 131, 9, 1).unwrap();
+            assert_eq!(dedup.len(), 4);
+        }
+
+        // The IDs of the last window are read back on opening
+        let storage = FileStorage::<Timestamp, Identified<i32>>::new("test_dedup_store").unwrap();
+        let mut dedup = DedupStore::new::<i32>(storage, 10).unwrap();
+
+        store(&mut dedup, 132, 7, 3).unwrap();
+        store(&mut dedup, 133, 9, 1).unwrap();
+        assert_eq!(dedup.duplicates(), 1);
+        assert!(dedup.store(Box::new(140 as Timestamp), Box::new(10 as i32)).is_err());
+
+        let records = dedup.retrieve_all().unwrap().into_vec::<Timestamp, Identified<i32>>();
+        assert_eq!(records.iter().map(|&(timestamp, value)| (timestamp, value.id)).collect::<Vec<_>>(), vec![(100, 1), (105, 2), (120, 3), (131, 1), (132, 3)]);
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(100), pooling: PoolingMethod::Sum, ..PoolingOptions::default() };
+        assert_eq!(dedup.pool_all(pooling_options).unwrap().into_vec::<Timestamp, Identified<i32>>(), vec![(100, Identified::new(34, 3))]);
+    }
+
+    #[test]
+    fn test_dedup_store_window() {
+        let _setup_file = SetupFile::new("test_dedup_store_window");
+
+        let storage = FileStorage::<Timestamp, Identified<i32>>::new("test_dedup_store_window").unwrap();
+        let mut dedup = DedupStore::new::<i32>(storage, 10).unwrap();
+
+        // An ID further back than the window is another trade, even while it's still held in memory
+        store(&mut dedup, 100, 1, 1).unwrap();
+        store(&mut dedup, 105, 2, 2).unwrap();
+        store(&mut dedup, 111, 3, 1).unwrap();
+        assert_eq!(dedup.duplicates(), 0);
+
+        // Forgetting the older record of an ID doesn't forget the newer one
+        store(&mut dedup, 122, 4, 1).unwrap();
+        store(&mut dedup, 125, 5, 1).unwrap();
+        assert_eq!(dedup.duplicates(), 1);
+        assert_eq!(dedup.len(), 4);
+    }
+}
@@ -0,0 +1,179 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Scheduled events that may disrupt data, such as maintenance windows and economic releases.
+//!
+//! Each event covers a range of time and applies to everything, a market, or a symbol of a market, so that the
+//! records of a range can be flagged or left out of analysis if a known event overlaps them.  Like annotations,
+//! events are kept in a plain log, one per line, as the start, end, kind, scope, and title separated by tabs, with
+//! the title escaped.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use time_series::Timestamp;
+use util::{escape_field, unescape_field};
+
+/// Something scheduled that may disrupt the data of a range of time
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    /// The inclusive start of the event
+    pub start: Timestamp,
+    /// The exclusive end of the event
+    pub end: Timestamp,
+    /// What sort of event it is, e.g. "maintenance" or "economic_release"
+    pub kind: String,
+    /// What the event applies to: "*" for everything, "market", or "market/symbol"
+    pub scope: String,
+    pub title: String,
+}
+
+impl Event {
+    /// Whether the event overlaps any of a range
+    pub fn overlaps(&self, range: &Range<Timestamp>) -> bool {
+        self.start < range.end && range.start < self.end
+    }
+
+    /// Whether the event applies to a symbol of a market
+    pub fn applies_to(&self, market: &str, symbol: &str) -> bool {
+        let parts = self.scope.split('/').collect::<Vec<&str>>();
+        match parts.len() {
+            1 => parts[0] == "*" || parts[0] == market,
+            2 => parts[0] == market && parts[1] == symbol,
+            _ => false,
+        }
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        let invalid = |message: &str| Err(io::Error::new(io::ErrorKind::InvalidInput, message.to_string()));
+
+        if self.start >= self.end {
+            return invalid("Events must end after they start");
+        }
+        if self.kind.is_empty() || self.kind.contains(char::is_whitespace) {
+            return invalid("Event kinds can't be empty or contain whitespace");
+        }
+        if self.scope.is_empty() || self.scope.contains(char::is_whitespace) || self.scope.split('/').count() > 2 {
+            return invalid("Event scopes must be \"*\", \"market\", or \"market/symbol\"");
+        }
+        if self.title.trim().is_empty() {
+            return invalid("Events need a title");
+        }
+
+        Ok(())
+    }
+}
+
+/// A log of scheduled events.  Clones share the file.
+#[derive(Clone)]
+pub struct Events {
+    filename: Arc<String>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Events {
+    /// Opens the events in `filename`, creating it if it doesn't exist
+    pub fn open(filename: &str) -> io::Result<Self> {
+        OpenOptions::new().append(true).create(true).open(filename)?;
+
+        Ok(Self {
+            filename: Arc::new(filename.to_string()),
+            lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, ()>> {
+        self.lock.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Events were poisoned"))
+    }
+
+    /// Adds an event.  Fails with `InvalidInput` if it ends before it starts, or if its kind, scope, or title is
+    /// empty or its kind or scope contains whitespace.
+    pub fn add(&self, event: &Event) -> io::Result<()> {
+        event.validate()?;
+
+        let _lock = self.lock()?;
+        let mut file = OpenOptions::new().append(true).open(&*self.filename)?;
+
+        // One write per event, so that an event is never split by a crash
+        file.write_all(format!("{}\t{}\t{}\t{}\t{}\n", event.start, event.end, event.kind, event.scope, escape_field(&event.title)).as_bytes())
+    }
+
+    /// Returns the events that overlap a range and apply to a symbol of a market, in order of their start
+    pub fn overlapping(&self, range: Range<Timestamp>, market: &str, symbol: &str) -> io::Result<Vec<Event>> {
+        let mut events = self.all()?;
+        events.retain(|event| event.overlaps(&range) && event.applies_to(market, symbol));
+        Ok(events)
+    }
+
+    /// Returns every event, in order of their start
+    pub fn all(&self) -> io::Result<Vec<Event>> {
+        let mut events = self.read()?;
+        events.sort_by_key(|event| (event.start, event.end));
+        Ok(events)
+    }
+
+    fn read(&self) -> io::Result<Vec<Event>> {
+        let _lock = self.lock()?;
+        let contents = fs::read_to_string(&*self.filename)?;
+
+        contents.lines().filter(|line| !line.is_empty()).map(|line| {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid event");
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            if fields.len() != 5 {
+                return Err(invalid());
+            }
+
+            Ok(Event {
+                start: fields[0].parse().map_err(|_| invalid())?,
+                end: fields[1].parse().map_err(|_| invalid())?,
+                kind: fields[2].to_string(),
+                scope: fields[3].to_string(),
+                title: unescape_field(fields[4]).ok_or_else(invalid)?,
+            })
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use util::SetupFile;
+
+    #[test]
+    fn test_events() {
+        let _setup_file = SetupFile::new("test_events");
+
+        let event = |start, end, scope: &str| Event { start: start, end: end, kind: "maintenance".to_string(), scope: scope.to_string(), title: "Upgrade".to_string() };
+
+        let events = Events::open("test_events").unwrap();
+        events.add(&event(50, 60, "gemini/btcusd")).unwrap();
+        events.add(&event(10, 20, "*")).unwrap();
+        events.add(&event(30, 40, "gemini")).unwrap();
+        events.add(&event(30, 40, "kraken")).unwrap();
+        assert!(events.add(&event(20, 20, "*")).is_err());
+        assert!(events.add(&event(20, 30, "a/b/c")).is_err());
+
+        assert_eq!(events.all().unwrap().len(), 4);
+        assert_eq!(events.overlapping(15..55, "gemini", "btcusd").unwrap(), vec![event(10, 20, "*"), event(30, 40, "gemini"), event(50, 60, "gemini/btcusd")]);
+        assert_eq!(events.overlapping(20..55, "gemini", "ethusd").unwrap(), vec![event(30, 40, "gemini")]);
+    }
+}
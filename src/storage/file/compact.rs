@@ -0,0 +1,193 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Offline rewriting of a storage file.
+//!
+//! Compaction reads a file line by line rather than by fixed-width records, so it gets through files that a
+//! `FileStorage` won't open, like ones with a torn final record or keys of mixed widths.  Whatever parses is written
+//! back in order at the width of the file's unit, and the new file replaces the old one in a single rename.  Lines
+//! that don't parse are appended to a backup beside the file first, the way repairing a file sets its tail aside.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::str;
+
+use key_value_store::Storable;
+use storage::file::{FileStorage, OpenMode, lock, unit, write_record_with_key_size};
+use storage::quarantine::{Quarantine, Reason};
+use time_series::{TimeUnit, Timestamp};
+use util::trim_whitespace;
+
+/// What compacting a file kept and dropped
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Compaction {
+    /// Records written to the new file
+    pub kept: usize,
+    /// Records with the same key and value as the record before them
+    pub duplicates: usize,
+    /// Records on or before the record before them, other than duplicates
+    pub out_of_order: usize,
+    /// Lines that couldn't be parsed as a record
+    pub malformed: usize,
+}
+
+impl<V> FileStorage<Timestamp, V> where V: Storable<FileStorage<Timestamp, V>> + PartialEq {
+    /// Rewrites the file of timestamps in `unit`, dropping duplicate, out of order, and malformed records.  Out of
+    /// order records are moved into `quarantine` if one is given, unless their value can't be quarantined because
+    /// it contains whitespace.  Malformed lines are appended to a file with ".bak" appended to its name.  The file is
+    /// locked while it's rewritten, so compaction fails if a storage has it open.  There's only the text format, so
+    /// the records are never converted to another.
+    ///
+    /// Fails, leaving the file alone, if its recorded unit isn't `unit` or if none of its lines hold a `V`, since
+    /// then it's being compacted as the wrong kind of file.
+    pub fn compact(filename: &str, unit: TimeUnit, quarantine: Option<&Quarantine>) -> io::Result<Compaction> {
+        let mut file = OpenOptions::new().read(true).write(true).open(filename)?;
+        lock::lock(&file, filename, OpenMode::ReadWrite)?;
+
+        let unit_file = unit::unit_file(filename);
+        if let Some(recorded) = unit::read(&unit_file)?.filter(|&recorded| recorded != unit) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("File's timestamps are in {}, not {}", recorded, unit)));
+        }
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let mut compaction = Compaction::default();
+        let mut records: Vec<(Timestamp, V)> = Vec::new();
+        let mut malformed = Vec::new();
+
+        for line in contents.split(|&byte| byte == b'\n').filter(|line| !trim_whitespace(line).is_empty()) {
+            let record = match parse_line::<V>(line) {
+                Some(record) => record,
+                None => {
+                    malformed.push(line);
+                    continue;
+                },
+            };
+
+            match records.last() {
+                Some(&(key, value)) if record.0 == key && record.1 == value => compaction.duplicates += 1,
+                Some(&(key, _)) if record.0 <= key => {
+                    compaction.out_of_order += 1;
+
+                    if let Some(quarantine) = quarantine {
+                        let value = String::from_utf8(trim_whitespace(&record.1.into_bytes()).to_vec()).unwrap_or_default();
+                        if !value.is_empty() && !value.contains(char::is_whitespace) {
+                            quarantine.add(record.0, &value, Reason::OutOfOrder)?;
+                        }
+                    }
+                },
+                _ => records.push(record),
+            }
+        }
+
+        if records.is_empty() && !malformed.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "No line of the file holds a record of this type"));
+        }
+
+        // The malformed lines are only dropped once they're safely in the backup
+        if !malformed.is_empty() {
+            let mut backup_file = OpenOptions::new().append(true).create(true).open(format!("{}.bak", filename))?;
+            for line in &malformed {
+                backup_file.write_all(line)?;
+                backup_file.write_all(b"\n")?;
+            }
+            backup_file.sync_all()?;
+        }
+
+        let temporary_filename = format!("{}.compact", filename);
+
+        {
+            let mut temporary_file = File::create(&temporary_filename)?;
+            for &(key, value) in &records {
+                write_record_with_key_size(&mut temporary_file, key, value, unit.significant_digits())?;
+            }
+            temporary_file.flush()?;
+            temporary_file.sync_all()?;
+        }
+
+        fs::rename(&temporary_filename, filename)?;
+        unit::write(&unit_file, unit)?;
+
+        compaction.kept = records.len();
+        compaction.malformed = malformed.len();
+        Ok(compaction)
+    }
+}
+
+/// Parses a line of a storage file, whatever the width of its key.  The key is the first word, and the value is the
+/// rest of the line, which may itself contain spaces.
+fn parse_line<V>(line: &[u8]) -> Option<(Timestamp, V)> where V: Storable<FileStorage<Timestamp, V>> {
+    let line = str::from_utf8(trim_whitespace(line)).ok()?;
+    let split = line.find(char::is_whitespace)?;
+
+    let key = <Timestamp as Storable<FileStorage<Timestamp, V>>>::from_bytes(line[..split].as_bytes()).ok()?;
+    let value = V::from_bytes(trim_whitespace(line[split..].as_bytes())).ok()?;
+
+    Some((key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use schema::Quote;
+    use time_series::TimeSeries;
+    use util::SetupFile;
+
+    #[test]
+    fn test_compact() {
+        let _setup_file = SetupFile::new("test_compact");
+        let _backup_file = SetupFile::new("test_compact.bak");
+        let _quarantine_file = SetupFile::new("test_compact_quarantine");
+
+        let contents = concat!(
+            "0000000000001    1\n",
+            "0000000000002    2\n",
+            "0000000000002    2\n",
+            "             3    3\n",
+            "0000000000002    7\n",
+            "0000000000x04    4\n",
+            "5 5\n",
+            "0000000000006",
+        );
+        fs::write("test_compact", contents).unwrap();
+
+        let quarantine = Quarantine::open("test_compact_quarantine").unwrap();
+        let compaction = FileStorage::<Timestamp, i32>::compact("test_compact", TimeUnit::Milliseconds, Some(&quarantine)).unwrap();
+
+        assert_eq!(compaction, Compaction { kept: 4, duplicates: 1, out_of_order: 1, malformed: 2 });
+        assert_eq!(quarantine.records::<i32>().unwrap().iter().map(|record| (record.timestamp, record.value)).collect::<Vec<_>>(), vec![(2, 7)]);
+
+        let storage = FileStorage::<Timestamp, i32>::new("test_compact").unwrap();
+        assert_eq!(storage.retrieve_all().unwrap().into_vec::<Timestamp, i32>(), vec![(1, 1), (2, 2), (3, 3), (5, 5)]);
+
+        // A file that's open can't be compacted out from under its storage
+        assert!(FileStorage::<Timestamp, i32>::compact("test_compact", TimeUnit::Milliseconds, None).is_err());
+        drop(storage);
+
+        assert_eq!(fs::read_to_string("test_compact.bak").unwrap(), "0000000000x04    4\n0000000000006\n");
+
+        // Compacting as the wrong value type or unit leaves the file alone rather than emptying it
+        let contents = fs::read("test_compact").unwrap();
+        assert!(FileStorage::<Timestamp, Quote<Timestamp>>::compact("test_compact", TimeUnit::Milliseconds, None).is_err());
+
+        unit::write(&unit::unit_file("test_compact"), TimeUnit::Microseconds).unwrap();
+        assert!(FileStorage::<Timestamp, i32>::compact("test_compact", TimeUnit::Milliseconds, None).is_err());
+        assert_eq!(fs::read("test_compact").unwrap(), contents);
+    }
+}
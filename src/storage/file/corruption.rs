@@ -0,0 +1,58 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// What's wrong with a storage file that couldn't be read.  Reads fail with an `InvalidData` error carrying one of
+/// these rather than panicking, so a damaged file can't take down whatever is reading it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Corruption {
+    /// The file isn't a whole number of records long
+    Size,
+    /// A record isn't text
+    Encoding,
+    /// A record's key couldn't be parsed
+    Key,
+    /// A record's value couldn't be parsed
+    Value,
+}
+
+impl Corruption {
+    /// The corruption behind an error, if it came from reading a damaged file
+    pub fn of(error: &io::Error) -> Option<Corruption> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<Corruption>()).cloned()
+    }
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Corruption::Size => "FileStorage file is an invalid size",
+            Corruption::Encoding => "FileStorage record is not valid UTF-8",
+            Corruption::Key => "FileStorage record has an invalid key",
+            Corruption::Value => "FileStorage record has an invalid value",
+        })
+    }
+}
+
+impl Error for Corruption {}
+
+impl From<Corruption> for io::Error {
+    fn from(corruption: Corruption) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, corruption)
+    }
+}
@@ -0,0 +1,42 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The last heartbeat of a storage file's feed, kept beside it as "<file>.heartbeat"
+
+use std::fs;
+use std::io;
+
+use time_series::Timestamp;
+
+pub fn heartbeat_file(filename: &str) -> String {
+    format!("{}.heartbeat", filename)
+}
+
+/// Reads the last heartbeat, or `None` if there hasn't been one
+pub fn read(heartbeat_file: &str) -> io::Result<Option<Timestamp>> {
+    match fs::read_to_string(heartbeat_file) {
+        Ok(text) => text.trim().parse().map(Some).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Heartbeat file is corrupt")),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Replaces the last heartbeat.  It's written beside the file and renamed over it, so that a crash can't leave it
+/// half written.
+pub fn write(heartbeat_file: &str, heartbeat: Timestamp) -> io::Result<()> {
+    let temporary = format!("{}.tmp", heartbeat_file);
+    fs::write(&temporary, heartbeat.to_string())?;
+    fs::rename(&temporary, heartbeat_file)
+}
@@ -0,0 +1,90 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+
+use key_value_store::IoStats;
+use storage::deadline;
+
+/// Wraps a file and counts the bytes read from it and the seeks performed on it.  Reads and seeks fail once the
+/// thread's deadline, if it has one, has passed.
+pub struct IoCounter<F> {
+    inner: F,
+    stats: IoStats,
+}
+
+impl<F> IoCounter<F> {
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner: inner,
+            stats: IoStats::default(),
+        }
+    }
+
+    /// Returns the stats counted so far and starts counting again from zero
+    pub fn take_stats(&mut self) -> IoStats {
+        mem::replace(&mut self.stats, IoStats::default())
+    }
+}
+
+impl<F> Read for IoCounter<F> where F: Read {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        deadline::check()?;
+
+        let bytes = self.inner.read(buf)?;
+        self.stats.bytes_read += bytes as u64;
+        Ok(bytes)
+    }
+}
+
+impl<F> Seek for IoCounter<F> where F: Seek {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        deadline::check()?;
+
+        self.stats.seeks += 1;
+        self.inner.seek(pos)
+    }
+}
+
+impl<F> Write for IoCounter<F> where F: Write {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_io_counter() {
+        let mut counter = IoCounter::new(Cursor::new(vec![0u8; 100]));
+        let mut buffer = [0u8; 10];
+
+        counter.read_exact(&mut buffer).unwrap();
+        counter.seek(SeekFrom::Start(50)).unwrap();
+        counter.read_exact(&mut buffer).unwrap();
+
+        assert_eq!(counter.take_stats(), IoStats { bytes_read: 20, seeks: 1 });
+        assert_eq!(counter.take_stats(), IoStats::default());
+    }
+}
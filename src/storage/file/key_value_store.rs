@@ -13,17 +13,47 @@
 // You should have received a copy of the GNU General Public License
 // along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::io::{self, Seek, SeekFrom};
+use std::any::Any;
+use std::io;
+use std::sync::mpsc::Receiver;
 
-use key_value_store::{Data, KeyValueStore, Storable};
-use storage::file::{FileStorage, write_record};
+use key_value_store::{Data, IoStats, KeyValueStore, Notification, RATE_WINDOWS, RecordRate, Storable, StoreStats};
+use parse;
+use storage::file::{FileStorage, OpenMode, write_record_with_key_size};
+use time_series::{TimeUnit, Timestamp};
 
 impl<K, V> KeyValueStore for FileStorage<K, V> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>> {
     fn len(&self) -> usize {
         self.items
     }
 
+    fn io_stats(&self) -> IoStats {
+        self.readers.io_stats()
+    }
+
+    fn last_key(&self) -> Option<Box<Data>> {
+        if self.items > 0 {
+            Some(Box::new(self.last_key))
+        } else {
+            None
+        }
+    }
+
+    fn size_on_disk(&self) -> io::Result<u64> {
+        self.flush()?;
+        Ok(self.writer.metadata()?.len())
+    }
+
+    /// The first key is kept since opening, and the records of each window are counted by a binary search
+    fn stats(&self) -> io::Result<StoreStats> {
+        self.stats_at(TimeUnit::Milliseconds.convert(parse::now(), self.unit))
+    }
+
     fn store(&mut self, key: Box<Data>, value: Box<Data>) -> io::Result<()> {
+        if self.mode != OpenMode::ReadWrite {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "FileStorage was opened read-only"));
+        }
+
         let key = if let Some(&key) = key.downcast_ref::<K>() {
             key
         } else {
@@ -35,9 +65,29 @@ impl<K, V> KeyValueStore for FileStorage<K, V> where K: Storable<FileStorage<K,
         }
 
         if let Some(&value) = value.downcast_ref::<V>() {
-            self.file.borrow_mut().seek(SeekFrom::End(0))?;
+            // The bookkeeping is only updated once the record is safely buffered or written, so a failed store
+            // leaves the storage as it was
+            match self.write_buffer()? {
+                Some(mut buffer) => {
+                    write_record_with_key_size(buffer.bytes(), key, value, self.key_size)?;
 
-            write_record(&mut *self.file.borrow_mut(), key, value)?;
+                    // Records buffered before this one stay buffered if writing them out fails
+                    if buffer.add() {
+                        if let Err(error) = buffer.write_to(&self.writer) {
+                            buffer.discard_last(self.item_size);
+                            return Err(error);
+                        }
+                    }
+                },
+                None => {
+                    // Cut off whatever part of the record made it into the file
+                    let end = if self.items == 0 { 0 } else { self.end_offset + self.item_size as u64 };
+                    if let Err(error) = write_record_with_key_size(&mut &self.writer, key, value, self.key_size) {
+                        self.writer.set_len(end)?;
+                        return Err(error);
+                    }
+                },
+            }
 
             if self.items == 0 {
                 self.first_key = key;
@@ -48,15 +98,66 @@ impl<K, V> KeyValueStore for FileStorage<K, V> where K: Storable<FileStorage<K,
             self.items += 1;
             self.last_key = key;
 
+            self.subscribers.notify(key, value);
+
             Ok(())
         } else {
             Err(io::Error::new(io::ErrorKind::InvalidInput, "FileStorage was passed the wrong kind of data"))
         }
     }
 
+    /// Buffered records are written out before the file is synced
+    fn sync(&self) -> io::Result<()> {
+        if self.mode != OpenMode::ReadWrite {
+            return Ok(());
+        }
+
+        self.flush()?;
+        self.writer.sync_data()
+    }
+
+    fn subscribe(&self) -> io::Result<Receiver<Notification>> {
+        Ok(self.subscribers.subscribe())
+    }
+
     //fn retrieve(&self, key: Box<Data>) -> io::Result<Retrieval> {}
 }
 
+fn as_timestamp<K>(key: &K) -> Option<Timestamp> where K: 'static {
+    (key as &dyn Any).downcast_ref::<Timestamp>().cloned()
+}
+
+
+impl<K, V> FileStorage<K, V> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>> {
+    /// The stats of the store at `now`, in the unit of its keys
+    fn stats_at(&self, now: Timestamp) -> io::Result<StoreStats> {
+        let mut stats = StoreStats {
+            records: self.items,
+            bytes_on_disk: self.size_on_disk()?,
+            ..StoreStats::default()
+        };
+
+        let (first, last) = match (as_timestamp(&self.first_key), as_timestamp(&self.last_key)) {
+            (Some(first), Some(last)) if self.items > 0 => (first, last),
+            _ => return Ok(stats),
+        };
+        stats.first_timestamp = Some(first);
+        stats.last_timestamp = Some(last);
+
+        for &window in RATE_WINDOWS.iter() {
+            let span = TimeUnit::Milliseconds.convert(window, self.unit);
+            if now.saturating_sub(first) < span {
+                break;
+            }
+
+            let start = (&(now - span + 1) as &dyn Any).downcast_ref::<K>().cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "FileStorage keys aren't timestamps"))?;
+            stats.rates.push(RecordRate::new(window, self.count_from(start)?));
+        }
+
+        Ok(stats)
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -66,7 +167,7 @@ mod tests {
     use std::io::Read;
     use std::mem;
 
-    use time_series::Timestamp;
+    use time_series::{TimeSeries, Timestamp};
     use util::SetupFile;
 
     #[test]
@@ -96,6 +197,62 @@ mod tests {
         assert_eq!(fs.len(), 5);
     }
 
+    #[test]
+    fn test_last_key() {
+        let _setup_file = SetupFile::new("test_last_key");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_last_key").unwrap();
+        assert!(fs.last_key().is_none());
+        assert_eq!(fs.size_on_disk().unwrap(), 0);
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(14 as Timestamp), Box::new(2 as i32)).unwrap();
+
+        assert_eq!(fs.last_key().unwrap().downcast_ref::<Timestamp>(), Some(&14));
+        assert_eq!(fs.size_on_disk().unwrap(), 38);
+    }
+
+    #[test]
+    fn test_stats() {
+        let _setup_file = SetupFile::new("test_stats");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_stats").unwrap();
+        assert_eq!(fs.stats().unwrap(), StoreStats::default());
+
+        for &(timestamp, value) in &[(0, 1), (30_000, 2), (50_000, 3), (59_000, 4), (61_000, 5), (90_000, 6)] {
+            fs.store(Box::new(timestamp as Timestamp), Box::new(value as i32)).unwrap();
+        }
+
+        // Only the minute fits in the history, and it starts just after 30 seconds
+        let stats = fs.stats_at(90_000).unwrap();
+        assert_eq!(stats.records, 6);
+        assert_eq!(stats.first_timestamp, Some(0));
+        assert_eq!(stats.last_timestamp, Some(90_000));
+        assert_eq!(stats.bytes_on_disk, 114);
+        assert_eq!(stats.rates, vec![RecordRate::new(60_000, 4)]);
+        assert_eq!(stats.rates[0].per_second, 4.0 / 60.0);
+
+        // Rates are of the minute before now, so they fall off once records stop
+        assert_eq!(fs.stats_at(120_000).unwrap().rates, vec![RecordRate::new(60_000, 2)]);
+        assert_eq!(fs.stats_at(200_000).unwrap().rates, vec![RecordRate::new(60_000, 0)]);
+        assert_eq!(fs.stats().unwrap().rates, vec![RecordRate::new(60_000, 0), RecordRate::new(3_600_000, 0), RecordRate::new(86_400_000, 0)]);
+    }
+
+    #[test]
+    fn test_sync() {
+        let _setup_file = SetupFile::new("test_sync");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_sync").unwrap();
+        fs.sync().unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.sync().unwrap();
+
+        // Read-only storage has nothing to sync
+        drop(fs);
+        FileStorage::<Timestamp, i32>::read_only("test_sync").unwrap().sync().unwrap();
+    }
+
     #[test]
     fn test_reads_last_time() {
         let _setup_file = SetupFile::new("test_reads_last_time");
@@ -111,9 +268,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_io_stats() {
+        let _setup_file = SetupFile::new("test_io_stats");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_io_stats").unwrap();
+
+        fs.store(Box::new(1 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(2 as Timestamp), Box::new(2 as i32)).unwrap();
+
+        let before = fs.io_stats();
+        fs.retrieve_all().unwrap();
+        let query = fs.io_stats() - before;
+
+        assert_eq!(query.bytes_read, 38);
+        assert!(query.seeks > 0);
+    }
+
     //#[test]
     //fn test_retrieve() { }
 
+    #[test]
+    fn test_subscribe() {
+        let _setup_file = SetupFile::new("test_subscribe");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_subscribe").unwrap();
+        fs.store(Box::new(1 as Timestamp), Box::new(1 as i32)).unwrap();
+
+        let receiver = fs.subscribe().unwrap();
+        fs.store(Box::new(2 as Timestamp), Box::new(2 as i32)).unwrap();
+        assert!(fs.store(Box::new(2 as Timestamp), Box::new(3 as i32)).is_err());
+
+        // Only records stored after subscribing are sent, and failed stores aren't
+        assert_eq!(receiver.try_recv().unwrap().into_single::<Timestamp, i32>(), (2, 2));
+        assert!(receiver.try_recv().is_err());
+
+        // A subscriber that hangs up doesn't stop the store
+        mem::drop(receiver);
+        fs.store(Box::new(3 as Timestamp), Box::new(3 as i32)).unwrap();
+    }
+
     #[test]
     fn test_store() {
         let _setup_file = SetupFile::new("test_store");
@@ -0,0 +1,83 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+
+use fs2::{self, FileExt};
+
+/// How a storage file is opened.  Writers take an exclusive advisory lock on the file and readers a shared one, so
+/// another process can't append to a file behind the back of whatever has it open.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpenMode {
+    ReadWrite,
+    /// The file isn't created if it doesn't exist, and stores fail
+    ReadOnly,
+    /// Like `ReadOnly`, but takes no lock, so the file can be read while another process writes it.  The records
+    /// are the ones written when the file was opened, less any record that was only partly written.
+    Concurrent,
+}
+
+/// A storage file that couldn't be opened because another process holds a conflicting lock on it.  Opening fails
+/// with a `WouldBlock` error carrying this rather than waiting for the lock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Locked {
+    pub filename: String,
+    /// How the file was being opened
+    pub mode: OpenMode,
+}
+
+impl Locked {
+    /// The lock behind an error, if it came from opening a locked file
+    pub fn of(error: &io::Error) -> Option<&Locked> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<Locked>())
+    }
+}
+
+impl fmt::Display for Locked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            OpenMode::ReadWrite => write!(f, "{} is open in another process", self.filename),
+            OpenMode::ReadOnly | OpenMode::Concurrent => write!(f, "{} is being written by another process", self.filename),
+        }
+    }
+}
+
+impl Error for Locked {}
+
+impl From<Locked> for io::Error {
+    fn from(locked: Locked) -> Self {
+        io::Error::new(io::ErrorKind::WouldBlock, locked)
+    }
+}
+
+/// Takes the lock for a mode on an open storage file.  The lock is held until the file is closed.
+pub fn lock(file: &File, filename: &str, mode: OpenMode) -> io::Result<()> {
+    let result = match mode {
+        OpenMode::ReadWrite => FileExt::try_lock_exclusive(file),
+        OpenMode::ReadOnly => FileExt::try_lock_shared(file),
+        OpenMode::Concurrent => return Ok(()),
+    };
+
+    match result {
+        Err(ref error) if error.raw_os_error() == fs2::lock_contended_error().raw_os_error() => Err(Locked {
+            filename: filename.to_string(),
+            mode: mode,
+        }.into()),
+        result => result,
+    }
+}
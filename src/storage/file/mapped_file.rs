@@ -0,0 +1,161 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use memmap::Mmap;
+
+/// A file whose reads are served from a memory map instead of read syscalls.
+///
+/// Writes go straight to the underlying file.  The map is refreshed lazily the next time a read reaches
+/// past the end of what is currently mapped, picking up whatever has been appended through this or any other
+/// handle.
+pub struct MappedFile {
+    file: File,
+    map: Option<Mmap>,
+    len: u64,
+    position: u64,
+}
+
+impl MappedFile {
+    pub fn new(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+
+        let mut mapped_file = Self {
+            file: file,
+            map: None,
+            len: len,
+            position: 0,
+        };
+        mapped_file.remap()?;
+
+        Ok(mapped_file)
+    }
+
+    fn mapped_len(&self) -> u64 {
+        self.map.as_ref().map_or(0, |m| m.len() as u64)
+    }
+
+    fn remap(&mut self) -> io::Result<()> {
+        // Empty files can't be mapped
+        self.map = if self.len > 0 {
+            Some(unsafe { Mmap::map(&self.file)? })
+        } else {
+            None
+        };
+
+        Ok(())
+    }
+}
+
+impl Read for MappedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.mapped_len() {
+            // Another handle may have appended to the file
+            self.len = cmp::max(self.len, self.file.metadata()?.len());
+
+            if self.len > self.mapped_len() {
+                self.remap()?;
+            }
+        }
+
+        let map = match self.map {
+            Some(ref map) => map,
+            None => return Ok(0),
+        };
+
+        let start = cmp::min(self.position, map.len() as u64) as usize;
+        let bytes = cmp::min(buf.len(), map.len() - start);
+
+        buf[..bytes].copy_from_slice(&map[start..start + bytes]);
+        self.position += bytes as u64;
+
+        Ok(bytes)
+    }
+}
+
+impl Seek for MappedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => offset_position(self.len, offset),
+            SeekFrom::Current(offset) => offset_position(self.position, offset),
+        };
+
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative position")),
+        }
+    }
+}
+
+impl Write for MappedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The file is opened for appending, so every write lands at the end
+        let bytes = self.file.write(buf)?;
+        self.len += bytes as u64;
+        self.position = self.len;
+
+        Ok(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn offset_position(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::OpenOptions;
+
+    use util::SetupFile;
+
+    #[test]
+    fn test_mapped_file_reads_appended_data() {
+        let _setup_file = SetupFile::new("test_mapped_file_reads_appended_data");
+
+        let file = OpenOptions::new().read(true).append(true).create(true).open("test_mapped_file_reads_appended_data").unwrap();
+        let mut mapped_file = MappedFile::new(file).unwrap();
+
+        mapped_file.write_all(b"hello").unwrap();
+        mapped_file.seek(SeekFrom::Start(1)).unwrap();
+
+        let mut buffer = [0u8; 3];
+        mapped_file.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"ell");
+
+        mapped_file.write_all(b" world").unwrap();
+        mapped_file.seek(SeekFrom::End(-5)).unwrap();
+
+        let mut buffer = [0u8; 5];
+        mapped_file.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"world");
+    }
+}
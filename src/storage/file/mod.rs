@@ -13,55 +13,169 @@
 // You should have received a copy of the GNU General Public License
 // along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::cell::RefCell;
 use std::cmp;
 use std::fs::{File, OpenOptions};
 use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
 use std::marker::PhantomData;
 use std::str;
-
-use key_value_store::Storable;
-use time_series::RetrievalDirection;
-
+use std::sync::Mutex;
+
+use key_value_store::{Storable, Subscribers};
+use storage::file::io_counter::IoCounter;
+#[cfg(feature = "mmap")]
+use storage::file::mapped_file::MappedFile;
+use storage::file::reader_pool::{Reader, ReaderPool};
+use storage::file::write_buffer::WriteBuffer;
+use time_series::{RetrievalDirection, TimeUnit, Timestamp};
+use util::trim_whitespace;
+
+pub use self::compact::Compaction;
+pub use self::corruption::Corruption;
+pub use self::lock::{Locked, OpenMode};
+pub use self::read_only::ReadOnlyFileStorage;
+pub use self::repair::Repair;
+pub use self::tail::TailCursor;
+pub use self::write_buffer::BufferPolicy;
+
+#[cfg(not(feature = "mmap"))]
+type StorageFile = File;
+#[cfg(feature = "mmap")]
+type StorageFile = MappedFile;
+
+type CountedFile = IoCounter<StorageFile>;
+
+/// Records stored in a file, in key order.
+///
+/// Records are appended through a single write handle, while reads take their own handles from a pool, so a
+/// storage shared between threads can serve many reads at once.  The file is locked against other processes for as
+/// long as it's open; see `OpenMode`.
 pub struct FileStorage<K, V> {
-    file: RefCell<File>,
+    writer: File,
+    mode: OpenMode,
+    readers: ReaderPool,
+    /// The width of each key, which timestamp keys can widen beyond `K::size()` to fit a finer unit
+    key_size: usize,
     item_size: usize,
     items: usize,
     first_key: K,
     last_key: K,
     end_offset: u64,
+    /// The unit of the keys, if they're timestamps
+    unit: TimeUnit,
+    subscribers: Subscribers,
+    /// Where the last heartbeat is kept
+    heartbeat_file: String,
+    /// When the feed last sent a heartbeat, in the unit of the keys
+    heartbeat: Option<Timestamp>,
+    /// Stored records that haven't been written out yet, if stores are buffered
+    buffer: Option<Mutex<WriteBuffer>>,
     _phantom: PhantomData<V>,
 }
 
+impl<V> FileStorage<Timestamp, V> where V: Storable<FileStorage<Timestamp, V>> {
+    /// Opens a file of timestamps in a unit other than milliseconds.  The timestamps are stored with as many
+    /// digits as the unit needs.
+    pub fn with_unit(filename: &str, unit: TimeUnit) -> io::Result<Self> {
+        Self::open(filename, unit.significant_digits(), unit, OpenMode::ReadWrite)
+    }
+
+    /// Opens an existing file of timestamps in a unit other than milliseconds for reading only
+    pub fn read_only_with_unit(filename: &str, unit: TimeUnit) -> io::Result<Self> {
+        Self::open(filename, unit.significant_digits(), unit, OpenMode::ReadOnly)
+    }
+
+    /// Opens a file of timestamps in a unit other than milliseconds for reading alongside its writer
+    pub fn open_read_only_with_unit(filename: &str, unit: TimeUnit) -> io::Result<ReadOnlyFileStorage<Timestamp, V>> {
+        Self::open(filename, unit.significant_digits(), unit, OpenMode::Concurrent).map(ReadOnlyFileStorage::new)
+    }
+}
+
 impl<K, V> FileStorage<K, V> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>> {
     pub fn new(filename: &str) -> io::Result<Self> {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .open(filename)?;
+        Self::open(filename, K::size(), TimeUnit::Milliseconds, OpenMode::ReadWrite)
+    }
+
+    /// Opens an existing file for reading only.  Any number of readers can have a file open at once, but not while
+    /// a writer does.
+    pub fn read_only(filename: &str) -> io::Result<Self> {
+        Self::open(filename, K::size(), TimeUnit::Milliseconds, OpenMode::ReadOnly)
+    }
+
+    /// Opens an existing file for reading, even while another process is writing it, e.g. for an analytics job
+    /// running beside the collector.  Records written after it's opened aren't seen, and the handle it's opened as
+    /// can't be stored to.
+    pub fn open_read_only(filename: &str) -> io::Result<ReadOnlyFileStorage<K, V>> {
+        Self::open(filename, K::size(), TimeUnit::Milliseconds, OpenMode::Concurrent).map(ReadOnlyFileStorage::new)
+    }
+
+    fn open(filename: &str, key_size: usize, unit: TimeUnit, mode: OpenMode) -> io::Result<Self> {
+        let mut file = match mode {
+            OpenMode::ReadWrite => OpenOptions::new().read(true).append(true).create(true).open(filename)?,
+            OpenMode::ReadOnly | OpenMode::Concurrent => OpenOptions::new().read(true).open(filename)?,
+        };
+
+        // Lock before reading anything, so that the bookkeeping can't be changed underneath us
+        lock::lock(&file, filename, mode)?;
 
         // Get the length of the file by seeking to the end
-        let end = file.seek(SeekFrom::End(0))?;
+        let mut end = file.seek(SeekFrom::End(0))?;
 
-        let item_size = K::size() + 1 + V::size() + 1;
+        let item_size = key_size + 1 + V::size() + 1;
 
         let items = if end as usize % item_size == 0 {
             end as usize / item_size
+        } else if mode == OpenMode::Concurrent {
+            // The writer is partway through appending a record, so leave it out
+            end -= end % item_size as u64;
+            end as usize / item_size
         } else {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "FileStorage file is an invalid size"));
+            return Err(Corruption::Size.into());
         };
 
+        // Make sure the file's timestamps are in the unit it's being opened in, since the records can't say
+        let unit_file = unit::unit_file(filename);
+        if items == 0 && mode == OpenMode::ReadWrite {
+            unit::write(&unit_file, unit)?;
+        } else {
+            match unit::read(&unit_file)? {
+                Some(recorded) if recorded != unit => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("File's timestamps are in {}, not {}", recorded, unit),
+                    ));
+                },
+                Some(_) => {},
+                None if items > 0 && unit != TimeUnit::Milliseconds => {
+                    // Written before units were recorded, so check that the first record is laid out for this unit
+                    let mut buffer = vec![0u8; item_size];
+                    file.seek(SeekFrom::Start(0))?;
+                    file.read_exact(&mut buffer)?;
+
+                    if buffer[key_size - 1] == b' ' || buffer[key_size] != b' ' || buffer[item_size - 1] != b'\n' {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("File's timestamps aren't in {}", unit),
+                        ));
+                    }
+
+                    if mode == OpenMode::ReadWrite {
+                        unit::write(&unit_file, unit)?;
+                    }
+                },
+                None => {},
+            }
+        }
+
         // If the file is bigger than a single element,
         let (first_key, last_key, end_offset) = if end >= item_size as u64 {
-            let mut buffer = vec![0u8; K::size()];
+            let mut buffer = vec![0u8; key_size];
 
             // Seek to the beginning of the first item
             file.seek(SeekFrom::Start(0))?;
             let first_key = read_key::<K, V, File>(&mut file, &mut buffer)?;
 
             // Seek to the beginning of the last item
-            let end_offset = file.seek(SeekFrom::End(-(item_size as i64)))?;
+            let end_offset = file.seek(SeekFrom::Start(end - item_size as u64))?;
             let last_key = read_key::<K, V, File>(&mut file, &mut buffer)?;
 
             (first_key, last_key, end_offset)
@@ -70,43 +184,61 @@ impl<K, V> FileStorage<K, V> where K: Storable<FileStorage<K, V>> + Ord, V: Stor
         };
 
         Ok(Self {
-            file: RefCell::new(file),
+            writer: file,
+            mode: mode,
+            readers: ReaderPool::new(filename),
+            key_size: key_size,
             item_size: item_size,
             items: items,
             first_key: first_key,
             last_key: last_key,
             end_offset: end_offset,
+            unit: unit,
+            subscribers: Subscribers::default(),
+            heartbeat_file: heartbeat::heartbeat_file(filename),
+            heartbeat: heartbeat::read(&heartbeat::heartbeat_file(filename))?,
+            buffer: None,
             _phantom: PhantomData,
         })
     }
 
+    pub fn mode(&self) -> OpenMode {
+        self.mode
+    }
+
+    /// Takes a read handle from the pool, writing out any buffered records first so the read sees them
+    fn reader<'a>(&'a self) -> io::Result<Reader<'a>> {
+        self.flush()?;
+        self.readers.get()
+    }
+
     /// Finds the key and offset of the first record that occurs on or before the search key.
     /// If the search key is before the first record, it returns the key and offset of the first record.
-    fn find_from(&self, search_key: K) -> io::Result<(K, u64)> {
+    fn find_from(&self, file: &mut CountedFile, search_key: K) -> io::Result<(K, u64)> {
         // Scratch buffer into which we'll read new timestamps for parsing
-        let mut read_buffer = vec![0u8; K::size()];
+        let mut read_buffer = vec![0u8; self.key_size];
 
         let from_offset = if search_key >= self.first_key {
-            binary_search_for_key::<K, V, File>(&mut *self.file.borrow_mut(), &mut read_buffer, Some(RetrievalDirection::Backward), search_key, 0, self.end_offset)?
+            binary_search_for_key::<K, V, CountedFile>(file, &mut read_buffer, Some(RetrievalDirection::Backward), search_key, 0, self.end_offset)?
         } else {
             0
         };
 
-        self.file.borrow_mut().seek(SeekFrom::Start(from_offset))?;
-        let from_key = cmp::max(read_key::<K, V, File>(&mut *self.file.borrow_mut(), &mut read_buffer)?, search_key);
+        file.seek(SeekFrom::Start(from_offset))?;
+        let from_key = cmp::max(read_key::<K, V, CountedFile>(file, &mut read_buffer)?, search_key);
 
         Ok((from_key, from_offset))
     }
 
     /// Finds the offset of the first record that occurs before the search key.
-    fn find_to(&self, search_key: K) -> io::Result<u64> {
+    fn find_to(&self, file: &mut CountedFile, search_key: K) -> io::Result<u64> {
         // Scratch buffer into which we'll read new keys for parsing
-        let mut read_buffer = vec![0u8; K::size()];
+        let mut read_buffer = vec![0u8; self.key_size];
 
-        let to_offset = binary_search_for_key::<K, V, File>(&mut *self.file.borrow_mut(), &mut read_buffer, Some(RetrievalDirection::Backward), search_key, 0, self.end_offset)?;
+        let to_offset = binary_search_for_key::<K, V, CountedFile>(file, &mut read_buffer, Some(RetrievalDirection::Backward), search_key, 0, self.end_offset)?;
 
-        self.file.borrow_mut().seek(SeekFrom::Start(to_offset))?;
-        let to_key = read_key::<K, V, File>(&mut *self.file.borrow_mut(), &mut read_buffer)?;
+        file.seek(SeekFrom::Start(to_offset))?;
+        let to_key = read_key::<K, V, CountedFile>(file, &mut read_buffer)?;
 
         // find_to is exclusive.  If the bounding key is found exactly, exclude that record from the result.
         Ok(if to_key != search_key {
@@ -117,6 +249,21 @@ impl<K, V> FileStorage<K, V> where K: Storable<FileStorage<K, V>> + Ord, V: Stor
             return Err(io::Error::new(io::ErrorKind::InvalidInput, "find_to search key was equal to the first record"));
         })
     }
+
+    /// Counts the records on or after the search key from their offsets, without reading them
+    fn count_from(&self, search_key: K) -> io::Result<usize> {
+        if self.items == 0 || search_key > self.last_key {
+            return Ok(0);
+        } else if search_key <= self.first_key {
+            return Ok(self.items);
+        }
+
+        let file = &mut *self.reader()?;
+        let mut read_buffer = vec![0u8; self.key_size];
+        let from_offset = binary_search_for_key::<K, V, CountedFile>(file, &mut read_buffer, Some(RetrievalDirection::Forward), search_key, 0, self.end_offset)?;
+
+        Ok(self.items - from_offset as usize / self.item_size)
+    }
 }
 
 fn binary_search_for_key<K, V, F>(
@@ -127,8 +274,16 @@ fn binary_search_for_key<K, V, F>(
     start_offset: u64,
     end_offset: u64,
 ) -> io::Result<u64> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>>, F: Read + Seek {
+    // A range of a single record starts and ends at the same offset, so it's only empty if there's no record there
     if start_offset == end_offset {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "No items in search range"));
+        file.seek(SeekFrom::Start(start_offset))?;
+        match read_key::<K, V, F>(file, buffer) {
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "No items in search range"));
+            },
+            Err(error) => return Err(error),
+            Ok(_) => {},
+        }
     }
 
     // Check the beginning of the range
@@ -170,7 +325,8 @@ fn binary_search_for_key<K, V, F>(
         end_offset: u64,
     ) -> io::Result<u64> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>>, F: Read + Seek {
         let range = end_offset - start_offset;
-        let range_items = range / (K::size() + 1 + V::size() + 1) as u64;
+        let item_size = (buffer.len() + 1 + V::size() + 1) as u64;
+        let range_items = range / item_size;
 
         // If we've narrowed it down to just one item, the search key must occur between it and the next item.
         // Depending on the direction we want to retrieve, return it, the next item, or neither.
@@ -182,7 +338,7 @@ fn binary_search_for_key<K, V, F>(
             };
         }
 
-        let center_offset = start_offset + range_items / 2 * (K::size() + 1 + V::size() + 1) as u64;
+        let center_offset = start_offset + range_items / 2 * item_size;
 
         // Check the center of the range (rounded down)
         file.seek(SeekFrom::Start(center_offset))?;
@@ -201,48 +357,300 @@ fn binary_search_for_key<K, V, F>(
     bisect_and_descend::<K, V, F>(file, buffer, retrieval_direction, search_key, start_offset, end_offset)
 }
 
+/// Reads a key into a buffer the width of the file's keys
 fn read_key<K, V, F>(file: &mut F, buffer: &mut [u8]) -> io::Result<K> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>>, F: Read {
-    debug_assert_eq!(buffer.len(), K::size(), "read_key was passed a buffer of the wrong size");
+    debug_assert!(buffer.len() >= K::size(), "read_key was passed a buffer of the wrong size");
 
     file.read_exact(buffer)?;
 
-    if let Ok(str_buffer) = str::from_utf8(buffer) {
-        Ok(K::from_bytes(str_buffer.as_bytes())?)
-    } else {
-        Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid data"))
+    if str::from_utf8(buffer).is_err() {
+        return Err(Corruption::Encoding.into());
     }
+
+    K::from_bytes(trim_whitespace(buffer)).map_err(|_| Corruption::Key.into())
 }
 
 fn read_record<K, V, F>(file: &mut F, buffer: &mut [u8]) -> io::Result<(K, V)> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>>, F: Read {
-    debug_assert_eq!(buffer.len(), K::size() + 1 + V::size() + 1, "read_record was passed a buffer of the wrong size");
+    debug_assert!(buffer.len() >= K::size() + 1 + V::size() + 1, "read_record was passed a buffer of the wrong size");
 
     file.read_exact(buffer)?;
 
-    if let Ok(str_buffer) = str::from_utf8(buffer) {
-        // Parse the string into chunks
-        let mut parts = str_buffer.split_whitespace();
-
-        Ok((
-            K::from_bytes(parts.next().unwrap().as_bytes())?,    // The first chunk is the key
-            V::from_bytes(parts.next().unwrap().as_bytes())?,    // The second chunk is the value
-        ))
+    if str::from_utf8(buffer).is_ok() {
+        parse_record(buffer)
     } else {
-        Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid data"))
+        Err(Corruption::Encoding.into())
+    }
+}
+
+/// Parses a single record out of a buffer that has already been checked for valid UTF-8.
+fn parse_record<K, V>(buffer: &[u8]) -> io::Result<(K, V)> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>> {
+    // The key and value are fixed width, separated by a space.  The value may itself contain spaces.  Whatever
+    // the value and the separators don't take up is the key.
+    let key_size = buffer.len() - (1 + V::size() + 1);
+    let value_start = key_size + 1;
+
+    Ok((
+        K::from_bytes(trim_whitespace(&buffer[..key_size])).map_err(|_| io::Error::from(Corruption::Key))?,
+        V::from_bytes(trim_whitespace(&buffer[value_start..value_start + V::size()])).map_err(|_| io::Error::from(Corruption::Value))?,
+    ))
+}
+
+/// The number of records decoded per read by a RecordReader
+const RECORDS_PER_CHUNK: usize = 1024;
+
+/// Reads a run of consecutive records in large chunks, decoding a whole chunk per disk read
+/// rather than paying for a read call per record.
+pub struct RecordReader<'a, K, V, F> where F: 'a {
+    file: &'a mut F,
+    item_size: usize,
+    remaining: usize,
+    chunk: Vec<u8>,
+    chunk_offset: usize,
+    _phantom: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, F> RecordReader<'a, K, V, F> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>>, F: Read {
+    /// Prepares to read `count` records from the file's current position.
+    pub fn new(file: &'a mut F, count: usize) -> Self {
+        Self::with_key_size(file, count, K::size())
+    }
+
+    /// Prepares to read `count` records whose keys are `key_size` wide
+    pub fn with_key_size(file: &'a mut F, count: usize, key_size: usize) -> Self {
+        Self {
+            file: file,
+            item_size: key_size + 1 + V::size() + 1,
+            remaining: count,
+            chunk: Vec::new(),
+            chunk_offset: 0,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<(K, V)>> {
+        if self.chunk_offset >= self.chunk.len() {
+            if self.remaining == 0 {
+                return Ok(None);
+            }
+
+            // Never read past the last requested record
+            let chunk_records = cmp::min(self.remaining, RECORDS_PER_CHUNK);
+
+            self.chunk.resize(chunk_records * self.item_size, 0);
+            self.file.read_exact(&mut self.chunk)?;
+
+            if str::from_utf8(&self.chunk).is_err() {
+                return Err(Corruption::Encoding.into());
+            }
+
+            self.remaining -= chunk_records;
+            self.chunk_offset = 0;
+        }
+
+        let record = parse_record(&self.chunk[self.chunk_offset..self.chunk_offset + self.item_size])?;
+        self.chunk_offset += self.item_size;
+
+        Ok(Some(record))
+    }
+
+    pub fn read_all(mut self, records: &mut Vec<(K, V)>) -> io::Result<()> {
+        while let Some(record) = self.next_record()? {
+            records.push(record);
+        }
+
+        Ok(())
     }
 }
 
-fn write_record<K, V, F>(file: &mut F, key: K, value: V) -> io::Result<()>  where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>>, F: Write {
+impl<'a, K, V, F> Iterator for RecordReader<'a, K, V, F> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>>, F: Read {
+    type Item = io::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<io::Result<(K, V)>> {
+        match self.next_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(error) => {
+                // Don't keep reading after an error
+                self.remaining = 0;
+                self.chunk_offset = self.chunk.len();
+                Some(Err(error))
+            },
+        }
+    }
+}
+
+pub fn write_record<K, V, F>(file: &mut F, key: K, value: V) -> io::Result<()>  where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>>, F: Write {
+    write_record_with_key_size(file, key, value, K::size())
+}
+
+/// Writes a record whose key is right-aligned in `key_size` columns
+fn write_record_with_key_size<K, V, F>(file: &mut F, key: K, value: V, key_size: usize) -> io::Result<()>  where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>>, F: Write {
+    let key = key.into_bytes();
+    if key.len() > key_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Key is too wide for the file"));
+    }
+
     // We don't want to incur a write per part of the data
-    let mut buffer = BufWriter::with_capacity(K::size() + 1 + V::size() + 1, file);
+    let mut buffer = BufWriter::with_capacity(key_size + 1 + V::size() + 1, file);
 
     // Format the data and write it
-    buffer.write(&key.into_bytes())?;
+    for _ in key.len()..key_size {
+        buffer.write(b" ")?;
+    }
+    buffer.write(&key)?;
     buffer.write(b" ")?;
     buffer.write(&value.into_bytes())?;
     buffer.write(b"\n")?;
     buffer.flush()
 }
 
+mod compact;
+mod corruption;
+mod heartbeat;
+mod io_counter;
 mod key_value_store;
+mod lock;
+#[cfg(feature = "mmap")]
+mod mapped_file;
 mod pooled_time_series;
+#[cfg(test)]
+mod properties;
+mod read_only;
+mod reader_pool;
+mod repair;
+mod tail;
 mod time_series;
+mod unit;
+mod write_buffer;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use key_value_store::KeyValueStore;
+    use time_series::TimeSeries;
+    use util::SetupFile;
+
+    fn corruption_of<T>(result: io::Result<T>) -> Option<Corruption> {
+        result.err().and_then(|error| Corruption::of(&error))
+    }
+
+    #[test]
+    fn test_malformed_size() {
+        let _setup_file = SetupFile::new("test_malformed_size");
+        fs::write("test_malformed_size", b"0000000000001    1\n00000").unwrap();
+
+        assert_eq!(corruption_of(FileStorage::<Timestamp, i32>::new("test_malformed_size")), Some(Corruption::Size));
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        let _setup_file = SetupFile::new("test_malformed_records");
+
+        let cases: &[(&[u8], Corruption)] = &[
+            (b"0000000000001    1\n00000000x0002    2\n0000000000003    3\n", Corruption::Key),
+            (b"0000000000001    1\n                 2\n0000000000003    3\n", Corruption::Key),
+            (b"0000000000001    1\n0000000000002   x2\n0000000000003    3\n", Corruption::Value),
+            (b"0000000000001    1\n0000000000002    \xff\n0000000000003    3\n", Corruption::Encoding),
+        ];
+
+        for &(contents, corruption) in cases {
+            fs::write("test_malformed_records", contents).unwrap();
+
+            // The first and last records are fine, so the file opens, but reading through the middle one fails
+            let storage = FileStorage::<Timestamp, i32>::new("test_malformed_records").unwrap();
+            assert_eq!(storage.len(), 3);
+            assert_eq!(corruption_of(storage.retrieve_all()), Some(corruption));
+            assert_eq!(corruption_of(storage.retrieve_range(2..3)), Some(corruption));
+            assert_eq!(storage.retrieve_range(3..4).unwrap().into_vec::<Timestamp, i32>(), vec![(3, 3)]);
+        }
+
+        fs::write("test_malformed_records", b"000000000000x    1\n0000000000002    2\n").unwrap();
+        assert_eq!(corruption_of(FileStorage::<Timestamp, i32>::new("test_malformed_records")), Some(Corruption::Key));
+
+        fs::write("test_malformed_records", b"0000000000001    1\n\xff000000000002    2\n").unwrap();
+        assert_eq!(corruption_of(FileStorage::<Timestamp, i32>::new("test_malformed_records")), Some(Corruption::Encoding));
+    }
+
+    #[test]
+    fn test_locking() {
+        let _setup_file = SetupFile::new("test_locking");
+
+        assert_eq!(FileStorage::<Timestamp, i32>::read_only("test_locking").err().map(|error| error.kind()), Some(io::ErrorKind::NotFound));
+
+        let mut writer = FileStorage::<Timestamp, i32>::new("test_locking").unwrap();
+        writer.store(Box::new(1 as Timestamp), Box::new(1 as i32)).unwrap();
+
+        // Locks are per open file, so a second open in the same process contends like another process would
+        let error = FileStorage::<Timestamp, i32>::new("test_locking").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(Locked::of(&error).map(|locked| locked.mode), Some(OpenMode::ReadWrite));
+        assert_eq!(FileStorage::<Timestamp, i32>::read_only("test_locking").err().as_ref().and_then(Locked::of).map(|locked| locked.mode), Some(OpenMode::ReadOnly));
+
+        drop(writer);
+
+        let mut reader = FileStorage::<Timestamp, i32>::read_only("test_locking").unwrap();
+        let other_reader = FileStorage::<Timestamp, i32>::read_only("test_locking").unwrap();
+        assert_eq!(other_reader.mode(), OpenMode::ReadOnly);
+        assert_eq!(reader.retrieve_all().unwrap().into_vec::<Timestamp, i32>(), vec![(1, 1)]);
+        assert_eq!(reader.store(Box::new(2 as Timestamp), Box::new(2 as i32)).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(FileStorage::<Timestamp, i32>::new("test_locking").is_err());
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let _setup_file = SetupFile::new("test_open_read_only");
+
+        assert_eq!(FileStorage::<Timestamp, i32>::open_read_only("test_open_read_only").err().map(|error| error.kind()), Some(io::ErrorKind::NotFound));
+        assert!(fs::metadata("test_open_read_only").is_err());
+
+        let mut writer = FileStorage::<Timestamp, i32>::new("test_open_read_only").unwrap();
+        writer.store(Box::new(1 as Timestamp), Box::new(1 as i32)).unwrap();
+        writer.store(Box::new(2 as Timestamp), Box::new(2 as i32)).unwrap();
+
+        // A record the writer is partway through appending
+        OpenOptions::new().append(true).open("test_open_read_only").unwrap().write_all(b"000000000").unwrap();
+
+        let reader = FileStorage::<Timestamp, i32>::open_read_only("test_open_read_only").unwrap();
+        assert_eq!(reader.mode(), OpenMode::Concurrent);
+        assert_eq!(reader.len(), 2);
+        assert_eq!(reader.retrieve_all().unwrap().into_vec::<Timestamp, i32>(), vec![(1, 1), (2, 2)]);
+        assert_eq!(reader.retrieve_nearest(5, Some(RetrievalDirection::Backward)).unwrap().into_single::<Timestamp, i32>(), (2, 2));
+
+        // Readers that take a lock still can't open it, and neither can a second writer
+        assert!(FileStorage::<Timestamp, i32>::read_only("test_open_read_only").is_err());
+        assert!(FileStorage::<Timestamp, i32>::new("test_open_read_only").is_err());
+    }
+
+    #[test]
+    fn test_unit() {
+        let _setup_file = SetupFile::new("test_unit");
+
+        drop(FileStorage::<Timestamp, i32>::with_unit("test_unit", TimeUnit::Microseconds).unwrap());
+        assert_eq!(fs::read_to_string("test_unit.unit").unwrap(), "us");
+
+        // An empty file is still in microseconds, so it can't be read as milliseconds
+        assert_eq!(FileStorage::<Timestamp, i32>::read_only("test_unit").err().map(|error| error.kind()), Some(io::ErrorKind::InvalidData));
+
+        {
+            let mut storage = FileStorage::<Timestamp, i32>::with_unit("test_unit", TimeUnit::Microseconds).unwrap();
+
+            // 25 microsecond records are as long as 22 nanosecond ones
+            for timestamp in 1..26 {
+                storage.store(Box::new(timestamp as Timestamp), Box::new(timestamp as i32)).unwrap();
+            }
+        }
+
+        assert_eq!(FileStorage::<Timestamp, i32>::with_unit("test_unit", TimeUnit::Nanoseconds).err().map(|error| error.kind()), Some(io::ErrorKind::InvalidData));
+        assert_eq!(FileStorage::<Timestamp, i32>::with_unit("test_unit", TimeUnit::Microseconds).unwrap().len(), 25);
+
+        // Files written before units were recorded are checked against the layout of their first record instead
+        fs::remove_file("test_unit.unit").unwrap();
+        assert_eq!(FileStorage::<Timestamp, i32>::read_only_with_unit("test_unit", TimeUnit::Nanoseconds).err().map(|error| error.kind()), Some(io::ErrorKind::InvalidData));
+        assert!(fs::metadata("test_unit.unit").is_err());
+        assert_eq!(FileStorage::<Timestamp, i32>::with_unit("test_unit", TimeUnit::Microseconds).unwrap().len(), 25);
+        assert_eq!(fs::read_to_string("test_unit.unit").unwrap(), "us");
+    }
+}
@@ -13,247 +13,225 @@
 // You should have received a copy of the GNU General Public License
 // along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::fs::File;
-use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::ops::Range;
 
 use key_value_store::{Retrieval, Storable};
-use pooled_time_series::{GapFillMethod, Poolable, PooledTimeSeries, PoolingMethod, PoolingOptions};
-use storage::file::{FileStorage, read_record};
-use time_series::{TimeSeries, Timestamp};
+use pooled_time_series::{BucketAnchor, Interval, Poolable, PooledTimeSeries, PoolingOptions, Summary, pool_records, summarize_records};
+use storage::file::{binary_search_for_key, CountedFile, FileStorage, RecordReader};
+use time_series::{RetrievalDirection, Timestamp};
 
 impl<V> PooledTimeSeries for FileStorage<Timestamp, V> where V: Storable<FileStorage<Timestamp, V>> + Poolable {
     fn pool_all(&self, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
-        // Reset the file to the beginning
-        self.file.borrow_mut().seek(SeekFrom::Start(0))?;
-
-        // Buffer the file to reduce the number of disk reads
-        let file = &mut *self.file.borrow_mut();
-        let mut file_buffer = BufReader::new(file);
+        let file = &mut *self.reader()?;
 
-        // Scratch buffer into which we'll read new records for parsing
-        let mut read_buffer = vec![0u8; self.item_size];
+        // Reset the file to the beginning
+        file.seek(SeekFrom::Start(0))?;
 
         // Gather all buckets between the beginning and end of the file
-        let values = gather_buckets::<V, BufReader<&mut File>>(
-            &mut file_buffer,
-            &mut read_buffer,
+        let values = gather_buckets::<V, CountedFile>(
+            file,
+            self.key_size,
             pooling_options,
             self.first_key,
             0,
             self.end_offset,
+            None,
         )?;
 
         Ok(Retrieval::new(Box::new(values)))
     }
 
     fn pool_from(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
-        let (from_timestamp, from_offset) = self.find_from(timestamp)?;
-        self.file.borrow_mut().seek(SeekFrom::Start(from_offset))?;
-
-        // Buffer the file to reduce the number of disk reads
-        let file = &mut *self.file.borrow_mut();
-        let mut file_buffer = BufReader::new(file);
+        let file = &mut *self.reader()?;
 
-        // Scratch buffer into which we'll read new records for parsing
-        let mut read_buffer = vec![0u8; self.item_size];
+        let (from_timestamp, from_offset) = self.find_from(file, timestamp)?;
+        let from_timestamp = anchor_timestamp(from_timestamp, timestamp, pooling_options);
+        file.seek(SeekFrom::Start(from_offset))?;
 
         // Gather all buckets between the beginning and end of the file
-        let values = gather_buckets::<V, BufReader<&mut File>>(
-            &mut file_buffer,
-            &mut read_buffer,
+        let values = gather_buckets::<V, CountedFile>(
+            file,
+            self.key_size,
             pooling_options,
             from_timestamp,
             from_offset,
             self.end_offset,
+            None,
         )?;
 
         Ok(Retrieval::new(Box::new(values)))
     }
 
     fn pool_to(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
-        let to_offset = match self.find_to(timestamp) {
+        let file = &mut *self.reader()?;
+
+        let to_offset = match self.find_to(file, timestamp) {
             Ok(offset) => offset,
-            Err(error) => return if error.kind() == io::ErrorKind::InvalidInput && format!("{}", error) == "find_to search key was equal to the first record" {
+            Err(error) => return if error.kind() == io::ErrorKind::InvalidInput && format!("{}", error) == "find_to search key was equal to the first record" || error.kind() == io::ErrorKind::NotFound {
                 Ok(Retrieval::new(Box::new(Vec::<(Timestamp, V)>::new())))
             } else {
                 Err(error)
             },
         };
 
-        self.file.borrow_mut().seek(SeekFrom::Start(0))?;
-
-        // Buffer the file to reduce the number of disk reads
-        let file = &mut *self.file.borrow_mut();
-        let mut file_buffer = BufReader::new(file);
-
-        // Scratch buffer into which we'll read new records for parsing
-        let mut read_buffer = vec![0u8; self.item_size];
+        file.seek(SeekFrom::Start(0))?;
 
         // Gather all buckets between the beginning and end of the file
-        let values = gather_buckets::<V, BufReader<&mut File>>(
-            &mut file_buffer,
-            &mut read_buffer,
+        let values = gather_buckets::<V, CountedFile>(
+            file,
+            self.key_size,
             pooling_options,
             self.first_key,
             0,
             to_offset,
+            Some(timestamp),
         )?;
 
         Ok(Retrieval::new(Box::new(values)))
     }
 
     fn pool_range(&self, range: Range<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
-        let (from_timestamp, from_offset) = self.find_from(range.start)?;
+        let file = &mut *self.reader()?;
+
+        let (from_timestamp, from_offset) = self.find_from(file, range.start)?;
+        let from_timestamp = anchor_timestamp(from_timestamp, range.start, pooling_options);
 
-        let to_offset = match self.find_to(range.end) {
+        let to_offset = match self.find_to(file, range.end) {
             Ok(offset) => offset,
-            Err(error) => return if error.kind() == io::ErrorKind::InvalidInput && format!("{}", error) == "find_to search key was equal to the first record" {
+            Err(error) => return if error.kind() == io::ErrorKind::InvalidInput && format!("{}", error) == "find_to search key was equal to the first record" || error.kind() == io::ErrorKind::NotFound {
                 Ok(Retrieval::new(Box::new(Vec::<(Timestamp, V)>::new())))
             } else {
                 Err(error)
             },
         };
 
-        // Since the range is exclusive of the end, if the from and to offsets are the same record, there are no records to return.
-        // Also no records to return if the from is after the to, obviously.
-        if (to_offset as i64 - from_offset as i64) < self.item_size as i64 {
+        // No records to return if the from is after the to.  If they're the same record, it's either in the range
+        // or only carried forward into it, which gather_buckets sorts out.
+        if to_offset < from_offset {
             return Ok(Retrieval::new(Box::new(Vec::<(Timestamp, V)>::new())));
         }
 
-        self.file.borrow_mut().seek(SeekFrom::Start(from_offset))?;
-
-        // Buffer the file to reduce the number of disk reads
-        let file = &mut *self.file.borrow_mut();
-        let mut file_buffer = BufReader::new(file);
-
-        // Scratch buffer into which we'll read new records for parsing
-        let mut read_buffer = vec![0u8; self.item_size];
+        file.seek(SeekFrom::Start(from_offset))?;
 
         // Gather all buckets between the beginning and end of the file
-        let values = gather_buckets::<V, BufReader<&mut File>>(
-            &mut file_buffer,
-            &mut read_buffer,
+        let values = gather_buckets::<V, CountedFile>(
+            file,
+            self.key_size,
             pooling_options,
             from_timestamp,
             from_offset,
             to_offset,
+            Some(range.end),
         )?;
 
         Ok(Retrieval::new(Box::new(values)))
     }
 
-    fn as_time_series(&self) -> &dyn TimeSeries {
-        self
-    }
+    /// Reads the range's records once, and pools them at each interval in memory
+    fn pool_multi(&self, range: Range<Timestamp>, intervals: &[Interval], pooling_options: PoolingOptions) -> io::Result<Vec<Retrieval>> {
+        let empty = || intervals.iter().map(|_| Retrieval::new(Box::new(Vec::<(Timestamp, V)>::new()))).collect();
 
-    fn as_mut_time_series(&mut self) -> &mut dyn TimeSeries {
-        self
-    }
-}
+        let file = &mut *self.reader()?;
 
-fn gather_buckets<V, F>(
-    file: &mut F,
-    buffer: &mut [u8],
-    pooling_options: PoolingOptions,
-    start_time: Timestamp,
-    start_offset: u64,
-    end_offset: u64,
-) -> io::Result<Vec<(Timestamp, V)>> where V: Storable<FileStorage<Timestamp, V>> + Poolable, F: Read {
-    let mut values: Vec<(Timestamp, V)> = Vec::new();
+        let (from_timestamp, from_offset) = self.find_from(file, range.start)?;
+        let from_timestamp = anchor_timestamp(from_timestamp, range.start, pooling_options);
+
+        let to_offset = match self.find_to(file, range.end) {
+            Ok(offset) => offset,
+            Err(error) => return if error.kind() == io::ErrorKind::InvalidInput && format!("{}", error) == "find_to search key was equal to the first record" || error.kind() == io::ErrorKind::NotFound {
+                Ok(empty())
+            } else {
+                Err(error)
+            },
+        };
+
+        if to_offset < from_offset {
+            return Ok(empty());
+        }
+
+        file.seek(SeekFrom::Start(from_offset))?;
 
-    let record_count = (end_offset - start_offset) / (<Timestamp as Storable<FileStorage<Timestamp, V>>>::size() + 1 + V::size() + 1) as u64 + 1;
+        let count = ((to_offset - from_offset) / self.item_size as u64 + 1) as usize;
 
-    struct Bucket<V> {
-        pub records: Vec<(Timestamp, V)>,
-        pub start: Timestamp,
-        pub end: Timestamp,
+        let mut records = Vec::with_capacity(count);
+        RecordReader::<Timestamp, V, CountedFile>::with_key_size(file, count, self.key_size).read_all(&mut records)?;
+
+        intervals.iter().map(|&interval| {
+            let pooling_options = PoolingOptions { interval: interval, ..pooling_options };
+            let values = pool_records(records.iter().cloned().map(Ok), from_timestamp, Some(range.end), pooling_options)?;
+            Ok(Retrieval::new(Box::new(values)))
+        }).collect()
     }
 
-    let first_record = read_record::<Timestamp, V, F>(file, buffer)?;
-
-    // Start off the first bucket with the first record if it belongs there
-    let mut bucket = Bucket {
-        records: if first_record.0 == start_time {
-            vec![first_record]
-        } else {
-            Vec::new()
-        },
-        start: start_time,
-        end: start_time + pooling_options.interval,
-    };
-
-    // Add the final bucket value onto the list, depending on the type of pooling
-    fn conclude_bucket<V>(
-        bucket: &Bucket<V>,
-        values: &mut Vec<(Timestamp, V)>,
-        last_record: (Timestamp, V),
-        pooling_options: PoolingOptions
-    ) where V: Poolable {
-        if !bucket.records.is_empty() {
-            values.push((bucket.start, match pooling_options.pooling {
-                PoolingMethod::End => bucket.records.last().unwrap().1,
-                PoolingMethod::High => bucket.records.iter().max_by_key(|r| r.1).unwrap().1,
-                PoolingMethod::Low => bucket.records.iter().min_by_key(|r| r.1).unwrap().1,
-                PoolingMethod::Mean => V::mean(&bucket.records.iter().map(|r| r.1).collect::<Vec<V>>()),
-                PoolingMethod::Start => if bucket.records.first().unwrap().0 == bucket.start || pooling_options.gap_fill == Some(GapFillMethod::Default) {
-                    bucket.records.first().unwrap().1
-                } else {
-                    last_record.1
-                },
-                PoolingMethod::Sum => V::sum(&bucket.records.iter().map(|r| r.1).collect::<Vec<V>>()),
-            }));
-        } else if let Some(gap_fill_method) = pooling_options.gap_fill {
-            let value = match gap_fill_method {
-                GapFillMethod::Default => V::default(),
-                GapFillMethod::Previous => last_record.1,
-            };
-
-            values.push((bucket.start, value));
+    fn summarize_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval> {
+        if self.items == 0 || range.start >= range.end || range.start > self.last_key {
+            return Ok(Retrieval::new(Box::new(None::<Summary<V>>)));
         }
-    }
 
-    let mut last_record = first_record;
+        let file = &mut *self.reader()?;
 
-    // For the rest of the records
-    for _ in 1..record_count {
-        let record = read_record::<Timestamp, V, F>(file, buffer)?;
+        let from_offset = {
+            let mut read_buffer = vec![0u8; self.key_size];
+            binary_search_for_key::<Timestamp, V, CountedFile>(file, &mut read_buffer, Some(RetrievalDirection::Forward), range.start, 0, self.end_offset)?
+        };
 
-        // If the record we just read doesn't fit in this bucket,
-        if record.0 >= bucket.end {
-            // end the current bucket and start new ones until the record fits.
-            conclude_bucket(&bucket, &mut values, last_record, pooling_options);
+        let to_offset = match self.find_to(file, range.end) {
+            Ok(offset) => offset,
+            Err(error) => return if error.kind() == io::ErrorKind::InvalidInput || error.kind() == io::ErrorKind::NotFound {
+                Ok(Retrieval::new(Box::new(None::<Summary<V>>)))
+            } else {
+                Err(error)
+            },
+        };
 
-            if !bucket.records.is_empty() {
-                last_record = *bucket.records.last().unwrap();
+        if to_offset < from_offset {
+            return Ok(Retrieval::new(Box::new(None::<Summary<V>>)));
+        }
 
-                bucket.records.clear();
-            }
+        file.seek(SeekFrom::Start(from_offset))?;
 
-            bucket.start = bucket.end;
-            bucket.end += pooling_options.interval;
+        let count = ((to_offset - from_offset) / self.item_size as u64 + 1) as usize;
 
-            while bucket.end <= record.0 {
-                conclude_bucket(&bucket, &mut values, last_record, pooling_options);
+        // The records stream through the reader's chunks, so only one chunk is ever held
+        let reader = RecordReader::<Timestamp, V, CountedFile>::with_key_size(file, count, self.key_size);
 
-                bucket.start = bucket.end;
-                bucket.end += pooling_options.interval;
-            }
-        }
+        Ok(Retrieval::new(Box::new(summarize_records(reader)?)))
+    }
+}
 
-        bucket.records.push(record);
+/// Chooses where the first bucket starts, given where the records start and where the caller asked to start
+fn anchor_timestamp(from_timestamp: Timestamp, requested: Timestamp, pooling_options: PoolingOptions) -> Timestamp {
+    match pooling_options.anchor {
+        BucketAnchor::FirstRecord => from_timestamp,
+        BucketAnchor::RequestedStart => requested,
     }
+}
+
+fn gather_buckets<V, F>(
+    file: &mut F,
+    key_size: usize,
+    pooling_options: PoolingOptions,
+    start_time: Timestamp,
+    start_offset: u64,
+    end_offset: u64,
+    range_end: Option<Timestamp>,
+) -> io::Result<Vec<(Timestamp, V)>> where V: Storable<FileStorage<Timestamp, V>> + Poolable, F: Read {
+    let record_count = (end_offset - start_offset) / (key_size + 1 + V::size() + 1) as u64 + 1;
 
-    conclude_bucket(&bucket, &mut values, last_record, pooling_options);
+    // Read the records in large chunks to reduce the number of disk reads
+    let reader = RecordReader::<Timestamp, V, F>::with_key_size(file, record_count as usize, key_size);
 
-    Ok(values)
+    pool_records(reader, start_time, range_end, pooling_options)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use calendar::{CalendarInterval, HolidayCalendar, HolidayPolicy, TimeZone};
     use key_value_store::KeyValueStore;
+    use pooled_time_series::{GapFillMethod, OpenBucket, PoolingMethod};
     use util::SetupFile;
 
     #[test]
@@ -268,19 +246,46 @@ mod tests {
         fs.store(Box::new(20 as Timestamp), Box::new(4 as i32)).unwrap();
         fs.store(Box::new(26 as Timestamp), Box::new(5 as i32)).unwrap();
 
-        let pooling_options = PoolingOptions { interval: 3, pooling: PoolingMethod::Start, gap_fill: Some(GapFillMethod::Previous) };
+        let pooling_options = PoolingOptions { interval: Interval::millis(3), pooling: PoolingMethod::Start, gap_fill: Some(GapFillMethod::Previous), ..PoolingOptions::default() };
         let retrieval = fs.pool_all(pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (13, 1), (16, 3), (19, 3), (22, 4), (25, 4)]));
 
-        let pooling_options = PoolingOptions { interval: 3, pooling: PoolingMethod::Start, gap_fill: Some(GapFillMethod::Default) };
+        let pooling_options = PoolingOptions { interval: Interval::millis(3), pooling: PoolingMethod::Start, gap_fill: Some(GapFillMethod::Default), ..PoolingOptions::default() };
         let retrieval = fs.pool_all(pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (13, 2), (16, 0), (19, 4), (22, 0), (25, 5)]));
 
-        let pooling_options = PoolingOptions { interval: 3, pooling: PoolingMethod::Start, gap_fill: None };
+        let pooling_options = PoolingOptions { interval: Interval::millis(3), pooling: PoolingMethod::Start, gap_fill: None, ..PoolingOptions::default() };
         let retrieval = fs.pool_all(pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (13, 1), (19, 3), (25, 4)]));
     }
 
+    #[test]
+    fn test_summarize_range() {
+        let _setup_file = SetupFile::new("test_summarize_range");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_summarize_range").unwrap();
+
+        assert_eq!(fs.summarize_range(0..100).unwrap().into_summary::<i32>(), None);
+
+        fs.store(Box::new(10 as Timestamp), Box::new(5 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(-3 as i32)).unwrap();
+        fs.store(Box::new(30 as Timestamp), Box::new(8 as i32)).unwrap();
+        fs.store(Box::new(40 as Timestamp), Box::new(2 as i32)).unwrap();
+
+        assert_eq!(fs.summarize_range(15..40).unwrap().into_summary::<i32>(), Some(Summary {
+            count: 2,
+            min: -3,
+            max: 8,
+            mean: 2,
+            sum: 5,
+            first: (20, -3),
+            last: (30, 8),
+        }));
+        assert_eq!(fs.summarize_range(0..100).unwrap().into_summary::<i32>().map(|summary| (summary.count, summary.sum)), Some((4, 12)));
+        assert_eq!(fs.summarize_range(0..10).unwrap().into_summary::<i32>(), None);
+        assert_eq!(fs.summarize_range(41..100).unwrap().into_summary::<i32>(), None);
+    }
+
     #[test]
     fn test_pool_all() {
         let _setup_file = SetupFile::new("test_pool_all");
@@ -291,7 +296,7 @@ mod tests {
         fs.store(Box::new(2 as Timestamp), Box::new(2 as i32)).unwrap();
         fs.store(Box::new(3 as Timestamp), Box::new(3 as i32)).unwrap();
 
-        let pooling_options = PoolingOptions { interval: 1, ..PoolingOptions::default() };
+        let pooling_options = PoolingOptions { interval: Interval::millis(1), ..PoolingOptions::default() };
         let retrieval = fs.pool_all(pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(1, 1), (2, 2), (3, 3)]));
     }
@@ -307,12 +312,132 @@ mod tests {
         fs.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
         fs.store(Box::new(40 as Timestamp), Box::new(4 as i32)).unwrap();
 
-        let pooling_options = PoolingOptions { interval: 10, ..PoolingOptions::default() };
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), ..PoolingOptions::default() };
         let retrieval = fs.pool_from(17, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(17, 2), (27, 3), (37, 4)]));
 
         let retrieval = fs.pool_from(7, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2), (30, 3), (40, 4)]));
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), anchor: BucketAnchor::RequestedStart, ..PoolingOptions::default() };
+        let retrieval = fs.pool_from(7, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(7, 1), (17, 2), (27, 3), (37, 4)]));
+    }
+
+    #[test]
+    fn test_calendar_buckets() {
+        let _setup_file = SetupFile::new("test_calendar_buckets");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_calendar_buckets").unwrap();
+
+        // Local midnight is 05:00 UTC, so the third record is still on the first local day
+        const DAY: Timestamp = 86_400_000;
+        const HOUR: Timestamp = 3_600_000;
+        fs.store(Box::new(DAY + 6 * HOUR), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(DAY + 20 * HOUR), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(2 * DAY + 4 * HOUR), Box::new(3 as i32)).unwrap();
+        fs.store(Box::new(2 * DAY + 6 * HOUR), Box::new(4 as i32)).unwrap();
+
+        let tz: &'static TimeZone = Box::leak(Box::new(TimeZone::fixed("Test/Minus5", -5 * 3600)));
+        let pooling_options = PoolingOptions { pooling: PoolingMethod::Sum, calendar: Some(CalendarInterval::Day { tz: tz }), ..PoolingOptions::default() };
+
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(DAY + 5 * HOUR, 6), (2 * DAY + 5 * HOUR, 4)]));
+
+        let retrieval = fs.pool_bucket(DAY + 5 * HOUR, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(DAY + 5 * HOUR, 6)]));
+
+        let pooling_options = PoolingOptions { open_bucket: OpenBucket::Exclude, ..pooling_options };
+        let retrieval = fs.pool_range(DAY..3 * DAY, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(DAY + 5 * HOUR, 6)]));
+    }
+
+    #[test]
+    fn test_holiday_buckets() {
+        let _setup_file = SetupFile::new("test_holiday_buckets");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_holiday_buckets").unwrap();
+
+        // One record on each of the local days 1970-01-02, -03, and -04
+        const DAY: Timestamp = 86_400_000;
+        const HOUR: Timestamp = 3_600_000;
+        fs.store(Box::new(DAY + 6 * HOUR), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(2 * DAY + 6 * HOUR), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(3 * DAY + 6 * HOUR), Box::new(3 as i32)).unwrap();
+
+        let tz: &'static TimeZone = Box::leak(Box::new(TimeZone::fixed("Test/Minus5", -5 * 3600)));
+        let holidays: &'static HolidayCalendar = Box::leak(Box::new(HolidayCalendar::parse("Test/Holidays", "1970-01-03").unwrap()));
+        let pooling_options = PoolingOptions {
+            pooling: PoolingMethod::Sum,
+            calendar: Some(CalendarInterval::Day { tz: tz }),
+            holidays: Some((holidays, HolidayPolicy::Skip)),
+            ..PoolingOptions::default()
+        };
+
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(DAY + 5 * HOUR, 1), (3 * DAY + 5 * HOUR, 3)]));
+
+        let retrieval = fs.pool_from(2 * DAY + 7 * HOUR, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(3 * DAY + 5 * HOUR, 3)]));
+
+        let pooling_options = PoolingOptions { holidays: Some((holidays, HolidayPolicy::Merge)), ..pooling_options };
+        let retrieval = fs.pool_all(pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(DAY + 5 * HOUR, 3), (3 * DAY + 5 * HOUR, 3)]));
+    }
+
+    #[test]
+    fn test_open_bucket() {
+        let _setup_file = SetupFile::new("test_open_bucket");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_open_bucket").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
+        fs.store(Box::new(35 as Timestamp), Box::new(4 as i32)).unwrap();
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), open_bucket: OpenBucket::Exclude, ..PoolingOptions::default() };
+
+        // Without an end to the range, the last bucket is always open
+        let retrieval = fs.pool_from(10, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2)]));
+
+        let retrieval = fs.pool_range(10..40, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2), (30, 4)]));
+
+        let retrieval = fs.pool_range(10..38, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2)]));
+
+        // Re-requesting a bucket returns it even if it's open
+        let retrieval = fs.pool_bucket(30, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(30, 4)]));
+
+        let retrieval = fs.pool_bucket(20, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(20, 2)]));
+    }
+
+    #[test]
+    fn test_bucket_anchor() {
+        let _setup_file = SetupFile::new("test_bucket_anchor");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_bucket_anchor").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(5), gap_fill: Some(GapFillMethod::Previous), ..PoolingOptions::default() };
+        let retrieval = fs.pool_range(0..22, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (15, 1), (20, 2)]));
+
+        // Leading buckets are filled with the default value, even when filling with the previous value
+        let pooling_options = PoolingOptions { anchor: BucketAnchor::RequestedStart, ..pooling_options };
+        let retrieval = fs.pool_range(0..22, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(0, 0), (5, 0), (10, 1), (15, 1), (20, 2)]));
+
+        // Without gap filling, the leading buckets are left out but the anchor is kept
+        let pooling_options = PoolingOptions { gap_fill: None, ..pooling_options };
+        let retrieval = fs.pool_range(2..22, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(7, 1), (17, 2)]));
     }
 
     #[test]
@@ -329,27 +454,27 @@ mod tests {
         fs.store(Box::new(21 as Timestamp), Box::new(6 as i32)).unwrap();
         fs.store(Box::new(26 as Timestamp), Box::new(7 as i32)).unwrap();
 
-        let pooling_options = PoolingOptions { interval: 3, pooling: PoolingMethod::End, gap_fill: Some(GapFillMethod::Previous) };
+        let pooling_options = PoolingOptions { interval: Interval::millis(3), pooling: PoolingMethod::End, gap_fill: Some(GapFillMethod::Previous), ..PoolingOptions::default() };
         let retrieval = fs.pool_from(12, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(12, 2), (15, 3), (18, 4), (21, 6), (24, 7)]));
 
-        let pooling_options = PoolingOptions { interval: 3, pooling: PoolingMethod::High, gap_fill: Some(GapFillMethod::Previous) };
+        let pooling_options = PoolingOptions { interval: Interval::millis(3), pooling: PoolingMethod::High, gap_fill: Some(GapFillMethod::Previous), ..PoolingOptions::default() };
         let retrieval = fs.pool_from(12, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(12, 2), (15, 3), (18, 5), (21, 6), (24, 7)]));
 
-        let pooling_options = PoolingOptions { interval: 3, pooling: PoolingMethod::Low, gap_fill: Some(GapFillMethod::Previous) };
+        let pooling_options = PoolingOptions { interval: Interval::millis(3), pooling: PoolingMethod::Low, gap_fill: Some(GapFillMethod::Previous), ..PoolingOptions::default() };
         let retrieval = fs.pool_from(12, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(12, 2), (15, 3), (18, 4), (21, 6), (24, 7)]));
 
-        let pooling_options = PoolingOptions { interval: 3, pooling: PoolingMethod::Mean, gap_fill: Some(GapFillMethod::Previous) };
+        let pooling_options = PoolingOptions { interval: Interval::millis(3), pooling: PoolingMethod::Mean, gap_fill: Some(GapFillMethod::Previous), ..PoolingOptions::default() };
         let retrieval = fs.pool_from(12, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(12, 2), (15, 3), (18, 4), (21, 6), (24, 7)]));
 
-        let pooling_options = PoolingOptions { interval: 3, pooling: PoolingMethod::Start, gap_fill: Some(GapFillMethod::Previous) };
+        let pooling_options = PoolingOptions { interval: Interval::millis(3), pooling: PoolingMethod::Start, gap_fill: Some(GapFillMethod::Previous), ..PoolingOptions::default() };
         let retrieval = fs.pool_from(12, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(12, 1), (15, 3), (18, 3), (21, 6), (24, 6)]));
 
-        let pooling_options = PoolingOptions { interval: 3, pooling: PoolingMethod::Sum, gap_fill: Some(GapFillMethod::Previous) };
+        let pooling_options = PoolingOptions { interval: Interval::millis(3), pooling: PoolingMethod::Sum, gap_fill: Some(GapFillMethod::Previous), ..PoolingOptions::default() };
         let retrieval = fs.pool_from(12, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(12, 2), (15, 3), (18, 9), (21, 6), (24, 7)]));
     }
@@ -365,7 +490,7 @@ mod tests {
         fs.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
         fs.store(Box::new(40 as Timestamp), Box::new(4 as i32)).unwrap();
 
-        let pooling_options = PoolingOptions { interval: 10, ..PoolingOptions::default() };
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), ..PoolingOptions::default() };
         let retrieval = fs.pool_range(10..33, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2), (30, 3)]));
 
@@ -376,6 +501,31 @@ mod tests {
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2), (30, 3), (40, 4)]));
     }
 
+    #[test]
+    fn test_pool_multi() {
+        let _setup_file = SetupFile::new("test_pool_multi");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_pool_multi").unwrap();
+
+        for timestamp in 10..40 {
+            fs.store(Box::new(timestamp as Timestamp), Box::new(timestamp as i32)).unwrap();
+        }
+
+        let intervals = [Interval::millis(1), Interval::millis(5), Interval::millis(20)];
+        let pooling_options = PoolingOptions { pooling: PoolingMethod::Sum, anchor: BucketAnchor::RequestedStart, ..PoolingOptions::default() };
+
+        // Each interval pools as it would alone, including past the last record
+        for range in &[12..37, 41..50] {
+            let retrievals = fs.pool_multi(range.clone(), &intervals, pooling_options).unwrap();
+            assert_eq!(retrievals.len(), 3);
+
+            for (retrieval, &interval) in retrievals.into_iter().zip(&intervals) {
+                let pooling_options = PoolingOptions { interval: interval, ..pooling_options };
+                assert_eq!(retrieval.into_vec::<Timestamp, i32>(), fs.pool_range(range.clone(), pooling_options).unwrap().into_vec::<Timestamp, i32>());
+            }
+        }
+    }
+
     #[test]
     fn test_retrieve_range_is_exclusive() {
         let _setup_file = SetupFile::new("test_pool_range_is_exclusive");
@@ -387,7 +537,7 @@ mod tests {
         fs.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
         fs.store(Box::new(40 as Timestamp), Box::new(4 as i32)).unwrap();
 
-        let pooling_options = PoolingOptions { interval: 10, ..PoolingOptions::default() };
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), ..PoolingOptions::default() };
         let retrieval = fs.pool_range(10..30, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2)]));
 
@@ -406,7 +556,7 @@ mod tests {
         fs.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
         fs.store(Box::new(40 as Timestamp), Box::new(4 as i32)).unwrap();
 
-        let pooling_options = PoolingOptions { interval: 10, ..PoolingOptions::default() };
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), ..PoolingOptions::default() };
         let retrieval = fs.pool_to(33, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2), (30, 3)]));
     }
@@ -422,7 +572,7 @@ mod tests {
         fs.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
         fs.store(Box::new(40 as Timestamp), Box::new(4 as i32)).unwrap();
 
-        let pooling_options = PoolingOptions { interval: 10, ..PoolingOptions::default() };
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), ..PoolingOptions::default() };
         let retrieval = fs.pool_to(30, pooling_options).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2)]));
 
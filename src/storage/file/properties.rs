@@ -0,0 +1,158 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Checks `FileStorage` against a sorted vector of records on random sequences of stores, retrievals, and pools.
+//!
+//! The model answers every read by filtering or bucketing the whole vector, so it shares none of the storage's
+//! seeking, searching, or bucket boundary math.
+
+use proptest::prelude::*;
+
+use key_value_store::KeyValueStore;
+use pooled_time_series::{BucketAnchor, Interval, PooledTimeSeries, PoolingMethod, PoolingOptions, pool_values};
+use storage::file::FileStorage;
+use time_series::{TimeSeries, Timestamp};
+use util::SetupFile;
+
+/// What `FileStorage` should hold after the same stores
+#[derive(Default)]
+struct Model {
+    records: Vec<(Timestamp, i32)>,
+}
+
+impl Model {
+    /// Keys have to increase, as they do for the storage
+    fn store(&mut self, key: Timestamp, value: i32) -> bool {
+        if self.records.last().map_or(false, |last| key <= last.0) {
+            return false;
+        }
+
+        self.records.push((key, value));
+        true
+    }
+
+    fn retrieve(&self, keep: impl Fn(Timestamp) -> bool) -> Vec<(Timestamp, i32)> {
+        self.records.iter().cloned().filter(|record| keep(record.0)).collect()
+    }
+
+    /// Buckets start at the start of the range, and only the records inside the range are pooled
+    fn pool_range(&self, start: Timestamp, end: Timestamp, interval: Timestamp, pooling: PoolingMethod) -> Vec<(Timestamp, i32)> {
+        let mut buckets = Vec::new();
+
+        let mut bucket_start = start;
+        while bucket_start < end {
+            let values = self.retrieve(|key| key >= bucket_start && key < end && key < bucket_start + interval)
+                .into_iter()
+                .map(|record| record.1)
+                .collect::<Vec<i32>>();
+
+            if !values.is_empty() {
+                buckets.push((bucket_start, pool_values(&values, values[0], pooling)));
+            }
+
+            bucket_start += interval;
+        }
+
+        buckets
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Operation {
+    Store(Timestamp, i32),
+    RetrieveAll,
+    RetrieveFrom(Timestamp),
+    RetrieveTo(Timestamp),
+    RetrieveRange(Timestamp, Timestamp),
+    PoolRange(Timestamp, Timestamp, Timestamp, PoolingMethod),
+}
+
+fn timestamp() -> impl Strategy<Value = Timestamp> {
+    0..1_000 as Timestamp
+}
+
+fn pooling_method() -> impl Strategy<Value = PoolingMethod> {
+    prop_oneof![
+        Just(PoolingMethod::End),
+        Just(PoolingMethod::High),
+        Just(PoolingMethod::Low),
+        Just(PoolingMethod::Mean),
+        Just(PoolingMethod::Sum),
+    ]
+}
+
+/// Values fit the four columns of the test storage's `i32`
+fn operation() -> impl Strategy<Value = Operation> {
+    prop_oneof![
+        4 => (timestamp(), -999..1_000i32).prop_map(|(key, value)| Operation::Store(key, value)),
+        1 => Just(Operation::RetrieveAll),
+        1 => timestamp().prop_map(Operation::RetrieveFrom),
+        1 => timestamp().prop_map(Operation::RetrieveTo),
+        2 => (timestamp(), timestamp()).prop_map(|(a, b)| Operation::RetrieveRange(a.min(b), a.max(b))),
+        2 => (timestamp(), timestamp(), 1..100 as Timestamp, pooling_method())
+            .prop_map(|(a, b, interval, pooling)| Operation::PoolRange(a.min(b), a.max(b), interval, pooling)),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_file_storage_matches_model(operations in prop::collection::vec(operation(), 1..64)) {
+        let _setup_file = SetupFile::new("test_file_storage_matches_model");
+
+        let mut storage = FileStorage::<Timestamp, i32>::new("test_file_storage_matches_model").unwrap();
+        let mut model = Model::default();
+
+        for operation in operations {
+            // Reads of an empty storage aren't modeled
+            let empty = model.records.is_empty();
+
+            match operation {
+                Operation::Store(key, value) => {
+                    let stored = storage.store(Box::new(key), Box::new(value)).is_ok();
+                    prop_assert_eq!(stored, model.store(key, value), "store of {} at {}", value, key);
+                },
+                _ if empty => {},
+                Operation::RetrieveAll => {
+                    prop_assert_eq!(storage.retrieve_all().unwrap().into_vec::<Timestamp, i32>(), model.records.clone());
+                },
+                Operation::RetrieveFrom(start) => {
+                    prop_assert_eq!(storage.retrieve_from(start).unwrap().into_vec::<Timestamp, i32>(), model.retrieve(|key| key >= start));
+                },
+                Operation::RetrieveTo(end) => {
+                    prop_assert_eq!(storage.retrieve_to(end).unwrap().into_vec::<Timestamp, i32>(), model.retrieve(|key| key < end));
+                },
+                Operation::RetrieveRange(start, end) => {
+                    prop_assert_eq!(storage.retrieve_range(start..end).unwrap().into_vec::<Timestamp, i32>(), model.retrieve(|key| key >= start && key < end));
+                },
+                Operation::PoolRange(start, end, interval, pooling) => {
+                    let pooling_options = PoolingOptions {
+                        interval: Interval::millis(interval),
+                        pooling: pooling,
+                        anchor: BucketAnchor::RequestedStart,
+                        ..PoolingOptions::default()
+                    };
+
+                    prop_assert_eq!(
+                        storage.pool_range(start..end, pooling_options).unwrap().into_vec::<Timestamp, i32>(),
+                        model.pool_range(start, end, interval, pooling),
+                        "{:?} pooling of {}..{} by {}", pooling, start, end, interval
+                    );
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage files opened for reading alongside their writer.
+
+use std::ops::Deref;
+
+use storage::file::FileStorage;
+
+/// A storage file opened alongside the process writing it, as by `FileStorage::open_read_only`.  It only lends out
+/// the storage for reading, so nothing can be stored to it.
+pub struct ReadOnlyFileStorage<K, V> {
+    storage: FileStorage<K, V>,
+}
+
+impl<K, V> ReadOnlyFileStorage<K, V> {
+    pub(super) fn new(storage: FileStorage<K, V>) -> Self {
+        Self {
+            storage: storage,
+        }
+    }
+}
+
+impl<K, V> Deref for ReadOnlyFileStorage<K, V> {
+    type Target = FileStorage<K, V>;
+
+    fn deref(&self) -> &FileStorage<K, V> {
+        &self.storage
+    }
+}
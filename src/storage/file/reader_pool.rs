@@ -0,0 +1,146 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use key_value_store::IoStats;
+use storage::file::CountedFile;
+use storage::file::io_counter::IoCounter;
+#[cfg(feature = "mmap")]
+use storage::file::mapped_file::MappedFile;
+
+/// Read handles to a storage file, each with its own position, so that any number of reads can run at once.
+/// Handles are opened as they're needed and kept for reuse.
+pub struct ReaderPool {
+    filename: String,
+    idle: Mutex<Vec<CountedFile>>,
+    /// The disk activity of the handles that have been returned
+    io_stats: Mutex<IoStats>,
+}
+
+impl ReaderPool {
+    pub fn new(filename: &str) -> Self {
+        Self {
+            filename: filename.to_string(),
+            idle: Mutex::new(Vec::new()),
+            io_stats: Mutex::new(IoStats::default()),
+        }
+    }
+
+    /// Takes an idle handle, or opens a new one if they're all in use
+    pub fn get<'a>(&'a self) -> io::Result<Reader<'a>> {
+        let idle = self.idle.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Reader pool was poisoned"))?.pop();
+
+        let file = match idle {
+            Some(file) => file,
+            None => {
+                let file = File::open(&self.filename)?;
+
+                #[cfg(feature = "mmap")]
+                let file = MappedFile::new(file)?;
+
+                IoCounter::new(file)
+            },
+        };
+
+        Ok(Reader {
+            file: Some(file),
+            pool: self,
+        })
+    }
+
+    /// The disk activity of every read so far
+    pub fn io_stats(&self) -> IoStats {
+        match self.io_stats.lock() {
+            Ok(io_stats) => *io_stats,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+}
+
+/// A handle borrowed from a `ReaderPool`.  It's returned to the pool, and its disk activity tallied, when dropped.
+pub struct Reader<'a> {
+    file: Option<CountedFile>,
+    pool: &'a ReaderPool,
+}
+
+impl<'a> Deref for Reader<'a> {
+    type Target = CountedFile;
+
+    fn deref(&self) -> &CountedFile {
+        self.file.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for Reader<'a> {
+    fn deref_mut(&mut self) -> &mut CountedFile {
+        self.file.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for Reader<'a> {
+    fn drop(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            let stats = file.take_stats();
+
+            if let Ok(mut io_stats) = self.pool.io_stats.lock() {
+                io_stats.bytes_read += stats.bytes_read;
+                io_stats.seeks += stats.seeks;
+            }
+
+            if let Ok(mut idle) = self.pool.idle.lock() {
+                idle.push(file);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom};
+
+    use util::SetupFile;
+
+    #[test]
+    fn test_reader_pool() {
+        let _setup_file = SetupFile::new("test_reader_pool");
+        fs::write("test_reader_pool", b"0123456789").unwrap();
+
+        let pool = ReaderPool::new("test_reader_pool");
+
+        {
+            // Readers held at the same time keep their own positions
+            let mut a = pool.get().unwrap();
+            let mut b = pool.get().unwrap();
+            let mut buffer = [0u8; 3];
+
+            a.seek(SeekFrom::Start(5)).unwrap();
+            b.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer, b"012");
+
+            a.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer, b"567");
+        }
+
+        assert_eq!(pool.idle.lock().unwrap().len(), 2);
+        assert_eq!(pool.io_stats(), IoStats { bytes_read: 6, seeks: 1 });
+    }
+}
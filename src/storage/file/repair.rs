@@ -0,0 +1,156 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Recovery of a storage file whose final record was torn.
+//!
+//! A crash partway through an append leaves a file that isn't a whole number of records long, which `FileStorage`
+//! refuses to open.  Repairing it finds the last whole record that still parses, moves everything after it into a
+//! backup file beside it, and cuts the file off there, so that nothing is thrown away and the file opens again.
+
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::str;
+
+use key_value_store::Storable;
+use storage::file::{FileStorage, OpenMode, lock, parse_record};
+use time_series::{TimeUnit, Timestamp};
+
+/// What repairing a file kept and set aside
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Repair {
+    /// Bytes of whole records left in the file
+    pub recovered: u64,
+    /// Bytes moved out of the file into the backup
+    pub discarded: u64,
+    /// Where the discarded bytes were appended, if there were any
+    pub backup: Option<String>,
+}
+
+impl<V> FileStorage<Timestamp, V> where V: Storable<FileStorage<Timestamp, V>> {
+    /// Repairs a file of timestamps in a unit other than milliseconds, then opens it
+    pub fn repair_with_unit(filename: &str, unit: TimeUnit) -> io::Result<(Self, Repair)> {
+        let repair = repair_tail::<Timestamp, V>(filename, unit.significant_digits())?;
+        Ok((Self::with_unit(filename, unit)?, repair))
+    }
+}
+
+impl<K, V> FileStorage<K, V> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>> {
+    /// Opens a file like `new`, first moving anything after its last whole record into a file with ".bak" appended to
+    /// its name if it isn't a whole number of records long.  The backup is appended to rather than replaced, so
+    /// repeated repairs keep everything they've set aside.  Files that are whole are opened untouched.
+    pub fn repair(filename: &str) -> io::Result<(Self, Repair)> {
+        let repair = repair_tail::<K, V>(filename, K::size())?;
+        Ok((Self::new(filename)?, repair))
+    }
+}
+
+/// Cuts a file off after its last whole record whose keys are `key_size` wide.  The file is locked while it's
+/// repaired, so repairing fails if a storage has it open, and it has to exist already.
+fn repair_tail<K, V>(filename: &str, key_size: usize) -> io::Result<Repair> where K: Storable<FileStorage<K, V>> + Ord, V: Storable<FileStorage<K, V>> {
+    let mut file = OpenOptions::new().read(true).write(true).truncate(false).open(filename)?;
+    lock::lock(&file, filename, OpenMode::ReadWrite)?;
+
+    let length = file.metadata()?.len();
+    let item_size = key_size + 1 + V::size() + 1;
+
+    let mut end = length - length % item_size as u64;
+    if end == length {
+        return Ok(Repair {
+            recovered: length,
+            discarded: 0,
+            backup: None,
+        });
+    }
+
+    // Bytes that went missing or were added earlier in the file shift every record after them, so step back until a
+    // record lines up again
+    let mut buffer = vec![0u8; item_size];
+    while end > 0 {
+        file.seek(SeekFrom::Start(end - item_size as u64))?;
+        file.read_exact(&mut buffer)?;
+
+        if buffer[item_size - 1] == b'\n' && str::from_utf8(&buffer).is_ok() && parse_record::<K, V>(&buffer).is_ok() {
+            break;
+        }
+
+        end -= item_size as u64;
+    }
+
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(end))?;
+    file.read_to_end(&mut tail)?;
+
+    let backup = format!("{}.bak", filename);
+    {
+        let mut backup_file = OpenOptions::new().append(true).create(true).open(&backup)?;
+        backup_file.write_all(&tail)?;
+        backup_file.sync_all()?;
+    }
+
+    // The tail is only cut off once it's safely in the backup
+    file.set_len(end)?;
+    file.sync_all()?;
+
+    Ok(Repair {
+        recovered: end,
+        discarded: length - end,
+        backup: Some(backup),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use storage::file::Corruption;
+    use time_series::TimeSeries;
+    use util::SetupFile;
+
+    #[test]
+    fn test_repair() {
+        let _setup_file = SetupFile::new("test_repair");
+        let _backup_file = SetupFile::new("test_repair.bak");
+
+        // A torn final record
+        fs::write("test_repair", "0000000000001    1\n0000000000002    2\n00000000").unwrap();
+        assert_eq!(FileStorage::<Timestamp, i32>::new("test_repair").err().as_ref().and_then(Corruption::of), Some(Corruption::Size));
+
+        let (fs, repair) = FileStorage::<Timestamp, i32>::repair("test_repair").unwrap();
+        assert_eq!(repair, Repair { recovered: 38, discarded: 8, backup: Some("test_repair.bak".to_string()) });
+        assert_eq!(fs.retrieve_all().unwrap().into_vec::<Timestamp, i32>(), vec![(1, 1), (2, 2)]);
+        drop(fs);
+
+        // A whole file is left alone
+        let (_, repair) = FileStorage::<Timestamp, i32>::repair("test_repair").unwrap();
+        assert_eq!(repair, Repair { recovered: 38, discarded: 0, backup: None });
+
+        // Stray bytes in the middle throw off every record after them, which are set aside along with them
+        fs::write("test_repair", "0000000000001    1\nxx0000000000002    2\n").unwrap();
+
+        let (fs, repair) = FileStorage::<Timestamp, i32>::repair("test_repair").unwrap();
+        assert_eq!((repair.recovered, repair.discarded), (19, 21));
+        assert_eq!(fs.retrieve_all().unwrap().into_vec::<Timestamp, i32>(), vec![(1, 1)]);
+
+        assert_eq!(fs::read_to_string("test_repair.bak").unwrap(), "00000000xx0000000000002    2\n");
+        drop(fs);
+
+        // There's nothing to repair in a file that isn't there, and it isn't created
+        fs::remove_file("test_repair").unwrap();
+        assert_eq!(FileStorage::<Timestamp, i32>::repair("test_repair").err().map(|error| error.kind()), Some(io::ErrorKind::NotFound));
+        assert!(fs::metadata("test_repair").is_err());
+    }
+}
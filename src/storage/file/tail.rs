@@ -0,0 +1,127 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Following a file as records are appended to it, like `tail -f`.
+//!
+//! A `TailCursor` remembers the offset just past the last record it returned, so each poll reads only what was
+//! appended since, without searching the file again.  It borrows the storage only while polling, so the storage can
+//! be stored to between polls.
+
+use std::io::{self, Seek, SeekFrom};
+use std::marker::PhantomData;
+
+use key_value_store::Storable;
+use storage::file::{binary_search_for_key, CountedFile, FileStorage, RecordReader};
+use time_series::{RetrievalDirection, Timestamp};
+
+/// Where a reader following a `FileStorage` is up to
+#[derive(Clone, Debug)]
+pub struct TailCursor<V> {
+    /// Records before this are never returned
+    from: Timestamp,
+    /// The offset of the next record to return, once the first one has been found
+    offset: Option<u64>,
+    /// The timestamp of the last record returned
+    position: Option<Timestamp>,
+    _phantom: PhantomData<V>,
+}
+
+impl<V> TailCursor<V> where V: Storable<FileStorage<Timestamp, V>> {
+    /// Returns the records appended since the last poll, or on the first poll, every record from the cursor's start
+    pub fn poll(&mut self, storage: &FileStorage<Timestamp, V>) -> io::Result<Vec<(Timestamp, V)>> {
+        let file = &mut *storage.reader()?;
+
+        let offset = match self.offset {
+            Some(offset) => offset,
+            // Nothing has been stored from the start yet
+            None if storage.items == 0 || self.from > storage.last_key => return Ok(Vec::new()),
+            None => {
+                let mut read_buffer = vec![0u8; storage.key_size];
+                binary_search_for_key::<Timestamp, V, CountedFile>(file, &mut read_buffer, Some(RetrievalDirection::Forward), self.from, 0, storage.end_offset)?
+            },
+        };
+
+        let from_item = offset as usize / storage.item_size;
+        if from_item > storage.items {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "FileStorage has fewer records than the cursor has read"));
+        }
+
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut results = Vec::with_capacity(storage.items - from_item);
+
+        // Read the records in large chunks to reduce the number of disk reads
+        RecordReader::<Timestamp, V, CountedFile>::with_key_size(file, storage.items - from_item, storage.key_size).read_all(&mut results)?;
+
+        self.offset = Some(offset + (results.len() * storage.item_size) as u64);
+        if let Some(&(timestamp, _)) = results.last() {
+            self.position = Some(timestamp);
+        }
+
+        Ok(results)
+    }
+
+    /// The timestamp of the last record returned, from which a new cursor can resume
+    pub fn position(&self) -> Option<Timestamp> {
+        self.position
+    }
+}
+
+impl<V> FileStorage<Timestamp, V> where V: Storable<FileStorage<Timestamp, V>> {
+    /// A cursor that follows the records from `from` on as they're appended
+    pub fn tail(&self, from: Timestamp) -> TailCursor<V> {
+        TailCursor {
+            from: from,
+            offset: None,
+            position: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use key_value_store::KeyValueStore;
+    use util::SetupFile;
+
+    #[test]
+    fn test_tail() {
+        let _setup_file = SetupFile::new("test_tail");
+
+        let mut storage = FileStorage::<Timestamp, i32>::new("test_tail").unwrap();
+
+        let mut early = storage.tail(15);
+        let mut late = storage.tail(35);
+        assert!(early.poll(&storage).unwrap().is_empty());
+
+        storage.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        storage.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+        storage.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
+
+        assert_eq!(early.poll(&storage).unwrap(), vec![(20, 2), (30, 3)]);
+        assert!(early.poll(&storage).unwrap().is_empty());
+        assert_eq!(early.position(), Some(30));
+        assert!(late.poll(&storage).unwrap().is_empty());
+
+        storage.store(Box::new(40 as Timestamp), Box::new(4 as i32)).unwrap();
+        storage.store(Box::new(50 as Timestamp), Box::new(5 as i32)).unwrap();
+
+        assert_eq!(early.poll(&storage).unwrap(), vec![(40, 4), (50, 5)]);
+        assert_eq!(late.poll(&storage).unwrap(), vec![(40, 4), (50, 5)]);
+        assert_eq!(late.position(), Some(50));
+    }
+}
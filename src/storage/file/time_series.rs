@@ -13,75 +13,71 @@
 // You should have received a copy of the GNU General Public License
 // along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::fs::File;
-use std::io::{self, BufReader, Seek, SeekFrom};
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::ops::Range;
 
-use key_value_store::{KeyValueStore, Retrieval, Storable};
-use storage::file::{binary_search_for_key, FileStorage, read_record};
-use time_series::{RetrievalDirection, TimeSeries, Timestamp};
+use key_value_store::{Retrieval, Storable};
+use storage::file::{binary_search_for_key, CountedFile, FileStorage, OpenMode, read_key, read_record, RecordReader};
+use storage::file::heartbeat;
+use time_series::{RetrievalDirection, TimeSeries, TimeUnit, Timestamp};
 
 impl<V> TimeSeries for FileStorage<Timestamp, V> where V: Storable<FileStorage<Timestamp, V>> {
     fn retrieve_nearest(&self, timestamp: Timestamp, retrieval_direction: Option<RetrievalDirection>) -> io::Result<Retrieval> {
-        let mut file = self.file.borrow_mut();
+        let mut file = self.reader()?;
 
         let record_offset = {
-            let mut read_buffer = vec![0u8; <Timestamp as Storable<FileStorage<Timestamp, V>>>::size()];
-            binary_search_for_key::<Timestamp, V, File>(&mut file, &mut read_buffer, retrieval_direction, timestamp, 0, self.end_offset)?
+            let mut read_buffer = vec![0u8; self.key_size];
+            binary_search_for_key::<Timestamp, V, CountedFile>(&mut file, &mut read_buffer, retrieval_direction, timestamp, 0, self.end_offset)?
         };
         file.seek(SeekFrom::Start(record_offset))?;
 
         let mut read_buffer = vec![0u8; self.item_size];
 
-        Ok(Retrieval::new(Box::new(read_record::<Timestamp, V, File>(&mut file, &mut read_buffer)?)))
+        Ok(Retrieval::new(Box::new(read_record::<Timestamp, V, CountedFile>(&mut file, &mut read_buffer)?)))
     }
 
     fn retrieve_all(&self) -> io::Result<Retrieval> {
-        // Buffer the file to reduce the number of disk reads
-        let file = &mut *self.file.borrow_mut();
-        let mut file_buffer = BufReader::new(file);
-        file_buffer.seek(SeekFrom::Start(0))?;
+        let file = &mut *self.reader()?;
+        file.seek(SeekFrom::Start(0))?;
 
         let mut results = Vec::with_capacity(self.items);
 
-        let mut read_buffer = vec![0u8; self.item_size];
-        for _ in 0..self.items {
-            results.push(read_record::<Timestamp, V, BufReader<&mut File>>(&mut file_buffer, &mut read_buffer)?);
-        }
+        // Read the records in large chunks to reduce the number of disk reads
+        RecordReader::<Timestamp, V, CountedFile>::with_key_size(file, self.items, self.key_size).read_all(&mut results)?;
 
         Ok(Retrieval::new(Box::new(results)))
     }
 
     fn retrieve_from(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        let file = &mut *self.reader()?;
+
         // Don't use self.find_from because that wants to grab the record on or before the timestamp, not on or after
         let from_offset = {
             if timestamp <= self.last_key {
-                let mut read_buffer = vec![0u8; <Timestamp as Storable<FileStorage<Timestamp, V>>>::size()];
-                binary_search_for_key::<Timestamp, V, File>(&mut self.file.borrow_mut(), &mut read_buffer, Some(RetrievalDirection::Forward), timestamp, 0, self.end_offset)?
+                let mut read_buffer = vec![0u8; self.key_size];
+                binary_search_for_key::<Timestamp, V, CountedFile>(file, &mut read_buffer, Some(RetrievalDirection::Forward), timestamp, 0, self.end_offset)?
             } else {
                 return Ok(Retrieval::new(Box::new(Vec::<(Timestamp, V)>::new())));
             }
         };
 
-        // Buffer the file to reduce the number of disk reads
-        let file = &mut *self.file.borrow_mut();
-        let mut file_buffer = BufReader::new(file);
-        file_buffer.seek(SeekFrom::Start(from_offset))?;
+        file.seek(SeekFrom::Start(from_offset))?;
 
         let from_item = from_offset as usize / self.item_size;
 
         let mut results = Vec::with_capacity(self.items - from_item);
 
-        let mut read_buffer = vec![0u8; self.item_size];
-        for _ in from_item..self.items {
-            results.push(read_record::<Timestamp, V, BufReader<&mut File>>(&mut file_buffer, &mut read_buffer)?);
-        }
+        // Read the records in large chunks to reduce the number of disk reads
+        RecordReader::<Timestamp, V, CountedFile>::with_key_size(file, self.items - from_item, self.key_size).read_all(&mut results)?;
 
         Ok(Retrieval::new(Box::new(results)))
     }
 
     fn retrieve_to(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
-        let to_offset = match self.find_to(timestamp) {
+        let file = &mut *self.reader()?;
+
+        let to_offset = match self.find_to(file, timestamp) {
             Ok(offset) => offset,
             Err(error) => return if error.kind() == io::ErrorKind::InvalidInput || error.kind() == io::ErrorKind::NotFound {
                 Ok(Retrieval::new(Box::new(Vec::<(Timestamp, V)>::new())))
@@ -90,67 +86,216 @@ impl<V> TimeSeries for FileStorage<Timestamp, V> where V: Storable<FileStorage<T
             },
         };
 
-        // Buffer the file to reduce the number of disk reads
-        let file = &mut *self.file.borrow_mut();
-        let mut file_buffer = BufReader::new(file);
-        file_buffer.seek(SeekFrom::Start(0))?;
+        file.seek(SeekFrom::Start(0))?;
 
         let to_item = to_offset as usize / self.item_size + 1;
 
         let mut results = Vec::with_capacity(to_item);
 
-        let mut read_buffer = vec![0u8; self.item_size];
-        for _ in 0..to_item {
-            results.push(read_record::<Timestamp, V, BufReader<&mut File>>(&mut file_buffer, &mut read_buffer)?);
-        }
+        // Read the records in large chunks to reduce the number of disk reads
+        RecordReader::<Timestamp, V, CountedFile>::with_key_size(file, to_item, self.key_size).read_all(&mut results)?;
 
         Ok(Retrieval::new(Box::new(results)))
     }
 
     fn retrieve_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval> {
+        let mut results = Vec::new();
+        self.retrieve_range_into(range, &mut results)?;
+
+        Ok(Retrieval::new(Box::new(results)))
+    }
+
+    fn preload(&self, range: Range<Timestamp>) -> io::Result<()> {
+        if self.items == 0 || range.start >= range.end || range.start > self.last_key {
+            return Ok(());
+        }
+
+        let file = &mut *self.reader()?;
+
+        let offsets = {
+            let mut read_buffer = vec![0u8; self.key_size];
+            binary_search_for_key::<Timestamp, V, CountedFile>(file, &mut read_buffer, Some(RetrievalDirection::Forward), range.start, 0, self.end_offset)
+                .and_then(|from_offset| Ok((from_offset, self.find_to(file, range.end)? + self.item_size as u64)))
+        };
+
+        let (from_offset, to_offset) = match offsets {
+            Ok(offsets) => offsets,
+            // Nothing in the range
+            Err(ref error) if error.kind() == io::ErrorKind::InvalidInput || error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        // Reading the range through brings it into the page cache, which every read handle shares
+        file.seek(SeekFrom::Start(from_offset))?;
+        io::copy(&mut Read::by_ref(file).take(to_offset.saturating_sub(from_offset)), &mut io::sink())?;
+
+        Ok(())
+    }
+
+    fn find_gaps(&self, min_gap: Timestamp, range: Range<Timestamp>) -> io::Result<Vec<Range<Timestamp>>> {
+        let mut gaps = Vec::new();
+
+        if range.start >= range.end {
+            return Ok(gaps);
+        }
+
+        // A zero-length span is never a gap, regardless of min_gap
+        let is_gap = |start: Timestamp, end: Timestamp| end > start && end - start >= min_gap;
+
+        let file = &mut *self.reader()?;
+
+        // Find the offsets of the first and last records within the range, if there are any
+        let bounds = if self.items == 1 {
+            // The binary search needs at least two records to work with
+            if range.start <= self.first_key && range.end > self.first_key {
+                Some((0, 0))
+            } else {
+                None
+            }
+        } else if self.items > 1 && range.start <= self.last_key && range.end > self.first_key {
+            let from_offset = {
+                let mut read_buffer = vec![0u8; self.key_size];
+                binary_search_for_key::<Timestamp, V, CountedFile>(file, &mut read_buffer, Some(RetrievalDirection::Forward), range.start, 0, self.end_offset)?
+            };
+            let to_offset = self.find_to(file, range.end)?;
+
+            if to_offset >= from_offset {
+                Some((from_offset, to_offset))
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        let (from_offset, to_offset) = match bounds {
+            Some(bounds) => bounds,
+            None => {
+                // The whole range is empty
+                if is_gap(range.start, range.end) {
+                    gaps.push(range);
+                }
+                return Ok(gaps);
+            },
+        };
+
+        let mut read_buffer = vec![0u8; self.key_size];
+
+        file.seek(SeekFrom::Start(from_offset))?;
+        let first_key = read_key::<Timestamp, V, CountedFile>(file, &mut read_buffer)?;
+
+        file.seek(SeekFrom::Start(to_offset))?;
+        let last_key = read_key::<Timestamp, V, CountedFile>(file, &mut read_buffer)?;
+
+        if is_gap(range.start, first_key) {
+            gaps.push(range.start..first_key);
+        }
+
+        find_gaps_between::<V, CountedFile>(file, &mut read_buffer, min_gap, self.item_size, (from_offset, first_key), (to_offset, last_key), &mut gaps)?;
+
+        if is_gap(last_key, range.end) {
+            gaps.push(last_key..range.end);
+        }
+
+        Ok(gaps)
+    }
+
+    fn time_unit(&self) -> TimeUnit {
+        self.unit
+    }
+
+    /// Heartbeats are kept beside the file, and only move forward
+    fn heartbeat(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        if self.mode != OpenMode::ReadWrite {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "FileStorage was opened read-only"));
+        }
+
+        if self.heartbeat.map_or(true, |heartbeat| timestamp > heartbeat) {
+            heartbeat::write(&self.heartbeat_file, timestamp)?;
+            self.heartbeat = Some(timestamp);
+        }
+
+        Ok(())
+    }
+
+    fn last_update(&self) -> Option<Timestamp> {
+        let last_record = if self.items > 0 { Some(self.last_key) } else { None };
+        cmp::max(self.heartbeat, last_record)
+    }
+}
+
+/// Recursively bisects the records between two offsets, only descending into spans whose keys are far enough
+/// apart to possibly contain a gap.  Densely populated stretches are skipped without being read.
+fn find_gaps_between<V, F>(
+    file: &mut F,
+    buffer: &mut [u8],
+    min_gap: Timestamp,
+    item_size: usize,
+    start: (u64, Timestamp),
+    end: (u64, Timestamp),
+    gaps: &mut Vec<Range<Timestamp>>,
+) -> io::Result<()> where V: Storable<FileStorage<Timestamp, V>>, F: Read + Seek {
+    // No two records in this span can be far enough apart to form a gap.  Keys out of order, as in a corrupt file,
+    // can't either.
+    if end.0 <= start.0 || end.1 < start.1 || end.1 - start.1 < min_gap {
+        return Ok(());
+    }
+
+    // Adjacent records that are far enough apart form a gap
+    if end.0 - start.0 == item_size as u64 {
+        gaps.push(start.1..end.1);
+        return Ok(());
+    }
+
+    let center_offset = start.0 + (end.0 - start.0) / item_size as u64 / 2 * item_size as u64;
+
+    file.seek(SeekFrom::Start(center_offset))?;
+    let center_key = read_key::<Timestamp, V, F>(file, buffer)?;
+
+    find_gaps_between::<V, F>(file, buffer, min_gap, item_size, start, (center_offset, center_key), gaps)?;
+    find_gaps_between::<V, F>(file, buffer, min_gap, item_size, (center_offset, center_key), end, gaps)
+}
+
+impl<V> FileStorage<Timestamp, V> where V: Storable<FileStorage<Timestamp, V>> {
+    /// Retrieves a range like `retrieve_range`, but into `results`, which is cleared first.  A caller that retrieves
+    /// over and over can keep reusing the same vector, rather than allocating one per retrieval, and gets the records
+    /// without going through a `Retrieval`.
+    pub fn retrieve_range_into(&self, range: Range<Timestamp>, results: &mut Vec<(Timestamp, V)>) -> io::Result<()> {
+        results.clear();
+
+        let file = &mut *self.reader()?;
+
         // Don't use self.find_from because that wants to grab the record on or before the timestamp, not on or after
         let from_offset = {
-            let mut read_buffer = vec![0u8; <Timestamp as Storable<FileStorage<Timestamp, V>>>::size()];
+            let mut read_buffer = vec![0u8; self.key_size];
             if range.start <= self.last_key {
-                binary_search_for_key::<Timestamp, V, File>(&mut self.file.borrow_mut(), &mut read_buffer, Some(RetrievalDirection::Forward), range.start, 0, self.end_offset)?
+                binary_search_for_key::<Timestamp, V, CountedFile>(file, &mut read_buffer, Some(RetrievalDirection::Forward), range.start, 0, self.end_offset)?
             } else {
-                return Ok(Retrieval::new(Box::new(Vec::<(Timestamp, V)>::new())));
+                return Ok(());
             }
         };
 
-        let to_offset = match self.find_to(range.end) {
+        let to_offset = match self.find_to(file, range.end) {
             Ok(offset) => offset,
             Err(error) => return if error.kind() == io::ErrorKind::InvalidInput || error.kind() == io::ErrorKind::NotFound {
-                Ok(Retrieval::new(Box::new(Vec::<(Timestamp, V)>::new())))
+                Ok(())
             } else {
                 Err(error)
             },
         };
 
-        // Buffer the file to reduce the number of disk reads
-        let file = &mut *self.file.borrow_mut();
-        let mut file_buffer = BufReader::new(file);
-        file_buffer.seek(SeekFrom::Start(from_offset))?;
+        file.seek(SeekFrom::Start(from_offset))?;
 
         let from_item = from_offset as usize / self.item_size;
         let to_item = to_offset as usize / self.item_size + 1;
 
-        let mut results = Vec::with_capacity(to_item - from_item);
+        // Out of order records, which only a corrupt file has, can put the end before the start
+        let count = to_item.saturating_sub(from_item);
 
-        let mut read_buffer = vec![0u8; self.item_size];
-        for _ in from_item..to_item {
-            results.push(read_record::<Timestamp, V, BufReader<&mut File>>(&mut file_buffer, &mut read_buffer)?);
-        }
+        results.reserve(count);
 
-        Ok(Retrieval::new(Box::new(results)))
-    }
-
-    fn as_key_value_store(&self) -> &dyn KeyValueStore {
-        self
-    }
-
-    fn as_mut_key_value_store(&mut self) -> &mut dyn KeyValueStore {
-        self
+        // Read the records in large chunks to reduce the number of disk reads
+        RecordReader::<Timestamp, V, CountedFile>::with_key_size(file, count, self.key_size).read_all(results)
     }
 }
 
@@ -158,6 +303,11 @@ impl<V> TimeSeries for FileStorage<Timestamp, V> where V: Storable<FileStorage<T
 mod tests {
     use super::*;
 
+    use std::fs;
+
+    use key_value_store::KeyValueStore;
+    use pooled_time_series::{BucketAnchor, GapFillMethod, Interval, PooledTimeSeries, PoolingMethod, PoolingOptions};
+    use storage::file::write_record;
     use util::SetupFile;
 
     #[test]
@@ -197,6 +347,24 @@ mod tests {
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2), (30, 3), (40, 4)]));
     }
 
+    #[test]
+    fn test_retrieve_all_across_chunks() {
+        let _setup_file = SetupFile::new("test_retrieve_all_across_chunks");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_retrieve_all_across_chunks").unwrap();
+
+        let records = (1..2500).map(|i| (i as Timestamp, (i % 1000) as i32)).collect::<Vec<(Timestamp, i32)>>();
+        for &(timestamp, value) in &records {
+            fs.store(Box::new(timestamp), Box::new(value)).unwrap();
+        }
+
+        let retrieval = fs.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&records));
+
+        let retrieval = fs.retrieve_range(1000..2100).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>().map(|r| r.as_slice()), Some(&records[999..2099]));
+    }
+
     #[test]
     fn test_retrieve_from() {
         let _setup_file = SetupFile::new("test_retrieve_from");
@@ -268,4 +436,189 @@ mod tests {
         let retrieval = fs.retrieve_range(21..44).unwrap();
         assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(30, 3), (40, 4)]));
     }
+
+    #[test]
+    fn test_retrieve_range_into() {
+        let _setup_file = SetupFile::new("test_retrieve_range_into");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_retrieve_range_into").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
+
+        let mut results = Vec::with_capacity(16);
+        fs.retrieve_range_into(10..31, &mut results).unwrap();
+        assert_eq!(results, vec![(10, 1), (20, 2), (30, 3)]);
+
+        // The buffer is reused, not appended to
+        let buffer = results.as_ptr();
+        fs.retrieve_range_into(15..25, &mut results).unwrap();
+        assert_eq!(results, vec![(20, 2)]);
+        assert_eq!(results.as_ptr(), buffer);
+
+        fs.retrieve_range_into(40..50, &mut results).unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_preload() {
+        let _setup_file = SetupFile::new("test_preload");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_preload").unwrap();
+        fs.preload(0..100).unwrap();
+
+        for &(timestamp, value) in &[(10, 1), (20, 2), (30, 3), (40, 4)] {
+            fs.store(Box::new(timestamp as Timestamp), Box::new(value as i32)).unwrap();
+        }
+
+        // Ranges with nothing in them aren't errors
+        for range in vec![5..10, 41..50, 30..30, 21..29] {
+            fs.preload(range).unwrap();
+        }
+
+        let before = fs.io_stats();
+        fs.preload(15..40).unwrap();
+        assert!((fs.io_stats() - before).bytes_read >= 2 * 19);
+
+        assert_eq!(fs.retrieve_range(15..40).unwrap().into_vec::<Timestamp, i32>(), vec![(20, 2), (30, 3)]);
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let _setup_file = SetupFile::new("test_find_gaps");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_find_gaps").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(11 as Timestamp), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(12 as Timestamp), Box::new(3 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(4 as i32)).unwrap();
+        fs.store(Box::new(21 as Timestamp), Box::new(5 as i32)).unwrap();
+        fs.store(Box::new(30 as Timestamp), Box::new(6 as i32)).unwrap();
+
+        assert_eq!(fs.find_gaps(5, 10..31).unwrap(), vec![12..20, 21..30]);
+        assert_eq!(fs.find_gaps(9, 10..31).unwrap(), vec![21..30]);
+        assert_eq!(fs.find_gaps(5, 0..40).unwrap(), vec![0..10, 12..20, 21..30, 30..40]);
+        assert_eq!(fs.find_gaps(5, 14..19).unwrap(), vec![14..19]);
+        assert_eq!(fs.find_gaps(5, 50..60).unwrap(), vec![50..60]);
+        assert_eq!(fs.find_gaps(20, 10..31).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_find_gaps_single_record() {
+        let _setup_file = SetupFile::new("test_find_gaps_single_record");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_find_gaps_single_record").unwrap();
+
+        assert_eq!(fs.find_gaps(5, 0..10).unwrap(), vec![0..10]);
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+
+        assert_eq!(fs.find_gaps(5, 0..20).unwrap(), vec![0..10, 10..20]);
+    }
+
+    #[test]
+    fn test_nanosecond_timestamps() {
+        let _setup_file = SetupFile::new("test_nanosecond_timestamps");
+
+        let records = vec![
+            (1_546_300_800_000_000_001 as Timestamp, 1 as i32),
+            (1_546_300_800_000_000_500, 2),
+            (1_546_300_800_000_001_000, 3),
+            (1_546_300_800_000_002_250, 4),
+        ];
+
+        {
+            let mut fs = FileStorage::<Timestamp, i32>::with_unit("test_nanosecond_timestamps", TimeUnit::Nanoseconds).unwrap();
+            assert_eq!(fs.time_unit(), TimeUnit::Nanoseconds);
+
+            for &(timestamp, value) in &records {
+                fs.store(Box::new(timestamp), Box::new(value)).unwrap();
+            }
+        }
+
+        // Each record is as wide as a nanosecond timestamp, plus the value and separators
+        assert_eq!(fs::metadata("test_nanosecond_timestamps").unwrap().len(), 4 * (19 + 1 + 4 + 1));
+
+        let fs = FileStorage::<Timestamp, i32>::with_unit("test_nanosecond_timestamps", TimeUnit::Nanoseconds).unwrap();
+
+        let retrieval = fs.retrieve_range(1_546_300_800_000_000_400..1_546_300_800_000_002_000).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&records[1..3].to_vec()));
+
+        let retrieval = fs.retrieve_nearest(1_546_300_800_000_002_000, Some(RetrievalDirection::Backward)).unwrap();
+        assert_eq!(retrieval.as_single::<Timestamp, i32>(), Some(&records[2]));
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(1_000), pooling: PoolingMethod::Sum, anchor: BucketAnchor::RequestedStart, ..PoolingOptions::default() };
+        let retrieval = fs.pool_range(1_546_300_800_000_000_000..1_546_300_800_000_003_000, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![
+            (1_546_300_800_000_000_000, 3),
+            (1_546_300_800_000_001_000, 3),
+            (1_546_300_800_000_002_000, 4),
+        ]));
+
+        // A millisecond file can't hold them
+        let mut ms = FileStorage::<Timestamp, i32>::new("test_nanosecond_timestamps.ms").unwrap();
+        assert!(ms.store(Box::new(records[0].0), Box::new(records[0].1)).is_err());
+        drop(ms);
+        fs::remove_file("test_nanosecond_timestamps.ms").ok();
+    }
+
+    #[test]
+    fn test_out_of_order_file() {
+        let _setup_file = SetupFile::new("test_out_of_order_file");
+
+        {
+            let mut file = fs::File::create("test_out_of_order_file").unwrap();
+            for &(timestamp, value) in &[(10, 1), (50, 2), (20, 3), (60, 4)] {
+                write_record::<Timestamp, i32, _>(&mut file, timestamp, value).unwrap();
+            }
+        }
+
+        // The answers from a corrupt file are wrong, but asking doesn't panic
+        let fs = FileStorage::<Timestamp, i32>::new("test_out_of_order_file").unwrap();
+        let pooling_options = PoolingOptions { interval: Interval::millis(5), pooling: PoolingMethod::End, ..PoolingOptions::default() };
+
+        for start in (0..70).step_by(5) {
+            for end in (start..70).step_by(5) {
+                fs.retrieve_range(start..end).ok();
+                fs.find_gaps(5, start..end).ok();
+                fs.pool_range(start..end, pooling_options).ok();
+            }
+        }
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let _setup_file = SetupFile::new("test_heartbeat");
+        let _setup_heartbeat = SetupFile::new("test_heartbeat.heartbeat");
+
+        {
+            let mut fs = FileStorage::<Timestamp, i32>::new("test_heartbeat").unwrap();
+            assert_eq!(fs.last_update(), None);
+
+            fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+            assert_eq!(fs.last_update(), Some(10));
+
+            fs.heartbeat(25).unwrap();
+            fs.heartbeat(20).unwrap();
+            assert_eq!(fs.last_update(), Some(25));
+
+            fs.store(Box::new(30 as Timestamp), Box::new(2 as i32)).unwrap();
+            assert_eq!(fs.last_update(), Some(30));
+
+            fs.heartbeat(40).unwrap();
+        }
+
+        // The heartbeat outlives the storage
+        let fs = FileStorage::<Timestamp, i32>::new("test_heartbeat").unwrap();
+        assert_eq!(fs.last_update(), Some(40));
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), gap_fill: Some(GapFillMethod::Previous), heartbeat: fs.last_update(), ..PoolingOptions::default() };
+        assert_eq!(fs.pool_range(10..60, pooling_options).unwrap().into_vec::<Timestamp, i32>(), vec![(10, 1), (20, 1), (30, 2), (40, 2)]);
+
+        drop(fs);
+        let mut fs = FileStorage::<Timestamp, i32>::read_only("test_heartbeat").unwrap();
+        assert!(fs.heartbeat(50).is_err());
+    }
 }
@@ -0,0 +1,50 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The unit of a storage file's timestamps, kept beside it as "<file>.unit" unless it's milliseconds.  The records
+//! don't say what unit their timestamps are in, so without it a file could be opened in another unit and misread.
+
+use std::fs;
+use std::io;
+
+use time_series::TimeUnit;
+
+pub fn unit_file(filename: &str) -> String {
+    format!("{}.unit", filename)
+}
+
+/// Reads the unit the file was written in, or `None` if it doesn't say
+pub fn read(unit_file: &str) -> io::Result<Option<TimeUnit>> {
+    match fs::read_to_string(unit_file) {
+        Ok(text) => text.trim().parse().map(Some).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Unit file is corrupt")),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Records the unit the file is written in.  Milliseconds are recorded by leaving the unit file out.  It's written
+/// beside the file and renamed over it, so that a crash can't leave it half written.
+pub fn write(unit_file: &str, unit: TimeUnit) -> io::Result<()> {
+    if unit == TimeUnit::Milliseconds {
+        return match fs::remove_file(unit_file) {
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        };
+    }
+
+    let temporary = format!("{}.tmp", unit_file);
+    fs::write(&temporary, unit.to_string())?;
+    fs::rename(&temporary, unit_file)
+}
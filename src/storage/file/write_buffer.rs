@@ -0,0 +1,244 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Buffering of stores in memory.
+//!
+//! A busy channel pays for a write call on every record it stores.  A buffered `FileStorage` encodes records into
+//! memory instead, and writes them out in one call once enough have built up or the oldest has waited long enough.
+//! The storage's bookkeeping counts buffered records as stored, and every read writes them out first, so reads see
+//! them as they would unbuffered.  Whatever is still buffered is written out when the storage is synced or dropped.
+//! If writing them out fails, the file is cut back to its last whole record and they stay buffered for the next
+//! attempt, so the file and the bookkeeping never disagree.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use storage::file::FileStorage;
+
+/// When a buffered storage writes its records out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BufferPolicy {
+    /// Write out once this many records are buffered
+    pub max_records: usize,
+    /// Write out once the oldest buffered record has waited this long.  It's only checked when a record is stored,
+    /// so a channel that goes quiet keeps its last records buffered until it's read, synced, or dropped.
+    pub max_delay: Option<Duration>,
+}
+
+impl Default for BufferPolicy {
+    fn default() -> Self {
+        Self {
+            max_records: 1024,
+            max_delay: Some(Duration::from_millis(100)),
+        }
+    }
+}
+
+/// Encoded records waiting to be written out
+pub struct WriteBuffer {
+    policy: BufferPolicy,
+    bytes: Vec<u8>,
+    records: usize,
+    /// When the oldest buffered record was stored
+    oldest: Option<Instant>,
+}
+
+impl WriteBuffer {
+    pub fn new(policy: BufferPolicy) -> Self {
+        Self {
+            policy: policy,
+            bytes: Vec::new(),
+            records: 0,
+            oldest: None,
+        }
+    }
+
+    /// Where the next record is encoded.  It has to be counted with `add` once it's written.
+    pub fn bytes(&mut self) -> &mut Vec<u8> {
+        &mut self.bytes
+    }
+
+    /// Counts a record just encoded, giving whether the buffer should be written out
+    pub fn add(&mut self) -> bool {
+        self.records += 1;
+
+        let now = Instant::now();
+        let oldest = *self.oldest.get_or_insert(now);
+
+        self.records >= self.policy.max_records || self.policy.max_delay.map_or(false, |max_delay| now.duration_since(oldest) >= max_delay)
+    }
+
+    /// Takes back the last record counted, when it couldn't be stored after all
+    pub fn discard_last(&mut self, item_size: usize) {
+        let len = self.bytes.len().saturating_sub(item_size);
+        self.bytes.truncate(len);
+        self.records = self.records.saturating_sub(1);
+
+        if self.records == 0 {
+            self.oldest = None;
+        }
+    }
+
+    /// Writes the buffered records out in one call.  If the write fails, whatever part of them made it into the file
+    /// is cut off again and they stay buffered, to be written out on the next attempt.
+    pub fn write_to(&mut self, mut file: &File) -> io::Result<()> {
+        if self.records == 0 {
+            return Ok(());
+        }
+
+        let end = file.metadata()?.len();
+
+        if let Err(error) = file.write_all(&self.bytes) {
+            file.set_len(end)?;
+            return Err(error);
+        }
+
+        self.bytes.clear();
+        self.records = 0;
+        self.oldest = None;
+
+        Ok(())
+    }
+}
+
+impl<K, V> FileStorage<K, V> {
+    /// Buffers stores in memory under `policy` instead of writing each record out as it's stored
+    pub fn buffered(mut self, policy: BufferPolicy) -> Self {
+        self.buffer = Some(Mutex::new(WriteBuffer::new(policy)));
+        self
+    }
+
+    /// Writes out any records that are buffered
+    pub fn flush(&self) -> io::Result<()> {
+        match self.write_buffer()? {
+            Some(mut buffer) => buffer.write_to(&self.writer),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) fn write_buffer<'a>(&'a self) -> io::Result<Option<MutexGuard<'a, WriteBuffer>>> {
+        match self.buffer {
+            Some(ref buffer) => buffer.lock().map(Some).map_err(|_| io::Error::new(io::ErrorKind::Other, "Write buffer was poisoned")),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<K, V> Drop for FileStorage<K, V> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{self, OpenOptions};
+    use std::mem;
+    use std::thread;
+
+    use key_value_store::KeyValueStore;
+    use time_series::{TimeSeries, Timestamp};
+    use util::SetupFile;
+
+    #[test]
+    fn test_write_buffer() {
+        let _setup_file = SetupFile::new("test_write_buffer");
+
+        let policy = BufferPolicy { max_records: 3, max_delay: None };
+        let mut storage = FileStorage::<Timestamp, i32>::new("test_write_buffer").unwrap().buffered(policy);
+
+        storage.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        storage.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+        assert_eq!(fs::metadata("test_write_buffer").unwrap().len(), 0);
+        assert_eq!(storage.len(), 2);
+
+        // The third record fills the buffer
+        storage.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
+        assert_eq!(fs::metadata("test_write_buffer").unwrap().len(), 57);
+
+        // Reads see buffered records
+        storage.store(Box::new(40 as Timestamp), Box::new(4 as i32)).unwrap();
+        assert_eq!(storage.retrieve_from(25).unwrap().into_vec::<Timestamp, i32>(), vec![(30, 3), (40, 4)]);
+
+        storage.store(Box::new(50 as Timestamp), Box::new(5 as i32)).unwrap();
+        drop(storage);
+        assert_eq!(fs::metadata("test_write_buffer").unwrap().len(), 95);
+    }
+
+    #[test]
+    fn test_write_buffer_failure() {
+        let _setup_file = SetupFile::new("test_write_buffer_failure");
+
+        let policy = BufferPolicy { max_records: 3, max_delay: None };
+        let mut storage = FileStorage::<Timestamp, i32>::new("test_write_buffer_failure").unwrap().buffered(policy);
+
+        storage.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        storage.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+
+        // A handle that can't be written to makes writing the buffer out fail
+        let writer = mem::replace(&mut storage.writer, File::open("test_write_buffer_failure").unwrap());
+
+        assert!(storage.store(Box::new(30 as Timestamp), Box::new(3 as i32)).is_err());
+        assert!(storage.flush().is_err());
+        assert_eq!(fs::metadata("test_write_buffer_failure").unwrap().len(), 0);
+        assert_eq!(storage.len(), 2);
+        assert_eq!(storage.last_key, 20);
+
+        // The records buffered before the failure are written out once writing works again
+        storage.writer = writer;
+        storage.store(Box::new(30 as Timestamp), Box::new(3 as i32)).unwrap();
+        assert_eq!(fs::metadata("test_write_buffer_failure").unwrap().len(), 57);
+        assert_eq!(storage.retrieve_all().unwrap().into_vec::<Timestamp, i32>(), vec![(10, 1), (20, 2), (30, 3)]);
+    }
+
+    #[test]
+    fn test_write_buffer_write_to() {
+        let _setup_file = SetupFile::new("test_write_buffer_write_to");
+        fs::write("test_write_buffer_write_to", b"0123456789").unwrap();
+
+        let mut buffer = WriteBuffer::new(BufferPolicy::default());
+        buffer.bytes().extend_from_slice(b"abc");
+        buffer.add();
+
+        let file = File::open("test_write_buffer_write_to").unwrap();
+        assert!(buffer.write_to(&file).is_err());
+        assert_eq!(buffer.records, 1);
+        assert_eq!(buffer.bytes(), b"abc");
+
+        let file = OpenOptions::new().append(true).open("test_write_buffer_write_to").unwrap();
+        buffer.write_to(&file).unwrap();
+        assert_eq!(buffer.records, 0);
+        assert_eq!(fs::read("test_write_buffer_write_to").unwrap(), b"0123456789abc");
+    }
+
+    #[test]
+    fn test_write_buffer_delay() {
+        let _setup_file = SetupFile::new("test_write_buffer_delay");
+
+        let policy = BufferPolicy { max_records: 100, max_delay: Some(Duration::from_millis(10)) };
+        let mut storage = FileStorage::<Timestamp, i32>::new("test_write_buffer_delay").unwrap().buffered(policy);
+
+        storage.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(fs::metadata("test_write_buffer_delay").unwrap().len(), 0);
+
+        storage.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+        assert_eq!(fs::metadata("test_write_buffer_delay").unwrap().len(), 38);
+    }
+}
@@ -0,0 +1,154 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Where channels' files go under a storage root.
+//!
+//! Each channel is kept at "<root>/<market>/<symbol>/<channel>", with its sidecar files beside it.  Names come from the
+//! configuration and from requests, so each has to be a single plain path component, and a path given relative to
+//! the root may not climb out of it.  Files from before there was a root, which were kept flat as
+//! "<market>_<symbol>_<channel>", can be moved into the layout.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// The suffixes of the files kept beside a channel's storage file
+const SIDECARS: &[&str] = &[".info", ".quarantine", ".segments", ".heartbeat"];
+
+/// Checks that a market, symbol, or channel name can be used as a path component.  It can't be empty, "." or "..",
+/// or contain a path separator or NUL.
+pub fn check_name(name: &str) -> io::Result<&str> {
+    let unsafe_name = name.is_empty() || name == "." || name == ".." || name.contains(|c| c == '/' || c == '\\' || c == '\0');
+
+    if unsafe_name {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" can't be used as a file name", name.escape_default())))
+    } else {
+        Ok(name)
+    }
+}
+
+/// The storage file of a channel under the root
+pub fn channel_path(root: &Path, market: &str, symbol: &str, channel: &str) -> io::Result<PathBuf> {
+    Ok(root.join(check_name(market)?).join(check_name(symbol)?).join(check_name(channel)?))
+}
+
+/// A path relative to the root, which may not be absolute or climb out of the root with "..".  Fails rather than
+/// normalizing such paths, since they're almost certainly mistakes.
+pub fn resolve(root: &Path, relative: &str) -> io::Result<PathBuf> {
+    let escapes = || io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is outside of the storage root", relative));
+
+    let mut depth = 0usize;
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {},
+            Component::ParentDir => depth = depth.checked_sub(1).ok_or_else(escapes)?,
+            Component::RootDir | Component::Prefix(_) => return Err(escapes()),
+        }
+    }
+
+    if depth == 0 {
+        return Err(escapes());
+    }
+
+    Ok(root.join(relative))
+}
+
+/// The name a channel's file had before there was a storage root
+pub fn flat_name(market: &str, symbol: &str, channel: &str) -> String {
+    format!("{}_{}_{}", market, symbol, channel)
+}
+
+/// Moves a channel's flat file at `flat`, and its sidecar files, to the channel's place under the root.  Does nothing
+/// and returns false if there's no flat file, or if the channel already has a file under the root.
+pub fn migrate(root: &Path, market: &str, symbol: &str, channel: &str, flat: &Path) -> io::Result<bool> {
+    let path = channel_path(root, market, symbol, channel)?;
+
+    if !flat.is_file() || path.exists() {
+        return Ok(false);
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Sidecars first, so that an interrupted migration is picked up again by the storage file still being flat
+    for suffix in SIDECARS {
+        let sidecar = with_suffix(flat, suffix);
+        if sidecar.is_file() {
+            fs::rename(&sidecar, with_suffix(&path, suffix))?;
+        }
+    }
+
+    fs::rename(flat, &path)?;
+    Ok(true)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("btcusd").is_ok());
+        assert!(check_name("btc-usd.perp").is_ok());
+
+        for name in &["", ".", "..", "../trades", "a/b", "a\\b", "a\0b"] {
+            assert!(check_name(name).is_err(), "{:?}", name);
+        }
+
+        let root = Path::new("data");
+        assert_eq!(channel_path(root, "gemini", "btcusd", "trades").unwrap(), Path::new("data/gemini/btcusd/trades"));
+        assert!(channel_path(root, "gemini", "..", "trades").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let root = Path::new("data");
+        assert_eq!(resolve(root, "gemini/trades").unwrap(), Path::new("data/gemini/trades"));
+        assert_eq!(resolve(root, "./a/../b").unwrap(), Path::new("data/./a/../b"));
+
+        for relative in &["", ".", "..", "../trades", "a/../../trades", "/etc/passwd"] {
+            assert!(resolve(root, relative).is_err(), "{:?}", relative);
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let root = Path::new("test_migrate");
+        fs::remove_dir_all(root).ok();
+        fs::create_dir_all(root).unwrap();
+
+        let flat = root.join(flat_name("gemini", "btcusd", "trades"));
+        fs::write(&flat, "records").unwrap();
+        fs::write(with_suffix(&flat, ".info"), "info").unwrap();
+
+        assert!(migrate(root, "gemini", "btcusd", "trades", &flat).unwrap());
+        assert_eq!(fs::read_to_string(root.join("gemini/btcusd/trades")).unwrap(), "records");
+        assert_eq!(fs::read_to_string(root.join("gemini/btcusd/trades.info")).unwrap(), "info");
+        assert!(!flat.exists());
+
+        // Once migrated, there's nothing left to move
+        assert!(!migrate(root, "gemini", "btcusd", "trades", &flat).unwrap());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -13,6 +13,35 @@
 // You should have received a copy of the GNU General Public License
 // along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
 
-pub use self::file::FileStorage;
+pub use self::annotations::{Annotation, Annotations};
+pub use self::deadline::with_deadline;
+pub use self::dedup::DedupStore;
+pub use self::events::{Event, Events};
+pub use self::file::{BufferPolicy, Compaction, Corruption, FileStorage, Locked, OpenMode, ReadOnlyFileStorage, Repair, TailCursor};
+/// The old name of the storage trait, which is deprecated along with the rest of `compat`
+#[allow(deprecated)]
+pub use compat::Storable;
+#[cfg(feature = "postgresql")]
+pub use self::postgres::{PostgresPool, PostgresStorage, SqlValue, connect as connect_postgres};
+pub use self::quarantine::{Quarantine, QuarantinedRecord, Reason, Reprocessed};
+pub use self::registry::{StorageFactory, StorageRegistry};
+pub use self::rollup::{RollupPolicy, RollupStorage};
+pub use self::tiered::{DirectoryStore, ObjectStore, TieredStorage, collect_garbage};
+pub use self::validated::{Bounded, Constraints, ValidatedStore, ValidationPolicy, Violation};
+#[cfg(feature = "s3")]
+pub use self::tiered::S3Store;
 
+pub mod layout;
+
+mod annotations;
+mod deadline;
+mod dedup;
+mod events;
 mod file;
+#[cfg(feature = "postgresql")]
+mod postgres;
+mod quarantine;
+mod registry;
+mod rollup;
+mod tiered;
+mod validated;
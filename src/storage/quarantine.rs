@@ -0,0 +1,247 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Records that were refused, kept aside instead of lost.
+//!
+//! A `Quarantine` is a sidecar file of rejected records, each with a code for why it was rejected.  Since rejected
+//! records may be out of order, the file is a plain log, one record per line, rather than a `FileStorage`.  Once
+//! whatever refused them has been fixed, the records can be taken back out and stored again.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use key_value_store::KeyValueStore;
+use storage::validated::Violation;
+use time_series::Timestamp;
+
+/// Why a record was quarantined
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reason {
+    BelowMin,
+    AboveMax,
+    Skew,
+    Jump,
+    /// The record was on or before the last record of the store
+    OutOfOrder,
+}
+
+impl Reason {
+    /// The code the reason is written as
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Reason::BelowMin => "below_min",
+            Reason::AboveMax => "above_max",
+            Reason::Skew => "skew",
+            Reason::Jump => "jump",
+            Reason::OutOfOrder => "out_of_order",
+        }
+    }
+}
+
+impl From<Violation> for Reason {
+    fn from(violation: Violation) -> Self {
+        match violation {
+            Violation::BelowMin => Reason::BelowMin,
+            Violation::AboveMax => Reason::AboveMax,
+            Violation::Skew => Reason::Skew,
+            Violation::Jump => Reason::Jump,
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.code())
+    }
+}
+
+impl FromStr for Reason {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        match text {
+            "below_min" => Ok(Reason::BelowMin),
+            "above_max" => Ok(Reason::AboveMax),
+            "skew" => Ok(Reason::Skew),
+            "jump" => Ok(Reason::Jump),
+            "out_of_order" => Ok(Reason::OutOfOrder),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown quarantine reason")),
+        }
+    }
+}
+
+/// A record in quarantine
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuarantinedRecord<V> {
+    pub timestamp: Timestamp,
+    pub value: V,
+    pub reason: Reason,
+}
+
+/// What reprocessing a quarantine did
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Reprocessed {
+    /// Records that were stored
+    pub stored: usize,
+    /// Records that were refused again, and are back in quarantine
+    pub quarantined: usize,
+}
+
+/// A sidecar file of rejected records.  Clones share the file, so a quarantine can be listed and reprocessed while
+/// the store that fills it keeps running.
+#[derive(Clone)]
+pub struct Quarantine {
+    filename: Arc<String>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Quarantine {
+    /// Opens the quarantine in `filename`, creating it if it doesn't exist
+    pub fn open(filename: &str) -> io::Result<Self> {
+        OpenOptions::new().append(true).create(true).open(filename)?;
+
+        Ok(Self {
+            filename: Arc::new(filename.to_string()),
+            lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Waits for the quarantined records to reach the disk
+    pub fn sync(&self) -> io::Result<()> {
+        let _lock = self.lock()?;
+        OpenOptions::new().append(true).open(&*self.filename)?.sync_all()
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, ()>> {
+        self.lock.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Quarantine was poisoned"))
+    }
+
+    /// Quarantines a record.  `value` is written as text, and must not contain whitespace.
+    pub fn add(&self, timestamp: Timestamp, value: &str, reason: Reason) -> io::Result<()> {
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Quarantined values can't contain whitespace"));
+        }
+
+        let _lock = self.lock()?;
+        let mut file = OpenOptions::new().append(true).open(&*self.filename)?;
+
+        // One write per record, so that a record is never split by a crash
+        file.write_all(format!("{} {} {}\n", timestamp, value, reason).as_bytes())
+    }
+
+    /// Returns the quarantined records, in the order they were quarantined
+    pub fn records<V>(&self) -> io::Result<Vec<QuarantinedRecord<V>>> where V: FromStr {
+        let _lock = self.lock()?;
+        self.read()
+    }
+
+    /// Removes every record from the quarantine and returns them
+    pub fn take<V>(&self) -> io::Result<Vec<QuarantinedRecord<V>>> where V: FromStr {
+        let _lock = self.lock()?;
+        let records = self.read()?;
+        File::create(&*self.filename)?;
+        Ok(records)
+    }
+
+    fn read<V>(&self) -> io::Result<Vec<QuarantinedRecord<V>>> where V: FromStr {
+        let contents = fs::read_to_string(&*self.filename)?;
+
+        contents.lines().filter(|line| !line.is_empty()).map(|line| {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid quarantined record");
+            let fields = line.split(' ').collect::<Vec<&str>>();
+            if fields.len() != 3 {
+                return Err(invalid());
+            }
+
+            Ok(QuarantinedRecord {
+                timestamp: fields[0].parse().map_err(|_| invalid())?,
+                value: fields[1].parse().map_err(|_| invalid())?,
+                reason: fields[2].parse()?,
+            })
+        }).collect()
+    }
+
+    /// Takes every record out of the quarantine and stores it again, in timestamp order.  If the store quarantines
+    /// into this quarantine, records it refuses again end up back in it with their new reason.  If storing fails
+    /// some other way, the records that weren't stored yet are put back and the error is returned.
+    pub fn reprocess<V>(&self, store: &mut dyn KeyValueStore) -> io::Result<Reprocessed> where V: 'static + Copy + FromStr + ToString {
+        let mut records = self.take::<V>()?;
+        records.sort_by_key(|record| record.timestamp);
+
+        let mut reprocessed = Reprocessed::default();
+
+        for (i, record) in records.iter().enumerate() {
+            let stored_before = store.len();
+
+            if let Err(error) = store.store(Box::new(record.timestamp), Box::new(record.value)) {
+                for record in &records[i..] {
+                    self.add(record.timestamp, &record.value.to_string(), record.reason)?;
+                }
+                return Err(error);
+            }
+
+            if store.len() > stored_before {
+                reprocessed.stored += 1;
+            } else {
+                reprocessed.quarantined += 1;
+            }
+        }
+
+        Ok(reprocessed)
+    }
+
+    /// The number of records in quarantine
+    pub fn len(&self) -> io::Result<usize> {
+        let _lock = self.lock()?;
+        Ok(fs::read_to_string(&*self.filename)?.lines().filter(|line| !line.is_empty()).count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use util::SetupFile;
+
+    #[test]
+    fn test_quarantine() {
+        let _setup_file = SetupFile::new("test_quarantine");
+
+        let quarantine = Quarantine::open("test_quarantine").unwrap();
+        quarantine.add(5, "100", Reason::AboveMax).unwrap();
+        quarantine.add(3, "7", Reason::OutOfOrder).unwrap();
+        assert!(quarantine.add(6, "1 2", Reason::Jump).is_err());
+
+        assert_eq!(quarantine.len().unwrap(), 2);
+        assert_eq!(quarantine.clone().records::<i32>().unwrap(), vec![
+            QuarantinedRecord { timestamp: 5, value: 100, reason: Reason::AboveMax },
+            QuarantinedRecord { timestamp: 3, value: 7, reason: Reason::OutOfOrder },
+        ]);
+
+        assert_eq!(quarantine.take::<i32>().unwrap().len(), 2);
+        assert_eq!(quarantine.len().unwrap(), 0);
+
+        for reason in &[Reason::BelowMin, Reason::AboveMax, Reason::Skew, Reason::Jump, Reason::OutOfOrder] {
+            assert_eq!(reason.to_string().parse::<Reason>().unwrap(), *reason);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Opening stores by the name of their value type.
+//!
+//! `FileStorage` is generic over its values, so code that opens one has to name the value type at compile time.  A
+//! `StorageRegistry` maps names, as a configuration file would give them, to factories that open a store of that type
+//! from its file, so that the type can be picked at run time.  Value types defined outside this crate are registered
+//! by whoever defines them.
+
+use std::collections::HashMap;
+use std::io;
+
+use key_value_store::Storable;
+use pooled_time_series::{Poolable, PooledTimeSeries};
+use schema::{Identified, Quote, Sourced};
+use storage::FileStorage;
+use time_series::{TimeUnit, Timestamp};
+
+/// Opens the store kept in a file, with timestamps in the given unit
+pub type StorageFactory = Box<dyn Fn(&str, TimeUnit) -> io::Result<Box<dyn PooledTimeSeries>> + Send + Sync>;
+
+pub struct StorageRegistry {
+    factories: HashMap<String, StorageFactory>,
+}
+
+impl StorageRegistry {
+    /// A registry with no value types
+    pub fn new() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Registers a factory under `name`, replacing any that was there
+    pub fn register<F>(&mut self, name: &str, factory: F) where F: 'static + Fn(&str, TimeUnit) -> io::Result<Box<dyn PooledTimeSeries>> + Send + Sync {
+        self.factories.insert(name.to_string(), Box::new(factory));
+    }
+
+    /// Registers `FileStorage` of `V` values under `name`
+    pub fn register_file<V>(&mut self, name: &str) where V: Storable<FileStorage<Timestamp, V>> + Poolable {
+        self.register(name, |file, unit| Ok(Box::new(FileStorage::<Timestamp, V>::with_unit(file, unit)?) as Box<dyn PooledTimeSeries>));
+    }
+
+    /// Opens the store of `name` values kept in `file`.  Fails with `InvalidInput` if no value type has that name.
+    pub fn open(&self, name: &str, file: &str, unit: TimeUnit) -> io::Result<Box<dyn PooledTimeSeries>> {
+        let factory = self.factories.get(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("No storage is registered for \"{}\" values", name)))?;
+
+        factory(file, unit)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// The names of the registered value types, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.factories.keys().map(|name| name.as_str()).collect::<Vec<&str>>();
+        names.sort();
+        names
+    }
+}
+
+/// A registry of the value types this crate can store: "u64", "quote" for quotes of `u64` prices, "sourced" for `u64`
+/// values tagged with their source, and "trade" for `u64` values of trades with their IDs
+impl Default for StorageRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register_file::<Timestamp>("u64");
+        registry.register_file::<Quote<Timestamp>>("quote");
+        registry.register_file::<Sourced<Timestamp>>("sourced");
+        registry.register_file::<Identified<Timestamp>>("trade");
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use util::SetupFile;
+
+    #[test]
+    fn test_storage_registry() {
+        let _u64_file = SetupFile::new("test_storage_registry_u64");
+        let _i32_file = SetupFile::new("test_storage_registry_i32");
+
+        let mut registry = StorageRegistry::default();
+        assert_eq!(registry.names(), vec!["quote", "sourced", "trade", "u64"]);
+
+        let mut storage = registry.open("u64", "test_storage_registry_u64", TimeUnit::Microseconds).unwrap();
+        storage.store(Box::new(10 as Timestamp), Box::new(3 as Timestamp)).unwrap();
+        assert_eq!(storage.time_unit(), TimeUnit::Microseconds);
+        assert_eq!(storage.retrieve_all().unwrap().into_vec::<Timestamp, Timestamp>(), vec![(10, 3)]);
+
+        assert_eq!(registry.open("i32", "test_storage_registry_i32", TimeUnit::Milliseconds).err().map(|error| error.kind()), Some(io::ErrorKind::InvalidInput));
+
+        registry.register_file::<i32>("i32");
+        assert!(registry.contains("i32"));
+
+        let mut storage = registry.open("i32", "test_storage_registry_i32", TimeUnit::Milliseconds).unwrap();
+        storage.store(Box::new(10 as Timestamp), Box::new(-3 as i32)).unwrap();
+        assert_eq!(storage.retrieve_all().unwrap().into_vec::<Timestamp, i32>(), vec![(10, -3)]);
+    }
+}
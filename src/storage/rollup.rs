@@ -0,0 +1,415 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Lossy storage for long histories.
+//!
+//! Recent records are kept at full resolution in a raw `FileStorage`.  Once they've aged out of a window, they're
+//! pooled into buckets, a minute long by default, which are kept in a second `FileStorage` at `<filename>.rollup`,
+//! and the raw records are dropped.  Each bucket is kept as a record at the start of the bucket, so reads see the
+//! buckets followed by the raw records, and a query pooled at the bucket interval or coarser gives the same results
+//! it would have before the roll-up, for pooling methods that can be pooled again, like `End`, `High`, or `Low`.
+
+use std::cmp;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::sync::mpsc::Receiver;
+
+use key_value_store::{Data, IoStats, KeyValueStore, Notification, Retrieval, Storable, StoreStats, Subscribers};
+use pooled_time_series::{BucketAnchor, Interval, Poolable, PooledTimeSeries, PoolingMethod, PoolingOptions, pool_records, pool_with_carry};
+use storage::file::FileStorage;
+use time_series::{RetrievalDirection, TimeSeries, TimeUnit, Timestamp};
+
+/// When and how raw records are rolled up.  The lengths are in the unit of the storage's timestamps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RollupPolicy {
+    /// How far back from the latest record the raw records are kept
+    pub window: Timestamp,
+    /// The length of the buckets older records are pooled into
+    pub interval: Interval,
+    /// How each bucket is pooled
+    pub pooling: PoolingMethod,
+    /// How far past the window the oldest raw record may get before a store rolls the raw records up.  Every roll-up
+    /// rewrites the raw file, so they're done in batches rather than a bucket at a time.
+    pub batch: Timestamp,
+}
+
+impl Default for RollupPolicy {
+    fn default() -> Self {
+        Self {
+            window: 7 * 24 * 60 * 60 * 1000,
+            interval: Interval::minutes(1),
+            pooling: PoolingMethod::End,
+            batch: 60 * 60 * 1000,
+        }
+    }
+}
+
+pub struct RollupStorage<V> {
+    filename: String,
+    unit: TimeUnit,
+    policy: RollupPolicy,
+    raw: FileStorage<Timestamp, V>,
+    rolled: FileStorage<Timestamp, V>,
+    /// Just past the last rolled up bucket.  Every raw record is at or after this.
+    rolled_end: Timestamp,
+    /// The first raw record, if there is one
+    raw_start: Option<Timestamp>,
+    subscribers: Subscribers,
+}
+
+impl<V> RollupStorage<V> where V: Storable<FileStorage<Timestamp, V>> + Poolable {
+    pub fn new(filename: &str, policy: RollupPolicy) -> io::Result<Self> {
+        Self::with_unit(filename, TimeUnit::Milliseconds, policy)
+    }
+
+    /// Opens the storage of timestamps in `unit`, finishing any roll-up that was interrupted
+    pub fn with_unit(filename: &str, unit: TimeUnit, policy: RollupPolicy) -> io::Result<Self> {
+        let rolled = FileStorage::<Timestamp, V>::with_unit(&rollup_filename(filename), unit)?;
+        let rolled_end = match rolled.last_key().and_then(|key| key.downcast_ref::<Timestamp>().cloned()) {
+            Some(last_bucket) => last_bucket.saturating_add(policy.interval.get()),
+            None => 0,
+        };
+
+        let raw = FileStorage::<Timestamp, V>::with_unit(filename, unit)?;
+        let raw_start = nearest(&raw, 0, Some(RetrievalDirection::Forward))?.map(|retrieval| retrieval.into_single::<Timestamp, V>().0);
+
+        let mut storage = Self {
+            filename: filename.to_string(),
+            unit: unit,
+            policy: policy,
+            raw: raw,
+            rolled: rolled,
+            rolled_end: rolled_end,
+            raw_start: raw_start,
+            subscribers: Subscribers::default(),
+        };
+
+        // The buckets are saved before the raw records are dropped, so an interrupted roll-up leaves raw records
+        // that have already been rolled up
+        if storage.raw_start.map_or(false, |raw_start| raw_start < rolled_end) {
+            storage.rewrite_raw()?;
+        }
+
+        Ok(storage)
+    }
+
+    pub fn policy(&self) -> RollupPolicy {
+        self.policy
+    }
+
+    /// Rolls up every raw record that has aged out of the window as of `now`, returning how many were rolled up.
+    /// Only whole buckets are rolled up, so a bucket is never split between the two files.
+    pub fn roll_up(&mut self, now: Timestamp) -> io::Result<usize> {
+        let interval = self.policy.interval.get();
+
+        let cutoff = now.saturating_sub(self.policy.window);
+        let cutoff = cutoff - cutoff % interval;
+        if cutoff <= self.rolled_end || self.raw.len() == 0 {
+            return Ok(0);
+        }
+
+        let records = self.raw.retrieve_range(self.rolled_end..cutoff)?.into_vec::<Timestamp, V>();
+        if records.is_empty() {
+            return Ok(0);
+        }
+
+        let pooling_options = PoolingOptions {
+            interval: self.policy.interval,
+            pooling: self.policy.pooling,
+            anchor: BucketAnchor::RequestedStart,
+            unit: self.unit,
+            ..PoolingOptions::default()
+        };
+
+        let first_bucket = records[0].0 - records[0].0 % interval;
+        let buckets = pool_records(records.iter().map(|&record| Ok(record)), first_bucket, Some(cutoff), pooling_options)?;
+
+        for (bucket_start, value) in buckets {
+            self.rolled.store(Box::new(bucket_start), Box::new(value))?;
+        }
+        self.rolled.sync()?;
+
+        self.rolled_end = cutoff;
+        self.rewrite_raw()?;
+
+        Ok(records.len())
+    }
+
+    /// Replaces the raw file with one that holds only the records that haven't been rolled up
+    fn rewrite_raw(&mut self) -> io::Result<()> {
+        let remaining = self.raw.retrieve_from(self.rolled_end)?.into_vec::<Timestamp, V>();
+        let temporary_filename = format!("{}.rewrite", self.filename);
+
+        if let Err(error) = fs::remove_file(&temporary_filename) {
+            if error.kind() != io::ErrorKind::NotFound {
+                return Err(error);
+            }
+        }
+
+        {
+            let mut rewrite = FileStorage::<Timestamp, V>::with_unit(&temporary_filename, self.unit)?;
+            for &(timestamp, value) in &remaining {
+                rewrite.store(Box::new(timestamp), Box::new(value))?;
+            }
+            rewrite.sync()?;
+        }
+
+        fs::rename(&temporary_filename, &self.filename)?;
+        self.raw = FileStorage::with_unit(&self.filename, self.unit)?;
+        self.raw_start = remaining.first().map(|record| record.0);
+
+        Ok(())
+    }
+
+    /// Returns the records within the range from both files
+    fn records(&self, range: Range<Timestamp>) -> io::Result<Vec<(Timestamp, V)>> {
+        let mut records = Vec::new();
+
+        let rolled_end = cmp::min(range.end, self.rolled_end);
+        if range.start < rolled_end && self.rolled.len() > 0 {
+            records.extend(self.rolled.retrieve_range(range.start..rolled_end)?.into_vec::<Timestamp, V>());
+        }
+
+        let raw_start = cmp::max(range.start, self.rolled_end);
+        if raw_start < range.end && self.raw.len() > 0 {
+            records.extend(self.raw.retrieve_range(raw_start..range.end)?.into_vec::<Timestamp, V>());
+        }
+
+        Ok(records)
+    }
+
+    /// Pools the records in the range.  Without a requested start, buckets start at the first record.
+    fn pool(&self, range: Range<Timestamp>, requested_start: Option<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        let records = self.records(range.clone())?;
+        let values = pool_with_carry(self, records, range, requested_start, pooling_options)?;
+
+        Ok(Retrieval::new(Box::new(values)))
+    }
+}
+
+impl<V> KeyValueStore for RollupStorage<V> where V: Storable<FileStorage<Timestamp, V>> + Poolable {
+    fn len(&self) -> usize {
+        self.rolled.len() + self.raw.len()
+    }
+
+    fn io_stats(&self) -> IoStats {
+        self.rolled.io_stats() + self.raw.io_stats()
+    }
+
+    fn last_key(&self) -> Option<Box<Data>> {
+        self.raw.last_key().or_else(|| self.rolled.last_key())
+    }
+
+    fn size_on_disk(&self) -> io::Result<u64> {
+        Ok(self.rolled.size_on_disk()? + self.raw.size_on_disk()?)
+    }
+
+    /// The rates are of the raw records alone, over the windows their history covers
+    fn stats(&self) -> io::Result<StoreStats> {
+        let rolled = self.rolled.stats()?;
+        let raw = self.raw.stats()?;
+
+        Ok(StoreStats {
+            records: rolled.records + raw.records,
+            first_timestamp: rolled.first_timestamp.or(raw.first_timestamp),
+            last_timestamp: raw.last_timestamp.or(rolled.last_timestamp),
+            bytes_on_disk: rolled.bytes_on_disk + raw.bytes_on_disk,
+            rates: raw.rates,
+        })
+    }
+
+    /// The buckets are already synced whenever they're rolled up, so only the raw records need it
+    fn sync(&self) -> io::Result<()> {
+        self.raw.sync()
+    }
+
+    /// Rolls up the raw records once the oldest has aged out of the window by more than a batch.  If that fails, the
+    /// store fails with it, even though the record was stored.
+    fn store(&mut self, key: Box<Data>, value: Box<Data>) -> io::Result<()> {
+        if let Some(&key) = key.downcast_ref::<Timestamp>() {
+            if key < self.rolled_end {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Passed key was before the last rolled up bucket"));
+            }
+        }
+
+        let record = (key.downcast_ref::<Timestamp>().cloned(), value.downcast_ref::<V>().cloned());
+
+        self.raw.store(key, value)?;
+
+        if let (Some(key), Some(value)) = record {
+            // The raw store is replaced whenever records are rolled up, so subscribers are kept here instead
+            self.subscribers.notify(key, value);
+
+            let raw_start = *self.raw_start.get_or_insert(key);
+            let due = raw_start.saturating_add(self.policy.window).saturating_add(self.policy.batch);
+            if key >= due {
+                self.roll_up(key)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn subscribe(&self) -> io::Result<Receiver<Notification>> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl<V> TimeSeries for RollupStorage<V> where V: Storable<FileStorage<Timestamp, V>> + Poolable {
+    fn retrieve_nearest(&self, timestamp: Timestamp, retrieval_direction: Option<RetrievalDirection>) -> io::Result<Retrieval> {
+        // Look in the file the timestamp falls in first, then, if there's a direction, carry on into the other
+        let (first, second) = if timestamp < self.rolled_end { (&self.rolled, &self.raw) } else { (&self.raw, &self.rolled) };
+
+        let found = match nearest(first, timestamp, retrieval_direction)? {
+            Some(retrieval) => Some(retrieval),
+            None if retrieval_direction.is_some() => nearest(second, timestamp, retrieval_direction)?,
+            None => None,
+        };
+
+        found.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Search key was not found"))
+    }
+
+    fn retrieve_all(&self) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.records(0..Timestamp::max_value())?)))
+    }
+
+    fn retrieve_from(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.records(timestamp..Timestamp::max_value())?)))
+    }
+
+    fn retrieve_to(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.records(0..timestamp)?)))
+    }
+
+    fn retrieve_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.records(range)?)))
+    }
+
+    fn preload(&self, range: Range<Timestamp>) -> io::Result<()> {
+        self.rolled.preload(range.clone())?;
+        self.raw.preload(range)
+    }
+
+    /// Gaps are found in each file separately, so a gap that spans the end of the rolled up buckets is found in two
+    /// parts
+    fn find_gaps(&self, min_gap: Timestamp, range: Range<Timestamp>) -> io::Result<Vec<Range<Timestamp>>> {
+        let mut gaps = Vec::new();
+
+        let rolled_end = cmp::min(range.end, self.rolled_end);
+        if range.start < rolled_end {
+            gaps.extend(self.rolled.find_gaps(min_gap, range.start..rolled_end)?);
+        }
+
+        let raw_start = cmp::max(range.start, self.rolled_end);
+        if raw_start < range.end {
+            gaps.extend(self.raw.find_gaps(min_gap, raw_start..range.end)?);
+        }
+
+        Ok(gaps)
+    }
+
+    fn time_unit(&self) -> TimeUnit {
+        self.unit
+    }
+}
+
+impl<V> PooledTimeSeries for RollupStorage<V> where V: Storable<FileStorage<Timestamp, V>> + Poolable {
+    fn pool_all(&self, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.pool(0..Timestamp::max_value(), None, pooling_options)
+    }
+
+    fn pool_from(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.pool(timestamp..Timestamp::max_value(), Some(timestamp), pooling_options)
+    }
+
+    fn pool_to(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.pool(0..timestamp, None, pooling_options)
+    }
+
+    fn pool_range(&self, range: Range<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.pool(range.clone(), Some(range.start), pooling_options)
+    }
+}
+
+fn rollup_filename(filename: &str) -> String {
+    format!("{}.rollup", filename)
+}
+
+/// The nearest record in one of the files, or `None` if it has none in that direction
+fn nearest<V>(storage: &FileStorage<Timestamp, V>, timestamp: Timestamp, retrieval_direction: Option<RetrievalDirection>) -> io::Result<Option<Retrieval>> where V: Storable<FileStorage<Timestamp, V>> {
+    if storage.len() == 0 {
+        return Ok(None);
+    }
+
+    match storage.retrieve_nearest(timestamp, retrieval_direction) {
+        Ok(retrieval) => Ok(Some(retrieval)),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use key_value_store::RATE_WINDOWS;
+    use util::SetupFile;
+
+    #[test]
+    fn test_rollup_storage() {
+        let _setup_file = SetupFile::new("test_rollup_storage");
+        let _setup_rollup = SetupFile::new("test_rollup_storage.rollup");
+
+        let policy = RollupPolicy { window: 100, interval: Interval::millis(10), pooling: PoolingMethod::End, batch: 50 };
+        let mut rs = RollupStorage::<i32>::new("test_rollup_storage", policy).unwrap();
+
+        for timestamp in (0..150).step_by(5) {
+            rs.store(Box::new(timestamp as Timestamp), Box::new(timestamp as i32)).unwrap();
+        }
+        assert_eq!(rs.rolled.len(), 0);
+
+        // The oldest record is now a batch past the window, so everything before 50 is rolled up
+        rs.store(Box::new(150 as Timestamp), Box::new(150 as i32)).unwrap();
+        assert_eq!(rs.rolled.len(), 5);
+        assert_eq!(rs.raw.len(), 21);
+        assert!(rs.store(Box::new(45 as Timestamp), Box::new(0 as i32)).is_err());
+
+        let retrieval = rs.retrieve_range(30..60).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(30, 35), (40, 45), (50, 50), (55, 55)]));
+
+        let retrieval = rs.retrieve_nearest(48, Some(RetrievalDirection::Backward)).unwrap();
+        assert_eq!(retrieval.as_single::<Timestamp, i32>(), Some(&(40, 45)));
+
+        let retrieval = rs.retrieve_nearest(41, Some(RetrievalDirection::Forward)).unwrap();
+        assert_eq!(retrieval.as_single::<Timestamp, i32>(), Some(&(50, 50)));
+
+        // Pooling at the bucket interval is unchanged by the roll-up
+        let pooling_options = PoolingOptions { interval: Interval::millis(20), pooling: PoolingMethod::End, ..PoolingOptions::default() };
+        let retrieval = rs.pool_range(0..80, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(0, 15), (20, 35), (40, 55), (60, 75)]));
+
+        drop(rs);
+
+        let rs = RollupStorage::<i32>::new("test_rollup_storage", policy).unwrap();
+        assert_eq!(rs.len(), 26);
+
+        // The first bucket starts the history, and nothing has been stored in any window back from now
+        let stats = rs.stats().unwrap();
+        assert_eq!((stats.records, stats.first_timestamp, stats.last_timestamp), (26, Some(0), Some(150)));
+        assert_eq!(stats.rates.len(), RATE_WINDOWS.len());
+        assert!(stats.rates.iter().all(|rate| rate.per_second == 0.0));
+    }
+}
@@ -0,0 +1,55 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use storage::tiered::ObjectStore;
+
+/// Keeps archived segments as files in a local directory, such as a network mount
+pub struct DirectoryStore {
+    directory: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new(directory: &str) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+
+        Ok(Self {
+            directory: PathBuf::from(directory),
+        })
+    }
+}
+
+impl ObjectStore for DirectoryStore {
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.directory.join(key);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = File::create(path)?;
+        file.write_all(data)?;
+        file.sync_all()
+    }
+
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        File::open(self.directory.join(key))?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
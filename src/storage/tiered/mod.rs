@@ -0,0 +1,589 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hot/cold tiered storage.
+//!
+//! Recent records live in a hot `FileStorage`.  Older records are archived in segments to a cold object
+//! store, such as an S3 bucket, and are downloaded again whenever a retrieval needs them.  Each segment is
+//! kept in the same format as a `FileStorage` file.  The list of archived segments is kept next to the hot
+//! file, in `<filename>.segments`.
+
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use key_value_store::{Data, IoStats, KeyValueStore, Notification, Retrieval, Storable, StoreStats, Subscribers};
+use pooled_time_series::{Poolable, PooledTimeSeries, PoolingOptions, pool_with_carry};
+use storage::file::{FileStorage, RecordReader, write_record};
+use time_series::{RetrievalDirection, TimeSeries, Timestamp};
+
+pub use self::directory::DirectoryStore;
+#[cfg(feature = "s3")]
+pub use self::s3::S3Store;
+
+/// The most records archived into a single segment
+const SEGMENT_RECORDS: usize = 65536;
+
+/// The number of downloaded segments kept in memory
+const CACHED_SEGMENTS: usize = 4;
+
+/// Somewhere to keep archived segments
+pub trait ObjectStore: Send + Sync {
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()>;
+    fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+}
+
+/// A run of consecutive records that has been archived to the cold store
+#[derive(Clone, Debug, PartialEq)]
+struct Segment {
+    /// The timestamp of the first record
+    start: Timestamp,
+    /// Just past the timestamp of the last record
+    end: Timestamp,
+    records: usize,
+    key: String,
+}
+
+pub struct TieredStorage<V> {
+    filename: String,
+    hot: FileStorage<Timestamp, V>,
+    cold: Box<dyn ObjectStore>,
+    segments: Vec<Segment>,
+    cache: Mutex<Vec<(usize, Vec<(Timestamp, V)>)>>,
+    subscribers: Subscribers,
+}
+
+impl<V> TieredStorage<V> where V: Storable<FileStorage<Timestamp, V>> {
+    pub fn new(filename: &str, cold: Box<dyn ObjectStore>) -> io::Result<Self> {
+        let segments = match File::open(index_filename(filename)) {
+            Ok(file) => read_index(file)?,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self {
+            filename: filename.to_string(),
+            hot: FileStorage::new(filename)?,
+            cold: cold,
+            segments: segments,
+            cache: Mutex::new(Vec::new()),
+            subscribers: Subscribers::default(),
+        })
+    }
+
+    /// Archives every record older than `age`, as of `now`.
+    pub fn archive_older_than(&mut self, age: Timestamp, now: Timestamp) -> io::Result<usize> {
+        self.archive(now.saturating_sub(age))
+    }
+
+    /// Moves every hot record before the cutoff to the cold store, returning how many were moved.
+    pub fn archive(&mut self, cutoff: Timestamp) -> io::Result<usize> {
+        let cold_end = self.cold_end();
+        if cutoff <= cold_end || self.hot.len() == 0 {
+            return Ok(0);
+        }
+
+        let records = self.hot.retrieve_range(cold_end..cutoff)?.into_vec::<Timestamp, V>();
+        if records.is_empty() {
+            return Ok(0);
+        }
+
+        let prefix = Path::new(&self.filename).file_name().and_then(|name| name.to_str()).unwrap_or("segments").to_string();
+
+        for chunk in records.chunks(SEGMENT_RECORDS) {
+            let mut data = Vec::new();
+            for &(timestamp, value) in chunk {
+                write_record(&mut data, timestamp, value)?;
+            }
+
+            let segment = Segment {
+                start: chunk[0].0,
+                end: chunk[chunk.len() - 1].0 + 1,
+                records: chunk.len(),
+                key: format!("{}/{}-{}", prefix, chunk[0].0, chunk[chunk.len() - 1].0),
+            };
+
+            self.cold.put(&segment.key, &data)?;
+            self.segments.push(segment);
+        }
+
+        // The index has to be saved before the hot records are dropped.  If we're interrupted in between,
+        // the leftover hot records are ignored, since they're before the end of the cold records.
+        self.save_index()?;
+        self.rewrite_hot()?;
+
+        Ok(records.len())
+    }
+
+    /// Just past the last archived record.  Every hot record is at or after this.
+    fn cold_end(&self) -> Timestamp {
+        self.segments.last().map_or(0, |segment| segment.end)
+    }
+
+    fn save_index(&self) -> io::Result<()> {
+        let index_filename = index_filename(&self.filename);
+        let temporary_filename = format!("{}.tmp", index_filename);
+
+        {
+            let mut file = File::create(&temporary_filename)?;
+            for segment in &self.segments {
+                writeln!(file, "{} {} {} {}", segment.start, segment.end, segment.records, segment.key)?;
+            }
+            file.sync_all()?;
+        }
+
+        fs::rename(temporary_filename, index_filename)
+    }
+
+    /// Replaces the hot file with one that holds only the records that haven't been archived
+    fn rewrite_hot(&mut self) -> io::Result<()> {
+        let remaining = self.hot.retrieve_from(self.cold_end())?.into_vec::<Timestamp, V>();
+        let temporary_filename = format!("{}.rewrite", self.filename);
+
+        if let Err(error) = fs::remove_file(&temporary_filename) {
+            if error.kind() != io::ErrorKind::NotFound {
+                return Err(error);
+            }
+        }
+
+        {
+            let mut rewrite = FileStorage::<Timestamp, V>::new(&temporary_filename)?;
+            for (timestamp, value) in remaining {
+                rewrite.store(Box::new(timestamp), Box::new(value))?;
+            }
+        }
+
+        fs::rename(&temporary_filename, &self.filename)?;
+        self.hot = FileStorage::new(&self.filename)?;
+
+        Ok(())
+    }
+
+    fn cache<'a>(&'a self) -> io::Result<MutexGuard<'a, Vec<(usize, Vec<(Timestamp, V)>)>>> {
+        self.cache.lock().map_err(|_| io::Error::new(io::ErrorKind::Other, "Segment cache was poisoned"))
+    }
+
+    /// Returns the records of a segment, downloading it if it isn't cached
+    fn load_segment(&self, index: usize) -> io::Result<Vec<(Timestamp, V)>> {
+        if let Some(&(_, ref records)) = self.cache()?.iter().find(|&&(cached, _)| cached == index) {
+            return Ok(records.clone());
+        }
+
+        let segment = &self.segments[index];
+        let data = self.cold.get(&segment.key)?;
+
+        let item_size = <Timestamp as Storable<FileStorage<Timestamp, V>>>::size() + 1 + V::size() + 1;
+        if segment.records.checked_mul(item_size) != Some(data.len()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Archived segment is the wrong size"));
+        }
+
+        let mut records = Vec::with_capacity(segment.records);
+        RecordReader::<Timestamp, V, Cursor<Vec<u8>>>::new(&mut Cursor::new(data), segment.records).read_all(&mut records)?;
+
+        let mut cache = self.cache()?;
+        if cache.len() >= CACHED_SEGMENTS {
+            cache.remove(0);
+        }
+        cache.push((index, records.clone()));
+
+        Ok(records)
+    }
+
+    /// Returns the archived records within the range
+    fn cold_records(&self, range: Range<Timestamp>) -> io::Result<Vec<(Timestamp, V)>> {
+        let mut records = Vec::new();
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            if segment.start < range.end && segment.end > range.start {
+                records.extend(self.load_segment(index)?.into_iter().filter(|r| r.0 >= range.start && r.0 < range.end));
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Returns the records within the range from both tiers
+    fn records(&self, range: Range<Timestamp>) -> io::Result<Vec<(Timestamp, V)>> {
+        let mut records = self.cold_records(range.clone())?;
+
+        let hot_start = cmp::max(range.start, self.cold_end());
+        if hot_start < range.end && self.hot.len() > 0 {
+            records.extend(self.hot.retrieve_range(hot_start..range.end)?.into_vec::<Timestamp, V>());
+        }
+
+        Ok(records)
+    }
+}
+
+impl<V> TieredStorage<V> where V: Storable<FileStorage<Timestamp, V>> + Poolable {
+    /// Pools the records in the range.  Without a requested start, buckets start at the first record.
+    fn pool(&self, range: Range<Timestamp>, requested_start: Option<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        let records = self.records(range.clone())?;
+        let values = pool_with_carry(self, records, range, requested_start, pooling_options)?;
+
+        Ok(Retrieval::new(Box::new(values)))
+    }
+}
+
+impl<V> KeyValueStore for TieredStorage<V> where V: Storable<FileStorage<Timestamp, V>> {
+    fn len(&self) -> usize {
+        self.segments.iter().map(|segment| segment.records).sum::<usize>() + self.hot.len()
+    }
+
+    fn io_stats(&self) -> IoStats {
+        self.hot.io_stats()
+    }
+
+    fn last_key(&self) -> Option<Box<Data>> {
+        self.hot.last_key().or_else(|| self.segments.last().map(|segment| Box::new(segment.end - 1) as Box<Data>))
+    }
+
+    /// Only the hot records and the index are local.  Archived segments are counted by the object store, if at all.
+    fn size_on_disk(&self) -> io::Result<u64> {
+        let index = match fs::metadata(index_filename(&self.filename)) {
+            Ok(metadata) => metadata.len(),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => 0,
+            Err(error) => return Err(error),
+        };
+
+        Ok(self.hot.size_on_disk()? + index)
+    }
+
+    /// The rates are of the hot records alone, over the windows their history covers
+    fn stats(&self) -> io::Result<StoreStats> {
+        let hot = self.hot.stats()?;
+
+        Ok(StoreStats {
+            records: self.len(),
+            first_timestamp: self.segments.first().map(|segment| segment.start).or(hot.first_timestamp),
+            last_timestamp: hot.last_timestamp.or_else(|| self.segments.last().map(|segment| segment.end - 1)),
+            bytes_on_disk: self.size_on_disk()?,
+            rates: hot.rates,
+        })
+    }
+
+    /// The index is already synced whenever it's replaced, so only the hot records need it
+    fn sync(&self) -> io::Result<()> {
+        self.hot.sync()
+    }
+
+    fn store(&mut self, key: Box<Data>, value: Box<Data>) -> io::Result<()> {
+        if let Some(&key) = key.downcast_ref::<Timestamp>() {
+            if key < self.cold_end() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Passed key was equal to or before the last archived key"));
+            }
+        }
+
+        let record = (key.downcast_ref::<Timestamp>().cloned(), value.downcast_ref::<V>().cloned());
+
+        self.hot.store(key, value)?;
+
+        // The hot store is replaced whenever records are archived, so subscribers are kept here instead
+        if let (Some(key), Some(value)) = record {
+            self.subscribers.notify(key, value);
+        }
+
+        Ok(())
+    }
+
+    fn subscribe(&self) -> io::Result<Receiver<Notification>> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl<V> TimeSeries for TieredStorage<V> where V: Storable<FileStorage<Timestamp, V>> {
+    fn retrieve_nearest(&self, timestamp: Timestamp, retrieval_direction: Option<RetrievalDirection>) -> io::Result<Retrieval> {
+        let cold_end = self.cold_end();
+
+        if timestamp >= cold_end && self.hot.len() > 0 {
+            match self.hot.retrieve_nearest(timestamp, retrieval_direction) {
+                // Before the first hot record, looking backward, the answer is the last archived record
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound && retrieval_direction == Some(RetrievalDirection::Backward) => (),
+                result => return result,
+            }
+        }
+
+        let record = match retrieval_direction {
+            None => self.cold_records(timestamp..timestamp.saturating_add(1))?.pop(),
+            Some(RetrievalDirection::Backward) => {
+                let segment = self.segments.iter().rposition(|segment| segment.start <= timestamp);
+                match segment {
+                    Some(index) => self.load_segment(index)?.into_iter().filter(|r| r.0 <= timestamp).last(),
+                    None => None,
+                }
+            },
+            Some(RetrievalDirection::Forward) => match self.cold_records(timestamp..cold_end)?.first() {
+                Some(&record) => Some(record),
+                None if self.hot.len() > 0 => return self.hot.retrieve_nearest(cmp::max(timestamp, cold_end), retrieval_direction),
+                None => None,
+            },
+        };
+
+        match record {
+            Some(record) => Ok(Retrieval::new(Box::new(record))),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "Search key was not found")),
+        }
+    }
+
+    fn retrieve_all(&self) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.records(0..Timestamp::max_value())?)))
+    }
+
+    fn retrieve_from(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.records(timestamp..Timestamp::max_value())?)))
+    }
+
+    fn retrieve_to(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.records(0..timestamp)?)))
+    }
+
+    fn retrieve_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval> {
+        Ok(Retrieval::new(Box::new(self.records(range)?)))
+    }
+
+    /// Only the hot file is preloaded.  Archived segments are fetched whole when they're needed.
+    fn preload(&self, range: Range<Timestamp>) -> io::Result<()> {
+        self.hot.preload(range)
+    }
+
+    fn find_gaps(&self, min_gap: Timestamp, range: Range<Timestamp>) -> io::Result<Vec<Range<Timestamp>>> {
+        if range.start >= range.end {
+            return Ok(Vec::new());
+        }
+
+        let cold_end = cmp::min(self.cold_end(), range.end);
+        let cold_records = if range.start < cold_end {
+            self.cold_records(range.start..cold_end)?
+        } else {
+            Vec::new()
+        };
+
+        // Treat the start of the range as a record so that a leading hole is found
+        let mut boundaries = vec![range.start];
+        boundaries.extend(cold_records.iter().map(|r| r.0));
+
+        let mut gaps = boundaries.windows(2)
+            .filter(|w| w[1] > w[0] && w[1] - w[0] >= min_gap)
+            .map(|w| w[0]..w[1])
+            .collect::<Vec<Range<Timestamp>>>();
+
+        // The hot gaps start from the last archived record, so that a hole spanning both tiers is found whole
+        let last_cold = boundaries[boundaries.len() - 1];
+        if self.hot.len() > 0 {
+            gaps.extend(self.hot.find_gaps(min_gap, last_cold..range.end)?);
+        } else if range.end > last_cold && range.end - last_cold >= min_gap {
+            gaps.push(last_cold..range.end);
+        }
+
+        Ok(gaps)
+    }
+}
+
+impl<V> PooledTimeSeries for TieredStorage<V> where V: Storable<FileStorage<Timestamp, V>> + Poolable {
+    fn pool_all(&self, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.pool(0..Timestamp::max_value(), None, pooling_options)
+    }
+
+    fn pool_from(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.pool(timestamp..Timestamp::max_value(), Some(timestamp), pooling_options)
+    }
+
+    fn pool_to(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.pool(0..timestamp, None, pooling_options)
+    }
+
+    fn pool_range(&self, range: Range<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.pool(range.clone(), Some(range.start), pooling_options)
+    }
+}
+
+fn index_filename(filename: &str) -> String {
+    format!("{}.segments", filename)
+}
+
+/// Removes what interrupted archives leave behind in `directory`: temporary indexes, unfinished rewrites of hot
+/// files, and segment indexes whose hot file no longer exists.  Files modified within `older_than` are left
+/// alone, in case an archive is still running.  Returns the removed files.
+pub fn collect_garbage<P: AsRef<Path>>(directory: P, older_than: Duration) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !entry.file_type()?.is_file() || !is_garbage(&path) {
+            continue;
+        }
+
+        // A modification time in the future counts as fresh
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or(Duration::from_secs(0));
+        if age < older_than {
+            continue;
+        }
+
+        fs::remove_file(&path)?;
+        removed.push(path);
+    }
+
+    removed.sort();
+    Ok(removed)
+}
+
+fn is_garbage(path: &Path) -> bool {
+    let filename = match path.to_str() {
+        Some(filename) => filename,
+        None => return false,
+    };
+
+    if filename.ends_with(".segments.tmp") || filename.ends_with(".rewrite") {
+        true
+    } else if filename.ends_with(".segments") {
+        !Path::new(&filename[..filename.len() - ".segments".len()]).exists()
+    } else {
+        false
+    }
+}
+
+fn read_index(file: File) -> io::Result<Vec<Segment>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Segment index is corrupt");
+
+    let mut segments = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let fields = line.split(' ').collect::<Vec<&str>>();
+
+        if fields.len() != 4 {
+            return Err(invalid());
+        }
+
+        let segment = Segment {
+            start: fields[0].parse().map_err(|_| invalid())?,
+            end: fields[1].parse().map_err(|_| invalid())?,
+            records: fields[2].parse().map_err(|_| invalid())?,
+            key: fields[3].to_string(),
+        };
+
+        // Segments are nonempty and in order, which the rest of the tiered storage relies on
+        if segment.start >= segment.end || segments.last().map_or(false, |last: &Segment| last.end > segment.start) {
+            return Err(invalid());
+        }
+
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+mod directory;
+#[cfg(feature = "s3")]
+mod s3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use pooled_time_series::{Interval, PoolingMethod};
+    use util::SetupFile;
+
+    fn tiered_storage(filename: &str, cold: &str) -> TieredStorage<i32> {
+        TieredStorage::<i32>::new(filename, Box::new(DirectoryStore::new(cold).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn test_tiered_storage_archive() {
+        let _setup_file = SetupFile::new("test_tiered_storage_archive");
+        let _setup_index = SetupFile::new("test_tiered_storage_archive.segments");
+        fs::remove_dir_all("test_tiered_storage_archive_cold").ok();
+
+        let mut ts = tiered_storage("test_tiered_storage_archive", "test_tiered_storage_archive_cold");
+
+        for &(timestamp, value) in &[(10, 1), (20, 2), (30, 3), (40, 4), (50, 5)] {
+            ts.store(Box::new(timestamp as Timestamp), Box::new(value as i32)).unwrap();
+        }
+
+        assert_eq!(ts.archive(35).unwrap(), 3);
+        assert_eq!(ts.archive(35).unwrap(), 0);
+        assert_eq!(ts.len(), 5);
+        assert!(ts.store(Box::new(25 as Timestamp), Box::new(0 as i32)).is_err());
+
+        let retrieval = ts.retrieve_range(15..45).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(20, 2), (30, 3), (40, 4)]));
+
+        let retrieval = ts.retrieve_nearest(38, Some(RetrievalDirection::Backward)).unwrap();
+        assert_eq!(retrieval.as_single::<Timestamp, i32>(), Some(&(30, 3)));
+
+        let retrieval = ts.retrieve_nearest(32, Some(RetrievalDirection::Forward)).unwrap();
+        assert_eq!(retrieval.as_single::<Timestamp, i32>(), Some(&(40, 4)));
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(20), pooling: PoolingMethod::Sum, ..PoolingOptions::default() };
+        let retrieval = ts.pool_range(10..60, pooling_options).unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 3), (30, 7), (50, 5)]));
+
+        assert_eq!(ts.find_gaps(15, 0..60).unwrap(), vec![]);
+        assert_eq!(ts.find_gaps(10, 25..60).unwrap(), vec![30..40, 40..50, 50..60]);
+
+        // The archive survives reopening
+        drop(ts);
+        let ts = tiered_storage("test_tiered_storage_archive", "test_tiered_storage_archive_cold");
+        let retrieval = ts.retrieve_all().unwrap();
+        assert_eq!(retrieval.as_vec::<Timestamp, i32>(), Some(&vec![(10, 1), (20, 2), (30, 3), (40, 4), (50, 5)]));
+
+        fs::remove_dir_all("test_tiered_storage_archive_cold").ok();
+    }
+
+    #[test]
+    fn test_corrupt_index() {
+        let _setup_index = SetupFile::new("test_corrupt_index.segments");
+
+        for index in &["10 20 1", "10 x 1 key", "20 10 1 key", "0 0 1 key", "10 30 2 a\n20 40 2 b\n"] {
+            fs::write("test_corrupt_index.segments", index).unwrap();
+            assert_eq!(read_index(File::open("test_corrupt_index.segments").unwrap()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+
+        fs::write("test_corrupt_index.segments", "10 30 2 a\n30 40 2 b\n").unwrap();
+        assert_eq!(read_index(File::open("test_corrupt_index.segments").unwrap()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let directory = "test_collect_garbage";
+        fs::remove_dir_all(directory).ok();
+        fs::create_dir(directory).unwrap();
+
+        for filename in &["live", "live.segments", "live.segments.tmp", "live.rewrite", "gone.segments", "other.tmp"] {
+            File::create(Path::new(directory).join(filename)).unwrap();
+        }
+
+        assert_eq!(collect_garbage(directory, Duration::from_secs(3600)).unwrap(), Vec::<PathBuf>::new());
+
+        let removed = collect_garbage(directory, Duration::from_secs(0)).unwrap();
+        let expected = ["gone.segments", "live.rewrite", "live.segments.tmp"].iter().map(|f| Path::new(directory).join(f)).collect::<Vec<_>>();
+        assert_eq!(removed, expected);
+
+        let mut remaining = fs::read_dir(directory).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(remaining, vec!["live", "live.segments", "other.tmp"]);
+
+        fs::remove_dir_all(directory).ok();
+    }
+}
@@ -0,0 +1,73 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{self, Read};
+
+use rusoto_core::Region;
+use rusoto_s3::{GetObjectRequest, PutObjectRequest, S3, S3Client};
+
+use storage::tiered::ObjectStore;
+
+/// Keeps archived segments in an S3 bucket, or any S3-compatible object store
+pub struct S3Store {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3Store {
+    pub fn new(region: Region, bucket: &str) -> Self {
+        Self {
+            client: S3Client::new(region),
+            bucket: bucket.to_string(),
+        }
+    }
+
+    /// Connects to an S3-compatible store at a custom endpoint, e.g. "http://localhost:9000"
+    pub fn with_endpoint(endpoint: &str, bucket: &str) -> Self {
+        Self::new(Region::Custom { name: "custom".to_string(), endpoint: endpoint.to_string() }, bucket)
+    }
+}
+
+fn s3_error<E>(error: E) -> io::Error where E: ToString {
+    io::Error::new(io::ErrorKind::Other, error.to_string())
+}
+
+impl ObjectStore for S3Store {
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let request = PutObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            body: Some(data.to_vec().into()),
+            ..PutObjectRequest::default()
+        };
+
+        self.client.put_object(request).sync().map(|_| ()).map_err(s3_error)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let request = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            ..GetObjectRequest::default()
+        };
+
+        let output = self.client.get_object(request).sync().map_err(s3_error)?;
+        let body = output.body.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Archived segment has no body"))?;
+
+        let mut data = Vec::new();
+        body.into_blocking_read().read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
@@ -0,0 +1,508 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validation of records as they're stored.
+//!
+//! A `ValidatedStore` wraps any store and checks each record against its constraints before passing it on: bounds
+//! on the value, how far the timestamp may be from the wall clock, and how far the value may jump from the previous
+//! record's.  What happens to a record that breaks them is up to its policy.  Under quarantine, records that are out
+//! of order are kept aside too, rather than failing in the wrapped store.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+
+use indicator::Numeric;
+use key_value_store::{Data, IoStats, KeyValueStore, Notification, Retrieval, StoreStats};
+use pooled_time_series::{Interval, PooledTimeSeries, PoolingOptions};
+use parse;
+use storage::quarantine::{Quarantine, Reason, Reprocessed};
+use time_series::{RetrievalDirection, TimeSeries, TimeUnit, Timestamp};
+
+/// What a record must satisfy to be stored.  Unset constraints aren't checked.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Constraints {
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    /// The furthest a record's timestamp may be from the wall clock, either way, in milliseconds
+    pub max_skew: Option<Timestamp>,
+    /// The largest change from the previous record's value, as a fraction of it
+    pub max_jump: Option<f64>,
+}
+
+/// What to do with a record that breaks its constraints
+pub enum ValidationPolicy {
+    /// Fail the store
+    Reject,
+    /// Store the nearest value that satisfies the constraints.  Records too far from the wall clock are still
+    /// rejected, since moving them would reorder the series.
+    Clamp,
+    /// Keep the record in a quarantine instead, with the reason it was refused, to be looked at later
+    Quarantine(Quarantine),
+}
+
+/// A value that validation can bound
+pub trait Bounded: 'static + Numeric + fmt::Display + FromStr {
+    /// The value nearest to `value`
+    fn from_f64(value: f64) -> Self;
+}
+
+impl Bounded for i32 {
+    fn from_f64(value: f64) -> Self {
+        value.round() as i32
+    }
+}
+
+impl Bounded for i64 {
+    fn from_f64(value: f64) -> Self {
+        value.round() as i64
+    }
+}
+
+impl Bounded for u32 {
+    fn from_f64(value: f64) -> Self {
+        value.round() as u32
+    }
+}
+
+impl Bounded for u64 {
+    fn from_f64(value: f64) -> Self {
+        value.round() as u64
+    }
+}
+
+impl Bounded for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+}
+
+impl Bounded for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+}
+
+/// A constraint a record broke
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Violation {
+    BelowMin,
+    AboveMax,
+    Skew,
+    Jump,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Violation::BelowMin => "Value is below the channel's minimum",
+            Violation::AboveMax => "Value is above the channel's maximum",
+            Violation::Skew => "Timestamp is too far from the current time",
+            Violation::Jump => "Value jumped too far from the previous record",
+        })
+    }
+}
+
+/// A store that validates records before storing them in another
+pub struct ValidatedStore<S> {
+    store: S,
+    constraints: Constraints,
+    policy: ValidationPolicy,
+    unit: TimeUnit,
+    /// The value of the last record stored, for checking jumps.  The first record after opening isn't checked.
+    last_value: Option<f64>,
+    value_of: fn(&Data) -> Option<f64>,
+    boxed: fn(f64) -> Box<Data>,
+    text_of: fn(&Data) -> Option<String>,
+}
+
+impl<S> ValidatedStore<S> where S: KeyValueStore {
+    /// Validates records whose values are `V`
+    pub fn new<V>(store: S, constraints: Constraints, policy: ValidationPolicy) -> Self where V: Bounded {
+        Self {
+            store: store,
+            constraints: constraints,
+            policy: policy,
+            unit: TimeUnit::Milliseconds,
+            last_value: None,
+            value_of: value_of::<V>,
+            boxed: boxed::<V>,
+            text_of: text_of::<V>,
+        }
+    }
+
+    /// Sets the unit of the store's timestamps, for comparing them with the wall clock
+    pub fn unit(mut self, unit: TimeUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    /// The quarantine of a store whose policy is to quarantine
+    pub fn quarantine(&self) -> Option<&Quarantine> {
+        match self.policy {
+            ValidationPolicy::Quarantine(ref quarantine) => Some(quarantine),
+            _ => None,
+        }
+    }
+
+    /// Validates the records in quarantine again and stores the ones that pass, e.g. after the constraints were
+    /// loosened.  `V` must be the type the store was made for.
+    pub fn reprocess<V>(&mut self) -> io::Result<Reprocessed> where V: Bounded {
+        let quarantine = self.quarantine().cloned().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Store doesn't quarantine records"))?;
+        quarantine.reprocess::<V>(self)
+    }
+
+    /// Checks a record without storing it
+    pub fn validate(&self, timestamp: Option<Timestamp>, value: f64) -> Result<(), Violation> {
+        if let Some(max_skew) = self.constraints.max_skew {
+            let now = TimeUnit::Milliseconds.convert(parse::now(), self.unit);
+            let max_skew = TimeUnit::Milliseconds.convert(max_skew, self.unit);
+
+            match timestamp {
+                Some(timestamp) if now.saturating_sub(timestamp) <= max_skew && timestamp.saturating_sub(now) <= max_skew => {},
+                _ => return Err(Violation::Skew),
+            }
+        }
+
+        if self.constraints.min_value.map_or(false, |min| !(value >= min)) {
+            return Err(Violation::BelowMin);
+        }
+        if self.constraints.max_value.map_or(false, |max| !(value <= max)) {
+            return Err(Violation::AboveMax);
+        }
+
+        if let (Some(max_jump), Some(last_value)) = (self.constraints.max_jump, self.last_value) {
+            if last_value != 0.0 && !((value - last_value).abs() / last_value.abs() <= max_jump) {
+                return Err(Violation::Jump);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The nearest value to `value` within the bounds and the largest jump
+    fn clamp(&self, value: f64) -> f64 {
+        let mut value = value;
+
+        if let (Some(max_jump), Some(last_value)) = (self.constraints.max_jump, self.last_value) {
+            let reach = last_value.abs() * max_jump;
+            value = value.max(last_value - reach).min(last_value + reach);
+        }
+        if let Some(min) = self.constraints.min_value {
+            value = value.max(min);
+        }
+        if let Some(max) = self.constraints.max_value {
+            value = value.min(max);
+        }
+
+        value
+    }
+
+    /// Whether a record is on or before the last record of the wrapped store
+    fn is_out_of_order(&self, timestamp: Option<Timestamp>) -> bool {
+        match (timestamp, self.store.last_key()) {
+            (Some(timestamp), Some(last_key)) => last_key.downcast_ref::<Timestamp>().map_or(false, |&last_key| timestamp <= last_key),
+            _ => false,
+        }
+    }
+
+    fn quarantine_record(&self, timestamp: Option<Timestamp>, value: &Data, reason: Reason) -> io::Result<()> {
+        let quarantine = self.quarantine().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Store doesn't quarantine records"))?;
+        let timestamp = timestamp.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Only timestamped records can be quarantined"))?;
+        let text = (self.text_of)(value).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Value has the wrong type"))?;
+
+        quarantine.add(timestamp, &text, reason)
+    }
+
+    fn store_valid(&mut self, key: Box<Data>, value: Box<Data>, number: f64) -> io::Result<()> {
+        self.store.store(key, value)?;
+        self.last_value = Some(number);
+        Ok(())
+    }
+}
+
+fn value_of<V>(value: &Data) -> Option<f64> where V: Bounded {
+    value.downcast_ref::<V>().map(|&value| value.to_f64())
+}
+
+fn boxed<V>(value: f64) -> Box<Data> where V: Bounded {
+    Box::new(V::from_f64(value))
+}
+
+fn text_of<V>(value: &Data) -> Option<String> where V: Bounded {
+    value.downcast_ref::<V>().map(|value| value.to_string())
+}
+
+impl<S> KeyValueStore for ValidatedStore<S> where S: KeyValueStore {
+    fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    fn io_stats(&self) -> IoStats {
+        self.store.io_stats()
+    }
+
+    fn last_key(&self) -> Option<Box<Data>> {
+        self.store.last_key()
+    }
+
+    fn size_on_disk(&self) -> io::Result<u64> {
+        self.store.size_on_disk()
+    }
+
+    fn stats(&self) -> io::Result<StoreStats> {
+        self.store.stats()
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.store.sync()?;
+
+        match self.quarantine() {
+            Some(quarantine) => quarantine.sync(),
+            None => Ok(()),
+        }
+    }
+
+    fn store(&mut self, key: Box<Data>, value: Box<Data>) -> io::Result<()> {
+        let number = (self.value_of)(&*value).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Value has the wrong type"))?;
+        let timestamp = key.downcast_ref::<Timestamp>().cloned();
+
+        // Records out of order would fail in the wrapped store, so they're only caught here to be kept aside
+        if self.quarantine().is_some() && self.is_out_of_order(timestamp) {
+            return self.quarantine_record(timestamp, &*value, Reason::OutOfOrder);
+        }
+
+        let violation = match self.validate(timestamp, number) {
+            Ok(()) => return self.store_valid(key, value, number),
+            Err(violation) => violation,
+        };
+
+        match self.policy {
+            ValidationPolicy::Clamp if violation != Violation::Skew => {
+                let clamped = self.clamp(number);
+                let value = (self.boxed)(clamped);
+                self.store_valid(key, value, clamped)
+            },
+            ValidationPolicy::Quarantine(_) => self.quarantine_record(timestamp, &*value, violation.into()),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, violation.to_string())),
+        }
+    }
+
+    fn subscribe(&self) -> io::Result<Receiver<Notification>> {
+        self.store.subscribe()
+    }
+}
+
+impl<S> TimeSeries for ValidatedStore<S> where S: TimeSeries {
+    fn retrieve_nearest(&self, timestamp: Timestamp, retrieval_direction: Option<RetrievalDirection>) -> io::Result<Retrieval> {
+        self.store.retrieve_nearest(timestamp, retrieval_direction)
+    }
+
+    fn retrieve_all(&self) -> io::Result<Retrieval> {
+        self.store.retrieve_all()
+    }
+
+    fn retrieve_from(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        self.store.retrieve_from(timestamp)
+    }
+
+    fn retrieve_to(&self, timestamp: Timestamp) -> io::Result<Retrieval> {
+        self.store.retrieve_to(timestamp)
+    }
+
+    fn retrieve_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval> {
+        self.store.retrieve_range(range)
+    }
+
+    fn find_gaps(&self, min_gap: Timestamp, range: Range<Timestamp>) -> io::Result<Vec<Range<Timestamp>>> {
+        self.store.find_gaps(min_gap, range)
+    }
+
+    fn time_unit(&self) -> TimeUnit {
+        self.store.time_unit()
+    }
+
+    fn preload(&self, range: Range<Timestamp>) -> io::Result<()> {
+        self.store.preload(range)
+    }
+
+    fn heartbeat(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.store.heartbeat(timestamp)
+    }
+
+    fn last_update(&self) -> Option<Timestamp> {
+        self.store.last_update()
+    }
+}
+
+/// Pooling reads the records that were stored, so it goes straight to the store
+impl<S> PooledTimeSeries for ValidatedStore<S> where S: PooledTimeSeries {
+    fn pool_all(&self, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_all(pooling_options)
+    }
+
+    fn pool_from(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_from(timestamp, pooling_options)
+    }
+
+    fn pool_to(&self, timestamp: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_to(timestamp, pooling_options)
+    }
+
+    fn pool_range(&self, range: Range<Timestamp>, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_range(range, pooling_options)
+    }
+
+    fn pool_bucket(&self, bucket_start: Timestamp, pooling_options: PoolingOptions) -> io::Result<Retrieval> {
+        self.store.pool_bucket(bucket_start, pooling_options)
+    }
+
+    fn pool_multi(&self, range: Range<Timestamp>, intervals: &[Interval], pooling_options: PoolingOptions) -> io::Result<Vec<Retrieval>> {
+        self.store.pool_multi(range, intervals, pooling_options)
+    }
+
+    fn summarize_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval> {
+        self.store.summarize_range(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pooled_time_series::PoolingMethod;
+    use storage::FileStorage;
+    use storage::quarantine::QuarantinedRecord;
+    use util::SetupFile;
+
+    fn store(validated: &mut ValidatedStore<FileStorage<Timestamp, i32>>, timestamp: Timestamp, value: i32) -> io::Result<()> {
+        validated.store(Box::new(timestamp), Box::new(value))
+    }
+
+    fn records(store: &dyn TimeSeries) -> Vec<(Timestamp, i32)> {
+        store.retrieve_all().unwrap().into_vec()
+    }
+
+    #[test]
+    fn test_reject() {
+        let _setup_file = SetupFile::new("test_validated_reject");
+
+        let constraints = Constraints { min_value: Some(10.0), max_value: Some(1000.0), max_jump: Some(0.5), ..Constraints::default() };
+        let storage = FileStorage::<Timestamp, i32>::new("test_validated_reject").unwrap();
+        let mut validated = ValidatedStore::new::<i32>(storage, constraints, ValidationPolicy::Reject);
+
+        store(&mut validated, 1, 100).unwrap();
+        assert_eq!(store(&mut validated, 2, 5).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(store(&mut validated, 3, 2000).is_err());
+        assert!(store(&mut validated, 4, 151).is_err());
+        store(&mut validated, 5, 150).unwrap();
+
+        assert_eq!(validated.validate(None, 5.0), Err(Violation::BelowMin));
+        assert_eq!(validated.validate(None, 2000.0), Err(Violation::AboveMax));
+        assert_eq!(validated.validate(None, 50.0), Err(Violation::Jump));
+        assert_eq!(records(&validated), vec![(1, 100), (5, 150)]);
+
+        assert!(validated.store(Box::new(6 as Timestamp), Box::new(1.0f64)).is_err());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let _setup_file = SetupFile::new("test_validated_clamp");
+
+        let constraints = Constraints { min_value: Some(10.0), max_value: Some(1000.0), max_jump: Some(0.5), ..Constraints::default() };
+        let storage = FileStorage::<Timestamp, i32>::new("test_validated_clamp").unwrap();
+        let mut validated = ValidatedStore::new::<i32>(storage, constraints, ValidationPolicy::Clamp);
+
+        store(&mut validated, 1, 5).unwrap();
+        store(&mut validated, 2, 100).unwrap();
+        store(&mut validated, 3, 1).unwrap();
+
+        assert_eq!(records(&validated), vec![(1, 10), (2, 15), (3, 10)]);
+
+        let pooling_options = PoolingOptions { interval: Interval::millis(10), pooling: PoolingMethod::Sum, ..PoolingOptions::default() };
+        assert_eq!(validated.pool_all(pooling_options).unwrap().into_vec::<Timestamp, i32>(), vec![(1, 35)]);
+    }
+
+    #[test]
+    fn test_skew() {
+        let _setup_file = SetupFile::new("test_validated_skew");
+
+        let constraints = Constraints { max_skew: Some(60_000), ..Constraints::default() };
+        let storage = FileStorage::<Timestamp, i32>::with_unit("test_validated_skew", TimeUnit::Microseconds).unwrap();
+        let mut validated = ValidatedStore::new::<i32>(storage, constraints, ValidationPolicy::Clamp).unit(TimeUnit::Microseconds);
+
+        let now = TimeUnit::Milliseconds.convert(parse::now(), TimeUnit::Microseconds);
+        store(&mut validated, now - 1_000_000, 1).unwrap();
+        assert!(store(&mut validated, now - 120_000_000, 2).is_err());
+        assert!(store(&mut validated, now + 120_000_000, 3).is_err());
+
+        assert_eq!(records(&validated), vec![(now - 1_000_000, 1)]);
+        assert_eq!(validated.time_unit(), TimeUnit::Microseconds);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let _setup_file = SetupFile::new("test_validated_quarantine");
+        let _setup_quarantine = SetupFile::new("test_validated_quarantine.quarantine");
+
+        let constraints = Constraints { max_value: Some(100.0), ..Constraints::default() };
+        let storage = FileStorage::<Timestamp, i32>::new("test_validated_quarantine").unwrap();
+        let quarantine = Quarantine::open("test_validated_quarantine.quarantine").unwrap();
+        let mut validated = ValidatedStore::new::<i32>(storage, constraints, ValidationPolicy::Quarantine(quarantine));
+
+        store(&mut validated, 1, 50).unwrap();
+        store(&mut validated, 2, 500).unwrap();
+        store(&mut validated, 3, 60).unwrap();
+        store(&mut validated, 3, 70).unwrap();
+
+        assert_eq!(records(&validated), vec![(1, 50), (3, 60)]);
+
+        let quarantine = Quarantine::open("test_validated_quarantine.quarantine").unwrap();
+        assert_eq!(quarantine.records::<i32>().unwrap(), vec![
+            QuarantinedRecord { timestamp: 2, value: 500, reason: Reason::AboveMax },
+            QuarantinedRecord { timestamp: 3, value: 70, reason: Reason::OutOfOrder },
+        ]);
+    }
+
+    #[test]
+    fn test_reprocess() {
+        let _setup_file = SetupFile::new("test_validated_reprocess");
+        let _setup_quarantine = SetupFile::new("test_validated_reprocess.quarantine");
+
+        let constraints = Constraints { max_value: Some(100.0), ..Constraints::default() };
+        let storage = FileStorage::<Timestamp, i32>::new("test_validated_reprocess").unwrap();
+        let quarantine = Quarantine::open("test_validated_reprocess.quarantine").unwrap();
+        let mut validated = ValidatedStore::new::<i32>(storage, constraints, ValidationPolicy::Quarantine(quarantine));
+
+        store(&mut validated, 1, 50).unwrap();
+        store(&mut validated, 2, 500).unwrap();
+        store(&mut validated, 3, 200).unwrap();
+
+        // Once the bound is loosened, the records stored after the last record can go in, and the rest stay out
+        validated.constraints.max_value = Some(300.0);
+        assert_eq!(validated.reprocess::<i32>().unwrap(), Reprocessed { stored: 1, quarantined: 1 });
+
+        assert_eq!(records(&validated), vec![(1, 50), (3, 200)]);
+        assert_eq!(validated.quarantine().unwrap().records::<i32>().unwrap(), vec![
+            QuarantinedRecord { timestamp: 2, value: 500, reason: Reason::AboveMax },
+        ]);
+    }
+}
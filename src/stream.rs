@@ -0,0 +1,381 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use pooled_time_series::{BucketAnchor, OpenBucket, PooledTimeSeries};
+use query::{Query, Transform};
+use time_series::{TimeSeries, Timestamp};
+
+/// Where a stream left off, so that a client that disconnects can resume it without gaps or duplicates.
+///
+/// In text, a token is "sequence" before anything has been delivered and "sequence.timestamp" after.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResumeToken {
+    /// The sequence number of the last update sent.  Sequence numbers start at 1.
+    pub sequence: u64,
+    /// The timestamp of the last record or closed bucket sent
+    pub position: Option<Timestamp>,
+}
+
+impl fmt::Display for ResumeToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some(timestamp) => write!(f, "{}.{}", self.sequence, timestamp),
+            None => write!(f, "{}", self.sequence),
+        }
+    }
+}
+
+impl FromStr for ResumeToken {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Invalid resume token");
+
+        let parts = text.split('.').map(|part| part.parse::<u64>().map_err(|_| invalid())).collect::<io::Result<Vec<u64>>>()?;
+        match parts.len() {
+            1 => Ok(ResumeToken { sequence: parts[0], position: None }),
+            2 => Ok(ResumeToken { sequence: parts[0], position: Some(parts[1]) }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// An update of a stream, with the token to resume the stream from just after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequenced<U> {
+    pub update: U,
+    pub resume: ResumeToken,
+}
+
+/// A change to the buckets of a live pooled query
+#[derive(Clone, Debug, PartialEq)]
+pub enum CandleUpdate<V> {
+    /// The open bucket, as pooled so far.  Sent again whenever its value changes.
+    Partial(Timestamp, V),
+    /// A bucket that has closed, with its final value.  Sent once per bucket.
+    Closed(Timestamp, V),
+}
+
+/// Follows a pooled query as records arrive, producing the updates an exchange candle stream would.
+///
+/// A bucket closes once a record arrives in a later bucket, or once it ends at or before the end of the query's
+/// range.  The query's transforms and indicators aren't applied.
+pub struct CandleStream<V> {
+    query: Query,
+    open: Option<(Timestamp, V)>,
+    token: ResumeToken,
+}
+
+impl<V> CandleStream<V> where V: 'static + Clone + PartialEq {
+    /// Fails if the query doesn't pool.
+    pub fn new(query: Query) -> io::Result<Self> {
+        Self::resume(query, ResumeToken::default())
+    }
+
+    /// Picks up a stream after the last closed bucket of `token`.  The open bucket is sent again.
+    pub fn resume(mut query: Query, token: ResumeToken) -> io::Result<Self> {
+        if query.pooling_options()?.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Streamed query has no interval"));
+        }
+
+        query.open_bucket = OpenBucket::Label;
+        query.transform.clear();
+        query.indicators.clear();
+        query.bands = None;
+
+        // The last closed bucket starts on the query's bucket boundaries, so pooling can restart there
+        if let Some(last_closed) = token.position {
+            query = query.from(last_closed).anchor(BucketAnchor::RequestedStart);
+        }
+
+        Ok(Self {
+            query: query,
+            open: None,
+            token: token,
+        })
+    }
+
+    /// Returns the updates since the last poll.  The first poll returns every bucket of the query so far.
+    pub fn poll(&mut self, pooled_time_series: &dyn PooledTimeSeries) -> io::Result<Vec<Sequenced<CandleUpdate<V>>>> {
+        // Only the open bucket and anything after it can have changed
+        let query = match self.open {
+            Some((start, _)) => self.query.clone().from(start).anchor(BucketAnchor::RequestedStart),
+            None => self.query.clone(),
+        };
+
+        let (closed, open) = query.evaluate_live::<V>(pooled_time_series)?;
+
+        let mut updates = Vec::new();
+
+        for (start, value) in closed {
+            if self.token.position.map_or(true, |last_closed| start > last_closed) {
+                self.token.position = Some(start);
+                updates.push(self.sequence(CandleUpdate::Closed(start, value)));
+            }
+        }
+
+        if let Some((start, ref value)) = open {
+            if self.open.as_ref().map_or(true, |open| open.0 != start || open.1 != *value) {
+                updates.push(self.sequence(CandleUpdate::Partial(start, value.clone())));
+            }
+        }
+
+        self.open = open;
+
+        Ok(updates)
+    }
+
+    /// Whether the stream has closed the last bucket of the query's range and will produce no more updates
+    pub fn is_finished(&self) -> bool {
+        match (self.query.end, self.token.position) {
+            (Some(end), Some(last_closed)) => {
+                // The query was checked for pooling options when the stream was made
+                let last_end = match self.query.pooling_options() {
+                    Ok(Some(pooling_options)) => pooling_options.bucket_end(last_closed),
+                    _ => last_closed,
+                };
+                self.open.is_none() && last_end >= end
+            },
+            _ => false,
+        }
+    }
+
+    fn sequence(&mut self, update: CandleUpdate<V>) -> Sequenced<CandleUpdate<V>> {
+        self.token.sequence += 1;
+        Sequenced { update: update, resume: self.token }
+    }
+}
+
+/// Follows a raw query as records arrive
+pub struct RecordStream<V> {
+    query: Query,
+    token: ResumeToken,
+    /// The most records a poll returns, if the backlog is caught up on in chunks
+    chunk: Option<usize>,
+    caught_up: bool,
+    value_type: PhantomData<V>,
+}
+
+impl<V> RecordStream<V> where V: 'static {
+    /// Fails if the query pools.
+    pub fn new(query: Query) -> io::Result<Self> {
+        Self::resume(query, ResumeToken::default())
+    }
+
+    /// Picks up a stream after the last record of `token`
+    pub fn resume(mut query: Query, token: ResumeToken) -> io::Result<Self> {
+        if query.interval.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Streamed records can't be pooled"));
+        }
+
+        query.transform.clear();
+        query.indicators.clear();
+        query.bands = None;
+
+        Ok(Self {
+            query: query,
+            token: token,
+            chunk: None,
+            caught_up: false,
+            value_type: PhantomData,
+        })
+    }
+
+    /// Picks up a stream after the last record a client saw, for a client that kept the timestamp rather than a token.
+    /// Sequence numbers start over.
+    pub fn since(query: Query, timestamp: Timestamp) -> io::Result<Self> {
+        Self::resume(query, ResumeToken { sequence: 0, position: Some(timestamp) })
+    }
+
+    /// Returns at most `size` records per poll, so that a long backlog is sent a chunk at a time.  Each chunk picks up
+    /// right after the last, and records stored meanwhile are in a later chunk, so there's nothing to do to switch
+    /// from catching up to following the query live but keep polling.
+    pub fn chunked(mut self, size: usize) -> Self {
+        self.chunk = Some(size.max(1));
+        self
+    }
+
+    /// Whether the last poll returned every record there was, rather than a full chunk
+    pub fn is_caught_up(&self) -> bool {
+        self.caught_up
+    }
+
+    /// Returns the records stored since the last poll.  The first poll returns every record of the query so far, or
+    /// the first chunk of them.
+    pub fn poll(&mut self, time_series: &dyn TimeSeries) -> io::Result<Vec<Sequenced<(Timestamp, V)>>> {
+        // Stored timestamps strictly increase, so everything after the last one sent is new
+        let query = match self.token.position {
+            Some(timestamp) => self.query.clone().from(timestamp.saturating_add(1)),
+            None => self.query.clone(),
+        };
+        let query = match self.chunk {
+            Some(size) => query.transform(Transform::Limit(size)),
+            None => query,
+        };
+
+        let mut updates = Vec::new();
+
+        for record in query.evaluate::<V>(time_series)? {
+            self.token.position = Some(record.0);
+            self.token.sequence += 1;
+            updates.push(Sequenced { update: record, resume: self.token });
+        }
+
+        self.caught_up = self.chunk.map_or(true, |size| updates.len() < size);
+
+        Ok(updates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use key_value_store::KeyValueStore;
+    use pooled_time_series::PoolingMethod;
+    use storage::FileStorage;
+    use util::SetupFile;
+
+    #[test]
+    fn test_candle_stream() {
+        let _setup_file = SetupFile::new("test_candle_stream");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_candle_stream").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(14 as Timestamp), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(3 as i32)).unwrap();
+
+        let query = Query::new("m/s/c").interval(10).pooling(PoolingMethod::Sum);
+        let mut stream = CandleStream::<i32>::new(query).unwrap();
+
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![CandleUpdate::Closed(10, 3), CandleUpdate::Partial(20, 3)]);
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![]);
+
+        fs.store(Box::new(25 as Timestamp), Box::new(4 as i32)).unwrap();
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![CandleUpdate::Partial(20, 7)]);
+
+        // A record in a later bucket closes the open one
+        fs.store(Box::new(31 as Timestamp), Box::new(5 as i32)).unwrap();
+        let polled = stream.poll(&fs).unwrap();
+        assert_eq!(polled[0].resume, ResumeToken { sequence: 4, position: Some(20) });
+        assert_eq!(updates(polled), vec![CandleUpdate::Closed(20, 7), CandleUpdate::Partial(30, 5)]);
+        assert!(!stream.is_finished());
+
+        // A client that saw the bucket at 10 close resumes without it
+        let query = Query::new("m/s/c").interval(10).pooling(PoolingMethod::Sum);
+        let mut resumed = CandleStream::<i32>::resume(query, ResumeToken { sequence: 1, position: Some(10) }).unwrap();
+        let polled = resumed.poll(&fs).unwrap();
+        assert_eq!(polled[0].resume.sequence, 2);
+        assert_eq!(updates(polled), vec![CandleUpdate::Closed(20, 7), CandleUpdate::Partial(30, 5)]);
+
+        assert!(CandleStream::<i32>::new(Query::new("m/s/c")).is_err());
+    }
+
+    #[test]
+    fn test_candle_stream_finished() {
+        let _setup_file = SetupFile::new("test_candle_stream_finished");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_candle_stream_finished").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(24 as Timestamp), Box::new(2 as i32)).unwrap();
+
+        let query = Query::new("m/s/c").from(10).to(30).interval(10).pooling(PoolingMethod::Sum);
+        let mut stream = CandleStream::<i32>::new(query).unwrap();
+
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![CandleUpdate::Closed(10, 1), CandleUpdate::Closed(20, 2)]);
+        assert!(stream.is_finished());
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![]);
+    }
+
+    #[test]
+    fn test_record_stream() {
+        let _setup_file = SetupFile::new("test_record_stream");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_record_stream").unwrap();
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+
+        let mut stream = RecordStream::<i32>::new(Query::new("m/s/c")).unwrap();
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![(10, 1), (20, 2)]);
+
+        fs.store(Box::new(21 as Timestamp), Box::new(3 as i32)).unwrap();
+        fs.store(Box::new(30 as Timestamp), Box::new(4 as i32)).unwrap();
+        let polled = stream.poll(&fs).unwrap();
+        assert_eq!(polled[0].resume, ResumeToken { sequence: 3, position: Some(21) });
+        assert_eq!(updates(polled), vec![(21, 3), (30, 4)]);
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![]);
+
+        // A client that saw the record at 20 resumes without gaps or duplicates
+        let token = "2.20".parse().unwrap();
+        let mut resumed = RecordStream::<i32>::resume(Query::new("m/s/c"), token).unwrap();
+        let polled = resumed.poll(&fs).unwrap();
+        assert_eq!(polled[0].resume.sequence, 3);
+        assert_eq!(updates(polled), vec![(21, 3), (30, 4)]);
+
+        assert!(RecordStream::<i32>::new(Query::new("m/s/c").interval(10)).is_err());
+    }
+
+    #[test]
+    fn test_record_stream_catch_up() {
+        let _setup_file = SetupFile::new("test_record_stream_catch_up");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_record_stream_catch_up").unwrap();
+
+        for timestamp in 1..6 {
+            fs.store(Box::new(timestamp as Timestamp), Box::new(timestamp as i32)).unwrap();
+        }
+
+        // A client that last saw the record at 1 gets the backlog two at a time
+        let mut stream = RecordStream::<i32>::since(Query::new("m/s/c"), 1).unwrap().chunked(2);
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![(2, 2), (3, 3)]);
+        assert!(!stream.is_caught_up());
+
+        // Records stored while catching up come after the backlog, once each
+        fs.store(Box::new(6 as Timestamp), Box::new(6 as i32)).unwrap();
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![(4, 4), (5, 5)]);
+        assert!(!stream.is_caught_up());
+
+        let polled = stream.poll(&fs).unwrap();
+        assert_eq!(polled[0].resume, ResumeToken { sequence: 5, position: Some(6) });
+        assert_eq!(updates(polled), vec![(6, 6)]);
+        assert!(stream.is_caught_up());
+
+        fs.store(Box::new(7 as Timestamp), Box::new(7 as i32)).unwrap();
+        assert_eq!(updates(stream.poll(&fs).unwrap()), vec![(7, 7)]);
+        assert!(stream.is_caught_up());
+    }
+
+    #[test]
+    fn test_resume_token() {
+        let token = ResumeToken { sequence: 12, position: Some(1546398245678) };
+        assert_eq!(token.to_string(), "12.1546398245678");
+        assert_eq!("12.1546398245678".parse::<ResumeToken>().unwrap(), token);
+        assert_eq!("0".parse::<ResumeToken>().unwrap(), ResumeToken::default());
+        assert!("1.2.3".parse::<ResumeToken>().is_err());
+        assert!("x".parse::<ResumeToken>().is_err());
+    }
+
+    fn updates<U>(sequenced: Vec<Sequenced<U>>) -> Vec<U> {
+        sequenced.into_iter().map(|sequenced| sequenced.update).collect()
+    }
+}
@@ -0,0 +1,212 @@
+// This file is part of trade-data.
+//
+// trade-data is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// trade-data is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Translation between the names markets give pairs, like "BTC-USD", "XBT/USD", or "btcusd", and the canonical names
+//! channels are served under, which are lower case with no separator, e.g. "btcusd".
+
+use std::collections::HashMap;
+use std::io;
+
+/// How a market writes the names of pairs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SymbolFormat {
+    /// What comes between the base and quote assets, if anything
+    pub separator: Option<char>,
+    /// Whether names are in upper case
+    pub uppercase: bool,
+}
+
+/// Translates one market's symbols to and from canonical ones.
+///
+/// An external name is taken apart at the market's separator, its assets are renamed by the market's aliases, and
+/// they're joined in lower case.  Going the other way, a canonical name has to be taken apart without a separator, so
+/// it's split at the longest quote asset it ends with.  Pairs that follow no rule can be named outright.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolMap {
+    format: SymbolFormat,
+    /// Canonical assets by the market's names for them, both in lower case
+    aliases: HashMap<String, String>,
+    /// Canonical assets that pairs are quoted in
+    quotes: Vec<String>,
+    /// External names by canonical symbol, for pairs named outright
+    symbols: HashMap<String, String>,
+}
+
+impl SymbolMap {
+    pub fn new(format: SymbolFormat) -> Self {
+        Self {
+            format: format,
+            ..Self::default()
+        }
+    }
+
+    /// Has the market call an asset by another name, e.g. "XBT" for "BTC"
+    pub fn alias(mut self, external: &str, canonical: &str) -> Self {
+        self.aliases.insert(external.to_lowercase(), canonical.to_lowercase());
+        self
+    }
+
+    /// Lets canonical symbols quoted in an asset be taken apart, e.g. "usd" for "btcusd"
+    pub fn quote(mut self, asset: &str) -> Self {
+        self.quotes.push(asset.to_lowercase());
+        self
+    }
+
+    /// Names a pair outright, for pairs that don't follow the market's format
+    pub fn symbol(mut self, canonical: &str, external: &str) -> Self {
+        self.symbols.insert(canonical.to_lowercase(), external.to_string());
+        self
+    }
+
+    /// The canonical name of a symbol as the market names it.  Fails with `InvalidInput` if it doesn't follow the
+    /// market's format.
+    pub fn canonical(&self, external: &str) -> io::Result<String> {
+        if let Some((canonical, _)) = self.symbols.iter().find(|&(_, e)| e == external) {
+            return Ok(canonical.clone());
+        }
+
+        let canonical = match self.format.separator {
+            Some(separator) => {
+                let mut assets = external.splitn(2, separator);
+                match (assets.next(), assets.next()) {
+                    (Some(base), Some(quote)) if !base.is_empty() && !quote.is_empty() => self.canonical_asset(base) + &self.canonical_asset(quote),
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not a pair separated by '{}'", external, separator))),
+                }
+            },
+            None => external.to_lowercase(),
+        };
+
+        if canonical.is_empty() || !canonical.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not a symbol", external)));
+        }
+
+        Ok(canonical)
+    }
+
+    /// The market's name for a canonical symbol.  Fails with `NotFound` if the market has a separator and the symbol
+    /// isn't quoted in any of its quote assets.
+    pub fn external(&self, canonical: &str) -> io::Result<String> {
+        let canonical = canonical.to_lowercase();
+
+        if let Some(external) = self.symbols.get(&canonical) {
+            return Ok(external.clone());
+        }
+
+        let external = match self.format.separator {
+            Some(separator) => {
+                let quote = self.quotes.iter()
+                    .filter(|quote| canonical.len() > quote.len() && canonical.ends_with(quote.as_str()))
+                    .max_by_key(|quote| quote.len())
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No quote asset to split \"{}\" at", canonical)))?;
+                let base = &canonical[..canonical.len() - quote.len()];
+
+                format!("{}{}{}", self.external_asset(base), separator, self.external_asset(quote))
+            },
+            None => canonical,
+        };
+
+        Ok(if self.format.uppercase { external.to_uppercase() } else { external })
+    }
+
+    fn canonical_asset(&self, asset: &str) -> String {
+        let asset = asset.to_lowercase();
+        self.aliases.get(&asset).cloned().unwrap_or(asset)
+    }
+
+    fn external_asset(&self, asset: &str) -> String {
+        self.aliases.iter().find(|&(_, canonical)| canonical == asset).map_or(asset.to_string(), |(external, _)| external.clone())
+    }
+}
+
+/// The symbol maps of each market.  Markets without one name symbols canonically.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolRegistry(HashMap<String, SymbolMap>);
+
+impl SymbolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, market: &str, map: SymbolMap) {
+        self.0.insert(market.to_string(), map);
+    }
+
+    pub fn get(&self, market: &str) -> Option<&SymbolMap> {
+        self.0.get(market)
+    }
+
+    /// The canonical name of a symbol as a market names it
+    pub fn canonical(&self, market: &str, external: &str) -> io::Result<String> {
+        match self.0.get(market) {
+            Some(map) => map.canonical(external),
+            None => Ok(external.to_string()),
+        }
+    }
+
+    /// A market's name for a canonical symbol
+    pub fn external(&self, market: &str, canonical: &str) -> io::Result<String> {
+        match self.0.get(market) {
+            Some(map) => map.external(canonical),
+            None => Ok(canonical.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kraken() -> SymbolMap {
+        SymbolMap::new(SymbolFormat { separator: Some('/'), uppercase: true })
+            .alias("XBT", "BTC")
+            .quote("usd")
+            .quote("usdt")
+            .quote("btc")
+    }
+
+    #[test]
+    fn test_symbol_map() {
+        let kraken = kraken();
+        assert_eq!(kraken.canonical("XBT/USD").unwrap(), "btcusd");
+        assert_eq!(kraken.canonical("ETH/XBT").unwrap(), "ethbtc");
+        assert_eq!(kraken.external("btcusd").unwrap(), "XBT/USD");
+        assert_eq!(kraken.external("ethusdt").unwrap(), "ETH/USDT");
+        assert_eq!(kraken.external("ethbtc").unwrap(), "ETH/XBT");
+        assert!(kraken.canonical("XBTUSD").is_err());
+        assert_eq!(kraken.external("etheur").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let coinbase = SymbolMap::new(SymbolFormat { separator: Some('-'), uppercase: true }).quote("usd");
+        assert_eq!(coinbase.canonical("BTC-USD").unwrap(), "btcusd");
+        assert_eq!(coinbase.external("btcusd").unwrap(), "BTC-USD");
+
+        let gemini = SymbolMap::new(SymbolFormat::default()).symbol("btcusd", "BTC.USD.PERP");
+        assert_eq!(gemini.canonical("btcusd").unwrap(), "btcusd");
+        assert_eq!(gemini.external("ethusd").unwrap(), "ethusd");
+        assert_eq!(gemini.external("btcusd").unwrap(), "BTC.USD.PERP");
+        assert_eq!(gemini.canonical("BTC.USD.PERP").unwrap(), "btcusd");
+        assert!(gemini.canonical("btc/usd").is_err());
+    }
+
+    #[test]
+    fn test_symbol_registry() {
+        let mut registry = SymbolRegistry::new();
+        registry.insert("kraken", kraken());
+
+        assert_eq!(registry.canonical("kraken", "XBT/USD").unwrap(), "btcusd");
+        assert_eq!(registry.external("kraken", "btcusd").unwrap(), "XBT/USD");
+        assert_eq!(registry.canonical("gemini", "btcusd").unwrap(), "btcusd");
+        assert_eq!(registry.external("gemini", "btcusd").unwrap(), "btcusd");
+    }
+}
@@ -13,28 +13,288 @@
 // You should have received a copy of the GNU General Public License
 // along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cmp::Ordering;
+use std::fmt;
 use std::io;
 use std::ops::Range;
+use std::str::FromStr;
 
-use key_value_store::{KeyValueStore, Retrieval};
+use key_value_store::{AsKeyValueStore, KeyValueStore, Retrieval};
 
+/// A count of time units since the Unix epoch.  Milliseconds, unless the time series says otherwise.
 pub type Timestamp = u64;
 
+/// The unit a time series counts its timestamps and intervals in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeUnit {
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl TimeUnit {
+    pub fn per_second(&self) -> u64 {
+        match *self {
+            TimeUnit::Milliseconds => 1_000,
+            TimeUnit::Microseconds => 1_000_000,
+            TimeUnit::Nanoseconds => 1_000_000_000,
+        }
+    }
+
+    /// The digits needed to store a timestamp of this unit until the year 2286
+    pub fn significant_digits(&self) -> usize {
+        match *self {
+            TimeUnit::Milliseconds => 13,
+            TimeUnit::Microseconds => 16,
+            TimeUnit::Nanoseconds => 19,
+        }
+    }
+
+    /// Converts a timestamp or interval from this unit to another.  Converting to a finer unit saturates, and
+    /// converting to a coarser one rounds down.
+    pub fn convert(&self, value: Timestamp, to: TimeUnit) -> Timestamp {
+        let (from, to) = (self.per_second(), to.per_second());
+
+        if to >= from {
+            value.saturating_mul(to / from)
+        } else {
+            value / (from / to)
+        }
+    }
+}
+
+impl Default for TimeUnit {
+    fn default() -> Self {
+        TimeUnit::Milliseconds
+    }
+}
+
+impl fmt::Display for TimeUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            TimeUnit::Milliseconds => "ms",
+            TimeUnit::Microseconds => "us",
+            TimeUnit::Nanoseconds => "ns",
+        })
+    }
+}
+
+impl FromStr for TimeUnit {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        match text {
+            "ms" => Ok(TimeUnit::Milliseconds),
+            "us" => Ok(TimeUnit::Microseconds),
+            "ns" => Ok(TimeUnit::Nanoseconds),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Time unit must be one of ms, us, or ns")),
+        }
+    }
+}
+
+/// A timestamp that carries its unit, for comparing and converting timestamps from series of different units
+#[derive(Clone, Copy, Debug)]
+pub struct UnitTimestamp {
+    pub value: Timestamp,
+    pub unit: TimeUnit,
+}
+
+impl UnitTimestamp {
+    pub fn new(value: Timestamp, unit: TimeUnit) -> Self {
+        Self {
+            value: value,
+            unit: unit,
+        }
+    }
+
+    /// The same instant in another unit, rounded down
+    pub fn to_unit(&self, unit: TimeUnit) -> Self {
+        Self::new(self.unit.convert(self.value, unit), unit)
+    }
+
+    fn nanoseconds(&self) -> u128 {
+        self.value as u128 * (TimeUnit::Nanoseconds.per_second() / self.unit.per_second()) as u128
+    }
+}
+
+impl PartialEq for UnitTimestamp {
+    fn eq(&self, other: &Self) -> bool {
+        self.nanoseconds() == other.nanoseconds()
+    }
+}
+
+impl Eq for UnitTimestamp {}
+
+impl PartialOrd for UnitTimestamp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for UnitTimestamp {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.nanoseconds().cmp(&other.nanoseconds())
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RetrievalDirection {
     Forward,
     Backward,
 }
 
-pub trait TimeSeries: KeyValueStore {
+pub trait TimeSeries: KeyValueStore + AsKeyValueStore {
     fn retrieve_nearest(&self, timestamp: Timestamp, retrieval_direction: Option<RetrievalDirection>) -> io::Result<Retrieval>;
     fn retrieve_all(&self) -> io::Result<Retrieval>;
     fn retrieve_from(&self, timestamp: Timestamp) -> io::Result<Retrieval>;
     fn retrieve_to(&self, timestamp: Timestamp) -> io::Result<Retrieval>;
     fn retrieve_range(&self, range: Range<Timestamp>) -> io::Result<Retrieval>;
 
-    fn as_key_value_store(&self) -> &dyn KeyValueStore;
-    fn as_mut_key_value_store(&mut self) -> &mut dyn KeyValueStore;
+    /// Finds every span within the range that is at least `min_gap` long and contains no records.
+    /// Each gap runs from the record (or range start) before the hole to the record (or range end) after it.
+    fn find_gaps(&self, min_gap: Timestamp, range: Range<Timestamp>) -> io::Result<Vec<Range<Timestamp>>>;
+
+    /// The unit of the timestamps, and of the intervals used to pool them
+    fn time_unit(&self) -> TimeUnit {
+        TimeUnit::Milliseconds
+    }
+
+    /// Reads the records in a range ahead of time, e.g. the recent records of a busy channel after startup, so that
+    /// the first queries of them don't pay to bring them in from disk.  Stores with nothing to warm do nothing.
+    fn preload(&self, _range: Range<Timestamp>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Records that the feed was up at `timestamp`, even though it had nothing to store, so that quiet stretches can
+    /// be told apart from outages.  Fails if the series doesn't keep heartbeats.
+    fn heartbeat(&mut self, _timestamp: Timestamp) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "Time series doesn't keep heartbeats"))
+    }
+
+    /// When the feed was last known to be up: the later of its last heartbeat and its last record, or `None` if it
+    /// has had neither
+    fn last_update(&self) -> Option<Timestamp> {
+        self.last_key().and_then(|key| key.downcast_ref::<Timestamp>().cloned())
+    }
+}
+
+/// Views a time series as a `dyn TimeSeries`.  Sealed, like `AsKeyValueStore`.
+pub trait AsTimeSeries: AsKeyValueStore {
+    fn as_time_series(&self) -> &dyn TimeSeries;
+    fn as_mut_time_series(&mut self) -> &mut dyn TimeSeries;
+}
+
+impl<T> AsTimeSeries for T where T: TimeSeries {
+    fn as_time_series(&self) -> &dyn TimeSeries {
+        self
+    }
+
+    fn as_mut_time_series(&mut self) -> &mut dyn TimeSeries {
+        self
+    }
+}
+
+/// Retrieves the record nearest to `timestamp` in a direction, or on either side if none is given, but only if it's
+/// within `within` of it.  Fails with `NotFound` if there's no record that close, e.g. because the data is stale, so
+/// that lookups like "the price at 12:00, give or take 5s" don't quietly use an old value.  Ties go to the earlier
+/// record.
+pub fn retrieve_nearest_within<V>(time_series: &dyn TimeSeries, timestamp: Timestamp, retrieval_direction: Option<RetrievalDirection>, within: Timestamp) -> io::Result<(Timestamp, V)> where V: 'static + Copy {
+    let before = match retrieval_direction {
+        Some(RetrievalDirection::Forward) => None,
+        _ => nearest::<V>(time_series, timestamp, RetrievalDirection::Backward)?,
+    };
+    let after = match retrieval_direction {
+        Some(RetrievalDirection::Backward) => None,
+        _ => nearest::<V>(time_series, timestamp, RetrievalDirection::Forward)?,
+    };
+
+    let record = match (before, after) {
+        (Some(before), Some(after)) => Some(if after.0 - timestamp < timestamp - before.0 { after } else { before }),
+        (before, after) => before.or(after),
+    };
+
+    match record {
+        Some(record) if distance(record.0, timestamp) <= within => Ok(record),
+        Some(_) => Err(io::Error::new(io::ErrorKind::NotFound, "Nearest record is too far from the search key")),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "Search key was not found")),
+    }
+}
+
+/// The nearest record in a direction, or `None` if there's none that way
+fn nearest<V>(time_series: &dyn TimeSeries, timestamp: Timestamp, retrieval_direction: RetrievalDirection) -> io::Result<Option<(Timestamp, V)>> where V: 'static + Copy {
+    if time_series.len() == 0 {
+        return Ok(None);
+    }
+
+    match time_series.retrieve_nearest(timestamp, Some(retrieval_direction)) {
+        Ok(retrieval) => retrieval.as_single::<Timestamp, V>().cloned().map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Time series holds values of another type")),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn distance(a: Timestamp, b: Timestamp) -> Timestamp {
+    if a > b { a - b } else { b - a }
 }
 
 mod storage;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use storage::FileStorage;
+    use util::SetupFile;
+
+    #[test]
+    fn test_time_unit() {
+        assert_eq!(TimeUnit::Milliseconds.convert(1_546_300_800_123, TimeUnit::Nanoseconds), 1_546_300_800_123_000_000);
+        assert_eq!(TimeUnit::Nanoseconds.convert(1_546_300_800_123_456_789, TimeUnit::Microseconds), 1_546_300_800_123_456);
+        assert_eq!(TimeUnit::Microseconds.convert(Timestamp::max_value(), TimeUnit::Nanoseconds), Timestamp::max_value());
+        assert_eq!(TimeUnit::Microseconds.convert(5, TimeUnit::Microseconds), 5);
+
+        assert_eq!(TimeUnit::Nanoseconds.convert(1_546_300_800_123_456_789, TimeUnit::Milliseconds).to_string().len(), TimeUnit::Milliseconds.significant_digits());
+        assert_eq!(TimeUnit::Milliseconds.convert(1_546_300_800_123, TimeUnit::Nanoseconds).to_string().len(), TimeUnit::Nanoseconds.significant_digits());
+
+        for unit in &[TimeUnit::Milliseconds, TimeUnit::Microseconds, TimeUnit::Nanoseconds] {
+            assert_eq!(unit.to_string().parse::<TimeUnit>().unwrap(), *unit);
+        }
+        assert!("s".parse::<TimeUnit>().is_err());
+    }
+
+    #[test]
+    fn test_unit_timestamp() {
+        let milliseconds = UnitTimestamp::new(1_546_300_800_123, TimeUnit::Milliseconds);
+        let nanoseconds = UnitTimestamp::new(1_546_300_800_123_456_789, TimeUnit::Nanoseconds);
+
+        assert!(milliseconds < nanoseconds);
+        assert_eq!(nanoseconds.to_unit(TimeUnit::Milliseconds), milliseconds);
+        assert_eq!(nanoseconds.to_unit(TimeUnit::Milliseconds).value, 1_546_300_800_123);
+        assert_eq!(milliseconds.to_unit(TimeUnit::Microseconds), UnitTimestamp::new(1_546_300_800_123_000, TimeUnit::Microseconds));
+    }
+
+    #[test]
+    fn test_retrieve_nearest_within() {
+        let _setup_file = SetupFile::new("test_retrieve_nearest_within");
+
+        let mut fs = FileStorage::<Timestamp, i32>::new("test_retrieve_nearest_within").unwrap();
+        assert_eq!(retrieve_nearest_within::<i32>(&fs, 10, None, 5).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        fs.store(Box::new(10 as Timestamp), Box::new(1 as i32)).unwrap();
+        fs.store(Box::new(20 as Timestamp), Box::new(2 as i32)).unwrap();
+        fs.store(Box::new(40 as Timestamp), Box::new(4 as i32)).unwrap();
+
+        assert_eq!(retrieve_nearest_within::<i32>(&fs, 17, None, 5).unwrap(), (20, 2));
+        assert_eq!(retrieve_nearest_within::<i32>(&fs, 15, None, 5).unwrap(), (10, 1));
+        assert_eq!(retrieve_nearest_within::<i32>(&fs, 17, Some(RetrievalDirection::Backward), 7).unwrap(), (10, 1));
+        assert_eq!(retrieve_nearest_within::<i32>(&fs, 5, Some(RetrievalDirection::Forward), 5).unwrap(), (10, 1));
+
+        // The data is too stale, or there's none that way
+        assert_eq!(retrieve_nearest_within::<i32>(&fs, 30, None, 5).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert!(retrieve_nearest_within::<i32>(&fs, 17, Some(RetrievalDirection::Backward), 5).is_err());
+        assert!(retrieve_nearest_within::<i32>(&fs, 45, Some(RetrievalDirection::Forward), 100).is_err());
+
+        assert_eq!(retrieve_nearest_within::<i64>(&fs, 10, None, 0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
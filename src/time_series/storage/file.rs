@@ -14,7 +14,6 @@
 // along with trade-data.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::io;
-use std::str::FromStr;
 
 use key_value_store::Storable;
 use storage::FileStorage;
@@ -22,7 +21,7 @@ use time_series::Timestamp;
 
 const SIGNIFICANT_DIGITS: usize = 13;
 
-impl<V> Storable<FileStorage<Timestamp, V>> for Timestamp where V: Storable<FileStorage<Timestamp, V>> {
+impl<V> Storable<FileStorage<Timestamp, V>> for Timestamp {
     fn size() -> usize {
         SIGNIFICANT_DIGITS
     }
@@ -32,12 +31,17 @@ impl<V> Storable<FileStorage<Timestamp, V>> for Timestamp where V: Storable<File
     }
 
     fn from_bytes(buffer: &[u8]) -> io::Result<Self> {
-        if let Ok(string) = String::from_utf8(buffer.to_vec()) {
-            if let Ok(value) = Timestamp::from_str(&string) {
-                return Ok(value);
-            }
+        // Timestamps are parsed on every record read, so parse the digits directly instead of going through a String
+        if buffer.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid data"));
         }
 
-        Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid data"))
+        buffer.iter().try_fold(0 as Timestamp, |value, &byte| {
+            if byte.is_ascii_digit() {
+                value.checked_mul(10).and_then(|v| v.checked_add((byte - b'0') as Timestamp))
+            } else {
+                None
+            }
+        }).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid data"))
     }
 }
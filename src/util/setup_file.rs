@@ -22,6 +22,7 @@ pub struct SetupFile {
 impl SetupFile {
     pub fn new(filename: &'static str) -> Self {
         fs::remove_file(filename).ok();
+        fs::remove_file(format!("{}.unit", filename)).ok();
         Self {
             filename: filename,
         }
@@ -31,5 +32,6 @@ impl SetupFile {
 impl Drop for SetupFile {
     fn drop(&mut self) {
         fs::remove_file(self.filename).ok();
+        fs::remove_file(format!("{}.unit", self.filename)).ok();
     }
 }
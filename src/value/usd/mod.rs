@@ -15,6 +15,7 @@
 
 use std::fmt;
 
+use channel_info::ValueMetadata;
 use value::Value;
 
 const MAJOR_DIGITS: usize = 6;
@@ -43,6 +44,20 @@ impl Value for Usd {
     }
 }
 
+impl ValueMetadata for Usd {
+    fn unit() -> Option<&'static str> {
+        Some("USD")
+    }
+
+    fn currency() -> Option<&'static str> {
+        Some("USD")
+    }
+
+    fn precision() -> Option<u32> {
+        Some(MINOR_DIGITS as u32)
+    }
+}
+
 impl fmt::Display for Usd {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.pad(&format!("{}.{:0minor_digits$}", self.whole(), self.fractional(), minor_digits = MINOR_DIGITS))